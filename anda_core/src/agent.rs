@@ -272,6 +272,7 @@ where
                         Some(Function {
                             definition: agent.definition(),
                             supported_resource_tags: agent.supported_resource_tags(),
+                            output_schema: None,
                         })
                     } else {
                         None
@@ -280,6 +281,7 @@ where
                 None => Some(Function {
                     definition: agent.definition(),
                     supported_resource_tags: agent.supported_resource_tags(),
+                    output_schema: None,
                 }),
             })
             .collect()
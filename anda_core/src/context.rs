@@ -25,6 +25,7 @@
 //! - [`KeysFeatures`]: Cryptographic operations and key management;
 //! - [`StoreFeatures`]: Persistent storage capabilities;
 //! - [`CacheFeatures`]: In-memory caching with expiration policies;
+//! - [`KvFeatures`]: Typed, namespaced key-value access to the store;
 //! - [`HttpFeatures`]: HTTP communication capabilities;
 //! - [`VectorSearchFeatures`]: Semantic search functionality.
 //!
@@ -37,7 +38,7 @@ use bytes::Bytes;
 use ciborium::from_reader;
 use ic_cose_types::to_cbor_bytes;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use std::{future::Future, sync::Arc, time::Duration};
+use std::{future::Future, marker::PhantomData, sync::Arc, time::Duration};
 
 pub use candid::Principal;
 pub use ic_cose_types::{CanisterCaller, types::object_store::UpdateVersion};
@@ -45,8 +46,8 @@ pub use object_store::{ObjectMeta, PutMode, PutResult, UpdateVersion as OsVersio
 pub use serde_json::Value;
 pub use tokio_util::sync::CancellationToken;
 
-use crate::BoxError;
 use crate::model::*;
+use crate::{BoxError, validate_path_part};
 
 /// AgentContext provides the execution environment for Agents.
 /// It combines core functionality with AI-specific features:
@@ -602,6 +603,167 @@ pub trait CacheStoreFeatures: StoreFeatures + CacheFeatures + Send + Sync + 'sta
     }
 }
 
+/// KvFeatures provides a typed, namespaced key-value view of the store, see [`Kv`].
+pub trait KvFeatures: StoreFeatures + Sync {
+    /// Returns the key-value view of values of type `T` in the namespace.
+    ///
+    /// ```rust,ignore
+    /// let counters = ctx.kv::<u64>("counters");
+    /// counters.update("visits", 3, |n| n.unwrap_or_default() + 1).await?;
+    /// ```
+    fn kv<T>(&self, namespace: &str) -> Kv<'_, Self, T> {
+        Kv {
+            ctx: self,
+            namespace: namespace.to_string(),
+            _t: PhantomData,
+        }
+    }
+}
+
+impl<C: StoreFeatures + Sync> KvFeatures for C {}
+
+/// A typed key-value view of the store, created by [`KvFeatures::kv`].
+///
+/// Values are CBOR encoded at `kv/{namespace}/{key}` in the store of the context. Namespaces
+/// and keys are case-insensitive and must not contain `/`. Every write returns the version
+/// of the value, which [`compare_and_swap`](Self::compare_and_swap) takes for atomic updates.
+pub struct Kv<'a, C, T> {
+    ctx: &'a C,
+    namespace: String,
+    _t: PhantomData<T>,
+}
+
+impl<C, T> Kv<'_, C, T>
+where
+    C: StoreFeatures + Sync,
+    T: Serialize + DeserializeOwned + Send + Sync,
+{
+    fn path(&self, key: &str) -> Result<Path, BoxError> {
+        validate_path_part(&self.namespace)?;
+        validate_path_part(key)?;
+        Ok(Path::from(format!("kv/{}/{}", self.namespace, key)))
+    }
+
+    /// Gets the value and its version, or `None` if the key does not exist.
+    pub async fn get(&self, key: &str) -> Result<Option<(T, UpdateVersion)>, BoxError> {
+        let path = self.path(key)?;
+        match self.ctx.store_get(&path).await {
+            Ok((data, meta)) => {
+                let val: T = from_reader(&data[..])
+                    .map_err(|err| format!("failed to decode kv {:?}: {}", key, err))?;
+                Ok(Some((
+                    val,
+                    UpdateVersion {
+                        e_tag: meta.e_tag,
+                        version: meta.version,
+                    },
+                )))
+            }
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Puts the value, overwriting the current value if any.
+    pub async fn put(&self, key: &str, val: &T) -> Result<UpdateVersion, BoxError> {
+        self.write(key, PutMode::Overwrite, val).await
+    }
+
+    /// Puts the value if its current version is `version`, or if the key does not exist
+    /// when `version` is `None`. Fails if the value was changed or created concurrently.
+    pub async fn compare_and_swap(
+        &self,
+        key: &str,
+        version: Option<UpdateVersion>,
+        val: &T,
+    ) -> Result<UpdateVersion, BoxError> {
+        let mode = match version {
+            Some(ver) => PutMode::Update(OsVersion {
+                e_tag: ver.e_tag,
+                version: ver.version,
+            }),
+            None => PutMode::Create,
+        };
+        self.write(key, mode, val).await
+    }
+
+    /// Updates the value with `f`, which gets the current value or `None`, by compare and
+    /// swap. Retries up to `max_retries` times on concurrent changes. Returns the new value
+    /// and its version.
+    pub async fn update<F>(
+        &self,
+        key: &str,
+        max_retries: usize,
+        mut f: F,
+    ) -> Result<(T, UpdateVersion), BoxError>
+    where
+        F: FnMut(Option<T>) -> T + Send,
+    {
+        self.try_update(key, max_retries, |val| Ok(f(val))).await
+    }
+
+    /// Like [`update`](Self::update), but `f` can reject the update with an error, which is
+    /// returned without writing the value.
+    pub async fn try_update<F>(
+        &self,
+        key: &str,
+        max_retries: usize,
+        mut f: F,
+    ) -> Result<(T, UpdateVersion), BoxError>
+    where
+        F: FnMut(Option<T>) -> Result<T, BoxError> + Send,
+    {
+        let mut retries = 0;
+        loop {
+            let (current, version) = match self.get(key).await? {
+                Some((val, ver)) => (Some(val), Some(ver)),
+                None => (None, None),
+            };
+            let val = f(current)?;
+            match self.compare_and_swap(key, version, &val).await {
+                Ok(ver) => return Ok((val, ver)),
+                Err(err) if retries < max_retries && is_conflict(&err) => retries += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Deletes the value. It is not an error if the key does not exist.
+    pub async fn delete(&self, key: &str) -> Result<(), BoxError> {
+        let path = self.path(key)?;
+        match self.ctx.store_delete(&path).await {
+            Err(err) if !is_not_found(&err) => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    async fn write(&self, key: &str, mode: PutMode, val: &T) -> Result<UpdateVersion, BoxError> {
+        let path = self.path(key)?;
+        let res = self
+            .ctx
+            .store_put(&path, mode, to_cbor_bytes(val).into())
+            .await?;
+        Ok(UpdateVersion {
+            e_tag: res.e_tag,
+            version: res.version,
+        })
+    }
+}
+
+fn is_not_found(err: &BoxError) -> bool {
+    matches!(
+        err.downcast_ref::<object_store::Error>(),
+        Some(object_store::Error::NotFound { .. })
+    )
+}
+
+fn is_conflict(err: &BoxError) -> bool {
+    matches!(
+        err.downcast_ref::<object_store::Error>(),
+        Some(object_store::Error::Precondition { .. } | object_store::Error::AlreadyExists { .. })
+    )
+}
+
 /// Derives a derivation path with the given path and derivation path.
 pub fn derivation_path_with<'a>(path: &'a Path, derivation_path: &'a [&'a [u8]]) -> Vec<&'a [u8]> {
     let mut dp = Vec::with_capacity(derivation_path.len() + 1);
@@ -63,3 +63,315 @@ fn fix_obj_schema(schema: &mut SchemaObject) {
         }
     }
 }
+
+/// Returns the JSON text in a model output: the content of the first fenced code block if
+/// any, otherwise the content from the first `{` or `[` to the last `}` or `]`.
+pub fn extract_json(content: &str) -> &str {
+    if let Some(start) = content.find("```") {
+        let rest = &content[start + 3..];
+        // skips the language tag, e.g. "json"
+        let rest = rest.find('\n').map_or(rest, |i| &rest[i + 1..]);
+        if let Some(end) = rest.find("```") {
+            return rest[..end].trim();
+        }
+    }
+    let start = content.find(['{', '[']);
+    let end = content.rfind(['}', ']']);
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => &content[start..=end],
+        _ => content.trim(),
+    }
+}
+
+/// Validates a JSON value against a JSON schema generated by [`gen_schema_for`].
+///
+/// It supports the keywords used by such schemas: `type`, `enum`, `const`, `properties`,
+/// `required`, `additionalProperties`, `items`, `anyOf`, `oneOf`, `allOf`, the numeric bounds
+/// and the length bounds of strings and arrays. References are not resolved.
+/// Returns the first violation with the path of the invalid value.
+pub fn validate_json(schema: &serde_json::Value, value: &serde_json::Value) -> Result<(), String> {
+    validate_at("$", schema, value)
+}
+
+fn validate_at(
+    path: &str,
+    schema: &serde_json::Value,
+    value: &serde_json::Value,
+) -> Result<(), String> {
+    use serde_json::Value;
+
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("{}: no value is allowed", path)),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(ty) = schema.get("type") {
+        let types: Vec<&str> = match ty {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|t| json_type_matches(t, value)) {
+            return Err(format!(
+                "{}: expected {}, got {}",
+                path,
+                types.join(" or "),
+                json_type_name(value)
+            ));
+        }
+    }
+    if let Some(Value::Array(values)) = schema.get("enum")
+        && !values.contains(value)
+    {
+        return Err(format!(
+            "{}: {} is not one of {}",
+            path,
+            value,
+            Value::Array(values.clone())
+        ));
+    }
+    if let Some(expected) = schema.get("const")
+        && expected != value
+    {
+        return Err(format!("{}: expected {}, got {}", path, expected, value));
+    }
+
+    for key in ["allOf", "anyOf", "oneOf"] {
+        let Some(Value::Array(subs)) = schema.get(key) else {
+            continue;
+        };
+        let errors: Vec<String> = subs
+            .iter()
+            .filter_map(|s| validate_at(path, s, value).err())
+            .collect();
+        let matched = subs.len() - errors.len();
+        match key {
+            "allOf" => {
+                if let Some(err) = errors.into_iter().next() {
+                    return Err(err);
+                }
+            }
+            _ if matched == 0 => {
+                return Err(format!(
+                    "{}: does not match any schema of {}: {}",
+                    path,
+                    key,
+                    errors.join("; ")
+                ));
+            }
+            "oneOf" if matched > 1 => {
+                return Err(format!(
+                    "{}: matches {} schemas of oneOf, expected exactly one",
+                    path, matched
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    match value {
+        Value::Object(obj) => {
+            let properties = schema.get("properties").and_then(|v| v.as_object());
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(|v| v.as_str()) {
+                    if !obj.contains_key(name) {
+                        return Err(format!("{}: missing required property {:?}", path, name));
+                    }
+                }
+            }
+            for (name, v) in obj {
+                let sub = format!("{}.{}", path, name);
+                match properties.and_then(|p| p.get(name)) {
+                    Some(s) => validate_at(&sub, s, v)?,
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            return Err(format!("{}: unknown property {:?}", path, name));
+                        }
+                        Some(s) => validate_at(&sub, s, v)?,
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            check_bounds(path, "items", items.len(), schema, "minItems", "maxItems")?;
+            match schema.get("items") {
+                Some(Value::Array(tuple)) => {
+                    for (i, (s, v)) in tuple.iter().zip(items).enumerate() {
+                        validate_at(&format!("{}[{}]", path, i), s, v)?;
+                    }
+                }
+                Some(s) => {
+                    for (i, v) in items.iter().enumerate() {
+                        validate_at(&format!("{}[{}]", path, i), s, v)?;
+                    }
+                }
+                None => {}
+            }
+        }
+        Value::String(s) => {
+            check_bounds(
+                path,
+                "characters",
+                s.chars().count(),
+                schema,
+                "minLength",
+                "maxLength",
+            )?;
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(|v| v.as_f64())
+                && n < min
+            {
+                return Err(format!("{}: {} is less than the minimum {}", path, n, min));
+            }
+            if let Some(max) = schema.get("maximum").and_then(|v| v.as_f64())
+                && n > max
+            {
+                return Err(format!(
+                    "{}: {} is greater than the maximum {}",
+                    path, n, max
+                ));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn check_bounds(
+    path: &str,
+    unit: &str,
+    len: usize,
+    schema: &serde_json::Map<String, serde_json::Value>,
+    min_key: &str,
+    max_key: &str,
+) -> Result<(), String> {
+    if let Some(min) = schema.get(min_key).and_then(|v| v.as_u64())
+        && (len as u64) < min
+    {
+        return Err(format!(
+            "{}: expected at least {} {}, got {}",
+            path, min, unit, len
+        ));
+    }
+    if let Some(max) = schema.get(max_key).and_then(|v| v.as_u64())
+        && (len as u64) > max
+    {
+        return Err(format!(
+            "{}: expected at most {} {}, got {}",
+            path, max, unit, len
+        ));
+    }
+    Ok(())
+}
+
+fn json_type_matches(ty: &str, value: &serde_json::Value) -> bool {
+    match ty {
+        "integer" => value.as_i64().is_some() || value.as_u64().is_some(),
+        "number" => value.is_number(),
+        _ => json_type_name(value) == ty,
+    }
+}
+
+fn json_type_name(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    enum Mood {
+        Happy,
+        Sad,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct Contact {
+        name: String,
+        age: u8,
+        phone: Option<String>,
+        tags: Vec<String>,
+        mood: Mood,
+    }
+
+    #[test]
+    fn test_extract_json() {
+        assert_eq!(extract_json(r#"{"a":1}"#), r#"{"a":1}"#);
+        assert_eq!(
+            extract_json("Here it is:\n```json\n{\"a\":1}\n```\nDone."),
+            r#"{"a":1}"#
+        );
+        assert_eq!(extract_json("The result is [1, 2]."), "[1, 2]");
+        assert_eq!(extract_json(" no json "), "no json");
+    }
+
+    #[test]
+    fn test_validate_json() {
+        let schema = gen_schema_for::<Contact>();
+        let valid = json!({"name": "Alice", "age": 30, "phone": null, "tags": [], "mood": "Happy"});
+        assert!(validate_json(&schema, &valid).is_ok());
+
+        let cases = [
+            (
+                json!({"name": "Alice", "age": 30, "phone": null, "tags": []}),
+                "missing required property \"mood\"",
+            ),
+            (
+                json!({"name": 1, "age": 30, "phone": null, "tags": [], "mood": "Sad"}),
+                "$.name: expected string, got number",
+            ),
+            (
+                json!({"name": "Alice", "age": -1, "phone": null, "tags": [], "mood": "Sad"}),
+                "$.age: -1 is less than the minimum 0",
+            ),
+            (
+                json!({"name": "Alice", "age": 1.5, "phone": null, "tags": [], "mood": "Sad"}),
+                "$.age: expected integer, got number",
+            ),
+            (
+                json!({"name": "Alice", "age": 30, "phone": null, "tags": [1], "mood": "Sad"}),
+                "$.tags[0]: expected string",
+            ),
+            (
+                json!({"name": "Alice", "age": 30, "phone": null, "tags": [], "mood": "Angry"}),
+                "$.mood: \"Angry\" is not one of",
+            ),
+            (
+                json!({"name": "Alice", "age": 30, "phone": null, "tags": [], "mood": "Sad", "x": 1}),
+                "unknown property \"x\"",
+            ),
+        ];
+        for (value, expected) in cases {
+            let err = validate_json(&schema, &value).unwrap_err();
+            assert!(err.contains(expected), "{err}");
+        }
+
+        let schema = json!({"anyOf": [{"type": "string", "minLength": 2}, {"type": "integer"}]});
+        assert!(validate_json(&schema, &json!("ab")).is_ok());
+        assert!(validate_json(&schema, &json!(3)).is_ok());
+        assert!(validate_json(&schema, &json!("a")).is_err());
+
+        let schema = json!({"oneOf": [{"type": "integer"}, {"type": "number", "minimum": 0}]});
+        assert!(validate_json(&schema, &json!(-1)).is_ok());
+        assert!(validate_json(&schema, &json!(0.5)).is_ok());
+        assert!(validate_json(&schema, &json!(1)).is_err());
+        assert!(validate_json(&schema, &json!("1")).is_err());
+    }
+}
@@ -9,6 +9,7 @@ pub mod model;
 pub mod tool;
 
 pub use agent::*;
+pub use anda_macros::{agent, tool};
 pub use context::*;
 pub use http::*;
 pub use json::*;
@@ -0,0 +1,319 @@
+use candid::Principal;
+use proptest::{collection::vec, option, prelude::*};
+use serde_json::{Map, Number};
+
+use super::{
+    AgentInput, ByteArrayB64, ByteBufB64, Message, RequestMeta, RequestPriority, Resource,
+    ResourceFailurePolicy, ThreadMessage, ToolInput, UiCallback, Value, Xid,
+};
+
+/// A strategy of JSON values, nested up to 3 levels. Floats are quarters, which are exact
+/// in decimal, so that values survive a JSON round trip without the `float_roundtrip` feature.
+pub fn arb_json_value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        any::<i64>().prop_map(|v| Value::Number(v.into())),
+        any::<i32>()
+            .prop_map(|v| Number::from_f64(v as f64 / 4.0).map_or(Value::Null, Value::Number)),
+        ".{0,16}".prop_map(Value::String),
+    ];
+    leaf.prop_recursive(3, 32, 4, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..4).prop_map(Value::Array),
+            vec(("[a-z_]{1,8}", inner), 0..4)
+                .prop_map(|kv| Value::Object(kv.into_iter().collect::<Map<_, _>>())),
+        ]
+    })
+}
+
+/// A strategy of principals, including the anonymous and management canister principals.
+pub fn arb_principal() -> impl Strategy<Value = Principal> {
+    vec(any::<u8>(), 0..29).prop_map(|b| Principal::from_slice(&b))
+}
+
+/// A strategy of XIDs.
+pub fn arb_xid() -> impl Strategy<Value = Xid> {
+    any::<[u8; 12]>().prop_map(Xid)
+}
+
+impl Arbitrary for RequestPriority {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![Just(Self::Interactive), Just(Self::Batch)].boxed()
+    }
+}
+
+impl Arbitrary for ResourceFailurePolicy {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(Self::Fail),
+            Just(Self::SkipWithNote),
+            Just(Self::AskUser)
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for UiCallback {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        // a null payload is serialized as absent
+        let payload = || option::of(arb_json_value().prop_filter("not null", |v| !v.is_null()));
+        prop_oneof![
+            ".{0,32}".prop_map(|text| Self::Reply { text }),
+            ("[a-z_:0-9]{1,16}", ".{0,16}", payload())
+                .prop_map(|(id, label, payload)| Self::Button { id, label, payload }),
+            ("[a-z_]{1,16}", any::<bool>(), payload()).prop_map(|(id, confirmed, payload)| {
+                Self::Confirm {
+                    id,
+                    confirmed,
+                    payload,
+                }
+            }),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for Resource {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            "[a-z]{1,8}",
+            option::of(".{0,32}"),
+            option::of(".{0,16}"),
+            option::of(".{0,32}"),
+            option::of("[a-z]{1,8}/[a-z0-9.+-]{1,16}"),
+            option::of(vec(any::<u8>(), 0..64)),
+            option::of(any::<u32>()),
+            option::of(any::<[u8; 32]>()),
+        )
+            .prop_map(
+                |(tag, uri, name, description, mime_type, blob, size, hash)| Resource {
+                    tag,
+                    uri,
+                    name,
+                    description,
+                    mime_type,
+                    blob: blob.map(ByteBufB64::from),
+                    size: size.map(|v| v as usize),
+                    hash: hash.map(ByteArrayB64::from),
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for RequestMeta {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            option::of(arb_principal()),
+            option::of(arb_xid()),
+            option::of(".{0,16}"),
+            any::<RequestPriority>(),
+            option::of(any::<ResourceFailurePolicy>()),
+            option::of("[a-z_]{1,16}"),
+            option::of("[a-z_]{1,16}"),
+            option::of(any::<UiCallback>()),
+            option::of(arb_principal()),
+            option::of(any::<u64>()),
+            option::of("[a-z]{2}"),
+        )
+            .prop_map(
+                |(
+                    engine,
+                    thread,
+                    user,
+                    priority,
+                    resource_failure,
+                    workflow,
+                    form,
+                    callback,
+                    person,
+                    deadline_ms,
+                    lang,
+                )| {
+                    RequestMeta {
+                        engine,
+                        thread,
+                        user,
+                        priority,
+                        resource_failure,
+                        workflow,
+                        form,
+                        callback,
+                        person,
+                        deadline_ms,
+                        lang,
+                    }
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for AgentInput {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            "[a-z_]{0,16}",
+            ".{0,64}",
+            option::of(vec(any::<Resource>(), 0..3)),
+            option::of(any::<RequestMeta>()),
+        )
+            .prop_map(|(name, prompt, resources, meta)| AgentInput {
+                name,
+                prompt,
+                resources,
+                meta,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for ToolInput<Value> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            "[a-z_]{1,16}",
+            arb_json_value(),
+            option::of(vec(any::<Resource>(), 0..3)),
+            option::of(any::<RequestMeta>()),
+        )
+            .prop_map(|(name, args, resources, meta)| ToolInput {
+                name,
+                args,
+                resources,
+                meta,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Message {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            prop_oneof![
+                Just("system"),
+                Just("user"),
+                Just("assistant"),
+                Just("tool")
+            ],
+            arb_json_value(),
+            option::of("[a-z_]{1,16}"),
+            option::of("[a-zA-Z0-9_]{1,16}"),
+        )
+            .prop_map(|(role, content, name, tool_call_id)| Message {
+                role: role.to_string(),
+                content,
+                name,
+                tool_call_id,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for ThreadMessage {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (arb_xid(), any::<Message>())
+            .prop_map(|(id, msg)| ThreadMessage {
+                id,
+                role: msg.role,
+                content: msg.content,
+                name: msg.name,
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ThreadMeta, Versioned};
+    use ic_cose_types::to_cbor_bytes;
+    use serde::{Serialize, de::DeserializeOwned};
+
+    /// Checks that the value survives a CBOR and a JSON round trip, compared by JSON.
+    fn assert_roundtrip<T: Serialize + DeserializeOwned>(v: &T) {
+        let expected = serde_json::to_value(v).unwrap();
+        let cbor = to_cbor_bytes(v);
+        let decoded: T = ciborium::from_reader(&cbor[..]).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+        let json = serde_json::to_vec(v).unwrap();
+        let decoded: T = serde_json::from_slice(&json).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+    }
+
+    /// Decodes untrusted input as the engine server does. It must fail, not panic.
+    fn decode_untrusted(data: &[u8]) {
+        let _ = ciborium::from_reader::<(AgentInput,), _>(data);
+        let _ = ciborium::from_reader::<(ToolInput<Value>,), _>(data);
+        let _ = Versioned::<ThreadMeta>::decode(data);
+        let _ = Versioned::<Vec<ThreadMessage>>::decode(data);
+        let _ = serde_json::from_slice::<AgentInput>(data);
+        let _ = serde_json::from_slice::<ToolInput<Value>>(data);
+    }
+
+    proptest! {
+        #[test]
+        fn test_wire_roundtrip(
+            agent in any::<AgentInput>(),
+            tool in any::<ToolInput<Value>>(),
+            messages in vec(any::<ThreadMessage>(), 0..4),
+        ) {
+            assert_roundtrip(&agent);
+            assert_roundtrip(&tool);
+            assert_roundtrip(&messages);
+
+            let data = to_cbor_bytes(&Versioned(messages.clone()));
+            let decoded = Versioned::<Vec<ThreadMessage>>::decode(&data).unwrap();
+            prop_assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(&messages).unwrap()
+            );
+        }
+
+        #[test]
+        fn test_decode_random_bytes(data in vec(any::<u8>(), 0..256)) {
+            decode_untrusted(&data);
+        }
+
+        #[test]
+        fn test_decode_corrupted_input(
+            input in any::<AgentInput>(),
+            flips in vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let mut data = to_cbor_bytes(&(input,));
+            for (i, b) in flips {
+                let i = i.index(data.len());
+                data[i] ^= b;
+            }
+            decode_untrusted(&data);
+            decode_untrusted(&data[..cut.index(data.len())]);
+        }
+    }
+}
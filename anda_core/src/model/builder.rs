@@ -0,0 +1,303 @@
+use candid::Principal;
+use std::marker::PhantomData;
+
+use super::{
+    AgentInput, CompletionRequest, ContentPart, ContextSource, Document, Documents,
+    FunctionDefinition, RequestMeta, Resource, SafetySetting, Value,
+};
+
+/// The state of a builder whose required field is not set yet; it has no `build` method.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Missing;
+
+/// The state of a builder whose required field is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Present;
+
+/// A builder of [`CompletionRequest`], created by [`CompletionRequest::builder`].
+///
+/// The prompt or the content parts are required: `build` is only available after
+/// [`prompt`](Self::prompt) or [`content_part`](Self::content_part) is called.
+///
+/// ```rust
+/// use anda_core::CompletionRequest;
+///
+/// let req = CompletionRequest::builder()
+///     .system("You are a helpful assistant.")
+///     .document("1", "Anda is an AI agent framework built with Rust.")
+///     .temperature(0.2)
+///     .prompt("What is Anda?")
+///     .build();
+/// assert_eq!(req.documents.len(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompletionRequestBuilder<P = Missing> {
+    req: CompletionRequest,
+    _p: PhantomData<P>,
+}
+
+impl CompletionRequest {
+    /// Creates a builder of a completion request.
+    pub fn builder() -> CompletionRequestBuilder<Missing> {
+        CompletionRequestBuilder {
+            req: CompletionRequest::default(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<P> CompletionRequestBuilder<P> {
+    fn into_state<Q>(self) -> CompletionRequestBuilder<Q> {
+        CompletionRequestBuilder {
+            req: self.req,
+            _p: PhantomData,
+        }
+    }
+
+    /// Sets the prompt, sent as the "user" role. An empty prompt continues from the chat history.
+    pub fn prompt(mut self, prompt: impl Into<String>) -> CompletionRequestBuilder<Present> {
+        self.req.prompt = prompt.into();
+        self.into_state()
+    }
+
+    /// Adds a content part; the prompt is ignored if there are content parts.
+    pub fn content_part(mut self, part: ContentPart) -> CompletionRequestBuilder<Present> {
+        self.req.content_parts.push(part);
+        self.into_state()
+    }
+
+    /// Sets the system message.
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.req.system = Some(system.into());
+        self
+    }
+
+    /// Sets the name of the system role.
+    pub fn system_name(mut self, name: impl Into<String>) -> Self {
+        self.req.system_name = Some(name.into());
+        self
+    }
+
+    /// Sets the name of the prompter.
+    pub fn prompter_name(mut self, name: impl Into<String>) -> Self {
+        self.req.prompter_name = Some(name.into());
+        self
+    }
+
+    /// Appends raw messages to the chat history.
+    pub fn history(mut self, messages: impl IntoIterator<Item = Value>) -> Self {
+        self.req.chat_history.extend(messages);
+        self
+    }
+
+    /// Adds a document to the context.
+    pub fn document(mut self, id: impl Into<String>, text: impl Into<String>) -> Self {
+        self.req.documents.0.push(Document {
+            id: id.into(),
+            text: text.into(),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Adds documents to the context.
+    pub fn documents(mut self, docs: Documents) -> Self {
+        self.req.documents.0.extend(docs.0);
+        self
+    }
+
+    /// Adds a tool.
+    pub fn tool(mut self, tool: FunctionDefinition) -> Self {
+        self.req.tools.push(tool);
+        self
+    }
+
+    /// Adds tools.
+    pub fn tools(mut self, tools: impl IntoIterator<Item = FunctionDefinition>) -> Self {
+        self.req.tools.extend(tools);
+        self
+    }
+
+    /// Requires the model to call a tool.
+    pub fn tool_choice_required(mut self) -> Self {
+        self.req.tool_choice_required = true;
+        self
+    }
+
+    /// Sets the temperature.
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.req.temperature = Some(temperature);
+        self
+    }
+
+    /// Sets the max tokens.
+    pub fn max_tokens(mut self, max_tokens: usize) -> Self {
+        self.req.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Sets the JSON format that the model must output.
+    pub fn response_format(mut self, format: Value) -> Self {
+        self.req.response_format = Some(format);
+        self
+    }
+
+    /// Sets the stop sequences.
+    pub fn stop(mut self, stop: Vec<String>) -> Self {
+        self.req.stop = Some(stop);
+        self
+    }
+
+    /// Adds a safety setting.
+    pub fn safety_setting(mut self, setting: SafetySetting) -> Self {
+        self.req.safety_settings.push(setting);
+        self
+    }
+
+    /// Sets the order to drop context when the request exceeds the model's context length.
+    pub fn context_drop_order(mut self, order: Vec<ContextSource>) -> Self {
+        self.req.context_drop_order = Some(order);
+        self
+    }
+
+    /// Sets the caller on whose behalf the request is made.
+    pub fn caller(mut self, caller: Principal) -> Self {
+        self.req.caller = Some(caller);
+        self
+    }
+}
+
+impl CompletionRequestBuilder<Present> {
+    /// Builds the request.
+    pub fn build(self) -> CompletionRequest {
+        self.req
+    }
+}
+
+/// A builder of [`AgentInput`], created by [`AgentInput::builder`].
+///
+/// The prompt is required: `build` is only available after [`prompt`](Self::prompt) is
+/// called. The default agent runs if no name is set.
+///
+/// ```rust
+/// use anda_core::AgentInput;
+///
+/// let input = AgentInput::builder()
+///     .name("assistant")
+///     .prompt("Hello!")
+///     .build();
+/// assert_eq!(input.name, "assistant");
+/// ```
+#[derive(Debug, Clone)]
+pub struct AgentInputBuilder<P = Missing> {
+    input: AgentInput,
+    _p: PhantomData<P>,
+}
+
+impl AgentInput {
+    /// Creates a builder of an agent input.
+    pub fn builder() -> AgentInputBuilder<Missing> {
+        AgentInputBuilder {
+            input: AgentInput::default(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<P> AgentInputBuilder<P> {
+    /// Sets the prompt.
+    pub fn prompt(mut self, prompt: impl Into<String>) -> AgentInputBuilder<Present> {
+        self.input.prompt = prompt.into();
+        AgentInputBuilder {
+            input: self.input,
+            _p: PhantomData,
+        }
+    }
+
+    /// Sets the name of the agent.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.input.name = name.into();
+        self
+    }
+
+    /// Adds a resource.
+    pub fn resource(mut self, resource: Resource) -> Self {
+        self.input
+            .resources
+            .get_or_insert_with(Vec::new)
+            .push(resource);
+        self
+    }
+
+    /// Adds resources.
+    pub fn resources(mut self, resources: impl IntoIterator<Item = Resource>) -> Self {
+        self.input
+            .resources
+            .get_or_insert_with(Vec::new)
+            .extend(resources);
+        self
+    }
+
+    /// Sets the metadata of the request.
+    pub fn meta(mut self, meta: RequestMeta) -> Self {
+        self.input.meta = Some(meta);
+        self
+    }
+}
+
+impl AgentInputBuilder<Present> {
+    /// Builds the input.
+    pub fn build(self) -> AgentInput {
+        self.input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ImageDetail;
+    use serde_json::json;
+
+    #[test]
+    fn test_completion_request_builder() {
+        let req = CompletionRequest::builder()
+            .system("system")
+            .history([json!({"role": "user", "content": "Hi"})])
+            .tool(FunctionDefinition {
+                name: "tool".to_string(),
+                ..Default::default()
+            })
+            .tool_choice_required()
+            .max_tokens(100)
+            .content_part(ContentPart::Image {
+                image_url: ImageDetail {
+                    url: "https://example.com/cat.png".to_string(),
+                    detail: None,
+                },
+            })
+            .prompt("Describe it")
+            .build();
+        assert_eq!(req.system.as_deref(), Some("system"));
+        assert_eq!(req.chat_history.len(), 1);
+        assert_eq!(req.tools.len(), 1);
+        assert!(req.tool_choice_required);
+        assert_eq!(req.max_tokens, Some(100));
+        assert_eq!(req.content_parts.len(), 1);
+        assert_eq!(req.prompt, "Describe it");
+    }
+
+    #[test]
+    fn test_agent_input_builder() {
+        let input = AgentInput::builder()
+            .resource(Resource {
+                tag: "text".to_string(),
+                ..Default::default()
+            })
+            .prompt("Hello")
+            .build();
+        assert!(input.name.is_empty());
+        assert_eq!(input.prompt, "Hello");
+        assert_eq!(input.resources.unwrap().len(), 1);
+        assert!(input.meta.is_none());
+    }
+}
@@ -1,8 +1,13 @@
-use serde::{Deserialize, Serialize};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use candid::Principal;
+use futures::stream::BoxStream;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
 use std::{collections::BTreeMap, convert::Infallible, str::FromStr};
 
-use super::{AgentOutput, FunctionDefinition, Knowledge, Resource, Value};
-use crate::BoxError;
+use super::{AgentOutput, FunctionDefinition, Knowledge, Resource, Usage, Value, sniff_mime_type};
+use crate::{BoxError, extract_json, gen_schema_for, validate_json};
 
 /// Provides LLM completion capabilities for agents.
 pub trait CompletionFeatures: Sized {
@@ -12,10 +17,116 @@ pub trait CompletionFeatures: Sized {
         req: CompletionRequest,
         resources: Option<Vec<Resource>>,
     ) -> impl Future<Output = Result<AgentOutput, BoxError>> + Send;
+
+    /// Generates a completion as a stream of incremental outputs: chunks of the content,
+    /// fragments of tool calls, and the usage in the last output. Fragments of a tool call
+    /// share its id. The outputs can be merged with [`AgentOutput::accumulate`].
+    ///
+    /// The default implementation yields the result of [`completion`](Self::completion)
+    /// as a single output.
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+        resources: Option<Vec<Resource>>,
+    ) -> BoxStream<'_, Result<AgentOutput, BoxError>> {
+        Box::pin(futures::stream::once(self.completion(req, resources)))
+    }
+
+    /// Generates a completion whose content is JSON data of type `T`.
+    ///
+    /// The JSON schema of `T` is set as the response format of the request, and the content
+    /// is validated against it. If it is invalid, the model is asked to correct it with the
+    /// validation error, up to `max_retries` times. Returns the data and the last output,
+    /// with the usage of all attempts.
+    fn completion_structured<T>(
+        &self,
+        mut req: CompletionRequest,
+        max_retries: usize,
+    ) -> impl Future<Output = Result<(T, AgentOutput), BoxError>> + Send
+    where
+        T: JsonSchema + DeserializeOwned + Send,
+        Self: Sync,
+    {
+        async move {
+            let schema = gen_schema_for::<T>();
+            let name: String = schema
+                .get("title")
+                .and_then(|v| v.as_str())
+                .unwrap_or("response")
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            req.response_format = Some(json!({
+                "type": "json_schema",
+                "json_schema": {"name": name, "schema": schema, "strict": true},
+            }));
+
+            let mut usage = Usage::default();
+            let mut attempts = 0;
+            loop {
+                attempts += 1;
+                let mut output = self.completion(req.clone(), None).await?;
+                usage.accumulate(&output.usage);
+                if let Some(reason) = output.failed_reason {
+                    return Err(format!("completion failed: {}", reason).into());
+                }
+
+                let err = match parse_structured::<T>(&schema, &output.content) {
+                    Ok(data) => {
+                        output.usage = usage;
+                        return Ok((data, output));
+                    }
+                    Err(err) if attempts > max_retries => {
+                        return Err(format!(
+                            "invalid structured output after {} attempts: {}",
+                            attempts, err
+                        )
+                        .into());
+                    }
+                    Err(err) => err,
+                };
+
+                // continues the conversation with the validation error
+                match output.full_history {
+                    Some(history) => {
+                        req.system = None;
+                        req.chat_history = history;
+                    }
+                    None => {
+                        if let Some(prompt) = req.prompt_with_context() {
+                            req.chat_history
+                                .push(json!({"role": "user", "content": prompt}));
+                        }
+                        req.chat_history
+                            .push(json!({"role": "assistant", "content": output.content}));
+                    }
+                }
+                req.documents.clear();
+                req.content_parts.clear();
+                req.prompt = format!(
+                    "Your output is invalid: {}. Respond again with only the JSON data that conforms to the schema.",
+                    err
+                );
+            }
+        }
+    }
+}
+
+fn parse_structured<T: DeserializeOwned>(schema: &Value, content: &str) -> Result<T, String> {
+    let value: Value = serde_json::from_str(extract_json(content))
+        .map_err(|err| format!("not valid JSON, {}", err))?;
+    validate_json(schema, &value)?;
+    serde_json::from_value(value).map_err(|err| err.to_string())
 }
 
 /// Represents a general completion request that can be sent to a completion model provider.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct CompletionRequest {
     /// The system message to be sent to the completion model provider, as the "system" role.
     pub system: Option<String>,
@@ -36,8 +147,9 @@ pub struct CompletionRequest {
     /// The name of the prompter.
     pub prompter_name: Option<String>,
 
-    /// The content parts to be sent to the completion model provider.
-    /// prompt will be ignored if content_parts is not empty.
+    /// The content parts to be sent to the completion model provider, e.g. text and images.
+    /// If they have no text part, the prompt with context is sent as the first text part,
+    /// otherwise the prompt is ignored. See [`CompletionRequest::user_content_parts`].
     pub content_parts: Vec<ContentPart>,
 
     /// The tools to be sent to the completion model provider.
@@ -61,6 +173,94 @@ pub struct CompletionRequest {
 
     /// The stop sequence to be sent to the completion model provider.
     pub stop: Option<Vec<String>>,
+
+    /// The safety settings for providers that filter harmful content, e.g. Gemini.
+    /// Other providers ignore them.
+    pub safety_settings: Vec<SafetySetting>,
+
+    /// The context to drop, lowest priority first, when the request exceeds the model's
+    /// context length. Each retry drops part of the first non-empty source.
+    /// Defaults to [`DEFAULT_CONTEXT_DROP_ORDER`] if `None`; an empty list disables recovery.
+    pub context_drop_order: Option<Vec<ContextSource>>,
+
+    /// The caller on whose behalf the request is made, used by routers for decisions such as
+    /// data residency. It is not sent to the provider.
+    pub caller: Option<Principal>,
+}
+
+/// A safety setting that blocks a category of harmful content above a probability threshold.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct SafetySetting {
+    /// The harm category, e.g. "HARM_CATEGORY_HARASSMENT" or "HARM_CATEGORY_DANGEROUS_CONTENT".
+    pub category: String,
+    /// The blocking threshold, e.g. "BLOCK_LOW_AND_ABOVE", "BLOCK_ONLY_HIGH" or "BLOCK_NONE".
+    pub threshold: String,
+}
+
+impl SafetySetting {
+    /// Creates a safety setting.
+    pub fn new(category: impl Into<String>, threshold: impl Into<String>) -> Self {
+        Self {
+            category: category.into(),
+            threshold: threshold.into(),
+        }
+    }
+}
+
+/// A droppable source of context in a [`CompletionRequest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextSource {
+    /// The chat history, dropped from the oldest messages.
+    ChatHistory,
+    /// The documents, dropped from the last ones, which are expected to be the least relevant.
+    Documents,
+}
+
+/// The default order to drop context: old chat history first, then extra documents.
+pub const DEFAULT_CONTEXT_DROP_ORDER: [ContextSource; 2] =
+    [ContextSource::ChatHistory, ContextSource::Documents];
+
+/// The capabilities of a completion model, reported by its provider. Requests are adapted
+/// to them with [`CompletionRequest::adapt_to`] before they are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct ModelCapabilities {
+    /// Whether the model can call tools.
+    pub tools: bool,
+    /// Whether the model accepts image content parts.
+    pub images: bool,
+    /// Whether the model can follow a `response_format`.
+    pub json_mode: bool,
+    /// The context length of the model in tokens, if known.
+    pub max_context: Option<usize>,
+}
+
+impl Default for ModelCapabilities {
+    /// Supports everything, with an unknown context length.
+    fn default() -> Self {
+        Self {
+            tools: true,
+            images: true,
+            json_mode: true,
+            max_context: None,
+        }
+    }
+}
+
+impl ModelCapabilities {
+    /// Returns the capabilities supported by both, e.g. of models that can serve the same
+    /// requests, with the smallest known context length.
+    pub fn intersect(&self, other: &Self) -> Self {
+        Self {
+            tools: self.tools && other.tools,
+            images: self.images && other.images,
+            json_mode: self.json_mode && other.json_mode,
+            max_context: match (self.max_context, other.max_context) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
 }
 
 impl CompletionRequest {
@@ -86,6 +286,94 @@ impl CompletionRequest {
         self
     }
 
+    /// Drops part of the lowest-priority context according to `context_drop_order`:
+    /// a quarter of the chat history (at least one message), or half of the documents.
+    /// Tool results left without their tool call message are dropped too.
+    /// Returns the dropped source, or `None` if there is nothing left to drop.
+    pub fn drop_context(&mut self) -> Option<ContextSource> {
+        let order = self
+            .context_drop_order
+            .clone()
+            .unwrap_or_else(|| DEFAULT_CONTEXT_DROP_ORDER.to_vec());
+        for source in order {
+            match source {
+                ContextSource::ChatHistory if !self.chat_history.is_empty() => {
+                    let len = self.chat_history.len();
+                    let mut n = (len / 4).max(1);
+                    while n < len
+                        && self.chat_history[n].get("role").and_then(|v| v.as_str()) == Some("tool")
+                    {
+                        n += 1;
+                    }
+                    self.chat_history.drain(..n);
+                    return Some(source);
+                }
+                ContextSource::Documents if !self.documents.is_empty() => {
+                    let len = self.documents.len();
+                    self.documents.truncate(len / 2);
+                    return Some(source);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Adapts the request to the capabilities of the model: image parts are dropped for
+    /// text-only models. Returns the number of dropped images, or an error if the request
+    /// needs tools or a response format that the model does not support, or more output
+    /// tokens than its context length.
+    pub fn adapt_to(&mut self, capabilities: &ModelCapabilities) -> Result<usize, BoxError> {
+        if !capabilities.tools && !self.tools.is_empty() {
+            return Err(format!(
+                "the model does not support tools, but the request has {} tools",
+                self.tools.len()
+            )
+            .into());
+        }
+        if !capabilities.json_mode && self.response_format.is_some() {
+            return Err("the model does not support the response format".into());
+        }
+        if let (Some(max_context), Some(max_tokens)) = (capabilities.max_context, self.max_tokens)
+            && max_tokens > max_context
+        {
+            return Err(format!(
+                "max_tokens {} exceeds the context length {} of the model",
+                max_tokens, max_context
+            )
+            .into());
+        }
+        let mut dropped = 0;
+        if !capabilities.images {
+            self.content_parts.retain(|part| {
+                let image = matches!(part, ContentPart::Image { .. });
+                dropped += image as usize;
+                !image
+            });
+        }
+        Ok(dropped)
+    }
+
+    /// Returns the content parts of the user message: the content parts, with the prompt
+    /// with context as the first text part if they have no text part.
+    /// Returns an empty list if there are no content parts.
+    pub fn user_content_parts(&self) -> Vec<ContentPart> {
+        if self.content_parts.is_empty() {
+            return Vec::new();
+        }
+        let mut parts = Vec::with_capacity(self.content_parts.len() + 1);
+        if !self
+            .content_parts
+            .iter()
+            .any(|p| matches!(p, ContentPart::Text { .. }))
+            && let Some(prompt) = self.prompt_with_context()
+        {
+            parts.push(ContentPart::Text { text: prompt });
+        }
+        parts.extend(self.content_parts.iter().cloned());
+        parts
+    }
+
     /// Returns the prompt with context if available.
     pub fn prompt_with_context(&self) -> Option<String> {
         if self.documents.0.is_empty() && self.prompt.is_empty() {
@@ -103,7 +391,7 @@ impl CompletionRequest {
 }
 
 /// Represents a message send to LLM for completion.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct Message {
     /// Message role: "system", "user", "assistant", "tool".
     pub role: String,
@@ -121,7 +409,7 @@ pub struct Message {
 }
 
 /// Knowledge document with text and additional props.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct Document {
     /// The unique identifier for the document in local.
     pub id: String,
@@ -153,7 +441,7 @@ impl From<Knowledge> for Document {
 }
 
 /// Collection of knowledge documents.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct Documents(pub Vec<Document>);
 
 impl From<Vec<String>> for Documents {
@@ -230,12 +518,147 @@ impl std::fmt::Display for Documents {
 }
 
 /// OpenAI style content part for the completion request.
+///
+/// It serializes to the OpenAI format; [`ContentPart::to_anthropic`] converts it to the
+/// Anthropic format.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum ContentPart {
-    Text { text: String },
-    Image { image_url: ImageDetail },
-    Audio { input_audio: AudioDetail },
+    Text {
+        text: String,
+    },
+    #[serde(rename = "image_url", alias = "image")]
+    Image {
+        image_url: ImageDetail,
+    },
+    #[serde(rename = "input_audio", alias = "audio")]
+    Audio {
+        input_audio: AudioDetail,
+    },
+}
+
+impl ContentPart {
+    /// Creates a text part.
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    /// Creates an image part from the URL of the image.
+    pub fn image_url(url: impl Into<String>) -> Self {
+        ContentPart::Image {
+            image_url: ImageDetail {
+                url: url.into(),
+                detail: None,
+            },
+        }
+    }
+
+    /// Creates an image part from the image data, embedded as a base64 data URL.
+    pub fn image_base64(mime_type: &str, data: &[u8]) -> Self {
+        Self::image_url(format!(
+            "data:{};base64,{}",
+            mime_type,
+            BASE64_STANDARD.encode(data)
+        ))
+    }
+
+    /// Creates an image part from an image resource, from its blob or its URI.
+    /// Returns None if the resource is not an image.
+    pub fn from_resource(resource: &Resource) -> Option<Self> {
+        let mime_type = resource.mime_type.as_deref().unwrap_or_default();
+        if resource.tag != "image" && !mime_type.starts_with("image/") {
+            return None;
+        }
+        match (&resource.blob, &resource.uri) {
+            (Some(blob), _) => {
+                let mime_type = if mime_type.is_empty() {
+                    sniff_mime_type(blob)
+                } else {
+                    mime_type
+                };
+                Some(Self::image_base64(mime_type, blob))
+            }
+            (None, Some(uri)) if uri.starts_with("https://") || uri.starts_with("http://") => {
+                Some(Self::image_url(uri.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Sets the detail of an image part: "low", "high" or "auto".
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        if let ContentPart::Image { image_url } = &mut self {
+            image_url.detail = Some(detail.into());
+        }
+        self
+    }
+
+    /// Converts the part to the Anthropic format. Images embedded as data URLs are sent as
+    /// base64 sources, others as URL sources. Audio is not supported by Anthropic and
+    /// is converted to None.
+    pub fn to_anthropic(&self) -> Option<Value> {
+        match self {
+            ContentPart::Text { text } => Some(serde_json::json!({"type": "text", "text": text})),
+            ContentPart::Image { image_url } => {
+                let source = match image_url
+                    .url
+                    .strip_prefix("data:")
+                    .and_then(|rest| rest.split_once(";base64,"))
+                {
+                    Some((media_type, data)) => serde_json::json!({
+                        "type": "base64",
+                        "media_type": media_type,
+                        "data": data,
+                    }),
+                    None => serde_json::json!({"type": "url", "url": image_url.url}),
+                };
+                Some(serde_json::json!({"type": "image", "source": source}))
+            }
+            ContentPart::Audio { .. } => None,
+        }
+    }
+
+    /// Converts the part to a Gemini part. Images embedded as data URLs are sent as inline
+    /// data, others as file data with the MIME type guessed from the URL extension.
+    pub fn to_gemini(&self) -> Value {
+        match self {
+            ContentPart::Text { text } => serde_json::json!({"text": text}),
+            ContentPart::Image { image_url } => {
+                match image_url
+                    .url
+                    .strip_prefix("data:")
+                    .and_then(|rest| rest.split_once(";base64,"))
+                {
+                    Some((mime_type, data)) => serde_json::json!({
+                        "inlineData": {"mimeType": mime_type, "data": data},
+                    }),
+                    None => {
+                        let ext = image_url
+                            .url
+                            .split(['?', '#'])
+                            .next()
+                            .and_then(|path| path.rsplit_once('.'))
+                            .map(|(_, ext)| ext.to_ascii_lowercase());
+                        let mime_type = match ext.as_deref() {
+                            Some("png") => "image/png",
+                            Some("gif") => "image/gif",
+                            Some("webp") => "image/webp",
+                            _ => "image/jpeg",
+                        };
+                        serde_json::json!({
+                            "fileData": {"mimeType": mime_type, "fileUri": image_url.url},
+                        })
+                    }
+                }
+            }
+            ContentPart::Audio { input_audio } => serde_json::json!({
+                "inlineData": {
+                    "mimeType": format!("audio/{}", input_audio.format),
+                    "data": input_audio.data,
+                },
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -281,7 +704,198 @@ impl FromStr for ContentPart {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::{json, to_string};
+    use serde_json::to_string;
+    use std::sync::Mutex;
+
+    /// Returns the scripted contents in order and records the requests.
+    struct ScriptedCompleter {
+        contents: Mutex<Vec<&'static str>>,
+        requests: Mutex<Vec<CompletionRequest>>,
+    }
+
+    impl CompletionFeatures for ScriptedCompleter {
+        async fn completion(
+            &self,
+            req: CompletionRequest,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<AgentOutput, BoxError> {
+            self.requests.lock().unwrap().push(req);
+            let content = self.contents.lock().unwrap().remove(0);
+            Ok(AgentOutput {
+                content: content.to_string(),
+                usage: Usage {
+                    input_tokens: 10,
+                    output_tokens: 5,
+                    requests: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+        }
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    struct Contact {
+        name: String,
+        age: u32,
+    }
+
+    #[test]
+    fn test_completion_structured() {
+        let completer = ScriptedCompleter {
+            contents: Mutex::new(vec![
+                "Sure! {\"name\": \"Alice\"}",
+                "```json\n{\"name\": \"Alice\", \"age\": 30}\n```",
+            ]),
+            requests: Mutex::new(Vec::new()),
+        };
+        let req = CompletionRequest::builder()
+            .system("Extract the contact.")
+            .prompt("Alice is 30 years old.")
+            .build();
+        let (contact, output) =
+            futures::executor::block_on(completer.completion_structured::<Contact>(req.clone(), 1))
+                .unwrap();
+        assert_eq!(
+            contact,
+            Contact {
+                name: "Alice".to_string(),
+                age: 30
+            }
+        );
+        assert_eq!(output.usage.input_tokens, 20);
+        assert_eq!(output.usage.requests, 2);
+
+        let requests = completer.requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 2);
+        let format = requests[0].response_format.as_ref().unwrap();
+        assert_eq!(format["json_schema"]["name"], "Contact");
+        assert_eq!(
+            format["json_schema"]["schema"]["required"],
+            json!(["age", "name"])
+        );
+        // the retry continues the conversation with the error
+        assert_eq!(requests[1].chat_history.len(), 2);
+        assert_eq!(
+            requests[1].chat_history[0]["content"],
+            "Alice is 30 years old."
+        );
+        assert!(
+            requests[1]
+                .prompt
+                .contains("missing required property \"age\""),
+            "{}",
+            requests[1].prompt
+        );
+
+        let completer = ScriptedCompleter {
+            contents: Mutex::new(vec!["not json", "{\"name\": 1, \"age\": 30}"]),
+            requests: Mutex::new(Vec::new()),
+        };
+        let err = futures::executor::block_on(completer.completion_structured::<Contact>(req, 1))
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("after 2 attempts: $.name: expected string"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_adapt_to() {
+        let text_only = ModelCapabilities {
+            images: false,
+            max_context: Some(1000),
+            ..Default::default()
+        };
+        let mut req = CompletionRequest {
+            content_parts: vec![
+                ContentPart::text("what is in the picture?"),
+                ContentPart::image_url("https://example.com/a.png"),
+            ],
+            max_tokens: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(req.adapt_to(&ModelCapabilities::default()).unwrap(), 0);
+        assert_eq!(req.content_parts.len(), 2);
+        assert_eq!(req.adapt_to(&text_only).unwrap(), 1);
+        assert_eq!(
+            req.content_parts,
+            vec![ContentPart::text("what is in the picture?")]
+        );
+
+        req.max_tokens = Some(2000);
+        assert!(req.adapt_to(&text_only).is_err());
+        req.max_tokens = None;
+        req.response_format = Some(json!({"type": "json_object"}));
+        let no_json = ModelCapabilities {
+            json_mode: false,
+            ..Default::default()
+        };
+        assert!(req.adapt_to(&no_json).is_err());
+        req.response_format = None;
+        req.tools.push(FunctionDefinition {
+            name: "search".to_string(),
+            ..Default::default()
+        });
+        let no_tools = ModelCapabilities {
+            tools: false,
+            ..Default::default()
+        };
+        assert!(req.adapt_to(&no_tools).is_err());
+
+        let caps = text_only.intersect(&no_tools);
+        assert!(!caps.tools && !caps.images && caps.json_mode);
+        assert_eq!(caps.max_context, Some(1000));
+    }
+
+    #[test]
+    fn test_drop_context() {
+        let mut req = CompletionRequest {
+            chat_history: vec![
+                json!({"role": "user", "content": "1"}),
+                json!({"role": "assistant", "content": "2"}),
+                json!({"role": "user", "content": "3"}),
+                json!({"role": "assistant", "content": "", "tool_calls": []}),
+                json!({"role": "tool", "content": "4"}),
+                json!({"role": "tool", "content": "5"}),
+                json!({"role": "user", "content": "6"}),
+                json!({"role": "assistant", "content": "7"}),
+            ],
+            documents: vec!["a".to_string(), "b".to_string(), "c".to_string()].into(),
+            ..Default::default()
+        };
+
+        assert_eq!(req.drop_context(), Some(ContextSource::ChatHistory));
+        assert_eq!(req.chat_history.len(), 6);
+        assert_eq!(req.drop_context(), Some(ContextSource::ChatHistory));
+        assert_eq!(req.chat_history.len(), 5);
+        assert_eq!(req.drop_context(), Some(ContextSource::ChatHistory));
+        // orphaned tool results are dropped with their tool call
+        assert_eq!(req.chat_history.len(), 2);
+        assert_eq!(req.chat_history[0]["content"], "6");
+        assert_eq!(req.drop_context(), Some(ContextSource::ChatHistory));
+        assert_eq!(req.drop_context(), Some(ContextSource::ChatHistory));
+        assert!(req.chat_history.is_empty());
+        assert_eq!(req.drop_context(), Some(ContextSource::Documents));
+        assert_eq!(req.documents.len(), 1);
+        assert_eq!(req.drop_context(), Some(ContextSource::Documents));
+        assert!(req.documents.is_empty());
+        assert_eq!(req.drop_context(), None);
+
+        let mut req = CompletionRequest {
+            chat_history: vec![json!({"role": "user", "content": "1"})],
+            documents: vec!["a".to_string()].into(),
+            context_drop_order: Some(vec![ContextSource::Documents]),
+            ..Default::default()
+        };
+        assert_eq!(req.drop_context(), Some(ContextSource::Documents));
+        assert_eq!(req.drop_context(), None);
+        assert_eq!(req.chat_history.len(), 1);
+
+        req.context_drop_order = Some(vec![]);
+        assert_eq!(req.drop_context(), None);
+    }
 
     #[test]
     fn test_prompt() {
@@ -348,7 +962,7 @@ mod tests {
         let json = serde_json::to_string(&content).unwrap();
         assert_eq!(
             json,
-            r#"{"type":"image","image_url":{"url":"https://example.com/image.jpg","detail":"high"}}"#
+            r#"{"type":"image_url","image_url":{"url":"https://example.com/image.jpg","detail":"high"}}"#
         );
 
         let ct: ContentPart = serde_json::from_str(&json).unwrap();
@@ -367,7 +981,61 @@ mod tests {
         .unwrap();
         assert_eq!(
             json,
-            r#"[{"text":"What's in this image?","type":"text"},{"image_url":{"url":"https://example.com/image.jpg"},"type":"image"}]"#
+            r#"[{"text":"What's in this image?","type":"text"},{"image_url":{"url":"https://example.com/image.jpg"},"type":"image_url"}]"#
+        );
+    }
+
+    #[test]
+    fn test_image_content_parts() {
+        // the former type names are still accepted
+        let part: ContentPart =
+            serde_json::from_value(json!({"type": "image", "image_url": {"url": "u"}})).unwrap();
+        assert_eq!(part, ContentPart::image_url("u"));
+
+        let resource = Resource::from_bytes(None, b"\x89PNG\r\n\x1a\n".to_vec()).unwrap();
+        let part = ContentPart::from_resource(&resource).unwrap();
+        assert_eq!(
+            part,
+            ContentPart::image_base64("image/png", b"\x89PNG\r\n\x1a\n")
+        );
+        assert_eq!(
+            part.to_anthropic().unwrap(),
+            json!({"type": "image", "source": {
+                "type": "base64",
+                "media_type": "image/png",
+                "data": "iVBORw0KGgo=",
+            }})
+        );
+        assert_eq!(
+            ContentPart::image_url("https://example.com/a.png")
+                .to_anthropic()
+                .unwrap(),
+            json!({"type": "image", "source": {"type": "url", "url": "https://example.com/a.png"}})
+        );
+        assert_eq!(
+            part.to_gemini(),
+            json!({"inlineData": {"mimeType": "image/png", "data": "iVBORw0KGgo="}})
+        );
+        assert_eq!(
+            ContentPart::image_url("https://example.com/a.WEBP?v=1").to_gemini(),
+            json!({"fileData": {"mimeType": "image/webp", "fileUri": "https://example.com/a.WEBP?v=1"}})
+        );
+        let text = Resource::from_bytes(None, b"hello".to_vec()).unwrap();
+        assert!(ContentPart::from_resource(&text).is_none());
+
+        let req = CompletionRequest::builder()
+            .content_part(part.clone())
+            .prompt("Describe it")
+            .build();
+        assert_eq!(
+            req.user_content_parts(),
+            vec![ContentPart::text("Describe it"), part.clone()]
         );
+        let req = CompletionRequest::builder()
+            .content_part(ContentPart::text("What is this?"))
+            .content_part(part)
+            .prompt("ignored")
+            .build();
+        assert_eq!(req.user_content_parts(), req.content_parts);
     }
 }
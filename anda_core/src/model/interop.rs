@@ -0,0 +1,258 @@
+use serde_json::{Map, json};
+use std::collections::BTreeMap;
+
+use super::{FunctionDefinition, Knowledge, KnowledgeInput, Value};
+use crate::BoxError;
+
+/// The JSON formats of Python RAG frameworks, to migrate knowledge documents and tool
+/// definitions between them and Anda.
+///
+/// - LangChain documents are serialized by `langchain_core.load.dumpd`, tools are
+///   `{"name", "description", "args_schema"}`;
+/// - LlamaIndex documents are serialized by `Document.to_dict`, tools are `ToolMetadata`,
+///   `{"name", "description", "fn_schema"}`.
+///
+/// Imports also accept the plain `Document.dict()` form of LangChain documents, and the
+/// OpenAI function format of tools that both frameworks export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InteropFormat {
+    LangChain,
+    LlamaIndex,
+}
+
+impl InteropFormat {
+    /// Exports a knowledge document. The user is kept in the metadata.
+    pub fn export_knowledge(&self, doc: &Knowledge) -> Value {
+        let mut metadata: Map<String, Value> = doc
+            .meta
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        metadata.insert("user".to_string(), doc.user.clone().into());
+        match self {
+            Self::LangChain => json!({
+                "lc": 1,
+                "type": "constructor",
+                "id": ["langchain", "schema", "document", "Document"],
+                "kwargs": {
+                    "id": doc.id,
+                    "page_content": doc.text,
+                    "metadata": metadata,
+                    "type": "Document",
+                },
+            }),
+            Self::LlamaIndex => json!({
+                "id_": doc.id,
+                "text": doc.text,
+                "metadata": metadata,
+                "class_name": "Document",
+            }),
+        }
+    }
+
+    /// Imports a knowledge document, ready to be embedded and added to the knowledge store.
+    /// The document ID, if any, is kept as the "source_id" metadata. The embedding of a
+    /// LlamaIndex document, if any, is kept as the vector.
+    pub fn import_knowledge(&self, value: &Value) -> Result<KnowledgeInput, BoxError> {
+        let (id, text, metadata, vec) = match self {
+            Self::LangChain => {
+                let doc = match value.get("kwargs") {
+                    Some(kwargs) if value.get("lc").is_some() => kwargs,
+                    _ => value,
+                };
+                (
+                    doc.get("id"),
+                    doc.get("page_content"),
+                    doc.get("metadata"),
+                    None,
+                )
+            }
+            Self::LlamaIndex => (
+                value.get("id_"),
+                value.get("text"),
+                value.get("metadata"),
+                value.get("embedding"),
+            ),
+        };
+
+        let text = text
+            .and_then(|v| v.as_str())
+            .ok_or("missing document text")?
+            .to_string();
+        let mut meta: BTreeMap<String, Value> = match metadata {
+            Some(Value::Object(m)) => m.clone().into_iter().collect(),
+            Some(Value::Null) | None => BTreeMap::new(),
+            Some(_) => return Err("invalid document metadata".into()),
+        };
+        let user = match meta.remove("user") {
+            Some(Value::String(user)) => user,
+            _ => String::new(),
+        };
+        if let Some(Value::String(id)) = id {
+            meta.insert("source_id".to_string(), id.clone().into());
+        }
+        let vec = match vec {
+            Some(Value::Array(vec)) => vec
+                .iter()
+                .map(|v| v.as_f64().map(|v| v as f32))
+                .collect::<Option<Vec<_>>>()
+                .ok_or("invalid document embedding")?,
+            _ => Vec::new(),
+        };
+        Ok(KnowledgeInput {
+            user,
+            text,
+            meta,
+            vec,
+        })
+    }
+
+    /// Exports a tool definition.
+    pub fn export_tool(&self, definition: &FunctionDefinition) -> Value {
+        let schema_field = match self {
+            Self::LangChain => "args_schema",
+            Self::LlamaIndex => "fn_schema",
+        };
+        let mut tool = Map::new();
+        tool.insert("name".to_string(), definition.name.clone().into());
+        tool.insert(
+            "description".to_string(),
+            definition.description.clone().into(),
+        );
+        tool.insert(schema_field.to_string(), definition.parameters.clone());
+        Value::Object(tool)
+    }
+
+    /// Imports a tool definition, e.g. to describe a Python tool served by a remote engine.
+    pub fn import_tool(&self, value: &Value) -> Result<FunctionDefinition, BoxError> {
+        let value = match value.get("function") {
+            Some(function) if value.get("type").and_then(|v| v.as_str()) == Some("function") => {
+                function
+            }
+            _ => value,
+        };
+        let name = value
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or("missing tool name")?;
+        let description = value
+            .get("description")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        let fields: &[&str] = match self {
+            Self::LangChain => &["args_schema", "args", "parameters"],
+            Self::LlamaIndex => &["fn_schema", "parameters"],
+        };
+        let parameters = match fields.iter().find_map(|f| value.get(*f)) {
+            // LangChain's `tool.args` are the properties of the schema
+            Some(Value::Object(args))
+                if value.get("args").is_some() && !args.contains_key("type") =>
+            {
+                json!({"type": "object", "properties": args})
+            }
+            Some(schema @ Value::Object(_)) => schema.clone(),
+            _ => json!({"type": "object", "properties": {}}),
+        };
+        Ok(FunctionDefinition {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters,
+            strict: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interop_knowledge() {
+        let doc = Knowledge {
+            id: "doc_1".to_string(),
+            user: "alice".to_string(),
+            text: "Anda is an AI agent framework.".to_string(),
+            meta: BTreeMap::from([("source".to_string(), json!("README.md"))]),
+        };
+
+        for format in [InteropFormat::LangChain, InteropFormat::LlamaIndex] {
+            let value = format.export_knowledge(&doc);
+            let input = format.import_knowledge(&value).unwrap();
+            assert_eq!(input.user, "alice");
+            assert_eq!(input.text, doc.text);
+            assert_eq!(input.meta["source"], json!("README.md"));
+            assert_eq!(input.meta["source_id"], json!("doc_1"));
+        }
+
+        let input = InteropFormat::LangChain
+            .import_knowledge(&json!({
+                "page_content": "hello",
+                "metadata": {"page": 1},
+                "type": "Document",
+            }))
+            .unwrap();
+        assert_eq!(input.text, "hello");
+        assert_eq!(input.user, "");
+
+        let input = InteropFormat::LlamaIndex
+            .import_knowledge(&json!({
+                "id_": "n1",
+                "text": "hello",
+                "metadata": {},
+                "embedding": [0.5, 1.0],
+            }))
+            .unwrap();
+        assert_eq!(input.vec, vec![0.5, 1.0]);
+        assert!(
+            InteropFormat::LlamaIndex
+                .import_knowledge(&json!({}))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_interop_tools() {
+        let definition = FunctionDefinition {
+            name: "get_weather".to_string(),
+            description: "Gets the weather of a city.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"],
+            }),
+            strict: Some(true),
+        };
+
+        for format in [InteropFormat::LangChain, InteropFormat::LlamaIndex] {
+            let value = format.export_tool(&definition);
+            let def = format.import_tool(&value).unwrap();
+            assert_eq!(def.name, definition.name);
+            assert_eq!(def.description, definition.description);
+            assert_eq!(def.parameters, definition.parameters);
+        }
+        assert!(
+            InteropFormat::LangChain
+                .export_tool(&definition)
+                .get("args_schema")
+                .is_some()
+        );
+
+        let def = InteropFormat::LangChain
+            .import_tool(&json!({
+                "name": "search",
+                "description": "Searches the web.",
+                "args": {"query": {"type": "string"}},
+            }))
+            .unwrap();
+        assert_eq!(def.parameters["properties"]["query"]["type"], "string");
+
+        let def = InteropFormat::LlamaIndex
+            .import_tool(&json!({
+                "type": "function",
+                "function": {"name": "search", "parameters": {"type": "object"}},
+            }))
+            .unwrap();
+        assert_eq!(def.name, "search");
+        assert_eq!(def.parameters, json!({"type": "object"}));
+    }
+}
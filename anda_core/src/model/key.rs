@@ -0,0 +1,121 @@
+use candid::{CandidType, Principal};
+use ic_cose_types::{
+    cose::{ed25519::ed25519_verify, sha3_256},
+    to_cbor_bytes,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{ByteArrayB64, ByteBufB64};
+
+/// A generation of an engine's Ed25519 key.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct KeyEpoch {
+    /// The epoch number, starting from 0 and incremented on each rotation.
+    pub epoch: u32,
+
+    /// The Ed25519 public key.
+    pub public_key: ByteArrayB64<32>,
+
+    /// The unix timestamp in milliseconds from which the key is valid.
+    pub not_before: u64,
+
+    /// The unix timestamp in milliseconds until which the key is valid.
+    /// None for the current key.
+    pub not_after: Option<u64>,
+
+    /// The TEE attestation of the key, if any.
+    pub attestation: Option<ByteBufB64>,
+}
+
+impl KeyEpoch {
+    /// Returns true if the key is valid at the given time.
+    pub fn is_valid_at(&self, now_ms: u64) -> bool {
+        self.not_before <= now_ms && self.not_after.is_none_or(|t| now_ms < t)
+    }
+}
+
+/// The announcement of a key rotation, sent to the engine's peers.
+///
+/// It is signed by the previous key, so that peers who trust the previous key can trust
+/// the new one, and by the new key as proof of possession.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct KeyRotation {
+    /// The engine whose key is rotated.
+    pub engine: Principal,
+
+    /// The purpose of the key, e.g. "receipt".
+    pub purpose: String,
+
+    /// The previous key, valid until the end of the overlap.
+    pub previous: KeyEpoch,
+
+    /// The new key.
+    pub current: KeyEpoch,
+
+    /// The signature of the previous key over the digest.
+    pub previous_signature: ByteArrayB64<64>,
+
+    /// The signature of the new key over the digest.
+    pub current_signature: ByteArrayB64<64>,
+}
+
+impl KeyRotation {
+    /// Returns the SHA3-256 digest of the CBOR of `(engine, purpose, previous, current)`,
+    /// which is signed by both keys.
+    pub fn digest(&self) -> [u8; 32] {
+        sha3_256(&to_cbor_bytes(&(
+            &self.engine,
+            &self.purpose,
+            &self.previous,
+            &self.current,
+        )))
+    }
+
+    /// Verifies both signatures of the rotation.
+    pub fn verify(&self) -> Result<(), String> {
+        if self.current.epoch != self.previous.epoch + 1 {
+            return Err(format!(
+                "invalid key epoch {}, expected {}",
+                self.current.epoch,
+                self.previous.epoch + 1
+            ));
+        }
+        let digest = self.digest();
+        ed25519_verify(
+            &self.previous.public_key,
+            &digest,
+            self.previous_signature.as_slice(),
+        )?;
+        ed25519_verify(
+            &self.current.public_key,
+            &digest,
+            self.current_signature.as_slice(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_epoch() {
+        let key = KeyEpoch {
+            epoch: 1,
+            public_key: [1u8; 32].into(),
+            not_before: 100,
+            not_after: Some(200),
+            attestation: None,
+        };
+        assert!(!key.is_valid_at(99));
+        assert!(key.is_valid_at(100));
+        assert!(key.is_valid_at(199));
+        assert!(!key.is_valid_at(200));
+
+        let current = KeyEpoch {
+            not_after: None,
+            ..key
+        };
+        assert!(current.is_valid_at(u64::MAX));
+    }
+}
@@ -1,8 +1,12 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
 
+use candid::Principal;
 use serde::{Deserialize, Serialize};
 
-use super::Value;
+use super::{META_LANG, Value, Xid, detect_language, normalize_text};
 use crate::BoxError;
 
 /// Represents a knowledge document with user, text, and metadata.
@@ -14,6 +18,25 @@ pub struct Knowledge {
     pub meta: BTreeMap<String, Value>,
 }
 
+/// The metadata key of the source of a document, e.g. a URL or a file name.
+pub const META_SOURCE: &str = "source";
+
+/// The metadata key of the principal of the author of a document, in text form.
+pub const META_AUTHOR: &str = "author";
+
+/// The metadata key of the tags of a document, an array of strings.
+pub const META_TAGS: &str = "tags";
+
+impl Knowledge {
+    /// Returns the creation time of the document in unix milliseconds, with a precision of
+    /// seconds, if its ID is an xid.
+    pub fn created_at(&self) -> Option<u64> {
+        let id: Xid = self.id.parse().ok()?;
+        let secs = u32::from_be_bytes([id.0[0], id.0[1], id.0[2], id.0[3]]);
+        Some(secs as u64 * 1000)
+    }
+}
+
 /// Represents a knowledge document input with user, text, metadata, and vector.
 #[derive(Debug, Clone, Default)]
 pub struct KnowledgeInput {
@@ -23,6 +46,142 @@ pub struct KnowledgeInput {
     pub vec: Vec<f32>,
 }
 
+impl KnowledgeInput {
+    /// Normalizes the text with [`normalize_text`], and records its detected language in
+    /// the [`META_LANG`] metadata unless it is set.
+    pub fn normalize(mut self) -> Self {
+        self.text = normalize_text(&self.text);
+        if !self.meta.contains_key(META_LANG)
+            && let Some(lang) = detect_language(&self.text)
+        {
+            self.meta.insert(META_LANG.to_string(), lang.into());
+        }
+        self
+    }
+}
+
+/// Filters the documents of a knowledge search.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KnowledgeFilter {
+    /// The user who added the documents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// The language of the documents, an ISO 639-1 code, see [`META_LANG`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+
+    /// The source of the documents, see [`META_SOURCE`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    /// The author of the documents, see [`META_AUTHOR`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<Principal>,
+
+    /// The tags that the documents must all have, see [`META_TAGS`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// The documents created at or after the time, in unix milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_after: Option<u64>,
+
+    /// The documents created before the time, in unix milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<u64>,
+}
+
+impl KnowledgeFilter {
+    /// Returns true if the filter has no condition.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Returns true if the document matches all the conditions of the filter.
+    pub fn matches(&self, doc: &Knowledge) -> bool {
+        let meta_str = |key: &str| doc.meta.get(key).and_then(|v| v.as_str());
+        if let Some(user) = &self.user
+            && !doc.user.eq_ignore_ascii_case(user)
+        {
+            return false;
+        }
+        if let Some(lang) = &self.lang
+            && !meta_str(META_LANG).is_some_and(|v| v.eq_ignore_ascii_case(lang))
+        {
+            return false;
+        }
+        if let Some(source) = &self.source
+            && meta_str(META_SOURCE) != Some(source.as_str())
+        {
+            return false;
+        }
+        if let Some(author) = &self.author
+            && meta_str(META_AUTHOR) != Some(author.to_text().as_str())
+        {
+            return false;
+        }
+        if !self.tags.is_empty() {
+            let tags = doc.meta.get(META_TAGS).and_then(|v| v.as_array());
+            if !self.tags.iter().all(|tag| {
+                tags.is_some_and(|tags| tags.iter().any(|t| t.as_str() == Some(tag.as_str())))
+            }) {
+                return false;
+            }
+        }
+        if self.created_after.is_some() || self.created_before.is_some() {
+            let Some(created_at) = doc.created_at() else {
+                return false;
+            };
+            if self.created_after.is_some_and(|t| created_at < t)
+                || self.created_before.is_some_and(|t| created_at >= t)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The constant k of [`reciprocal_rank_fusion`], which damps the weight of the top ranks.
+pub const RRF_K: f32 = 60.0;
+
+/// The retrieval mode of a knowledge search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// Ranks the documents by vector similarity to the query's embedding.
+    Vector,
+    /// Ranks the documents by BM25 over the full-text index, which matches exact
+    /// identifiers and code symbols.
+    Keyword,
+    /// Fuses the vector and keyword rankings by [`reciprocal_rank_fusion`].
+    #[default]
+    Hybrid,
+}
+
+/// Fuses ranked lists by reciprocal rank fusion: an item scores the sum of `1 / (k + rank)`
+/// over the lists it appears in, ranks starting at 1. Returns the items with their scores,
+/// the highest first, ties in the order the items first appear.
+pub fn reciprocal_rank_fusion<T: Clone + Eq + Hash>(lists: &[Vec<T>], k: f32) -> Vec<(T, f32)> {
+    let mut fused: Vec<(T, f32)> = Vec::new();
+    let mut index: HashMap<T, usize> = HashMap::new();
+    for list in lists {
+        for (rank, item) in list.iter().enumerate() {
+            let score = 1.0 / (k + rank as f32 + 1.0);
+            match index.get(item) {
+                Some(&i) => fused[i].1 += score,
+                None => {
+                    index.insert(item.clone(), fused.len());
+                    fused.push((item.clone(), score));
+                }
+            }
+        }
+    }
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1));
+    fused
+}
+
 /// Provides knowledge management capabilities for agents.
 pub trait KnowledgeFeatures: Sized {
     /// Performs a semantic search to find top n most similar documents
@@ -34,6 +193,15 @@ pub trait KnowledgeFeatures: Sized {
         user: Option<String>,
     ) -> impl Future<Output = Result<Vec<Knowledge>, BoxError>> + Send;
 
+    /// Performs a semantic search to find top n most similar documents that match the filter,
+    /// which is applied by the underlying store
+    fn knowledge_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> impl Future<Output = Result<Vec<Knowledge>, BoxError>> + Send;
+
     /// Retrieves the latest n Knowledge documents created in last N seconds
     fn knowledge_latest_n(
         &self,
@@ -48,3 +216,121 @@ pub trait KnowledgeFeatures: Sized {
         docs: Vec<KnowledgeInput>,
     ) -> impl std::future::Future<Output = Result<(), BoxError>> + Send;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let vector = vec!["a", "b", "c"];
+        let keyword = vec!["c", "d", "b"];
+        let fused = reciprocal_rank_fusion(&[vector, keyword], RRF_K);
+        let items: Vec<&str> = fused.iter().map(|(item, _)| *item).collect();
+        // b and c appear in both lists, a precedes d at the same rank
+        assert_eq!(items, vec!["c", "b", "a", "d"]);
+        assert!((fused[0].1 - (1.0 / 63.0 + 1.0 / 61.0)).abs() < 1e-6);
+        assert!((fused[2].1 - 1.0 / 61.0).abs() < 1e-6);
+
+        assert!(reciprocal_rank_fusion::<&str>(&[], RRF_K).is_empty());
+        let single = reciprocal_rank_fusion(&[vec![1, 2]], RRF_K);
+        assert_eq!(single[0].0, 1);
+        assert_eq!(single[1].0, 2);
+    }
+
+    #[test]
+    fn test_knowledge_filter() {
+        let author = Principal::from_text("aaaaa-aa").unwrap();
+        let mut meta = BTreeMap::new();
+        meta.insert(META_LANG.to_string(), Value::from("en"));
+        meta.insert(META_SOURCE.to_string(), Value::from("docs/intro.md"));
+        meta.insert(META_AUTHOR.to_string(), Value::from(author.to_text()));
+        meta.insert(META_TAGS.to_string(), serde_json::json!(["rust", "icp"]));
+        let mut id = Xid::default();
+        id.0[..4].copy_from_slice(&1_700_000_000u32.to_be_bytes());
+        let doc = Knowledge {
+            id: id.to_string(),
+            user: "anda".to_string(),
+            text: "Anda is an AI agent framework.".to_string(),
+            meta,
+        };
+        assert_eq!(doc.created_at(), Some(1_700_000_000_000));
+
+        let filter = KnowledgeFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.matches(&doc));
+
+        let filter = KnowledgeFilter {
+            user: Some("Anda".to_string()),
+            lang: Some("EN".to_string()),
+            source: Some("docs/intro.md".to_string()),
+            author: Some(author),
+            tags: vec!["icp".to_string(), "rust".to_string()],
+            created_after: Some(1_700_000_000_000),
+            created_before: Some(1_700_000_001_000),
+        };
+        assert!(!filter.is_empty());
+        assert!(filter.matches(&doc));
+
+        for filter in [
+            KnowledgeFilter {
+                user: Some("dom".to_string()),
+                ..Default::default()
+            },
+            KnowledgeFilter {
+                source: Some("docs/intro".to_string()),
+                ..Default::default()
+            },
+            KnowledgeFilter {
+                author: Some(Principal::anonymous()),
+                ..Default::default()
+            },
+            KnowledgeFilter {
+                tags: vec!["rust".to_string(), "go".to_string()],
+                ..Default::default()
+            },
+            KnowledgeFilter {
+                created_after: Some(1_700_000_000_001),
+                ..Default::default()
+            },
+            KnowledgeFilter {
+                created_before: Some(1_700_000_000_000),
+                ..Default::default()
+            },
+        ] {
+            assert!(!filter.matches(&doc), "{:?}", filter);
+        }
+
+        let doc = Knowledge {
+            id: "1".to_string(),
+            ..doc
+        };
+        assert!(doc.created_at().is_none());
+        let filter = KnowledgeFilter {
+            created_before: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&doc));
+    }
+
+    #[test]
+    fn test_normalize_knowledge_input() {
+        let input = KnowledgeInput {
+            text: "Tom &amp; Jerry don&rsquo;t like the cafÃ©.".to_string(),
+            ..Default::default()
+        }
+        .normalize();
+        assert_eq!(input.text, "Tom & Jerry don’t like the café.");
+        assert_eq!(input.meta.get(META_LANG), Some(&Value::from("en")));
+
+        let mut meta = BTreeMap::new();
+        meta.insert(META_LANG.to_string(), Value::from("fr"));
+        let input = KnowledgeInput {
+            text: "What is the weather?".to_string(),
+            meta,
+            ..Default::default()
+        }
+        .normalize();
+        assert_eq!(input.meta.get(META_LANG), Some(&Value::from("fr")));
+    }
+}
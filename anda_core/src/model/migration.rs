@@ -0,0 +1,184 @@
+use ciborium::{Value as CborValue, tag::Required};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
+
+use crate::BoxError;
+
+/// The CBOR tag of [`Versioned`] data, "anda" in ASCII.
+pub const VERSIONED_TAG: u64 = 0x616e_6461;
+
+/// A persisted type with a schema version and the upgrades from its previous versions.
+///
+/// Data stored before the type was versioned, without the [`VERSIONED_TAG`], is version 0.
+/// When the persisted form of a type changes, bump [`Migrate::VERSION`] and upgrade the
+/// data of the previous version in [`Migrate::upgrade`], so that engines keep reading what
+/// older engines stored. Data of a newer version is rejected rather than silently truncated.
+pub trait Migrate: Sized {
+    /// The current version of the type.
+    const VERSION: u32;
+
+    /// Upgrades the data stored at `version` to `version + 1`.
+    /// The default implementation keeps the data as it is.
+    fn upgrade(version: u32, value: CborValue) -> Result<CborValue, BoxError> {
+        let _ = version;
+        Ok(value)
+    }
+}
+
+impl<T: Migrate> Migrate for Vec<T> {
+    const VERSION: u32 = T::VERSION;
+
+    fn upgrade(version: u32, value: CborValue) -> Result<CborValue, BoxError> {
+        match value {
+            CborValue::Array(items) => Ok(CborValue::Array(
+                items
+                    .into_iter()
+                    .map(|v| T::upgrade(version, v))
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => Err("invalid versioned data: expected an array".into()),
+        }
+    }
+}
+
+/// A wrapper that persists a [`Migrate`] type with its version, and upgrades older data
+/// when reading it. It is encoded as `VERSIONED_TAG([version, data])` in CBOR, and reads
+/// both tagged data and untagged legacy data.
+///
+/// ```rust,ignore
+/// let (Versioned(meta), ver) = ctx.cache_store_get::<Versioned<ThreadMeta>>(&key).await?;
+/// ctx.cache_store_set(&key, Versioned(meta), Some(ver)).await?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Versioned<T>(pub T);
+
+impl<T: Migrate + DeserializeOwned> Versioned<T> {
+    /// Decodes versioned or legacy CBOR data, upgrading it to the current version.
+    pub fn decode(data: &[u8]) -> Result<T, BoxError> {
+        let value: CborValue = ciborium::from_reader(data)?;
+        Self::from_value(value)
+    }
+
+    /// Converts a versioned or legacy CBOR value, upgrading it to the current version.
+    pub fn from_value(value: CborValue) -> Result<T, BoxError> {
+        let (mut version, mut value) = match value {
+            CborValue::Tag(VERSIONED_TAG, inner) => match *inner {
+                CborValue::Array(mut items) if items.len() == 2 => {
+                    let data = items.pop().unwrap();
+                    let version = items
+                        .pop()
+                        .and_then(|v| v.as_integer())
+                        .and_then(|v| u32::try_from(v).ok())
+                        .ok_or("invalid versioned data: invalid version")?;
+                    (version, data)
+                }
+                _ => return Err("invalid versioned data".into()),
+            },
+            value => (0, value),
+        };
+
+        if version > T::VERSION {
+            return Err(format!(
+                "data version {} is newer than the supported version {}",
+                version,
+                T::VERSION
+            )
+            .into());
+        }
+        while version < T::VERSION {
+            value = T::upgrade(version, value).map_err(|err| {
+                format!("failed to upgrade data from version {}: {}", version, err)
+            })?;
+            version += 1;
+        }
+        value.deserialized().map_err(|err| err.into())
+    }
+}
+
+impl<T: Migrate + Serialize> Serialize for Versioned<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Required::<_, VERSIONED_TAG>((T::VERSION, &self.0)).serialize(serializer)
+    }
+}
+
+impl<'de, T: Migrate + DeserializeOwned> Deserialize<'de> for Versioned<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = CborValue::deserialize(deserializer)?;
+        Self::from_value(value)
+            .map(Versioned)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cose_types::to_cbor_bytes;
+
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    struct NoteV1 {
+        text: String,
+    }
+
+    impl Migrate for NoteV1 {
+        const VERSION: u32 = 1;
+    }
+
+    /// Version 2 renamed `text` to `content` and added `tags`.
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    struct Note {
+        content: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    }
+
+    impl Migrate for Note {
+        const VERSION: u32 = 2;
+
+        fn upgrade(version: u32, mut value: CborValue) -> Result<CborValue, BoxError> {
+            if version == 1 {
+                let map = value.as_map_mut().ok_or("expected a map")?;
+                for (k, _) in map.iter_mut() {
+                    if k.as_text() == Some("text") {
+                        *k = "content".into();
+                    }
+                }
+            }
+            Ok(value)
+        }
+    }
+
+    #[test]
+    fn test_versioned() {
+        let legacy = to_cbor_bytes(&NoteV1 {
+            text: "hello".to_string(),
+        });
+        let v1 = to_cbor_bytes(&Versioned(NoteV1 {
+            text: "hello".to_string(),
+        }));
+        assert_eq!(Versioned::<NoteV1>::decode(&legacy).unwrap().text, "hello");
+        assert_eq!(Versioned::<NoteV1>::decode(&v1).unwrap().text, "hello");
+
+        let note = Note {
+            content: "hello".to_string(),
+            tags: vec![],
+        };
+        assert_eq!(Versioned::<Note>::decode(&legacy).unwrap(), note);
+        assert_eq!(Versioned::<Note>::decode(&v1).unwrap(), note);
+
+        let v2 = to_cbor_bytes(&Versioned(note.clone()));
+        let Versioned(res) = ciborium::from_reader::<Versioned<Note>, _>(&v2[..]).unwrap();
+        assert_eq!(res, note);
+        assert!(
+            Versioned::<NoteV1>::decode(&v2)
+                .unwrap_err()
+                .to_string()
+                .contains("newer")
+        );
+
+        let notes = to_cbor_bytes(&vec![NoteV1 {
+            text: "hi".to_string(),
+        }]);
+        let res = Versioned::<Vec<Note>>::decode(&notes).unwrap();
+        assert_eq!(res[0].content, "hi");
+    }
+}
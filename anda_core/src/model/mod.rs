@@ -3,33 +3,71 @@
 //! This module defines the fundamental data structures and interfaces used throughout the AI agent system.
 //! It includes:
 //! - Core message and conversation structures ([`AgentOutput`], [`Message`], [`ToolCall`]).
-//! - Function definition and tooling support ([`FunctionDefinition`]).
+//! - Function definition and tooling support ([`FunctionDefinition`]), and composite tools
+//!   chaining existing tools ([`ToolPipeline`]).
 //! - Knowledge and document handling ([`Document`], [`Documents`]).
+//! - Encoding repair and language detection of ingested texts ([`normalize_text`], [`detect_language`]).
+//! - Import and export of LangChain and LlamaIndex documents and tools ([`InteropFormat`]).
+//! - Versioned persistence with upgrades of older data ([`Versioned`], [`Migrate`]).
+//! - Canonical JSON schemas of the wire types ([`wire_schemas`]).
+//! - Property-based testing strategies of the wire types, with the `proptest` feature.
+//! - Interactive elements of outputs and the callbacks of their actions ([`UiHints`], [`UiCallback`]).
 //! - Completion request and response structures ([`CompletionRequest`], [`Embedding`]).
 //! - Core AI capabilities traits ([`CompletionFeatures`], [`EmbeddingFeatures`]).
 
 use candid::Principal;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 pub use ic_auth_types::{ByteArrayB64, ByteBufB64, Xid};
 
+#[cfg(any(test, feature = "proptest"))]
+mod arbitrary;
+mod builder;
 mod completion;
 mod embedding;
+mod interop;
+mod key;
 mod knowledge;
+mod migration;
+mod pipeline;
+mod receipt;
 mod resource;
+mod schema;
+mod slot;
+mod text;
 mod thread;
-
+mod tokenizer;
+mod truncation;
+mod ui;
+mod workflow;
+
+#[cfg(any(test, feature = "proptest"))]
+pub use arbitrary::*;
+pub use builder::*;
 pub use completion::*;
 pub use embedding::*;
+pub use interop::*;
+pub use key::*;
 pub use knowledge::*;
+pub use migration::*;
+pub use pipeline::*;
+pub use receipt::*;
 pub use resource::*;
+pub use schema::*;
+pub use slot::*;
+pub use text::*;
 pub use thread::*;
+pub use tokenizer::*;
+pub use truncation::*;
+pub use ui::*;
+pub use workflow::*;
 
 pub const ANONYMOUS: Principal = Principal::anonymous();
 
 /// Represents a request to an agent for processing.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct AgentInput {
     /// agent name, use default agent if empty.
     pub name: String,
@@ -59,7 +97,7 @@ impl AgentInput {
 }
 
 /// Represents the output of an agent execution.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct AgentOutput {
     /// The output content from the agent, may be empty.
     pub content: String,
@@ -69,6 +107,7 @@ pub struct AgentOutput {
 
     /// The unique identifier for the thread.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub thread: Option<Xid>,
 
     /// Indicates failure reason if present, None means successful execution.
@@ -88,10 +127,86 @@ pub struct AgentOutput {
     /// The resources generated by the agent execution.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub resources: Option<Vec<Resource>>,
+
+    /// The loading statuses of the resources attached to the request, in order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_statuses: Option<Vec<ResourceStatus>>,
+
+    /// The signed receipt of the run, if the engine issues receipts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<SignedReceipt>,
+
+    /// The contents that were truncated to fit the engine's size limits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncations: Option<Vec<Truncation>>,
+
+    /// The progress of the workflow driven in the thread, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<WorkflowProgress>,
+
+    /// The state of the form filled in the thread, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form: Option<SlotState>,
+
+    /// The interactive elements suggested to render with the content, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ui: Option<UiHints>,
+
+    /// The name of the model provider that served the completion, set by providers
+    /// that route requests between several ones, e.g. a fallback chain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+
+    /// The identifier of the model that served the completion, e.g. "gpt-4o-2024-08-06",
+    /// as reported by the provider if possible.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+impl AgentOutput {
+    /// Merges an incremental output of [`CompletionFeatures::completion_stream`] into this
+    /// output: contents and the arguments of tool calls with the same id are concatenated,
+    /// usage is accumulated, and the other fields are replaced if present in the delta.
+    pub fn accumulate(&mut self, delta: AgentOutput) {
+        self.content.push_str(&delta.content);
+        self.usage.accumulate(&delta.usage);
+        if let Some(calls) = delta.tool_calls {
+            let tool_calls = self.tool_calls.get_or_insert_with(Vec::new);
+            for call in calls {
+                match tool_calls.iter_mut().find(|tc| tc.id == call.id) {
+                    Some(tc) => tc.args.push_str(&call.args),
+                    None => tool_calls.push(call),
+                }
+            }
+        }
+        if delta.thread.is_some() {
+            self.thread = delta.thread;
+        }
+        if delta.failed_reason.is_some() {
+            self.failed_reason = delta.failed_reason;
+        }
+        if delta.full_history.is_some() {
+            self.full_history = delta.full_history;
+        }
+        if let Some(resources) = delta.resources {
+            self.resources
+                .get_or_insert_with(Vec::new)
+                .extend(resources);
+        }
+        if delta.ui.is_some() {
+            self.ui = delta.ui;
+        }
+        if delta.provider.is_some() {
+            self.provider = delta.provider;
+        }
+        if delta.model.is_some() {
+            self.model = delta.model;
+        }
+    }
 }
 
 /// Represents a request to a tool for processing.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct ToolInput<T> {
     /// tool name.
     pub name: String,
@@ -121,7 +236,7 @@ impl<T> ToolInput<T> {
 }
 
 /// Represents the output of a tool execution.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct ToolOutput<T> {
     /// The output from the tool.
     pub output: T,
@@ -146,25 +261,106 @@ impl<T> ToolOutput<T> {
 }
 
 /// Represents the metadata for an agent or tool request.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct RequestMeta {
     /// The target engine principal for the request.
+    #[schemars(with = "Option<String>")]
     pub engine: Option<Principal>,
 
     /// The target thread for the request. If not provided, a new thread will be created.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub thread: Option<Xid>,
 
     /// Gets the username from request context.
     /// Note: This is not verified and should not be used as a trusted identifier.
     /// For example, if triggered by a bot of X platform, this might be the username
     /// of the user interacting with the bot.
+    /// `anda_engine_server` replaces it with the verified caller principal, unless the
+    /// caller is one of its trusted connectors.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+
+    /// The scheduling priority of the request.
+    #[serde(default, skip_serializing_if = "RequestPriority::is_interactive")]
+    pub priority: RequestPriority,
+
+    /// What to do when some attached resources fail to load, overriding the engine's policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_failure: Option<ResourceFailurePolicy>,
+
+    /// Starts the named workflow in the thread, replacing the current one if any.
+    /// The engine then drives the thread turn by turn until the workflow is completed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<String>,
+
+    /// Starts filling the named form in the thread, replacing the current one if any.
+    /// The engine then extracts its values turn by turn until it is completed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form: Option<String>,
+
+    /// The action picked by the user from the [`AgentOutput::ui`] hints of the previous
+    /// output. If the prompt is empty, the engine uses the text of the callback.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback: Option<UiCallback>,
+
+    /// The logical user that the `user` account is linked to, e.g. the ICP principal that the
+    /// user's Telegram and Discord accounts are linked to. It is set by the engine when
+    /// identity linking is enabled, values from callers are ignored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub person: Option<Principal>,
+
+    /// The deadline of the request, in unix milliseconds. The engine cancels the run when
+    /// it is reached, and returns the partial output with the usage consumed so far.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+
+    /// The language of the prompt, an ISO 639-1 code, e.g. "en". It is detected by the
+    /// engine if not set by the caller.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+}
+
+impl RequestMeta {
+    /// Returns the key of the user's data, e.g. their memory: the linked person if any,
+    /// otherwise the user.
+    pub fn user_key(&self) -> Option<String> {
+        self.person
+            .map(|p| p.to_text())
+            .or_else(|| self.user.clone())
+    }
+
+    /// Returns true if the deadline of the request is reached at `now_ms`.
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.deadline_ms.is_some_and(|deadline| now_ms >= deadline)
+    }
+}
+
+/// Scheduling priority of a request.
+///
+/// Interactive requests (e.g. chat) are admitted before queued batch work,
+/// and batch runs yield their execution slot between tool calls when interactive requests are waiting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestPriority {
+    /// Latency sensitive requests, the default.
+    #[default]
+    Interactive,
+
+    /// Background requests that can be delayed by interactive requests.
+    Batch,
+}
+
+impl RequestPriority {
+    /// Returns true if the priority is interactive.
+    pub fn is_interactive(&self) -> bool {
+        matches!(self, RequestPriority::Interactive)
+    }
 }
 
 /// Represents the usage statistics for the agent or tool execution.
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
 pub struct Usage {
     /// input tokens sent to the LLM
     pub input_tokens: u64,
@@ -174,6 +370,10 @@ pub struct Usage {
 
     /// number of requests made to agents and tools
     pub requests: u64,
+
+    /// estimated cost in USD, from the pricing of the models
+    #[serde(default)]
+    pub cost: f64,
 }
 
 impl Usage {
@@ -182,11 +382,12 @@ impl Usage {
         self.input_tokens = self.input_tokens.saturating_add(other.input_tokens);
         self.output_tokens = self.output_tokens.saturating_add(other.output_tokens);
         self.requests = self.requests.saturating_add(other.requests);
+        self.cost += other.cost;
     }
 }
 
 /// Represents a tool call response with it's ID, function name, and arguments.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct ToolCall {
     /// tool call id.
     pub id: String,
@@ -210,6 +411,10 @@ pub struct Function {
 
     /// The tags of resource that this function supports.
     pub supported_resource_tags: Vec<String>,
+
+    /// JSON schema of the function's output, if declared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<Value>,
 }
 
 /// Defines a callable function with its metadata and schema.
@@ -235,9 +440,19 @@ impl FunctionDefinition {
         self.name = format!("{}{}", prefix, self.name);
         self
     }
+
+    /// Appends the JSON schema of the function's output to the description, so that the
+    /// model knows the structure of the result when chaining calls.
+    pub fn with_output_schema(mut self, schema: &Value) -> Self {
+        self.description = format!(
+            "{}\n\nReturns JSON conforming to the schema: {}",
+            self.description, schema
+        );
+        self
+    }
 }
 
-/// Returns the number of tokens in the given content in the simplest way.
+/// Returns the estimated number of tokens in the given content, see [`HeuristicTokenizer`].
 pub fn evaluate_tokens(content: &str) -> usize {
-    content.len() / 3
+    HeuristicTokenizer.count_tokens(content)
 }
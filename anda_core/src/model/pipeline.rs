@@ -0,0 +1,278 @@
+use serde::{Deserialize, Serialize};
+
+use super::{FunctionDefinition, Value};
+use crate::{BoxError, validate_function_name};
+
+/// A composite tool that chains existing tools, exposed to the model as one function to
+/// save round-trips for common multi-step operations.
+///
+/// The arguments of each step are a template: strings starting with `$` are JSONPath-style
+/// references resolved against `{"input": <pipeline arguments>, "steps": [<step outputs>]}`,
+/// e.g. `$.input.city` or `$.steps[0].results[1].id`, other values are literals. Strings
+/// starting with `$$` are literals with the first `$` removed.
+///
+/// # Example
+/// ```json
+/// {
+///   "name": "city_weather",
+///   "description": "Returns the current weather of a city.",
+///   "parameters": {"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]},
+///   "steps": [
+///     {"tool": "geocode", "args": {"query": "$.input.city"}},
+///     {"tool": "weather", "args": {"latitude": "$.steps[0].lat", "longitude": "$.steps[0].lon"}}
+///   ],
+///   "output": "$.steps[1].current"
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ToolPipeline {
+    /// The unique name of the pipeline, following the rules of tool names.
+    pub name: String,
+
+    /// The description of the pipeline, given to the model.
+    pub description: String,
+
+    /// JSON schema of the pipeline's arguments.
+    pub parameters: Value,
+
+    /// The ordered steps of the pipeline.
+    pub steps: Vec<PipelineStep>,
+
+    /// The reference to the output of the pipeline, the output of the last step by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// A step of a pipeline.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct PipelineStep {
+    /// The name of the tool to call.
+    pub tool: String,
+
+    /// The arguments template of the tool call.
+    #[serde(default)]
+    pub args: Value,
+}
+
+impl ToolPipeline {
+    /// Validates the pipeline: its name is a valid tool name, it has steps, and references
+    /// only point to the input or to earlier steps.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        validate_function_name(&self.name)?;
+        if self.steps.is_empty() {
+            return Err(format!("pipeline {} has no steps", self.name).into());
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if step.tool.is_empty() {
+                return Err(format!("step {} of pipeline {} has no tool", i, self.name).into());
+            }
+            let mut refs = Vec::new();
+            collect_refs(&step.args, &mut refs);
+            for r in refs {
+                check_ref(r, i).map_err(|err| {
+                    format!(
+                        "step {} of pipeline {} has an invalid reference {}",
+                        i, self.name, err
+                    )
+                })?;
+            }
+        }
+        if let Some(output) = &self.output {
+            check_ref(output, self.steps.len()).map_err(|err| {
+                format!(
+                    "pipeline {} has an invalid output reference {}",
+                    self.name, err
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the function definition of the pipeline.
+    pub fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            parameters: self.parameters.clone(),
+            strict: None,
+        }
+    }
+
+    /// Returns the arguments of the step, resolved against the pipeline arguments and the
+    /// outputs of the previous steps.
+    pub fn step_args(
+        &self,
+        index: usize,
+        input: &Value,
+        outputs: &[Value],
+    ) -> Result<Value, String> {
+        let step = self
+            .steps
+            .get(index)
+            .ok_or_else(|| format!("step {} not found", index))?;
+        resolve(&step.args, &scope(input, outputs))
+    }
+
+    /// Returns the output of the pipeline, given the outputs of all steps.
+    pub fn output(&self, input: &Value, outputs: &[Value]) -> Result<Value, String> {
+        match &self.output {
+            Some(output) => resolve(&Value::String(output.clone()), &scope(input, outputs)),
+            None => Ok(outputs.last().cloned().unwrap_or_default()),
+        }
+    }
+}
+
+fn scope(input: &Value, outputs: &[Value]) -> Value {
+    serde_json::json!({
+        "input": input,
+        "steps": outputs,
+    })
+}
+
+fn is_ref(s: &str) -> bool {
+    s.starts_with('$') && !s.starts_with("$$")
+}
+
+fn collect_refs<'a>(template: &'a Value, refs: &mut Vec<&'a str>) {
+    match template {
+        Value::String(s) if is_ref(s) => refs.push(s),
+        Value::Array(arr) => arr.iter().for_each(|v| collect_refs(v, refs)),
+        Value::Object(obj) => obj.values().for_each(|v| collect_refs(v, refs)),
+        _ => {}
+    }
+}
+
+/// Checks that the reference points to the input or to a step before `steps`.
+fn check_ref(r: &str, steps: usize) -> Result<(), String> {
+    let parts = parse_path(r).ok_or_else(|| r.to_string())?;
+    match parts.as_slice() {
+        [PathPart::Key(k), ..] if k == "input" => Ok(()),
+        [PathPart::Key(k), PathPart::Index(i), ..] if k == "steps" && *i < steps => Ok(()),
+        _ => Err(r.to_string()),
+    }
+}
+
+fn resolve(template: &Value, scope: &Value) -> Result<Value, String> {
+    match template {
+        Value::String(s) if is_ref(s) => json_path(scope, s)
+            .cloned()
+            .ok_or_else(|| format!("reference {} not found", s)),
+        Value::String(s) if s.starts_with("$$") => Ok(Value::String(s[1..].to_string())),
+        Value::Array(arr) => arr.iter().map(|v| resolve(v, scope)).collect(),
+        Value::Object(obj) => obj
+            .iter()
+            .map(|(k, v)| resolve(v, scope).map(|v| (k.clone(), v)))
+            .collect::<Result<serde_json::Map<_, _>, _>>()
+            .map(Value::Object),
+        v => Ok(v.clone()),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum PathPart {
+    Key(String),
+    Index(usize),
+}
+
+/// Parses a JSONPath-style path, e.g. `$.steps[0].items.1`, into its parts.
+fn parse_path(path: &str) -> Option<Vec<PathPart>> {
+    let rest = path.strip_prefix('$')?;
+    let mut parts = Vec::new();
+    for segment in rest.split('.').skip_while(|s| s.is_empty()) {
+        let (key, mut indexes) = match segment.find('[') {
+            Some(i) => (&segment[..i], &segment[i..]),
+            None => (segment, ""),
+        };
+        if !key.is_empty() {
+            match key.parse::<usize>() {
+                Ok(i) if !parts.is_empty() => parts.push(PathPart::Index(i)),
+                _ => parts.push(PathPart::Key(key.to_string())),
+            }
+        } else if indexes.is_empty() {
+            return None;
+        }
+        while !indexes.is_empty() {
+            let end = indexes.find(']')?;
+            parts.push(PathPart::Index(indexes[1..end].trim().parse().ok()?));
+            indexes = &indexes[end + 1..];
+            if !indexes.is_empty() && !indexes.starts_with('[') {
+                return None;
+            }
+        }
+    }
+    Some(parts)
+}
+
+/// Returns the value at a JSONPath-style path, e.g. `$.steps[0].items.1`.
+/// Only member and index accessors are supported.
+pub fn json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    parse_path(path)?
+        .into_iter()
+        .try_fold(value, |v, part| match part {
+            PathPart::Key(k) => v.get(k),
+            PathPart::Index(i) => v.get(i),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_path() {
+        let v = json!({"steps": [{"items": [1, {"id": "a"}]}], "input": {"x": null}});
+        assert_eq!(json_path(&v, "$"), Some(&v));
+        assert_eq!(json_path(&v, "$.steps[0].items[1].id"), Some(&json!("a")));
+        assert_eq!(json_path(&v, "$.steps.0.items.0"), Some(&json!(1)));
+        assert_eq!(json_path(&v, "$.input.x"), Some(&Value::Null));
+        assert_eq!(json_path(&v, "$.steps[1]"), None);
+        assert_eq!(json_path(&v, "$.steps[a]"), None);
+        assert_eq!(json_path(&v, "steps"), None);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let pipeline: ToolPipeline = serde_json::from_value(json!({
+            "name": "city_weather",
+            "description": "Returns the current weather of a city.",
+            "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+            "steps": [
+                {"tool": "geocode", "args": {"query": "$.input.city", "limit": 1}},
+                {"tool": "weather", "args": {
+                    "coords": ["$.steps[0].lat", "$.steps[0].lon"],
+                    "unit": "$$celsius"
+                }}
+            ],
+            "output": "$.steps[1].current"
+        }))
+        .unwrap();
+        pipeline.validate().unwrap();
+        assert_eq!(pipeline.definition().name, "city_weather");
+
+        let input = json!({"city": "Paris"});
+        assert_eq!(
+            pipeline.step_args(0, &input, &[]).unwrap(),
+            json!({"query": "Paris", "limit": 1})
+        );
+        let outputs = vec![json!({"lat": 48.8, "lon": 2.3})];
+        assert_eq!(
+            pipeline.step_args(1, &input, &outputs).unwrap(),
+            json!({"coords": [48.8, 2.3], "unit": "$celsius"})
+        );
+        assert!(pipeline.step_args(1, &input, &[json!({})]).is_err());
+
+        let outputs = vec![outputs[0].clone(), json!({"current": {"temp": 21}})];
+        assert_eq!(
+            pipeline.output(&input, &outputs).unwrap(),
+            json!({"temp": 21})
+        );
+
+        let mut invalid = pipeline.clone();
+        invalid.steps[0].args = json!({"query": "$.steps[0].lat"});
+        assert!(invalid.validate().is_err());
+        let mut invalid = pipeline.clone();
+        invalid.output = Some("$.steps[2]".to_string());
+        assert!(invalid.validate().is_err());
+    }
+}
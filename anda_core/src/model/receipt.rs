@@ -0,0 +1,132 @@
+use candid::{CandidType, Principal};
+use ic_cose_types::{
+    cose::{ed25519::ed25519_verify, sha3_256},
+    to_cbor_bytes,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{ByteArrayB64, ByteBufB64, ToolCall};
+
+/// The hash of a tool call made during an agent run.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, Serialize, JsonSchema)]
+pub struct ToolCallDigest {
+    /// The tool name.
+    pub name: String,
+
+    /// The SHA3-256 hash of the arguments and the result of the call.
+    #[schemars(with = "String")]
+    pub hash: ByteArrayB64<32>,
+}
+
+impl From<&ToolCall> for ToolCallDigest {
+    fn from(call: &ToolCall) -> Self {
+        let result = call
+            .result
+            .as_ref()
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        Self {
+            name: call.name.clone(),
+            hash: sha3_256(&to_cbor_bytes(&(&call.name, &call.args, &result))).into(),
+        }
+    }
+}
+
+/// A record of what an agent did in a run, as evidence for third parties.
+/// Inputs and outputs are only included as hashes.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, Serialize, JsonSchema)]
+pub struct Receipt {
+    /// The engine that ran the agent.
+    #[schemars(with = "String")]
+    pub engine: Principal,
+
+    /// The agent name.
+    pub agent: String,
+
+    /// The caller of the run.
+    #[schemars(with = "String")]
+    pub caller: Principal,
+
+    /// The thread of the run.
+    pub thread: Option<String>,
+
+    /// The model identifier, e.g. "deepseek-chat".
+    pub model: String,
+
+    /// The SHA3-256 hash of the prompt.
+    #[schemars(with = "String")]
+    pub input_hash: ByteArrayB64<32>,
+
+    /// The SHA3-256 hash of the output content.
+    #[schemars(with = "String")]
+    pub output_hash: ByteArrayB64<32>,
+
+    /// The tool calls made by the agent, in order.
+    pub tool_calls: Vec<ToolCallDigest>,
+
+    /// The measurement of the TEE the engine runs in, if any.
+    #[schemars(with = "Option<String>")]
+    pub tee_measurement: Option<ByteBufB64>,
+
+    /// The unix timestamp in milliseconds.
+    pub timestamp: u64,
+}
+
+impl Receipt {
+    /// Returns the SHA3-256 digest of the CBOR-encoded receipt, which is the signed message.
+    pub fn digest(&self) -> [u8; 32] {
+        sha3_256(&to_cbor_bytes(self))
+    }
+}
+
+/// A receipt signed by the engine with Ed25519.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, Serialize, JsonSchema)]
+pub struct SignedReceipt {
+    pub receipt: Receipt,
+
+    /// The Ed25519 public key of the engine.
+    #[schemars(with = "String")]
+    pub public_key: ByteArrayB64<32>,
+
+    /// The Ed25519 signature over the receipt digest.
+    #[schemars(with = "String")]
+    pub signature: ByteArrayB64<64>,
+}
+
+impl SignedReceipt {
+    /// Verifies the signature of the receipt.
+    /// Callers should also check that the public key belongs to the engine.
+    pub fn verify(&self) -> Result<(), String> {
+        ed25519_verify(
+            &self.public_key,
+            &self.receipt.digest(),
+            self.signature.as_slice(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tool_call_digest() {
+        let call = ToolCall {
+            id: "1".to_string(),
+            name: "transfer".to_string(),
+            args: r#"{"amount":1}"#.to_string(),
+            result: Some(json!("ok")),
+        };
+        let digest = ToolCallDigest::from(&call);
+        assert_eq!(digest.name, "transfer");
+        assert_eq!(digest, ToolCallDigest::from(&call));
+
+        let other = ToolCall {
+            result: Some(json!("failed")),
+            ..call
+        };
+        assert_ne!(digest.hash, ToolCallDigest::from(&other).hash);
+    }
+}
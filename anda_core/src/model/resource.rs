@@ -1,10 +1,32 @@
 use candid::CandidType;
+use futures::StreamExt;
+use ic_cose_types::cose::sha3_256;
+use object_store::path::Path;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 
 use super::{ByteArrayB64, ByteBufB64};
+use crate::{BoxError, KvFeatures, PutMode, StoreFeatures};
+
+/// The maximum size of a resource created by [`Resource::from_bytes`], [`Resource::from_path`]
+/// and [`Resource::from_url`].
+pub const MAX_RESOURCE_SIZE: usize = 20 * 1024 * 1024;
+
+/// The URI scheme of resources uploaded by [`ResourceStoreFeatures::store_resource`].
+pub const STORE_URI_SCHEME: &str = "store:";
+
+/// The store path prefix of the content-addressed blobs of resources.
+const BLOBS_PATH: &str = "blobs/";
+
+/// The [`Kv`](crate::Kv) namespace of the reference counts of the blobs.
+const BLOB_REFS_NAMESPACE: &str = "blob_refs";
+
+/// The retries of a reference count update on concurrent changes.
+const MAX_REFS_RETRIES: usize = 10;
 
 /// Represents a resource that can be sent to agents or tools.
-#[derive(Debug, Default, CandidType, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, CandidType, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Resource {
     /// A tag that identifies the type of this resource.
     pub tag: String,
@@ -28,6 +50,7 @@ pub struct Resource {
 
     /// The binary data of this resource.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub blob: Option<ByteBufB64>,
 
     /// The size of the resource in bytes.
@@ -36,9 +59,420 @@ pub struct Resource {
 
     /// The SHA3-256 hash of the resource.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub hash: Option<ByteArrayB64<32>>,
 }
 
+impl Resource {
+    /// Creates a resource from bytes, with the MIME type sniffed from the content if not given,
+    /// and the tag derived from the MIME type. Returns an error if the bytes are larger than
+    /// [`MAX_RESOURCE_SIZE`].
+    pub fn from_bytes(mime_type: Option<&str>, bytes: Vec<u8>) -> Result<Self, BoxError> {
+        if bytes.len() > MAX_RESOURCE_SIZE {
+            return Err(format!(
+                "resource is too large, {} bytes exceeds {} bytes",
+                bytes.len(),
+                MAX_RESOURCE_SIZE
+            )
+            .into());
+        }
+
+        let mime_type = mime_type
+            .map(|m| m.to_string())
+            .unwrap_or_else(|| sniff_mime_type(&bytes).to_string());
+        Ok(Self {
+            tag: mime_type_tag(&mime_type).to_string(),
+            mime_type: Some(mime_type),
+            size: Some(bytes.len()),
+            hash: Some(sha3_256(&bytes).into()),
+            blob: Some(bytes.into()),
+            ..Default::default()
+        })
+    }
+
+    /// Creates a resource from a local file, named by the file name. The MIME type is guessed
+    /// from the extension, or sniffed from the content.
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref();
+        let size = std::fs::metadata(path)?.len();
+        if size > MAX_RESOURCE_SIZE as u64 {
+            return Err(format!(
+                "resource is too large, {} bytes exceeds {} bytes",
+                size, MAX_RESOURCE_SIZE
+            )
+            .into());
+        }
+
+        let mime_type = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(extension_mime_type);
+        let mut resource = Self::from_bytes(mime_type, std::fs::read(path)?)?;
+        resource.name = path.file_name().map(|n| n.to_string_lossy().to_string());
+        Ok(resource)
+    }
+
+    /// Downloads a resource from the URL, named by the last segment of the URL path. The MIME
+    /// type is taken from the `Content-Type` header, or sniffed from the content. The download
+    /// stops if the resource is larger than [`MAX_RESOURCE_SIZE`].
+    pub async fn from_url(client: &reqwest::Client, url: &str) -> Result<Self, BoxError> {
+        let res = client.get(url).send().await?;
+        if !res.status().is_success() {
+            return Err(format!("failed to download {}, status: {}", url, res.status()).into());
+        }
+        if let Some(len) = res.content_length()
+            && len > MAX_RESOURCE_SIZE as u64
+        {
+            return Err(format!(
+                "resource is too large, {} bytes exceeds {} bytes",
+                len, MAX_RESOURCE_SIZE
+            )
+            .into());
+        }
+
+        let mime_type = res
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(';').next().unwrap_or_default().trim().to_string())
+            .filter(|v| !v.is_empty() && v != "application/octet-stream");
+        let mut bytes = Vec::new();
+        let mut stream = res.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            bytes.extend_from_slice(&chunk?);
+            if bytes.len() > MAX_RESOURCE_SIZE {
+                return Err(format!(
+                    "resource is too large, more than {} bytes",
+                    MAX_RESOURCE_SIZE
+                )
+                .into());
+            }
+        }
+
+        let mut resource = Self::from_bytes(mime_type.as_deref(), bytes)?;
+        resource.uri = Some(url.to_string());
+        resource.name = url
+            .split(['?', '#'])
+            .next()
+            .and_then(|u| u.rsplit('/').next())
+            .filter(|n| !n.is_empty() && !n.contains(':'))
+            .map(|n| n.to_string());
+        Ok(resource)
+    }
+
+    /// Sets the name of the resource.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the description of the resource.
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+/// Sniffs the MIME type of the content by its magic bytes. Valid UTF-8 content is
+/// "text/plain", or "application/json" if it parses as JSON; others are
+/// "application/octet-stream".
+pub fn sniff_mime_type(bytes: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+    ];
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(sig, _)| bytes.starts_with(sig)) {
+        return mime;
+    }
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" {
+        match &bytes[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            _ => {}
+        }
+    }
+    if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        return "video/mp4";
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            let text = text.trim_start();
+            if (text.starts_with('{') || text.starts_with('['))
+                && serde_json::from_slice::<serde::de::IgnoredAny>(bytes).is_ok()
+            {
+                "application/json"
+            } else {
+                "text/plain"
+            }
+        }
+        Err(_) => "application/octet-stream",
+    }
+}
+
+/// Returns the MIME type of a file extension, if known.
+pub fn extension_mime_type(ext: &str) -> Option<&'static str> {
+    let mime = match ext.to_ascii_lowercase().as_str() {
+        "txt" | "text" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "ics" => "text/calendar",
+        "json" => "application/json",
+        "toml" => "application/toml",
+        "yaml" | "yml" => "application/yaml",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// Returns the resource tag of a MIME type: "image", "audio", "video", "json", "text",
+/// "pdf" or "file".
+pub fn mime_type_tag(mime_type: &str) -> &'static str {
+    match mime_type.split('/').next().unwrap_or_default() {
+        "image" => "image",
+        "audio" => "audio",
+        "video" => "video",
+        "text" => "text",
+        _ => match mime_type {
+            "application/json" => "json",
+            "application/pdf" => "pdf",
+            "application/toml" | "application/yaml" | "application/xml" => "text",
+            _ => "file",
+        },
+    }
+}
+
+/// Uploads and downloads the blobs of resources to and from the store of the context, so that
+/// large resources are passed by reference.
+///
+/// Blobs are content-addressed by their BLAKE3 hash and reference counted, so a file attached
+/// to many threads is stored once. A blob is referenced before it is uploaded, and deleted
+/// only when its count is still zero, so a release does not delete a blob stored again
+/// concurrently, except if the store happens between this check and the deletion.
+///
+/// It is implemented for all contexts with [`StoreFeatures`].
+pub trait ResourceStoreFeatures: StoreFeatures + Sync {
+    /// Adds a reference to the blob of the resource, uploads it to the store under
+    /// `blobs/{blake3 hex}` unless it is already stored, and returns the resource without the
+    /// blob and with a [`STORE_URI_SCHEME`] URI. Resources without a blob are returned unchanged.
+    fn store_resource(
+        &self,
+        mut resource: Resource,
+    ) -> impl Future<Output = Result<Resource, BoxError>> + Send {
+        async move {
+            let Some(blob) = resource.blob.take() else {
+                return Ok(resource);
+            };
+            let address = blake3::hash(&blob).to_hex().to_string();
+            let path = Path::from(format!("{}{}", BLOBS_PATH, address));
+            let refs = self.kv::<u64>(BLOB_REFS_NAMESPACE);
+            resource.size = Some(blob.len());
+            resource.hash = Some(sha3_256(&blob).into());
+            // the blob is referenced before it is uploaded, so that a concurrent release
+            // does not delete it in between
+            refs.update(&address, MAX_REFS_RETRIES, |n| n.unwrap_or_default() + 1)
+                .await?;
+            match self.store_put(&path, PutMode::Create, blob.0.into()).await {
+                Err(err) if !is_already_exists(&err) => {
+                    // a failed upload leaves no dangling reference
+                    let _ = refs
+                        .update(&address, MAX_REFS_RETRIES, |n| {
+                            n.unwrap_or_default().saturating_sub(1)
+                        })
+                        .await;
+                    return Err(err);
+                }
+                _ => {}
+            }
+            resource.uri = Some(format!("{}{}", STORE_URI_SCHEME, path));
+            Ok(resource)
+        }
+    }
+
+    /// Downloads the blob of a resource uploaded by [`store_resource`](Self::store_resource)
+    /// and verifies its integrity: the BLAKE3 hash of a content-addressed blob must match its
+    /// address, and the SHA3-256 hash of the blob the resource's hash if any. Other resources
+    /// are returned unchanged.
+    fn load_resource(
+        &self,
+        mut resource: Resource,
+    ) -> impl Future<Output = Result<Resource, BoxError>> + Send {
+        async move {
+            let Some(path) = resource
+                .uri
+                .as_deref()
+                .and_then(|uri| uri.strip_prefix(STORE_URI_SCHEME))
+                .filter(|_| resource.blob.is_none())
+            else {
+                return Ok(resource);
+            };
+            let (blob, _) = self.store_get(&Path::from(path)).await?;
+            if let Some(address) = path.strip_prefix(BLOBS_PATH)
+                && blake3::hash(&blob).to_hex().as_str() != address
+            {
+                return Err(format!("integrity check failed for resource {}", path).into());
+            }
+            if let Some(hash) = &resource.hash
+                && **hash != sha3_256(&blob)
+            {
+                return Err(format!("hash mismatch of resource {}", path).into());
+            }
+            resource.size = Some(blob.len());
+            resource.blob = Some(blob.to_vec().into());
+            Ok(resource)
+        }
+    }
+
+    /// Removes a reference to the blob of a resource uploaded by
+    /// [`store_resource`](Self::store_resource), and deletes the blob when no reference is
+    /// left. Returns the number of remaining references.
+    fn release_resource(
+        &self,
+        resource: &Resource,
+    ) -> impl Future<Output = Result<u64, BoxError>> + Send {
+        async move {
+            let Some(address) = resource
+                .uri
+                .as_deref()
+                .and_then(|uri| uri.strip_prefix(STORE_URI_SCHEME))
+                .and_then(|path| path.strip_prefix(BLOBS_PATH))
+            else {
+                return Err("resource is not a content-addressed stored resource".into());
+            };
+            let refs = self.kv::<u64>(BLOB_REFS_NAMESPACE);
+            if refs.get(address).await?.is_none_or(|(n, _)| n == 0) {
+                return Err(format!("resource {} has no reference", address).into());
+            }
+            let (n, _) = refs
+                .update(address, MAX_REFS_RETRIES, |n| {
+                    n.unwrap_or_default().saturating_sub(1)
+                })
+                .await?;
+            // the count is kept at zero rather than deleted, so that a concurrent store
+            // that referenced the blob again is not lost, and the blob is kept if it did
+            if n == 0 && refs.get(address).await?.is_none_or(|(n, _)| n == 0) {
+                let path = Path::from(format!("{}{}", BLOBS_PATH, address));
+                match self.store_delete(&path).await {
+                    Err(err)
+                        if !matches!(
+                            err.downcast_ref::<object_store::Error>(),
+                            Some(object_store::Error::NotFound { .. })
+                        ) =>
+                    {
+                        return Err(err);
+                    }
+                    _ => {}
+                }
+            }
+            Ok(n)
+        }
+    }
+}
+
+impl<T: StoreFeatures + Sync> ResourceStoreFeatures for T {}
+
+fn is_already_exists(err: &BoxError) -> bool {
+    matches!(
+        err.downcast_ref::<object_store::Error>(),
+        Some(object_store::Error::AlreadyExists { .. })
+    )
+}
+
+/// What to do when some resources attached to a request fail to load or transform.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, CandidType, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceFailurePolicy {
+    /// Fails the request without running the agent, the default.
+    #[default]
+    Fail,
+    /// Runs the agent without the failed resources, with a note about them in the prompt.
+    SkipWithNote,
+    /// Asks the user how to proceed without running the agent.
+    AskUser,
+}
+
+/// The loading state of a resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceState {
+    /// The resource is loaded and passed to the agent.
+    Loaded,
+    /// The resource failed to load and is not passed to the agent.
+    Skipped,
+    /// The resource failed to load.
+    Failed,
+}
+
+/// The loading status of a resource attached to a request.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, Serialize, JsonSchema)]
+pub struct ResourceStatus {
+    /// The tag of the resource.
+    pub tag: String,
+
+    /// The name of the resource.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// The URI of the resource.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+
+    /// The loading state.
+    pub state: ResourceState,
+
+    /// The error message if the resource failed to load.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ResourceStatus {
+    /// Creates a status for the resource.
+    pub fn new(resource: &Resource, state: ResourceState, error: Option<String>) -> Self {
+        Self {
+            tag: resource.tag.clone(),
+            name: resource.name.clone(),
+            uri: resource.uri.clone(),
+            state,
+            error,
+        }
+    }
+
+    /// Returns a short label of the resource for messages: its name, URI or tag.
+    pub fn label(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.uri.as_deref())
+            .unwrap_or(&self.tag)
+    }
+}
+
 /// Extracts resources with the given tags from the list of resources.
 pub fn select_resources(resources: &mut Vec<Resource>, tags: &[&str]) -> Option<Vec<Resource>> {
     if tags.is_empty() {
@@ -71,3 +505,47 @@ pub fn select_resources(resources: &mut Vec<Resource>, tags: &[&str]) -> Option<
         if res.is_empty() { None } else { Some(res) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_mime_type() {
+        assert_eq!(sniff_mime_type(b"\x89PNG\r\n\x1a\n\0\0"), "image/png");
+        assert_eq!(sniff_mime_type(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff_mime_type(b"%PDF-1.7"), "application/pdf");
+        assert_eq!(sniff_mime_type(br#" {"a": [1, 2]}"#), "application/json");
+        assert_eq!(sniff_mime_type(b"{not json"), "text/plain");
+        assert_eq!(
+            sniff_mime_type(&[0xff, 0xfe, 0x00]),
+            "application/octet-stream"
+        );
+        assert_eq!(mime_type_tag("image/png"), "image");
+        assert_eq!(mime_type_tag("application/yaml"), "text");
+        assert_eq!(mime_type_tag("application/zip"), "file");
+    }
+
+    #[test]
+    fn test_resource_from_bytes_and_path() {
+        let r = Resource::from_bytes(None, b"hello".to_vec()).unwrap();
+        assert_eq!(r.tag, "text");
+        assert_eq!(r.mime_type.as_deref(), Some("text/plain"));
+        assert_eq!(r.size, Some(5));
+        assert_eq!(**r.hash.as_ref().unwrap(), sha3_256(b"hello"));
+
+        let r = Resource::from_bytes(Some("text/markdown"), b"# Title".to_vec()).unwrap();
+        assert_eq!(r.tag, "text");
+        assert_eq!(r.mime_type.as_deref(), Some("text/markdown"));
+
+        assert!(Resource::from_bytes(None, vec![0; MAX_RESOURCE_SIZE + 1]).is_err());
+
+        let path = std::env::temp_dir().join(format!("anda_resource_{}.json", std::process::id()));
+        std::fs::write(&path, br#"{"a": 1}"#).unwrap();
+        let r = Resource::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(r.tag, "json");
+        assert_eq!(r.name.as_deref(), path.file_name().and_then(|n| n.to_str()));
+        assert!(Resource::from_path(&path).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use schemars::{JsonSchema, r#gen::SchemaSettings};
+use serde_json::Map;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{
+    AgentInput, AgentOutput, Resource, Thread, ThreadMessage, ThreadMeta, ToolInput, ToolOutput,
+    Value,
+};
+
+/// The JSON schema of [`crate::UpdateVersion`], which is defined in `ic_cose_types`.
+#[derive(JsonSchema)]
+#[schemars(rename = "UpdateVersion")]
+#[allow(dead_code)]
+pub(crate) struct UpdateVersionSchema {
+    e_tag: Option<String>,
+    version: Option<String>,
+}
+
+/// Generates the canonical JSON schema (draft-07) of a wire type, with its subschemas in
+/// `definitions`. Unlike [`crate::gen_schema_for`], it is not adjusted for function calling:
+/// optional fields are not required.
+pub fn wire_schema_for<T: JsonSchema>() -> Value {
+    let generator = SchemaSettings::draft07().into_generator();
+    serde_json::to_value(generator.into_root_schema_for::<T>()).unwrap_or_default()
+}
+
+/// Returns the canonical JSON schemas of the types exchanged with engines, by type name.
+/// The arguments and the output of tools are generic JSON values.
+pub fn wire_schemas() -> BTreeMap<&'static str, Value> {
+    BTreeMap::from([
+        ("AgentInput", wire_schema_for::<AgentInput>()),
+        ("AgentOutput", wire_schema_for::<AgentOutput>()),
+        ("ToolInput", wire_schema_for::<ToolInput<Value>>()),
+        ("ToolOutput", wire_schema_for::<ToolOutput<Value>>()),
+        ("Resource", wire_schema_for::<Resource>()),
+        ("Thread", wire_schema_for::<Thread>()),
+        ("ThreadMessage", wire_schema_for::<ThreadMessage>()),
+        ("ThreadMeta", wire_schema_for::<ThreadMeta>()),
+    ])
+}
+
+/// Compares two versions of a schema generated by [`wire_schema_for`] and returns the
+/// changes that can break existing clients: removed fields, definitions or enum values,
+/// changed types, and fields that became required. Added optional fields are compatible.
+pub fn schema_breaking_changes(old: &Value, new: &Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    let name = old
+        .get("title")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    compare_schema(name, old, new, &mut changes);
+
+    let empty = Map::new();
+    let old_defs = old.get("definitions").and_then(|v| v.as_object());
+    let new_defs = new
+        .get("definitions")
+        .and_then(|v| v.as_object())
+        .unwrap_or(&empty);
+    for (name, old) in old_defs.unwrap_or(&empty) {
+        match new_defs.get(name) {
+            Some(new) => compare_schema(name, old, new, &mut changes),
+            None => changes.push(format!("{}: definition removed", name)),
+        }
+    }
+    changes
+}
+
+fn compare_schema(path: &str, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    let (Some(o), Some(n)) = (old.as_object(), new.as_object()) else {
+        if old != new {
+            changes.push(format!("{}: schema changed", path));
+        }
+        return;
+    };
+
+    if let Some(r) = o.get("$ref")
+        && n.get("$ref") != Some(r)
+    {
+        changes.push(format!("{}: type changed from {}", path, r));
+    }
+
+    let old_types = schema_types(old);
+    if !old_types.is_empty() && !old_types.is_subset(&schema_types(new)) {
+        changes.push(format!("{}: type changed from {:?}", path, old_types));
+    }
+
+    let new_values = enum_values(new);
+    for v in enum_values(old) {
+        if !new_values.contains(&v) {
+            changes.push(format!("{}: value {} removed", path, v));
+        }
+    }
+
+    for key in ["anyOf", "oneOf"] {
+        let new_refs: Vec<&Value> = n
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|v| v.iter().filter_map(|s| s.get("$ref")).collect())
+            .unwrap_or_default();
+        for r in o
+            .get(key)
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|s| s.get("$ref"))
+        {
+            if !new_refs.contains(&r) {
+                changes.push(format!("{}: variant {} removed", path, r));
+            }
+        }
+    }
+
+    let empty = Map::new();
+    let new_props = n
+        .get("properties")
+        .and_then(|v| v.as_object())
+        .unwrap_or(&empty);
+    for (k, old) in o
+        .get("properties")
+        .and_then(|v| v.as_object())
+        .unwrap_or(&empty)
+    {
+        let field = format!("{}.{}", path, k);
+        match new_props.get(k) {
+            Some(new) => compare_schema(&field, old, new, changes),
+            None => changes.push(format!("{}: removed", field)),
+        }
+    }
+
+    let old_required = required_fields(old);
+    for k in required_fields(new) {
+        if !old_required.contains(&k) {
+            changes.push(format!("{}.{}: became required", path, k));
+        }
+    }
+
+    for key in ["items", "additionalProperties"] {
+        if let (Some(old @ Value::Object(_)), Some(new)) = (o.get(key), n.get(key)) {
+            compare_schema(&format!("{}[]", path), old, new, changes);
+        }
+    }
+}
+
+fn schema_types(schema: &Value) -> BTreeSet<&str> {
+    match schema.get("type") {
+        Some(Value::String(t)) => BTreeSet::from([t.as_str()]),
+        Some(Value::Array(ts)) => ts.iter().filter_map(|t| t.as_str()).collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+fn enum_values(schema: &Value) -> Vec<Value> {
+    let mut values: Vec<Value> = schema
+        .get("enum")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+    values.extend(schema.get("const").cloned());
+    for key in ["anyOf", "oneOf"] {
+        for variant in schema
+            .get(key)
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+        {
+            values.extend(enum_values(variant));
+        }
+    }
+    values
+}
+
+fn required_fields(schema: &Value) -> BTreeSet<String> {
+    schema
+        .get("required")
+        .and_then(|v| v.as_array())
+        .map(|r| {
+            r.iter()
+                .filter_map(|f| f.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    /// The published schemas are in `anda_core/schemas`. This test fails on breaking
+    /// changes and updates the files on compatible ones, which should be committed.
+    #[test]
+    fn test_wire_schemas_compatibility() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("schemas");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, schema) in wire_schemas() {
+            let path = dir.join(format!("{}.json", name));
+            if let Ok(data) = std::fs::read(&path) {
+                let published: Value = serde_json::from_slice(&data).unwrap();
+                let changes = schema_breaking_changes(&published, &schema);
+                assert!(
+                    changes.is_empty(),
+                    "breaking changes in {}:\n{}",
+                    name,
+                    changes.join("\n")
+                );
+                if published == schema {
+                    continue;
+                }
+            }
+            let data = serde_json::to_string_pretty(&schema).unwrap() + "\n";
+            std::fs::write(&path, data).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_schema_breaking_changes() {
+        let old = wire_schema_for::<AgentInput>();
+        assert!(schema_breaking_changes(&old, &old).is_empty());
+
+        let mut new = old.clone();
+        new["properties"]["user"] = json!({"type": ["string", "null"]});
+        assert!(schema_breaking_changes(&old, &new).is_empty());
+
+        new["required"] = json!(["name", "prompt", "user"]);
+        new["properties"]
+            .as_object_mut()
+            .unwrap()
+            .remove("resources");
+        new["properties"]["prompt"] = json!({"type": "object"});
+        new["definitions"]["RequestPriority"] = json!({"type": "string", "enum": ["interactive"]});
+        new["definitions"]
+            .as_object_mut()
+            .unwrap()
+            .remove("Resource");
+        let changes = schema_breaking_changes(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                "AgentInput.prompt: type changed from {\"string\"}",
+                "AgentInput.resources: removed",
+                "AgentInput.user: became required",
+                "RequestPriority: value \"batch\" removed",
+                "Resource: definition removed",
+            ]
+        );
+    }
+}
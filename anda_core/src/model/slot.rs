@@ -0,0 +1,223 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+
+use super::Value;
+use crate::BoxError;
+
+/// A form whose fields (slots) are filled from the conversation turn by turn.
+///
+/// The schema is a JSON schema of an object. Its `required` fields must be filled to
+/// complete the form, and the `description` of its properties is used to ask for them.
+///
+/// # Example
+/// ```json
+/// {
+///   "name": "shipping_address",
+///   "description": "The address to ship the order to.",
+///   "schema": {
+///     "type": "object",
+///     "properties": {
+///       "name": { "type": "string", "description": "the recipient's full name" },
+///       "street": { "type": "string", "description": "the street and number" },
+///       "city": { "type": "string" },
+///       "phone": { "type": "string", "description": "a contact phone number" }
+///     },
+///     "required": ["name", "street", "city"]
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SlotForm {
+    /// The unique name of the form.
+    pub name: String,
+
+    /// The description of the form, given to the model as context.
+    #[serde(default)]
+    pub description: String,
+
+    /// The JSON schema of the object to fill.
+    pub schema: Value,
+}
+
+impl SlotForm {
+    /// Validates the form: the schema is an object schema with properties,
+    /// and the required fields are properties.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        if self.name.is_empty() {
+            return Err("form name should not be empty".into());
+        }
+        let properties = self
+            .properties()
+            .ok_or_else(|| format!("form {} should have an object schema", self.name))?;
+        if properties.is_empty() {
+            return Err(format!("form {} has no properties", self.name).into());
+        }
+        for field in self.required() {
+            if !properties.contains_key(field) {
+                return Err(format!(
+                    "required field {} of form {} is not a property",
+                    field, self.name
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the properties of the schema.
+    pub fn properties(&self) -> Option<&Map<String, Value>> {
+        self.schema.get("properties").and_then(|p| p.as_object())
+    }
+
+    /// Returns the required fields of the schema.
+    pub fn required(&self) -> Vec<&str> {
+        self.schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .map(|r| r.iter().filter_map(|f| f.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the description of a field, or its name if it has none.
+    pub fn field_description<'a>(&'a self, field: &'a str) -> &'a str {
+        self.properties()
+            .and_then(|p| p.get(field))
+            .and_then(|p| p.get("description"))
+            .and_then(|d| d.as_str())
+            .unwrap_or(field)
+    }
+
+    /// Returns the schema with no required fields, so that a partial object can be extracted.
+    pub fn partial_schema(&self) -> Value {
+        let mut schema = self.schema.clone();
+        if let Some(obj) = schema.as_object_mut() {
+            obj.remove("required");
+        }
+        schema
+    }
+}
+
+/// The state of a form being filled, persisted in the thread metadata.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct SlotState {
+    /// The name of the form.
+    pub form: String,
+
+    /// The values filled so far.
+    pub values: Map<String, Value>,
+
+    /// The number of turns so far.
+    pub turns: u32,
+
+    /// Whether all the required fields are filled.
+    pub completed: bool,
+
+    pub updated_at: u64,
+}
+
+impl SlotState {
+    /// Creates an empty state of the form.
+    pub fn new(form: &SlotForm, now_ms: u64) -> Self {
+        Self {
+            form: form.name.clone(),
+            updated_at: now_ms,
+            ..Default::default()
+        }
+    }
+
+    /// Merges extracted values into the state. Unknown fields and empty values are ignored,
+    /// so that a turn never erases a value filled before.
+    /// Returns the fields that were filled or changed.
+    pub fn merge(
+        &mut self,
+        form: &SlotForm,
+        extracted: Map<String, Value>,
+        now_ms: u64,
+    ) -> Vec<String> {
+        let mut changed = Vec::new();
+        if let Some(properties) = form.properties() {
+            for (field, value) in extracted {
+                if !properties.contains_key(&field) || is_empty(&value) {
+                    continue;
+                }
+                if self.values.get(&field) != Some(&value) {
+                    self.values.insert(field.clone(), value);
+                    changed.push(field);
+                }
+            }
+        }
+        self.turns += 1;
+        self.completed = self.missing(form).is_empty();
+        self.updated_at = now_ms;
+        changed
+    }
+
+    /// Returns the required fields that are not filled yet, in schema order.
+    pub fn missing<'a>(&self, form: &'a SlotForm) -> Vec<&'a str> {
+        form.required()
+            .into_iter()
+            .filter(|f| self.values.get(*f).is_none_or(is_empty))
+            .collect()
+    }
+}
+
+fn is_empty(v: &Value) -> bool {
+    match v {
+        Value::Null => true,
+        Value::String(s) => s.trim().is_empty(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_slot_state() {
+        let form: SlotForm = serde_json::from_value(json!({
+            "name": "shipping_address",
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string", "description": "the recipient's full name"},
+                    "city": {"type": "string"},
+                    "phone": {"type": "string"}
+                },
+                "required": ["name", "city"]
+            }
+        }))
+        .unwrap();
+        form.validate().unwrap();
+        assert_eq!(form.field_description("name"), "the recipient's full name");
+        assert_eq!(form.field_description("city"), "city");
+        assert!(form.partial_schema().get("required").is_none());
+
+        let mut state = SlotState::new(&form, 1);
+        assert_eq!(state.missing(&form), vec!["name", "city"]);
+
+        let extracted = json!({"name": "Alice", "city": "", "zip": "10001"});
+        let changed = state.merge(&form, extracted.as_object().unwrap().clone(), 2);
+        assert_eq!(changed, vec!["name"]);
+        assert_eq!(state.missing(&form), vec!["city"]);
+        assert!(!state.completed);
+
+        let extracted = json!({"name": null, "city": "Paris"});
+        state.merge(&form, extracted.as_object().unwrap().clone(), 3);
+        assert!(state.completed);
+        assert_eq!(state.turns, 2);
+        assert_eq!(
+            Value::Object(state.values),
+            json!({"name": "Alice", "city": "Paris"})
+        );
+
+        let invalid = SlotForm {
+            name: "x".to_string(),
+            schema: json!({"type": "object", "properties": {"a": {}}, "required": ["b"]}),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,484 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// The metadata key of the detected language of a document, an ISO 639-1 code.
+pub const META_LANG: &str = "lang";
+
+/// The metadata key of the original encoding of a document decoded from bytes.
+pub const META_ENCODING: &str = "encoding";
+
+/// The text encodings recognized by [`decode_text`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// The legacy Western encoding, the fallback for bytes that are not valid UTF-8.
+    Windows1252,
+}
+
+impl TextEncoding {
+    /// Returns the name of the encoding, e.g. "utf-8".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Windows1252 => "windows-1252",
+        }
+    }
+}
+
+/// The characters of the Windows-1252 bytes 0x80..=0x9F. The bytes undefined in
+/// Windows-1252 map to the C1 control characters, as in Latin-1.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// Returns the character of a Windows-1252 byte.
+pub fn windows_1252_char(b: u8) -> char {
+    match b {
+        0x80..=0x9F => WINDOWS_1252[(b - 0x80) as usize],
+        _ => b as char,
+    }
+}
+
+fn windows_1252_byte(c: char) -> Option<u8> {
+    match c as u32 {
+        0..=0x7F | 0xA0..=0xFF => Some(c as u8),
+        _ => WINDOWS_1252
+            .iter()
+            .position(|&w| w == c)
+            .map(|i| 0x80 + i as u8),
+    }
+}
+
+/// Decodes bytes into text: UTF-8 or UTF-16 with a byte order mark, valid UTF-8, or
+/// Windows-1252 otherwise. The byte order mark is removed.
+pub fn decode_text(bytes: &[u8]) -> (String, TextEncoding) {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return (
+            String::from_utf8_lossy(rest).into_owned(),
+            TextEncoding::Utf8,
+        );
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        let units = rest
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]));
+        return (
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+            TextEncoding::Utf16Le,
+        );
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units = rest
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]));
+        return (
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+            TextEncoding::Utf16Be,
+        );
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), TextEncoding::Utf8),
+        Err(_) => (
+            bytes.iter().map(|&b| windows_1252_char(b)).collect(),
+            TextEncoding::Windows1252,
+        ),
+    }
+}
+
+/// Repairs mojibake: UTF-8 text that was decoded as Windows-1252 or Latin-1, e.g. "cafÃ©"
+/// for "café", up to twice. Each run of characters of Windows-1252 is repaired on its own,
+/// and runs that do not decode as UTF-8 this way are kept as they are.
+pub fn repair_mojibake(text: &str) -> Cow<'_, str> {
+    match repair_mojibake_once(text) {
+        Cow::Borrowed(text) => Cow::Borrowed(text),
+        Cow::Owned(repaired) => match repair_mojibake_once(&repaired) {
+            Cow::Borrowed(_) => Cow::Owned(repaired),
+            Cow::Owned(repaired) => Cow::Owned(repaired),
+        },
+    }
+}
+
+fn repair_mojibake_once(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut run: Vec<u8> = Vec::new();
+    let mut run_start = 0;
+    let mut repaired = false;
+    let mut flush = |out: &mut String, run: &mut Vec<u8>, original: &str| {
+        match std::str::from_utf8(run) {
+            Ok(decoded) if decoded != original => {
+                out.push_str(decoded);
+                repaired = true;
+            }
+            _ => out.push_str(original),
+        }
+        run.clear();
+    };
+    for (i, c) in text.char_indices() {
+        match windows_1252_byte(c) {
+            Some(b) => run.push(b),
+            None => {
+                flush(&mut out, &mut run, &text[run_start..i]);
+                out.push(c);
+                run_start = i + c.len_utf8();
+            }
+        }
+    }
+    flush(&mut out, &mut run, &text[run_start..]);
+    if repaired {
+        Cow::Owned(out)
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+const HTML_ENTITIES: [(&str, &str); 36] = [
+    ("amp", "&"),
+    ("lt", "<"),
+    ("gt", ">"),
+    ("quot", "\""),
+    ("apos", "'"),
+    ("nbsp", " "),
+    ("copy", "©"),
+    ("reg", "®"),
+    ("trade", "™"),
+    ("hellip", "…"),
+    ("mdash", "—"),
+    ("ndash", "–"),
+    ("lsquo", "‘"),
+    ("rsquo", "’"),
+    ("ldquo", "“"),
+    ("rdquo", "”"),
+    ("sbquo", "‚"),
+    ("bdquo", "„"),
+    ("laquo", "«"),
+    ("raquo", "»"),
+    ("euro", "€"),
+    ("pound", "£"),
+    ("yen", "¥"),
+    ("cent", "¢"),
+    ("deg", "°"),
+    ("middot", "·"),
+    ("bull", "•"),
+    ("times", "×"),
+    ("divide", "÷"),
+    ("plusmn", "±"),
+    ("para", "¶"),
+    ("sect", "§"),
+    ("iexcl", "¡"),
+    ("iquest", "¿"),
+    ("shy", ""),
+    ("zwj", "\u{200D}"),
+];
+
+/// Decodes the HTML character references of the text: the numeric ones, e.g. "&#39;" and
+/// "&#x1F600;", and the common named ones, e.g. "&amp;" and "&rsquo;". References must end
+/// with ";", and are decoded once, so "&amp;lt;" is decoded to "&lt;".
+pub fn decode_html_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end > 0 && end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end + 1])?, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                out.push_str(&c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+fn decode_entity(name: &str) -> Option<Cow<'static, str>> {
+    if let Some(num) = name.strip_prefix('#') {
+        let code = match num.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        return match char::from_u32(code) {
+            Some(c) if code != 0 => Some(Cow::Owned(c.to_string())),
+            _ => None,
+        };
+    }
+    HTML_ENTITIES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, v)| Cow::Borrowed(*v))
+}
+
+/// Normalizes text for ingestion: repairs mojibake, decodes HTML character references,
+/// converts line endings to "\n" and removes the control characters except tabs and
+/// line feeds, and the byte order marks.
+pub fn normalize_text(text: &str) -> String {
+    let text = repair_mojibake(text);
+    let text = decode_html_entities(&text);
+    let text = text.replace("\r\n", "\n");
+    text.chars()
+        .filter_map(|c| match c {
+            '\r' => Some('\n'),
+            '\n' | '\t' => Some(c),
+            '\u{FEFF}' => None,
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+const STOPWORDS: [(&str, &[&str]); 7] = [
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "of", "to", "that", "it", "with", "for", "this", "was",
+            "you", "what", "how", "not", "be", "have", "i", "my",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "que", "y", "es", "por", "con", "para", "una", "del", "qué",
+            "cómo", "está", "pero", "muy", "yo", "su",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "les", "des", "et", "est", "une", "pour", "pas", "dans", "du", "qui", "ce",
+            "vous", "je", "avec", "sur", "au", "mais",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "zu", "mit", "den", "von",
+            "ich", "sie", "auf", "für", "wie", "was", "auch",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "di", "che", "è", "per", "non", "sono", "della", "gli", "come", "questo",
+            "anche", "ma", "mi", "ho", "nel",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "os", "que", "é", "um", "uma", "para", "não", "com", "em", "do", "da", "você",
+            "está", "mas", "muito", "eu",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "het", "een", "en", "van", "is", "niet", "dat", "op", "te", "zijn", "met", "voor",
+            "ik", "je", "wat", "hoe", "er", "ook",
+        ],
+    ),
+];
+
+/// Detects the language of the text, returning its ISO 639-1 code, e.g. "en", or None if
+/// it is not recognized. The script of the letters identifies most languages; texts in
+/// the Latin script are told apart by their most frequent words, which recognizes
+/// English, Spanish, French, German, Italian, Portuguese and Dutch.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    // letters of: latin, cyrillic, greek, arabic, hebrew, devanagari, thai, hangul, kana, han
+    let mut scripts = [0usize; 10];
+    let mut ukrainian = false;
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        let script = match c as u32 {
+            0x0041..=0x024F => 0,
+            0x0400..=0x04FF => {
+                ukrainian |= matches!(c, 'і' | 'ї' | 'є' | 'ґ' | 'І' | 'Ї' | 'Є' | 'Ґ');
+                1
+            }
+            0x0370..=0x03FF => 2,
+            0x0600..=0x06FF => 3,
+            0x0590..=0x05FF => 4,
+            0x0900..=0x097F => 5,
+            0x0E00..=0x0E7F => 6,
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => 7,
+            0x3040..=0x30FF => 8,
+            0x4E00..=0x9FFF | 0x3400..=0x4DBF => 9,
+            _ => continue,
+        };
+        scripts[script] += 1;
+    }
+
+    let (script, count) = scripts
+        .iter()
+        .enumerate()
+        .max_by_key(|(i, n)| (**n, std::cmp::Reverse(*i)))?;
+    if *count == 0 {
+        return None;
+    }
+    match script {
+        0 => detect_latin_language(text),
+        1 if ukrainian => Some("uk"),
+        1 => Some("ru"),
+        2 => Some("el"),
+        3 => Some("ar"),
+        4 => Some("he"),
+        5 => Some("hi"),
+        6 => Some("th"),
+        7 => Some("ko"),
+        8 => Some("ja"),
+        // Japanese mixes kana with kanji
+        _ if scripts[8] > 0 => Some("ja"),
+        _ => Some("zh"),
+    }
+}
+
+fn detect_latin_language(text: &str) -> Option<&'static str> {
+    let text = text.to_lowercase();
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let mut best: Option<(&'static str, usize)> = None;
+    let mut tie = false;
+    for (lang, stopwords) in STOPWORDS {
+        let hits = words.iter().filter(|w| stopwords.contains(w)).count();
+        match best {
+            Some((_, n)) if hits == n => tie = true,
+            Some((_, n)) if hits < n => {}
+            _ if hits > 0 => {
+                best = Some((lang, hits));
+                tie = false;
+            }
+            _ => {}
+        }
+    }
+    match best {
+        Some((lang, _)) if !tie => Some(lang),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(
+            decode_text("café".as_bytes()),
+            ("café".to_string(), TextEncoding::Utf8)
+        );
+        assert_eq!(
+            decode_text(b"\xEF\xBB\xBFhello"),
+            ("hello".to_string(), TextEncoding::Utf8)
+        );
+        assert_eq!(
+            decode_text(b"\xFF\xFEh\x00i\x00"),
+            ("hi".to_string(), TextEncoding::Utf16Le)
+        );
+        assert_eq!(
+            decode_text(b"\xFE\xFF\x00h\x00i"),
+            ("hi".to_string(), TextEncoding::Utf16Be)
+        );
+        assert_eq!(
+            decode_text(b"caf\xE9 \x93ok\x94"),
+            ("café “ok”".to_string(), TextEncoding::Windows1252)
+        );
+        assert_eq!(TextEncoding::Utf16Le.as_str(), "utf-16le");
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(repair_mojibake("cafÃ©"), "café");
+        assert_eq!(repair_mojibake("itâ€™s"), "it’s");
+        // encoded twice
+        assert_eq!(repair_mojibake("cafÃƒÂ©"), "café");
+        // valid text is kept
+        assert!(matches!(repair_mojibake("café"), Cow::Borrowed("café")));
+        assert!(matches!(repair_mojibake("日本語"), Cow::Borrowed(_)));
+        // runs are repaired on their own
+        assert_eq!(repair_mojibake("日本 cafÃ© 😀 naïve"), "日本 café 😀 naïve");
+
+        assert_eq!(
+            decode_html_entities("Tom &amp; Jerry &lt;3 &#39;hi&#x27; &rsquo;&#x1F600;"),
+            "Tom & Jerry <3 'hi' ’😀"
+        );
+        assert_eq!(decode_html_entities("&amp;lt;"), "&lt;");
+        assert_eq!(
+            decode_html_entities("AT&T & co &unknown; &#0; &#xZZ; &"),
+            "AT&T & co &unknown; &#0; &#xZZ; &"
+        );
+
+        assert_eq!(
+            normalize_text("\u{FEFF}Caf\u{00C3}\u{00A9}\r\nline&nbsp;2\rend\u{7}\t!"),
+            "Café\nline 2\nend\t!"
+        );
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(
+            detect_language("What is the weather like in Paris today?"),
+            Some("en")
+        );
+        assert_eq!(
+            detect_language("¿Qué tiempo hace hoy en la ciudad? Es muy bonito."),
+            Some("es")
+        );
+        assert_eq!(
+            detect_language("Je ne sais pas si vous avez le temps pour cela."),
+            Some("fr")
+        );
+        assert_eq!(
+            detect_language("Ich weiß nicht, wie das Wetter in der Stadt ist."),
+            Some("de")
+        );
+        assert_eq!(
+            detect_language("Non so come sono arrivato qui, ma questo è il posto."),
+            Some("it")
+        );
+        assert_eq!(
+            detect_language("Eu não sei se você está em casa, mas é muito tarde."),
+            Some("pt")
+        );
+        assert_eq!(
+            detect_language("Ik weet niet wat het is, maar het is ook goed."),
+            Some("nl")
+        );
+        assert_eq!(detect_language("Привет, как дела?"), Some("ru"));
+        assert_eq!(detect_language("Привіт, як справи? Їжак"), Some("uk"));
+        assert_eq!(detect_language("今天天气怎么样？"), Some("zh"));
+        assert_eq!(detect_language("今日はいい天気ですね"), Some("ja"));
+        assert_eq!(detect_language("안녕하세요"), Some("ko"));
+        assert_eq!(detect_language("مرحبا كيف حالك"), Some("ar"));
+        assert_eq!(detect_language("Γειά σου κόσμε"), Some("el"));
+        assert_eq!(detect_language("Anda ICP"), None);
+        assert_eq!(detect_language("12345 !!!"), None);
+        assert_eq!(detect_language(""), None);
+    }
+}
@@ -1,13 +1,15 @@
 use candid::Principal;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
 
-use super::{Message, Value, Xid};
+use super::{Message, Migrate, SlotState, Value, WorkflowProgress, Xid};
 use crate::UpdateVersion;
 
 /// Thread is a conversation session between Agents and user. Threads store Messages and automatically handle truncation to fit content into a model’s context.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Thread {
+    #[schemars(with = "String")]
     pub id: Xid,
 
     /// The messages in the thread.
@@ -19,8 +21,9 @@ pub struct Thread {
 }
 
 /// Represents a message in a thread.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct ThreadMessage {
+    #[schemars(with = "String")]
     pub id: Xid,
 
     /// Message role: "system", "user", "assistant", "tool".
@@ -34,6 +37,10 @@ pub struct ThreadMessage {
     pub name: Option<String>,
 }
 
+impl Migrate for ThreadMessage {
+    const VERSION: u32 = 1;
+}
+
 impl ThreadMessage {
     pub fn new(id: Xid, msg: Message) -> Self {
         Self {
@@ -46,22 +53,27 @@ impl ThreadMessage {
 }
 
 /// Represents the metadata for a thread of conversation.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct ThreadMeta {
     /// The unique identifier for the thread.
+    #[schemars(with = "String")]
     pub id: Xid,
 
     /// The principal of the agent that created and serve the thread.
+    #[schemars(with = "String")]
     pub agent: Principal,
 
     /// The initiator of the thread, typically an agent or user principal.
+    #[schemars(with = "String")]
     pub initiator: Principal,
 
     /// The participants of the thread.
+    #[schemars(with = "BTreeSet<String>")]
     pub participants: BTreeSet<Principal>,
 
     /// The children threads of this thread.
     /// The key is the principal of the agent that created the child thread.
+    #[schemars(with = "BTreeMap<String, String>")]
     pub children: BTreeMap<Principal, Xid>,
 
     /// The timestamp when the thread was last updated.
@@ -69,17 +81,31 @@ pub struct ThreadMeta {
 
     /// The parent thread of this thread.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub parent: Option<Xid>,
 
     /// The description of the thread, can be generated by LLM from the conversation.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
 
+    /// The progress of the workflow driven in the thread, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<WorkflowProgress>,
+
+    /// The state of the form filled in the thread, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub form: Option<SlotState>,
+
     /// The version of the thread object.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<super::schema::UpdateVersionSchema>")]
     pub version: Option<UpdateVersion>,
 }
 
+impl Migrate for ThreadMeta {
+    const VERSION: u32 = 1;
+}
+
 impl ThreadMeta {
     pub fn new(id: Xid, agent: Principal, initiator: Principal, now_ms: u64) -> Self {
         Self {
@@ -91,6 +117,8 @@ impl ThreadMeta {
             updated_at: now_ms,
             parent: None,
             description: None,
+            workflow: None,
+            form: None,
             version: None,
         }
     }
@@ -117,6 +145,10 @@ pub struct MyThreads {
     threads: BTreeMap<Xid, u64>,
 }
 
+impl Migrate for MyThreads {
+    const VERSION: u32 = 1;
+}
+
 impl MyThreads {
     pub fn new(self_id: Principal) -> Self {
         let mut agents = BTreeMap::new();
@@ -0,0 +1,85 @@
+/// Counts the tokens of texts for a model, for context-window budgeting and usage estimates.
+pub trait Tokenizer: Send + Sync {
+    /// Returns the number of tokens of the text.
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+/// A tokenizer that estimates the number of tokens without a vocabulary.
+///
+/// Runs of ASCII letters and digits count a token per 5 characters, runs of other letters
+/// and of punctuation a token per 2 characters, and CJK characters a token each. It is
+/// close to BPE tokenizers for English prose, code and CJK text, but it is only an estimate.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeuristicTokenizer;
+
+impl Tokenizer for HeuristicTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        // the lengths of the current runs of ASCII letters and digits, other letters and
+        // punctuation; at most one of them is not zero
+        let (mut ascii, mut letters, mut puncts) = (0usize, 0usize, 0usize);
+        let mut tokens = 0;
+        for c in text.chars() {
+            let kind = if c.is_ascii_alphanumeric() {
+                0
+            } else if c.is_alphanumeric() && !is_cjk(c) {
+                1
+            } else if !c.is_whitespace() && !is_cjk(c) {
+                2
+            } else {
+                3
+            };
+            if kind != 0 {
+                tokens += ascii.div_ceil(5);
+                ascii = 0;
+            }
+            if kind != 1 {
+                tokens += letters.div_ceil(2);
+                letters = 0;
+            }
+            if kind != 2 {
+                tokens += puncts.div_ceil(2);
+                puncts = 0;
+            }
+            match kind {
+                0 => ascii += 1,
+                1 => letters += 1,
+                2 => puncts += 1,
+                // whitespace is merged into the next token, a CJK character is a token
+                _ => tokens += usize::from(!c.is_whitespace()),
+            }
+        }
+        tokens + ascii.div_ceil(5) + letters.div_ceil(2) + puncts.div_ceil(2)
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF     // Hiragana, Katakana
+        | 0x3400..=0x4DBF   // CJK Extension A
+        | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+        | 0xAC00..=0xD7AF   // Hangul Syllables
+        | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+        | 0x20000..=0x2FFFF // CJK Extensions B to F
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heuristic_tokenizer() {
+        let t = HeuristicTokenizer;
+        assert_eq!(t.count_tokens(""), 0);
+        // cl100k_base: 10 tokens
+        assert_eq!(
+            t.count_tokens("Anda is an AI agent framework built with Rust."),
+            11
+        );
+        // cl100k_base: 12 tokens
+        assert_eq!(t.count_tokens("你好，世界！欢迎使用安达。"), 13);
+        // cl100k_base: 12 tokens
+        assert_eq!(t.count_tokens("fn main() { println!(\"{}\", x); }"), 13);
+        assert_eq!(t.count_tokens("Привет, мир"), 6);
+    }
+}
@@ -0,0 +1,134 @@
+use candid::CandidType;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How to fit content that exceeds a size limit.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, CandidType, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    /// Keeps the beginning of the content, the default.
+    #[default]
+    Head,
+    /// Keeps the end of the content, e.g. for logs.
+    Tail,
+    /// Replaces the content with a summary generated by the model,
+    /// falling back to [`TruncationStrategy::Head`] if the summary is still too large.
+    Summarize,
+    /// Rejects the content.
+    Reject,
+}
+
+/// A maximum size in bytes with the strategy to apply above it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct SizeLimit {
+    pub max_bytes: usize,
+
+    #[serde(default)]
+    pub strategy: TruncationStrategy,
+}
+
+impl SizeLimit {
+    /// Creates a new size limit.
+    pub fn new(max_bytes: usize, strategy: TruncationStrategy) -> Self {
+        Self {
+            max_bytes,
+            strategy,
+        }
+    }
+}
+
+/// The size limits of an engine. No limit is applied if a field is None.
+#[derive(Debug, Clone, Default, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct SizeLimits {
+    /// The maximum size of the prompt of a request.
+    #[serde(default)]
+    pub prompt: Option<SizeLimit>,
+
+    /// The maximum size of a tool result passed back to the model.
+    #[serde(default)]
+    pub tool_result: Option<SizeLimit>,
+
+    /// The maximum size of an inline resource blob. Only text resources can be truncated,
+    /// larger binary resources are rejected.
+    #[serde(default)]
+    pub resource_inline: Option<SizeLimit>,
+}
+
+/// A record of content that was truncated to fit a size limit.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, Serialize, JsonSchema)]
+pub struct Truncation {
+    /// What was truncated: "prompt", "tool:{name}" or "resource:{label}".
+    pub target: String,
+
+    /// The strategy applied.
+    pub strategy: TruncationStrategy,
+
+    /// The original size in bytes.
+    pub original_size: usize,
+
+    /// The size in bytes after truncation.
+    pub size: usize,
+}
+
+/// Truncates the text to at most `max_bytes` bytes at a char boundary, keeping the head or
+/// the tail, with a marker of the truncated size. Returns None if the text fits.
+///
+/// [`TruncationStrategy::Summarize`] and [`TruncationStrategy::Reject`] are handled by the
+/// caller; they keep the head here.
+pub fn truncate_text(text: &str, max_bytes: usize, strategy: TruncationStrategy) -> Option<String> {
+    if text.len() <= max_bytes {
+        return None;
+    }
+
+    let marker = |n: usize| format!("\n…[truncated {} bytes]…\n", n);
+    // reserve room for the marker, whose length only depends on the number of digits
+    let keep = max_bytes.saturating_sub(marker(text.len()).len());
+    match strategy {
+        TruncationStrategy::Tail => {
+            let mut start = text.len() - keep;
+            while !text.is_char_boundary(start) {
+                start += 1;
+            }
+            Some(format!("{}{}", marker(start).trim_start(), &text[start..]))
+        }
+        _ => {
+            let mut end = keep;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            Some(format!(
+                "{}{}",
+                &text[..end],
+                marker(text.len() - end).trim_end()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_text() {
+        assert_eq!(truncate_text("hello", 5, TruncationStrategy::Head), None);
+
+        let text = "你好世界".repeat(20) + &"abc".repeat(20);
+        let head = truncate_text(&text, 64, TruncationStrategy::Head).unwrap();
+        assert!(head.len() <= 64);
+        assert!(head.starts_with("你好"));
+        assert!(head.ends_with("bytes]…"));
+
+        let tail = truncate_text(&text, 64, TruncationStrategy::Tail).unwrap();
+        assert!(tail.len() <= 64);
+        assert!(tail.starts_with("…[truncated"));
+        assert!(tail.ends_with("abcabc"));
+
+        assert_eq!(
+            truncate_text("abcdef", 2, TruncationStrategy::Head).unwrap(),
+            "\n…[truncated 6 bytes]…"
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use super::Value;
+use crate::BoxError;
+
+/// Structured UI hints of an agent output: suggested replies, buttons and a confirmation,
+/// that connectors can render as the interactive elements of their platforms.
+///
+/// Every element is an action with an ID ([`UiHints::actions`]). When the user picks one,
+/// the connector resolves the ID with [`UiHints::resolve`] and sends the [`UiCallback`]
+/// back in [`RequestMeta::callback`](super::RequestMeta::callback), instead of free text.
+///
+/// # Example
+/// ```json
+/// {
+///   "replies": ["Show more", "That's all"],
+///   "buttons": [
+///     { "id": "buy", "label": "Buy now", "payload": { "sku": "A1" }, "style": "primary" }
+///   ],
+///   "confirm": { "id": "transfer", "text": "Transfer 10 ICP to alice?" }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct UiHints {
+    /// Suggested replies, sent back as the user's message when picked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replies: Vec<String>,
+
+    /// Buttons with callback payloads.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<UiButton>,
+
+    /// A confirmation asked to the user, rendered as confirm and cancel buttons.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<UiConfirm>,
+}
+
+/// A button with a callback payload.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct UiButton {
+    /// The ID of the button, unique in the hints.
+    pub id: String,
+
+    /// The label of the button.
+    pub label: String,
+
+    /// The payload sent back when the button is pressed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+
+    /// The style of the button.
+    #[serde(default, skip_serializing_if = "UiStyle::is_default")]
+    pub style: UiStyle,
+}
+
+impl UiButton {
+    /// Creates a button with the given ID and label.
+    pub fn new(id: impl Into<String>, label: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            label: label.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the payload sent back when the button is pressed.
+    pub fn with_payload(mut self, payload: Value) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+
+    /// Sets the style of the button.
+    pub fn with_style(mut self, style: UiStyle) -> Self {
+        self.style = style;
+        self
+    }
+}
+
+/// A confirmation asked to the user.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct UiConfirm {
+    /// The ID of the confirmation, unique in the hints.
+    pub id: String,
+
+    /// The question to confirm.
+    pub text: String,
+
+    /// The label of the confirm button, "Confirm" if empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub confirm_label: String,
+
+    /// The label of the cancel button, "Cancel" if empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cancel_label: String,
+
+    /// The payload sent back with the answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+}
+
+impl UiConfirm {
+    /// Creates a confirmation with the given ID and question.
+    pub fn new(id: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the labels of the confirm and cancel buttons.
+    pub fn with_labels(mut self, confirm: impl Into<String>, cancel: impl Into<String>) -> Self {
+        self.confirm_label = confirm.into();
+        self.cancel_label = cancel.into();
+        self
+    }
+
+    /// Sets the payload sent back with the answer.
+    pub fn with_payload(mut self, payload: Value) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+}
+
+/// The style of a button, mapped to the closest style of each platform.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UiStyle {
+    #[default]
+    Default,
+    Primary,
+    Danger,
+}
+
+impl UiStyle {
+    pub fn is_default(&self) -> bool {
+        *self == UiStyle::Default
+    }
+}
+
+/// An action of the hints to render, in the order of [`UiHints::actions`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UiAction {
+    /// The ID to route back, e.g. as the callback data of a Telegram button,
+    /// the value of a Slack button or the custom ID of a Discord button.
+    pub id: String,
+
+    /// The label of the action.
+    pub label: String,
+
+    /// The style of the action.
+    pub style: UiStyle,
+}
+
+/// An action picked by the user, sent back to the agent as a structured input.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UiCallback {
+    /// A suggested reply was picked.
+    Reply { text: String },
+
+    /// A button was pressed.
+    Button {
+        id: String,
+        label: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+
+    /// A confirmation was answered.
+    Confirm {
+        id: String,
+        confirmed: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+}
+
+impl UiCallback {
+    /// Returns the text of the callback, used as the prompt of a request without one.
+    pub fn to_prompt(&self) -> String {
+        match self {
+            UiCallback::Reply { text } => text.clone(),
+            UiCallback::Button { label, .. } => label.clone(),
+            UiCallback::Confirm { confirmed, .. } => {
+                if *confirmed {
+                    "Confirmed.".to_string()
+                } else {
+                    "Cancelled.".to_string()
+                }
+            }
+        }
+    }
+}
+
+const REPLY_PREFIX: &str = "reply:";
+const CONFIRM_SUFFIX: &str = ":confirm";
+const CANCEL_SUFFIX: &str = ":cancel";
+
+impl UiHints {
+    /// Returns true if there is nothing to render.
+    pub fn is_empty(&self) -> bool {
+        self.replies.is_empty() && self.buttons.is_empty() && self.confirm.is_none()
+    }
+
+    /// Adds a suggested reply.
+    pub fn with_reply(mut self, reply: impl Into<String>) -> Self {
+        self.replies.push(reply.into());
+        self
+    }
+
+    /// Adds a button.
+    pub fn with_button(mut self, button: UiButton) -> Self {
+        self.buttons.push(button);
+        self
+    }
+
+    /// Sets the confirmation.
+    pub fn with_confirm(mut self, confirm: UiConfirm) -> Self {
+        self.confirm = Some(confirm);
+        self
+    }
+
+    /// Returns the actions to render: the suggested replies (`reply:{index}`), the buttons
+    /// (their IDs), then the confirm and cancel buttons (`{id}:confirm` and `{id}:cancel`).
+    pub fn actions(&self) -> Vec<UiAction> {
+        let mut actions: Vec<UiAction> = self
+            .replies
+            .iter()
+            .enumerate()
+            .map(|(i, reply)| UiAction {
+                id: format!("{}{}", REPLY_PREFIX, i),
+                label: reply.clone(),
+                style: UiStyle::Default,
+            })
+            .collect();
+        actions.extend(self.buttons.iter().map(|b| UiAction {
+            id: b.id.clone(),
+            label: b.label.clone(),
+            style: b.style,
+        }));
+        if let Some(confirm) = &self.confirm {
+            actions.push(UiAction {
+                id: format!("{}{}", confirm.id, CONFIRM_SUFFIX),
+                label: if confirm.confirm_label.is_empty() {
+                    "Confirm".to_string()
+                } else {
+                    confirm.confirm_label.clone()
+                },
+                style: UiStyle::Primary,
+            });
+            actions.push(UiAction {
+                id: format!("{}{}", confirm.id, CANCEL_SUFFIX),
+                label: if confirm.cancel_label.is_empty() {
+                    "Cancel".to_string()
+                } else {
+                    confirm.cancel_label.clone()
+                },
+                style: UiStyle::Default,
+            });
+        }
+        actions
+    }
+
+    /// Resolves the ID of an action picked by the user to its callback.
+    pub fn resolve(&self, action_id: &str) -> Option<UiCallback> {
+        if let Some(button) = self.buttons.iter().find(|b| b.id == action_id) {
+            return Some(UiCallback::Button {
+                id: button.id.clone(),
+                label: button.label.clone(),
+                payload: button.payload.clone(),
+            });
+        }
+        if let Some(confirm) = &self.confirm
+            && let Some(answer) = action_id.strip_prefix(confirm.id.as_str())
+            && (answer == CONFIRM_SUFFIX || answer == CANCEL_SUFFIX)
+        {
+            return Some(UiCallback::Confirm {
+                id: confirm.id.clone(),
+                confirmed: answer == CONFIRM_SUFFIX,
+                payload: confirm.payload.clone(),
+            });
+        }
+        let index: usize = action_id.strip_prefix(REPLY_PREFIX)?.parse().ok()?;
+        self.replies
+            .get(index)
+            .map(|text| UiCallback::Reply { text: text.clone() })
+    }
+
+    /// Validates the hints: IDs are not empty, and the IDs of actions are unique.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        for button in &self.buttons {
+            if button.id.is_empty() {
+                return Err(format!("button {:?} has no ID", button.label).into());
+            }
+        }
+        if let Some(confirm) = &self.confirm
+            && confirm.id.is_empty()
+        {
+            return Err("confirmation has no ID".into());
+        }
+        let mut ids = BTreeSet::new();
+        for action in self.actions() {
+            if !ids.insert(action.id.clone()) {
+                return Err(format!("duplicate action ID {}", action.id).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_ui_hints() {
+        let hints = UiHints::default()
+            .with_reply("Show more")
+            .with_button(
+                UiButton::new("buy", "Buy now")
+                    .with_payload(json!({"sku": "A1"}))
+                    .with_style(UiStyle::Primary),
+            )
+            .with_confirm(UiConfirm::new("transfer", "Transfer 10 ICP to alice?"));
+        assert!(hints.validate().is_ok());
+
+        let ids: Vec<String> = hints.actions().into_iter().map(|a| a.id).collect();
+        assert_eq!(
+            ids,
+            vec!["reply:0", "buy", "transfer:confirm", "transfer:cancel"]
+        );
+
+        assert_eq!(
+            hints.resolve("reply:0"),
+            Some(UiCallback::Reply {
+                text: "Show more".to_string()
+            })
+        );
+        assert_eq!(hints.resolve("reply:1"), None);
+        assert_eq!(
+            hints.resolve("buy"),
+            Some(UiCallback::Button {
+                id: "buy".to_string(),
+                label: "Buy now".to_string(),
+                payload: Some(json!({"sku": "A1"})),
+            })
+        );
+        let cancel = hints.resolve("transfer:cancel").unwrap();
+        assert_eq!(cancel.to_prompt(), "Cancelled.");
+        assert_eq!(
+            serde_json::to_value(&cancel).unwrap(),
+            json!({"type": "confirm", "id": "transfer", "confirmed": false})
+        );
+        assert_eq!(hints.resolve("transfer:maybe"), None);
+
+        let value = serde_json::to_value(&hints).unwrap();
+        assert_eq!(value["buttons"][0]["style"], "primary");
+        assert!(value["confirm"].get("confirm_label").is_none());
+        let hints2: UiHints = serde_json::from_value(value).unwrap();
+        assert_eq!(hints, hints2);
+
+        let hints = UiHints::default()
+            .with_button(UiButton::new("reply:0", "Oops"))
+            .with_reply("Hi");
+        assert!(hints.validate().is_err());
+    }
+}
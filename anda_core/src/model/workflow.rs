@@ -0,0 +1,579 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::Value;
+use crate::BoxError;
+
+/// The step ID that ends a workflow when used as a branch target.
+pub static WORKFLOW_END: &str = "end";
+
+/// A guided workflow, e.g. an onboarding or KYC intake flow, that the engine drives
+/// turn by turn in a thread.
+///
+/// # Example
+/// ```toml
+/// name = "kyc_intake"
+/// description = "Collects the customer's identity for KYC."
+/// completion = "Thanks, your application is submitted."
+///
+/// [[steps]]
+/// id = "identity"
+/// prompt = "What is your full name and country of residence?"
+/// output_schema = { type = "object", properties = { name = { type = "string" }, country = { type = "string" } }, required = ["name", "country"] }
+///
+/// [[steps.branches]]
+/// when = { field = "identity.country", op = "in", value = ["US", "CA"] }
+/// goto = "ssn"
+///
+/// [[steps]]
+/// id = "passport"
+/// prompt = "Please provide your passport number."
+/// output_schema = { type = "object", properties = { passport = { type = "string" } }, required = ["passport"] }
+/// next = "end"
+///
+/// [[steps]]
+/// id = "ssn"
+/// prompt = "Please provide the last 4 digits of your SSN."
+/// output_schema = { type = "object", properties = { ssn4 = { type = "string" } }, required = ["ssn4"] }
+///
+/// [[steps]]
+/// id = "review"
+/// prompt = "Your application is under review, we will get back to you."
+/// wait = { type = "webhook", event = "kyc_reviewed", timeout_ms = 259200000 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Workflow {
+    /// The unique name of the workflow.
+    pub name: String,
+
+    /// The description of the workflow, given to the model as context.
+    #[serde(default)]
+    pub description: String,
+
+    /// The ordered steps of the workflow.
+    pub steps: Vec<WorkflowStep>,
+
+    /// The message to the user when the workflow is completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion: Option<String>,
+}
+
+/// A step of a workflow.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct WorkflowStep {
+    /// The unique ID of the step in the workflow, other than "end".
+    pub id: String,
+
+    /// The prompt presented to the user for the step.
+    pub prompt: String,
+
+    /// The JSON schema of the structured output required to complete the step.
+    /// A step without schema is informational: its prompt is presented and the workflow
+    /// moves on immediately.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<Value>,
+
+    /// The branches evaluated in order when the step is completed; the first match wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<WorkflowBranch>,
+
+    /// The next step if no branch matches, the following step by default.
+    /// "end" ends the workflow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+
+    /// What the step waits for once its prompt is presented. The workflow is suspended,
+    /// with its progress persisted in the thread, until the wait ends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait: Option<WorkflowWait>,
+}
+
+/// What a workflow step waits for before the workflow moves on.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WorkflowWait {
+    /// Waits for the user's reply, which is the step output `{"text": reply}` if the step
+    /// has no output schema. Moves on without output after `timeout_ms`, if set.
+    Reply {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+
+    /// Waits for a webhook event, whose payload is the step output. Moves on without output
+    /// after `timeout_ms`, if set.
+    Webhook {
+        event: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+
+    /// Waits `after_ms` after the step is reached, or until the Unix timestamp in
+    /// milliseconds at the dotted path `field` of the outputs, e.g. "booking.follow_up_at".
+    Until {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        after_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        field: Option<String>,
+    },
+}
+
+/// A conditional jump to a step.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct WorkflowBranch {
+    pub when: Condition,
+    pub goto: String,
+}
+
+/// A condition on the outputs collected by a workflow.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Condition {
+    /// The dotted path of the field in the outputs, starting with the step ID,
+    /// e.g. "identity.country".
+    pub field: String,
+
+    pub op: ConditionOp,
+
+    /// The value to compare with, an array for [`ConditionOp::In`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+/// The comparison operator of a [`Condition`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    In,
+    Exists,
+    Missing,
+}
+
+impl Condition {
+    /// Evaluates the condition on the outputs by step ID.
+    pub fn eval(&self, outputs: &BTreeMap<String, Value>) -> bool {
+        let field = output_field(outputs, &self.field);
+
+        match (self.op, field, &self.value) {
+            (ConditionOp::Exists, f, _) => f.is_some(),
+            (ConditionOp::Missing, f, _) => f.is_none(),
+            (ConditionOp::Eq, Some(f), Some(v)) => loosely_eq(f, v),
+            (ConditionOp::Ne, f, Some(v)) => f.is_none_or(|f| !loosely_eq(f, v)),
+            (ConditionOp::In, Some(f), Some(Value::Array(vs))) => {
+                vs.iter().any(|v| loosely_eq(f, v))
+            }
+            (op, Some(f), Some(v)) => match (as_f64(f), as_f64(v)) {
+                (Some(f), Some(v)) => match op {
+                    ConditionOp::Gt => f > v,
+                    ConditionOp::Gte => f >= v,
+                    ConditionOp::Lt => f < v,
+                    ConditionOp::Lte => f <= v,
+                    _ => false,
+                },
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Returns the non-null value at the dotted path of the outputs, starting with the step ID.
+fn output_field<'a>(outputs: &'a BTreeMap<String, Value>, path: &str) -> Option<&'a Value> {
+    let mut parts = path.split('.');
+    let mut field = parts.next().and_then(|step| outputs.get(step));
+    for part in parts {
+        field = field.and_then(|v| match v {
+            Value::Array(arr) => part.parse::<usize>().ok().and_then(|i| arr.get(i)),
+            v => v.get(part),
+        });
+    }
+    field.filter(|v| !v.is_null())
+}
+
+fn as_f64(v: &Value) -> Option<f64> {
+    match v {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Compares numbers by value and strings case-insensitively, as models are not consistent
+/// with either.
+fn loosely_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.trim().eq_ignore_ascii_case(b.trim()),
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => a == b,
+        },
+    }
+}
+
+impl Workflow {
+    /// Validates the workflow: it has steps, step IDs are unique and all targets exist.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        if self.name.is_empty() {
+            return Err("workflow name should not be empty".into());
+        }
+        if self.steps.is_empty() {
+            return Err(format!("workflow {} has no steps", self.name).into());
+        }
+
+        let mut ids = BTreeSet::new();
+        for step in &self.steps {
+            if step.id.is_empty() || step.id == WORKFLOW_END || !ids.insert(step.id.as_str()) {
+                return Err(format!(
+                    "workflow {} has an invalid or duplicate step ID {:?}",
+                    self.name, step.id
+                )
+                .into());
+            }
+            if let Some(schema) = &step.output_schema
+                && !schema.is_object()
+            {
+                return Err(format!(
+                    "step {} of workflow {} has an invalid output schema",
+                    step.id, self.name
+                )
+                .into());
+            }
+            let valid_wait = match &step.wait {
+                Some(WorkflowWait::Webhook { event, .. }) => !event.is_empty(),
+                Some(WorkflowWait::Until { after_ms, field }) => {
+                    after_ms.is_some() != field.is_some()
+                }
+                _ => true,
+            };
+            if !valid_wait {
+                return Err(format!(
+                    "step {} of workflow {} has an invalid wait",
+                    step.id, self.name
+                )
+                .into());
+            }
+        }
+        for step in &self.steps {
+            let targets = step
+                .branches
+                .iter()
+                .map(|b| b.goto.as_str())
+                .chain(step.next.as_deref());
+            for target in targets {
+                if target != WORKFLOW_END && !ids.contains(target) {
+                    return Err(format!(
+                        "step {} of workflow {} targets unknown step {}",
+                        step.id, self.name, target
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the step by ID.
+    pub fn step(&self, id: &str) -> Option<&WorkflowStep> {
+        self.steps.iter().find(|s| s.id == id)
+    }
+
+    /// Returns the ID of the step after the given one, or None if the workflow ends.
+    pub fn next_step(&self, id: &str, outputs: &BTreeMap<String, Value>) -> Option<String> {
+        let idx = self.steps.iter().position(|s| s.id == id)?;
+        let step = &self.steps[idx];
+        let next = step
+            .branches
+            .iter()
+            .find(|b| b.when.eval(outputs))
+            .map(|b| b.goto.clone())
+            .or_else(|| step.next.clone())
+            .or_else(|| self.steps.get(idx + 1).map(|s| s.id.clone()))?;
+        if next == WORKFLOW_END {
+            None
+        } else {
+            Some(next)
+        }
+    }
+}
+
+impl WorkflowStep {
+    /// Checks that the output is an object with the fields required by the output schema.
+    pub fn check_output(&self, output: &Value) -> Result<(), String> {
+        let schema = match &self.output_schema {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+        let obj = output
+            .as_object()
+            .ok_or_else(|| "the output should be a JSON object".to_string())?;
+        let missing: Vec<&str> = schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .map(|r| {
+                r.iter()
+                    .filter_map(|f| f.as_str())
+                    .filter(|f| obj.get(*f).is_none_or(|v| v.is_null() || v == ""))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("missing required fields: {}", missing.join(", ")))
+        }
+    }
+}
+
+/// The progress of a workflow in a thread, persisted in the thread metadata.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct WorkflowProgress {
+    /// The name of the workflow.
+    pub workflow: String,
+
+    /// The current step, None if the workflow is completed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+
+    /// The structured outputs of the completed steps, by step ID.
+    pub outputs: BTreeMap<String, Value>,
+
+    /// The completed steps, in order.
+    pub history: Vec<String>,
+
+    /// The wait of the current step, if the workflow is suspended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub waiting: Option<WorkflowWaiting>,
+
+    pub started_at: u64,
+    pub updated_at: u64,
+}
+
+/// The wait of a suspended workflow, see [`WorkflowWait`].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct WorkflowWaiting {
+    /// The step that waits.
+    pub step: String,
+
+    /// The webhook event that resumes the workflow, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+
+    /// The Unix timestamp in milliseconds when the wait ends, if limited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+
+    /// The Unix timestamp in milliseconds when the wait started.
+    pub since: u64,
+}
+
+impl WorkflowProgress {
+    /// Creates the progress of a workflow starting at its first step.
+    pub fn new(workflow: &Workflow, now_ms: u64) -> Self {
+        Self {
+            workflow: workflow.name.clone(),
+            step: workflow.steps.first().map(|s| s.id.clone()),
+            outputs: BTreeMap::new(),
+            history: Vec::new(),
+            waiting: None,
+            started_at: now_ms,
+            updated_at: now_ms,
+        }
+    }
+
+    /// Returns true if the workflow is completed.
+    pub fn is_completed(&self) -> bool {
+        self.step.is_none()
+    }
+
+    /// Suspends the workflow if the current step waits. Returns true if it is suspended.
+    pub fn suspend(&mut self, workflow: &Workflow, now_ms: u64) -> bool {
+        let Some(step) = self.step.as_deref().and_then(|id| workflow.step(id)) else {
+            return false;
+        };
+        let Some(wait) = &step.wait else {
+            return false;
+        };
+        let (event, until) = match wait {
+            WorkflowWait::Reply { timeout_ms } => (None, timeout_ms.map(|t| now_ms + t)),
+            WorkflowWait::Webhook { event, timeout_ms } => {
+                (Some(event.clone()), timeout_ms.map(|t| now_ms + t))
+            }
+            WorkflowWait::Until { after_ms, field } => {
+                let until = match (after_ms, field) {
+                    (Some(after_ms), _) => now_ms + after_ms,
+                    // a missing or invalid timestamp ends the wait at once
+                    (None, Some(field)) => output_field(&self.outputs, field)
+                        .and_then(as_f64)
+                        .map(|t| t as u64)
+                        .unwrap_or(now_ms),
+                    (None, None) => now_ms,
+                };
+                (None, Some(until))
+            }
+        };
+        self.waiting = Some(WorkflowWaiting {
+            step: step.id.clone(),
+            event,
+            until,
+            since: now_ms,
+        });
+        self.updated_at = now_ms;
+        true
+    }
+
+    /// Returns true if the workflow is suspended and its wait ends at `now_ms`.
+    pub fn is_due(&self, now_ms: u64) -> bool {
+        self.waiting
+            .as_ref()
+            .and_then(|w| w.until)
+            .is_some_and(|until| until <= now_ms)
+    }
+
+    /// Completes the current step with its output and moves to the next step, ending the
+    /// wait if the workflow is suspended. Returns the new current step, None if the workflow
+    /// is completed.
+    pub fn complete_step(
+        &mut self,
+        workflow: &Workflow,
+        output: Option<Value>,
+        now_ms: u64,
+    ) -> Option<String> {
+        self.waiting = None;
+        if let Some(step) = self.step.take() {
+            if let Some(output) = output {
+                self.outputs.insert(step.clone(), output);
+            }
+            self.step = workflow.next_step(&step, &self.outputs);
+            self.history.push(step);
+        }
+        self.updated_at = now_ms;
+        self.step.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_workflow() {
+        let wf: Workflow = serde_json::from_value(json!({
+            "name": "kyc_intake",
+            "steps": [
+                {"id": "welcome", "prompt": "Welcome!"},
+                {
+                    "id": "identity",
+                    "prompt": "Name and country?",
+                    "output_schema": {"type": "object", "required": ["name", "country"]},
+                    "branches": [{
+                        "when": {"field": "identity.country", "op": "in", "value": ["US", "CA"]},
+                        "goto": "ssn"
+                    }]
+                },
+                {"id": "passport", "prompt": "Passport?", "next": "end"},
+                {"id": "ssn", "prompt": "SSN?"}
+            ]
+        }))
+        .unwrap();
+        wf.validate().unwrap();
+
+        let step = wf.step("identity").unwrap();
+        assert!(step.check_output(&json!({"name": "Alice"})).is_err());
+        assert!(
+            step.check_output(&json!({"name": "Alice", "country": ""}))
+                .is_err()
+        );
+        step.check_output(&json!({"name": "Alice", "country": "us"}))
+            .unwrap();
+
+        let mut progress = WorkflowProgress::new(&wf, 1);
+        assert_eq!(
+            progress.complete_step(&wf, None, 2),
+            Some("identity".to_string())
+        );
+        assert_eq!(
+            progress.complete_step(&wf, Some(json!({"name": "Alice", "country": "us"})), 3),
+            Some("ssn".to_string())
+        );
+        assert_eq!(progress.complete_step(&wf, None, 4), None);
+        assert!(progress.is_completed());
+        assert_eq!(progress.history, vec!["welcome", "identity", "ssn"]);
+
+        let mut progress = WorkflowProgress::new(&wf, 1);
+        progress.complete_step(&wf, None, 2);
+        progress.complete_step(&wf, Some(json!({"name": "Bob", "country": "FR"})), 3);
+        assert_eq!(progress.step, Some("passport".to_string()));
+        assert_eq!(progress.complete_step(&wf, None, 4), None);
+
+        let cond = Condition {
+            field: "identity.age".to_string(),
+            op: ConditionOp::Gte,
+            value: Some(json!(18)),
+        };
+        let outputs = BTreeMap::from([("identity".to_string(), json!({"age": "21"}))]);
+        assert!(cond.eval(&outputs));
+
+        let mut invalid = wf.clone();
+        invalid.steps[2].next = Some("unknown".to_string());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_workflow_wait() {
+        let wf: Workflow = serde_json::from_value(json!({
+            "name": "follow_up",
+            "steps": [
+                {
+                    "id": "booking",
+                    "prompt": "When is your appointment?",
+                    "output_schema": {"type": "object", "required": ["at"]}
+                },
+                {"id": "remind", "prompt": "See you soon!", "wait": {"type": "until", "field": "booking.at"}},
+                {"id": "approval", "prompt": "Waiting for approval.", "wait": {"type": "webhook", "event": "approved", "timeout_ms": 1000}},
+                {"id": "feedback", "prompt": "How was it?", "wait": {"type": "reply"}}
+            ]
+        }))
+        .unwrap();
+        wf.validate().unwrap();
+
+        let mut progress = WorkflowProgress::new(&wf, 1);
+        assert!(!progress.suspend(&wf, 1));
+        progress.complete_step(&wf, Some(json!({"at": 5000})), 2);
+        assert!(progress.suspend(&wf, 2));
+        let waiting = progress.waiting.clone().unwrap();
+        assert_eq!(waiting.step, "remind");
+        assert_eq!(waiting.until, Some(5000));
+        assert!(!progress.is_due(4999));
+        assert!(progress.is_due(5000));
+
+        progress.complete_step(&wf, None, 5000);
+        assert!(progress.waiting.is_none());
+        assert!(progress.suspend(&wf, 5000));
+        let waiting = progress.waiting.clone().unwrap();
+        assert_eq!(waiting.event.as_deref(), Some("approved"));
+        assert_eq!(waiting.until, Some(6000));
+
+        progress.complete_step(&wf, Some(json!({"approved": true})), 5500);
+        assert!(progress.suspend(&wf, 5500));
+        assert_eq!(progress.waiting.as_ref().unwrap().until, None);
+        assert!(!progress.is_due(u64::MAX));
+
+        let mut invalid = wf.clone();
+        invalid.steps[1].wait = Some(WorkflowWait::Until {
+            after_ms: None,
+            field: None,
+        });
+        assert!(invalid.validate().is_err());
+        invalid.steps[1].wait = Some(WorkflowWait::Webhook {
+            event: String::new(),
+            timeout_ms: None,
+        });
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -32,7 +32,7 @@ use std::{collections::BTreeMap, future::Future, marker::PhantomData, sync::Arc}
 
 use crate::{
     BoxError, BoxPinFut, Function, Resource, ToolOutput, Value, context::BaseContext,
-    model::FunctionDefinition, select_resources, validate_function_name,
+    model::FunctionDefinition, select_resources, validate_function_name, validate_json,
 };
 
 /// Core trait for implementing tools that can be used by the AI Agent system.
@@ -68,6 +68,14 @@ where
     /// - `FunctionDefinition`: The schema definition of the tool's parameters and metadata.
     fn definition(&self) -> FunctionDefinition;
 
+    /// Returns the JSON schema of the tool's output, if declared, e.g.
+    /// `Some(gen_schema_for::<Self::Output>())`. The output of each call is validated
+    /// against it, and it is appended to the description of the definition sent to models.
+    /// By default, it returns `None`.
+    fn output_schema(&self) -> Option<Value> {
+        None
+    }
+
     /// It is used to select resources based on the provided tags.
     /// If the tool requires specific resources, it can filter them based on the tags.
     /// By default, it returns an empty list.
@@ -116,6 +124,10 @@ where
                 .await
                 .map_err(|err| format!("tool {}, call failed: {}", self.name(), err))?;
             let output = serde_json::to_value(&result.output)?;
+            if let Some(schema) = self.output_schema() {
+                validate_json(&schema, &output)
+                    .map_err(|err| format!("tool {}, invalid output: {}", self.name(), err))?;
+            }
             if result.usage.requests == 0 {
                 result.usage.requests = 1;
             }
@@ -141,6 +153,8 @@ where
 
     fn definition(&self) -> FunctionDefinition;
 
+    fn output_schema(&self) -> Option<Value>;
+
     fn supported_resource_tags(&self) -> Vec<String>;
 
     fn init(&self, ctx: C) -> BoxPinFut<Result<(), BoxError>>;
@@ -169,7 +183,14 @@ where
     }
 
     fn definition(&self) -> FunctionDefinition {
-        self.0.definition()
+        match self.0.output_schema() {
+            Some(schema) => self.0.definition().with_output_schema(&schema),
+            None => self.0.definition(),
+        }
+    }
+
+    fn output_schema(&self) -> Option<Value> {
+        self.0.output_schema()
     }
 
     fn supported_resource_tags(&self) -> Vec<String> {
@@ -261,6 +282,7 @@ where
                         Some(Function {
                             definition: tool.definition(),
                             supported_resource_tags: tool.supported_resource_tags(),
+                            output_schema: tool.output_schema(),
                         })
                     } else {
                         None
@@ -269,6 +291,7 @@ where
                 None => Some(Function {
                     definition: tool.definition(),
                     supported_resource_tags: tool.supported_resource_tags(),
+                    output_schema: tool.output_schema(),
                 }),
             })
             .collect()
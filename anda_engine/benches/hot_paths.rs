@@ -0,0 +1,199 @@
+//! Benchmarks of the hot paths of a run: prompt packing, serialization of large histories
+//! and tool dispatch. Vector scoring is in `similarity.rs`.
+//!
+//! Save a baseline before a change and compare against it after, e.g. `make bench-baseline`
+//! on the release branch and `make bench-compare` on the candidate.
+
+use anda_core::{
+    Agent, BoxError, CompletionRequest, Document, Documents, FunctionDefinition, Message, Resource,
+    ThreadMessage, Tool, ToolInput, ToolOutput, TruncationStrategy, Versioned, Xid, truncate_text,
+};
+use anda_engine::{
+    context::AgentCtx,
+    context::BaseCtx,
+    engine::{EngineBuilder, ManagementBuilder, Visibility},
+    extension::segmenter::DocumentSegmenter,
+};
+use candid::Principal;
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use ic_cose_types::to_cbor_bytes;
+use serde_json::{Value, json};
+
+fn text(n: usize) -> String {
+    "Anda is an AI agent framework built with Rust. "
+        .repeat(n / 47 + 1)
+        .chars()
+        .take(n)
+        .collect()
+}
+
+fn history(n: usize) -> Vec<Message> {
+    (0..n)
+        .map(|i| Message {
+            role: if i % 2 == 0 { "user" } else { "assistant" }.to_string(),
+            content: text(500).into(),
+            name: Some(format!("user_{}", i % 7)),
+            tool_call_id: None,
+        })
+        .collect()
+}
+
+fn bench_prompt_packing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("prompt_packing");
+    let docs: Documents = (0..32)
+        .map(|i| Document {
+            id: i.to_string(),
+            text: text(2000),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>()
+        .into();
+    let req = CompletionRequest {
+        prompt: text(1000),
+        documents: docs,
+        ..Default::default()
+    };
+    group.bench_function("prompt_with_context_32_docs", |bench| {
+        bench.iter(|| black_box(&req).prompt_with_context())
+    });
+
+    let chat_history: Vec<Value> = history(256)
+        .into_iter()
+        .map(|m| serde_json::to_value(m).unwrap())
+        .collect();
+    let req = CompletionRequest {
+        chat_history,
+        ..req
+    };
+    group.bench_function("drop_context_until_empty", |bench| {
+        bench.iter_batched(
+            || req.clone(),
+            |mut req| while req.drop_context().is_some() {},
+            criterion::BatchSize::LargeInput,
+        )
+    });
+
+    for size in [64 * 1024, 1024 * 1024] {
+        let input = text(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        for strategy in [TruncationStrategy::Head, TruncationStrategy::Tail] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("truncate_text_{:?}", strategy), size),
+                &size,
+                |bench, _| bench.iter(|| truncate_text(black_box(&input), size / 4, strategy)),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_history_serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("history_serialization");
+    for n in [100, 1000] {
+        let messages: Vec<ThreadMessage> = history(n)
+            .into_iter()
+            .map(|m| ThreadMessage {
+                id: Xid::new(),
+                role: m.role,
+                content: m.content,
+                name: m.name,
+            })
+            .collect();
+        let cbor = to_cbor_bytes(&Versioned(messages.clone()));
+        let json = serde_json::to_vec(&messages).unwrap();
+
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::new("cbor_encode", n), &n, |bench, _| {
+            bench.iter(|| to_cbor_bytes(&Versioned(black_box(&messages).clone())))
+        });
+        group.bench_with_input(BenchmarkId::new("cbor_decode", n), &n, |bench, _| {
+            bench.iter(|| Versioned::<Vec<ThreadMessage>>::decode(black_box(&cbor)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("json_encode", n), &n, |bench, _| {
+            bench.iter(|| serde_json::to_vec(black_box(&messages)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("json_decode", n), &n, |bench, _| {
+            bench.iter(|| serde_json::from_slice::<Vec<ThreadMessage>>(black_box(&json)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// A tool that returns its arguments, to measure the overhead of the engine.
+struct EchoTool;
+
+impl Tool<BaseCtx> for EchoTool {
+    type Args = Value;
+    type Output = Value;
+
+    fn name(&self) -> String {
+        "echo".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Returns its arguments.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: json!({"type": "object"}),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        _ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        Ok(ToolOutput::new(args))
+    }
+}
+
+fn bench_tool_dispatch(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let agent = DocumentSegmenter::new(100, 1000);
+    let name = Agent::<AgentCtx>::name(&agent);
+    let engine = rt
+        .block_on(
+            EngineBuilder::new()
+                .with_management(ManagementBuilder::new(
+                    Visibility::Public,
+                    Principal::anonymous(),
+                ))
+                .register_agent(agent)
+                .unwrap()
+                .register_tool(EchoTool)
+                .unwrap()
+                .export_tools(vec!["echo".to_string()])
+                .build(name),
+        )
+        .unwrap();
+    let args = json!({"query": text(200)});
+
+    let mut group = c.benchmark_group("tool_dispatch");
+    group.bench_function("engine_tool_call", |bench| {
+        bench.iter(|| {
+            rt.block_on(engine.tool_call(
+                Principal::anonymous(),
+                ToolInput::new("echo".to_string(), args.clone()),
+            ))
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_prompt_packing,
+    bench_history_serialization,
+    bench_tool_dispatch
+);
+criterion_main!(benches);
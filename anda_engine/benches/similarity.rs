@@ -0,0 +1,63 @@
+use anda_engine::store::similarity::{cosine_similarity, dot_product, scalar};
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+
+fn rand_vec(n: usize) -> Vec<f32> {
+    (0..n)
+        .map(|_| anda_engine::rand_number(-1.0f32..1.0f32))
+        .collect()
+}
+
+fn bench_similarity(c: &mut Criterion) {
+    let mut group = c.benchmark_group("similarity");
+    for ndims in [384, 1024, 1536, 3072] {
+        let a = rand_vec(ndims);
+        let b = rand_vec(ndims);
+        group.throughput(Throughput::Elements(ndims as u64));
+        group.bench_with_input(
+            BenchmarkId::new("cosine_simd", ndims),
+            &ndims,
+            |bench, _| bench.iter(|| cosine_similarity(black_box(&a), black_box(&b))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("cosine_scalar", ndims),
+            &ndims,
+            |bench, _| bench.iter(|| scalar::cosine_similarity(black_box(&a), black_box(&b))),
+        );
+        group.bench_with_input(BenchmarkId::new("dot_simd", ndims), &ndims, |bench, _| {
+            bench.iter(|| dot_product(black_box(&a), black_box(&b)))
+        });
+        group.bench_with_input(BenchmarkId::new("dot_scalar", ndims), &ndims, |bench, _| {
+            bench.iter(|| scalar::dot_product(black_box(&a), black_box(&b)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_top_n(c: &mut Criterion) {
+    // scoring a large candidate set, as in in-memory retrieval
+    let ndims = 1024;
+    let query = rand_vec(ndims);
+    let candidates: Vec<Vec<f32>> = (0..10_000).map(|_| rand_vec(ndims)).collect();
+    let mut group = c.benchmark_group("score_10k_candidates");
+    group.throughput(Throughput::Elements(candidates.len() as u64));
+    group.bench_function("simd", |bench| {
+        bench.iter(|| {
+            candidates
+                .iter()
+                .map(|v| cosine_similarity(&query, v))
+                .fold(f32::MIN, f32::max)
+        })
+    });
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| {
+            candidates
+                .iter()
+                .map(|v| scalar::cosine_similarity(&query, v))
+                .fold(f32::MIN, f32::max)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_similarity, bench_top_n);
+criterion_main!(benches);
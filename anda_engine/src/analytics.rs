@@ -0,0 +1,869 @@
+//! Export of agent runs to analytics warehouses.
+//!
+//! The engine records a [`RunRecord`] for each agent run, and a [`FeedbackRecord`] for each
+//! feedback submitted on a thread. A [`RunExporter`] buffers the records and inserts them in
+//! batches into a warehouse on a schedule, for SQL-based product analytics over agent behavior:
+//! - [`ClickHouseSink`] inserts rows with the HTTP interface, in the `JSONEachRow` format;
+//! - [`BigQuerySink`] inserts rows with the streaming `insertAll` API.
+//!
+//! Records hold usage, latencies and outcomes, not prompts nor completions. Deployments with
+//! strict privacy requirements can export only k-anonymized aggregates, see [`AggregationPolicy`].
+//!
+//! ```rust,ignore
+//! let sink = ClickHouseSink::new("https://clickhouse.example.com:8443", "anda")
+//!     .with_credentials("exporter", &password);
+//! let exporter = RunExporter::new(Arc::new(sink), Duration::from_secs(60));
+//! let engine = EngineBuilder::new()
+//!     .with_agent_version("assistant", "2025-06-01")
+//!     .with_run_exporter(exporter)
+//!     .build(agent)
+//!     .await?;
+//! ```
+//!
+//! The tables are expected to exist, with the columns of the records, e.g. in ClickHouse:
+//!
+//! ```sql
+//! CREATE TABLE anda.runs (
+//!     engine String, agent String, agent_version String, thread String, caller String,
+//!     started_at UInt64, latency_ms UInt64, input_tokens UInt64, output_tokens UInt64,
+//!     requests UInt64, tool_calls UInt32, failed_reason Nullable(String)
+//! ) ENGINE = MergeTree ORDER BY (agent, started_at);
+//!
+//! CREATE TABLE anda.feedback (
+//!     engine String, thread String, caller String, score Int8, comment Nullable(String),
+//!     created_at UInt64
+//! ) ENGINE = MergeTree ORDER BY created_at;
+//! ```
+
+use anda_core::{BoxError, BoxPinFut};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+use structured_logger::unix_ms;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{APP_USER_AGENT, rand_number};
+
+/// A record of an agent run.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RunRecord {
+    /// The principal ID of the engine.
+    pub engine: String,
+    pub agent: String,
+    /// The version of the agent, set with `EngineBuilder::with_agent_version`.
+    pub agent_version: String,
+    pub thread: String,
+    pub caller: String,
+    /// When the run started, unix timestamp in milliseconds.
+    pub started_at: u64,
+    pub latency_ms: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub requests: u64,
+    pub tool_calls: u32,
+    pub failed_reason: Option<String>,
+}
+
+/// A feedback on the answers of an agent in a thread.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FeedbackRecord {
+    /// The principal ID of the engine.
+    pub engine: String,
+    pub thread: String,
+    pub caller: String,
+    /// The score of the feedback, e.g. 1 for thumbs up and -1 for thumbs down.
+    pub score: i8,
+    pub comment: Option<String>,
+    /// Unix timestamp in milliseconds.
+    pub created_at: u64,
+}
+
+/// A warehouse that stores analytics rows.
+pub trait AnalyticsSink: Send + Sync + 'static {
+    /// Inserts JSON rows into the table.
+    fn insert(&self, table: &str, rows: Vec<Value>) -> BoxPinFut<Result<(), BoxError>>;
+}
+
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .use_rustls_tls()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(60))
+        .gzip(true)
+        .user_agent(APP_USER_AGENT)
+        .build()
+        .expect("analytics reqwest client should build")
+}
+
+async fn check_response(warehouse: &str, res: reqwest::Response) -> Result<String, BoxError> {
+    let status = res.status();
+    let body = res.text().await?;
+    if !status.is_success() {
+        return Err(format!(
+            "{} insert failed, status: {}, body: {}",
+            warehouse, status, body
+        )
+        .into());
+    }
+    Ok(body)
+}
+
+/// Inserts rows into ClickHouse with its HTTP interface.
+#[derive(Clone)]
+pub struct ClickHouseSink {
+    http: reqwest::Client,
+    endpoint: String,
+    database: String,
+    credentials: Option<(String, String)>,
+}
+
+impl ClickHouseSink {
+    /// Creates a sink of the database, e.g. `ClickHouseSink::new("http://localhost:8123", "anda")`.
+    pub fn new(endpoint: &str, database: &str) -> Self {
+        Self {
+            http: http_client(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            database: database.to_string(),
+            credentials: None,
+        }
+    }
+
+    /// Sets the user and password.
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Some((user.to_string(), password.to_string()));
+        self
+    }
+
+    fn query(&self, table: &str) -> String {
+        format!(
+            "INSERT INTO `{}`.`{}` FORMAT JSONEachRow",
+            self.database.replace('`', ""),
+            table.replace('`', "")
+        )
+    }
+}
+
+impl AnalyticsSink for ClickHouseSink {
+    fn insert(&self, table: &str, rows: Vec<Value>) -> BoxPinFut<Result<(), BoxError>> {
+        let mut body = String::new();
+        for row in &rows {
+            body.push_str(&row.to_string());
+            body.push('\n');
+        }
+        let mut req = self
+            .http
+            .post(&self.endpoint)
+            .query(&[("query", self.query(table))])
+            .body(body);
+        if let Some((user, password)) = &self.credentials {
+            req = req
+                .header("X-ClickHouse-User", user)
+                .header("X-ClickHouse-Key", password);
+        }
+        Box::pin(async move {
+            check_response("ClickHouse", req.send().await?).await?;
+            Ok(())
+        })
+    }
+}
+
+/// Inserts rows into BigQuery with the streaming `insertAll` API.
+///
+/// The API is authorized by an OAuth 2.0 access token, which expires: the owner of the sink
+/// should refresh it with [`BigQuerySink::set_access_token`], e.g. from the metadata server
+/// of the VM or a service account.
+#[derive(Clone)]
+pub struct BigQuerySink {
+    http: reqwest::Client,
+    endpoint: String,
+    project: String,
+    dataset: String,
+    access_token: Arc<RwLock<String>>,
+}
+
+impl BigQuerySink {
+    /// Creates a sink of the dataset.
+    pub fn new(project: &str, dataset: &str, access_token: &str) -> Self {
+        Self {
+            http: http_client(),
+            endpoint: "https://bigquery.googleapis.com/bigquery/v2".to_string(),
+            project: project.to_string(),
+            dataset: dataset.to_string(),
+            access_token: Arc::new(RwLock::new(access_token.to_string())),
+        }
+    }
+
+    /// Sets the endpoint of the API, e.g. for an emulator.
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Replaces the access token.
+    pub fn set_access_token(&self, access_token: &str) {
+        *self.access_token.write().unwrap() = access_token.to_string();
+    }
+
+    fn url(&self, table: &str) -> String {
+        format!(
+            "{}/projects/{}/datasets/{}/tables/{}/insertAll",
+            self.endpoint, self.project, self.dataset, table
+        )
+    }
+}
+
+impl AnalyticsSink for BigQuerySink {
+    fn insert(&self, table: &str, rows: Vec<Value>) -> BoxPinFut<Result<(), BoxError>> {
+        let body = json!({
+            "rows": rows.into_iter().map(|row| json!({"json": row})).collect::<Vec<_>>(),
+        });
+        let req = self
+            .http
+            .post(self.url(table))
+            .bearer_auth(self.access_token.read().unwrap().as_str())
+            .json(&body);
+        Box::pin(async move {
+            let body = check_response("BigQuery", req.send().await?).await?;
+            // a 200 response can still report rows that were not inserted
+            let res: Value = serde_json::from_str(&body).unwrap_or_default();
+            match res.get("insertErrors").and_then(|v| v.as_array()) {
+                Some(errors) if !errors.is_empty() => Err(format!(
+                    "BigQuery insert failed for {} rows: {}",
+                    errors.len(),
+                    errors[0]
+                )
+                .into()),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// The aggregation-only mode of a [`RunExporter`], for deployments with strict privacy
+/// requirements. Runs and feedback are not exported one by one: they are aggregated in
+/// memory per time window, agent and agent version, and only the aggregates are exported,
+/// into the "run_aggregates" and "feedback_aggregates" tables. Callers, threads and feedback
+/// comments are never exported.
+///
+/// - Aggregates of fewer than `min_callers` distinct callers are suppressed (k-anonymity);
+/// - If `epsilon` is set, Laplace noise of scale `1 / epsilon` is added to the counts of
+///   runs, failures and feedback, for epsilon-differential privacy of the presence of a
+///   single record in the counts. Token and latency figures are not noised.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AggregationPolicy {
+    /// The length of the aggregation windows in milliseconds.
+    pub window_ms: u64,
+    /// The minimum number of distinct callers of an exported aggregate, the "k".
+    pub min_callers: usize,
+    /// The privacy budget of the noise of counts, no noise if `None`.
+    #[serde(default)]
+    pub epsilon: Option<f64>,
+}
+
+impl Default for AggregationPolicy {
+    fn default() -> Self {
+        Self {
+            window_ms: 3600 * 1000,
+            min_callers: 5,
+            epsilon: None,
+        }
+    }
+}
+
+/// Aggregated statistics of the runs of an agent in a time window.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct RunAggregate {
+    pub engine: String,
+    pub agent: String,
+    pub agent_version: String,
+    /// The start of the window, unix timestamp in milliseconds.
+    pub window_start: u64,
+    pub window_ms: u64,
+    pub runs: u64,
+    pub failures: u64,
+    /// The number of distinct callers, at least the `min_callers` of the policy.
+    pub callers: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub requests: u64,
+    pub tool_calls: u64,
+    pub avg_latency_ms: u64,
+    pub p95_latency_ms: u64,
+}
+
+/// Aggregated feedback of the threads of an engine in a time window.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct FeedbackAggregate {
+    pub engine: String,
+    /// The start of the window, unix timestamp in milliseconds.
+    pub window_start: u64,
+    pub window_ms: u64,
+    pub count: u64,
+    pub positive: u64,
+    pub negative: u64,
+    pub avg_score: f64,
+    /// The number of distinct callers, at least the `min_callers` of the policy.
+    pub callers: u64,
+}
+
+#[derive(Default)]
+struct RunBucket {
+    callers: HashSet<String>,
+    runs: u64,
+    failures: u64,
+    input_tokens: u64,
+    output_tokens: u64,
+    requests: u64,
+    tool_calls: u64,
+    latencies: Vec<u64>,
+}
+
+#[derive(Default)]
+struct FeedbackBucket {
+    callers: HashSet<String>,
+    count: u64,
+    positive: u64,
+    negative: u64,
+    score_sum: i64,
+}
+
+#[derive(Default)]
+struct Aggregates {
+    // keyed by (window start, engine, agent, agent version)
+    runs: BTreeMap<(u64, String, String, String), RunBucket>,
+    // keyed by (window start, engine)
+    feedback: BTreeMap<(u64, String), FeedbackBucket>,
+}
+
+/// Samples the Laplace distribution of the scale.
+fn laplace_noise(scale: f64) -> f64 {
+    let u: f64 = rand_number(-0.5..0.5);
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+impl AggregationPolicy {
+    fn window_start(&self, ts: u64) -> u64 {
+        let window = self.window_ms.max(1);
+        ts - ts % window
+    }
+
+    fn noised(&self, count: u64) -> u64 {
+        match self.epsilon {
+            Some(epsilon) if epsilon > 0.0 => (count as f64 + laplace_noise(1.0 / epsilon))
+                .round()
+                .max(0.0) as u64,
+            _ => count,
+        }
+    }
+}
+
+/// The state of an exporter.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExporterStats {
+    /// The records waiting to be exported.
+    pub pending: u64,
+    pub exported: u64,
+    /// The records dropped because the buffer was full.
+    pub dropped: u64,
+    /// The aggregates suppressed because they had too few distinct callers.
+    #[serde(default)]
+    pub suppressed: u64,
+    pub failures: u64,
+    /// Unix timestamp in milliseconds.
+    #[serde(default)]
+    pub last_export_at: Option<u64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct Buffers {
+    runs: VecDeque<RunRecord>,
+    feedback: VecDeque<FeedbackRecord>,
+    run_aggregates: VecDeque<RunAggregate>,
+    feedback_aggregates: VecDeque<FeedbackAggregate>,
+}
+
+/// Buffers run and feedback records and exports them to an [`AnalyticsSink`] in batches.
+pub struct RunExporter {
+    sink: Arc<dyn AnalyticsSink>,
+    interval: Duration,
+    batch_size: usize,
+    max_buffer: usize,
+    runs_table: String,
+    feedback_table: String,
+    aggregation: Option<AggregationPolicy>,
+    aggregates: Mutex<Aggregates>,
+    buffers: Mutex<Buffers>,
+    stats: Mutex<ExporterStats>,
+}
+
+impl RunExporter {
+    /// Creates an exporter that exports the buffered records every `interval`,
+    /// into the "runs" and "feedback" tables.
+    pub fn new(sink: Arc<dyn AnalyticsSink>, interval: Duration) -> Self {
+        Self {
+            sink,
+            interval,
+            batch_size: 500,
+            max_buffer: 100_000,
+            runs_table: "runs".to_string(),
+            feedback_table: "feedback".to_string(),
+            aggregation: None,
+            aggregates: Mutex::new(Aggregates::default()),
+            buffers: Mutex::new(Buffers::default()),
+            stats: Mutex::new(ExporterStats::default()),
+        }
+    }
+
+    /// Sets the maximum number of rows per insert. Defaults to 500.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Sets the maximum number of buffered records of each kind, the oldest records are
+    /// dropped when the warehouse is unavailable for too long. Defaults to 100,000.
+    pub fn with_max_buffer(mut self, max_buffer: usize) -> Self {
+        self.max_buffer = max_buffer.max(1);
+        self
+    }
+
+    /// Sets the names of the tables.
+    pub fn with_tables(mut self, runs: &str, feedback: &str) -> Self {
+        self.runs_table = runs.to_string();
+        self.feedback_table = feedback.to_string();
+        self
+    }
+
+    /// Exports only aggregated statistics, see [`AggregationPolicy`].
+    pub fn with_aggregation(mut self, policy: AggregationPolicy) -> Self {
+        self.aggregation = Some(policy);
+        self
+    }
+
+    /// Buffers a run record, or adds it to its aggregate in the aggregation-only mode.
+    pub fn record_run(&self, record: RunRecord) {
+        if let Some(policy) = &self.aggregation {
+            let key = (
+                policy.window_start(record.started_at),
+                record.engine,
+                record.agent,
+                record.agent_version,
+            );
+            let mut aggregates = self.aggregates.lock().unwrap();
+            let bucket = aggregates.runs.entry(key).or_default();
+            bucket.callers.insert(record.caller);
+            bucket.runs += 1;
+            bucket.failures += record.failed_reason.is_some() as u64;
+            bucket.input_tokens += record.input_tokens;
+            bucket.output_tokens += record.output_tokens;
+            bucket.requests += record.requests;
+            bucket.tool_calls += record.tool_calls as u64;
+            bucket.latencies.push(record.latency_ms);
+            return;
+        }
+
+        let dropped = {
+            let mut buffers = self.buffers.lock().unwrap();
+            buffers.runs.push_back(record);
+            Self::trim(&mut buffers.runs, self.max_buffer)
+        };
+        self.add_dropped(dropped);
+    }
+
+    /// Buffers a feedback record, or adds it to its aggregate in the aggregation-only mode.
+    pub fn record_feedback(&self, record: FeedbackRecord) {
+        if let Some(policy) = &self.aggregation {
+            let key = (policy.window_start(record.created_at), record.engine);
+            let mut aggregates = self.aggregates.lock().unwrap();
+            let bucket = aggregates.feedback.entry(key).or_default();
+            bucket.callers.insert(record.caller);
+            bucket.count += 1;
+            bucket.positive += (record.score > 0) as u64;
+            bucket.negative += (record.score < 0) as u64;
+            bucket.score_sum += record.score as i64;
+            return;
+        }
+
+        let dropped = {
+            let mut buffers = self.buffers.lock().unwrap();
+            buffers.feedback.push_back(record);
+            Self::trim(&mut buffers.feedback, self.max_buffer)
+        };
+        self.add_dropped(dropped);
+    }
+
+    /// Returns the stats of the exporter.
+    pub fn stats(&self) -> ExporterStats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        let buffers = self.buffers.lock().unwrap();
+        stats.pending = (buffers.runs.len()
+            + buffers.feedback.len()
+            + buffers.run_aggregates.len()
+            + buffers.feedback_aggregates.len()) as u64;
+        stats
+    }
+
+    /// Closes the aggregates of the windows that ended before `now`, and buffers the ones
+    /// with enough distinct callers for export.
+    pub fn close_windows(&self, now: u64) {
+        let Some(policy) = &self.aggregation else {
+            return;
+        };
+
+        let (runs, feedback) = {
+            let mut aggregates = self.aggregates.lock().unwrap();
+            let is_open = |start: u64| start.saturating_add(policy.window_ms) > now;
+            let open_runs = match aggregates.runs.keys().find(|k| is_open(k.0)).cloned() {
+                Some(key) => aggregates.runs.split_off(&key),
+                None => BTreeMap::new(),
+            };
+            let open_feedback = match aggregates.feedback.keys().find(|k| is_open(k.0)).cloned() {
+                Some(key) => aggregates.feedback.split_off(&key),
+                None => BTreeMap::new(),
+            };
+            (
+                std::mem::replace(&mut aggregates.runs, open_runs),
+                std::mem::replace(&mut aggregates.feedback, open_feedback),
+            )
+        };
+
+        let mut suppressed = 0;
+        let mut run_aggregates = Vec::new();
+        for ((window_start, engine, agent, agent_version), mut bucket) in runs {
+            if bucket.callers.len() < policy.min_callers {
+                suppressed += 1;
+                continue;
+            }
+            bucket.latencies.sort_unstable();
+            let n = bucket.latencies.len();
+            run_aggregates.push(RunAggregate {
+                engine,
+                agent,
+                agent_version,
+                window_start,
+                window_ms: policy.window_ms,
+                runs: policy.noised(bucket.runs),
+                failures: policy.noised(bucket.failures),
+                callers: bucket.callers.len() as u64,
+                input_tokens: bucket.input_tokens,
+                output_tokens: bucket.output_tokens,
+                requests: bucket.requests,
+                tool_calls: bucket.tool_calls,
+                avg_latency_ms: bucket.latencies.iter().sum::<u64>() / n.max(1) as u64,
+                p95_latency_ms: bucket.latencies[(n * 95 / 100).min(n - 1)],
+            });
+        }
+
+        let mut feedback_aggregates = Vec::new();
+        for ((window_start, engine), bucket) in feedback {
+            if bucket.callers.len() < policy.min_callers {
+                suppressed += 1;
+                continue;
+            }
+            feedback_aggregates.push(FeedbackAggregate {
+                engine,
+                window_start,
+                window_ms: policy.window_ms,
+                count: policy.noised(bucket.count),
+                positive: policy.noised(bucket.positive),
+                negative: policy.noised(bucket.negative),
+                avg_score: bucket.score_sum as f64 / bucket.count as f64,
+                callers: bucket.callers.len() as u64,
+            });
+        }
+
+        let dropped = {
+            let mut buffers = self.buffers.lock().unwrap();
+            buffers.run_aggregates.extend(run_aggregates);
+            buffers.feedback_aggregates.extend(feedback_aggregates);
+            Self::trim(&mut buffers.run_aggregates, self.max_buffer)
+                + Self::trim(&mut buffers.feedback_aggregates, self.max_buffer)
+        };
+        self.add_dropped(dropped);
+        if suppressed > 0 {
+            self.stats.lock().unwrap().suppressed += suppressed;
+        }
+    }
+
+    /// Exports the buffered records, and the aggregates of the ended windows.
+    /// Records of a failed batch are kept for the next export.
+    /// Returns the number of exported records.
+    pub async fn flush(&self) -> Result<usize, BoxError> {
+        self.close_windows(unix_ms());
+
+        let mut exported = 0;
+        let res = async {
+            exported += self.export_queue(&self.runs_table, |b| &mut b.runs).await?;
+            exported += self
+                .export_queue(&self.feedback_table, |b| &mut b.feedback)
+                .await?;
+            exported += self
+                .export_queue("run_aggregates", |b| &mut b.run_aggregates)
+                .await?;
+            exported += self
+                .export_queue("feedback_aggregates", |b| &mut b.feedback_aggregates)
+                .await?;
+            Ok::<(), BoxError>(())
+        }
+        .await;
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.exported += exported as u64;
+        match res {
+            Ok(_) => {
+                if exported > 0 {
+                    stats.last_export_at = Some(unix_ms());
+                }
+                stats.last_error = None;
+                Ok(exported)
+            }
+            Err(err) => {
+                stats.failures += 1;
+                stats.last_error = Some(err.to_string());
+                Err(err)
+            }
+        }
+    }
+
+    /// Spawns a background task that exports the buffered records every interval,
+    /// and a last time when the token is cancelled, with the aggregates of the open windows.
+    pub fn spawn(self: Arc<Self>, token: CancellationToken) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let cancelled = tokio::select! {
+                    _ = token.cancelled() => true,
+                    _ = tokio::time::sleep(self.interval) => false,
+                };
+                if cancelled {
+                    self.close_windows(u64::MAX);
+                }
+                if let Err(err) = self.flush().await {
+                    log::error!("failed to export runs: {}", err);
+                }
+                if cancelled {
+                    return;
+                }
+            }
+        })
+    }
+
+    /// Exports the records of a buffer in batches, and returns the number of exported records.
+    async fn export_queue<T: Serialize>(
+        &self,
+        table: &str,
+        queue: fn(&mut Buffers) -> &mut VecDeque<T>,
+    ) -> Result<usize, BoxError> {
+        let mut exported = 0;
+        loop {
+            let batch: Vec<T> = {
+                let mut buffers = self.buffers.lock().unwrap();
+                let queue = queue(&mut buffers);
+                let n = queue.len().min(self.batch_size);
+                queue.drain(..n).collect()
+            };
+            if batch.is_empty() {
+                return Ok(exported);
+            }
+            if let Err(err) = self.export(table, &batch).await {
+                let mut buffers = self.buffers.lock().unwrap();
+                let queue = queue(&mut buffers);
+                for record in batch.into_iter().rev() {
+                    queue.push_front(record);
+                }
+                return Err(err);
+            }
+            exported += batch.len();
+        }
+    }
+
+    async fn export<T: Serialize>(&self, table: &str, records: &[T]) -> Result<(), BoxError> {
+        let rows = records
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.sink.insert(table, rows).await
+    }
+
+    fn trim<T>(buffer: &mut VecDeque<T>, max: usize) -> u64 {
+        let mut dropped = 0;
+        while buffer.len() > max {
+            buffer.pop_front();
+            dropped += 1;
+        }
+        dropped
+    }
+
+    fn add_dropped(&self, dropped: u64) {
+        if dropped > 0 {
+            self.stats.lock().unwrap().dropped += dropped;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Default)]
+    struct MemorySink {
+        rows: Mutex<Vec<(String, Value)>>,
+        failing: AtomicBool,
+    }
+
+    impl AnalyticsSink for Arc<MemorySink> {
+        fn insert(&self, table: &str, rows: Vec<Value>) -> BoxPinFut<Result<(), BoxError>> {
+            let sink = self.clone();
+            let table = table.to_string();
+            Box::pin(async move {
+                if sink.failing.load(Ordering::Relaxed) {
+                    return Err("unavailable".into());
+                }
+                let mut res = sink.rows.lock().unwrap();
+                res.extend(rows.into_iter().map(|row| (table.clone(), row)));
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_run_exporter() {
+        let sink = Arc::new(MemorySink::default());
+        let exporter = RunExporter::new(Arc::new(sink.clone()), Duration::from_secs(60))
+            .with_batch_size(2)
+            .with_max_buffer(3);
+        for i in 0..4 {
+            exporter.record_run(RunRecord {
+                agent: "assistant".to_string(),
+                latency_ms: i,
+                ..Default::default()
+            });
+        }
+        exporter.record_feedback(FeedbackRecord {
+            score: 1,
+            ..Default::default()
+        });
+        let stats = exporter.stats();
+        assert_eq!(stats.pending, 4);
+        assert_eq!(stats.dropped, 1);
+
+        sink.failing.store(true, Ordering::Relaxed);
+        assert!(exporter.flush().await.is_err());
+        let stats = exporter.stats();
+        assert_eq!(stats.pending, 4);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.last_error.as_deref(), Some("unavailable"));
+
+        sink.failing.store(false, Ordering::Relaxed);
+        assert_eq!(exporter.flush().await.unwrap(), 4);
+        let rows = sink.rows.lock().unwrap().clone();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].0, "runs");
+        assert_eq!(rows[0].1["latency_ms"], 1);
+        assert_eq!(rows[2].1["latency_ms"], 3);
+        assert_eq!(rows[3].0, "feedback");
+        assert_eq!(rows[3].1["score"], 1);
+
+        let stats = exporter.stats();
+        assert_eq!(stats.pending, 0);
+        assert_eq!(stats.exported, 4);
+        assert!(stats.last_error.is_none());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_run_exporter_aggregation() {
+        let sink = Arc::new(MemorySink::default());
+        let exporter = RunExporter::new(Arc::new(sink.clone()), Duration::from_secs(60))
+            .with_aggregation(AggregationPolicy {
+                window_ms: 1000,
+                min_callers: 2,
+                epsilon: None,
+            });
+        for (agent, caller, started_at, latency_ms) in [
+            ("assistant", "alice", 1000, 100),
+            ("assistant", "bob", 1500, 300),
+            ("assistant", "bob", 1900, 200),
+            ("coder", "alice", 1200, 100),
+            ("assistant", "carol", 2100, 100),
+        ] {
+            exporter.record_run(RunRecord {
+                agent: agent.to_string(),
+                caller: caller.to_string(),
+                started_at,
+                latency_ms,
+                input_tokens: 10,
+                failed_reason: (latency_ms == 300).then(|| "timeout".to_string()),
+                ..Default::default()
+            });
+        }
+        for (caller, score) in [("alice", 1), ("bob", -1), ("carol", 1)] {
+            exporter.record_feedback(FeedbackRecord {
+                caller: caller.to_string(),
+                score,
+                created_at: 1100,
+                comment: Some("private".to_string()),
+                ..Default::default()
+            });
+        }
+        assert_eq!(exporter.stats().pending, 0);
+
+        exporter.close_windows(2000);
+        let stats = exporter.stats();
+        // the "coder" aggregate has a single caller
+        assert_eq!((stats.pending, stats.suppressed), (2, 1));
+
+        assert_eq!(exporter.flush().await.unwrap(), 2);
+        let rows = sink.rows.lock().unwrap().clone();
+        assert_eq!(rows[0].0, "run_aggregates");
+        let run: RunAggregate = serde_json::from_value(rows[0].1.clone()).unwrap();
+        assert_eq!(run.window_start, 1000);
+        assert_eq!((run.runs, run.failures, run.callers), (3, 1, 2));
+        assert_eq!(run.input_tokens, 30);
+        assert_eq!((run.avg_latency_ms, run.p95_latency_ms), (200, 300));
+        assert_eq!(rows[1].0, "feedback_aggregates");
+        assert!(rows[1].1.get("comment").is_none());
+        let feedback: FeedbackAggregate = serde_json::from_value(rows[1].1.clone()).unwrap();
+        assert_eq!(
+            (feedback.count, feedback.positive, feedback.negative),
+            (3, 2, 1)
+        );
+
+        // the window of carol's run is still open
+        exporter.close_windows(u64::MAX);
+        assert_eq!(exporter.stats().suppressed, 2);
+    }
+
+    #[test]
+    fn test_aggregation_noise() {
+        let policy = AggregationPolicy {
+            epsilon: Some(1.0),
+            ..Default::default()
+        };
+        let n = 1000;
+        let avg = (0..n).map(|_| policy.noised(100)).sum::<u64>() as f64 / n as f64;
+        assert!((avg - 100.0).abs() < 1.0);
+        assert!((0..n).any(|_| policy.noised(100) != 100));
+    }
+
+    #[test]
+    fn test_clickhouse_query() {
+        let sink = ClickHouseSink::new("http://localhost:8123/", "anda");
+        assert_eq!(sink.endpoint, "http://localhost:8123");
+        assert_eq!(
+            sink.query("runs"),
+            "INSERT INTO `anda`.`runs` FORMAT JSONEachRow"
+        );
+        let sink = BigQuerySink::new("p", "d", "token");
+        assert_eq!(
+            sink.url("runs"),
+            "https://bigquery.googleapis.com/bigquery/v2/projects/p/datasets/d/tables/runs/insertAll"
+        );
+    }
+}
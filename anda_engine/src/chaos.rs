@@ -0,0 +1,421 @@
+//! Fault injection for test and staging builds, enabled by the `chaos` feature.
+//!
+//! A [`FaultInjector`] injects faults into the layers of an engine with configured
+//! probabilities, so that operators can verify that agents and retry policies degrade
+//! gracefully:
+//! - provider timeouts, with [`FaultInjector::model`];
+//! - tool errors, with the [`Hook`] of [`FaultInjector::hook`];
+//! - store latency and errors, with [`FaultInjector::object_store`].
+//!
+//! ```rust,ignore
+//! let faults = FaultInjector::new(FaultConfig {
+//!     provider_timeout: 0.1,
+//!     tool_error: 0.05,
+//!     store_latency: 0.5,
+//!     store_latency_ms: 200,
+//!     ..Default::default()
+//! });
+//! let mut hooks = Hooks::new();
+//! hooks.add(Box::new(faults.hook()));
+//! let engine = EngineBuilder::new()
+//!     .with_model(faults.model(model))
+//!     .with_store(Store::new(faults.object_store(object_store)))
+//!     .with_hooks(Arc::new(hooks))
+//!     .build(agent)
+//!     .await?;
+//! ```
+
+use anda_core::{AgentOutput, BoxError, BoxPinFut, CompletionRequest, ModelCapabilities};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore, PutMultipartOpts,
+    PutOptions, PutPayload, PutResult, path::Path,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{
+    context::BaseCtx,
+    engine::Hook,
+    management::UserStateWrapper,
+    model::{CompletionFeaturesDyn, Model, ProviderError, ProviderErrorKind},
+    rand_number,
+};
+
+/// The probabilities and parameters of the injected faults. Probabilities are in `[0, 1]`,
+/// 0 disables the fault.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct FaultConfig {
+    /// The probability that a completion times out.
+    #[serde(default)]
+    pub provider_timeout: f64,
+    /// How long a timed out completion hangs before it fails.
+    #[serde(default)]
+    pub provider_timeout_ms: u64,
+    /// The probability that a tool call fails before the tool runs.
+    #[serde(default)]
+    pub tool_error: f64,
+    /// The tools that can fail, all tools if empty.
+    #[serde(default)]
+    pub tools: BTreeSet<String>,
+    /// The probability that a store operation is delayed.
+    #[serde(default)]
+    pub store_latency: f64,
+    /// The delay of a delayed store operation.
+    #[serde(default)]
+    pub store_latency_ms: u64,
+    /// The probability that a store operation fails.
+    #[serde(default)]
+    pub store_error: f64,
+}
+
+/// The numbers of injected faults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FaultStats {
+    pub provider_timeouts: u64,
+    pub tool_errors: u64,
+    pub store_delays: u64,
+    pub store_errors: u64,
+}
+
+#[derive(Default)]
+struct FaultCounts {
+    provider_timeouts: AtomicU64,
+    tool_errors: AtomicU64,
+    store_delays: AtomicU64,
+    store_errors: AtomicU64,
+}
+
+/// Injects faults into models, tools and stores, see the [module documentation](self).
+#[derive(Clone)]
+pub struct FaultInjector {
+    config: Arc<FaultConfig>,
+    counts: Arc<FaultCounts>,
+}
+
+impl FaultInjector {
+    /// Creates an injector with the configuration.
+    pub fn new(config: FaultConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            counts: Arc::new(FaultCounts::default()),
+        }
+    }
+
+    /// Returns the numbers of injected faults.
+    pub fn stats(&self) -> FaultStats {
+        FaultStats {
+            provider_timeouts: self.counts.provider_timeouts.load(Ordering::Relaxed),
+            tool_errors: self.counts.tool_errors.load(Ordering::Relaxed),
+            store_delays: self.counts.store_delays.load(Ordering::Relaxed),
+            store_errors: self.counts.store_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Wraps the completer of the model to inject provider timeouts.
+    pub fn model(&self, model: Model) -> Model {
+        let mut faulty = Model::new(
+            Arc::new(FaultyCompleter {
+                inner: model.completer.clone(),
+                faults: self.clone(),
+            }),
+            model.embedder.clone(),
+        );
+        faulty.tokenizer = model.tokenizer;
+        faulty.context_window = model.context_window;
+        faulty.capabilities = model.capabilities;
+        faulty
+    }
+
+    /// Returns a hook that injects tool errors.
+    pub fn hook(&self) -> FaultHook {
+        FaultHook {
+            faults: self.clone(),
+        }
+    }
+
+    /// Wraps the object store to inject latency and errors.
+    pub fn object_store(&self, store: Arc<dyn ObjectStore>) -> Arc<dyn ObjectStore> {
+        Arc::new(FaultyObjectStore {
+            inner: store,
+            faults: self.clone(),
+        })
+    }
+
+    fn hit(probability: f64) -> bool {
+        probability > 0.0 && rand_number(0.0..1.0) < probability
+    }
+
+    async fn store_faults(&self, op: &str) -> object_store::Result<()> {
+        if Self::hit(self.config.store_latency) {
+            self.counts.store_delays.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(self.config.store_latency_ms)).await;
+        }
+        if Self::hit(self.config.store_error) {
+            self.counts.store_errors.fetch_add(1, Ordering::Relaxed);
+            return Err(object_store::Error::Generic {
+                store: "FaultyObjectStore",
+                source: format!("injected fault: {} failed", op).into(),
+            });
+        }
+        Ok(())
+    }
+}
+
+struct FaultyCompleter {
+    inner: Arc<dyn CompletionFeaturesDyn>,
+    faults: FaultInjector,
+}
+
+impl FaultyCompleter {
+    fn timeout(&self) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        self.faults
+            .counts
+            .provider_timeouts
+            .fetch_add(1, Ordering::Relaxed);
+        let delay = Duration::from_millis(self.faults.config.provider_timeout_ms);
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            Err(ProviderError::new(
+                ProviderErrorKind::Network,
+                "injected fault: provider request timed out".to_string(),
+            )
+            .into())
+        })
+    }
+}
+
+impl CompletionFeaturesDyn for FaultyCompleter {
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        if FaultInjector::hit(self.faults.config.provider_timeout) {
+            return self.timeout();
+        }
+        self.inner.completion(req)
+    }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        if FaultInjector::hit(self.faults.config.provider_timeout) {
+            return Box::pin(futures::stream::once(self.timeout()));
+        }
+        self.inner.completion_stream(req)
+    }
+
+    fn capabilities(&self) -> ModelCapabilities {
+        self.inner.capabilities()
+    }
+}
+
+/// A [`Hook`] that fails tool calls, created by [`FaultInjector::hook`].
+pub struct FaultHook {
+    faults: FaultInjector,
+}
+
+#[async_trait]
+impl Hook for FaultHook {
+    async fn on_tool_start(
+        &self,
+        _ctx: &BaseCtx,
+        tool: &str,
+        _state: &mut UserStateWrapper,
+    ) -> Result<(), BoxError> {
+        let config = &self.faults.config;
+        if (config.tools.is_empty() || config.tools.contains(tool))
+            && FaultInjector::hit(config.tool_error)
+        {
+            self.faults
+                .counts
+                .tool_errors
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(format!("injected fault: tool {} failed", tool).into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct FaultyObjectStore {
+    inner: Arc<dyn ObjectStore>,
+    faults: FaultInjector,
+}
+
+impl fmt::Debug for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjector")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl fmt::Display for FaultyObjectStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FaultyObjectStore({})", self.inner)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for FaultyObjectStore {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> object_store::Result<PutResult> {
+        self.faults.store_faults("put").await?;
+        self.inner.put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> object_store::Result<Box<dyn MultipartUpload>> {
+        self.faults.store_faults("put_multipart").await?;
+        self.inner.put_multipart_opts(location, opts).await
+    }
+
+    async fn get_opts(
+        &self,
+        location: &Path,
+        options: GetOptions,
+    ) -> object_store::Result<GetResult> {
+        self.faults.store_faults("get").await?;
+        self.inner.get_opts(location, options).await
+    }
+
+    async fn get_range(
+        &self,
+        location: &Path,
+        range: std::ops::Range<usize>,
+    ) -> object_store::Result<Bytes> {
+        self.faults.store_faults("get_range").await?;
+        self.inner.get_range(location, range).await
+    }
+
+    async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+        self.faults.store_faults("head").await?;
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        self.faults.store_faults("delete").await?;
+        self.inner.delete(location).await
+    }
+
+    // listing is not faulty, its stream is returned synchronously
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, object_store::Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> object_store::Result<ListResult> {
+        self.faults.store_faults("list").await?;
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.faults.store_faults("copy").await?;
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.faults.store_faults("copy").await?;
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::EngineBuilder,
+        model::{MockImplemented, ProviderError},
+    };
+    use anda_core::StoreFeatures;
+    use candid::Principal;
+    use object_store::memory::InMemory;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fault_injector() {
+        let faults = FaultInjector::new(FaultConfig {
+            provider_timeout: 1.0,
+            tool_error: 1.0,
+            tools: BTreeSet::from(["flaky".to_string()]),
+            store_error: 1.0,
+            ..Default::default()
+        });
+
+        let model = faults.model(Model::new(
+            Arc::new(MockImplemented),
+            Arc::new(MockImplemented),
+        ));
+        let err = model
+            .completer
+            .completion(CompletionRequest::default())
+            .await
+            .unwrap_err();
+        let err = err.downcast_ref::<ProviderError>().unwrap();
+        assert_eq!(err.kind, ProviderErrorKind::Network);
+
+        let ctx = EngineBuilder::new().mock_ctx();
+        let hook = faults.hook();
+        let mut state = UserStateWrapper::new(Principal::anonymous());
+        assert!(
+            hook.on_tool_start(&ctx.base, "flaky", &mut state)
+                .await
+                .is_err()
+        );
+        assert!(
+            hook.on_tool_start(&ctx.base, "stable", &mut state)
+                .await
+                .is_ok()
+        );
+
+        let store = faults.object_store(Arc::new(InMemory::new()));
+        let err = store
+            .put(&Path::from("key"), PutPayload::from_static(b"value"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("injected fault"));
+        assert!(store.list_with_delimiter(None).await.is_err());
+
+        let ctx = EngineBuilder::new()
+            .with_store(crate::store::Store::new(
+                FaultInjector::new(FaultConfig {
+                    store_latency: 1.0,
+                    store_latency_ms: 1,
+                    ..Default::default()
+                })
+                .object_store(Arc::new(InMemory::new())),
+            ))
+            .mock_ctx();
+        ctx.store_put(
+            &Path::from("key"),
+            object_store::PutMode::Overwrite,
+            Bytes::from("v"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            faults.stats(),
+            FaultStats {
+                provider_timeouts: 1,
+                tool_errors: 1,
+                store_delays: 0,
+                store_errors: 2,
+            }
+        );
+    }
+}
@@ -29,20 +29,36 @@ use anda_core::{
     AgentArgs, AgentContext, AgentInput, AgentOutput, AgentSet, BaseContext, BoxError, CacheExpiry,
     CacheFeatures, CacheStoreFeatures, CancellationToken, CanisterCaller, CompletionFeatures,
     CompletionRequest, Embedding, EmbeddingFeatures, FunctionDefinition, HttpFeatures,
-    KeysFeatures, Message, ObjectMeta, Path, PutMode, PutResult, RequestMeta, Resource,
-    StateFeatures, StoreFeatures, ToolCall, ToolInput, ToolOutput, ToolSet, Usage, Value,
+    KeysFeatures, Message, ObjectMeta, Path, PutMode, PutResult, RequestMeta, Resource, SizeLimits,
+    StateFeatures, StoreFeatures, ToolCall, ToolInput, ToolOutput, ToolPipeline, ToolSet,
+    Truncation, Usage, Value, Xid, extract_json, validate_json,
 };
 use bytes::Bytes;
 use candid::{CandidType, Principal, utils::ArgumentEncoder};
+use futures::{StreamExt, stream::BoxStream};
 use serde::{Serialize, de::DeserializeOwned};
 use serde_json::json;
-use std::{future::Future, sync::Arc, time::Duration};
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
 
-use super::{base::BaseCtx, engine::RemoteEngines};
-use crate::{management::Management, model::Model};
+use super::{
+    CostMeter, ToolResultSummarizer,
+    base::BaseCtx,
+    engine::RemoteEngines,
+    scheduler::{Scheduler, SchedulerPermit},
+};
+use crate::{
+    engine::{Hook, Hooks},
+    management::Management,
+    memory::{Memory, ThreadMemory},
+    model::{Model, ProviderErrorKind},
+    store::is_conflict,
+};
 
 pub static DYNAMIC_REMOTE_ENGINES: &str = "_engines";
 
+/// How many times a turn is recorded again in the thread memory after concurrent turns.
+const MEMORY_SAVE_RETRIES: usize = 3;
+
 /// Context for agent operations, providing access to models, tools, and other agents.
 #[derive(Clone)]
 pub struct AgentCtx {
@@ -52,8 +68,26 @@ pub struct AgentCtx {
     pub(crate) model: Model,
     /// Set of available tools that can be called.
     pub(crate) tools: Arc<ToolSet<BaseCtx>>,
+    /// Composite tools chaining the tools, called like tools.
+    pub(crate) pipelines: Arc<BTreeMap<String, Arc<ToolPipeline>>>,
     /// Set of available agents that can be invoked.
     pub(crate) agents: Arc<AgentSet<AgentCtx>>,
+    /// Scheduler shared by all requests in the engine.
+    pub(crate) scheduler: Arc<Scheduler>,
+    /// Execution slot of the run, shared with its nested agent runs.
+    pub(crate) permit: Option<Arc<tokio::sync::Mutex<SchedulerPermit>>>,
+    /// Size limits of prompts, tool results and inline resources.
+    pub(crate) limits: Arc<SizeLimits>,
+    /// Cost of the run, checked against the cost ceiling of the engine.
+    pub(crate) cost: Arc<CostMeter>,
+    /// Maximum number of tool calls of a completion that execute concurrently.
+    pub(crate) tool_concurrency: usize,
+    /// Conversation memory of threads, if enabled.
+    pub(crate) memory: Option<Arc<Memory>>,
+    /// Summarizer of large tool results, if enabled.
+    pub(crate) tool_result_summarizer: Option<Arc<ToolResultSummarizer>>,
+    /// Hooks of the completions and tool calls, shared with the engine.
+    pub(crate) hooks: Arc<Hooks>,
 
     management: Arc<Management>,
 }
@@ -66,22 +100,92 @@ impl AgentCtx {
     /// * `model` - AI model instance.
     /// * `tools` - Set of available tools.
     /// * `agents` - Set of available agents.
+    /// * `scheduler` - Scheduler for request admission.
+    /// * `limits` - Size limits of prompts, tool results and inline resources.
     pub(crate) fn new(
         base: BaseCtx,
         model: Model,
         tools: Arc<ToolSet<BaseCtx>>,
         agents: Arc<AgentSet<AgentCtx>>,
+        scheduler: Arc<Scheduler>,
+        limits: Arc<SizeLimits>,
         management: Arc<Management>,
     ) -> Self {
         Self {
             base,
             model,
             tools,
+            pipelines: Arc::new(BTreeMap::new()),
             agents,
+            scheduler,
+            permit: None,
+            limits,
+            cost: Arc::new(CostMeter::default()),
+            tool_concurrency: 1,
+            memory: None,
+            tool_result_summarizer: None,
+            hooks: Arc::new(Hooks::new()),
             management,
         }
     }
 
+    /// Sets the composite tools.
+    pub(crate) fn with_pipelines(
+        mut self,
+        pipelines: Arc<BTreeMap<String, Arc<ToolPipeline>>>,
+    ) -> Self {
+        self.pipelines = pipelines;
+        self
+    }
+
+    /// Sets the maximum cost of a run in USD, including its nested agent runs.
+    pub(crate) fn with_cost_ceiling(mut self, ceiling: Option<f64>) -> Self {
+        self.cost = Arc::new(CostMeter::new(ceiling));
+        self
+    }
+
+    /// Sets the maximum number of tool calls of a completion that execute concurrently.
+    pub(crate) fn with_tool_concurrency(mut self, tool_concurrency: usize) -> Self {
+        self.tool_concurrency = tool_concurrency;
+        self
+    }
+
+    /// Sets the conversation memory of threads.
+    pub(crate) fn with_memory(mut self, memory: Option<Arc<Memory>>) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Sets the summarizer of large tool results.
+    pub(crate) fn with_tool_result_summarizer(
+        mut self,
+        summarizer: Option<Arc<ToolResultSummarizer>>,
+    ) -> Self {
+        self.tool_result_summarizer = summarizer;
+        self
+    }
+
+    /// Sets the hooks of the completions and tool calls.
+    pub(crate) fn with_hooks(mut self, hooks: Arc<Hooks>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Sets the execution slot of the run, admitted by the scheduler.
+    pub(crate) fn with_permit(mut self, permit: SchedulerPermit) -> Self {
+        self.permit = Some(Arc::new(tokio::sync::Mutex::new(permit)));
+        self
+    }
+
+    /// Gives up the execution slot of the run to waiting interactive requests,
+    /// see [`SchedulerPermit::yield_now`].
+    async fn yield_now(&self) -> bool {
+        match &self.permit {
+            Some(permit) => permit.lock().await.yield_now().await,
+            None => false,
+        }
+    }
+
     /// Creates a child context for a specific agent.
     ///
     /// # Arguments
@@ -91,7 +195,16 @@ impl AgentCtx {
             base: self.base.child(format!("A:{}", agent_name))?,
             model: self.model.clone(),
             tools: self.tools.clone(),
+            pipelines: self.pipelines.clone(),
             agents: self.agents.clone(),
+            scheduler: self.scheduler.clone(),
+            permit: self.permit.clone(),
+            limits: self.limits.clone(),
+            cost: self.cost.clone(),
+            tool_concurrency: self.tool_concurrency,
+            memory: self.memory.clone(),
+            tool_result_summarizer: self.tool_result_summarizer.clone(),
+            hooks: self.hooks.clone(),
             management: self.management.clone(),
         })
     }
@@ -122,7 +235,16 @@ impl AgentCtx {
                 .child_with(caller, format!("A:{}", agent_name), meta)?,
             model: self.model.clone(),
             tools: self.tools.clone(),
+            pipelines: self.pipelines.clone(),
             agents: self.agents.clone(),
+            scheduler: self.scheduler.clone(),
+            permit: self.permit.clone(),
+            limits: self.limits.clone(),
+            cost: Arc::new(CostMeter::new(self.cost.ceiling())),
+            tool_concurrency: self.tool_concurrency,
+            memory: self.memory.clone(),
+            tool_result_summarizer: self.tool_result_summarizer.clone(),
+            hooks: self.hooks.clone(),
             management: self.management.clone(),
         })
     }
@@ -155,7 +277,14 @@ impl AgentContext for AgentCtx {
     /// # Returns
     /// Vector of function definitions for the requested tools.
     fn tool_definitions(&self, names: Option<&[&str]>) -> Vec<FunctionDefinition> {
-        self.tools.definitions(names)
+        let mut defs = self.tools.definitions(names);
+        defs.extend(
+            self.pipelines
+                .iter()
+                .filter(|(name, _)| names.is_none_or(|names| names.contains(&name.as_str())))
+                .map(|(_, pipeline)| pipeline.definition()),
+        );
+        defs
     }
 
     /// Retrieves definitions for available tools in the remote engines.
@@ -305,32 +434,11 @@ impl AgentContext for AgentCtx {
     ///
     /// # Returns
     /// Tuple containing the result string and a boolean indicating if further processing is needed
-    async fn tool_call(&self, mut input: ToolInput<Value>) -> Result<ToolOutput<Value>, BoxError> {
-        if !input.name.starts_with("RT_") {
-            let ctx = self.child_base(&input.name)?;
-            let tool = self.tools.get(&input.name).expect("tool not found");
-            let args = serde_json::to_string(&input.args)?;
-            return tool.call(ctx, args, input.resources).await;
+    async fn tool_call(&self, input: ToolInput<Value>) -> Result<ToolOutput<Value>, BoxError> {
+        match self.pipelines.get(&input.name) {
+            Some(pipeline) => self.pipeline_call(pipeline, input).await,
+            None => self.call_tool(input).await,
         }
-
-        // find registered remote tool and call it
-        if let Some((endpoint, tool_name)) = self.base.remote.get_tool_endpoint(&input.name) {
-            input.name = tool_name;
-            return self.base.remote_tool_call(&endpoint, input).await;
-        }
-
-        // find dynamic remote tool and call it
-        if let Ok((engines, _)) = self
-            .cache_store_get::<RemoteEngines>(DYNAMIC_REMOTE_ENGINES)
-            .await
-        {
-            if let Some((endpoint, tool_name)) = engines.get_tool_endpoint(&input.name) {
-                input.name = tool_name;
-                return self.base.remote_tool_call(&endpoint, input).await;
-            }
-        }
-
-        Err(format!("tool {} not found", &input.name).into())
     }
 
     /// Runs a local agent.
@@ -426,6 +534,275 @@ impl AgentContext for AgentCtx {
     }
 }
 
+/// The system message of the request that repairs the arguments of a tool call.
+const TOOL_ARGS_REPAIR_PROMPT: &str = "You repair the JSON arguments of function calls. \
+Given the function, the JSON schema of its parameters, the invalid arguments and the error, \
+respond with only the corrected JSON arguments, keeping the intent of the original arguments.";
+
+/// Parses the arguments of a tool call and validates them against the parameters of the tool.
+fn parse_tool_args(def: &FunctionDefinition, args: &str) -> Result<Value, String> {
+    let value: Value =
+        serde_json::from_str(args).map_err(|err| format!("not valid JSON, {}", err))?;
+    validate_json(&def.parameters, &value)?;
+    Ok(value)
+}
+
+impl AgentCtx {
+    /// Calls a tool, with the input and the output passed to the hooks.
+    async fn call_tool(&self, mut input: ToolInput<Value>) -> Result<ToolOutput<Value>, BoxError> {
+        self.hooks.before_tool_call(&self.base, &mut input).await?;
+        let name = input.name.clone();
+        let output = self.dispatch_tool(input).await?;
+        self.hooks.after_tool_call(&self.base, &name, output).await
+    }
+
+    /// Calls a local or remote tool.
+    async fn dispatch_tool(
+        &self,
+        mut input: ToolInput<Value>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        if !input.name.starts_with("RT_") {
+            let ctx = self.child_base(&input.name)?;
+            let tool = self
+                .tools
+                .get(&input.name)
+                .ok_or_else(|| format!("tool {} not found", &input.name))?;
+            let args = serde_json::to_string(&input.args)?;
+            if let Some(agent) = self.base.path.as_ref().strip_prefix("A:") {
+                self.management
+                    .consume_budget(agent, &input.name, &args)
+                    .await?;
+            }
+            if self.management.requires_approval(&input.name) {
+                let call = self
+                    .management
+                    .request_approval(ctx.caller(), input.name, args, input.resources)
+                    .await?;
+                return Ok(call.pending_output());
+            }
+            return tool.call(ctx, args, input.resources).await;
+        }
+
+        // find registered remote tool and call it
+        if let Some((endpoint, tool_name)) = self.base.remote.get_tool_endpoint(&input.name) {
+            input.name = tool_name;
+            return self.base.remote_tool_call(&endpoint, input).await;
+        }
+
+        // find dynamic remote tool and call it
+        if let Ok((engines, _)) = self
+            .cache_store_get::<RemoteEngines>(DYNAMIC_REMOTE_ENGINES)
+            .await
+        {
+            if let Some((endpoint, tool_name)) = engines.get_tool_endpoint(&input.name) {
+                input.name = tool_name;
+                return self.base.remote_tool_call(&endpoint, input).await;
+            }
+        }
+
+        Err(format!("tool {} not found", &input.name).into())
+    }
+
+    /// Calls the tools of a pipeline in order, each with the arguments resolved from the
+    /// pipeline arguments and the outputs of the previous steps. The resources are given to
+    /// the first step, and the usage of all steps is accumulated.
+    async fn pipeline_call(
+        &self,
+        pipeline: &ToolPipeline,
+        input: ToolInput<Value>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        let mut outputs: Vec<Value> = Vec::with_capacity(pipeline.steps.len());
+        let mut usage = Usage::default();
+        let mut resources = input.resources;
+        for (i, step) in pipeline.steps.iter().enumerate() {
+            let args = pipeline
+                .step_args(i, &input.args, &outputs)
+                .map_err(|err| format!("pipeline {}, step {}: {}", pipeline.name, i, err))?;
+            let output = self
+                .call_tool(ToolInput {
+                    name: step.tool.clone(),
+                    args,
+                    resources: resources.take(),
+                    meta: input.meta.clone(),
+                })
+                .await
+                .map_err(|err| format!("pipeline {}, step {}: {}", pipeline.name, i, err))?;
+            usage.accumulate(&output.usage);
+            outputs.push(output.output);
+        }
+
+        let output = pipeline
+            .output(&input.args, &outputs)
+            .map_err(|err| format!("pipeline {}: {}", pipeline.name, err))?;
+        Ok(ToolOutput {
+            output,
+            resources: None,
+            usage,
+        })
+    }
+
+    /// Calls the model, dropping the lowest-priority context and retrying
+    /// when the request exceeds the model's context length.
+    async fn model_completion(&self, req: &mut CompletionRequest) -> Result<AgentOutput, BoxError> {
+        if req.caller.is_none() {
+            req.caller = Some(self.base.caller);
+        }
+        self.hooks.before_completion(self, req).await?;
+        self.fit_context_window(req);
+        loop {
+            self.cost.check()?;
+            match self.model.completion(req.clone()).await {
+                Err(err) if ProviderErrorKind::of(&err) == ProviderErrorKind::ContextLength => {
+                    match req.drop_context() {
+                        Some(source) => {
+                            log::warn!(
+                                name = self.base.name,
+                                dropped:? = source;
+                                "context length exceeded, retrying with less context"
+                            );
+                        }
+                        None => return Err(err),
+                    }
+                }
+                Ok(mut output) => {
+                    self.model.estimate_usage(req, &mut output);
+                    self.cost.add(output.usage.cost);
+                    return self.hooks.after_completion(self, output).await;
+                }
+                rt => return rt,
+            }
+        }
+    }
+
+    /// Asks the model once to repair the arguments of a tool call that are not valid JSON
+    /// or do not conform to the parameters of the tool, given the error. Fails with the
+    /// error if the repair request fails or the repaired arguments are still invalid.
+    async fn repair_tool_args(
+        &self,
+        def: &FunctionDefinition,
+        args: &str,
+        err: &str,
+        usage: &mut Usage,
+    ) -> Result<Value, BoxError> {
+        log::warn!(name = self.base.name, tool = def.name; "invalid tool call arguments, repairing: {}", err);
+        let mut req = CompletionRequest {
+            system: Some(TOOL_ARGS_REPAIR_PROMPT.to_string()),
+            prompt: format!(
+                "Function: {}\nParameters schema: {}\nArguments: {}\nError: {}",
+                def.name, def.parameters, args, err
+            ),
+            response_format: self
+                .model
+                .capabilities
+                .json_mode
+                .then(|| json!({"type": "json_object"})),
+            ..Default::default()
+        };
+        let output = self.model_completion(&mut req).await?;
+        usage.accumulate(&output.usage);
+        parse_tool_args(def, extract_json(&output.content)).map_err(|repair_err| {
+            format!(
+                "invalid arguments of tool {}: {}, and the repair failed: {}",
+                def.name, err, repair_err
+            )
+            .into()
+        })
+    }
+
+    /// Loads the memory of the thread for a request of the agent that serves the thread,
+    /// if the request has no chat history of its own.
+    async fn load_thread_memory(&self, req: &CompletionRequest) -> Option<(Xid, ThreadMemory)> {
+        let memory = self.memory.as_ref()?;
+        let thread = self.base.meta.thread.as_ref()?;
+        // the agent run by the engine, not the agents it calls
+        if self.base.depth != 1 || !req.chat_history.is_empty() {
+            return None;
+        }
+        Some((thread.clone(), memory.load(self, thread).await))
+    }
+
+    /// Records the turn in the memory of the thread, and summarizes its older turns if it
+    /// exceeds the budget. The summarized history replaces the output's full history.
+    async fn update_thread_memory(
+        &self,
+        thread: &Xid,
+        mut thread_memory: ThreadMemory,
+        system: Option<String>,
+        prompt: &str,
+        output: &mut AgentOutput,
+    ) {
+        let Some(memory) = &self.memory else {
+            return;
+        };
+        let mut retries = 0;
+        loop {
+            thread_memory.push_turn(prompt, &output.content);
+            match memory.compact(&self.model, &mut thread_memory).await {
+                Ok(Some(usage)) => {
+                    output.usage.accumulate(&usage);
+                    let mut history: Vec<Value> = system
+                        .iter()
+                        .map(|system| json!({"role": "system", "content": system}))
+                        .collect();
+                    history.extend(thread_memory.history());
+                    output.full_history = Some(history);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    log::warn!(name = self.base.name, thread = thread.to_string(); "failed to summarize the thread memory: {}", err);
+                }
+            }
+            match memory.save(self, thread, thread_memory).await {
+                Ok(()) => return,
+                // another turn of the thread saved the memory, records the turn after it
+                Err(err) if retries < MEMORY_SAVE_RETRIES && is_conflict(&err) => {
+                    retries += 1;
+                    thread_memory = memory.load(self, thread).await;
+                }
+                Err(err) => {
+                    log::error!(name = self.base.name, thread = thread.to_string(); "failed to save the thread memory: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Prices the usage of an output of a completion stream, reported in its last output,
+    /// and adds its cost to the cost of the run.
+    fn charge_stream_usage(&self, output: &mut AgentOutput) {
+        let usage = &mut output.usage;
+        if usage.input_tokens == 0 && usage.output_tokens == 0 && usage.requests == 0 {
+            return;
+        }
+        if let Some(pricing) = &self.model.pricing
+            && usage.cost == 0.0
+        {
+            usage.cost = pricing.cost(usage);
+        }
+        self.cost.add(usage.cost);
+    }
+
+    /// Returns the output of a cancelled completion, with the usage consumed so far.
+    fn cancelled_output(&self, mut output: AgentOutput, usage: Usage) -> AgentOutput {
+        let reason = self.base.cancelled_reason().unwrap_or("cancelled");
+        output.failed_reason = Some(reason.to_string());
+        output.usage = usage;
+        output
+    }
+
+    /// Drops context from the request if it does not fit in the model's context window.
+    fn fit_context_window(&self, req: &mut CompletionRequest) {
+        let dropped = self.model.fit_context_window(req);
+        if !dropped.is_empty() {
+            log::warn!(
+                name = self.base.name,
+                dropped:? = dropped;
+                "request exceeds the context window, dropped context"
+            );
+        }
+    }
+}
+
 impl CompletionFeatures for AgentCtx {
     /// Executes a completion request with automatic tool call handling.
     ///
@@ -441,12 +818,21 @@ impl CompletionFeatures for AgentCtx {
     /// [`AgentOutput`] containing the final completion result.
     ///
     /// # Process Flow
-    /// 1. Makes initial completion request to the model;
+    /// 1. Makes initial completion request to the model, with context dropped by
+    ///    [`CompletionRequest::context_drop_order`] to fit in the model's context window;
+    ///    if it still exceeds the model's context length, drops more context and retries;
     /// 2. If tool calls are returned:
-    ///    - Executes each tool call;
-    ///    - Adds tool results to the chat history;
+    ///    - Yields to waiting interactive requests if it is a batch request;
+    ///    - Asks the model once to repair arguments that are not valid JSON or do not
+    ///      conform to the parameters of the tool, and fails if they are still invalid;
+    ///    - Executes the tool calls, up to the engine's tool call concurrency at a time;
+    ///    - Adds tool results to the chat history, in the order of the calls;
     ///    - Repeats the completion with updated history;
     /// 3. Returns final result when no more tool calls need processing.
+    ///
+    /// If the context is cancelled or the request's deadline is reached, the pending model
+    /// and tool calls are aborted, and the output is returned with the `failed_reason` and
+    /// the usage consumed so far.
     async fn completion(
         &self,
         mut req: CompletionRequest,
@@ -455,104 +841,234 @@ impl CompletionFeatures for AgentCtx {
         let mut tool_calls_result: Vec<ToolCall> = Vec::new();
         let mut usage = Usage::default();
         let mut resources = resources.unwrap_or_default();
+        let mut truncations: Vec<Truncation> = Vec::new();
+        let thread_memory = self.load_thread_memory(&req).await;
+        if let Some((_, memory)) = &thread_memory {
+            req.chat_history = memory.history();
+        }
+        let system = req.system.clone();
+        let prompt = req.prompt.clone();
         loop {
             let mut resources_out: Vec<Resource> = Vec::new();
-            let mut output = self.model.completion(req.clone()).await?;
+            let mut output = tokio::select! {
+                biased;
+                _ = self.base.cancellation_token.cancelled() => {
+                    let output = AgentOutput {
+                        tool_calls: if tool_calls_result.is_empty() {
+                            None
+                        } else {
+                            Some(tool_calls_result)
+                        },
+                        ..Default::default()
+                    };
+                    return Ok(self.cancelled_output(output, usage));
+                }
+                rt = self.model_completion(&mut req) => rt?,
+            };
             usage.accumulate(&output.usage);
             // automatically executes tools calls
             let mut tool_calls_continue: Vec<Value> = Vec::new();
             if let Some(tool_calls) = &mut output.tool_calls {
-                for tool in tool_calls.iter_mut() {
-                    if !req.tools.iter().any(|t| t.name == tool.name) {
+                let mut calls: Vec<(usize, PendingCall)> = Vec::new();
+                for (i, tool) in tool_calls.iter_mut().enumerate() {
+                    let Some(def) = req.tools.iter().find(|t| t.name == tool.name).cloned() else {
                         // tool already called, skip
                         continue;
-                    }
+                    };
 
                     // remove called tool from req.tools
                     req.tools.retain(|t| t.name != tool.name);
-                    if self.tools.contains(&tool.name) || tool.name.starts_with("RT_") {
-                        match self
-                            .tool_call(ToolInput {
-                                name: tool.name.clone(),
-                                args: serde_json::from_str(&tool.args)?,
-                                resources: self
-                                    .select_tool_resources(&tool.name, &mut resources)
-                                    .await,
-                                meta: Some(self.meta().clone()),
-                            })
+                    let is_tool = self.tools.contains(&tool.name)
+                        || self.pipelines.contains_key(&tool.name)
+                        || tool.name.starts_with("RT_");
+                    let is_agent = self.agents.contains(&tool.name)
+                        || tool.name.starts_with("LA_")
+                        || tool.name.starts_with("RA_");
+                    if !is_tool && !is_agent {
+                        // ignore unknown tool
+                        continue;
+                    }
+
+                    let args = match parse_tool_args(&def, &tool.args) {
+                        Ok(args) => args,
+                        Err(err) => match self
+                            .repair_tool_args(&def, &tool.args, &err, &mut usage)
                             .await
                         {
-                            Ok(mut res) => {
-                                usage.accumulate(&res.usage);
-                                let content: Value = if res.output.is_string() {
-                                    res.output.clone()
-                                } else {
-                                    serde_json::to_string(&res.output)?.into()
-                                };
-
+                            Ok(args) => {
+                                tool.args = args.to_string();
+                                args
+                            }
+                            Err(err) => {
+                                // the call fails, and the model is told why
+                                let reason = err.to_string();
                                 tool_calls_continue.push(json!(Message {
                                     role: "tool".to_string(),
-                                    content,
+                                    content: reason.clone().into(),
                                     name: None,
                                     tool_call_id: Some(tool.id.clone()),
                                 }));
-
-                                if let Some(resource) = res.resources {
-                                    resources_out.extend(resource);
-                                    res.resources = None;
-                                }
-
-                                tool.result = Some(serde_json::to_value(&res)?);
+                                tool.result = Some(json!({ "failed_reason": reason }));
+                                continue;
                             }
-                            Err(err) => {
-                                output.failed_reason = Some(err.to_string());
-                                output.usage = usage;
-                                return Ok(output);
-                            }
-                        }
-                    } else if self.agents.contains(&tool.name)
-                        || tool.name.starts_with("LA_")
-                        || tool.name.starts_with("RA_")
-                    {
-                        let args: AgentArgs = serde_json::from_str(&tool.args)?;
-                        match self
-                            .agent_run(AgentInput {
+                        },
+                    };
+                    if is_tool {
+                        calls.push((
+                            i,
+                            PendingCall::Tool(ToolInput {
+                                name: tool.name.clone(),
+                                args,
+                                resources: self
+                                    .select_tool_resources(&tool.name, &mut resources)
+                                    .await,
+                                meta: Some(self.meta().clone()),
+                            }),
+                        ));
+                    } else {
+                        let args: AgentArgs = serde_json::from_value(args)?;
+                        calls.push((
+                            i,
+                            PendingCall::Agent(AgentInput {
                                 name: tool.name.clone(),
                                 prompt: args.prompt,
                                 resources: self.agents.select_resources(&tool.name, &mut resources),
                                 meta: Some(self.meta().clone()),
-                            })
-                            .await
-                        {
-                            Ok(mut res) => {
-                                usage.accumulate(&res.usage);
-                                if res.failed_reason.is_some() {
-                                    output.failed_reason = res.failed_reason;
-                                    return Ok(output);
-                                }
+                            }),
+                        ));
+                    }
+                }
 
-                                tool_calls_continue.push(json!(Message {
-                                    role: "tool".to_string(),
-                                    content: res.content.clone().into(),
-                                    name: None,
-                                    tool_call_id: Some(tool.id.clone()),
-                                }));
+                // batch requests give way to waiting interactive requests between tool calls,
+                // once for the calls of the completion
+                if !calls.is_empty() {
+                    tokio::select! {
+                        biased;
+                        _ = self.base.cancellation_token.cancelled() => {
+                            return Ok(self.cancelled_output(output, usage));
+                        }
+                        _ = self.yield_now() => {}
+                    }
+                }
 
-                                if let Some(resource) = res.resources {
-                                    resources_out.extend(resource);
-                                    res.resources = None;
+                // runs up to `tool_concurrency` calls at a time, and merges the results
+                // in the order of the calls
+                let mut results = futures::stream::iter(calls)
+                    .map(|(i, call)| async move {
+                        let rt = match call {
+                            PendingCall::Tool(input) => {
+                                self.tool_call(input).await.map(CallOutput::Tool)
+                            }
+                            PendingCall::Agent(input) => self
+                                .agent_run(input)
+                                .await
+                                .map(|res| CallOutput::Agent(Box::new(res))),
+                        };
+                        (i, rt)
+                    })
+                    .buffered(self.tool_concurrency.max(1));
+                loop {
+                    let (i, rt) = tokio::select! {
+                        biased;
+                        _ = self.base.cancellation_token.cancelled() => {
+                            return Ok(self.cancelled_output(output, usage));
+                        }
+                        next = results.next() => match next {
+                            Some(next) => next,
+                            None => break,
+                        },
+                    };
+                    let tool = &mut tool_calls[i];
+                    match rt {
+                        Ok(CallOutput::Tool(mut res)) => {
+                            usage.accumulate(&res.usage);
+                            let content = match &res.output {
+                                Value::String(s) => s.clone(),
+                                v => serde_json::to_string(v)?,
+                            };
+                            let content = self
+                                .summarize_tool_result(
+                                    tool,
+                                    content,
+                                    &mut usage,
+                                    &mut resources_out,
+                                    &mut truncations,
+                                )
+                                .await;
+                            let content = match self
+                                .fit_tool_result(&tool.name, content, &mut truncations)
+                                .await
+                            {
+                                Ok(content) => content,
+                                Err(err) => {
+                                    output.failed_reason = Some(err.to_string());
+                                    output.usage = usage;
+                                    return Ok(output);
                                 }
-
-                                tool.result = Some(serde_json::to_value(&res)?);
+                            };
+
+                            tool_calls_continue.push(json!(Message {
+                                role: "tool".to_string(),
+                                content: content.into(),
+                                name: None,
+                                tool_call_id: Some(tool.id.clone()),
+                            }));
+
+                            if let Some(resource) = res.resources {
+                                resources_out.extend(resource);
+                                res.resources = None;
                             }
-                            Err(err) => {
-                                output.failed_reason = Some(err.to_string());
+
+                            tool.result = Some(serde_json::to_value(&res)?);
+                        }
+                        Ok(CallOutput::Agent(mut res)) => {
+                            usage.accumulate(&res.usage);
+                            if res.failed_reason.is_some() {
+                                output.failed_reason = res.failed_reason;
                                 output.usage = usage;
                                 return Ok(output);
                             }
+
+                            let content = self
+                                .summarize_tool_result(
+                                    tool,
+                                    res.content.clone(),
+                                    &mut usage,
+                                    &mut resources_out,
+                                    &mut truncations,
+                                )
+                                .await;
+                            let content = match self
+                                .fit_tool_result(&tool.name, content, &mut truncations)
+                                .await
+                            {
+                                Ok(content) => content,
+                                Err(err) => {
+                                    output.failed_reason = Some(err.to_string());
+                                    output.usage = usage;
+                                    return Ok(output);
+                                }
+                            };
+                            tool_calls_continue.push(json!(Message {
+                                role: "tool".to_string(),
+                                content: content.into(),
+                                name: None,
+                                tool_call_id: Some(tool.id.clone()),
+                            }));
+
+                            if let Some(resource) = res.resources {
+                                resources_out.extend(resource);
+                                res.resources = None;
+                            }
+
+                            tool.result = Some(serde_json::to_value(&res)?);
+                        }
+                        Err(err) => {
+                            output.failed_reason = Some(err.to_string());
+                            output.usage = usage;
+                            return Ok(output);
                         }
                     }
-                    // ignore unknown tool
                 }
 
                 tool_calls_result.append(tool_calls);
@@ -569,8 +1085,17 @@ impl CompletionFeatures for AgentCtx {
                 } else {
                     Some(resources_out)
                 };
+                if !truncations.is_empty() {
+                    output.truncations = Some(truncations);
+                }
 
                 output.usage = usage;
+                if let Some((thread, memory)) = thread_memory
+                    && output.failed_reason.is_none()
+                {
+                    self.update_thread_memory(&thread, memory, system, &prompt, &mut output)
+                        .await;
+                }
                 return Ok(output);
             }
 
@@ -584,6 +1109,59 @@ impl CompletionFeatures for AgentCtx {
             }
         }
     }
+
+    /// Streams a completion without tools from the model.
+    ///
+    /// Requests with tools are not streamed: the tool calls are executed as in
+    /// [`completion`](Self::completion), and the final result is yielded as a single output.
+    /// The outputs of the stream are passed to the `after_completion` hooks one by one.
+    /// A cancelled stream ends with an output with the `failed_reason`.
+    fn completion_stream(
+        &self,
+        mut req: CompletionRequest,
+        resources: Option<Vec<Resource>>,
+    ) -> BoxStream<'_, Result<AgentOutput, BoxError>> {
+        if !req.tools.is_empty() {
+            return Box::pin(futures::stream::once(CompletionFeatures::completion(
+                self, req, resources,
+            )));
+        }
+        if req.caller.is_none() {
+            req.caller = Some(self.base.caller);
+        }
+        let cancelled = futures::stream::once(async move {
+            self.base.cancelled_reason().map(|reason| {
+                Ok(AgentOutput {
+                    failed_reason: Some(reason.to_string()),
+                    ..Default::default()
+                })
+            })
+        })
+        .filter_map(futures::future::ready);
+        futures::stream::once(async move {
+            if let Err(err) = self.cost.check() {
+                return futures::stream::once(futures::future::ready(Err(err))).boxed();
+            }
+            if let Err(err) = self.hooks.before_completion(self, &mut req).await {
+                return futures::stream::once(futures::future::ready(Err(err))).boxed();
+            }
+            self.fit_context_window(&mut req);
+            self.model.completion_stream(req)
+        })
+        .flatten()
+        .then(move |output| async move {
+            match output {
+                Ok(mut output) => {
+                    self.charge_stream_usage(&mut output);
+                    self.hooks.after_completion(self, output).await
+                }
+                Err(err) => Err(err),
+            }
+        })
+        .take_until(self.base.cancellation_token.cancelled())
+        .chain(cancelled)
+        .boxed()
+    }
 }
 
 impl EmbeddingFeatures for AgentCtx {
@@ -950,11 +1528,626 @@ impl HttpFeatures for AgentCtx {
     }
 }
 
+/// A tool or agent call prepared from a model's tool call.
+enum PendingCall {
+    Tool(ToolInput<Value>),
+    Agent(AgentInput),
+}
+
+enum CallOutput {
+    Tool(ToolOutput<Value>),
+    Agent(Box<AgentOutput>),
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::{
+        engine::EngineBuilder,
+        model::{CompletionFeaturesDyn, Model, ModelPricing},
+    };
+    use anda_core::{Agent, Tool, TruncationStrategy};
     use ciborium::from_reader;
     use ic_cose_types::to_cbor_bytes;
     use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use structured_logger::unix_ms;
+
+    use crate::{extension::segmenter::DocumentSegmenter, memory::Memory};
+
+    /// Sleeps and reports the number of calls in flight.
+    struct SleepTool {
+        name: &'static str,
+        delay: Duration,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl Tool<BaseCtx> for SleepTool {
+        type Args = Value;
+        type Output = String;
+
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn description(&self) -> String {
+            "Sleeps.".to_string()
+        }
+
+        fn definition(&self) -> FunctionDefinition {
+            FunctionDefinition {
+                name: self.name(),
+                description: self.description(),
+                parameters: json!({"type": "object"}),
+                strict: None,
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: BaseCtx,
+            _args: Self::Args,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<ToolOutput<Self::Output>, BoxError> {
+            let n = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(n, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            let mut output = ToolOutput::new(self.name.to_string());
+            output.usage.requests = self.delay.as_millis() as u64;
+            output.resources = Some(vec![Resource {
+                tag: self.name.to_string(),
+                ..Default::default()
+            }]);
+            Ok(output)
+        }
+    }
+
+    async fn run_sleep_tools(tool_concurrency: usize) -> (AgentOutput, usize) {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let mut builder = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .with_parallel_tool_calls(tool_concurrency);
+        let mut tools = Vec::new();
+        for (name, delay) in [("slow_tool", 80), ("fast_tool", 10)] {
+            let tool = SleepTool {
+                name,
+                delay: Duration::from_millis(delay),
+                in_flight: in_flight.clone(),
+                max_in_flight: max_in_flight.clone(),
+            };
+            tools.push(tool.definition());
+            builder = builder.register_tool(tool).unwrap();
+        }
+        let ctx = builder.mock_ctx();
+        let output = ctx
+            .completion(
+                CompletionRequest {
+                    prompt: "{}".to_string(),
+                    tools,
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        (output, max_in_flight.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_parallel_tool_calls() {
+        let (sequential, max_in_flight) = run_sleep_tools(1).await;
+        assert_eq!(max_in_flight, 1);
+        let (parallel, max_in_flight) = run_sleep_tools(4).await;
+        assert_eq!(max_in_flight, 2);
+
+        // merged in the order of the calls, although the fast tool completes first
+        for output in [&sequential, &parallel] {
+            assert!(output.failed_reason.is_none());
+            let calls = output.tool_calls.as_ref().unwrap();
+            let names: Vec<&str> = calls.iter().map(|c| c.name.as_str()).collect();
+            assert_eq!(names, vec!["slow_tool", "fast_tool"]);
+            assert_eq!(calls[0].result.as_ref().unwrap()["output"], "slow_tool");
+            assert_eq!(calls[1].result.as_ref().unwrap()["output"], "fast_tool");
+            assert_eq!(output.usage.requests, 90);
+        }
+    }
+
+    /// Calls the tool with malformed arguments, then repairs them if asked,
+    /// or fails the repair request if the repaired arguments are empty.
+    struct MalformedArgsCompleter {
+        repaired: &'static str,
+    }
+
+    impl CompletionFeaturesDyn for MalformedArgsCompleter {
+        fn completion(
+            &self,
+            req: CompletionRequest,
+        ) -> anda_core::BoxPinFut<Result<AgentOutput, BoxError>> {
+            let output = if req.system.as_deref() == Some(TOOL_ARGS_REPAIR_PROMPT) {
+                assert!(req.prompt.contains("not valid JSON"));
+                if self.repaired.is_empty() {
+                    return Box::pin(futures::future::ready(Err("model unavailable".into())));
+                }
+                AgentOutput {
+                    content: format!("```json\n{}\n```", self.repaired),
+                    ..Default::default()
+                }
+            } else if let Some(tool) = req.tools.first() {
+                AgentOutput {
+                    tool_calls: Some(vec![ToolCall {
+                        id: "call_1".to_string(),
+                        name: tool.name.clone(),
+                        args: "{query: anda".to_string(),
+                        result: None,
+                    }]),
+                    ..Default::default()
+                }
+            } else {
+                AgentOutput {
+                    content: "done".to_string(),
+                    ..Default::default()
+                }
+            };
+            Box::pin(futures::future::ready(Ok(output)))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_repair_tool_args() {
+        for (repaired, failed) in [
+            (r#"{"query": "anda"}"#, None),
+            ("[]", Some("the repair failed")),
+            ("", Some("model unavailable")),
+        ] {
+            let tool = SleepTool {
+                name: "search",
+                delay: Duration::from_millis(1),
+                in_flight: Arc::new(AtomicUsize::new(0)),
+                max_in_flight: Arc::new(AtomicUsize::new(0)),
+            };
+            let req = CompletionRequest {
+                prompt: "search anda".to_string(),
+                tools: vec![tool.definition()],
+                ..Default::default()
+            };
+            let ctx = EngineBuilder::new()
+                .with_model(Model::with_completer(Arc::new(MalformedArgsCompleter {
+                    repaired,
+                })))
+                .register_tool(tool)
+                .unwrap()
+                .mock_ctx();
+            // a failed repair fails the call, not the completion
+            let output = ctx.completion(req, None).await.unwrap();
+            assert_eq!(output.content, "done");
+            assert!(output.failed_reason.is_none());
+            let calls = output.tool_calls.unwrap();
+            let result = calls[0].result.as_ref().unwrap();
+            match failed {
+                None => {
+                    assert_eq!(calls[0].args, r#"{"query":"anda"}"#);
+                    assert_eq!(result["output"], "search");
+                }
+                Some(failed) => {
+                    assert_eq!(calls[0].args, "{query: anda");
+                    let reason = result["failed_reason"].as_str().unwrap();
+                    assert!(reason.contains(failed), "{reason}");
+                }
+            }
+        }
+    }
+
+    /// Calls the tool with the prompt, then answers with the prompt.
+    struct PromptToolCompleter;
+
+    impl CompletionFeaturesDyn for PromptToolCompleter {
+        fn completion(
+            &self,
+            req: CompletionRequest,
+        ) -> anda_core::BoxPinFut<Result<AgentOutput, BoxError>> {
+            let output = match req.tools.first() {
+                Some(tool) => AgentOutput {
+                    tool_calls: Some(vec![ToolCall {
+                        id: "call_1".to_string(),
+                        name: tool.name.clone(),
+                        args: json!({"query": req.prompt}).to_string(),
+                        result: None,
+                    }]),
+                    ..Default::default()
+                },
+                None => AgentOutput {
+                    content: format!("done {}", req.prompt),
+                    ..Default::default()
+                },
+            };
+            Box::pin(futures::future::ready(Ok(output)))
+        }
+    }
+
+    /// Returns its arguments.
+    struct EchoTool;
+
+    impl Tool<BaseCtx> for EchoTool {
+        type Args = Value;
+        type Output = Value;
+
+        fn name(&self) -> String {
+            "echo".to_string()
+        }
+
+        fn description(&self) -> String {
+            "Echoes the arguments.".to_string()
+        }
+
+        fn definition(&self) -> FunctionDefinition {
+            FunctionDefinition {
+                name: self.name(),
+                description: self.description(),
+                parameters: json!({"type": "object"}),
+                strict: None,
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: BaseCtx,
+            args: Self::Args,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<ToolOutput<Self::Output>, BoxError> {
+            Ok(ToolOutput::new(args))
+        }
+    }
+
+    /// Redacts the prompts, and rewrites the outputs and the tool calls.
+    struct RewriteHook;
+
+    #[async_trait::async_trait]
+    impl Hook for RewriteHook {
+        async fn before_completion(
+            &self,
+            _ctx: &AgentCtx,
+            req: &mut CompletionRequest,
+        ) -> Result<(), BoxError> {
+            req.prompt = req.prompt.replace("secret", "***");
+            Ok(())
+        }
+
+        async fn after_completion(
+            &self,
+            _ctx: &AgentCtx,
+            mut output: AgentOutput,
+        ) -> Result<AgentOutput, BoxError> {
+            output.content = output.content.to_uppercase();
+            Ok(output)
+        }
+
+        async fn before_tool_call(
+            &self,
+            _ctx: &BaseCtx,
+            input: &mut ToolInput<Value>,
+        ) -> Result<(), BoxError> {
+            input.args["checked"] = json!(true);
+            Ok(())
+        }
+
+        async fn after_tool_call(
+            &self,
+            _ctx: &BaseCtx,
+            tool: &str,
+            mut output: ToolOutput<Value>,
+        ) -> Result<ToolOutput<Value>, BoxError> {
+            output.output = json!({ tool: output.output });
+            Ok(output)
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_completion_hooks() {
+        let mut hooks = Hooks::new();
+        hooks.add(Box::new(RewriteHook));
+        let ctx = EngineBuilder::new()
+            .with_model(Model::with_completer(Arc::new(PromptToolCompleter)))
+            .register_tool(EchoTool)
+            .unwrap()
+            .with_hooks(Arc::new(hooks))
+            .mock_ctx();
+
+        let output = ctx
+            .completion(
+                CompletionRequest {
+                    prompt: "my secret".to_string(),
+                    tools: vec![EchoTool.definition()],
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.content, "DONE ");
+        let calls = output.tool_calls.unwrap();
+        assert_eq!(
+            calls[0].result.as_ref().unwrap()["output"],
+            json!({"echo": {"query": "my ***", "checked": true}})
+        );
+
+        // streamed outputs are passed to the hooks one by one
+        let outputs: Vec<_> = ctx
+            .completion_stream(
+                CompletionRequest {
+                    prompt: "my secret".to_string(),
+                    ..Default::default()
+                },
+                None,
+            )
+            .collect()
+            .await;
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].as_ref().unwrap().content, "DONE MY ***");
+    }
+
+    /// Answers with the usage of 1000 input and 1000 output tokens.
+    struct UsageCompleter;
+
+    impl CompletionFeaturesDyn for UsageCompleter {
+        fn completion(
+            &self,
+            _req: CompletionRequest,
+        ) -> anda_core::BoxPinFut<Result<AgentOutput, BoxError>> {
+            Box::pin(futures::future::ready(Ok(AgentOutput {
+                content: "ok".to_string(),
+                usage: Usage {
+                    input_tokens: 1000,
+                    output_tokens: 1000,
+                    requests: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_stream_cost_ceiling() {
+        let model = Model::with_completer(Arc::new(UsageCompleter))
+            .with_pricing(ModelPricing::new(1.0, 1.0));
+        let ctx = EngineBuilder::new()
+            .with_model(model)
+            .with_cost_ceiling(0.003)
+            .mock_ctx();
+        let req = CompletionRequest {
+            prompt: "hi".to_string(),
+            ..Default::default()
+        };
+
+        let outputs: Vec<_> = ctx.completion_stream(req.clone(), None).collect().await;
+        assert_eq!(outputs[0].as_ref().unwrap().usage.cost, 0.002);
+        assert_eq!(ctx.cost.spent(), 0.002);
+        let outputs: Vec<_> = ctx.completion_stream(req.clone(), None).collect().await;
+        assert!(outputs[0].is_ok());
+        let outputs: Vec<_> = ctx.completion_stream(req, None).collect().await;
+        let err = outputs[0].as_ref().unwrap_err().to_string();
+        assert!(err.contains("cost ceiling exceeded"), "{err}");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_cost_ceiling() {
+        let model = Model::mock_implemented().with_pricing(ModelPricing::new(1.0, 1.0));
+        for (ceiling, ok) in [(None, true), (Some(0.000_001), false)] {
+            let tool = SleepTool {
+                name: "search",
+                delay: Duration::from_millis(1),
+                in_flight: Arc::new(AtomicUsize::new(0)),
+                max_in_flight: Arc::new(AtomicUsize::new(0)),
+            };
+            let req = CompletionRequest {
+                prompt: "{}".to_string(),
+                tools: vec![tool.definition()],
+                ..Default::default()
+            };
+            let mut builder = EngineBuilder::new()
+                .with_model(model.clone())
+                .register_tool(tool)
+                .unwrap();
+            if let Some(ceiling) = ceiling {
+                builder = builder.with_cost_ceiling(ceiling);
+            }
+            let ctx = builder.mock_ctx();
+            let rt = ctx.completion(req, None).await;
+            if ok {
+                // the tool call, then the final answer
+                let output = rt.unwrap();
+                assert!(output.usage.cost > 0.0);
+                assert_eq!(output.usage.cost, ctx.cost.spent());
+            } else {
+                let err = rt.unwrap_err().to_string();
+                assert!(err.contains("cost ceiling exceeded"), "{err}");
+            }
+        }
+    }
+
+    fn cancellable_ctx() -> (AgentCtx, CompletionRequest) {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let mut builder = EngineBuilder::new().with_model(Model::mock_implemented());
+        let mut tools = Vec::new();
+        for (name, delay) in [("fast_tool", 10), ("slow_tool", 5000)] {
+            let tool = SleepTool {
+                name,
+                delay: Duration::from_millis(delay),
+                in_flight: in_flight.clone(),
+                max_in_flight: max_in_flight.clone(),
+            };
+            tools.push(tool.definition());
+            builder = builder.register_tool(tool).unwrap();
+        }
+        let req = CompletionRequest {
+            prompt: "{}".to_string(),
+            tools,
+            ..Default::default()
+        };
+        (builder.mock_ctx(), req)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_completion_cancellation() {
+        // the slow tool is aborted at the deadline, with the usage of the fast tool
+        let (mut ctx, req) = cancellable_ctx();
+        ctx.base.meta.deadline_ms = Some(unix_ms() + 200);
+        let _guard = ctx.base.cancel_on(None);
+        let started = std::time::Instant::now();
+        let output = ctx.completion(req, None).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(output.failed_reason.as_deref(), Some("deadline exceeded"));
+        assert_eq!(output.usage.requests, 10);
+        let calls = output.tool_calls.as_ref().unwrap();
+        assert_eq!(calls[0].result.as_ref().unwrap()["output"], "fast_tool");
+        assert!(calls[1].result.is_none());
+
+        // cancelled by an external token
+        let (ctx, req) = cancellable_ctx();
+        let token = CancellationToken::new();
+        let _guard = ctx.base.cancel_on(Some(token.clone()));
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            token.cancel();
+        });
+        let output = ctx.completion(req.clone(), None).await.unwrap();
+        assert_eq!(output.failed_reason.as_deref(), Some("cancelled"));
+        assert_eq!(output.usage.requests, 10);
+
+        // a cancelled context fails at once
+        let output = ctx.completion(req, None).await.unwrap();
+        assert_eq!(output.failed_reason.as_deref(), Some("cancelled"));
+        assert!(output.tool_calls.is_none());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_thread_memory() {
+        let agent = DocumentSegmenter::new(100, 1000);
+        let name = agent.name();
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .with_memory(Memory::new(60).with_keep_messages(2))
+            .register_agent(agent)
+            .unwrap()
+            .mock_ctx();
+        let thread = Xid::new();
+        let ctx = ctx
+            .child_with(
+                anda_core::ANONYMOUS,
+                &name,
+                RequestMeta {
+                    thread: Some(thread.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let memory = ctx.memory.clone().unwrap();
+
+        let output = ctx
+            .completion(
+                CompletionRequest {
+                    prompt: "My name is Alice.".to_string(),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(output.failed_reason.is_none());
+        let tm = memory.load(&ctx, &thread).await;
+        assert_eq!(tm.messages.len(), 2);
+        assert!(tm.summary.is_none());
+
+        // the older turns are summarized above the budget
+        for i in 0..3 {
+            ctx.completion(
+                CompletionRequest {
+                    prompt: format!("Tell me a long story, part {}.", i),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        let tm = memory.load(&ctx, &thread).await;
+        assert!(tm.summary.as_ref().unwrap().contains("My name is Alice."));
+        assert!(tm.summarized > 0);
+        assert!(tm.messages.len() < 8);
+        assert_eq!(
+            tm.messages.last().unwrap()["content"],
+            "Tell me a long story, part 2."
+        );
+
+        // requests with their own chat history are left as is
+        ctx.completion(
+            CompletionRequest {
+                prompt: "Hi".to_string(),
+                chat_history: vec![json!({"role": "user", "content": "Hello"})],
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(memory.load(&ctx, &thread).await, tm);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_summarize_tool_result() {
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .with_tool_result_summarizer(ToolResultSummarizer::new(50))
+            .mock_ctx();
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "search".to_string(),
+            args: "{}".to_string(),
+            result: None,
+        };
+        let mut usage = Usage::default();
+        let mut resources = Vec::new();
+        let mut truncations = Vec::new();
+
+        let content = ctx
+            .summarize_tool_result(
+                &call,
+                "short".to_string(),
+                &mut usage,
+                &mut resources,
+                &mut truncations,
+            )
+            .await;
+        assert_eq!(content, "short");
+        assert!(resources.is_empty());
+
+        let result = json!({"items": vec!["some search result"; 50]}).to_string();
+        let content = ctx
+            .summarize_tool_result(
+                &call,
+                result.clone(),
+                &mut usage,
+                &mut resources,
+                &mut truncations,
+            )
+            .await;
+        assert!(content.ends_with("the full result is the resource \"search_call_1\"]"));
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].name.as_deref(), Some("search_call_1"));
+        assert_eq!(resources[0].mime_type.as_deref(), Some("application/json"));
+        assert_eq!(
+            resources[0].blob.as_ref().unwrap().as_ref(),
+            result.as_bytes()
+        );
+        assert_eq!(truncations.len(), 1);
+        assert_eq!(truncations[0].target, "tool:search");
+        assert_eq!(truncations[0].strategy, TruncationStrategy::Summarize);
+        assert_eq!(truncations[0].original_size, result.len());
+    }
 
     #[test]
     fn json_in_cbor_works() {
@@ -26,7 +26,7 @@
 use anda_core::{
     ANONYMOUS, BaseContext, BoxError, CacheExpiry, CacheFeatures, CacheStoreFeatures,
     CancellationToken, CanisterCaller, HttpFeatures, KeysFeatures, ObjectMeta, Path, PutMode,
-    PutResult, RequestMeta, StateFeatures, StoreFeatures, ToolInput, ToolOutput, Value,
+    PutResult, RequestMeta, StateFeatures, StoreFeatures, ToolInput, ToolOutput, Value, Xid,
     derivation_path_with,
 };
 use bytes::Bytes;
@@ -38,6 +38,8 @@ use std::{
     sync::Arc,
     time::{Duration, Instant},
 };
+use structured_logger::unix_ms;
+use tokio_util::sync::DropGuard;
 
 const CONTEXT_MAX_DEPTH: u8 = 42;
 const CACHE_MAX_CAPACITY: u64 = 1000000;
@@ -45,6 +47,7 @@ const CACHE_MAX_CAPACITY: u64 = 1000000;
 use super::{
     RemoteEngines,
     cache::CacheService,
+    thread_state::{ThreadState, delete_thread_states},
     web3::{Web3Client, Web3SDK},
 };
 use crate::store::Store;
@@ -188,8 +191,89 @@ impl BaseCtx {
             engine: Some(target),
             thread: self.meta.thread.clone(),
             user: Some(self.name.clone()),
+            priority: self.meta.priority,
+            resource_failure: self.meta.resource_failure,
+            workflow: None,
+            form: None,
+            callback: None,
+            person: None,
+            deadline_ms: self.meta.deadline_ms,
+            lang: self.meta.lang.clone(),
         }
     }
+
+    /// Cancels the context when the deadline of its request is reached, or when the `external`
+    /// token is cancelled. The watch stops when the returned guard is dropped.
+    pub(crate) fn cancel_on(&self, external: Option<CancellationToken>) -> Option<DropGuard> {
+        if self.meta.deadline_ms.is_none() && external.is_none() {
+            return None;
+        }
+
+        let token = self.cancellation_token.clone();
+        let deadline = self
+            .meta
+            .deadline_ms
+            .map(|deadline| Duration::from_millis(deadline.saturating_sub(unix_ms())));
+        let external = external.unwrap_or_default();
+        let guard = CancellationToken::new();
+        let done = guard.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = done.cancelled() => {}
+                _ = external.cancelled() => token.cancel(),
+                _ = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep(deadline).await,
+                        None => futures::future::pending().await,
+                    }
+                } => token.cancel(),
+            }
+        });
+        Some(guard.drop_guard())
+    }
+
+    /// Runs the future until the context is cancelled, failing with the reason then.
+    pub(crate) async fn until_cancelled<T>(
+        &self,
+        fut: impl Future<Output = Result<T, BoxError>>,
+    ) -> Result<T, BoxError> {
+        tokio::select! {
+            biased;
+            _ = self.cancellation_token.cancelled() => {
+                Err(self.cancelled_reason().unwrap_or("cancelled").into())
+            }
+            rt = fut => rt,
+        }
+    }
+
+    /// Returns why the context is cancelled, or None if it is not.
+    pub(crate) fn cancelled_reason(&self) -> Option<&'static str> {
+        if self.meta.is_expired(unix_ms()) {
+            Some("deadline exceeded")
+        } else if self.cancellation_token.is_cancelled() {
+            Some("cancelled")
+        } else {
+            None
+        }
+    }
+
+    /// Returns the private state of the tool in the thread of the request, see [`ThreadState`].
+    ///
+    /// # Errors
+    /// Returns an error if the request has no thread.
+    pub fn thread_state(&self) -> Result<ThreadState, BoxError> {
+        let thread = self
+            .meta
+            .thread
+            .clone()
+            .ok_or("thread state requires a thread in the request")?;
+        Ok(ThreadState::new(self.store.clone(), thread, &self.path))
+    }
+
+    /// Deletes the private states of all tools in the thread.
+    pub(crate) async fn delete_thread_states(&self, thread: &Xid) -> Result<usize, BoxError> {
+        delete_thread_states(&self.store, thread).await
+    }
 }
 
 impl BaseContext for BaseCtx {
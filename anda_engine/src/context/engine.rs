@@ -1,5 +1,5 @@
 use anda_core::{
-    Agent, AgentContext, AgentInput, AgentOutput, BaseContext, BoxError, Function,
+    Agent, AgentContext, AgentInput, AgentOutput, BaseContext, BoxError, ByteArrayB64, Function,
     FunctionDefinition, HttpFeatures, Resource, Tool, ToolInput, ToolOutput, Value,
     select_resources, validate_function_name,
 };
@@ -24,6 +24,52 @@ pub struct Information {
     pub tools: Vec<Function>,
     /// The endpoint of the engine. It can be empty if the engine is local.
     pub endpoint: String,
+    /// Whether the engine is read-only: it serves reads but does not accept agent runs.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// The pricing of an agent, advertised in its [`AgentCard`].
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct AgentPricing {
+    /// The currency or token of the prices, e.g. "USD" or "ICP".
+    pub currency: String,
+
+    /// The price per call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_call: Option<f64>,
+
+    /// The price per 1,000 tokens, prompt and completion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_1k_tokens: Option<f64>,
+}
+
+/// A machine-readable description of an agent, for marketplaces and other ecosystems
+/// to discover and index the agents of an engine.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentCard {
+    /// The name of the agent.
+    pub name: String,
+    /// Description of the agent.
+    pub description: String,
+    /// The principal ID of the engine that serves the agent.
+    pub engine: Principal,
+    /// The name of the engine.
+    pub engine_name: String,
+    /// The RPC endpoint of the engine. It is empty if unknown to the engine,
+    /// and filled in by the server that serves the card.
+    pub endpoint: String,
+    /// The JSON schema of the agent's arguments.
+    pub parameters: Value,
+    /// The tools the agent can use.
+    pub capabilities: Vec<String>,
+    /// The input modalities: "text", and the resource tags the agent supports, e.g. "image".
+    pub input_modalities: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<AgentPricing>,
+    /// The current public keys of the engine by purpose, e.g. "receipt".
+    #[serde(default)]
+    pub public_keys: BTreeMap<String, ByteArrayB64<32>>,
 }
 
 /// Collection of remote engines.
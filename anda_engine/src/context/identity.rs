@@ -0,0 +1,215 @@
+//! Rotation of the engine's signing and encryption keys.
+//!
+//! All keys of the engine are derived from its root key, so a key can be rotated by moving
+//! to a new derivation path. [`KeyManager`] derives the keys of a purpose on the path
+//! `[purpose, epoch]` and keeps the history of epochs in the store:
+//! - on rotation, the previous key stays valid for an overlap period, so that signatures
+//!   and ciphertexts in flight can still be verified and decrypted;
+//! - the new key is re-attested by the [`Attestor`], if any;
+//! - a [`KeyRotation`] signed by both keys is sent to the registered remote engines with
+//!   the `key_rotated` RPC method.
+
+use anda_core::{
+    BoxError, CacheStoreFeatures, HttpFeatures, KeyEpoch, KeyRotation, KeysFeatures, StateFeatures,
+};
+use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+use structured_logger::unix_ms;
+use tokio::sync::RwLock;
+
+use super::BaseCtx;
+
+/// Attests a newly derived public key, e.g. by requesting a TEE attestation report
+/// that binds the key.
+#[async_trait]
+pub trait Attestor: Send + Sync {
+    /// Returns the attestation of the public key.
+    async fn attest(&self, ctx: &BaseCtx, public_key: &[u8; 32]) -> Result<Vec<u8>, BoxError>;
+}
+
+/// Manages the epochs of the engine's keys for one purpose.
+#[derive(Clone)]
+pub struct KeyManager {
+    purpose: String,
+    overlap: Duration,
+    attestor: Option<Arc<dyn Attestor>>,
+    epochs: Arc<RwLock<Vec<KeyEpoch>>>,
+}
+
+impl KeyManager {
+    /// Creates a new key manager.
+    ///
+    /// # Arguments
+    /// * `purpose` - The purpose of the keys, e.g. "receipt";
+    /// * `overlap` - How long the previous key stays valid after a rotation.
+    pub fn new(purpose: String, overlap: Duration) -> Self {
+        Self {
+            purpose,
+            overlap,
+            attestor: None,
+            epochs: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    /// Sets the attestor of new keys.
+    pub fn with_attestor(mut self, attestor: Arc<dyn Attestor>) -> Self {
+        self.attestor = Some(attestor);
+        self
+    }
+
+    /// Returns the purpose of the keys.
+    pub fn purpose(&self) -> &str {
+        &self.purpose
+    }
+
+    fn store_key(&self) -> String {
+        format!("KEYS_{}.cbor", self.purpose)
+    }
+
+    fn derivation_path(&self, epoch: u32) -> [Vec<u8>; 2] {
+        [
+            self.purpose.as_bytes().to_vec(),
+            epoch.to_be_bytes().to_vec(),
+        ]
+    }
+
+    async fn new_epoch(
+        &self,
+        ctx: &BaseCtx,
+        epoch: u32,
+        now_ms: u64,
+    ) -> Result<KeyEpoch, BoxError> {
+        let path = self.derivation_path(epoch);
+        let public_key = ctx.ed25519_public_key(&[&path[0], &path[1]]).await?;
+        let attestation = match &self.attestor {
+            Some(attestor) => Some(attestor.attest(ctx, &public_key).await?.into()),
+            None => None,
+        };
+        Ok(KeyEpoch {
+            epoch,
+            public_key: public_key.into(),
+            not_before: now_ms,
+            not_after: None,
+            attestation,
+        })
+    }
+
+    /// Loads the key epochs from the store, creating the first epoch if there is none.
+    pub async fn init(&self, ctx: &BaseCtx) -> Result<(), BoxError> {
+        let key = self.store_key();
+        let epochs = match ctx.cache_store_get::<Vec<KeyEpoch>>(&key).await {
+            Ok((epochs, _)) if !epochs.is_empty() => epochs,
+            _ => {
+                let epochs = vec![self.new_epoch(ctx, 0, unix_ms()).await?];
+                ctx.cache_store_set(&key, epochs.clone(), None).await?;
+                epochs
+            }
+        };
+        *self.epochs.write().await = epochs;
+        Ok(())
+    }
+
+    /// Returns the key epochs, oldest first.
+    pub async fn epochs(&self) -> Vec<KeyEpoch> {
+        self.epochs.read().await.clone()
+    }
+
+    /// Returns the current key epoch.
+    pub async fn current(&self) -> Result<KeyEpoch, BoxError> {
+        self.epochs
+            .read()
+            .await
+            .last()
+            .cloned()
+            .ok_or_else(|| "key manager is not initialized".into())
+    }
+
+    /// Signs the message with the current key.
+    /// Returns the epoch of the key and the signature.
+    pub async fn sign(
+        &self,
+        ctx: &BaseCtx,
+        message: &[u8],
+    ) -> Result<(KeyEpoch, [u8; 64]), BoxError> {
+        let current = self.current().await?;
+        let path = self.derivation_path(current.epoch);
+        let signature = ctx
+            .ed25519_sign_message(&[&path[0], &path[1]], message)
+            .await?;
+        Ok((current, signature))
+    }
+
+    /// Returns the AES-256-GCM key of the epoch, for data encrypted while it was current.
+    pub async fn a256gcm_key(&self, ctx: &BaseCtx, epoch: u32) -> Result<[u8; 32], BoxError> {
+        let path = self.derivation_path(epoch);
+        ctx.a256gcm_key(&[&path[0], &path[1]]).await
+    }
+
+    /// Returns the epoch of the public key if it is valid at the given time.
+    pub async fn find_valid(&self, public_key: &[u8; 32], now_ms: u64) -> Option<KeyEpoch> {
+        self.epochs
+            .read()
+            .await
+            .iter()
+            .find(|k| *k.public_key == *public_key && k.is_valid_at(now_ms))
+            .cloned()
+    }
+
+    /// Rotates to a new key. The previous key stays valid for the overlap period.
+    /// The rotation is announced to the registered remote engines in the background.
+    pub async fn rotate(&self, ctx: &BaseCtx) -> Result<KeyRotation, BoxError> {
+        let now_ms = unix_ms();
+        let key = self.store_key();
+        let (mut epochs, ver) = ctx.cache_store_get::<Vec<KeyEpoch>>(&key).await?;
+        let mut previous = epochs
+            .last()
+            .cloned()
+            .ok_or("key manager is not initialized")?;
+        let current = self.new_epoch(ctx, previous.epoch + 1, now_ms).await?;
+        previous.not_after = Some(now_ms + self.overlap.as_millis() as u64);
+
+        let mut rotation = KeyRotation {
+            engine: ctx.id(),
+            purpose: self.purpose.clone(),
+            previous: previous.clone(),
+            current: current.clone(),
+            previous_signature: [0u8; 64].into(),
+            current_signature: [0u8; 64].into(),
+        };
+        let digest = rotation.digest();
+        let path = self.derivation_path(previous.epoch);
+        rotation.previous_signature = ctx
+            .ed25519_sign_message(&[&path[0], &path[1]], &digest)
+            .await?
+            .into();
+        let path = self.derivation_path(current.epoch);
+        rotation.current_signature = ctx
+            .ed25519_sign_message(&[&path[0], &path[1]], &digest)
+            .await?
+            .into();
+
+        // drop the keys that are no longer valid
+        epochs.retain(|k| k.not_after.is_none_or(|t| now_ms < t));
+        if let Some(last) = epochs.last_mut() {
+            *last = previous;
+        }
+        epochs.push(current);
+        ctx.cache_store_set(&key, epochs.clone(), Some(ver)).await?;
+        *self.epochs.write().await = epochs;
+        log::warn!(purpose = self.purpose, epoch = rotation.current.epoch; "key rotated");
+
+        let ctx = ctx.clone();
+        let announcement = rotation.clone();
+        tokio::spawn(async move {
+            for info in ctx.remote.engines.values() {
+                if let Err(err) = ctx
+                    .https_signed_rpc::<()>(&info.endpoint, "key_rotated", &(&announcement,))
+                    .await
+                {
+                    log::error!(endpoint = info.endpoint, error = err.to_string(); "failed to announce key rotation");
+                }
+            }
+        });
+        Ok(rotation)
+    }
+}
@@ -0,0 +1,371 @@
+use anda_core::{
+    BoxError, CompletionRequest, Resource, ResourceState, ResourceStatus, SizeLimit, ToolCall,
+    Truncation, TruncationStrategy, Usage, truncate_text,
+};
+
+use std::sync::Mutex;
+
+use super::AgentCtx;
+use crate::model::Model;
+
+/// Summarizes the tool results above a token threshold before they are passed back to the
+/// model, with a cheaper model than the agent's. The full result is not lost: it is kept as
+/// a resource of the output, which the next tool calls of the completion can also use.
+#[derive(Clone)]
+pub struct ToolResultSummarizer {
+    /// Results with more tokens are summarized.
+    pub max_tokens: usize,
+
+    /// The model that summarizes the results, the agent's model if None.
+    pub model: Option<Model>,
+}
+
+impl ToolResultSummarizer {
+    /// Creates a summarizer of the tool results above `max_tokens`.
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            model: None,
+        }
+    }
+
+    /// Sets the model that summarizes the results.
+    pub fn with_model(mut self, model: Model) -> Self {
+        self.model = Some(model);
+        self
+    }
+}
+
+/// The estimated cost of the model calls of a run, shared with its nested agent runs and
+/// checked against the cost ceiling of the engine before each call.
+#[derive(Debug, Default)]
+pub(crate) struct CostMeter {
+    ceiling: Option<f64>,
+    spent: Mutex<f64>,
+}
+
+impl CostMeter {
+    pub(crate) fn new(ceiling: Option<f64>) -> Self {
+        Self {
+            ceiling,
+            spent: Mutex::new(0.0),
+        }
+    }
+
+    pub(crate) fn ceiling(&self) -> Option<f64> {
+        self.ceiling
+    }
+
+    /// Returns the cost spent so far in USD.
+    pub(crate) fn spent(&self) -> f64 {
+        *self.spent.lock().expect("cost lock poisoned")
+    }
+
+    /// Records the cost of a call.
+    pub(crate) fn add(&self, cost: f64) {
+        *self.spent.lock().expect("cost lock poisoned") += cost;
+    }
+
+    /// Returns an error if the run has reached the cost ceiling.
+    pub(crate) fn check(&self) -> Result<(), BoxError> {
+        let spent = self.spent();
+        match self.ceiling {
+            Some(ceiling) if spent >= ceiling => Err(format!(
+                "cost ceiling exceeded: {:.4} USD spent, the ceiling is {:.4} USD",
+                spent, ceiling
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// MIME types of resources that can be truncated as text.
+fn is_text_mime(mime_type: Option<&str>) -> bool {
+    match mime_type {
+        Some(mime) => {
+            mime.starts_with("text/")
+                || mime.ends_with("json")
+                || mime.ends_with("xml")
+                || mime.ends_with("yaml")
+                || mime == "application/toml"
+        }
+        None => false,
+    }
+}
+
+impl AgentCtx {
+    /// Fits the text into the size limit with the limit's strategy.
+    /// Returns the text unchanged and no truncation record if it fits.
+    pub(crate) async fn fit_text(
+        &self,
+        target: String,
+        text: String,
+        limit: &SizeLimit,
+    ) -> Result<(String, Option<Truncation>), BoxError> {
+        if text.len() <= limit.max_bytes {
+            return Ok((text, None));
+        }
+
+        let original_size = text.len();
+        let output = match limit.strategy {
+            TruncationStrategy::Reject => {
+                return Err(format!(
+                    "{} exceeds the size limit: {} > {} bytes",
+                    target, original_size, limit.max_bytes
+                )
+                .into());
+            }
+            TruncationStrategy::Summarize => match self.summarize(&text, limit.max_bytes).await {
+                Ok(summary) if summary.len() <= limit.max_bytes => summary,
+                Ok(summary) => truncate_text(&summary, limit.max_bytes, TruncationStrategy::Head)
+                    .unwrap_or(summary),
+                Err(err) => {
+                    log::warn!(target = target; "failed to summarize, truncating instead: {}", err);
+                    truncate_text(&text, limit.max_bytes, TruncationStrategy::Head)
+                        .unwrap_or_default()
+                }
+            },
+            strategy => truncate_text(&text, limit.max_bytes, strategy).unwrap_or_default(),
+        };
+
+        let truncation = Truncation {
+            target,
+            strategy: limit.strategy,
+            original_size,
+            size: output.len(),
+        };
+        Ok((output, Some(truncation)))
+    }
+
+    /// Fits a tool or agent result into the tool result limit.
+    pub(crate) async fn fit_tool_result(
+        &self,
+        name: &str,
+        content: String,
+        truncations: &mut Vec<Truncation>,
+    ) -> Result<String, BoxError> {
+        match &self.limits.tool_result {
+            Some(limit) => {
+                let (content, truncation) = self
+                    .fit_text(format!("tool:{}", name), content, limit)
+                    .await?;
+                truncations.extend(truncation);
+                Ok(content)
+            }
+            None => Ok(content),
+        }
+    }
+
+    /// Summarizes a tool or agent result above the token threshold of the
+    /// [`ToolResultSummarizer`], and keeps the full result as a resource.
+    /// The result is returned as is if it fits, or if the summarization fails.
+    pub(crate) async fn summarize_tool_result(
+        &self,
+        call: &ToolCall,
+        content: String,
+        usage: &mut Usage,
+        resources: &mut Vec<Resource>,
+        truncations: &mut Vec<Truncation>,
+    ) -> String {
+        let Some(summarizer) = &self.tool_result_summarizer else {
+            return content;
+        };
+        let model = summarizer.model.as_ref().unwrap_or(&self.model);
+        let tokens = model.count_tokens(&content);
+        if tokens <= summarizer.max_tokens {
+            return content;
+        }
+
+        let mime_type = if serde_json::from_str::<serde_json::Value>(&content).is_ok() {
+            "application/json"
+        } else {
+            "text/plain"
+        };
+        let mut resource = match Resource::from_bytes(Some(mime_type), content.as_bytes().to_vec())
+        {
+            Ok(resource) => resource,
+            Err(err) => {
+                log::warn!(tool = call.name; "failed to keep the full tool result: {}", err);
+                return content;
+            }
+        };
+        let name = format!("{}_{}", call.name, call.id);
+        resource.name = Some(name.clone());
+        resource.description = Some(format!("The full result of the {} call", call.name));
+
+        let req = CompletionRequest {
+            system: Some(format!(
+                "Summarize the result of the {} tool, called with the arguments {}, for the \
+                assistant that called it. Keep the key facts, names, numbers, identifiers and \
+                errors relevant to the call. Output only the summary.",
+                call.name, call.args
+            )),
+            prompt: content,
+            max_tokens: Some(summarizer.max_tokens),
+            ..Default::default()
+        };
+        let original_size = req.prompt.len();
+        let output = match model.completion(req.clone()).await {
+            Ok(output) if output.failed_reason.is_none() => output,
+            Ok(output) => {
+                log::warn!(tool = call.name; "failed to summarize the tool result: {}", output.failed_reason.unwrap_or_default());
+                return req.prompt;
+            }
+            Err(err) => {
+                log::warn!(tool = call.name; "failed to summarize the tool result: {}", err);
+                return req.prompt;
+            }
+        };
+        usage.accumulate(&output.usage);
+        let content = format!(
+            "{}\n\n[Summary of a result of {} tokens, the full result is the resource {:?}]",
+            output.content, tokens, name
+        );
+        truncations.push(Truncation {
+            target: format!("tool:{}", call.name),
+            strategy: TruncationStrategy::Summarize,
+            original_size,
+            size: content.len(),
+        });
+        resources.push(resource);
+        content
+    }
+
+    async fn summarize(&self, text: &str, max_bytes: usize) -> Result<String, BoxError> {
+        let req = CompletionRequest {
+            system: Some(format!(
+                "Summarize the user's content, keeping the key facts, names, numbers and identifiers. \
+                The summary must be shorter than {} bytes. Output only the summary.",
+                max_bytes
+            )),
+            prompt: text.to_string(),
+            // roughly 3 bytes per token for mixed content
+            max_tokens: Some(max_bytes / 3 + 1),
+            ..Default::default()
+        };
+        let output = self.model.completion(req).await?;
+        if let Some(reason) = output.failed_reason {
+            return Err(reason.into());
+        }
+        Ok(output.content)
+    }
+
+    /// Fits the inline blobs of the loaded resources into the resource limit.
+    /// Text resources are truncated, other resources above the limit are marked as failed
+    /// in the statuses and removed.
+    pub(crate) async fn fit_resources(
+        &self,
+        loaded: Vec<Resource>,
+        statuses: &mut [ResourceStatus],
+        truncations: &mut Vec<Truncation>,
+    ) -> Vec<Resource> {
+        let limit = match &self.limits.resource_inline {
+            Some(limit) => *limit,
+            None => return loaded,
+        };
+
+        let mut output = Vec::with_capacity(loaded.len());
+        let statuses = statuses
+            .iter_mut()
+            .filter(|s| s.state == ResourceState::Loaded);
+        for (mut resource, status) in loaded.into_iter().zip(statuses) {
+            let size = resource.blob.as_ref().map(|b| b.len()).unwrap_or(0);
+            if size <= limit.max_bytes {
+                output.push(resource);
+                continue;
+            }
+
+            let text = if is_text_mime(resource.mime_type.as_deref()) {
+                resource
+                    .blob
+                    .as_ref()
+                    .and_then(|b| String::from_utf8(b.to_vec()).ok())
+            } else {
+                None
+            };
+            let rt = match text {
+                Some(text) => {
+                    self.fit_text(format!("resource:{}", status.label()), text, &limit)
+                        .await
+                }
+                None => Err(format!(
+                    "resource exceeds the inline size limit: {} > {} bytes",
+                    size, limit.max_bytes
+                )
+                .into()),
+            };
+            match rt {
+                Ok((text, truncation)) => {
+                    let blob = text.into_bytes();
+                    resource.size = Some(blob.len());
+                    resource.hash = None;
+                    resource.blob = Some(blob.into());
+                    truncations.extend(truncation);
+                    output.push(resource);
+                }
+                Err(err) => {
+                    status.state = ResourceState::Failed;
+                    status.error = Some(err.to_string());
+                }
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineBuilder;
+    use anda_core::SizeLimits;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fit_resources() {
+        let ctx = EngineBuilder::new()
+            .with_size_limits(SizeLimits {
+                resource_inline: Some(SizeLimit::new(64, TruncationStrategy::Tail)),
+                ..Default::default()
+            })
+            .mock_ctx();
+        let resources = vec![
+            Resource {
+                tag: "text".to_string(),
+                name: Some("small.txt".to_string()),
+                mime_type: Some("text/plain".to_string()),
+                blob: Some(b"hello".to_vec().into()),
+                ..Default::default()
+            },
+            Resource {
+                tag: "text".to_string(),
+                name: Some("app.log".to_string()),
+                mime_type: Some("text/plain".to_string()),
+                blob: Some("line\n".repeat(100).into_bytes().into()),
+                ..Default::default()
+            },
+            Resource {
+                tag: "image".to_string(),
+                name: Some("a.png".to_string()),
+                mime_type: Some("image/png".to_string()),
+                blob: Some(vec![0u8; 100].into()),
+                ..Default::default()
+            },
+        ];
+        let mut statuses: Vec<ResourceStatus> = resources
+            .iter()
+            .map(|r| ResourceStatus::new(r, ResourceState::Loaded, None))
+            .collect();
+        let mut truncations = Vec::new();
+        let loaded = ctx
+            .fit_resources(resources, &mut statuses, &mut truncations)
+            .await;
+
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded[1].blob.as_ref().unwrap().len() <= 64);
+        assert!(loaded[1].blob.as_ref().unwrap().ends_with(b"line\n"));
+        assert_eq!(statuses[2].state, ResourceState::Failed);
+        assert_eq!(truncations.len(), 1);
+        assert_eq!(truncations[0].target, "resource:app.log");
+        assert_eq!(truncations[0].original_size, 500);
+    }
+}
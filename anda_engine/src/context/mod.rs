@@ -6,12 +6,30 @@ mod agent;
 mod base;
 mod cache;
 mod engine;
+mod identity;
+mod limits;
+mod receipt;
+mod resource;
+mod scheduler;
+mod slot;
+mod thread_state;
+mod vetkd;
 mod web3;
+mod workflow;
 
 pub use agent::*;
 pub use base::*;
 pub use engine::*;
+pub use identity::*;
+pub use limits::*;
+pub use receipt::*;
+pub use resource::*;
+pub use scheduler::*;
+pub use slot::*;
+pub use thread_state::*;
+pub use vetkd::*;
 pub use web3::*;
+pub use workflow::*;
 
 /// Mock implementations for testing purposes.
 ///
@@ -167,4 +185,79 @@ mod tests {
         assert_eq!(res.method, "canister_update");
         assert_eq!(res.args, empty_args);
     }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_kv() {
+        use anda_core::KvFeatures;
+        use serde::Serialize;
+
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        struct Profile {
+            name: String,
+            visits: u64,
+        }
+
+        let ctx = crate::engine::EngineBuilder::new().mock_ctx();
+        let profiles = ctx.kv::<Profile>("profiles");
+        assert!(profiles.get("alice").await.unwrap().is_none());
+
+        let alice = Profile {
+            name: "Alice".to_string(),
+            visits: 1,
+        };
+        let v1 = profiles
+            .compare_and_swap("alice", None, &alice)
+            .await
+            .unwrap();
+        // already exists
+        assert!(
+            profiles
+                .compare_and_swap("alice", None, &alice)
+                .await
+                .is_err()
+        );
+        let (val, ver) = profiles.get("alice").await.unwrap().unwrap();
+        assert_eq!(val, alice);
+        assert_eq!(ver, v1);
+
+        let (val, v2) = profiles
+            .update("alice", 3, |p| {
+                let mut p = p.unwrap();
+                p.visits += 1;
+                p
+            })
+            .await
+            .unwrap();
+        assert_eq!(val.visits, 2);
+        let err = profiles
+            .try_update("alice", 3, |_| Err("rejected".into()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "rejected");
+        assert_eq!(profiles.get("alice").await.unwrap().unwrap().1, v2);
+        // stale version
+        assert!(
+            profiles
+                .compare_and_swap("alice", Some(v1), &alice)
+                .await
+                .is_err()
+        );
+        profiles
+            .compare_and_swap("alice", Some(v2), &alice)
+            .await
+            .unwrap();
+
+        // namespaces are isolated
+        let counters = ctx.kv::<u64>("counters");
+        assert!(counters.get("alice").await.unwrap().is_none());
+        counters.put("alice", &7).await.unwrap();
+        assert_eq!(counters.get("ALICE").await.unwrap().unwrap().0, 7);
+        // decoding a value of another type fails
+        assert!(ctx.kv::<String>("counters").get("alice").await.is_err());
+
+        profiles.delete("alice").await.unwrap();
+        profiles.delete("alice").await.unwrap();
+        assert!(profiles.get("alice").await.unwrap().is_none());
+        assert!(profiles.get("a/b").await.is_err());
+    }
 }
@@ -0,0 +1,214 @@
+//! Signed receipts of agent runs.
+//!
+//! When enabled, the engine issues a [`SignedReceipt`] for every run: the hashes of the
+//! prompt, the output and each tool call, the identifier of the model that served the run
+//! and the TEE measurement, signed with the engine's Ed25519 key. The receipt is returned in
+//! [`anda_core::AgentOutput::receipt`] and can be appended to an on-chain log canister,
+//! so third parties can verify what the agent did without seeing the conversation.
+//!
+//! The log canister must implement:
+//! ```candid
+//! append_receipt : (SignedReceipt) -> (variant { Ok : nat64; Err : text });
+//! ```
+
+use anda_core::{
+    AgentOutput, BoxError, ByteBufB64, CanisterCaller, KeysFeatures, Receipt, SignedReceipt,
+    ToolCallDigest,
+};
+use candid::Principal;
+use ic_cose_types::cose::sha3_256;
+use structured_logger::unix_ms;
+
+use super::{BaseCtx, KeyManager};
+
+/// The derivation path of the receipt signing key.
+static RECEIPT_KEY_PATH: &[u8] = b"anda_receipt";
+
+/// Issues signed receipts of agent runs.
+#[derive(Clone)]
+pub struct ReceiptIssuer {
+    model: String,
+    tee_measurement: Option<ByteBufB64>,
+    log_canister: Option<Principal>,
+    keys: Option<KeyManager>,
+}
+
+impl ReceiptIssuer {
+    /// Creates a new issuer, with the identifier of the model used by the engine.
+    /// Receipts name the model reported in the output of the run, see
+    /// [`AgentOutput::model`], and this one if the output does not report it.
+    pub fn new(model: String) -> Self {
+        Self {
+            model,
+            tee_measurement: None,
+            log_canister: None,
+            keys: None,
+        }
+    }
+
+    /// Sets the measurement of the TEE the engine runs in.
+    pub fn with_tee_measurement(mut self, measurement: Vec<u8>) -> Self {
+        self.tee_measurement = Some(measurement.into());
+        self
+    }
+
+    /// Appends every receipt to the log canister.
+    pub fn with_log_canister(mut self, canister: Principal) -> Self {
+        self.log_canister = Some(canister);
+        self
+    }
+
+    /// Signs the receipts with the rotating keys of the key manager,
+    /// instead of the static receipt key.
+    pub fn with_key_manager(mut self, keys: KeyManager) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    pub(crate) fn key_manager(&self) -> Option<&KeyManager> {
+        self.keys.as_ref()
+    }
+
+    /// Returns the public key that currently signs the receipts.
+    pub async fn public_key(&self, ctx: &BaseCtx) -> Result<[u8; 32], BoxError> {
+        match &self.keys {
+            Some(keys) => Ok(*keys.current().await?.public_key),
+            None => ctx.ed25519_public_key(&[RECEIPT_KEY_PATH]).await,
+        }
+    }
+
+    /// Issues a signed receipt of the run.
+    pub async fn issue(
+        &self,
+        ctx: &BaseCtx,
+        agent: &str,
+        caller: Principal,
+        prompt: &str,
+        output: &AgentOutput,
+    ) -> Result<SignedReceipt, BoxError> {
+        let receipt = self.receipt(ctx.id, agent, caller, prompt, output);
+        let (public_key, signature) = match &self.keys {
+            Some(keys) => {
+                let (epoch, signature) = keys.sign(ctx, &receipt.digest()).await?;
+                (*epoch.public_key, signature)
+            }
+            None => (
+                ctx.ed25519_public_key(&[RECEIPT_KEY_PATH]).await?,
+                ctx.ed25519_sign_message(&[RECEIPT_KEY_PATH], &receipt.digest())
+                    .await?,
+            ),
+        };
+        Ok(SignedReceipt {
+            receipt,
+            public_key: public_key.into(),
+            signature: signature.into(),
+        })
+    }
+
+    fn receipt(
+        &self,
+        engine: Principal,
+        agent: &str,
+        caller: Principal,
+        prompt: &str,
+        output: &AgentOutput,
+    ) -> Receipt {
+        Receipt {
+            engine,
+            agent: agent.to_string(),
+            caller,
+            thread: output.thread.as_ref().map(|t| t.to_string()),
+            model: output.model.clone().unwrap_or_else(|| self.model.clone()),
+            input_hash: sha3_256(prompt.as_bytes()).into(),
+            output_hash: sha3_256(output.content.as_bytes()).into(),
+            tool_calls: output
+                .tool_calls
+                .iter()
+                .flatten()
+                .map(ToolCallDigest::from)
+                .collect(),
+            tee_measurement: self.tee_measurement.clone(),
+            timestamp: unix_ms(),
+        }
+    }
+
+    /// Appends the receipt to the log canister, if configured.
+    /// Returns the index of the receipt in the log.
+    pub async fn append(
+        &self,
+        ctx: &BaseCtx,
+        receipt: &SignedReceipt,
+    ) -> Result<Option<u64>, BoxError> {
+        match &self.log_canister {
+            Some(canister) => {
+                let res: Result<u64, String> = ctx
+                    .canister_update(canister, "append_receipt", (receipt,))
+                    .await?;
+                Ok(Some(res?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::ToolCall;
+    use ed25519_consensus::SigningKey;
+    use serde_json::json;
+
+    fn sign(key: &SigningKey, receipt: Receipt) -> SignedReceipt {
+        SignedReceipt {
+            public_key: key.verification_key().to_bytes().into(),
+            signature: key.sign(&receipt.digest()).to_bytes().into(),
+            receipt,
+        }
+    }
+
+    #[test]
+    fn test_receipt() {
+        let issuer =
+            ReceiptIssuer::new("deepseek-chat".to_string()).with_tee_measurement(vec![1, 2, 3]);
+        let engine = Principal::from_slice(&[1]);
+        let caller = Principal::from_slice(&[2]);
+        let mut output = AgentOutput {
+            content: "done".to_string(),
+            tool_calls: Some(vec![ToolCall {
+                id: "1".to_string(),
+                name: "transfer".to_string(),
+                args: r#"{"amount":1}"#.to_string(),
+                result: Some(json!("ok")),
+            }]),
+            model: Some("gpt-4o-2024-08-06".to_string()),
+            ..Default::default()
+        };
+
+        let receipt = issuer.receipt(engine, "assistant", caller, "pay alice", &output);
+        assert_eq!(receipt.model, "gpt-4o-2024-08-06");
+        assert_eq!(*receipt.input_hash, sha3_256(b"pay alice"));
+        assert_eq!(*receipt.output_hash, sha3_256(b"done"));
+        assert_eq!(receipt.tool_calls.len(), 1);
+        assert_eq!(receipt.tee_measurement, Some(vec![1, 2, 3].into()));
+        output.model = None;
+        let receipt = issuer.receipt(engine, "assistant", caller, "pay alice", &output);
+        assert_eq!(receipt.model, "deepseek-chat");
+
+        let key = SigningKey::from([7u8; 32]);
+        let signed = sign(&key, receipt);
+        signed.verify().unwrap();
+
+        let mut tampered = signed.clone();
+        tampered.receipt.output_hash = sha3_256(b"not done").into();
+        assert!(tampered.verify().is_err());
+        let mut tampered = signed.clone();
+        tampered.receipt.model = "gpt-4o-2024-08-06".to_string();
+        assert!(tampered.verify().is_err());
+        let mut tampered = signed.clone();
+        tampered.public_key = SigningKey::from([8u8; 32])
+            .verification_key()
+            .to_bytes()
+            .into();
+        assert!(tampered.verify().is_err());
+    }
+}
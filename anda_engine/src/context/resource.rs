@@ -0,0 +1,203 @@
+//! Loading of resources attached to agent requests.
+//!
+//! Before an agent runs, the engine passes each attached resource through a
+//! [`ResourceLoader`], which may fetch, validate or transform it. Resources that fail
+//! are handled by the request's [`ResourceFailurePolicy`], and the statuses of all
+//! resources are reported in [`anda_core::AgentOutput::resource_statuses`].
+
+use anda_core::{BoxError, Resource, ResourceState, ResourceStatus};
+use async_trait::async_trait;
+use ic_cose_types::cose::sha3_256;
+
+use super::BaseCtx;
+
+/// Loads or transforms a resource attached to a request before it is passed to the agent.
+#[async_trait]
+pub trait ResourceLoader: Send + Sync {
+    /// Returns the loaded resource, or an error if it can not be used.
+    async fn load(&self, ctx: &BaseCtx, resource: Resource) -> Result<Resource, BoxError>;
+}
+
+/// The default [`ResourceLoader`], which checks inline blobs against their declared
+/// size and SHA3-256 hash and passes resources through unchanged.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceValidator;
+
+#[async_trait]
+impl ResourceLoader for ResourceValidator {
+    async fn load(&self, _ctx: &BaseCtx, resource: Resource) -> Result<Resource, BoxError> {
+        if let Some(blob) = &resource.blob {
+            if let Some(size) = resource.size
+                && size != blob.len()
+            {
+                return Err(format!("size mismatch, expected {}, got {}", size, blob.len()).into());
+            }
+            if let Some(hash) = &resource.hash
+                && **hash != sha3_256(blob)
+            {
+                return Err("hash mismatch".into());
+            }
+        } else if resource.uri.is_none() {
+            return Err("resource has neither blob nor uri".into());
+        }
+        Ok(resource)
+    }
+}
+
+/// Loads resources concurrently with the loader, returning the loaded resources
+/// and the statuses of all resources in input order.
+pub async fn load_resources(
+    loader: &dyn ResourceLoader,
+    ctx: &BaseCtx,
+    resources: Vec<Resource>,
+) -> (Vec<Resource>, Vec<ResourceStatus>) {
+    let mut statuses: Vec<ResourceStatus> = resources
+        .iter()
+        .map(|r| ResourceStatus::new(r, ResourceState::Loaded, None))
+        .collect();
+    let rts = futures::future::join_all(resources.into_iter().map(|r| loader.load(ctx, r))).await;
+
+    let mut loaded = Vec::with_capacity(rts.len());
+    for (rt, status) in rts.into_iter().zip(statuses.iter_mut()) {
+        match rt {
+            Ok(r) => loaded.push(r),
+            Err(err) => {
+                status.state = ResourceState::Failed;
+                status.error = Some(err.to_string());
+            }
+        }
+    }
+    (loaded, statuses)
+}
+
+/// Formats the failed resources as a list, one per line.
+pub fn failed_resources_list(statuses: &[ResourceStatus]) -> String {
+    statuses
+        .iter()
+        .filter(|s| s.state != ResourceState::Loaded)
+        .map(|s| {
+            format!(
+                "- {}: {}",
+                s.label(),
+                s.error.as_deref().unwrap_or("unknown error")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineBuilder;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_load_resources() {
+        let ctx = EngineBuilder::new().mock_ctx();
+        let blob = b"hello".to_vec();
+        let resources = vec![
+            Resource {
+                tag: "text".to_string(),
+                name: Some("ok.txt".to_string()),
+                size: Some(5),
+                hash: Some(sha3_256(&blob).into()),
+                blob: Some(blob.clone().into()),
+                ..Default::default()
+            },
+            Resource {
+                tag: "text".to_string(),
+                name: Some("bad.txt".to_string()),
+                size: Some(4),
+                blob: Some(blob.clone().into()),
+                ..Default::default()
+            },
+            Resource {
+                tag: "image".to_string(),
+                uri: Some("https://example.com/a.png".to_string()),
+                ..Default::default()
+            },
+            Resource {
+                tag: "empty".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        let (loaded, statuses) = load_resources(&ResourceValidator, &ctx.base, resources).await;
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].tag, "image");
+        assert_eq!(
+            statuses.iter().map(|s| s.state).collect::<Vec<_>>(),
+            vec![
+                ResourceState::Loaded,
+                ResourceState::Failed,
+                ResourceState::Loaded,
+                ResourceState::Failed
+            ]
+        );
+        assert_eq!(
+            failed_resources_list(&statuses),
+            "- bad.txt: size mismatch, expected 4, got 5\n- empty: resource has neither blob nor uri"
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_store_resource() {
+        use anda_core::{ResourceStoreFeatures, STORE_URI_SCHEME, StoreFeatures};
+
+        let ctx = EngineBuilder::new().mock_ctx();
+        let resource = Resource::from_bytes(None, b"hello".to_vec()).unwrap();
+        let stored = ctx.base.store_resource(resource.clone()).await.unwrap();
+        assert!(stored.blob.is_none());
+        assert!(stored.uri.as_ref().unwrap().starts_with(STORE_URI_SCHEME));
+        assert_eq!(stored.size, Some(5));
+
+        let loaded = ctx.base.load_resource(stored.clone()).await.unwrap();
+        assert_eq!(loaded.blob, resource.blob);
+        assert_eq!(loaded.hash, resource.hash);
+
+        let mut tampered = stored.clone();
+        tampered.hash = Some(sha3_256(b"other").into());
+        assert!(ctx.base.load_resource(tampered).await.is_err());
+
+        // identical blobs are stored once and reference counted
+        let mut other = resource.clone();
+        other.name = Some("copy.txt".to_string());
+        let copy = ctx.base.store_resource(other).await.unwrap();
+        assert_eq!(copy.uri, stored.uri);
+        let path = object_store::path::Path::from(
+            stored
+                .uri
+                .as_deref()
+                .unwrap()
+                .strip_prefix(STORE_URI_SCHEME)
+                .unwrap(),
+        );
+        assert_eq!(ctx.base.release_resource(&copy).await.unwrap(), 1);
+        assert!(ctx.base.load_resource(stored.clone()).await.is_ok());
+
+        // the content is verified against its address
+        ctx.base
+            .store_put(
+                &path,
+                anda_core::PutMode::Overwrite,
+                b"hellO".to_vec().into(),
+            )
+            .await
+            .unwrap();
+        let mut unhashed = stored.clone();
+        unhashed.hash = None;
+        let err = ctx.base.load_resource(unhashed).await.unwrap_err();
+        assert!(err.to_string().starts_with("integrity check failed"));
+
+        assert_eq!(ctx.base.release_resource(&stored).await.unwrap(), 0);
+        assert!(ctx.base.store_get(&path).await.is_err());
+        assert!(ctx.base.release_resource(&stored).await.is_err());
+        assert!(ctx.base.release_resource(&resource).await.is_err());
+
+        // a released blob is uploaded again
+        let stored = ctx.base.store_resource(resource.clone()).await.unwrap();
+        let loaded = ctx.base.load_resource(stored.clone()).await.unwrap();
+        assert_eq!(loaded.blob, resource.blob);
+        assert_eq!(ctx.base.release_resource(&stored).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,261 @@
+//! Priority-aware admission control for agent runs and tool calls.
+//!
+//! The [`Scheduler`] limits how many requests execute concurrently in the engine.
+//! When the limit is reached, requests wait in two lanes:
+//! - [`RequestPriority::Interactive`] requests are always admitted first;
+//! - [`RequestPriority::Batch`] requests are admitted only when no interactive request is waiting.
+//!
+//! Running batch requests call [`SchedulerPermit::yield_now`] between tool calls, giving up
+//! their slot to waiting interactive requests and re-entering the batch lane afterwards.
+
+use anda_core::RequestPriority;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Priority-aware concurrency limiter.
+pub struct Scheduler {
+    max_concurrency: usize,
+    state: Mutex<SchedulerState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    running: usize,
+    waiting_interactive: usize,
+}
+
+/// An admitted execution slot. The slot is released when the permit is dropped.
+pub struct SchedulerPermit {
+    scheduler: Arc<Scheduler>,
+    priority: RequestPriority,
+    /// Whether the slot is held, false while the permit yields.
+    held: bool,
+}
+
+impl SchedulerPermit {
+    /// Gives up the execution slot of a running batch request if interactive requests are waiting,
+    /// then waits to be admitted again. Returns true if the request yielded.
+    ///
+    /// It is a no-op for interactive requests. If the future is dropped while waiting,
+    /// the permit no longer holds a slot.
+    pub async fn yield_now(&mut self) -> bool {
+        if self.priority.is_interactive() || !self.held {
+            return false;
+        }
+
+        {
+            let mut state = self.scheduler.state.lock().unwrap();
+            if state.waiting_interactive == 0 {
+                return false;
+            }
+            state.running -= 1;
+            self.held = false;
+        }
+        self.scheduler.notify.notify_waiters();
+        self.scheduler.enter(self.priority).await;
+        self.held = true;
+        true
+    }
+}
+
+impl Drop for SchedulerPermit {
+    fn drop(&mut self) {
+        if self.held {
+            self.scheduler.leave();
+        }
+    }
+}
+
+/// Unregisters an interactive waiter if the waiting future is dropped before admission.
+struct WaitingGuard<'a> {
+    scheduler: &'a Scheduler,
+    registered: bool,
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        if self.registered {
+            let mut state = self.scheduler.state.lock().unwrap();
+            state.waiting_interactive -= 1;
+            drop(state);
+            // batch waiters may proceed now
+            self.scheduler.notify.notify_waiters();
+        }
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(usize::MAX)
+    }
+}
+
+impl Scheduler {
+    /// Creates a new scheduler that admits at most `max_concurrency` requests at a time.
+    pub fn new(max_concurrency: usize) -> Self {
+        Self {
+            max_concurrency: max_concurrency.max(1),
+            state: Mutex::new(SchedulerState::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Returns the maximum number of concurrently running requests.
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    /// Returns the number of running requests and the number of waiting interactive requests.
+    pub fn stats(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.running, state.waiting_interactive)
+    }
+
+    /// Waits for an execution slot in the lane of the given priority.
+    pub async fn acquire(self: &Arc<Self>, priority: RequestPriority) -> SchedulerPermit {
+        self.enter(priority).await;
+        SchedulerPermit {
+            scheduler: self.clone(),
+            priority,
+            held: true,
+        }
+    }
+
+    async fn enter(&self, priority: RequestPriority) {
+        let interactive = priority.is_interactive();
+        let mut guard = WaitingGuard {
+            scheduler: self,
+            registered: false,
+        };
+
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.running < self.max_concurrency
+                    && (interactive || state.waiting_interactive == 0)
+                {
+                    state.running += 1;
+                    if guard.registered {
+                        state.waiting_interactive -= 1;
+                        guard.registered = false;
+                    }
+                    return;
+                }
+
+                if interactive && !guard.registered {
+                    state.waiting_interactive += 1;
+                    guard.registered = true;
+                }
+            }
+
+            notified.await;
+        }
+    }
+
+    fn leave(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.running -= 1;
+        }
+        self.notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_scheduler() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let mut batch = scheduler.acquire(RequestPriority::Batch).await;
+        assert_eq!(scheduler.stats(), (1, 0));
+
+        {
+            let scheduler = scheduler.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire(RequestPriority::Batch).await;
+                tx.send("batch").unwrap();
+            });
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        {
+            let scheduler = scheduler.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire(RequestPriority::Interactive).await;
+                tx.send("interactive").unwrap();
+            });
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(scheduler.stats(), (1, 1));
+
+        // the running batch request yields to the waiting interactive request,
+        // and then waits behind it
+        assert!(batch.yield_now().await);
+        assert_eq!(rx.recv().await, Some("interactive"));
+        assert_eq!(scheduler.stats(), (1, 0));
+        assert!(!batch.yield_now().await);
+
+        drop(batch);
+        assert_eq!(rx.recv().await, Some("batch"));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_scheduler_cancel_waiting() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        let permit = scheduler.acquire(RequestPriority::Batch).await;
+        let res = tokio::time::timeout(
+            Duration::from_millis(10),
+            scheduler.acquire(RequestPriority::Interactive),
+        )
+        .await;
+        assert!(res.is_err());
+        assert_eq!(scheduler.stats(), (1, 0));
+        drop(permit);
+        let _permit = scheduler.acquire(RequestPriority::Batch).await;
+        assert_eq!(scheduler.stats(), (1, 0));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_scheduler_cancel_yielded() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        // interactive requests never yield
+        let mut interactive = scheduler.acquire(RequestPriority::Interactive).await;
+        assert!(!interactive.yield_now().await);
+        drop(interactive);
+        let mut batch = scheduler.acquire(RequestPriority::Batch).await;
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire(RequestPriority::Interactive).await;
+                let _ = rx.await;
+            });
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(scheduler.stats(), (1, 1));
+
+        // the run is cancelled while it waits to be admitted again
+        let res = tokio::time::timeout(Duration::from_millis(10), batch.yield_now()).await;
+        assert!(res.is_err());
+        assert_eq!(scheduler.stats(), (1, 0));
+        drop(batch);
+        assert_eq!(scheduler.stats(), (1, 0));
+
+        tx.send(()).unwrap();
+        let _permit = scheduler.acquire(RequestPriority::Batch).await;
+        assert_eq!(scheduler.stats(), (1, 0));
+    }
+}
@@ -0,0 +1,147 @@
+use anda_core::{
+    AgentOutput, BoxError, CompletionRequest, FunctionDefinition, SlotForm, SlotState, Value,
+};
+
+use super::AgentCtx;
+use crate::unix_ms;
+
+/// The tool the model calls to submit the values extracted from a message.
+pub static EXTRACT_SLOTS_TOOL: &str = "extract_slots";
+
+impl AgentCtx {
+    /// Fills the form with the values extracted from the user's message.
+    ///
+    /// Returns a follow-up question for the missing required fields, or the completed object
+    /// as JSON content when all of them are filled. The updated state is returned in
+    /// [`AgentOutput::form`].
+    pub async fn fill_slots(
+        &self,
+        form: &SlotForm,
+        state: &mut SlotState,
+        prompt: &str,
+    ) -> Result<AgentOutput, BoxError> {
+        let req = CompletionRequest {
+            system: Some(format!(
+                "You are filling the form \"{}\". {}\n\n\
+                Values filled so far:\n{}\n\n\
+                Call the `{}` tool with the values of the form found in the user's message. \
+                Omit the fields that the message does not mention, never guess them.",
+                form.name,
+                form.description,
+                serde_json::to_string(&state.values)?,
+                EXTRACT_SLOTS_TOOL,
+            )),
+            prompt: prompt.to_string(),
+            tools: vec![FunctionDefinition {
+                name: EXTRACT_SLOTS_TOOL.to_string(),
+                description: format!("Submits the values of the form {}.", form.name),
+                parameters: form.partial_schema(),
+                strict: None,
+            }],
+            tool_choice_required: true,
+            caller: Some(self.base.caller),
+            ..Default::default()
+        };
+        let res = self.model.completion(req).await?;
+        let mut output = AgentOutput {
+            usage: res.usage,
+            failed_reason: res.failed_reason,
+            ..Default::default()
+        };
+        if output.failed_reason.is_some() {
+            output.form = Some(state.clone());
+            return Ok(output);
+        }
+
+        let extracted = res
+            .tool_calls
+            .unwrap_or_default()
+            .into_iter()
+            .find(|call| call.name == EXTRACT_SLOTS_TOOL)
+            .and_then(|call| match serde_json::from_str::<Value>(&call.args) {
+                Ok(Value::Object(values)) => Some(values),
+                _ => None,
+            })
+            .unwrap_or_default();
+        state.merge(form, extracted, unix_ms());
+
+        output.content = if state.completed {
+            serde_json::to_string(&state.values)?
+        } else {
+            follow_up_question(form, state)
+        };
+        output.form = Some(state.clone());
+        Ok(output)
+    }
+}
+
+/// Asks for the missing required fields by their descriptions.
+fn follow_up_question(form: &SlotForm, state: &SlotState) -> String {
+    let missing: Vec<&str> = state
+        .missing(form)
+        .into_iter()
+        .map(|f| form.field_description(f))
+        .collect();
+    match missing.split_last() {
+        Some((last, [])) => format!("Please provide {}.", last),
+        Some((last, rest)) => format!("Please provide {} and {}.", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::EngineBuilder, model::Model};
+    use serde_json::json;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fill_slots() {
+        // the mock model submits the prompt as the extracted values
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .mock_ctx();
+        let form: SlotForm = serde_json::from_value(json!({
+            "name": "shipping_address",
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string", "description": "the recipient's full name"},
+                    "street": {"type": "string", "description": "the street and number"},
+                    "city": {"type": "string"}
+                },
+                "required": ["name", "street", "city"]
+            }
+        }))
+        .unwrap();
+
+        let mut state = SlotState::new(&form, unix_ms());
+        let output = ctx
+            .fill_slots(&form, &mut state, "ship it to me")
+            .await
+            .unwrap();
+        assert_eq!(
+            output.content,
+            "Please provide the recipient's full name, the street and number and city."
+        );
+
+        let output = ctx
+            .fill_slots(&form, &mut state, r#"{"name": "Alice", "city": "Paris"}"#)
+            .await
+            .unwrap();
+        assert_eq!(output.content, "Please provide the street and number.");
+        assert!(!state.completed);
+
+        let output = ctx
+            .fill_slots(&form, &mut state, r#"{"street": "1 Rue de Rivoli"}"#)
+            .await
+            .unwrap();
+        assert!(state.completed);
+        let values: Value = serde_json::from_str(&output.content).unwrap();
+        assert_eq!(
+            values,
+            json!({"name": "Alice", "street": "1 Rue de Rivoli", "city": "Paris"})
+        );
+        assert_eq!(output.form, Some(state));
+    }
+}
@@ -0,0 +1,285 @@
+//! Private state of tools per thread.
+//!
+//! A tool that keeps state across the turns of a conversation, e.g. a browser session, a
+//! database cursor or a shopping cart, gets a [`ThreadState`] handle for the thread of the
+//! request with [`BaseCtx::thread_state`](super::BaseCtx::thread_state). The values are
+//! keyed by the tool and a key chosen by the tool, so a tool can't read the state of another
+//! tool, and are stored as CBOR in the store of the engine, under `_/thread_state/<thread>/`.
+//!
+//! The states of a thread are deleted with the thread. The states of threads that are left,
+//! e.g. when their session ends, are deleted by the [`ThreadStateExpiry`] maintenance job
+//! once they have not been written for its time to live.
+//!
+//! # Example
+//! ```rust,ignore
+//! async fn call(&self, ctx: BaseCtx, args: Self::Args, _resources: Vec<Resource>)
+//!     -> Result<ToolOutput<Self::Output>, BoxError> {
+//!     let state = ctx.thread_state()?;
+//!     let mut cart: Cart = state.get("cart").await?.unwrap_or_default();
+//!     cart.items.push(args.item);
+//!     state.set("cart", &cart).await?;
+//!     Ok(ToolOutput::new(cart))
+//! }
+//!
+//! let maintenance = MaintenanceScheduler::new(Duration::from_secs(600)).with_job(Arc::new(
+//!     ThreadStateExpiry::new(store.clone(), Duration::from_secs(7 * 24 * 3600)),
+//! ));
+//! ```
+
+use anda_core::{BoxError, BoxPinFut, ObjectMeta, Path, PutMode, Xid, validate_path_part};
+use ciborium::from_reader;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use structured_logger::unix_ms;
+
+use crate::{
+    management::SYSTEM_PATH,
+    store::{
+        MaintenanceJob, Store, is_not_found,
+        maintenance::{MaintenanceProgress, Throttle},
+    },
+};
+
+static THREAD_STATE_PATH: &str = "thread_state";
+
+fn states_path() -> Path {
+    Path::from(SYSTEM_PATH).child(THREAD_STATE_PATH)
+}
+
+/// A handle to the private state of a tool in a thread.
+#[derive(Clone)]
+pub struct ThreadState {
+    store: Store,
+    thread: Xid,
+    namespace: Path,
+}
+
+impl ThreadState {
+    pub(crate) fn new(store: Store, thread: Xid, tool: &Path) -> Self {
+        let namespace = states_path().child(thread.to_string()).child(tool.as_ref());
+        Self {
+            store,
+            thread,
+            namespace,
+        }
+    }
+
+    /// Returns the thread of the state.
+    pub fn thread(&self) -> &Xid {
+        &self.thread
+    }
+
+    /// Gets the value of the key, None if it is not set.
+    pub async fn get<T>(&self, key: &str) -> Result<Option<T>, BoxError>
+    where
+        T: DeserializeOwned,
+    {
+        match self.store.store_get(&self.namespace, &key_path(key)?).await {
+            Ok((data, _)) => Ok(Some(from_reader(&data[..])?)),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Sets the value of the key.
+    pub async fn set<T>(&self, key: &str, val: &T) -> Result<(), BoxError>
+    where
+        T: Serialize,
+    {
+        self.store
+            .store_put(
+                &self.namespace,
+                &key_path(key)?,
+                PutMode::Overwrite,
+                to_cbor_bytes(val).into(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the value of the key, if any.
+    pub async fn delete(&self, key: &str) -> Result<(), BoxError> {
+        match self
+            .store
+            .store_delete(&self.namespace, &key_path(key)?)
+            .await
+        {
+            Err(err) if !is_not_found(&err) => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn key_path(key: &str) -> Result<Path, BoxError> {
+    validate_path_part(key)?;
+    Ok(Path::from(format!("{}.cbor", key)))
+}
+
+/// Lists the state objects of the thread, or of all threads.
+async fn list_states(store: &Store, thread: Option<&Xid>) -> Result<Vec<ObjectMeta>, BoxError> {
+    let (namespace, prefix) = match thread {
+        Some(thread) => (states_path(), Path::from(thread.to_string())),
+        None => (Path::from(SYSTEM_PATH), Path::from(THREAD_STATE_PATH)),
+    };
+    store.store_list(&namespace, Some(&prefix), &prefix).await
+}
+
+async fn delete_state(store: &Store, meta: &ObjectMeta) -> Result<(), BoxError> {
+    let mut parts: Vec<_> = meta.location.parts().collect();
+    let file = parts.pop().ok_or("invalid state path")?;
+    store
+        .store_delete(&Path::from_iter(parts), &Path::from_iter([file]))
+        .await
+}
+
+/// Deletes the states of all tools in the thread. Returns the number of deleted values.
+pub(crate) async fn delete_thread_states(store: &Store, thread: &Xid) -> Result<usize, BoxError> {
+    let metas = list_states(store, Some(thread)).await?;
+    for meta in &metas {
+        delete_state(store, meta).await?;
+    }
+    Ok(metas.len())
+}
+
+/// A maintenance job that deletes the thread states that have not been written for
+/// a time to live. It sweeps the states at most once per half of the time to live.
+#[derive(Clone)]
+pub struct ThreadStateExpiry {
+    store: Store,
+    ttl: Duration,
+    last_run_at: Arc<AtomicU64>,
+}
+
+impl ThreadStateExpiry {
+    /// Creates an expiry job of the thread states in the store of the engine.
+    pub fn new(store: Store, ttl: Duration) -> Self {
+        Self {
+            store,
+            ttl,
+            last_run_at: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Deletes the expired states. Returns the number of deleted values.
+    pub async fn sweep(
+        &self,
+        throttle: Throttle,
+        progress: &MaintenanceProgress,
+    ) -> Result<u64, BoxError> {
+        self.last_run_at.store(unix_ms(), Ordering::Relaxed);
+        let expire_before = unix_ms().saturating_sub(self.ttl.as_millis() as u64);
+        let metas = list_states(&self.store, None).await?;
+        let expired: Vec<_> = metas
+            .into_iter()
+            .filter(|meta| (meta.last_modified.timestamp_millis() as u64) < expire_before)
+            .collect();
+
+        progress.start(expired.len() as u64);
+        for batch in expired.chunks(throttle.batch_size) {
+            for meta in batch {
+                delete_state(&self.store, meta).await?;
+            }
+            progress.advance(batch.len() as u64);
+            if !throttle.pause.is_zero() {
+                tokio::time::sleep(throttle.pause).await;
+            }
+        }
+        Ok(expired.len() as u64)
+    }
+}
+
+impl MaintenanceJob for ThreadStateExpiry {
+    fn name(&self) -> String {
+        "thread_state_expiry".to_string()
+    }
+
+    fn is_due(&self) -> bool {
+        let last_run_at = self.last_run_at.load(Ordering::Relaxed);
+        unix_ms().saturating_sub(last_run_at) >= (self.ttl.as_millis() as u64) / 2
+    }
+
+    fn run(
+        &self,
+        throttle: Throttle,
+        progress: MaintenanceProgress,
+    ) -> BoxPinFut<Result<u64, BoxError>> {
+        let job = self.clone();
+        Box::pin(async move { job.sweep(throttle, &progress).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemory;
+    use serde::Deserialize;
+
+    #[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+    struct Cart {
+        items: Vec<String>,
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_thread_state() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let (t1, t2): (Xid, Xid) = (crate::id::new_xid(), crate::id::new_xid());
+        let tool = Path::from("T:shop");
+        let state = ThreadState::new(store.clone(), t1.clone(), &tool);
+        let other_tool = ThreadState::new(store.clone(), t1.clone(), &Path::from("T:browser"));
+        let other_thread = ThreadState::new(store.clone(), t2.clone(), &tool);
+
+        assert_eq!(state.get::<Cart>("cart").await.unwrap(), None);
+        let cart = Cart {
+            items: vec!["apple".to_string()],
+        };
+        state.set("cart", &cart).await.unwrap();
+        state.set("cursor", &42u64).await.unwrap();
+        assert_eq!(state.get::<Cart>("cart").await.unwrap(), Some(cart));
+        assert_eq!(other_tool.get::<Cart>("cart").await.unwrap(), None);
+        assert_eq!(other_thread.get::<Cart>("cart").await.unwrap(), None);
+        assert!(state.set("a/b", &1).await.is_err());
+
+        state.delete("cursor").await.unwrap();
+        state.delete("cursor").await.unwrap();
+        assert_eq!(state.get::<u64>("cursor").await.unwrap(), None);
+
+        other_tool.set("session", &"s1").await.unwrap();
+        other_thread.set("cart", &Cart::default()).await.unwrap();
+        assert_eq!(delete_thread_states(&store, &t1).await.unwrap(), 2);
+        assert_eq!(state.get::<Cart>("cart").await.unwrap(), None);
+        assert_eq!(other_tool.get::<String>("session").await.unwrap(), None);
+        assert!(other_thread.get::<Cart>("cart").await.unwrap().is_some());
+
+        let expiry = ThreadStateExpiry::new(store.clone(), Duration::from_secs(3600));
+        assert!(expiry.is_due());
+        let progress = MaintenanceProgress::default();
+        assert_eq!(
+            expiry
+                .sweep(Throttle::unlimited(), &progress)
+                .await
+                .unwrap(),
+            0
+        );
+        assert!(!expiry.is_due());
+        assert!(other_thread.get::<Cart>("cart").await.unwrap().is_some());
+
+        let expiry = ThreadStateExpiry::new(store.clone(), Duration::ZERO);
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert_eq!(
+            expiry
+                .sweep(Throttle::unlimited(), &progress)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(progress.get(), (1, 1));
+        assert_eq!(other_thread.get::<Cart>("cart").await.unwrap(), None);
+    }
+}
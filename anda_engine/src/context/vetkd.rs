@@ -0,0 +1,207 @@
+//! Encryption of thread contents under keys held by the end user (vetKD).
+//!
+//! With ICP's verifiably encrypted threshold key derivation (vetKD), the COSE canister can
+//! derive a BLS key for the user which is only delivered encrypted to the user's transport
+//! key. The engine encrypts thread records with identity-based encryption (IBE) under the
+//! derived public key of the user, so the user can decrypt them with the vetKey while the
+//! engine operator, who only knows the public key, can not.
+//!
+//! # Scheme
+//! Identity-based encryption in the Boneh-Franklin style over BLS12-381, with the same
+//! identity hashing as vetKD (`BLS_SIG_BLS12381G1_XMD:SHA-256_SSWU_RO_AUG_`):
+//! - `Q = H(dpk || identity)` in G1, the vetKey of the identity is `k = s·Q`;
+//! - the encryptor picks a random `r`, sends `c1 = r·G2` and uses `e(Q, dpk)^r`;
+//! - the decryptor computes the same value as `e(k, c1)`.
+//!
+//! The shared value is expanded with HKDF-SHA256 into an AES-256-GCM key for a
+//! COSE_Encrypt0 payload. A ciphertext is `IBE_HEADER || c1 || COSE_Encrypt0`.
+
+use anda_core::{BoxError, CanisterCaller};
+use candid::Principal;
+use ic_bls12_381::{
+    G1Affine, G1Projective, G2Affine, Gt, Scalar,
+    hash_to_curve::{ExpandMsgXmd, HashToCurve},
+    pairing,
+};
+use ic_cose::rand_bytes;
+use ic_cose_types::{
+    cose::{encrypt0::cose_decrypt0, encrypt0::cose_encrypt0, kdf::hkdf256},
+    types::setting::SettingPath,
+};
+use serde_bytes::ByteBuf;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::RwLock;
+
+/// The header of IBE ciphertexts, with the scheme version.
+pub static IBE_HEADER: [u8; 8] = *b"ANDAIBE\x01";
+
+static AUGMENTED_DOMAIN_SEP: &[u8] = b"BLS_SIG_BLS12381G1_XMD:SHA-256_SSWU_RO_AUG_";
+static IBE_KDF_INFO: &[u8] = b"anda_ibe_a256gcm";
+
+fn augmented_hash_to_g1(dpk: &G2Affine, identity: &[u8]) -> G1Affine {
+    let mut msg = Vec::with_capacity(96 + identity.len());
+    msg.extend_from_slice(&dpk.to_compressed());
+    msg.extend_from_slice(identity);
+    let pt = <G1Projective as HashToCurve<ExpandMsgXmd<sha2::Sha256>>>::hash_to_curve(
+        &msg,
+        AUGMENTED_DOMAIN_SEP,
+    );
+    G1Affine::from(pt)
+}
+
+fn decode_g2(bytes: &[u8]) -> Result<G2Affine, BoxError> {
+    let bytes: &[u8; 96] = bytes
+        .try_into()
+        .map_err(|_| format!("invalid G2 point length, expected 96, got {}", bytes.len()))?;
+    Option::from(G2Affine::from_compressed(bytes)).ok_or_else(|| "invalid G2 point".into())
+}
+
+fn decode_g1(bytes: &[u8]) -> Result<G1Affine, BoxError> {
+    let bytes: &[u8; 48] = bytes
+        .try_into()
+        .map_err(|_| format!("invalid G1 point length, expected 48, got {}", bytes.len()))?;
+    Option::from(G1Affine::from_compressed(bytes)).ok_or_else(|| "invalid G1 point".into())
+}
+
+fn ibe_key(shared: &Gt, c1: &[u8]) -> [u8; 32] {
+    hkdf256::<32>(&shared.to_bytes(), Some(c1), IBE_KDF_INFO)
+}
+
+fn ibe_aad(c1: &[u8], identity: &[u8]) -> Vec<u8> {
+    [&IBE_HEADER[..], c1, identity].concat()
+}
+
+/// Encrypts the plaintext for the identity under the derived public key (96 bytes, G2).
+pub fn ibe_encrypt(dpk: &[u8], identity: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, BoxError> {
+    let dpk = decode_g2(dpk)?;
+    let q = augmented_hash_to_g1(&dpk, identity);
+    let r = Scalar::from_bytes_wide(&rand_bytes::<64>());
+    let c1 = G2Affine::from(G2Affine::generator() * r).to_compressed();
+    let shared = pairing(&q, &dpk) * r;
+    let key = ibe_key(&shared, &c1);
+    let nonce: [u8; 12] = rand_bytes();
+    let payload = cose_encrypt0(plaintext, &key, &ibe_aad(&c1, identity), &nonce, None)?;
+    Ok([&IBE_HEADER[..], &c1, &payload].concat())
+}
+
+/// Decrypts an IBE ciphertext with the vetKey (48 bytes, G1) of the identity.
+pub fn ibe_decrypt(vetkey: &[u8], identity: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, BoxError> {
+    let k = decode_g1(vetkey)?;
+    let data = ciphertext
+        .strip_prefix(&IBE_HEADER[..])
+        .ok_or("invalid IBE ciphertext header")?;
+    if data.len() <= 96 {
+        return Err("invalid IBE ciphertext length".into());
+    }
+    let (c1, payload) = data.split_at(96);
+    let shared = pairing(&k, &decode_g2(c1)?);
+    let key = ibe_key(&shared, c1);
+    let plaintext = cose_decrypt0(payload, &key, &ibe_aad(c1, identity))?;
+    Ok(plaintext)
+}
+
+/// Verifies that the vetKey (48 bytes, G1) belongs to the identity under the derived public key.
+pub fn verify_vetkey(dpk: &[u8], identity: &[u8], vetkey: &[u8]) -> bool {
+    match (decode_g2(dpk), decode_g1(vetkey)) {
+        (Ok(dpk), Ok(k)) => {
+            let q = augmented_hash_to_g1(&dpk, identity);
+            pairing(&k, &G2Affine::generator()) == pairing(&q, &dpk)
+        }
+        _ => false,
+    }
+}
+
+/// Encrypts thread records for users, under vetKD public keys derived by a COSE canister.
+///
+/// For each user, the key is derived on the setting path `{namespace, user_owned: true,
+/// subject: user, key: user}`, and the identity is the user's principal. The user obtains
+/// the vetKey by calling `vetkd_encrypted_key` on the same path with a transport key.
+#[derive(Clone)]
+pub struct ThreadEncryptor {
+    cose_canister: Principal,
+    namespace: String,
+    keys: Arc<RwLock<BTreeMap<Principal, ByteBuf>>>,
+}
+
+impl ThreadEncryptor {
+    /// Creates a new encryptor with the COSE canister and its namespace.
+    pub fn new(cose_canister: Principal, namespace: String) -> Self {
+        Self {
+            cose_canister,
+            namespace,
+            keys: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    /// Returns the setting path of the user's key.
+    pub fn setting_path(&self, user: &Principal) -> SettingPath {
+        SettingPath {
+            ns: self.namespace.clone(),
+            user_owned: true,
+            subject: Some(*user),
+            key: ByteBuf::from(user.as_slice()),
+            version: 0,
+        }
+    }
+
+    /// Returns the derived public key of the user, fetching it from the COSE canister once.
+    pub async fn public_key(
+        &self,
+        ctx: &impl CanisterCaller,
+        user: &Principal,
+    ) -> Result<ByteBuf, BoxError> {
+        if let Some(dpk) = self.keys.read().await.get(user) {
+            return Ok(dpk.clone());
+        }
+
+        let res: Result<ByteBuf, String> = ctx
+            .canister_update(
+                &self.cose_canister,
+                "vetkd_public_key",
+                (self.setting_path(user),),
+            )
+            .await?;
+        let dpk = res?;
+        decode_g2(&dpk)?;
+        self.keys.write().await.insert(*user, dpk.clone());
+        Ok(dpk)
+    }
+
+    /// Encrypts the plaintext so that only the user can decrypt it.
+    pub async fn encrypt(
+        &self,
+        ctx: &impl CanisterCaller,
+        user: &Principal,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, BoxError> {
+        let dpk = self.public_key(ctx, user).await?;
+        ibe_encrypt(&dpk, user.as_slice(), plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ibe() {
+        // simulate the master key of vetKD
+        let s = Scalar::from_bytes_wide(&rand_bytes::<64>());
+        let dpk = G2Affine::from(G2Affine::generator() * s).to_compressed();
+        let user = Principal::from_text("2vxsx-fae").unwrap();
+        let identity = user.as_slice();
+        let vetkey = G1Affine::from(augmented_hash_to_g1(&decode_g2(&dpk).unwrap(), identity) * s)
+            .to_compressed();
+        assert!(verify_vetkey(&dpk, identity, &vetkey));
+        assert!(!verify_vetkey(&dpk, b"other", &vetkey));
+
+        let msg = b"hello, vetKD";
+        let ct = ibe_encrypt(&dpk, identity, msg).unwrap();
+        assert!(ct.starts_with(&IBE_HEADER));
+        assert_eq!(ibe_decrypt(&vetkey, identity, &ct).unwrap(), msg);
+        assert!(ibe_decrypt(&vetkey, b"other", &ct).is_err());
+
+        let other = G1Affine::from(G1Affine::generator() * s).to_compressed();
+        assert!(ibe_decrypt(&other, identity, &ct).is_err());
+    }
+}
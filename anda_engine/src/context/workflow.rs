@@ -0,0 +1,271 @@
+use anda_core::{
+    AgentOutput, BoxError, CompletionRequest, FunctionDefinition, Value, Workflow,
+    WorkflowProgress, WorkflowWait,
+};
+use serde_json::json;
+
+use super::AgentCtx;
+use crate::unix_ms;
+
+/// The tool the model calls to submit the structured output of a workflow step.
+pub static SUBMIT_STEP_TOOL: &str = "submit_step";
+
+impl AgentCtx {
+    /// Runs a turn of the workflow: the user's message is checked against the current step
+    /// and, once the step's required output is submitted, the workflow moves on and presents
+    /// the next steps. If `started` is true, the workflow has just started and the first
+    /// step is presented without checking the message.
+    ///
+    /// A suspended workflow whose wait has ended moves on without using the message. One
+    /// that waits for a webhook event or a timestamp presents the current step again.
+    pub(crate) async fn workflow_turn(
+        &self,
+        workflow: &Workflow,
+        progress: &mut WorkflowProgress,
+        prompt: &str,
+        started: bool,
+    ) -> Result<AgentOutput, BoxError> {
+        let mut output = AgentOutput::default();
+        let mut messages: Vec<String> = Vec::new();
+
+        if !started && progress.waiting.is_some() {
+            let step = progress.step.as_deref().and_then(|id| workflow.step(id));
+            let resumed = if progress.is_due(unix_ms()) {
+                Some(None)
+            } else {
+                match step.map(|s| (&s.wait, &s.output_schema)) {
+                    Some((Some(WorkflowWait::Reply { .. }), None)) => {
+                        Some(Some(json!({"text": prompt})))
+                    }
+                    Some((Some(WorkflowWait::Reply { .. }), Some(_))) => None,
+                    _ => {
+                        output.content = step.map(|s| s.prompt.clone()).unwrap_or_default();
+                        output.workflow = Some(progress.clone());
+                        return Ok(output);
+                    }
+                }
+            };
+            if let Some(value) = resumed {
+                output.content = resume_workflow(workflow, progress, value)?;
+                output.workflow = Some(progress.clone());
+                return Ok(output);
+            }
+        }
+
+        if !started && let Some(step) = progress.step.as_deref().and_then(|id| workflow.step(id)) {
+            let req = CompletionRequest {
+                system: Some(format!(
+                    "You are guiding the user through the workflow \"{}\". {}\n\n\
+                    Current step: {}\n\n\
+                    Information collected in previous steps:\n{}\n\n\
+                    If the user's message provides all the information required by the current step, \
+                    call the `{}` tool with it. Otherwise, reply briefly and ask for the missing information. \
+                    Do not ask about other steps.",
+                    workflow.name,
+                    workflow.description,
+                    step.prompt,
+                    serde_json::to_string(&progress.outputs)?,
+                    SUBMIT_STEP_TOOL,
+                )),
+                prompt: prompt.to_string(),
+                tools: vec![FunctionDefinition {
+                    name: SUBMIT_STEP_TOOL.to_string(),
+                    description: format!("Submits the information required by step {}.", step.id),
+                    parameters: step.output_schema.clone().unwrap_or_default(),
+                    strict: None,
+                }],
+                caller: Some(self.base.caller),
+                ..Default::default()
+            };
+            let res = self.model.completion(req).await?;
+            output.usage = res.usage;
+            if res.failed_reason.is_some() {
+                output.failed_reason = res.failed_reason;
+                output.workflow = Some(progress.clone());
+                return Ok(output);
+            }
+
+            let submitted = res
+                .tool_calls
+                .unwrap_or_default()
+                .into_iter()
+                .find(|call| call.name == SUBMIT_STEP_TOOL)
+                .and_then(|call| serde_json::from_str::<Value>(&call.args).ok());
+            let checked = submitted.map(|v| step.check_output(&v).map(|_| v));
+            match checked {
+                Some(Ok(value)) => {
+                    progress.complete_step(workflow, Some(value), unix_ms());
+                }
+                Some(Err(err)) => {
+                    output.content = format!("{}, {}", err, step.prompt);
+                    output.workflow = Some(progress.clone());
+                    return Ok(output);
+                }
+                None => {
+                    output.content = if res.content.is_empty() {
+                        step.prompt.clone()
+                    } else {
+                        res.content
+                    };
+                    output.workflow = Some(progress.clone());
+                    return Ok(output);
+                }
+            }
+        }
+
+        present_steps(workflow, progress, &mut messages);
+        output.content = messages.join("\n\n");
+        output.workflow = Some(progress.clone());
+        Ok(output)
+    }
+}
+
+/// Presents the next steps: informational steps are completed right away, until a step
+/// that requires an output or waits, which suspends the workflow.
+fn present_steps(workflow: &Workflow, progress: &mut WorkflowProgress, messages: &mut Vec<String>) {
+    while let Some(step) = progress.step.as_deref().and_then(|id| workflow.step(id)) {
+        messages.push(step.prompt.clone());
+        if progress.suspend(workflow, unix_ms()) || step.output_schema.is_some() {
+            break;
+        }
+        progress.complete_step(workflow, None, unix_ms());
+    }
+    if progress.is_completed() {
+        messages.push(
+            workflow
+                .completion
+                .clone()
+                .unwrap_or_else(|| format!("The {} workflow is completed.", workflow.name)),
+        );
+    }
+}
+
+/// Resumes a suspended workflow: completes the current step with the output, e.g. the
+/// payload of a webhook event, or None when the wait ends, and presents the next steps.
+/// Returns the messages of the presented steps.
+pub(crate) fn resume_workflow(
+    workflow: &Workflow,
+    progress: &mut WorkflowProgress,
+    output: Option<Value>,
+) -> Result<String, BoxError> {
+    let step = progress
+        .step
+        .as_deref()
+        .and_then(|id| workflow.step(id))
+        .ok_or_else(|| format!("the {} workflow is completed", workflow.name))?;
+    if let Some(value) = &output {
+        step.check_output(value)?;
+    }
+    progress.complete_step(workflow, output, unix_ms());
+    let mut messages: Vec<String> = Vec::new();
+    present_steps(workflow, progress, &mut messages);
+    Ok(messages.join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::EngineBuilder, model::Model};
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_workflow_turn() {
+        // the mock model submits the prompt as the step output
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .mock_ctx();
+        let wf: Workflow = serde_json::from_value(json!({
+            "name": "onboarding",
+            "completion": "All set!",
+            "steps": [
+                {"id": "welcome", "prompt": "Welcome!"},
+                {
+                    "id": "profile",
+                    "prompt": "What is your name?",
+                    "output_schema": {"type": "object", "required": ["name"]}
+                }
+            ]
+        }))
+        .unwrap();
+
+        let mut progress = WorkflowProgress::new(&wf, unix_ms());
+        let output = ctx
+            .workflow_turn(&wf, &mut progress, "hi", true)
+            .await
+            .unwrap();
+        assert_eq!(output.content, "Welcome!\n\nWhat is your name?");
+        assert_eq!(progress.step.as_deref(), Some("profile"));
+
+        let output = ctx
+            .workflow_turn(&wf, &mut progress, "I am Alice", false)
+            .await
+            .unwrap();
+        assert_eq!(output.content, "I am Alice");
+        assert_eq!(progress.step.as_deref(), Some("profile"));
+
+        let output = ctx
+            .workflow_turn(&wf, &mut progress, r#"{"nickname": "A"}"#, false)
+            .await
+            .unwrap();
+        assert!(output.content.starts_with("missing required fields: name"));
+
+        let output = ctx
+            .workflow_turn(&wf, &mut progress, r#"{"name": "Alice"}"#, false)
+            .await
+            .unwrap();
+        assert_eq!(output.content, "All set!");
+        assert!(progress.is_completed());
+        assert_eq!(progress.outputs["profile"], json!({"name": "Alice"}));
+        assert_eq!(output.workflow, Some(progress));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_workflow_wait() {
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .mock_ctx();
+        let wf: Workflow = serde_json::from_value(json!({
+            "name": "follow_up",
+            "completion": "Thanks!",
+            "steps": [
+                {"id": "intro", "prompt": "We will review your request."},
+                {"id": "review", "prompt": "Under review.", "wait": {"type": "webhook", "event": "reviewed"}},
+                {"id": "check_in", "prompt": "How is it going?", "wait": {"type": "reply", "timeout_ms": 60000}}
+            ]
+        }))
+        .unwrap();
+
+        let mut progress = WorkflowProgress::new(&wf, unix_ms());
+        let output = ctx
+            .workflow_turn(&wf, &mut progress, "hi", true)
+            .await
+            .unwrap();
+        assert_eq!(
+            output.content,
+            "We will review your request.\n\nUnder review."
+        );
+        let waiting = progress.waiting.clone().unwrap();
+        assert_eq!(waiting.event.as_deref(), Some("reviewed"));
+
+        // messages do not resume a workflow waiting for an event
+        let output = ctx
+            .workflow_turn(&wf, &mut progress, "any news?", false)
+            .await
+            .unwrap();
+        assert_eq!(output.content, "Under review.");
+        assert_eq!(progress.step.as_deref(), Some("review"));
+
+        let content = resume_workflow(&wf, &mut progress, Some(json!({"ok": true}))).unwrap();
+        assert_eq!(content, "How is it going?");
+        assert_eq!(progress.outputs["review"], json!({"ok": true}));
+        assert!(progress.waiting.as_ref().unwrap().until.is_some());
+
+        let output = ctx
+            .workflow_turn(&wf, &mut progress, "great", false)
+            .await
+            .unwrap();
+        assert_eq!(output.content, "Thanks!");
+        assert_eq!(progress.outputs["check_in"], json!({"text": "great"}));
+        assert!(progress.is_completed());
+        assert!(resume_workflow(&wf, &mut progress, None).is_err());
+    }
+}
@@ -30,29 +30,54 @@
 //! ```
 
 use anda_core::{
-    ANONYMOUS, Agent, AgentInput, AgentOutput, AgentSet, BoxError, Function, Path, RequestMeta,
-    ThreadMeta, Tool, ToolInput, ToolOutput, ToolSet, Value, validate_function_name,
+    ANONYMOUS, Agent, AgentInput, AgentOutput, AgentSet, BoxError, CompletionRequest, Function,
+    KeyEpoch, KeyRotation, Path, RequestMeta, ResourceFailurePolicy, ResourceState, SizeLimits,
+    SlotForm, SlotState, ThreadMessage, ThreadMeta, Tool, ToolDyn, ToolInput, ToolOutput,
+    ToolPipeline, ToolSet, Truncation, Value, Versioned, Workflow, WorkflowProgress, Xid,
+    detect_language, normalize_text, validate_function_name,
 };
 use async_trait::async_trait;
 use candid::Principal;
+use ic_cose_types::to_cbor_bytes;
 use object_store::memory::InMemory;
+use serde_bytes::ByteBuf;
 use std::{
     collections::{BTreeMap, BTreeSet},
-    sync::Arc,
+    sync::{Arc, RwLock},
+    time::Duration,
 };
 use structured_logger::unix_ms;
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    context::{AgentCtx, BaseCtx, Web3Client, Web3SDK},
-    management::{Management, SYSTEM_PATH, ThreadMetaTool, UserStateTool, UserStateWrapper},
-    model::Model,
-    store::Store,
+    analytics::{ExporterStats, FeedbackRecord, RunExporter, RunRecord},
+    context::{
+        AgentCtx, BaseCtx, KeyManager, ReceiptIssuer, ResourceLoader, ResourceValidator, Scheduler,
+        ThreadEncryptor, ToolResultSummarizer, Web3Client, Web3SDK, failed_resources_list,
+        load_resources, resume_workflow,
+    },
+    fleet::{EngineHealth, EngineMetrics, FleetConfig},
+    id::new_xid,
+    management::{
+        ApiKey, ApiKeyTarget, ApiKeyUsage, BudgetUsage, CreateApiKeyArgs, IdentityTool, LeaseGuard,
+        LeaseManager, Management, RemainingQuota, Reminder, ReminderNotifier, ReminderTool,
+        RequestIdentity, SYSTEM_PATH, SessionPolicy, SuspendedWorkflow, ThreadMetaTool,
+        UserStateTool, UserStateWrapper,
+    },
+    memory::Memory,
+    model::{Model, ProviderErrorKind},
+    redaction::Redactor,
+    rollout::{Rollout, RolloutStatus},
+    store::{MaintenanceScheduler, MaintenanceStats, Store},
+    telemetry::{Telemetry, TelemetryStats},
 };
 
 pub use crate::{
-    context::{Information, RemoteEngineArgs, RemoteEngines},
-    management::{ManagementBuilder, Visibility},
+    context::{AgentCard, AgentPricing, Information, RemoteEngineArgs, RemoteEngines},
+    management::{
+        ActionBudget, ApprovalPolicy, BudgetPolicy, ManagementBuilder, PendingToolCall,
+        QuotaLimits, QuotaPolicy, Visibility,
+    },
 };
 
 /// Engine is the core component that manages agents, tools, and execution context.
@@ -68,10 +93,54 @@ pub struct Engine {
     export_tools: BTreeSet<String>,
     hooks: Arc<Hooks>,
     management: Arc<Management>,
+    leases: Option<LeaseManager>,
+    resource_loader: Arc<dyn ResourceLoader>,
+    resource_failure: ResourceFailurePolicy,
+    thread_encryptor: Option<ThreadEncryptor>,
+    receipt_issuer: Option<ReceiptIssuer>,
+    key_managers: BTreeMap<String, KeyManager>,
+    redactor: Option<Redactor>,
+    workflows: Arc<BTreeMap<String, Arc<Workflow>>>,
+    forms: BTreeMap<String, Arc<SlotForm>>,
+    pricing: BTreeMap<String, AgentPricing>,
+    maintenance: Option<Arc<MaintenanceScheduler>>,
+    reminder_notifier: Option<Arc<dyn ReminderNotifier>>,
+    read_only: Option<BTreeSet<String>>,
+    agent_versions: BTreeMap<String, String>,
+    run_exporter: Option<Arc<RunExporter>>,
+    telemetry: Option<Arc<Telemetry>>,
+    rollouts: BTreeMap<String, Arc<Rollout>>,
+    fleet_config: Arc<RwLock<FleetConfig>>,
+    started_at: u64,
+    identity_links: bool,
+    session_policies: Arc<BTreeMap<String, SessionPolicy>>,
+}
+
+/// How a request is routed by the session policy of its connector.
+enum SessionRoute {
+    /// The connector has no session policy, or the request sets its thread.
+    Unmanaged,
+    /// The request is a turn of the session of the account.
+    Session(String),
+    /// The request only resets the session.
+    Reset,
+}
+
+/// A thread turn driven by the engine instead of the agent.
+enum DrivenTurn {
+    /// A workflow, and whether it has just started.
+    Workflow(Arc<Workflow>, bool),
+    Form(Arc<SlotForm>),
 }
 
 /// Hook trait for customizing engine behavior.
 /// Hooks can be used to intercept and modify agent and tool execution.
+///
+/// The `on_*` hooks wrap the requests to the engine: the agent runs and the tool calls of
+/// callers. The `before_*` and `after_*` hooks wrap every model completion and tool call
+/// inside them, including the tool calls of agents, so they can inspect and rewrite what is
+/// sent to the model and to the tools, e.g. for logging, PII redaction and policy checks.
+/// An error returned by a hook fails the request.
 #[async_trait]
 pub trait Hook: Send + Sync {
     /// Called before an agent is executed.
@@ -114,6 +183,46 @@ pub trait Hook: Send + Sync {
     ) -> Result<ToolOutput<Value>, BoxError> {
         Ok(output)
     }
+
+    /// Called before each completion request is sent to the model, including streamed ones.
+    async fn before_completion(
+        &self,
+        _ctx: &AgentCtx,
+        _req: &mut CompletionRequest,
+    ) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    /// Called after each completion of the model, before its tool calls are executed.
+    /// For streamed completions, it is called with each output of the stream.
+    async fn after_completion(
+        &self,
+        _ctx: &AgentCtx,
+        output: AgentOutput,
+    ) -> Result<AgentOutput, BoxError> {
+        Ok(output)
+    }
+
+    /// Called before each tool call. The context is the one of the calling agent for the
+    /// tool calls of agents, and the one of the tool for the tool calls of callers, whose
+    /// tool is resolved before, so renaming it has no effect.
+    async fn before_tool_call(
+        &self,
+        _ctx: &BaseCtx,
+        _input: &mut ToolInput<Value>,
+    ) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    /// Called after each tool call, with the same context as `before_tool_call`.
+    async fn after_tool_call(
+        &self,
+        _ctx: &BaseCtx,
+        _tool: &str,
+        output: ToolOutput<Value>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        Ok(output)
+    }
 }
 
 /// Hooks struct for managing multiple hooks.
@@ -188,6 +297,51 @@ impl Hook for Hooks {
         }
         Ok(output)
     }
+
+    async fn before_completion(
+        &self,
+        ctx: &AgentCtx,
+        req: &mut CompletionRequest,
+    ) -> Result<(), BoxError> {
+        for hook in &self.hooks {
+            hook.before_completion(ctx, req).await?;
+        }
+        Ok(())
+    }
+
+    async fn after_completion(
+        &self,
+        ctx: &AgentCtx,
+        mut output: AgentOutput,
+    ) -> Result<AgentOutput, BoxError> {
+        for hook in &self.hooks {
+            output = hook.after_completion(ctx, output).await?;
+        }
+        Ok(output)
+    }
+
+    async fn before_tool_call(
+        &self,
+        ctx: &BaseCtx,
+        input: &mut ToolInput<Value>,
+    ) -> Result<(), BoxError> {
+        for hook in &self.hooks {
+            hook.before_tool_call(ctx, input).await?;
+        }
+        Ok(())
+    }
+
+    async fn after_tool_call(
+        &self,
+        ctx: &BaseCtx,
+        tool: &str,
+        mut output: ToolOutput<Value>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        for hook in &self.hooks {
+            output = hook.after_tool_call(ctx, tool, output).await?;
+        }
+        Ok(output)
+    }
 }
 
 impl Engine {
@@ -246,10 +400,219 @@ impl Engine {
     /// If no agent name is provided, uses the default agent.
     /// Returns the agent's output or an error if the agent is not found.
     pub async fn agent_run(
+        &self,
+        caller: Principal,
+        input: AgentInput,
+    ) -> Result<AgentOutput, BoxError> {
+        self.run_agent(caller, input, false, None).await
+    }
+
+    /// Executes an agent that can be aborted with the given cancellation token.
+    /// A cancelled run returns the partial output, with the `failed_reason` and
+    /// the usage consumed so far.
+    pub async fn agent_run_with_cancellation(
+        &self,
+        caller: Principal,
+        input: AgentInput,
+        cancellation_token: CancellationToken,
+    ) -> Result<AgentOutput, BoxError> {
+        self.run_agent(caller, input, false, Some(cancellation_token))
+            .await
+    }
+
+    /// Executes an agent for a third-party caller authenticated by an API key.
+    /// The key must be scoped to the agent, and the call is charged to its limits.
+    pub async fn agent_run_with_api_key(
+        &self,
+        secret: &str,
+        input: AgentInput,
+    ) -> Result<AgentOutput, BoxError> {
+        let name = if input.name.is_empty() {
+            self.default_agent.clone()
+        } else {
+            input.name.to_ascii_lowercase()
+        };
+        let caller = self
+            .management
+            .authorize_api_key(secret, ApiKeyTarget::Agent(&name))
+            .await?;
+        self.run_agent(caller, input, true, None).await
+    }
+
+    /// Loads the state of the caller after checking its access to the engine.
+    /// Callers authenticated by an API key are allowed by the key's scope.
+    async fn load_caller_state(
+        &self,
+        caller: &Principal,
+        api_key: bool,
+    ) -> Result<UserStateWrapper, BoxError> {
+        if api_key {
+            return self.management.load_user_state(caller).await;
+        }
+
+        let visibility = self.management.try_get_visibility(caller)?;
+        if visibility == Visibility::Public {
+            // use anonymous user state for public
+            self.management.load_user_state(&ANONYMOUS).await
+        } else {
+            let sw = self.management.load_user_state(caller).await?;
+            if !sw.has_permission(caller, unix_ms()) {
+                return Err("caller does not have permission".into());
+            }
+            Ok(sw)
+        }
+    }
+
+    /// Sets the person that the request's account is linked to, if identity linking is
+    /// enabled, and returns the state of the person to check and charge instead of the
+    /// caller's. Requests with an API key are charged to the key.
+    async fn link_person(
+        &self,
+        caller: &Principal,
+        meta: &mut RequestMeta,
+        api_key: bool,
+    ) -> Result<Option<UserStateWrapper>, BoxError> {
+        meta.person = None;
+        if !self.identity_links {
+            return Ok(None);
+        }
+        let Some(RequestIdentity::Account(account)) =
+            RequestIdentity::of(caller, meta.user.as_deref())
+        else {
+            return Ok(None);
+        };
+        meta.person = self.management.resolve_account(&account).await;
+        match meta.person {
+            Some(person) if !api_key => {
+                let sw = self.management.load_user_state(&person).await?;
+                if sw.state.status < 0 {
+                    return Err("user is suspended".into());
+                }
+                Ok(Some(sw))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Routes a request of a connector's user that does not set a thread to the user's
+    /// session thread, by the session policy of the connector. The connector is the prefix
+    /// of the account, e.g. "telegram" for "telegram:12345".
+    async fn route_session(
+        &self,
+        caller: &Principal,
+        meta: &mut RequestMeta,
+        prompt: &mut String,
+    ) -> Result<SessionRoute, BoxError> {
+        if meta.thread.is_some() {
+            return Ok(SessionRoute::Unmanaged);
+        }
+        let Some(RequestIdentity::Account(account)) =
+            RequestIdentity::of(caller, meta.user.as_deref())
+        else {
+            return Ok(SessionRoute::Unmanaged);
+        };
+        let Some(policy) = account
+            .split_once(':')
+            .and_then(|(connector, _)| self.session_policies.get(connector))
+        else {
+            return Ok(SessionRoute::Unmanaged);
+        };
+
+        if let Some(rest) = policy.strip_reset_command(prompt) {
+            self.management.end_session(caller, &account).await?;
+            if rest.is_empty() && meta.callback.is_none() {
+                return Ok(SessionRoute::Reset);
+            }
+            *prompt = rest.to_string();
+            return Ok(SessionRoute::Session(account));
+        }
+
+        if let Some(session) = self.management.get_session(caller, &account).await
+            && !policy.is_expired(&session, unix_ms())
+            // the thread may have been deleted
+            && self.management.get_thread_meta(&session.thread).await.is_ok()
+        {
+            meta.thread = Some(session.thread);
+        }
+        Ok(SessionRoute::Session(account))
+    }
+
+    /// Runs an agent, or the candidate of its [`Rollout`] if the caller is routed to it, and
+    /// records the run if the engine has a [`RunExporter`], [`Telemetry`] or a rollout.
+    async fn run_agent(
+        &self,
+        caller: Principal,
+        mut input: AgentInput,
+        api_key: bool,
+        cancellation_token: Option<CancellationToken>,
+    ) -> Result<AgentOutput, BoxError> {
+        input.name = if input.name.is_empty() {
+            self.default_agent.clone()
+        } else {
+            input.name.to_ascii_lowercase()
+        };
+        let rollout = self.rollouts.get(&input.name);
+        if let Some(rollout) = rollout {
+            let user = input.meta.as_ref().and_then(|m| m.user.as_deref());
+            input.name = rollout.route(&caller, user).to_string();
+        }
+
+        let agent = input.name.clone();
+        let input_thread = input.meta.as_ref().and_then(|m| m.thread.clone());
+        let started_at = unix_ms();
+        let res = self
+            .run_agent_turn(caller, input, api_key, cancellation_token)
+            .await;
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_run(api_key, &res);
+        }
+        if let Some(rollout) = rollout {
+            rollout.record_run(&agent, &res).await;
+        }
+        let Some(exporter) = &self.run_exporter else {
+            return res;
+        };
+
+        let mut record = RunRecord {
+            engine: self.id.to_text(),
+            agent_version: self.agent_version(&agent),
+            agent,
+            caller: caller.to_text(),
+            started_at,
+            latency_ms: unix_ms().saturating_sub(started_at),
+            ..Default::default()
+        };
+        match &res {
+            Ok(output) => {
+                record.thread = output
+                    .thread
+                    .as_ref()
+                    .or(input_thread.as_ref())
+                    .map(|t| t.to_string())
+                    .unwrap_or_default();
+                record.input_tokens = output.usage.input_tokens;
+                record.output_tokens = output.usage.output_tokens;
+                record.requests = output.usage.requests;
+                record.tool_calls = output.tool_calls.as_ref().map_or(0, |c| c.len() as u32);
+                record.failed_reason = output.failed_reason.clone();
+            }
+            Err(err) => {
+                record.thread = input_thread.map(|t| t.to_string()).unwrap_or_default();
+                record.failed_reason = Some(err.to_string());
+            }
+        }
+        exporter.record_run(record);
+        res
+    }
+
+    async fn run_agent_turn(
         &self,
         caller: Principal,
         mut input: AgentInput,
+        api_key: bool,
+        cancellation_token: Option<CancellationToken>,
     ) -> Result<AgentOutput, BoxError> {
+        self.ensure_writable()?;
         let mut meta = input.meta.unwrap_or_default();
         if meta.engine.is_some() && meta.engine != Some(self.id) {
             return Err(format!(
@@ -259,6 +622,18 @@ impl Engine {
             )
             .into());
         }
+        if meta.is_expired(unix_ms()) {
+            return Err("deadline exceeded".into());
+        }
+        if input.prompt.is_empty()
+            && let Some(callback) = &meta.callback
+        {
+            input.prompt = callback.to_prompt();
+        }
+        input.prompt = normalize_text(&input.prompt);
+        if meta.lang.is_none() {
+            meta.lang = detect_language(&input.prompt).map(String::from);
+        }
 
         input.name = if input.name.is_empty() {
             self.default_agent.clone()
@@ -271,43 +646,692 @@ impl Engine {
             .get(&input.name)
             .ok_or_else(|| format!("agent {} not found", input.name))?;
 
-        let visibility = self.management.try_get_visibility(&caller)?;
-        let mut sw = if visibility == Visibility::Public {
-            // use anonymous user state for public
-            self.management.load_user_state(&ANONYMOUS).await?
-        } else {
-            let sw = self.management.load_user_state(&caller).await?;
-            if !sw.has_permission(&caller, unix_ms()) {
-                return Err("caller does not have permission".into());
+        let mut sw = self.load_caller_state(&caller, api_key).await?;
+        let mut payer = caller;
+        if let Some(person) = self.link_person(&caller, &mut meta, api_key).await? {
+            sw = person;
+            payer = *sw.user();
+        }
+        self.management.consume_quota(&payer).await?;
+
+        let session = match self
+            .route_session(&caller, &mut meta, &mut input.prompt)
+            .await?
+        {
+            SessionRoute::Reset => {
+                return Ok(AgentOutput {
+                    content: "A new conversation is started.".to_string(),
+                    ..Default::default()
+                });
             }
-            sw
+            SessionRoute::Session(account) => Some(account),
+            SessionRoute::Unmanaged => None,
         };
 
-        let thread = self
+        // claim the thread before loading its metadata, so that other runs sharing the store
+        // will not run it concurrently or overwrite its metadata with a stale copy.
+        // the guard releases the lease if a step below returns early
+        let run = new_xid().to_string();
+        let mut lease = match &meta.thread {
+            Some(id) => self.hold_thread(&run, id).await?,
+            None => None,
+        };
+        let mut thread = self
             .management
             .load_thread_meta(&caller, &meta.thread)
             .await?;
+        if lease.is_none() {
+            lease = self.hold_thread(&run, &thread.id).await?;
+        }
+        let driven = self.driven_turn(&mut thread, &mut meta)?;
+
+        let input_thread = meta.thread.replace(thread.id.clone());
+        let permit = self.ctx.scheduler.acquire(meta.priority).await;
+        let ctx = self
+            .ctx_with(caller, &input.name, meta.clone())?
+            .with_permit(permit);
+        let _cancel_guard = ctx.base.cancel_on(cancellation_token);
+
+        let mut truncations: Vec<Truncation> = Vec::new();
+        if let Some(limit) = &self.ctx.limits.prompt {
+            match ctx
+                .fit_text("prompt".to_string(), input.prompt, limit)
+                .await
+            {
+                Ok((prompt, truncation)) => {
+                    input.prompt = prompt;
+                    truncations.extend(truncation);
+                }
+                Err(err) => {
+                    return Ok(AgentOutput {
+                        failed_reason: Some(err.to_string()),
+                        thread: input_thread,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        let mut resource_statuses = None;
+        if let Some(resources) = input.resources.take()
+            && !resources.is_empty()
+        {
+            let (loaded, mut statuses) =
+                load_resources(self.resource_loader.as_ref(), &ctx.base, resources).await;
+            let loaded = ctx
+                .fit_resources(loaded, &mut statuses, &mut truncations)
+                .await;
+            if loaded.len() < statuses.len() {
+                let failed = failed_resources_list(&statuses);
+                match meta.resource_failure.unwrap_or(self.resource_failure) {
+                    ResourceFailurePolicy::Fail => {
+                        return Ok(AgentOutput {
+                            failed_reason: Some(format!("failed to load resources:\n{}", failed)),
+                            thread: input_thread,
+                            resource_statuses: Some(statuses),
+                            ..Default::default()
+                        });
+                    }
+                    ResourceFailurePolicy::AskUser => {
+                        return Ok(AgentOutput {
+                            content: format!(
+                                "Some attached resources could not be loaded:\n{}\n\nPlease attach them again, or confirm to continue without them.",
+                                failed
+                            ),
+                            thread: input_thread,
+                            resource_statuses: Some(statuses),
+                            ..Default::default()
+                        });
+                    }
+                    ResourceFailurePolicy::SkipWithNote => {
+                        for s in statuses.iter_mut() {
+                            if s.state == ResourceState::Failed {
+                                s.state = ResourceState::Skipped;
+                            }
+                        }
+                        input.prompt = format!(
+                            "{}\n\nNote: the following attached resources could not be loaded and were skipped:\n{}",
+                            input.prompt, failed
+                        );
+                    }
+                }
+            }
+            input.resources = Some(loaded);
+            resource_statuses = Some(statuses);
+        }
 
-        meta.thread = Some(thread.id.clone());
-        let ctx = self.ctx_with(caller, &input.name, meta.clone())?;
         self.hooks
             .on_agent_start(&ctx, &input.name, &thread, &mut sw)
             .await?;
 
         sw.increment_agent_requests(unix_ms());
         self.management.save_user_state(sw.state).await?;
-        // should save the thread meta before running the agent
-        self.management.save_thread_meta(thread).await?;
-
-        let output = agent
-            .run(ctx.clone(), input.prompt, input.resources)
-            .await?;
-        let mut output = self.hooks.on_agent_end(&ctx, &input.name, output).await?;
+        let thread_id = thread.id.clone();
+        let prompt = input.prompt.clone();
+        let output = match driven {
+            Some(DrivenTurn::Workflow(workflow, started)) => {
+                let mut progress = thread
+                    .workflow
+                    .take()
+                    .unwrap_or_else(|| WorkflowProgress::new(&workflow, unix_ms()));
+                let waiting = progress.waiting.clone();
+                let output = ctx
+                    .workflow_turn(&workflow, &mut progress, &input.prompt, started)
+                    .await;
+                let suspended = (started || progress.waiting != waiting)
+                    .then(|| suspended_workflow(&thread_id, &progress, caller, meta.user.clone()));
+                thread.workflow = Some(progress);
+                self.management.save_thread_meta(thread).await?;
+                if let Some(suspended) = suspended {
+                    self.management
+                        .set_suspended_workflow(&thread_id, suspended)
+                        .await?;
+                }
+                output
+            }
+            Some(DrivenTurn::Form(form)) => {
+                let mut state = thread
+                    .form
+                    .take()
+                    .unwrap_or_else(|| SlotState::new(&form, unix_ms()));
+                let output = ctx.fill_slots(&form, &mut state, &input.prompt).await;
+                thread.form = Some(state);
+                self.management.save_thread_meta(thread).await?;
+                output
+            }
+            None => {
+                // should save the thread meta before running the agent
+                self.management.save_thread_meta(thread).await?;
+                agent.run(ctx.clone(), input.prompt, input.resources).await
+            }
+        };
+        if let Some(account) = &session
+            && let Err(err) = self
+                .management
+                .record_session_turn(&caller, account, &thread_id, unix_ms())
+                .await
+        {
+            log::warn!(thread = thread_id.to_string(); "failed to record the session turn: {}", err);
+        }
+        if let Some(lease) = lease {
+            let _ = lease.release().await;
+        }
+        let mut output = self.hooks.on_agent_end(&ctx, &input.name, output?).await?;
+        if let Err(err) = self
+            .management
+            .charge_quota_tokens(
+                &payer,
+                output.usage.input_tokens + output.usage.output_tokens,
+            )
+            .await
+        {
+            log::warn!(caller = payer.to_text(); "failed to charge the quota: {}", err);
+        }
+        if let Some(encryptor) = &self.thread_encryptor
+            && caller != ANONYMOUS
+        {
+            let redact = |text: &str| match &self.redactor {
+                Some(redactor) => redactor.redact(text).into_owned(),
+                None => text.to_string(),
+            };
+            let record = to_cbor_bytes(&Versioned(vec![
+                ThreadMessage {
+                    id: new_xid(),
+                    role: "user".to_string(),
+                    content: redact(&prompt).into(),
+                    name: Some(caller.to_text()),
+                },
+                ThreadMessage {
+                    id: new_xid(),
+                    role: "assistant".to_string(),
+                    content: redact(&output.content).into(),
+                    name: Some(input.name.clone()),
+                },
+            ]));
+            match encryptor.encrypt(&ctx.base, &caller, &record).await {
+                Ok(record) => {
+                    self.management
+                        .append_thread_record(&thread_id, record)
+                        .await?
+                }
+                Err(err) => {
+                    log::error!(thread = thread_id.to_string(); "failed to encrypt thread record: {}", err)
+                }
+            }
+        }
         output.thread = meta.thread;
+        output.resource_statuses = resource_statuses;
         output.full_history = None; // clear full history
+        if let Some(run_truncations) = output.truncations.take() {
+            truncations.extend(run_truncations);
+        }
+        if !truncations.is_empty() {
+            output.truncations = Some(truncations);
+        }
+        if let Some(issuer) = &self.receipt_issuer {
+            match issuer
+                .issue(&self.ctx.base, &input.name, caller, &prompt, &output)
+                .await
+            {
+                Ok(receipt) => {
+                    let issuer = issuer.clone();
+                    let ctx = self.ctx.base.clone();
+                    let rec = receipt.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = issuer.append(&ctx, &rec).await {
+                            log::error!("failed to append receipt: {}", err);
+                        }
+                    });
+                    output.receipt = Some(receipt);
+                }
+                Err(err) => log::error!("failed to issue receipt: {}", err),
+            }
+        }
         Ok(output)
     }
 
+    /// Claims the thread for a run, if leases are enabled.
+    async fn hold_thread(&self, run: &str, id: &Xid) -> Result<Option<LeaseGuard>, BoxError> {
+        match &self.leases {
+            Some(leases) => {
+                let leases = leases.for_run(run);
+                let lease = leases.acquire(&format!("TH_{}", id.xid())).await?;
+                Ok(Some(leases.hold(lease, CancellationToken::new())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Selects the workflow or form that the engine drives in the thread instead of the agent:
+    /// the one started by the request, which replaces the current one, or the one in progress.
+    fn driven_turn(
+        &self,
+        thread: &mut ThreadMeta,
+        meta: &mut RequestMeta,
+    ) -> Result<Option<DrivenTurn>, BoxError> {
+        if let Some(name) = meta.workflow.take() {
+            let workflow = self
+                .workflows
+                .get(&name)
+                .ok_or_else(|| format!("workflow {} not found", name))?;
+            thread.workflow = Some(WorkflowProgress::new(workflow, unix_ms()));
+            thread.form = None;
+            return Ok(Some(DrivenTurn::Workflow(workflow.clone(), true)));
+        }
+        if let Some(name) = meta.form.take() {
+            let form = self
+                .forms
+                .get(&name)
+                .ok_or_else(|| format!("form {} not found", name))?;
+            thread.form = Some(SlotState::new(form, unix_ms()));
+            thread.workflow = None;
+            return Ok(Some(DrivenTurn::Form(form.clone())));
+        }
+
+        if let Some(workflow) = thread
+            .workflow
+            .as_ref()
+            .filter(|p| !p.is_completed())
+            .and_then(|p| self.workflows.get(&p.workflow))
+        {
+            return Ok(Some(DrivenTurn::Workflow(workflow.clone(), false)));
+        }
+        Ok(thread
+            .form
+            .as_ref()
+            .filter(|s| !s.completed)
+            .and_then(|s| self.forms.get(&s.form))
+            .map(|form| DrivenTurn::Form(form.clone())))
+    }
+
+    /// Resumes the workflow suspended in the thread by a webhook event, with the payload of
+    /// the event as the output of the waiting step. Only the controller and managers can
+    /// deliver events, e.g. from the webhook handler of a server.
+    ///
+    /// The messages of the next steps are returned, and delivered to the user through the
+    /// [`ReminderNotifier`] if reminders are enabled.
+    pub async fn resume_workflow(
+        &self,
+        caller: &Principal,
+        thread_id: &Xid,
+        event: &str,
+        payload: Value,
+    ) -> Result<AgentOutput, BoxError> {
+        self.ensure_writable()?;
+        if !self.management.is_manager(caller) {
+            return Err("caller is not allowed to resume workflows".into());
+        }
+        let suspended = self
+            .management
+            .list_suspended_workflows()
+            .await?
+            .into_iter()
+            .find(|s| &s.thread == thread_id)
+            .ok_or_else(|| format!("no workflow is suspended in thread {}", thread_id))?;
+        if suspended.event.as_deref() != Some(event) {
+            return Err(format!("thread {} is not waiting for event {}", thread_id, event).into());
+        }
+
+        let output =
+            resume_thread_workflow(&self.management, &self.workflows, &suspended, Some(payload))
+                .await?;
+        if let Some(notifier) = &self.reminder_notifier {
+            let reminder = follow_up_reminder(&suspended, &output.content);
+            deliver_reminder(
+                &self.ctx.base,
+                &self.management,
+                &self.thread_encryptor,
+                notifier.as_ref(),
+                &reminder,
+                &suspended.workflow,
+            )
+            .await;
+        }
+        Ok(output)
+    }
+
+    /// Rotates the key of the purpose. Only the controller can rotate keys.
+    pub async fn rotate_key(
+        &self,
+        caller: &Principal,
+        purpose: &str,
+    ) -> Result<KeyRotation, BoxError> {
+        self.ensure_writable()?;
+        if !self.management.is_controller(caller) {
+            return Err("caller is not the controller".into());
+        }
+        let keys = self
+            .key_managers
+            .get(purpose)
+            .ok_or_else(|| format!("key manager {} not found", purpose))?;
+        keys.rotate(&self.ctx.base).await
+    }
+
+    /// Returns the key epochs of the purpose, oldest first.
+    pub async fn key_epochs(&self, purpose: &str) -> Vec<KeyEpoch> {
+        match self.key_managers.get(purpose) {
+            Some(keys) => keys.epochs().await,
+            None => Vec::new(),
+        }
+    }
+
+    /// Accepts a key rotation announced by a peer engine.
+    /// If the peer's key of the purpose is known, the rotation must be signed by it.
+    pub async fn key_rotated(
+        &self,
+        caller: Principal,
+        rotation: KeyRotation,
+    ) -> Result<(), BoxError> {
+        self.ensure_writable()?;
+        if caller != rotation.engine {
+            return Err(format!(
+                "caller {} is not the engine {}",
+                caller.to_text(),
+                rotation.engine.to_text()
+            )
+            .into());
+        }
+        rotation.verify()?;
+        self.management.save_peer_key_rotation(rotation).await
+    }
+
+    /// Returns today's action budget usage of the agent.
+    /// Only the controller and managers can read it.
+    pub async fn budget_usage(
+        &self,
+        caller: &Principal,
+        agent: &str,
+    ) -> Result<BudgetUsage, BoxError> {
+        if !self.management.is_manager(caller) {
+            return Err("caller is not a manager".into());
+        }
+        self.management
+            .get_budget_usage(&agent.to_ascii_lowercase())
+            .await
+    }
+
+    /// Returns the remaining quota of the caller, or of another principal for the controller
+    /// and managers. Returns None if the principal has no quota.
+    pub async fn remaining_quota(
+        &self,
+        caller: &Principal,
+        principal: Option<Principal>,
+    ) -> Result<Option<RemainingQuota>, BoxError> {
+        let principal = match principal {
+            Some(principal) if &principal != caller => {
+                if !self.management.is_manager(caller) {
+                    return Err("caller is not a manager".into());
+                }
+                principal
+            }
+            _ => *caller,
+        };
+        Ok(self.management.get_remaining_quota(&principal).await)
+    }
+
+    /// Returns the remaining quota of an API key, like [`Engine::remaining_quota`] for the
+    /// key's principal. The key is verified, but the call is not charged to its limits.
+    pub async fn remaining_quota_with_api_key(
+        &self,
+        secret: &str,
+        principal: Option<Principal>,
+    ) -> Result<Option<RemainingQuota>, BoxError> {
+        let caller = self
+            .management
+            .verify_api_key(secret, ApiKeyTarget::Quota)
+            .await?;
+        self.remaining_quota(&caller, principal).await
+    }
+
+    /// Creates an API key for a third-party caller. Returns the key and its secret,
+    /// which is not stored and cannot be retrieved again.
+    /// Only the controller and managers can create API keys.
+    pub async fn create_api_key(
+        &self,
+        caller: &Principal,
+        args: CreateApiKeyArgs,
+    ) -> Result<(ApiKey, String), BoxError> {
+        self.ensure_writable()?;
+        if !self.management.is_manager(caller) {
+            return Err("caller is not a manager".into());
+        }
+        self.management.create_api_key(*caller, args).await
+    }
+
+    /// Returns the API keys and their usage.
+    /// Only the controller and managers can list them.
+    pub async fn api_keys(
+        &self,
+        caller: &Principal,
+    ) -> Result<Vec<(ApiKey, ApiKeyUsage)>, BoxError> {
+        if !self.management.is_manager(caller) {
+            return Err("caller is not a manager".into());
+        }
+        let keys = self.management.list_api_keys().await?;
+        let mut res = Vec::with_capacity(keys.len());
+        for key in keys {
+            let usage = self.management.get_api_key_usage(&key.id).await;
+            res.push((key, usage));
+        }
+        Ok(res)
+    }
+
+    /// Replaces the secret of an API key and returns the new one.
+    /// Only the controller and managers can rotate API keys.
+    pub async fn rotate_api_key(&self, caller: &Principal, id: &Xid) -> Result<String, BoxError> {
+        self.ensure_writable()?;
+        if !self.management.is_manager(caller) {
+            return Err("caller is not a manager".into());
+        }
+        self.management.rotate_api_key(id).await
+    }
+
+    /// Revokes an API key.
+    /// Only the controller and managers can revoke API keys.
+    pub async fn revoke_api_key(&self, caller: &Principal, id: &Xid) -> Result<ApiKey, BoxError> {
+        self.ensure_writable()?;
+        if !self.management.is_manager(caller) {
+            return Err("caller is not a manager".into());
+        }
+        self.management.revoke_api_key(id).await
+    }
+
+    /// Returns the known keys of a peer engine, by purpose.
+    pub async fn peer_keys(&self, peer: &Principal) -> BTreeMap<String, Vec<KeyEpoch>> {
+        self.management.get_peer_keys(peer).await
+    }
+
+    /// Returns the records of the thread, encrypted for the caller with vetKD.
+    /// Records are only stored if the engine has a [`ThreadEncryptor`]. A decrypted record
+    /// is decoded with `Versioned::<Vec<ThreadMessage>>::decode`.
+    pub async fn thread_records(
+        &self,
+        caller: Principal,
+        thread_id: &Xid,
+    ) -> Result<Vec<ByteBuf>, BoxError> {
+        let thread = self.management.get_thread_meta(thread_id).await?;
+        if !thread.has_permission(&caller) {
+            return Err(format!(
+                "caller {} does not have permission to access the thread {}",
+                caller.to_text(),
+                thread_id
+            )
+            .into());
+        }
+        self.management.get_thread_records(thread_id).await
+    }
+
+    /// Returns the model error counts per class, for metrics and alerting.
+    pub fn model_errors(&self) -> BTreeMap<ProviderErrorKind, u64> {
+        self.ctx.model.errors()
+    }
+
+    /// Returns the progress and results of the store maintenance jobs, for metrics.
+    pub fn maintenance_stats(&self) -> Vec<MaintenanceStats> {
+        self.maintenance
+            .as_ref()
+            .map(|m| m.stats())
+            .unwrap_or_default()
+    }
+
+    /// Returns the lease manager if the engine runs in a multi-instance deployment.
+    pub fn leases(&self) -> Option<&LeaseManager> {
+        self.leases.as_ref()
+    }
+
+    /// Returns the version of the agent, pushed by a fleet controller or set with
+    /// [`EngineBuilder::with_agent_version`].
+    pub fn agent_version(&self, agent: &str) -> String {
+        self.fleet_config
+            .read()
+            .unwrap()
+            .agent_versions
+            .get(agent)
+            .or_else(|| self.agent_versions.get(agent))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the health of the engine, for fleet controllers.
+    pub fn health(&self) -> EngineHealth {
+        let config = self.fleet_config.read().unwrap();
+        let mut agent_versions = self.agent_versions.clone();
+        agent_versions.extend(config.agent_versions.clone());
+        EngineHealth {
+            id: self.id,
+            name: self.name.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: self.started_at,
+            checked_at: unix_ms(),
+            read_only: self.is_read_only(),
+            config_version: config.version,
+            agent_versions,
+            model_errors: self.model_errors(),
+        }
+    }
+
+    /// Returns the metrics of the engine.
+    pub fn metrics(&self) -> EngineMetrics {
+        EngineMetrics {
+            id: self.id,
+            name: self.name.clone(),
+            model_errors: self.model_errors(),
+            maintenance: self.maintenance_stats(),
+            run_exporter: self.run_exporter_stats(),
+            telemetry: self.telemetry_stats(),
+        }
+    }
+
+    /// Returns the config pushed by a fleet controller.
+    pub fn fleet_config(&self) -> FleetConfig {
+        self.fleet_config.read().unwrap().clone()
+    }
+
+    /// Applies a config pushed by a fleet controller, who must be the controller or a manager
+    /// of the engine. The config is persisted, and must not be older than the current one.
+    /// Pushing the current config again is a no-op.
+    pub async fn apply_fleet_config(
+        &self,
+        caller: &Principal,
+        config: FleetConfig,
+    ) -> Result<(), BoxError> {
+        if !self.management.is_manager(caller) {
+            return Err("caller does not have permission".into());
+        }
+        for agent in config.agent_versions.keys() {
+            if !self.ctx.agents.contains(agent) {
+                return Err(format!("agent {} not found", agent).into());
+            }
+        }
+
+        if self.management.save_fleet_config(&config).await? {
+            *self.fleet_config.write().unwrap() = config;
+        }
+        Ok(())
+    }
+
+    /// Returns the statuses of the blue/green rollouts of agents.
+    pub fn rollouts(&self) -> Vec<RolloutStatus> {
+        self.rollouts.values().map(|r| r.status()).collect()
+    }
+
+    /// Sets the percentage of the requests for an agent routed to the candidate of its
+    /// rollout, which resumes a rolled back rollout. The caller must be a manager.
+    pub fn set_rollout_percent(
+        &self,
+        caller: &Principal,
+        agent: &str,
+        percent: u8,
+    ) -> Result<RolloutStatus, BoxError> {
+        if !self.management.is_manager(caller) {
+            return Err("caller does not have permission".into());
+        }
+        let rollout = self
+            .rollouts
+            .get(&agent.to_ascii_lowercase())
+            .ok_or_else(|| format!("rollout of agent {} not found", agent))?;
+        rollout.set_percent(percent);
+        Ok(rollout.status())
+    }
+
+    /// Returns the stats of the telemetry reporter, if any.
+    pub fn telemetry_stats(&self) -> Option<TelemetryStats> {
+        self.telemetry.as_ref().map(|t| t.stats())
+    }
+
+    /// Returns the stats of the run exporter, if any.
+    pub fn run_exporter_stats(&self) -> Option<ExporterStats> {
+        self.run_exporter.as_ref().map(|e| e.stats())
+    }
+
+    /// Submits the caller's feedback on the answers in a thread, exported by the
+    /// [`RunExporter`] for analytics and counted by the [`Rollout`] that answered the thread.
+    /// The score is e.g. 1 for thumbs up and -1 for thumbs down.
+    pub async fn submit_feedback(
+        &self,
+        caller: Principal,
+        thread_id: &Xid,
+        score: i8,
+        comment: Option<String>,
+    ) -> Result<(), BoxError> {
+        if self.run_exporter.is_none() && self.rollouts.is_empty() {
+            return Err("feedback is not enabled".into());
+        }
+        let thread = self.management.get_thread_meta(thread_id).await?;
+        if !thread.has_permission(&caller) {
+            return Err("caller does not have permission".into());
+        }
+        for rollout in self.rollouts.values() {
+            if rollout.record_feedback(thread_id, score).await {
+                break;
+            }
+        }
+        let Some(exporter) = &self.run_exporter else {
+            return Ok(());
+        };
+        exporter.record_feedback(FeedbackRecord {
+            engine: self.id.to_text(),
+            thread: thread_id.to_string(),
+            caller: caller.to_text(),
+            score,
+            comment,
+            created_at: unix_ms(),
+        });
+        Ok(())
+    }
+
+    /// Returns true if the engine is in read-only mode, see [`EngineBuilder::with_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.is_some()
+    }
+
+    fn ensure_writable(&self) -> Result<(), BoxError> {
+        if self.read_only.is_some() {
+            return Err("engine is read-only".into());
+        }
+        Ok(())
+    }
+
     /// Calls a tool by name with the specified arguments.
     /// Returns tuple containing the result string and a boolean indicating if further processing is needed.
     pub async fn tool_call(
@@ -315,8 +1339,63 @@ impl Engine {
         caller: Principal,
         input: ToolInput<Value>,
     ) -> Result<ToolOutput<Value>, BoxError> {
-        let args = serde_json::to_string(&input.args)?;
-        let meta = input.meta.unwrap_or_default();
+        self.call_tool(caller, input, false).await
+    }
+
+    /// Calls a tool for a third-party caller authenticated by an API key.
+    /// The key must be scoped to the tool, and the call is charged to its limits.
+    pub async fn tool_call_with_api_key(
+        &self,
+        secret: &str,
+        input: ToolInput<Value>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        let target = ApiKeyTarget::Tool(&input.name);
+        // a read-only engine does not write the key's usage to the replicated stores
+        let caller = if self.read_only.is_some() {
+            self.management.verify_api_key(secret, target).await?
+        } else {
+            self.management.authorize_api_key(secret, target).await?
+        };
+        self.call_tool(caller, input, true).await
+    }
+
+    /// Calls a tool, and records the call if the engine has [`Telemetry`].
+    async fn call_tool(
+        &self,
+        caller: Principal,
+        input: ToolInput<Value>,
+        api_key: bool,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        let res = self.call_tool_inner(caller, input, api_key).await;
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_tool_call(api_key, &res);
+        }
+        res
+    }
+
+    /// Returns the tool if it is exported, and allowed by a read-only engine.
+    fn exported_tool(&self, name: &str) -> Result<&dyn ToolDyn<BaseCtx>, BoxError> {
+        if !self.export_tools.contains(name) {
+            return Err(format!("tool {} not found", name).into());
+        }
+        if let Some(tools) = &self.read_only
+            && !tools.contains(name)
+        {
+            return Err(format!("engine is read-only, tool {} is not allowed", name).into());
+        }
+        self.ctx
+            .tools
+            .get(name)
+            .ok_or_else(|| format!("tool {} not found", name).into())
+    }
+
+    async fn call_tool_inner(
+        &self,
+        caller: Principal,
+        mut input: ToolInput<Value>,
+        api_key: bool,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        let mut meta = input.meta.take().unwrap_or_default();
         if meta.engine.is_some() && meta.engine != Some(self.id) {
             return Err(format!(
                 "invalid engine ID, expected {}, got {}",
@@ -324,39 +1403,115 @@ impl Engine {
                 meta.engine.unwrap().to_text()
             )
             .into());
-        }
-
-        if !self.export_tools.contains(&input.name) || !self.ctx.tools.contains(&input.name) {
-            return Err(format!("tool {} not found", &input.name).into());
-        }
-        let tool = self
-            .ctx
-            .tools
-            .get(&input.name)
-            .ok_or_else(|| format!("tool {} not found", &input.name))?;
+        }
+        if meta.is_expired(unix_ms()) {
+            return Err("deadline exceeded".into());
+        }
 
-        let visibility = self.management.try_get_visibility(&caller)?;
-        let mut sw = if visibility == Visibility::Public {
-            // use anonymous user state for public
-            self.management.load_user_state(&ANONYMOUS).await?
-        } else {
-            let sw = self.management.load_user_state(&caller).await?;
-            if !sw.has_permission(&caller, unix_ms()) {
-                return Err("caller does not have permission".into());
-            }
-            sw
-        };
+        self.exported_tool(&input.name)?;
+        let mut sw = self.load_caller_state(&caller, api_key).await?;
+        let mut payer = caller;
+        if let Some(person) = self.link_person(&caller, &mut meta, api_key).await? {
+            sw = person;
+            payer = *sw.user();
+        }
+        // a read-only engine does not write quota usage to the replicated stores
+        if self.read_only.is_none() {
+            self.management.consume_quota(&payer).await?;
+        }
 
+        let _permit = self.ctx.scheduler.acquire(meta.priority).await;
         let ctx = self.ctx.child_base_with(caller, &input.name, meta)?;
+        let _cancel_guard = ctx.cancel_on(None);
         self.hooks.on_tool_start(&ctx, &input.name, &mut sw).await?;
+        self.hooks.before_tool_call(&ctx, &mut input).await?;
+        // the hooks can rename the tool, so it is looked up after them
+        let tool = self.exported_tool(&input.name)?;
+        let args = serde_json::to_string(&input.args)?;
+
+        if self.read_only.is_some() {
+            if self.management.requires_approval(&input.name) {
+                return Err(format!(
+                    "engine is read-only, tool {} requires approval",
+                    &input.name
+                )
+                .into());
+            }
+            let output = ctx
+                .until_cancelled(tool.call(ctx.clone(), args, input.resources))
+                .await?;
+            let output = self
+                .hooks
+                .after_tool_call(&ctx, &input.name, output)
+                .await?;
+            return self.hooks.on_tool_end(&ctx, &input.name, output).await;
+        }
 
         sw.increment_tool_requests(unix_ms());
         self.management.save_user_state(sw.state).await?;
 
-        let output = tool.call(ctx.clone(), args, input.resources).await?;
+        let output = if self.management.requires_approval(&input.name) {
+            let call = self
+                .management
+                .request_approval(caller, input.name.clone(), args, input.resources)
+                .await?;
+            call.pending_output()
+        } else {
+            ctx.until_cancelled(tool.call(ctx.clone(), args, input.resources))
+                .await?
+        };
+        let output = self
+            .hooks
+            .after_tool_call(&ctx, &input.name, output)
+            .await?;
         self.hooks.on_tool_end(&ctx, &input.name, output).await
     }
 
+    /// Returns the tool calls waiting for operator approval.
+    /// Only the controller and managers can list them.
+    pub async fn pending_tool_calls(
+        &self,
+        caller: &Principal,
+    ) -> Result<Vec<PendingToolCall>, BoxError> {
+        if !self.management.is_manager(caller) {
+            return Err("caller is not a manager".into());
+        }
+        self.management.pending_tool_calls().await
+    }
+
+    /// Submits an operator's signature of a pending tool call.
+    /// The call is executed once it has the threshold of signatures, and its output is returned.
+    pub async fn approve_tool_call(
+        &self,
+        id: &Xid,
+        public_key: [u8; 32],
+        signature: [u8; 64],
+    ) -> Result<Option<ToolOutput<Value>>, BoxError> {
+        self.ensure_writable()?;
+        let call = match self
+            .management
+            .approve_tool_call(id, public_key, signature)
+            .await?
+        {
+            Some(call) => call,
+            None => return Ok(None),
+        };
+
+        let tool = self
+            .ctx
+            .tools
+            .get(&call.tool)
+            .ok_or_else(|| format!("tool {} not found", &call.tool))?;
+        let ctx = self
+            .ctx
+            .child_base_with(call.caller, &call.tool, RequestMeta::default())?;
+        log::info!(id = call.id.to_string(), tool = call.tool; "executing approved tool call");
+        let output = tool.call(ctx.clone(), call.args, call.resources).await?;
+        let output = self.hooks.after_tool_call(&ctx, &call.tool, output).await?;
+        let output = self.hooks.on_tool_end(&ctx, &call.tool, output).await?;
+        Ok(Some(output))
+    }
+
     /// Returns function definitions for the specified agents.
     /// If no names are provided, returns definitions for all agents.
     pub fn agents(&self, names: Option<&[&str]>) -> Vec<Function> {
@@ -390,7 +1545,41 @@ impl Engine {
                     .collect::<Vec<_>>()
                     .as_slice(),
             )),
+            read_only: self.is_read_only(),
+        }
+    }
+
+    /// Returns the cards of the exported agents, for discovery by marketplaces.
+    /// The endpoint is empty, it is filled in by the server.
+    pub async fn agent_cards(&self) -> Vec<AgentCard> {
+        let mut public_keys = BTreeMap::new();
+        for (purpose, keys) in &self.key_managers {
+            if let Ok(epoch) = keys.current().await {
+                public_keys.insert(purpose.clone(), epoch.public_key);
+            }
         }
+
+        self.export_agents
+            .iter()
+            .filter_map(|name| {
+                let agent = self.ctx.agents.set.get(name)?;
+                let definition = agent.definition();
+                let mut input_modalities = vec!["text".to_string()];
+                input_modalities.extend(agent.supported_resource_tags());
+                Some(AgentCard {
+                    name: definition.name,
+                    description: definition.description,
+                    engine: self.id,
+                    engine_name: self.name.clone(),
+                    endpoint: "".to_string(),
+                    parameters: definition.parameters,
+                    capabilities: agent.tool_dependencies(),
+                    input_modalities,
+                    pricing: self.pricing.get(name).cloned(),
+                    public_keys: public_keys.clone(),
+                })
+            })
+            .collect()
     }
 }
 
@@ -401,6 +1590,7 @@ pub struct EngineBuilder {
     name: String,
     description: String,
     tools: ToolSet<BaseCtx>,
+    pipelines: BTreeMap<String, Arc<ToolPipeline>>,
     agents: AgentSet<AgentCtx>,
     remote: BTreeMap<String, RemoteEngineArgs>,
     model: Model,
@@ -411,6 +1601,31 @@ pub struct EngineBuilder {
     export_agents: BTreeSet<String>,
     export_tools: BTreeSet<String>,
     management: ManagementBuilder,
+    lease: Option<(String, Duration)>,
+    max_concurrency: usize,
+    tool_concurrency: usize,
+    memory: Option<Memory>,
+    tool_result_summarizer: Option<ToolResultSummarizer>,
+    session_policies: BTreeMap<String, SessionPolicy>,
+    resource_loader: Arc<dyn ResourceLoader>,
+    resource_failure: ResourceFailurePolicy,
+    thread_encryptor: Option<ThreadEncryptor>,
+    receipt_issuer: Option<ReceiptIssuer>,
+    key_managers: BTreeMap<String, KeyManager>,
+    redactor: Option<Redactor>,
+    size_limits: SizeLimits,
+    cost_ceiling: Option<f64>,
+    workflows: BTreeMap<String, Arc<Workflow>>,
+    forms: BTreeMap<String, Arc<SlotForm>>,
+    reminder_notifier: Option<Arc<dyn ReminderNotifier>>,
+    identity_links: bool,
+    pricing: BTreeMap<String, AgentPricing>,
+    maintenance: Option<MaintenanceScheduler>,
+    read_only: Option<BTreeSet<String>>,
+    agent_versions: BTreeMap<String, String>,
+    run_exporter: Option<RunExporter>,
+    telemetry: Option<Telemetry>,
+    rollouts: BTreeMap<String, Rollout>,
 }
 
 impl Default for EngineBuilder {
@@ -428,6 +1643,7 @@ impl EngineBuilder {
             name: "Anda".to_string(),
             description: "".to_string(),
             tools: ToolSet::new(),
+            pipelines: BTreeMap::new(),
             agents: AgentSet::new(),
             remote: BTreeMap::new(),
             model: Model::not_implemented(),
@@ -438,6 +1654,31 @@ impl EngineBuilder {
             export_agents: BTreeSet::new(),
             export_tools: BTreeSet::new(),
             management: ManagementBuilder::new(Visibility::Private, Principal::anonymous()),
+            lease: None,
+            max_concurrency: usize::MAX,
+            tool_concurrency: 1,
+            memory: None,
+            tool_result_summarizer: None,
+            session_policies: BTreeMap::new(),
+            resource_loader: Arc::new(ResourceValidator),
+            resource_failure: ResourceFailurePolicy::Fail,
+            thread_encryptor: None,
+            receipt_issuer: None,
+            key_managers: BTreeMap::new(),
+            redactor: None,
+            size_limits: SizeLimits::default(),
+            cost_ceiling: None,
+            workflows: BTreeMap::new(),
+            forms: BTreeMap::new(),
+            reminder_notifier: None,
+            identity_links: false,
+            pricing: BTreeMap::new(),
+            maintenance: None,
+            read_only: None,
+            agent_versions: BTreeMap::new(),
+            run_exporter: None,
+            telemetry: None,
+            rollouts: BTreeMap::new(),
         }
     }
 
@@ -489,6 +1730,208 @@ impl EngineBuilder {
         self
     }
 
+    /// Enables thread leases so that multiple engine instances can share the same store safely.
+    /// Each instance must use a unique `instance_id`. A thread claimed by a crashed instance
+    /// becomes available to others after `ttl`.
+    pub fn with_lease(mut self, instance_id: String, ttl: Duration) -> Self {
+        self.lease = Some((instance_id, ttl));
+        self
+    }
+
+    /// Sets the maximum number of agent runs and tool calls that execute concurrently.
+    /// Requests beyond the limit are queued by priority, interactive requests first.
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency;
+        self
+    }
+
+    /// Sets the maximum number of tool calls from one model response that execute
+    /// concurrently, 1 (sequential) by default. Usage, results and resources of the calls
+    /// are merged in the order of the calls, whatever order they complete in.
+    pub fn with_parallel_tool_calls(mut self, max_concurrency: usize) -> Self {
+        self.tool_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// Sets the conversation memory of threads. The agent that serves a thread continues
+    /// its conversation, and the older turns are summarized above the memory's token budget.
+    /// The memory is kept in plaintext, it can't be enabled with a [`ThreadEncryptor`].
+    pub fn with_memory(mut self, memory: Memory) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    /// Summarizes the tool and agent results above a token threshold before they are passed
+    /// back to the model. The full results are kept as resources of the output.
+    pub fn with_tool_result_summarizer(mut self, summarizer: ToolResultSummarizer) -> Self {
+        self.tool_result_summarizer = Some(summarizer);
+        self
+    }
+
+    /// Sets the session policy of a connector, e.g. "telegram". Requests of the connector's
+    /// users that do not set a thread are routed to the user's session thread, which the
+    /// engine resets by the policy: after an idle timeout, a maximum number of turns, or
+    /// on a command such as "/new".
+    pub fn with_session_policy(mut self, connector: &str, policy: SessionPolicy) -> Self {
+        self.session_policies.insert(connector.to_string(), policy);
+        self
+    }
+
+    /// Sets the loader for resources attached to agent requests,
+    /// [`ResourceValidator`] by default.
+    pub fn with_resource_loader(mut self, loader: Arc<dyn ResourceLoader>) -> Self {
+        self.resource_loader = loader;
+        self
+    }
+
+    /// Sets what to do when some attached resources fail to load,
+    /// unless overridden by the request. Defaults to [`ResourceFailurePolicy::Fail`].
+    pub fn with_resource_failure_policy(mut self, policy: ResourceFailurePolicy) -> Self {
+        self.resource_failure = policy;
+        self
+    }
+
+    /// Sets the maximum sizes of prompts, tool results and inline resources, and how to
+    /// truncate content above them. Applied truncations are listed in
+    /// [`AgentOutput::truncations`]. No limits by default.
+    pub fn with_size_limits(mut self, limits: SizeLimits) -> Self {
+        self.size_limits = limits;
+        self
+    }
+
+    /// Sets the maximum estimated cost of a run in USD, including its nested agent runs.
+    /// A run that reaches it fails before its next model call. The cost is estimated from
+    /// the pricing of the model, see [`Model::with_pricing`]. No ceiling by default.
+    pub fn with_cost_ceiling(mut self, usd: f64) -> Self {
+        self.cost_ceiling = Some(usd);
+        self
+    }
+
+    /// Registers a guided workflow, started in a thread by [`RequestMeta::workflow`].
+    /// While the workflow is in progress, the engine drives the thread turn by turn
+    /// instead of running the agent, and persists the progress in the thread metadata.
+    /// Steps that wait suspend the workflow until a reply, a webhook event delivered by
+    /// [`Engine::resume_workflow`], or a timestamp, which can be days later.
+    pub fn with_workflow(mut self, workflow: Workflow) -> Result<Self, BoxError> {
+        workflow.validate()?;
+        self.workflows
+            .insert(workflow.name.clone(), Arc::new(workflow));
+        Ok(self)
+    }
+
+    /// Registers a form, started in a thread by [`RequestMeta::form`]. While the form is not
+    /// completed, the engine extracts its values from the thread turn by turn instead of
+    /// running the agent, asks for the missing required fields, and returns the completed
+    /// object as JSON content.
+    pub fn with_form(mut self, form: SlotForm) -> Result<Self, BoxError> {
+        form.validate()?;
+        self.forms.insert(form.name.clone(), Arc::new(form));
+        Ok(self)
+    }
+
+    /// Enables reminders: the [`ReminderTool`] lets agents set reminders for users, and the
+    /// engine delivers due reminders to the connector through the notifier, and to the
+    /// originating thread as a thread record if thread encryption is enabled.
+    ///
+    /// Suspended workflows are also resumed when their wait ends, and their follow-ups are
+    /// delivered the same way. Without reminders, they are resumed by the next message.
+    pub fn with_reminders(mut self, notifier: Arc<dyn ReminderNotifier>) -> Self {
+        self.reminder_notifier = Some(notifier);
+        self
+    }
+
+    /// Enables identity linking: the [`IdentityTool`] lets users link their connector accounts,
+    /// e.g. Telegram, Discord and X, to their ICP principal with a verification challenge.
+    /// The engine then sets the linked principal as [`RequestMeta::person`] of requests from
+    /// the accounts, so that memory follows the person across platforms, and checks and
+    /// charges the person's user state and quota instead of the caller's.
+    pub fn with_identity_links(mut self) -> Self {
+        self.identity_links = true;
+        self
+    }
+
+    /// Encrypts the prompt and answer of every run for the caller with vetKD, and stores
+    /// them as thread records that only the caller can decrypt.
+    pub fn with_thread_encryptor(mut self, encryptor: ThreadEncryptor) -> Self {
+        self.thread_encryptor = Some(encryptor);
+        self
+    }
+
+    /// Issues a signed receipt for every run, see [`ReceiptIssuer`].
+    /// The key manager of the issuer, if any, is registered with the engine.
+    pub fn with_receipt_issuer(mut self, issuer: ReceiptIssuer) -> Self {
+        if let Some(keys) = issuer.key_manager() {
+            self.key_managers
+                .insert(keys.purpose().to_string(), keys.clone());
+        }
+        self.receipt_issuer = Some(issuer);
+        self
+    }
+
+    /// Redacts secrets from everything the engine persists: store objects and thread records.
+    /// Use [`RedactingWriter`](crate::redaction::RedactingWriter) to redact logs as well.
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Sets the maintenance jobs of the stores, run in the background while the engine runs.
+    pub fn with_maintenance(mut self, maintenance: MaintenanceScheduler) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    /// Puts the engine in read-only mode, to serve thread reads, knowledge queries and agent
+    /// discovery against replicated stores, independent of inference capacity.
+    /// A read-only engine does not accept agent runs nor management changes, and it does not
+    /// run reminders, maintenance jobs and leases. Only the given tools can be called, they
+    /// must not write to the stores nor require approval; the caller's state, quota and API
+    /// key usage are not updated by their calls.
+    pub fn with_read_only(mut self, tools: Vec<String>) -> Self {
+        self.read_only = Some(tools.into_iter().collect());
+        self
+    }
+
+    /// Sets the version of an agent, recorded in the runs exported by the [`RunExporter`].
+    pub fn with_agent_version(mut self, agent: &str, version: &str) -> Self {
+        self.agent_versions
+            .insert(agent.to_ascii_lowercase(), version.to_string());
+        self
+    }
+
+    /// Sets the exporter of run and feedback records to an analytics warehouse,
+    /// run in the background while the engine runs.
+    pub fn with_run_exporter(mut self, exporter: RunExporter) -> Self {
+        self.run_exporter = Some(exporter);
+        self
+    }
+
+    /// Enables the opt-in usage telemetry of the engine, reported in the background while
+    /// the engine runs. See [`Telemetry`] for what is reported.
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Adds a blue/green rollout that routes a percentage of the requests for its stable
+    /// agent to its candidate agent. Both agents must be registered.
+    pub fn with_rollout(mut self, rollout: Rollout) -> Self {
+        self.rollouts.insert(rollout.stable().to_string(), rollout);
+        self
+    }
+
+    /// Sets the pricing of an agent, advertised in its [`AgentCard`].
+    pub fn with_agent_pricing(mut self, agent: &str, pricing: AgentPricing) -> Self {
+        self.pricing.insert(agent.to_ascii_lowercase(), pricing);
+        self
+    }
+
+    /// Registers a key manager, so that its keys can be rotated with [`Engine::rotate_key`].
+    pub fn with_key_manager(mut self, keys: KeyManager) -> Self {
+        self.key_managers.insert(keys.purpose().to_string(), keys);
+        self
+    }
+
     /// Registers a single tool with the engine.
     /// Returns an error if the tool cannot be added.
     pub fn register_tool<T>(mut self, tool: T) -> Result<Self, BoxError>
@@ -512,6 +1955,26 @@ impl EngineBuilder {
         Ok(self)
     }
 
+    /// Registers a composite tool that chains the registered tools, exposed to the model as
+    /// one function. Its steps must only call tools registered before it, and each step goes
+    /// through the budgets and approvals of its tool.
+    pub fn register_pipeline(mut self, pipeline: ToolPipeline) -> Result<Self, BoxError> {
+        pipeline.validate()?;
+        if self.tools.contains(&pipeline.name) || self.pipelines.contains_key(&pipeline.name) {
+            return Err(format!("tool {} already exists", pipeline.name).into());
+        }
+        for step in &pipeline.steps {
+            if !self.tools.contains(&step.tool) {
+                return Err(
+                    format!("tool {} of pipeline {} not found", step.tool, pipeline.name).into(),
+                );
+            }
+        }
+        self.pipelines
+            .insert(pipeline.name.clone(), Arc::new(pipeline));
+        Ok(self)
+    }
+
     /// Registers a single agent with the engine.
     /// Verifies that all required tools are registered before adding the agent.
     /// Returns an error if any dependency is missing or if the agent cannot be added.
@@ -520,7 +1983,7 @@ impl EngineBuilder {
         T: Agent<AgentCtx> + Send + Sync + 'static,
     {
         for tool in agent.tool_dependencies() {
-            if !self.tools.contains(&tool) {
+            if !self.tools.contains(&tool) && !self.pipelines.contains_key(&tool) {
                 return Err(format!("dependent tool {} not found", tool).into());
             }
         }
@@ -539,7 +2002,7 @@ impl EngineBuilder {
             }
 
             for tool in agent.tool_dependencies() {
-                if !self.tools.contains(&tool) {
+                if !self.tools.contains(&tool) && !self.pipelines.contains_key(&tool) {
                     return Err(format!("dependent tool {} not found", tool).into());
                 }
             }
@@ -595,6 +2058,20 @@ impl EngineBuilder {
         }
 
         self.export_agents.insert(default_agent.clone());
+        for rollout in self.rollouts.values() {
+            for agent in [rollout.stable(), rollout.candidate()] {
+                if !self.agents.contains(agent) {
+                    return Err(format!("agent {} of rollout not found", agent).into());
+                }
+            }
+            if rollout.stable() == rollout.candidate()
+                || self.rollouts.contains_key(rollout.candidate())
+            {
+                return Err(format!("invalid rollout of agent {}", rollout.stable()).into());
+            }
+            self.export_agents.insert(rollout.stable().to_string());
+            self.export_agents.insert(rollout.candidate().to_string());
+        }
 
         let mut names: BTreeSet<Path> = self
             .tools
@@ -610,11 +2087,59 @@ impl EngineBuilder {
             .collect();
         names.insert(Path::from(SYSTEM_PATH));
 
+        let has_remote = !self.remote.is_empty();
         let mut remote = RemoteEngines::new();
         for (_, engine) in self.remote {
             remote.register(self.web3.as_ref(), engine).await?;
         }
 
+        if let Some(redactor) = &self.redactor {
+            self.store = self.store.with_redactor(redactor.clone());
+        }
+
+        if self.read_only.is_some() {
+            self.lease = None;
+            self.reminder_notifier = None;
+            self.maintenance = None;
+        }
+        // the encrypted threads can't be read back by the engine, it should not keep them in plaintext
+        if self.thread_encryptor.is_some() && self.memory.is_some() {
+            return Err("thread memory can not be enabled with thread encryption".into());
+        }
+        let features = self.telemetry.as_ref().map(|_| {
+            [
+                ("remote_engines", has_remote),
+                ("memory", self.memory.is_some()),
+                (
+                    "tool_result_summarizer",
+                    self.tool_result_summarizer.is_some(),
+                ),
+                ("session_policies", !self.session_policies.is_empty()),
+                ("leases", self.lease.is_some()),
+                ("thread_encryption", self.thread_encryptor.is_some()),
+                ("receipts", self.receipt_issuer.is_some()),
+                ("key_managers", !self.key_managers.is_empty()),
+                ("redaction", self.redactor.is_some()),
+                ("pipelines", !self.pipelines.is_empty()),
+                ("cost_ceiling", self.cost_ceiling.is_some()),
+                ("workflows", !self.workflows.is_empty()),
+                ("forms", !self.forms.is_empty()),
+                ("reminders", self.reminder_notifier.is_some()),
+                ("identity_links", self.identity_links),
+                ("pricing", !self.pricing.is_empty()),
+                ("maintenance", self.maintenance.is_some()),
+                ("read_only", self.read_only.is_some()),
+                ("run_exporter", self.run_exporter.is_some()),
+                ("rollouts", !self.rollouts.is_empty()),
+            ]
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect::<BTreeSet<String>>()
+        });
+        let leases = self
+            .lease
+            .map(|(holder, ttl)| LeaseManager::new(self.store.clone(), holder, ttl));
         let ctx = BaseCtx::new(
             self.id,
             self.name.clone(),
@@ -630,6 +2155,14 @@ impl EngineBuilder {
         }
 
         let management = self.management.build(&ctx);
+        if let Some(tool) = self
+            .read_only
+            .iter()
+            .flatten()
+            .find(|tool| management.requires_approval(tool))
+        {
+            return Err(format!("tool {} requires approval, it cannot be read-only", tool).into());
+        }
         let management = Arc::new(management);
         let user_state_tool = UserStateTool::new(management.clone());
         let thread_meta_tool = ThreadMetaTool::new(management.clone());
@@ -637,16 +2170,45 @@ impl EngineBuilder {
         self.tools.add(thread_meta_tool)?;
         self.export_tools.insert(UserStateTool::NAME.to_string());
         self.export_tools.insert(ThreadMetaTool::NAME.to_string());
+        if self.reminder_notifier.is_some() {
+            self.tools.add(ReminderTool::new(management.clone()))?;
+        }
+        if self.identity_links {
+            self.tools.add(IdentityTool::new(management.clone()))?;
+            self.export_tools.insert(IdentityTool::NAME.to_string());
+        }
 
         let tools = Arc::new(self.tools);
         let agents = Arc::new(self.agents);
+        let scheduler = Arc::new(Scheduler::new(self.max_concurrency));
+        if let (Some(telemetry), Some(features)) = (&self.telemetry, features) {
+            telemetry.attach(
+                &self.id,
+                self.model.clone(),
+                features,
+                agents.set.len(),
+                tools.set.len(),
+            );
+        }
         let ctx = AgentCtx::new(
             ctx,
             self.model,
             tools.clone(),
             agents.clone(),
+            scheduler.clone(),
+            Arc::new(self.size_limits),
             management.clone(),
-        );
+        )
+        .with_pipelines(Arc::new(self.pipelines))
+        .with_cost_ceiling(self.cost_ceiling)
+        .with_tool_concurrency(self.tool_concurrency)
+        .with_memory(self.memory.map(Arc::new))
+        .with_tool_result_summarizer(self.tool_result_summarizer.map(Arc::new))
+        .with_hooks(self.hooks.clone());
+
+        for keys in self.key_managers.values() {
+            keys.init(&ctx.base).await?;
+        }
 
         let meta = RequestMeta::default();
         for (name, tool) in &tools.set {
@@ -659,6 +2221,43 @@ impl EngineBuilder {
             agent.init(ct).await?;
         }
 
+        let workflows = Arc::new(self.workflows);
+        if let Some(notifier) = &self.reminder_notifier {
+            tokio::spawn(deliver_reminders(
+                ctx.base.clone(),
+                management.clone(),
+                workflows.clone(),
+                self.thread_encryptor.clone(),
+                notifier.clone(),
+            ));
+        }
+
+        let maintenance = self.maintenance.map(|maintenance| {
+            let maintenance = Arc::new(maintenance.with_scheduler(scheduler));
+            maintenance
+                .clone()
+                .spawn(ctx.base.cancellation_token.child_token());
+            maintenance
+        });
+
+        let run_exporter = self.run_exporter.map(|exporter| {
+            let exporter = Arc::new(exporter);
+            exporter
+                .clone()
+                .spawn(ctx.base.cancellation_token.child_token());
+            exporter
+        });
+
+        let (fleet_config, _) = management.get_fleet_config().await;
+
+        let telemetry = self.telemetry.map(|telemetry| {
+            let telemetry = Arc::new(telemetry);
+            telemetry
+                .clone()
+                .spawn(ctx.base.cancellation_token.child_token());
+            telemetry
+        });
+
         Ok(Engine {
             id: self.id,
             ctx,
@@ -669,6 +2268,31 @@ impl EngineBuilder {
             export_tools: self.export_tools,
             hooks: self.hooks,
             management,
+            leases,
+            resource_loader: self.resource_loader,
+            resource_failure: self.resource_failure,
+            thread_encryptor: self.thread_encryptor,
+            receipt_issuer: self.receipt_issuer,
+            key_managers: self.key_managers,
+            redactor: self.redactor,
+            workflows,
+            forms: self.forms,
+            pricing: self.pricing,
+            maintenance,
+            reminder_notifier: self.reminder_notifier,
+            read_only: self.read_only,
+            agent_versions: self.agent_versions,
+            run_exporter,
+            telemetry,
+            rollouts: self
+                .rollouts
+                .into_iter()
+                .map(|(agent, rollout)| (agent, Arc::new(rollout)))
+                .collect(),
+            fleet_config: Arc::new(RwLock::new(fleet_config)),
+            started_at: unix_ms(),
+            identity_links: self.identity_links,
+            session_policies: Arc::new(self.session_policies),
         })
     }
 
@@ -679,8 +2303,13 @@ impl EngineBuilder {
             .tools
             .set
             .keys()
-            .chain(self.agents.set.keys())
-            .map(|s| Path::from(s.as_str()))
+            .map(|p| Path::from(format!("T:{}", p)))
+            .chain(
+                self.agents
+                    .set
+                    .keys()
+                    .map(|p| Path::from(format!("A:{}", p))),
+            )
             .collect();
         names.insert(Path::from(SYSTEM_PATH));
         let ctx = BaseCtx::new(
@@ -699,7 +2328,895 @@ impl EngineBuilder {
             self.model,
             Arc::new(self.tools),
             Arc::new(self.agents),
+            Arc::new(Scheduler::new(self.max_concurrency)),
+            Arc::new(self.size_limits),
             management,
         )
+        .with_pipelines(Arc::new(self.pipelines))
+        .with_cost_ceiling(self.cost_ceiling)
+        .with_tool_concurrency(self.tool_concurrency)
+        .with_memory(self.memory.map(Arc::new))
+        .with_tool_result_summarizer(self.tool_result_summarizer.map(Arc::new))
+        .with_hooks(self.hooks)
+    }
+}
+
+/// How often the due reminders are checked.
+const REMINDER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Delivers the due reminders, and resumes the suspended workflows whose wait has ended,
+/// delivering their follow-ups, until the engine is cancelled.
+async fn deliver_reminders(
+    ctx: BaseCtx,
+    management: Arc<Management>,
+    workflows: Arc<BTreeMap<String, Arc<Workflow>>>,
+    thread_encryptor: Option<ThreadEncryptor>,
+    notifier: Arc<dyn ReminderNotifier>,
+) {
+    loop {
+        tokio::select! {
+            _ = ctx.cancellation_token.cancelled() => return,
+            _ = tokio::time::sleep(REMINDER_INTERVAL) => {}
+        }
+
+        let due = match management.take_due_reminders(unix_ms()).await {
+            Ok(due) => due,
+            Err(err) => {
+                // another engine instance may have taken them
+                log::warn!("failed to take due reminders: {}", err);
+                Vec::new()
+            }
+        };
+        for reminder in due {
+            deliver_reminder(
+                &ctx,
+                &management,
+                &thread_encryptor,
+                notifier.as_ref(),
+                &reminder,
+                ReminderTool::NAME,
+            )
+            .await;
+        }
+
+        let due = match management.take_due_workflows(unix_ms()).await {
+            Ok(due) => due,
+            Err(err) => {
+                log::warn!("failed to take due workflows: {}", err);
+                continue;
+            }
+        };
+        for suspended in due {
+            match resume_thread_workflow(&management, &workflows, &suspended, None).await {
+                Ok(output) => {
+                    let reminder = follow_up_reminder(&suspended, &output.content);
+                    deliver_reminder(
+                        &ctx,
+                        &management,
+                        &thread_encryptor,
+                        notifier.as_ref(),
+                        &reminder,
+                        &suspended.workflow,
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    log::warn!(thread = suspended.thread.to_string(); "failed to resume workflow: {}", err)
+                }
+            }
+        }
+    }
+}
+
+/// Appends the reminder to the thread if it is encrypted, and delivers it to the connector.
+async fn deliver_reminder(
+    ctx: &BaseCtx,
+    management: &Management,
+    thread_encryptor: &Option<ThreadEncryptor>,
+    notifier: &dyn ReminderNotifier,
+    reminder: &Reminder,
+    name: &str,
+) {
+    if let (Some(encryptor), Some(thread)) = (thread_encryptor, &reminder.thread) {
+        let content = if name == ReminderTool::NAME {
+            format!("Reminder: {}", reminder.message)
+        } else {
+            reminder.message.clone()
+        };
+        let record = to_cbor_bytes(&Versioned(vec![ThreadMessage {
+            id: new_xid(),
+            role: "assistant".to_string(),
+            content: content.into(),
+            name: Some(name.to_string()),
+        }]));
+        let rt = match encryptor.encrypt(ctx, &reminder.caller, &record).await {
+            Ok(record) => management.append_thread_record(thread, record).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = rt {
+            log::error!(thread = thread.to_string(); "failed to append reminder to the thread: {}", err);
+        }
+    }
+    if let Err(err) = notifier.notify(ctx, reminder).await {
+        log::error!(id = reminder.id.to_string(); "failed to deliver reminder: {}", err);
+    }
+}
+
+/// Returns the suspended workflow to index for the progress, if it waits for a webhook
+/// event or a timestamp. Workflows that wait for a reply without time limit are resumed by
+/// the reply, and are not indexed.
+fn suspended_workflow(
+    thread: &Xid,
+    progress: &WorkflowProgress,
+    caller: Principal,
+    user: Option<String>,
+) -> Option<SuspendedWorkflow> {
+    progress
+        .waiting
+        .as_ref()
+        .filter(|w| w.event.is_some() || w.until.is_some())
+        .map(|w| SuspendedWorkflow {
+            thread: thread.clone(),
+            workflow: progress.workflow.clone(),
+            caller,
+            user,
+            event: w.event.clone(),
+            until: w.until,
+            created_at: unix_ms(),
+        })
+}
+
+/// Resumes the workflow suspended in the thread with the output, None when its wait has
+/// ended, and saves the progress.
+async fn resume_thread_workflow(
+    management: &Management,
+    workflows: &BTreeMap<String, Arc<Workflow>>,
+    suspended: &SuspendedWorkflow,
+    output: Option<Value>,
+) -> Result<AgentOutput, BoxError> {
+    let mut thread = management.get_thread_meta(&suspended.thread).await?;
+    let mut progress = thread
+        .workflow
+        .take()
+        .filter(|p| p.workflow == suspended.workflow && p.waiting.is_some())
+        .ok_or_else(|| format!("no workflow is suspended in thread {}", suspended.thread))?;
+    if output.is_none() && !progress.is_due(unix_ms()) {
+        return Err(format!("the wait in thread {} has not ended", suspended.thread).into());
+    }
+    let workflow = workflows
+        .get(&progress.workflow)
+        .ok_or_else(|| format!("workflow {} not found", progress.workflow))?;
+
+    let content = resume_workflow(workflow, &mut progress, output)?;
+    let next = suspended_workflow(
+        &suspended.thread,
+        &progress,
+        suspended.caller,
+        suspended.user.clone(),
+    );
+    thread.workflow = Some(progress.clone());
+    management.save_thread_meta(thread).await?;
+    management
+        .set_suspended_workflow(&suspended.thread, next)
+        .await?;
+    Ok(AgentOutput {
+        content,
+        workflow: Some(progress),
+        thread: Some(suspended.thread.clone()),
+        ..Default::default()
+    })
+}
+
+/// Returns the follow-up of a resumed workflow as a reminder to deliver.
+fn follow_up_reminder(suspended: &SuspendedWorkflow, message: &str) -> Reminder {
+    Reminder {
+        id: new_xid(),
+        message: message.to_string(),
+        due_at: unix_ms(),
+        caller: suspended.caller,
+        thread: Some(suspended.thread.clone()),
+        user: suspended.user.clone(),
+        created_at: suspended.created_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::{calendar::CalendarTool, segmenter::DocumentSegmenter};
+    use anda_core::{AgentContext, Resource, agent, tool};
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    struct EchoAgent;
+
+    /// Echoes the prompt.
+    ///
+    /// Useful for testing.
+    #[agent(tools = ["create_calendar_event"], resource_tags = ["image"])]
+    impl Agent<AgentCtx> for EchoAgent {
+        async fn run(
+            &self,
+            _ctx: AgentCtx,
+            prompt: String,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<AgentOutput, BoxError> {
+            Ok(AgentOutput {
+                content: prompt,
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_agent_macro() {
+        assert_eq!(EchoAgent.name(), "echo_agent");
+        assert_eq!(
+            EchoAgent.description(),
+            "Echoes the prompt.\n\nUseful for testing."
+        );
+        assert_eq!(EchoAgent.tool_dependencies(), vec!["create_calendar_event"]);
+        assert_eq!(EchoAgent.supported_resource_tags(), vec!["image"]);
+
+        let engine = EngineBuilder::new()
+            .register_tool(CalendarTool::new())
+            .unwrap()
+            .register_agent(EchoAgent)
+            .unwrap()
+            .build("echo_agent".to_string())
+            .await
+            .unwrap();
+        let cards = engine.agent_cards().await;
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].name, "echo_agent");
+        assert_eq!(
+            cards[0].description,
+            "Echoes the prompt.\n\nUseful for testing."
+        );
+        assert_eq!(cards[0].capabilities, vec!["create_calendar_event"]);
+        assert_eq!(cards[0].input_modalities, vec!["text", "image"]);
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    struct EchoArgs {
+        /// The text to echo.
+        text: String,
+    }
+
+    struct EchoTool;
+
+    /// Echoes the text.
+    #[tool(strict = true)]
+    impl Tool<BaseCtx> for EchoTool {
+        type Args = EchoArgs;
+        type Output = String;
+
+        async fn call(
+            &self,
+            _ctx: BaseCtx,
+            args: Self::Args,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<ToolOutput<Self::Output>, BoxError> {
+            Ok(ToolOutput::new(args.text))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_tool_macro() {
+        let definition = EchoTool.definition();
+        assert_eq!(definition.name, "echo_tool");
+        assert_eq!(definition.description, "Echoes the text.");
+        assert_eq!(definition.strict, Some(true));
+        assert_eq!(
+            definition.parameters["properties"]["text"]["type"],
+            "string"
+        );
+        assert_eq!(
+            definition.parameters["properties"]["text"]["description"],
+            "The text to echo."
+        );
+        assert_eq!(definition.parameters["required"], json!(["text"]));
+
+        let engine = EngineBuilder::new()
+            .with_management(ManagementBuilder::new(
+                Visibility::Public,
+                Principal::anonymous(),
+            ))
+            .register_tool(EchoTool)
+            .unwrap()
+            .register_agent(DocumentSegmenter::new(100, 1000))
+            .unwrap()
+            .export_tools(vec!["echo_tool".to_string()])
+            .build("document_segmenter".to_string())
+            .await
+            .unwrap();
+        let output = engine
+            .tool_call(
+                ANONYMOUS,
+                ToolInput::new("echo_tool".to_string(), json!({"text": "hello"})),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.output, json!("hello"));
+        let err = engine
+            .tool_call(
+                ANONYMOUS,
+                ToolInput::new("echo_tool".to_string(), json!({"txt": "hello"})),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("text"), "{err}");
+    }
+
+    struct CountTool;
+
+    /// Counts the words of the text.
+    #[tool]
+    impl Tool<BaseCtx> for CountTool {
+        type Args = EchoArgs;
+        type Output = Value;
+
+        fn output_schema(&self) -> Option<Value> {
+            Some(json!({
+                "type": "object",
+                "properties": {"words": {"type": "integer"}},
+                "required": ["words"]
+            }))
+        }
+
+        async fn call(
+            &self,
+            _ctx: BaseCtx,
+            args: Self::Args,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<ToolOutput<Self::Output>, BoxError> {
+            // the text "invalid" violates the output schema
+            if args.text == "invalid" {
+                return Ok(ToolOutput::new(json!({"words": "one"})));
+            }
+            Ok(ToolOutput::new(
+                json!({"words": args.text.split_whitespace().count()}),
+            ))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_tool_output_schema() {
+        let mut tools: ToolSet<BaseCtx> = ToolSet::new();
+        tools.add(CountTool).unwrap();
+        let definition = tools.definition("count_tool").unwrap();
+        assert!(
+            definition.description.starts_with(
+                "Counts the words of the text.\n\nReturns JSON conforming to the schema: "
+            ),
+            "{}",
+            definition.description
+        );
+        assert!(definition.description.contains("\"words\""));
+        assert_eq!(EchoTool.output_schema(), None);
+
+        let engine = EngineBuilder::new()
+            .with_management(ManagementBuilder::new(
+                Visibility::Public,
+                Principal::anonymous(),
+            ))
+            .register_tool(CountTool)
+            .unwrap()
+            .register_agent(DocumentSegmenter::new(100, 1000))
+            .unwrap()
+            .export_tools(vec!["count_tool".to_string()])
+            .build("document_segmenter".to_string())
+            .await
+            .unwrap();
+        let output = engine
+            .tool_call(
+                ANONYMOUS,
+                ToolInput::new("count_tool".to_string(), json!({"text": "hello world"})),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.output, json!({"words": 2}));
+        let err = engine
+            .tool_call(
+                ANONYMOUS,
+                ToolInput::new("count_tool".to_string(), json!({"text": "invalid"})),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid output"), "{err}");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_pipeline() {
+        let pipeline: ToolPipeline = serde_json::from_value(json!({
+            "name": "echo_twice",
+            "description": "Echoes the text twice.",
+            "parameters": {"type": "object", "properties": {"text": {"type": "string"}}},
+            "steps": [
+                {"tool": "echo_tool", "args": {"text": "$.input.text"}},
+                {"tool": "echo_tool", "args": {"text": "$.steps[0]"}}
+            ],
+            "output": "$.steps"
+        }))
+        .unwrap();
+
+        let mut invalid = pipeline.clone();
+        invalid.steps[1].tool = "unknown_tool".to_string();
+        assert!(
+            EngineBuilder::new()
+                .register_tool(EchoTool)
+                .unwrap()
+                .register_pipeline(invalid)
+                .is_err()
+        );
+
+        let ctx = EngineBuilder::new()
+            .register_tool(EchoTool)
+            .unwrap()
+            .register_pipeline(pipeline)
+            .unwrap()
+            .mock_ctx();
+        let defs = ctx.tool_definitions(Some(&["echo_twice"]));
+        assert_eq!(defs.len(), 1);
+        assert_eq!(defs[0].description, "Echoes the text twice.");
+
+        let output = ctx
+            .tool_call(ToolInput::new(
+                "echo_twice".to_string(),
+                json!({"text": "hello"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(output.output, json!(["hello", "hello"]));
+        assert_eq!(output.usage.requests, 2);
+
+        let err = ctx
+            .tool_call(ToolInput::new("echo_twice".to_string(), json!({})))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("step 0"), "{err}");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_read_only_engine() {
+        let agent = DocumentSegmenter::new(100, 1000);
+        let name = agent.name();
+        let engine = EngineBuilder::new()
+            .register_agent(agent)
+            .unwrap()
+            .with_read_only(vec![])
+            .build(name.clone())
+            .await
+            .unwrap();
+        assert!(engine.is_read_only());
+        assert!(engine.information().read_only);
+        assert!(!engine.information().agents.is_empty());
+
+        let err = engine
+            .agent_run(
+                ANONYMOUS,
+                AgentInput::new(name.clone(), "hello".to_string()),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "engine is read-only");
+
+        let err = engine
+            .tool_call(
+                ANONYMOUS,
+                ToolInput::new(UserStateTool::NAME.to_string(), json!({})),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("is not allowed"));
+
+        let err = engine
+            .create_api_key(&engine.id(), CreateApiKeyArgs::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "engine is read-only");
+
+        let policy = ApprovalPolicy::new(BTreeSet::from([[1u8; 32]]), 1, Duration::from_secs(60))
+            .with_tool(UserStateTool::NAME);
+        let res = EngineBuilder::new()
+            .register_agent(DocumentSegmenter::new(100, 1000))
+            .unwrap()
+            .with_management(
+                ManagementBuilder::new(Visibility::Private, Principal::anonymous())
+                    .with_approval_policy(policy)
+                    .unwrap(),
+            )
+            .with_read_only(vec![UserStateTool::NAME.to_string()])
+            .build(name)
+            .await;
+        assert!(matches!(res, Err(err) if err.to_string().contains("requires approval")));
+    }
+
+    /// Renames every tool call to the user state tool.
+    struct RenameHook;
+
+    #[async_trait]
+    impl Hook for RenameHook {
+        async fn before_tool_call(
+            &self,
+            _ctx: &BaseCtx,
+            input: &mut ToolInput<Value>,
+        ) -> Result<(), BoxError> {
+            input.name = UserStateTool::NAME.to_string();
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_renamed_tool_requires_approval() {
+        let agent = DocumentSegmenter::new(100, 1000);
+        let name = agent.name();
+        let policy = ApprovalPolicy::new(BTreeSet::from([[1u8; 32]]), 1, Duration::from_secs(60))
+            .with_tool(UserStateTool::NAME);
+        let mut hooks = Hooks::new();
+        hooks.add(Box::new(RenameHook));
+        let engine = EngineBuilder::new()
+            .register_agent(agent)
+            .unwrap()
+            .with_management(
+                ManagementBuilder::new(Visibility::Public, Principal::anonymous())
+                    .with_approval_policy(policy)
+                    .unwrap(),
+            )
+            .with_hooks(Arc::new(hooks))
+            .build(name)
+            .await
+            .unwrap();
+
+        let output = engine
+            .tool_call(
+                ANONYMOUS,
+                ToolInput::new(ThreadMetaTool::NAME.to_string(), json!({})),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.output["status"], "pending_approval");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_expired_deadline() {
+        let agent = DocumentSegmenter::new(100, 1000);
+        let name = agent.name();
+        let engine = EngineBuilder::new()
+            .register_agent(agent)
+            .unwrap()
+            .build(name.clone())
+            .await
+            .unwrap();
+        let meta = RequestMeta {
+            deadline_ms: Some(unix_ms() - 1),
+            ..Default::default()
+        };
+
+        let mut input = AgentInput::new(name, "hello".to_string());
+        input.meta = Some(meta.clone());
+        let err = engine.agent_run(ANONYMOUS, input).await.unwrap_err();
+        assert_eq!(err.to_string(), "deadline exceeded");
+
+        let mut input = ToolInput::new(UserStateTool::NAME.to_string(), json!({}));
+        input.meta = Some(meta);
+        let err = engine.tool_call(ANONYMOUS, input).await.unwrap_err();
+        assert_eq!(err.to_string(), "deadline exceeded");
+    }
+
+    struct LoudAgent;
+
+    /// Echoes the prompt in upper case.
+    #[agent]
+    impl Agent<AgentCtx> for LoudAgent {
+        async fn run(
+            &self,
+            _ctx: AgentCtx,
+            prompt: String,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<AgentOutput, BoxError> {
+            Ok(AgentOutput {
+                content: prompt.to_uppercase(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_rollout() {
+        let err = EngineBuilder::new()
+            .register_tool(CalendarTool::new())
+            .unwrap()
+            .register_agent(EchoAgent)
+            .unwrap()
+            .with_rollout(Rollout::new("echo_agent", "loud_agent", 50))
+            .build("echo_agent".to_string())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "agent loud_agent of rollout not found");
+
+        let engine = EngineBuilder::new()
+            .register_tool(CalendarTool::new())
+            .unwrap()
+            .register_agent(EchoAgent)
+            .unwrap()
+            .register_agent(LoudAgent)
+            .unwrap()
+            .with_id(Principal::management_canister())
+            .with_management(ManagementBuilder::new(
+                Visibility::Public,
+                Principal::anonymous(),
+            ))
+            .with_rollout(
+                Rollout::new("echo_agent", "loud_agent", 100)
+                    .with_max_negative_feedback_rate(0.5, 2),
+            )
+            .build("echo_agent".to_string())
+            .await
+            .unwrap();
+
+        let output = engine
+            .agent_run(ANONYMOUS, AgentInput::new(String::new(), "hi".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(output.content, "HI");
+        let thread = output.thread.unwrap();
+        engine
+            .submit_feedback(ANONYMOUS, &thread, -1, None)
+            .await
+            .unwrap();
+        assert_eq!(engine.rollouts()[0].rolled_back, None);
+        engine
+            .submit_feedback(ANONYMOUS, &thread, -1, None)
+            .await
+            .unwrap();
+        let status = &engine.rollouts()[0];
+        assert_eq!(status.candidate_stats.runs, 1);
+        assert_eq!(status.candidate_stats.negative_feedback, 2);
+        assert!(status.rolled_back.is_some());
+
+        // rolled back to the stable agent
+        let output = engine
+            .agent_run(ANONYMOUS, AgentInput::new(String::new(), "hi".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(output.content, "hi");
+        assert_eq!(engine.rollouts()[0].stable_stats.runs, 1);
+
+        assert!(
+            engine
+                .set_rollout_percent(&ANONYMOUS, "echo_agent", 100)
+                .is_err()
+        );
+        let status = engine
+            .set_rollout_percent(&engine.id(), "Echo_Agent", 100)
+            .unwrap();
+        assert_eq!(status.percent, 100);
+        assert_eq!(status.rolled_back, None);
+        let output = engine
+            .agent_run(
+                ANONYMOUS,
+                AgentInput::new("echo_agent".to_string(), "hi".to_string()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.content, "HI");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fleet_config() {
+        let agent = DocumentSegmenter::new(100, 1000);
+        let name = agent.name();
+        let engine = EngineBuilder::new()
+            .register_agent(agent)
+            .unwrap()
+            .with_id(Principal::management_canister())
+            .with_agent_version(&name, "v1")
+            .build(name.clone())
+            .await
+            .unwrap();
+        let controller = engine.id();
+        assert_eq!(engine.agent_version(&name), "v1");
+        assert_eq!(engine.health().config_version, 0);
+
+        let config = FleetConfig {
+            version: 2,
+            agent_versions: BTreeMap::from([(name.clone(), "v2".to_string())]),
+            ..Default::default()
+        };
+        let err = engine
+            .apply_fleet_config(&ANONYMOUS, config.clone())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("permission"));
+        engine
+            .apply_fleet_config(&controller, config.clone())
+            .await
+            .unwrap();
+        // pushing the same config again is a no-op
+        engine
+            .apply_fleet_config(&controller, config.clone())
+            .await
+            .unwrap();
+        assert_eq!(engine.agent_version(&name), "v2");
+        let health = engine.health();
+        assert_eq!(health.config_version, 2);
+        assert_eq!(health.agent_versions[&name], "v2");
+
+        let stale = FleetConfig {
+            version: 1,
+            ..Default::default()
+        };
+        let err = engine
+            .apply_fleet_config(&controller, stale)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("stale"));
+        let unknown = FleetConfig {
+            version: 3,
+            agent_versions: BTreeMap::from([("unknown".to_string(), "v3".to_string())]),
+            ..Default::default()
+        };
+        assert!(
+            engine
+                .apply_fleet_config(&controller, unknown)
+                .await
+                .is_err()
+        );
+        assert_eq!(engine.fleet_config(), config);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_session_policy() {
+        let agent = DocumentSegmenter::new(100, 1000);
+        let name = agent.name();
+        let engine = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .register_agent(agent)
+            .unwrap()
+            .with_management(ManagementBuilder::new(
+                Visibility::Public,
+                Principal::anonymous(),
+            ))
+            .with_session_policy("telegram", SessionPolicy::default().with_max_turns(2))
+            .build(name.clone())
+            .await
+            .unwrap();
+        let run = |user: &str, prompt: &str| {
+            let mut input = AgentInput::new(name.clone(), prompt.to_string());
+            input.meta = Some(RequestMeta {
+                user: Some(user.to_string()),
+                ..Default::default()
+            });
+            engine.agent_run(ANONYMOUS, input)
+        };
+
+        let first = run("telegram:1", "hello").await.unwrap();
+        let thread = first.thread.clone().unwrap();
+        let output = run("telegram:1", "hello again").await.unwrap();
+        assert_eq!(output.thread.as_ref(), Some(&thread));
+        // the other users have their own sessions
+        let output = run("telegram:2", "hello").await.unwrap();
+        assert_ne!(output.thread.as_ref(), Some(&thread));
+        // connectors without a policy start a new thread every time
+        let output = run("discord:1", "hello").await.unwrap();
+        assert_ne!(output.thread.as_ref(), Some(&thread));
+
+        // the session is reset after 2 turns
+        let output = run("telegram:1", "hello").await.unwrap();
+        let thread2 = output.thread.clone().unwrap();
+        assert_ne!(thread2, thread);
+
+        // and by the /new command
+        let output = run("telegram:1", "/new").await.unwrap();
+        assert!(output.thread.is_none());
+        assert_eq!(output.content, "A new conversation is started.");
+        let output = run("telegram:1", "hello").await.unwrap();
+        assert_ne!(output.thread.as_ref(), Some(&thread2));
+        let thread3 = output.thread.clone().unwrap();
+        let output = run("telegram:1", "/new hello").await.unwrap();
+        assert_ne!(output.thread.as_ref(), Some(&thread3));
+    }
+
+    #[derive(Default)]
+    struct MockNotifier(std::sync::Mutex<Vec<Reminder>>);
+
+    #[async_trait]
+    impl ReminderNotifier for MockNotifier {
+        async fn notify(&self, _ctx: &BaseCtx, reminder: &Reminder) -> Result<(), BoxError> {
+            self.0.lock().unwrap().push(reminder.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_workflow_wait() {
+        let agent = DocumentSegmenter::new(100, 1000);
+        let name = agent.name();
+        let workflow: Workflow = serde_json::from_value(json!({
+            "name": "review",
+            "completion": "Approved!",
+            "steps": [
+                {"id": "submitted", "prompt": "We will review your request.", "wait": {"type": "webhook", "event": "reviewed"}},
+                {"id": "follow_up", "prompt": "Any questions?", "wait": {"type": "until", "after_ms": 0}},
+            ]
+        }))
+        .unwrap();
+        let notifier = Arc::new(MockNotifier::default());
+        let engine = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .register_agent(agent)
+            .unwrap()
+            .with_management(ManagementBuilder::new(
+                Visibility::Public,
+                Principal::anonymous(),
+            ))
+            .with_workflow(workflow)
+            .unwrap()
+            .with_reminders(notifier.clone())
+            .build(name.clone())
+            .await
+            .unwrap();
+
+        let mut input = AgentInput::new(name.clone(), "hi".to_string());
+        input.meta = Some(RequestMeta {
+            workflow: Some("review".to_string()),
+            user: Some("telegram:1".to_string()),
+            ..Default::default()
+        });
+        let output = engine.agent_run(ANONYMOUS, input).await.unwrap();
+        assert_eq!(output.content, "We will review your request.");
+        let thread = output.thread.unwrap();
+        let suspended = engine.management.list_suspended_workflows().await.unwrap();
+        assert_eq!(suspended.len(), 1);
+        assert_eq!(suspended[0].event.as_deref(), Some("reviewed"));
+        assert_eq!(suspended[0].user.as_deref(), Some("telegram:1"));
+
+        // messages do not resume the workflow
+        let mut input = AgentInput::new(name.clone(), "any news?".to_string());
+        input.meta = Some(RequestMeta {
+            thread: Some(thread.clone()),
+            ..Default::default()
+        });
+        let output = engine.agent_run(ANONYMOUS, input).await.unwrap();
+        assert_eq!(output.content, "We will review your request.");
+
+        let err = engine
+            .resume_workflow(&ANONYMOUS, &thread, "other", json!({}))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("is not waiting for event other"));
+        let output = engine
+            .resume_workflow(&ANONYMOUS, &thread, "reviewed", json!({"ok": true}))
+            .await
+            .unwrap();
+        assert_eq!(output.content, "Any questions?");
+        let delivered = notifier.0.lock().unwrap().clone();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].message, "Any questions?");
+        assert_eq!(delivered[0].thread.as_ref(), Some(&thread));
+
+        // the timer is due at once
+        let due = engine
+            .management
+            .take_due_workflows(unix_ms())
+            .await
+            .unwrap();
+        assert_eq!(due.len(), 1);
+        let output = resume_thread_workflow(&engine.management, &engine.workflows, &due[0], None)
+            .await
+            .unwrap();
+        assert_eq!(output.content, "Approved!");
+        assert!(output.workflow.unwrap().is_completed());
+        assert!(
+            engine
+                .management
+                .list_suspended_workflows()
+                .await
+                .unwrap()
+                .is_empty()
+        );
     }
 }
@@ -0,0 +1,330 @@
+//! Adaptive Context Module
+//!
+//! This module tunes how much context an agent packs into its completion requests. An
+//! [`AdaptiveContext`] observes, after each turn, how much of the packed context the answer
+//! actually references, and adjusts the retrieval top-k and the chat history window of the
+//! agent to what its answers use, within configured bounds, to cut token spend.
+//!
+//! References are detected without the model's attention, by heuristics on the answer:
+//! - a document is referenced if the answer cites its id, e.g. `doc_2`, or uses at least
+//!   [`AdaptiveContext::with_min_shared_words`] distinctive words of the document that are not
+//!   in the prompt;
+//! - a history message is referenced if the answer uses enough of its distinctive words.
+//!
+//! The depth of the context that is used, the rank of the last referenced document and the
+//! age of the oldest referenced message, is smoothed with an exponential moving average.
+//! When the last packed item is referenced, the context is likely too short, so the depth
+//! is observed as one more than what was packed and the window grows.
+//!
+//! # Example
+//! ```rust,ignore
+//! let adaptive = Arc::new(AdaptiveContext::new(5, 42).with_top_k_range(2, 10));
+//! let agent = character
+//!     .build(attention, segmenter, knowledge)
+//!     .with_adaptive_context(adaptive.clone());
+//! // later, e.g. for metrics
+//! let state = adaptive.state();
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, sync::RwLock};
+
+/// The default smoothing factor of the moving averages.
+pub const DEFAULT_SMOOTHING: f64 = 0.1;
+
+/// The default number of turns observed before the windows are tuned.
+pub const DEFAULT_WARMUP_TURNS: u64 = 10;
+
+/// The minimum number of characters of a distinctive word.
+const MIN_WORD_CHARS: usize = 4;
+
+/// How much of the packed context an answer references.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextUsage {
+    /// The number of packed documents.
+    pub documents: usize,
+
+    /// The rank of the last referenced document plus one, 0 if none is referenced.
+    pub document_depth: usize,
+
+    /// The number of packed history messages.
+    pub history: usize,
+
+    /// The age of the oldest referenced history message plus one, counted from the most
+    /// recent message, 0 if none is referenced.
+    pub history_depth: usize,
+}
+
+/// The tuned windows of an agent and the statistics behind them.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveState {
+    /// The number of documents to retrieve.
+    pub top_k: usize,
+
+    /// The number of history messages to pack.
+    pub history: usize,
+
+    /// The moving average of the document depth.
+    pub document_depth: f64,
+
+    /// The moving average of the history depth.
+    pub history_depth: f64,
+
+    /// The number of observed turns.
+    pub turns: u64,
+}
+
+/// Tunes the retrieval top-k and the chat history window of an agent by the usage of its
+/// context.
+#[derive(Debug)]
+pub struct AdaptiveContext {
+    top_k_range: (usize, usize),
+    history_range: (usize, usize),
+    smoothing: f64,
+    warmup_turns: u64,
+    min_shared_words: usize,
+    state: RwLock<AdaptiveState>,
+}
+
+impl AdaptiveContext {
+    /// Creates an adaptive context that starts with the given top-k and history window,
+    /// which are also the maximums of the windows.
+    pub fn new(top_k: usize, history: usize) -> Self {
+        Self {
+            top_k_range: (1, top_k),
+            history_range: (2, history),
+            smoothing: DEFAULT_SMOOTHING,
+            warmup_turns: DEFAULT_WARMUP_TURNS,
+            min_shared_words: 3,
+            state: RwLock::new(AdaptiveState {
+                top_k,
+                history,
+                document_depth: top_k as f64,
+                history_depth: history as f64,
+                turns: 0,
+            }),
+        }
+    }
+
+    /// Sets the bounds of the retrieval top-k, 1 to the initial top-k by default.
+    pub fn with_top_k_range(mut self, min: usize, max: usize) -> Self {
+        self.top_k_range = (min, max.max(min));
+        self
+    }
+
+    /// Sets the bounds of the history window, 2 to the initial window by default.
+    pub fn with_history_range(mut self, min: usize, max: usize) -> Self {
+        self.history_range = (min, max.max(min));
+        self
+    }
+
+    /// Sets the smoothing factor of the moving averages, between 0 and 1,
+    /// [`DEFAULT_SMOOTHING`] by default. Higher values adapt faster.
+    pub fn with_smoothing(mut self, smoothing: f64) -> Self {
+        self.smoothing = smoothing.clamp(0.01, 1.0);
+        self
+    }
+
+    /// Sets the number of turns observed before the windows are tuned,
+    /// [`DEFAULT_WARMUP_TURNS`] by default.
+    pub fn with_warmup_turns(mut self, turns: u64) -> Self {
+        self.warmup_turns = turns;
+        self
+    }
+
+    /// Sets the number of distinctive words an answer must share with a document or
+    /// a message to reference it, 3 by default.
+    pub fn with_min_shared_words(mut self, n: usize) -> Self {
+        self.min_shared_words = n.max(1);
+        self
+    }
+
+    /// Restores a state, e.g. one saved before a restart.
+    pub fn with_state(self, state: AdaptiveState) -> Self {
+        *self.state.write().expect("lock poisoned") = state;
+        self
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> AdaptiveState {
+        self.state.read().expect("lock poisoned").clone()
+    }
+
+    /// Returns the number of documents to retrieve.
+    pub fn top_k(&self) -> usize {
+        self.state.read().expect("lock poisoned").top_k
+    }
+
+    /// Returns the number of history messages to pack.
+    pub fn history(&self) -> usize {
+        self.state.read().expect("lock poisoned").history
+    }
+
+    /// Measures how much of the packed context the answer references.
+    ///
+    /// # Arguments
+    /// * `prompt` - The prompt of the turn;
+    /// * `documents` - The packed documents as (id, text), in rank order;
+    /// * `history` - The texts of the packed history messages, from the oldest to the most recent;
+    /// * `answer` - The answer of the model.
+    pub fn measure(
+        &self,
+        prompt: &str,
+        documents: &[(&str, &str)],
+        history: &[&str],
+        answer: &str,
+    ) -> ContextUsage {
+        let prompt_words = words(prompt);
+        let answer_words: BTreeSet<String> =
+            words(answer).difference(&prompt_words).cloned().collect();
+        let references =
+            |text: &str| words(text).intersection(&answer_words).count() >= self.min_shared_words;
+
+        let document_depth = documents
+            .iter()
+            .rposition(|(id, text)| (!id.is_empty() && answer.contains(id)) || references(text))
+            .map_or(0, |i| i + 1);
+        let history_depth = history
+            .iter()
+            .position(|text| references(text))
+            .map_or(0, |i| history.len() - i);
+        ContextUsage {
+            documents: documents.len(),
+            document_depth,
+            history: history.len(),
+            history_depth,
+        }
+    }
+
+    /// Records the usage of a turn and tunes the windows.
+    pub fn observe(&self, usage: &ContextUsage) {
+        let mut state = self.state.write().expect("lock poisoned");
+        if usage.documents > 0 {
+            state.document_depth = self.smooth(
+                state.document_depth,
+                observed_depth(usage.documents, usage.document_depth),
+            );
+        }
+        if usage.history > 0 {
+            state.history_depth = self.smooth(
+                state.history_depth,
+                observed_depth(usage.history, usage.history_depth),
+            );
+        }
+        state.turns += 1;
+        if state.turns < self.warmup_turns {
+            return;
+        }
+
+        // keep a slack of a document and of a turn, a user and an assistant message
+        state.top_k = (state.document_depth.ceil() as usize + 1)
+            .clamp(self.top_k_range.0, self.top_k_range.1);
+        state.history = (state.history_depth.ceil() as usize + 2)
+            .clamp(self.history_range.0, self.history_range.1);
+    }
+
+    fn smooth(&self, average: f64, value: usize) -> f64 {
+        average + self.smoothing * (value as f64 - average)
+    }
+}
+
+/// The depth to record: one more than what was packed if the last item is referenced.
+fn observed_depth(packed: usize, depth: usize) -> usize {
+    if depth >= packed { packed + 1 } else { depth }
+}
+
+/// Returns the distinctive words of the text, lowercased.
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= MIN_WORD_CHARS)
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let adaptive = AdaptiveContext::new(5, 10);
+        let documents = [
+            (
+                "doc_0",
+                "The Internet Computer hosts canisters with orthogonal persistence.",
+            ),
+            (
+                "doc_1",
+                "Cycles fuel the computation and storage of canisters.",
+            ),
+            ("doc_2", "Pandas live in bamboo forests of Sichuan."),
+        ];
+        let history = [
+            "What is the capital of France?",
+            "Paris is the capital of France.",
+            "Tell me about canisters.",
+        ];
+        let prompt = "How are canisters paid for?";
+        let answer = "Canisters consume cycles, which fuel computation and storage (doc_0).";
+        assert_eq!(
+            adaptive.measure(prompt, &documents, &history, answer),
+            ContextUsage {
+                documents: 3,
+                document_depth: 2,
+                history: 3,
+                history_depth: 0,
+            }
+        );
+
+        let answer = "Paris, the capital of France, has no pandas: they live in the bamboo forests of Sichuan.";
+        let usage = adaptive.measure("Where do pandas live?", &documents, &history, answer);
+        assert_eq!(usage.document_depth, 3);
+        assert_eq!(usage.history_depth, 2);
+    }
+
+    #[test]
+    fn test_observe() {
+        let adaptive = AdaptiveContext::new(8, 20)
+            .with_smoothing(0.5)
+            .with_warmup_turns(2);
+        let shallow = ContextUsage {
+            documents: 8,
+            document_depth: 1,
+            history: 20,
+            history_depth: 2,
+        };
+        adaptive.observe(&shallow);
+        assert_eq!(adaptive.top_k(), 8);
+        for _ in 0..10 {
+            adaptive.observe(&shallow);
+        }
+        let state = adaptive.state();
+        assert_eq!(state.turns, 11);
+        assert_eq!(state.top_k, 3);
+        assert_eq!(state.history, 5);
+
+        // the last documents are referenced: the window grows back, up to the maximum
+        let deep = ContextUsage {
+            documents: 3,
+            document_depth: 3,
+            history: 5,
+            history_depth: 0,
+        };
+        adaptive.observe(&deep);
+        assert_eq!(adaptive.top_k(), 4);
+        for _ in 0..20 {
+            let usage = ContextUsage {
+                documents: adaptive.top_k(),
+                document_depth: adaptive.top_k(),
+                ..Default::default()
+            };
+            adaptive.observe(&usage);
+        }
+        assert_eq!(adaptive.top_k(), 8);
+        // no history message is referenced
+        assert_eq!(adaptive.history(), 4);
+
+        let restored = AdaptiveContext::new(8, 20).with_state(adaptive.state());
+        assert_eq!(restored.state(), adaptive.state());
+    }
+}
@@ -0,0 +1,455 @@
+//! Calendar Extension for Anda Engine
+//!
+//! This module provides a tool for creating calendar events. Every event is generated as an
+//! iCalendar (ICS, RFC 5545) resource that can be attached to a message or imported in any
+//! calendar app, and is optionally created in a calendar backend:
+//! - [`CalDavBackend`]: any CalDAV server, e.g. Nextcloud, Fastmail or iCloud;
+//! - [`GoogleCalendarBackend`]: Google Calendar API.
+//!
+//! Reminders are managed by the engine, see [`ReminderTool`](crate::management::ReminderTool).
+//!
+//! # Usage
+//! ```rust,ignore
+//! let calendar = CalendarTool::new().with_backend(Arc::new(CalDavBackend::new(
+//!     "https://dav.example.com/calendars/alice/personal/".to_string(),
+//!     "Basic YWxpY2U6c2VjcmV0".to_string(),
+//! )));
+//! let engine = Engine::builder()
+//!     .register_tool(calendar)?
+//!     .register_agent(my_agent)?
+//!     .build("default_agent".to_string())?;
+//! ```
+
+use anda_core::{
+    BoxError, FunctionDefinition, HttpFeatures, Resource, Tool, ToolOutput, gen_schema_for,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use http::header;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+use crate::{context::BaseCtx, id::new_id, unix_ms};
+
+/// Arguments for creating a calendar event
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct CalendarEventArgs {
+    /// The title of the event
+    pub summary: String,
+    /// The start time in RFC 3339 format, e.g. "2026-10-16T09:00:00+08:00"
+    pub start: String,
+    /// The end time in RFC 3339 format, one hour after the start by default
+    pub end: Option<String>,
+    /// The description of the event
+    pub description: Option<String>,
+    /// The location of the event
+    pub location: Option<String>,
+    /// The email addresses of the attendees
+    #[serde(default)]
+    pub attendees: Vec<String>,
+    /// Alerts the attendees this number of minutes before the start
+    pub alarm_minutes: Option<u32>,
+}
+
+/// A calendar event.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct CalendarEvent {
+    /// The globally unique ID of the event.
+    pub uid: String,
+    pub summary: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    /// The start time, unix timestamp in milliseconds.
+    pub start_ms: u64,
+    /// The end time, unix timestamp in milliseconds.
+    pub end_ms: u64,
+    pub attendees: Vec<String>,
+    pub alarm_minutes: Option<u32>,
+}
+
+/// Parses an RFC 3339 date-time into a unix timestamp in milliseconds.
+pub fn parse_datetime(s: &str) -> Result<u64, BoxError> {
+    let dt = DateTime::parse_from_rfc3339(s.trim())
+        .map_err(|err| format!("invalid RFC 3339 date-time {:?}: {}", s, err))?;
+    u64::try_from(dt.timestamp_millis())
+        .map_err(|_| format!("date-time {:?} is before 1970", s).into())
+}
+
+fn utc(ms: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ms as i64).unwrap_or_default()
+}
+
+impl CalendarEvent {
+    /// Creates an event from the tool arguments.
+    pub fn try_from_args(args: CalendarEventArgs) -> Result<Self, BoxError> {
+        if args.summary.trim().is_empty() {
+            return Err("summary should not be empty".into());
+        }
+        let start_ms = parse_datetime(&args.start)?;
+        let end_ms = match &args.end {
+            Some(end) => parse_datetime(end)?,
+            None => start_ms + 3600 * 1000,
+        };
+        if end_ms < start_ms {
+            return Err("the event should not end before it starts".into());
+        }
+        Ok(Self {
+            uid: format!("{}@anda.bot", new_id()),
+            summary: args.summary,
+            description: args.description,
+            location: args.location,
+            start_ms,
+            end_ms,
+            attendees: args.attendees,
+            alarm_minutes: args.alarm_minutes,
+        })
+    }
+
+    /// Generates the event as an iCalendar (RFC 5545) object.
+    pub fn to_ics(&self, now_ms: u64) -> String {
+        let fmt = |ms: u64| utc(ms).format("%Y%m%dT%H%M%SZ").to_string();
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            "PRODID:-//anda.bot//Anda Engine//EN".to_string(),
+            "CALSCALE:GREGORIAN".to_string(),
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}", self.uid),
+            format!("DTSTAMP:{}", fmt(now_ms)),
+            format!("DTSTART:{}", fmt(self.start_ms)),
+            format!("DTEND:{}", fmt(self.end_ms)),
+            format!("SUMMARY:{}", ics_escape(&self.summary)),
+        ];
+        if let Some(description) = &self.description {
+            lines.push(format!("DESCRIPTION:{}", ics_escape(description)));
+        }
+        if let Some(location) = &self.location {
+            lines.push(format!("LOCATION:{}", ics_escape(location)));
+        }
+        for attendee in &self.attendees {
+            lines.push(format!("ATTENDEE;RSVP=TRUE:mailto:{}", attendee.trim()));
+        }
+        if let Some(minutes) = self.alarm_minutes {
+            lines.extend([
+                "BEGIN:VALARM".to_string(),
+                "ACTION:DISPLAY".to_string(),
+                format!("DESCRIPTION:{}", ics_escape(&self.summary)),
+                format!("TRIGGER:-PT{}M", minutes),
+                "END:VALARM".to_string(),
+            ]);
+        }
+        lines.push("END:VEVENT".to_string());
+        lines.push("END:VCALENDAR".to_string());
+
+        let mut ics = String::new();
+        for line in lines {
+            ics_fold(&mut ics, &line);
+        }
+        ics
+    }
+}
+
+/// Escapes a text value.
+fn ics_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ';' => out.push_str("\\;"),
+            ',' => out.push_str("\\,"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Appends a content line, folded at 75 octets without splitting a character.
+fn ics_fold(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// A calendar service where events are created.
+#[async_trait]
+pub trait CalendarBackend: Send + Sync {
+    /// Creates the event, returns its ID or URL in the backend.
+    async fn create_event(&self, ctx: &BaseCtx, event: &CalendarEvent) -> Result<String, BoxError>;
+}
+
+/// Creates events in a CalDAV calendar collection.
+pub struct CalDavBackend {
+    collection_url: String,
+    authorization: String,
+}
+
+impl CalDavBackend {
+    /// Creates a backend for the calendar collection URL, with the value of the
+    /// Authorization header, e.g. "Basic {base64(username:password)}" or "Bearer {token}".
+    pub fn new(collection_url: String, authorization: String) -> Self {
+        let collection_url = if collection_url.ends_with('/') {
+            collection_url
+        } else {
+            format!("{}/", collection_url)
+        };
+        Self {
+            collection_url,
+            authorization,
+        }
+    }
+}
+
+#[async_trait]
+impl CalendarBackend for CalDavBackend {
+    async fn create_event(&self, ctx: &BaseCtx, event: &CalendarEvent) -> Result<String, BoxError> {
+        let url = format!("{}{}.ics", self.collection_url, event.uid);
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            "text/calendar; charset=utf-8"
+                .parse()
+                .expect("invalid header value"),
+        );
+        headers.insert(header::AUTHORIZATION, self.authorization.parse()?);
+        // never overwrite an existing event
+        headers.insert(
+            header::IF_NONE_MATCH,
+            "*".parse().expect("invalid header value"),
+        );
+        let response = ctx
+            .https_call(
+                &url,
+                http::Method::PUT,
+                Some(headers),
+                Some(event.to_ics(unix_ms()).into_bytes()),
+            )
+            .await?;
+        if !response.status().is_success() {
+            return Err(format!("CalDAV server returned status: {}", response.status()).into());
+        }
+        Ok(url)
+    }
+}
+
+/// Creates events in a Google calendar.
+///
+/// # API Reference
+/// - Official documentation: https://developers.google.com/calendar/api/v3/reference/events/insert
+pub struct GoogleCalendarBackend {
+    calendar_id: String,
+    access_token: String,
+}
+
+impl GoogleCalendarBackend {
+    /// Creates a backend for the calendar, "primary" for the user's primary calendar,
+    /// with an OAuth 2.0 access token with the calendar.events scope.
+    pub fn new(calendar_id: String, access_token: String) -> Self {
+        Self {
+            calendar_id,
+            access_token,
+        }
+    }
+}
+
+#[async_trait]
+impl CalendarBackend for GoogleCalendarBackend {
+    async fn create_event(&self, ctx: &BaseCtx, event: &CalendarEvent) -> Result<String, BoxError> {
+        let mut url = url::Url::parse("https://www.googleapis.com/calendar/v3/calendars/")?;
+        url.path_segments_mut()
+            .map_err(|_| "invalid calendar URL")?
+            .pop_if_empty()
+            .extend([self.calendar_id.as_str(), "events"]);
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            "application/json".parse().expect("invalid header value"),
+        );
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", self.access_token).parse()?,
+        );
+
+        let mut body = json!({
+            "iCalUID": event.uid,
+            "summary": event.summary,
+            "start": {"dateTime": utc(event.start_ms).to_rfc3339()},
+            "end": {"dateTime": utc(event.end_ms).to_rfc3339()},
+            "attendees": event.attendees.iter().map(|email| json!({"email": email})).collect::<Vec<_>>(),
+        });
+        if let Some(description) = &event.description {
+            body["description"] = description.clone().into();
+        }
+        if let Some(location) = &event.location {
+            body["location"] = location.clone().into();
+        }
+        if let Some(minutes) = event.alarm_minutes {
+            body["reminders"] = json!({
+                "useDefault": false,
+                "overrides": [{"method": "popup", "minutes": minutes}],
+            });
+        }
+
+        let response = ctx
+            .https_call(
+                url.as_str(),
+                http::Method::POST,
+                Some(headers),
+                Some(serde_json::to_vec(&body)?),
+            )
+            .await?;
+        if !response.status().is_success() {
+            return Err(
+                format!("Google Calendar API returned status: {}", response.status()).into(),
+            );
+        }
+        let json: Value = response.json().await?;
+        json.get("htmlLink")
+            .or_else(|| json.get("id"))
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string())
+            .ok_or_else(|| "invalid Google Calendar API response".into())
+    }
+}
+
+/// The output of creating a calendar event
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CalendarEventOutput {
+    /// The globally unique ID of the event
+    pub uid: String,
+    /// The ID or URL of the event in the calendar backend, if any
+    pub backend_id: Option<String>,
+}
+
+/// Calendar Tool implementation
+///
+/// Creates calendar events as ICS resources, and in the calendar backend if configured.
+#[derive(Clone)]
+pub struct CalendarTool {
+    backend: Option<Arc<dyn CalendarBackend>>,
+    schema: Value,
+}
+
+impl Default for CalendarTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CalendarTool {
+    const NAME: &'static str = "create_calendar_event";
+
+    /// Creates a new CalendarTool that only generates ICS resources.
+    pub fn new() -> Self {
+        CalendarTool {
+            backend: None,
+            schema: gen_schema_for::<CalendarEventArgs>(),
+        }
+    }
+
+    /// Creates the events in the calendar backend too.
+    pub fn with_backend(mut self, backend: Arc<dyn CalendarBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+}
+
+impl Tool<BaseCtx> for CalendarTool {
+    type Args = CalendarEventArgs;
+    type Output = CalendarEventOutput;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Creates a calendar event and returns it as an ICS file.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let event = CalendarEvent::try_from_args(args)?;
+        let backend_id = match &self.backend {
+            Some(backend) => Some(backend.create_event(&ctx, &event).await?),
+            None => None,
+        };
+        let ics = event.to_ics(unix_ms()).into_bytes();
+        let mut output = ToolOutput::new(CalendarEventOutput {
+            uid: event.uid.clone(),
+            backend_id,
+        });
+        output.resources = Some(vec![Resource {
+            tag: "ics".to_string(),
+            name: Some("event.ics".to_string()),
+            mime_type: Some("text/calendar".to_string()),
+            size: Some(ics.len()),
+            blob: Some(ics.into()),
+            ..Default::default()
+        }]);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendar_event_ics() {
+        let event = CalendarEvent::try_from_args(CalendarEventArgs {
+            summary: "Sync, with the ICPanda team; agenda: roadmap".to_string(),
+            start: "2026-10-16T09:00:00+08:00".to_string(),
+            description: Some("Line 1\nLine 2 ".repeat(8)),
+            attendees: vec!["alice@example.com".to_string()],
+            alarm_minutes: Some(15),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(event.end_ms - event.start_ms, 3600 * 1000);
+
+        let ics = event.to_ics(event.start_ms);
+        assert!(ics.contains("DTSTART:20261016T010000Z\r\n"));
+        assert!(ics.contains("DTEND:20261016T020000Z\r\n"));
+        assert!(ics.contains("SUMMARY:Sync\\, with the ICPanda team\\; agenda: roadmap\r\n"));
+        assert!(ics.contains("ATTENDEE;RSVP=TRUE:mailto:alice@example.com\r\n"));
+        assert!(ics.contains("TRIGGER:-PT15M\r\n"));
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        for line in ics.split("\r\n") {
+            assert!(line.len() <= 75);
+        }
+        assert!(ics.contains("\r\n "));
+
+        assert!(
+            CalendarEvent::try_from_args(CalendarEventArgs {
+                summary: "x".to_string(),
+                start: "tomorrow".to_string(),
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+}
@@ -38,13 +38,21 @@ use serde_json::json;
 use std::{fmt::Write, sync::Arc, time::Duration};
 
 use super::{
+    adaptive::AdaptiveContext,
     attention::{Attention, AttentionCommand, ContentQuality},
+    glossary::Glossary,
     segmenter::DocumentSegmenter,
+    translator::Translator,
 };
 
-use crate::{context::AgentCtx, store::MAX_STORE_OBJECT_SIZE};
+use crate::{
+    context::AgentCtx,
+    model::{Reranker, rerank_texts},
+    store::MAX_STORE_OBJECT_SIZE,
+};
 
 const MAX_CHAT_HISTORY: usize = 42;
+const KNOWLEDGE_TOP_N: usize = 5;
 const CHAT_HISTORY_TTI: Duration = Duration::from_secs(3600 * 24 * 7);
 
 /// Represents a character definition with attributes, traits, and behaviors
@@ -299,6 +307,25 @@ impl Character {
     }
 }
 
+/// The rerank stage of the knowledge retrieval: the top candidates by similarity are
+/// reranked, and only the most relevant ones are added to the prompt.
+#[derive(Clone)]
+pub struct RerankStage {
+    /// The reranker, e.g. Cohere Rerank or a local cross-encoder
+    pub reranker: Arc<dyn Reranker>,
+
+    /// The number of candidates retrieved for reranking
+    pub candidates: usize,
+}
+
+impl std::fmt::Debug for RerankStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RerankStage")
+            .field("candidates", &self.candidates)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Agent implementation for character-based interactions
 #[derive(Debug, Clone)]
 pub struct CharacterAgent<K: KnowledgeFeatures + VectorSearchFeatures> {
@@ -313,6 +340,18 @@ pub struct CharacterAgent<K: KnowledgeFeatures + VectorSearchFeatures> {
 
     /// Knowledge base implementation
     pub knowledge: Arc<K>,
+
+    /// Optional translation layer for inputs not in the knowledge base's language
+    pub translator: Option<Arc<Translator>>,
+
+    /// Optional terminology that the answers must use
+    pub glossary: Option<Arc<Glossary>>,
+
+    /// Optional rerank stage of the knowledge retrieval
+    pub reranker: Option<RerankStage>,
+
+    /// Optional tuning of the retrieval top-k and the history window
+    pub adaptive: Option<Arc<AdaptiveContext>>,
 }
 
 impl<K: KnowledgeFeatures + VectorSearchFeatures> CharacterAgent<K> {
@@ -335,6 +374,63 @@ impl<K: KnowledgeFeatures + VectorSearchFeatures> CharacterAgent<K> {
             attention,
             segmenter,
             knowledge,
+            translator: None,
+            glossary: None,
+            reranker: None,
+            adaptive: None,
+        }
+    }
+
+    /// Enables automatic translation: inputs in other languages are translated into the
+    /// translator's target language before retrieval, and answers are translated back.
+    pub fn with_translator(mut self, translator: Arc<Translator>) -> Self {
+        self.translator = Some(translator);
+        self
+    }
+
+    /// Enforces the glossary's terminology: the terms are listed in the system prompt,
+    /// and their variants are replaced in the answers.
+    pub fn with_glossary(mut self, glossary: Arc<Glossary>) -> Self {
+        self.glossary = Some(glossary);
+        self
+    }
+
+    /// Reranks the knowledge retrieved for the prompt: the top `candidates` by similarity
+    /// are passed through the reranker, and the most relevant ones are kept.
+    pub fn with_reranker(mut self, reranker: Arc<dyn Reranker>, candidates: usize) -> Self {
+        self.reranker = Some(RerankStage {
+            reranker,
+            candidates,
+        });
+        self
+    }
+
+    /// Tunes the number of retrieved knowledge texts and of chat history messages in the
+    /// prompts by how much of them the answers reference.
+    pub fn with_adaptive_context(mut self, adaptive: Arc<AdaptiveContext>) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    /// Retrieves the top n knowledge texts relevant to the query, reranked if a rerank
+    /// stage is set. Falls back to the similarity order if the reranking fails.
+    pub async fn retrieve_knowledge(&self, query: &str, n: usize) -> Vec<String> {
+        let Some(stage) = &self.reranker else {
+            return self.knowledge.top_n(query, n).await.unwrap_or_default();
+        };
+
+        let mut candidates = self
+            .knowledge
+            .top_n(query, stage.candidates.max(n))
+            .await
+            .unwrap_or_default();
+        match rerank_texts(stage.reranker.as_ref(), query, candidates.clone(), n).await {
+            Ok(texts) => texts,
+            Err(err) => {
+                log::warn!("failed to rerank knowledge: {}", err);
+                candidates.truncate(n);
+                candidates
+            }
         }
     }
 
@@ -430,7 +526,7 @@ where
     ) -> Result<AgentOutput, BoxError> {
         // read chat history from store
         let meta = ctx.meta();
-        let mut chat_history = if let Some(user) = meta.user.clone() {
+        let mut chat_history = if let Some(user) = meta.user_key() {
             let chat: Vec<Message> = ctx
                 .cache_get_with(&user, async {
                     Ok((Vec::new(), Some(CacheExpiry::TTI(CHAT_HISTORY_TTI))))
@@ -441,6 +537,23 @@ where
             None
         };
 
+        // translate the input into the knowledge base's language
+        let mut source_lang: Option<String> = None;
+        let prompt = match &self.translator {
+            Some(translator) => match translator.translate_input(&ctx, &prompt).await {
+                Ok(rt) if rt.translated => {
+                    source_lang = Some(rt.source_lang);
+                    rt.text
+                }
+                Ok(_) => prompt,
+                Err(err) => {
+                    log::warn!("failed to translate input: {}", err);
+                    prompt
+                }
+            },
+            None => prompt,
+        };
+
         let mut content_quality = ContentQuality::Ignore;
         if evaluate_tokens(&prompt) <= self.attention.min_content_tokens {
             let recent_messages: Vec<Message> = vec![];
@@ -477,7 +590,11 @@ where
         }
 
         let knowledges: Documents = if content_quality == ContentQuality::Ignore {
-            let knowledges = self.knowledge.top_n(&prompt, 5).await.unwrap_or_default();
+            let top_n = self
+                .adaptive
+                .as_ref()
+                .map_or(KNOWLEDGE_TOP_N, |adaptive| adaptive.top_k());
+            let knowledges = self.retrieve_knowledge(&prompt, top_n).await;
             knowledges.into()
         } else {
             // do not append knowledges if content quality is high
@@ -487,7 +604,7 @@ where
         if content_quality > ContentQuality::Ignore {
             let content = prompt.clone();
             let ctx = ctx.clone();
-            let user = meta.user.clone().unwrap_or("anonymous".to_string());
+            let user = meta.user_key().unwrap_or("anonymous".to_string());
             let segmenter = self.segmenter.clone();
             let knowledge = self.knowledge.clone();
 
@@ -533,14 +650,38 @@ where
             .collect();
         let tools = ctx.tool_definitions(Some(&tools));
 
+        let packed_documents: Vec<(String, String)> = knowledges
+            .iter()
+            .map(|doc| (doc.id.clone(), doc.text.clone()))
+            .collect();
         let mut req = self
             .character
             .to_request(prompt, meta.user.clone())
             .append_documents(knowledges)
             .append_tools(tools);
+        if let Some(glossary) = &self.glossary
+            && !glossary.is_empty()
+        {
+            let system = req.system.take().unwrap_or_default();
+            req.system = Some(format!("{}\n\n{}", system, glossary.guidance()));
+        }
 
-        if let Some((user, chat)) = &mut chat_history {
-            req.chat_history = chat.clone().into_iter().map(|m| json!(m)).collect();
+        let packed_prompt = req.prompt.clone();
+        let mut packed_history: Vec<String> = Vec::new();
+        let mut res = if let Some((user, chat)) = &mut chat_history {
+            let window = self
+                .adaptive
+                .as_ref()
+                .map_or(chat.len(), |adaptive| adaptive.history());
+            let history = &chat[chat.len().saturating_sub(window)..];
+            packed_history = history
+                .iter()
+                .map(|m| match m.content.as_str() {
+                    Some(text) => text.to_string(),
+                    None => m.content.to_string(),
+                })
+                .collect();
+            req.chat_history = history.iter().map(|m| json!(m)).collect();
             chat.push(Message {
                 role: "user".to_string(),
                 content: req.prompt.clone().into(),
@@ -580,10 +721,36 @@ where
                 }
             }
 
-            Ok(res)
+            res
         } else {
-            ctx.completion(req, None).await
+            ctx.completion(req, None).await?
+        };
+
+        if let Some(adaptive) = &self.adaptive
+            && res.failed_reason.is_none()
+        {
+            let documents: Vec<(&str, &str)> = packed_documents
+                .iter()
+                .map(|(id, text)| (id.as_str(), text.as_str()))
+                .collect();
+            let history: Vec<&str> = packed_history.iter().map(|s| s.as_str()).collect();
+            let usage = adaptive.measure(&packed_prompt, &documents, &history, &res.content);
+            adaptive.observe(&usage);
+        }
+
+        // translate the answer back into the user's language
+        if let (Some(translator), Some(lang)) = (&self.translator, &source_lang)
+            && res.failed_reason.is_none()
+        {
+            match translator.translate_output(&ctx, &res.content, lang).await {
+                Ok(content) => res.content = content,
+                Err(err) => log::warn!("failed to translate answer: {}", err),
+            }
+        }
+        if let Some(glossary) = &self.glossary {
+            res.content = glossary.enforce(&res.content);
         }
+        Ok(res)
     }
 }
 
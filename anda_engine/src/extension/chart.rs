@@ -0,0 +1,439 @@
+//! Chart Extension for Anda Engine
+//!
+//! This module provides a tool for rendering line, area, scatter and bar charts from structured
+//! series data, so that analytical agents can answer with graphs instead of walls of numbers.
+//! Charts are rendered with [plotters](https://docs.rs/plotters) as SVG resources that can be
+//! attached to a message.
+//!
+//! Series can be given in the tool arguments, or as `json` resources produced by other tools,
+//! each one holding a [`ChartSeries`] or an array of them.
+//!
+//! PNG output is not supported, as the bitmap backend of plotters needs system fonts and image
+//! encoders; most clients can display or convert SVG images.
+//!
+//! # Usage
+//! ```rust,ignore
+//! let engine = Engine::builder()
+//!     .register_tool(ChartTool::new())?
+//!     .register_agent(my_agent)?
+//!     .build("default_agent".to_string())?;
+//! ```
+
+use anda_core::{BoxError, FunctionDefinition, Resource, Tool, ToolOutput, gen_schema_for};
+use plotters::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::context::BaseCtx;
+
+/// The default width of charts in pixels.
+pub const DEFAULT_WIDTH: u32 = 800;
+/// The default height of charts in pixels.
+pub const DEFAULT_HEIGHT: u32 = 480;
+
+const MIN_SIZE: u32 = 100;
+const MAX_SIZE: u32 = 4096;
+const MAX_SERIES: usize = 32;
+const MAX_POINTS: usize = 10_000;
+
+/// The kind of chart to render.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChartKind {
+    #[default]
+    Line,
+    Area,
+    Scatter,
+    Bar,
+}
+
+/// A named data series.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct ChartSeries {
+    /// The name of the series, shown in the legend
+    pub name: String,
+    /// The y values, plotted at x = 0, 1, 2... or against the categories
+    #[serde(default)]
+    pub values: Vec<f64>,
+    /// The [x, y] points, used instead of the values when not empty
+    #[serde(default)]
+    pub points: Vec<[f64; 2]>,
+}
+
+impl ChartSeries {
+    fn data(&self) -> Vec<(f64, f64)> {
+        if self.points.is_empty() {
+            self.values
+                .iter()
+                .enumerate()
+                .map(|(i, &y)| (i as f64, y))
+                .collect()
+        } else {
+            self.points.iter().map(|&[x, y]| (x, y)).collect()
+        }
+    }
+}
+
+/// Arguments for rendering a chart
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct ChartArgs {
+    /// The kind of chart: "line", "area", "scatter" or "bar"
+    #[serde(default)]
+    pub kind: ChartKind,
+    /// The title of the chart
+    pub title: Option<String>,
+    /// The label of the x axis
+    pub x_label: Option<String>,
+    /// The label of the y axis
+    pub y_label: Option<String>,
+    /// The labels of the x values 0, 1, 2..., e.g. dates or product names
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// The data series to plot
+    #[serde(default)]
+    pub series: Vec<ChartSeries>,
+    /// The width in pixels, 800 by default
+    pub width: Option<u32>,
+    /// The height in pixels, 480 by default
+    pub height: Option<u32>,
+}
+
+/// The output of the chart tool, the chart itself is returned as a resource
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ChartOutput {
+    /// The name of the chart resource
+    pub name: String,
+    /// The number of series plotted
+    pub series: usize,
+    /// The number of points plotted
+    pub points: usize,
+}
+
+fn value_range(values: impl Iterator<Item = f64>, zero: bool) -> (f64, f64) {
+    let (mut min, mut max) = if zero {
+        (0.0, 0.0)
+    } else {
+        (f64::MAX, f64::MIN)
+    };
+    for v in values {
+        min = min.min(v);
+        max = max.max(v);
+    }
+    if min > max {
+        return (0.0, 1.0);
+    }
+    let pad = if max > min {
+        (max - min) * 0.05
+    } else {
+        min.abs().max(1.0) * 0.5
+    };
+    (
+        if zero && min >= 0.0 { 0.0 } else { min - pad },
+        if zero && max <= 0.0 { 0.0 } else { max + pad },
+    )
+}
+
+/// Renders a chart as an SVG document.
+pub fn render_svg(args: &ChartArgs) -> Result<String, BoxError> {
+    let width = args.width.unwrap_or(DEFAULT_WIDTH);
+    let height = args.height.unwrap_or(DEFAULT_HEIGHT);
+    if !(MIN_SIZE..=MAX_SIZE).contains(&width) || !(MIN_SIZE..=MAX_SIZE).contains(&height) {
+        return Err(format!("chart size should be in {MIN_SIZE}..={MAX_SIZE} pixels").into());
+    }
+    if args.series.is_empty() {
+        return Err("no series to plot".into());
+    }
+    if args.series.len() > MAX_SERIES {
+        return Err(format!("too many series, expected at most {MAX_SERIES}").into());
+    }
+
+    let data: Vec<Vec<(f64, f64)>> = args.series.iter().map(|s| s.data()).collect();
+    let total: usize = data.iter().map(|d| d.len()).sum();
+    if total == 0 {
+        return Err("no points to plot".into());
+    }
+    if total > MAX_POINTS {
+        return Err(format!("too many points, expected at most {MAX_POINTS}").into());
+    }
+    if data
+        .iter()
+        .flatten()
+        .any(|(x, y)| !x.is_finite() || !y.is_finite())
+    {
+        return Err("points should be finite numbers".into());
+    }
+
+    let bar = args.kind == ChartKind::Bar;
+    let (x_min, x_max) = if bar {
+        let n = data.iter().map(|d| d.len()).max().unwrap_or(0);
+        (-0.5, n.max(args.categories.len()) as f64 - 0.5)
+    } else {
+        value_range(data.iter().flatten().map(|p| p.0), false)
+    };
+    let (y_min, y_max) = value_range(
+        data.iter().flatten().map(|p| p.1),
+        matches!(args.kind, ChartKind::Bar | ChartKind::Area),
+    );
+
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (width, height)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut builder = ChartBuilder::on(&root);
+        builder
+            .margin(16)
+            .x_label_area_size(40)
+            .y_label_area_size(56);
+        if let Some(title) = &args.title {
+            builder.caption(title, ("sans-serif", 22));
+        }
+        let mut chart = builder.build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+
+        let categories = &args.categories;
+        let x_formatter = |x: &f64| {
+            let i = x.round();
+            if !categories.is_empty() {
+                if (x - i).abs() < 1e-6 && i >= 0.0 && (i as usize) < categories.len() {
+                    categories[i as usize].clone()
+                } else {
+                    String::new()
+                }
+            } else if bar {
+                if (x - i).abs() < 1e-6 {
+                    format!("{i}")
+                } else {
+                    String::new()
+                }
+            } else {
+                format!("{x}")
+            }
+        };
+        let mut mesh = chart.configure_mesh();
+        mesh.x_label_formatter(&x_formatter);
+        if bar || !categories.is_empty() {
+            mesh.disable_x_mesh()
+                .x_labels((x_max - x_min).round() as usize + 1);
+        }
+        if let Some(label) = &args.x_label {
+            mesh.x_desc(label);
+        }
+        if let Some(label) = &args.y_label {
+            mesh.y_desc(label);
+        }
+        mesh.draw()?;
+
+        let n = data.len() as f64;
+        for (i, (series, points)) in args.series.iter().zip(data).enumerate() {
+            let color = Palette99::pick(i).to_rgba();
+            let anno = match args.kind {
+                ChartKind::Line => {
+                    chart.draw_series(LineSeries::new(points, color.stroke_width(2)))?
+                }
+                ChartKind::Area => chart.draw_series(
+                    AreaSeries::new(points, 0.0, color.mix(0.3)).border_style(color),
+                )?,
+                ChartKind::Scatter => chart.draw_series(
+                    points
+                        .into_iter()
+                        .map(|p| Circle::new(p, 3, color.filled())),
+                )?,
+                ChartKind::Bar => {
+                    // bars of the series are side by side, centered on the x value
+                    let w = 0.8 / n;
+                    let offset = -0.4 + w * i as f64;
+                    chart.draw_series(points.into_iter().map(|(x, y)| {
+                        Rectangle::new([(x + offset, 0.0), (x + offset + w, y)], color.filled())
+                    }))?
+                }
+            };
+            if !series.name.is_empty() {
+                anno.label(series.name.clone()).legend(move |(x, y)| {
+                    Rectangle::new([(x, y - 5), (x + 16, y + 5)], color.filled())
+                });
+            }
+        }
+
+        if args.series.iter().any(|s| !s.name.is_empty()) {
+            chart
+                .configure_series_labels()
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()?;
+        }
+        root.present()?;
+    }
+    Ok(svg)
+}
+
+/// Chart Tool implementation
+///
+/// Renders charts from series data as SVG resources.
+#[derive(Debug, Clone)]
+pub struct ChartTool {
+    schema: Value,
+}
+
+impl Default for ChartTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChartTool {
+    const NAME: &'static str = "render_chart";
+
+    /// Creates a new ChartTool.
+    pub fn new() -> Self {
+        ChartTool {
+            schema: gen_schema_for::<ChartArgs>(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for ChartTool {
+    type Args = ChartArgs;
+    type Output = ChartOutput;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Renders a line, area, scatter or bar chart from data series and returns it as an SVG image.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    fn supported_resource_tags(&self) -> Vec<String> {
+        vec!["json".to_string()]
+    }
+
+    async fn call(
+        &self,
+        _ctx: BaseCtx,
+        mut args: Self::Args,
+        resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        for resource in resources.unwrap_or_default() {
+            let Some(blob) = &resource.blob else {
+                continue;
+            };
+            let value: Value = serde_json::from_slice(blob)
+                .map_err(|err| format!("invalid json resource {:?}: {}", resource.name, err))?;
+            if value.is_array() {
+                args.series
+                    .extend(serde_json::from_value::<Vec<ChartSeries>>(value)?);
+            } else {
+                args.series.push(serde_json::from_value(value)?);
+            }
+        }
+
+        let svg = render_svg(&args)?.into_bytes();
+        let name = "chart.svg".to_string();
+        let mut output = ToolOutput::new(ChartOutput {
+            name: name.clone(),
+            series: args.series.len(),
+            points: args.series.iter().map(|s| s.data().len()).sum(),
+        });
+        output.resources = Some(vec![Resource {
+            tag: "svg".to_string(),
+            name: Some(name),
+            description: args.title,
+            mime_type: Some("image/svg+xml".to_string()),
+            size: Some(svg.len()),
+            blob: Some(svg.into()),
+            ..Default::default()
+        }]);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(kind: ChartKind) -> ChartArgs {
+        ChartArgs {
+            kind,
+            title: Some("Monthly revenue".to_string()),
+            x_label: Some("Month".to_string()),
+            y_label: Some("USD".to_string()),
+            categories: vec!["Jan".to_string(), "Feb".to_string(), "Mar".to_string()],
+            series: vec![
+                ChartSeries {
+                    name: "2025".to_string(),
+                    values: vec![120.0, 98.5, 143.0],
+                    ..Default::default()
+                },
+                ChartSeries {
+                    name: "2026".to_string(),
+                    values: vec![135.0, 110.0, -20.0],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_svg() {
+        for kind in [
+            ChartKind::Line,
+            ChartKind::Area,
+            ChartKind::Scatter,
+            ChartKind::Bar,
+        ] {
+            let svg = render_svg(&args(kind)).unwrap();
+            assert!(svg.starts_with("<svg"), "{kind:?}");
+            assert!(svg.trim_end().ends_with("</svg>"), "{kind:?}");
+            assert!(svg.contains("Monthly revenue"), "{kind:?}");
+            assert!(svg.contains("\nFeb\n"), "{kind:?}");
+            assert!(svg.contains("\n2026\n"), "{kind:?}");
+        }
+
+        let svg = render_svg(&ChartArgs {
+            kind: ChartKind::Scatter,
+            series: vec![ChartSeries {
+                points: vec![[1.5, 2.0], [3.0, 2.0]],
+                ..Default::default()
+            }],
+            width: Some(300),
+            height: Some(200),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(svg.contains(r#"width="300""#));
+        assert_eq!(svg.matches("<circle").count(), 2);
+
+        let mut bad = args(ChartKind::Line);
+        bad.series[0].values.push(f64::NAN);
+        assert!(render_svg(&bad).is_err());
+        bad.series.clear();
+        assert!(render_svg(&bad).is_err());
+        let mut bad = args(ChartKind::Line);
+        bad.width = Some(10);
+        assert!(render_svg(&bad).is_err());
+    }
+
+    #[test]
+    fn test_chart_args() {
+        let args: ChartArgs = serde_json::from_value(serde_json::json!({
+            "kind": "bar",
+            "series": [{"name": "a", "values": [1, 2]}, {"name": "b", "points": [[0, 1]]}],
+        }))
+        .unwrap();
+        assert_eq!(args.kind, ChartKind::Bar);
+        assert_eq!(args.series[0].data(), vec![(0.0, 1.0), (1.0, 2.0)]);
+        assert_eq!(args.series[1].data(), vec![(0.0, 1.0)]);
+        assert_eq!(value_range([2.0, 4.0].into_iter(), true), (0.0, 4.2));
+        assert_eq!(value_range([3.0].into_iter(), false), (1.5, 4.5));
+    }
+}
@@ -0,0 +1,649 @@
+//! Data Tool Pack for Anda Engine
+//!
+//! This module provides read-only data tools that deployments can register directly:
+//! - [`WeatherTool`]: current weather and forecast from [Open-Meteo](https://open-meteo.com/),
+//!   no API key required;
+//! - [`MarketPriceTool`]: crypto market prices from [CoinGecko](https://www.coingecko.com/en/api);
+//! - [`NewsTool`]: news headlines from [NewsAPI](https://newsapi.org/).
+//!
+//! The tools share a [`DataCache`] of API responses, so that agents asking the same question
+//! do not hit the APIs again within the TTL. When an API rate-limits the engine (HTTP 429),
+//! its host is paused for the `Retry-After` duration, and the last cached response is served
+//! instead, if any.
+//!
+//! # Usage
+//! ```rust,ignore
+//! let cache = DataCache::new(10_000);
+//! let engine = Engine::builder()
+//!     .register_tool(WeatherTool::new(cache.clone()))?
+//!     .register_tool(MarketPriceTool::new(cache.clone(), None))?
+//!     .register_tool(NewsTool::new(cache, news_api_key))?
+//!     .register_agent(my_agent)?
+//!     .build("default_agent".to_string())?;
+//! ```
+
+use anda_core::{
+    BoxError, FunctionDefinition, HttpFeatures, Resource, Tool, ToolOutput, gen_schema_for,
+};
+use http::{StatusCode, header};
+use moka::future::Cache;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use url::Url;
+
+use crate::{context::BaseCtx, unix_ms};
+
+/// The pause of a rate-limited host when the API does not return `Retry-After`.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+struct CachedResponse {
+    value: Value,
+    fetched_at: u64,
+}
+
+/// A response cache shared by the data tools, with rate-limit handling per API host.
+#[derive(Clone)]
+pub struct DataCache {
+    cache: Cache<String, Arc<CachedResponse>>,
+    paused_until: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl DataCache {
+    /// Creates a cache holding at most `max_capacity` responses.
+    /// Responses are evicted after one day without access.
+    pub fn new(max_capacity: u64) -> Self {
+        Self {
+            cache: Cache::builder()
+                .max_capacity(max_capacity)
+                .time_to_idle(Duration::from_secs(3600 * 24))
+                .build(),
+            paused_until: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the cached response of the key and whether it is still fresh.
+    async fn lookup(&self, key: &str, ttl: Duration, now_ms: u64) -> Option<(Value, bool)> {
+        self.cache.get(key).await.map(|res| {
+            let fresh = res.fetched_at + ttl.as_millis() as u64 > now_ms;
+            (res.value.clone(), fresh)
+        })
+    }
+
+    async fn insert(&self, key: String, value: Value, now_ms: u64) {
+        self.cache
+            .insert(
+                key,
+                Arc::new(CachedResponse {
+                    value,
+                    fetched_at: now_ms,
+                }),
+            )
+            .await;
+    }
+
+    fn paused_until(&self, host: &str) -> Option<u64> {
+        let paused = self.paused_until.lock().expect("paused lock poisoned");
+        paused.get(host).copied()
+    }
+
+    fn pause(&self, host: &str, until_ms: u64) {
+        let mut paused = self.paused_until.lock().expect("paused lock poisoned");
+        paused.insert(host.to_string(), until_ms);
+    }
+
+    /// Gets a JSON response from the cache, or from the API if it is not fresh.
+    /// The key identifies the request and must not contain secrets such as API keys.
+    pub async fn get_json(
+        &self,
+        ctx: &impl HttpFeatures,
+        key: &str,
+        url: &Url,
+        headers: Option<header::HeaderMap>,
+        ttl: Duration,
+    ) -> Result<Value, BoxError> {
+        let now_ms = unix_ms();
+        let cached = self.lookup(key, ttl, now_ms).await;
+        let stale = match cached {
+            Some((value, true)) => return Ok(value),
+            Some((value, false)) => Some(value),
+            None => None,
+        };
+
+        let host = url.host_str().unwrap_or_default().to_string();
+        if let Some(until) = self.paused_until(&host)
+            && until > now_ms
+        {
+            return stale.ok_or_else(|| {
+                format!(
+                    "{} is rate limited, retry in {} seconds",
+                    host,
+                    (until - now_ms).div_ceil(1000)
+                )
+                .into()
+            });
+        }
+
+        let mut headers = headers.unwrap_or_default();
+        headers.insert(
+            header::ACCEPT,
+            "application/json".parse().expect("invalid header value"),
+        );
+        let response = ctx
+            .https_call(url.as_str(), http::Method::GET, Some(headers), None)
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => return stale.ok_or(err),
+        };
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            self.pause(&host, now_ms + retry_after.as_millis() as u64);
+            log::warn!(host = host; "rate limited for {} seconds", retry_after.as_secs());
+            return stale.ok_or_else(|| {
+                format!(
+                    "{} is rate limited, retry in {} seconds",
+                    host,
+                    retry_after.as_secs()
+                )
+                .into()
+            });
+        }
+        if !status.is_success() {
+            return stale.ok_or_else(|| format!("{} returned status: {}", host, status).into());
+        }
+
+        let value: Value = response.json().await?;
+        self.insert(key.to_string(), value.clone(), now_ms).await;
+        Ok(value)
+    }
+}
+
+/// Parses a `Retry-After` header in seconds. HTTP dates are not supported by the data APIs.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Arguments for the weather query
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct WeatherArgs {
+    /// The city or place name, e.g. "Zurich"
+    pub location: String,
+    /// The number of forecast days, 1 to 7, 3 by default
+    pub days: Option<u8>,
+}
+
+/// Weather Tool implementation
+///
+/// Gets the current weather and the daily forecast of a place from Open-Meteo.
+///
+/// # API Reference
+/// - Official documentation: https://open-meteo.com/en/docs
+#[derive(Clone)]
+pub struct WeatherTool {
+    cache: DataCache,
+    ttl: Duration,
+    schema: Value,
+}
+
+impl WeatherTool {
+    const NAME: &'static str = "get_weather";
+
+    /// Creates a new WeatherTool. Responses are cached for 10 minutes.
+    pub fn new(cache: DataCache) -> Self {
+        Self {
+            cache,
+            ttl: Duration::from_secs(600),
+            schema: gen_schema_for::<WeatherArgs>(),
+        }
+    }
+
+    /// Gets the weather of the place.
+    pub async fn weather(
+        &self,
+        ctx: &impl HttpFeatures,
+        args: WeatherArgs,
+    ) -> Result<Value, BoxError> {
+        let location = args.location.trim();
+        if location.is_empty() {
+            return Err("location should not be empty".into());
+        }
+        let mut url = Url::parse("https://geocoding-api.open-meteo.com/v1/search")?;
+        url.query_pairs_mut()
+            .append_pair("name", location)
+            .append_pair("count", "1");
+        // places do not move
+        let places = self
+            .cache
+            .get_json(
+                ctx,
+                &format!("geocoding:{}", location.to_lowercase()),
+                &url,
+                None,
+                Duration::from_secs(3600 * 24),
+            )
+            .await?;
+        let place = places
+            .get("results")
+            .and_then(|r| r.get(0))
+            .ok_or_else(|| format!("location {:?} not found", location))?;
+        let (latitude, longitude) = match (
+            place.get("latitude").and_then(|v| v.as_f64()),
+            place.get("longitude").and_then(|v| v.as_f64()),
+        ) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => return Err(format!("location {:?} not found", location).into()),
+        };
+
+        let days = args.days.unwrap_or(3).clamp(1, 7).to_string();
+        let mut url = Url::parse("https://api.open-meteo.com/v1/forecast")?;
+        url.query_pairs_mut()
+            .append_pair("latitude", &format!("{:.2}", latitude))
+            .append_pair("longitude", &format!("{:.2}", longitude))
+            .append_pair(
+                "current",
+                "temperature_2m,relative_humidity_2m,weather_code,wind_speed_10m",
+            )
+            .append_pair(
+                "daily",
+                "weather_code,temperature_2m_max,temperature_2m_min,precipitation_probability_max",
+            )
+            .append_pair("timezone", "auto")
+            .append_pair("forecast_days", &days);
+        let mut forecast = self
+            .cache
+            .get_json(ctx, url.as_str(), &url, None, self.ttl)
+            .await?;
+        if let Some(obj) = forecast.as_object_mut() {
+            obj.insert("location".to_string(), place.clone());
+        }
+        Ok(forecast)
+    }
+}
+
+impl Tool<BaseCtx> for WeatherTool {
+    type Args = WeatherArgs;
+    type Output = Value;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Gets the current weather and the daily forecast of a place. Weather codes are WMO codes."
+            .to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.weather(&ctx, args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+/// Arguments for the market price query
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct MarketPriceArgs {
+    /// The CoinGecko IDs of the coins, e.g. ["bitcoin", "internet-computer"]
+    pub coins: Vec<String>,
+    /// The quote currencies, ["usd"] by default
+    #[serde(default)]
+    pub currencies: Vec<String>,
+}
+
+/// Market Price Tool implementation
+///
+/// Gets crypto prices, 24h changes and market caps from CoinGecko.
+///
+/// # API Reference
+/// - Official documentation: https://docs.coingecko.com/reference/simple-price
+#[derive(Clone)]
+pub struct MarketPriceTool {
+    cache: DataCache,
+    api_key: Option<String>,
+    ttl: Duration,
+    schema: Value,
+}
+
+impl MarketPriceTool {
+    const NAME: &'static str = "get_market_prices";
+
+    /// Creates a new MarketPriceTool, with an optional CoinGecko demo API key.
+    /// Responses are cached for 1 minute.
+    pub fn new(cache: DataCache, api_key: Option<String>) -> Self {
+        Self {
+            cache,
+            api_key,
+            ttl: Duration::from_secs(60),
+            schema: gen_schema_for::<MarketPriceArgs>(),
+        }
+    }
+
+    /// Gets the prices of the coins.
+    pub async fn prices(
+        &self,
+        ctx: &impl HttpFeatures,
+        args: MarketPriceArgs,
+    ) -> Result<Value, BoxError> {
+        let normalize = |items: Vec<String>| {
+            let mut items: Vec<String> = items
+                .into_iter()
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect();
+            items.sort();
+            items.dedup();
+            items.join(",")
+        };
+        let coins = normalize(args.coins);
+        if coins.is_empty() {
+            return Err("coins should not be empty".into());
+        }
+        let currencies = match normalize(args.currencies) {
+            c if c.is_empty() => "usd".to_string(),
+            c => c,
+        };
+
+        let mut url = Url::parse("https://api.coingecko.com/api/v3/simple/price")?;
+        url.query_pairs_mut()
+            .append_pair("ids", &coins)
+            .append_pair("vs_currencies", &currencies)
+            .append_pair("include_24hr_change", "true")
+            .append_pair("include_market_cap", "true");
+        let headers = match &self.api_key {
+            Some(key) => {
+                let mut headers = header::HeaderMap::new();
+                headers.insert("x-cg-demo-api-key", key.parse()?);
+                Some(headers)
+            }
+            None => None,
+        };
+        self.cache
+            .get_json(ctx, url.as_str(), &url, headers, self.ttl)
+            .await
+    }
+}
+
+impl Tool<BaseCtx> for MarketPriceTool {
+    type Args = MarketPriceArgs;
+    type Output = Value;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Gets the current prices, 24h changes and market caps of crypto coins.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.prices(&ctx, args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+/// Arguments for the news headlines query
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct NewsArgs {
+    /// Keywords to search for in the headlines
+    pub query: Option<String>,
+    /// The category: business, entertainment, general, health, science, sports or technology
+    pub category: Option<String>,
+    /// The 2-letter ISO 3166-1 code of the country, e.g. "us"
+    pub country: Option<String>,
+}
+
+/// A news headline
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NewsHeadline {
+    pub title: String,
+    pub source: String,
+    pub url: String,
+    pub published_at: String,
+    pub description: Option<String>,
+}
+
+/// News Tool implementation
+///
+/// Gets the top news headlines from NewsAPI.
+///
+/// # API Reference
+/// - Official documentation: https://newsapi.org/docs/endpoints/top-headlines
+#[derive(Clone)]
+pub struct NewsTool {
+    cache: DataCache,
+    api_key: String,
+    page_size: u8,
+    ttl: Duration,
+    schema: Value,
+}
+
+impl NewsTool {
+    const NAME: &'static str = "get_news_headlines";
+
+    /// Creates a new NewsTool with a NewsAPI key. Responses are cached for 15 minutes.
+    pub fn new(cache: DataCache, api_key: String) -> Self {
+        Self {
+            cache,
+            api_key,
+            page_size: 10,
+            ttl: Duration::from_secs(900),
+            schema: gen_schema_for::<NewsArgs>(),
+        }
+    }
+
+    /// Gets the headlines.
+    pub async fn headlines(
+        &self,
+        ctx: &impl HttpFeatures,
+        args: NewsArgs,
+    ) -> Result<Vec<NewsHeadline>, BoxError> {
+        let mut url = Url::parse("https://newsapi.org/v2/top-headlines")?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("pageSize", &self.page_size.to_string());
+            if let Some(q) = args
+                .query
+                .as_deref()
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+            {
+                query.append_pair("q", q);
+            }
+            if let Some(category) = &args.category {
+                query.append_pair("category", &category.trim().to_lowercase());
+            }
+            // NewsAPI requires a country, a category or a query
+            let country = args
+                .country
+                .map(|c| c.trim().to_lowercase())
+                .filter(|c| !c.is_empty())
+                .unwrap_or_else(|| "us".to_string());
+            query.append_pair("country", &country);
+        }
+        let mut headers = header::HeaderMap::new();
+        headers.insert("x-api-key", self.api_key.parse()?);
+        let json = self
+            .cache
+            .get_json(ctx, url.as_str(), &url, Some(headers), self.ttl)
+            .await?;
+
+        let mut res = Vec::new();
+        if let Some(articles) = json.get("articles").and_then(|v| v.as_array()) {
+            for article in articles {
+                let text = |field: &str| {
+                    article
+                        .get(field)
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                res.push(NewsHeadline {
+                    title: text("title"),
+                    source: article
+                        .get("source")
+                        .and_then(|s| s.get("name"))
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                    url: text("url"),
+                    published_at: text("publishedAt"),
+                    description: article
+                        .get("description")
+                        .and_then(|v| v.as_str())
+                        .map(|v| v.to_string()),
+                });
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl Tool<BaseCtx> for NewsTool {
+    type Args = NewsArgs;
+    type Output = Vec<NewsHeadline>;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Gets the top news headlines, optionally by keywords, category and country.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.headlines(&ctx, args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::EngineBuilder, model::Model};
+    use serde_json::json;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_data_cache() {
+        let cache = DataCache::new(100);
+        let ttl = Duration::from_secs(60);
+        assert!(cache.lookup("k", ttl, 1000).await.is_none());
+        cache.insert("k".to_string(), json!({"v": 1}), 1000).await;
+        assert_eq!(
+            cache.lookup("k", ttl, 2000).await,
+            Some((json!({"v": 1}), true))
+        );
+        assert_eq!(
+            cache.lookup("k", ttl, 62_000).await,
+            Some((json!({"v": 1}), false))
+        );
+
+        let ctx = EngineBuilder::new().mock_ctx();
+        let url = Url::parse("https://api.example.com/data").unwrap();
+        // a paused host serves the stale response without calling the API
+        cache.pause("api.example.com", unix_ms() + 60_000);
+        assert_eq!(
+            cache
+                .get_json(&ctx, "k", &url, None, Duration::ZERO)
+                .await
+                .unwrap(),
+            json!({"v": 1})
+        );
+        let err = cache
+            .get_json(&ctx, "other", &url, None, ttl)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("rate limited"));
+
+        assert_eq!(parse_retry_after(" 30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_data_tools() {
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .mock_ctx();
+        let cache = DataCache::new(100);
+
+        let weather = WeatherTool::new(cache.clone());
+        let res = weather
+            .weather(
+                &ctx,
+                WeatherArgs {
+                    location: "Zurich".to_string(),
+                    days: Some(2),
+                },
+            )
+            .await
+            .unwrap();
+        println!("{}", res);
+
+        let prices = MarketPriceTool::new(cache, None);
+        let res = prices
+            .prices(
+                &ctx,
+                MarketPriceArgs {
+                    coins: vec!["bitcoin".to_string(), "internet-computer".to_string()],
+                    currencies: vec![],
+                },
+            )
+            .await
+            .unwrap();
+        println!("{}", res);
+    }
+}
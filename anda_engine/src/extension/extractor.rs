@@ -209,14 +209,13 @@ impl<T: JsonSchema + DeserializeOwned + Serialize + Send + Sync> Extractor<T> {
         ctx: &impl CompletionFeatures,
         prompt: String,
     ) -> Result<(T, AgentOutput), BoxError> {
-        let req = CompletionRequest {
-            system: Some(self.system.clone()),
-            prompt,
-            tools: vec![self.tool.definition()],
-            tool_choice_required: true,
-            max_tokens: self.max_tokens,
-            ..Default::default()
-        };
+        let mut req = CompletionRequest::builder()
+            .system(self.system.clone())
+            .tool(self.tool.definition())
+            .tool_choice_required()
+            .prompt(prompt)
+            .build();
+        req.max_tokens = self.max_tokens;
 
         let mut res = ctx.completion(req, None).await?;
         if let Some(tool_calls) = &mut res.tool_calls {
@@ -0,0 +1,216 @@
+//! Terminology Enforcement Module
+//!
+//! This module keeps brand and product names consistent in agent outputs. A [`Glossary`]
+//! lists the required form of each term together with the variants the model tends to
+//! produce instead (wrong casing, spellings or translations). It is applied twice:
+//! - as prompt guidance appended to the system prompt, see [`Glossary::guidance`];
+//! - as a post-processor on the generated text, see [`Glossary::enforce`].
+//!
+//! Matching is ASCII case-insensitive. Terms starting or ending with an ASCII letter or
+//! digit only match at word boundaries, and matches inside URLs, paths and identifiers
+//! (e.g. `icp.ai` or `icp_ledger`) are left untouched.
+//!
+//! # Example
+//! ```rust,ignore
+//! let glossary = Glossary::from_toml(r#"
+//! [[terms]]
+//! term = "Internet Computer"
+//! variants = ["internet-computer", "互联网计算机"]
+//!
+//! [[terms]]
+//! term = "ICP"
+//! "#)?;
+//! assert_eq!(glossary.enforce("Icp runs on the internet computer"), "ICP runs on the Internet Computer");
+//! ```
+
+use anda_core::BoxError;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// A term with its required form.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Term {
+    /// The required form of the term, e.g. "Internet Computer".
+    pub term: String,
+
+    /// Other spellings or translations to be replaced by the term.
+    #[serde(default)]
+    pub variants: Vec<String>,
+
+    /// Optional usage note for the model.
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// A list of terms that must be used in their exact form.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Glossary {
+    pub terms: Vec<Term>,
+}
+
+impl Glossary {
+    /// Creates a new Glossary from terms.
+    pub fn new(terms: Vec<Term>) -> Self {
+        Self { terms }
+    }
+
+    /// Creates a Glossary from a TOML string with `[[terms]]` tables.
+    pub fn from_toml(content: &str) -> Result<Self, BoxError> {
+        let glossary: Self = toml::from_str(content)?;
+        Ok(glossary)
+    }
+
+    /// Returns true if the glossary has no terms.
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Returns (pattern, term index) pairs, longest pattern first.
+    fn patterns(&self) -> Vec<(&str, usize)> {
+        let mut patterns: Vec<(&str, usize)> = Vec::new();
+        for (i, term) in self.terms.iter().enumerate() {
+            for p in std::iter::once(&term.term).chain(term.variants.iter()) {
+                let p = p.trim();
+                if !p.is_empty() && !patterns.iter().any(|(q, _)| q.eq_ignore_ascii_case(p)) {
+                    patterns.push((p, i));
+                }
+            }
+        }
+        patterns.sort_by_key(|(p, _)| std::cmp::Reverse(p.len()));
+        patterns
+    }
+
+    /// Returns the prompt guidance listing the required terms,
+    /// to be appended to the system prompt.
+    pub fn guidance(&self) -> String {
+        if self.terms.is_empty() {
+            return String::new();
+        }
+
+        let mut output = String::from(
+            "## Terminology\nAlways write the following terms exactly as given, with the same casing, and never translate them:\n",
+        );
+        for term in &self.terms {
+            let _ = write!(output, "- {}", term.term);
+            if !term.variants.is_empty() {
+                let _ = write!(output, " (not: {})", term.variants.join(", "));
+            }
+            if let Some(note) = &term.note {
+                let _ = write!(output, ": {}", note);
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    /// Replaces variants and miscased occurrences of the terms in the text with their required form.
+    pub fn enforce(&self, text: &str) -> String {
+        let patterns = self.patterns();
+        if patterns.is_empty() {
+            return text.to_string();
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut i = 0;
+        'outer: while i < text.len() {
+            let rest = &text.as_bytes()[i..];
+            for (pattern, idx) in &patterns {
+                let n = pattern.len();
+                if rest.len() >= n
+                    && rest[..n].eq_ignore_ascii_case(pattern.as_bytes())
+                    && text.is_char_boundary(i + n)
+                    && is_boundary_before(text, i, pattern)
+                    && is_boundary_after(text, i + n, pattern)
+                {
+                    output.push_str(&self.terms[*idx].term);
+                    i += n;
+                    continue 'outer;
+                }
+            }
+
+            let ch = text[i..].chars().next().unwrap();
+            output.push(ch);
+            i += ch.len_utf8();
+        }
+        output
+    }
+}
+
+// Only ASCII words have boundaries, so that terms can be found in CJK text.
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_boundary_before(text: &str, at: usize, pattern: &str) -> bool {
+    if !pattern.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return true;
+    }
+    match text[..at].chars().next_back() {
+        None => true,
+        Some(c) => !is_word_char(c) && !matches!(c, '.' | '/' | '@' | '#' | '$'),
+    }
+}
+
+fn is_boundary_after(text: &str, at: usize, pattern: &str) -> bool {
+    if !pattern.ends_with(|c: char| c.is_ascii_alphanumeric()) {
+        return true;
+    }
+    let mut chars = text[at..].chars();
+    match chars.next() {
+        None => true,
+        // a dot followed by a word character is part of a domain or file name
+        Some('.' | '/' | '@') => !chars.next().is_some_and(is_word_char),
+        Some(c) => !is_word_char(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glossary() {
+        let glossary = Glossary::from_toml(
+            r#"
+            [[terms]]
+            term = "Internet Computer"
+            variants = ["internet-computer", "互联网计算机"]
+
+            [[terms]]
+            term = "ICP"
+            note = "the native token of the Internet Computer"
+
+            [[terms]]
+            term = "ckBTC"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            glossary.enforce("Icp runs on the internet computer."),
+            "ICP runs on the Internet Computer."
+        );
+        assert_eq!(
+            glossary.enforce("互联网计算机上的Icp和CKBTC"),
+            "Internet Computer上的ICP和ckBTC"
+        );
+        assert_eq!(
+            glossary.enforce("see https://icp.ai/icp_ledger or topics, icps, #icp"),
+            "see https://icp.ai/icp_ledger or topics, icps, #icp"
+        );
+        assert_eq!(
+            glossary.enforce("Buy icp. Or icp-based"),
+            "Buy ICP. Or ICP-based"
+        );
+
+        assert_eq!(
+            glossary.guidance(),
+            "## Terminology\nAlways write the following terms exactly as given, with the same casing, and never translate them:\n\
+            - Internet Computer (not: internet-computer, 互联网计算机)\n\
+            - ICP: the native token of the Internet Computer\n\
+            - ckBTC\n"
+        );
+        assert!(Glossary::default().guidance().is_empty());
+        assert_eq!(Glossary::default().enforce("icp"), "icp");
+    }
+}
@@ -0,0 +1,774 @@
+//! Model Context Protocol (MCP) Client
+//!
+//! This module connects to external MCP servers and wraps the tools they expose as local
+//! tools, so that agents discover and call them as any other tool of the engine.
+//!
+//! # Features
+//! - Stdio transport: spawns the server process and exchanges newline-delimited JSON-RPC
+//!   messages over its stdin and stdout
+//! - HTTP transport: the Streamable HTTP transport, with JSON or SSE responses and
+//!   `Mcp-Session-Id` sessions
+//! - Each remote tool becomes a [`McpTool`] named `{server}_{tool}`, with the tool's input
+//!   schema as parameters
+//! - Text and structured content are returned as the tool output, which the engine sets as
+//!   `ToolCall.result`; images, audio and embedded resources are returned as resources
+//!
+//! # Usage
+//! ```rust,ignore
+//! let mut cmd = tokio::process::Command::new("npx");
+//! cmd.args(["-y", "@modelcontextprotocol/server-filesystem", "/data"]);
+//! let files = McpClient::connect("files", StdioTransport::spawn(cmd)?).await?;
+//!
+//! let http = HttpTransport::new(reqwest::Client::new(), "https://mcp.example.com/mcp")
+//!     .with_header("authorization", "Bearer ...")?;
+//! let remote = McpClient::connect("remote", http).await?;
+//!
+//! let engine = Engine::builder()
+//!     .register_tools(files.tool_set().await?)?
+//!     .register_tools(remote.tool_set().await?)?
+//!     .register_agent(my_agent)?
+//!     .build("default_agent".to_string())?;
+//! ```
+
+use anda_core::{
+    BoxError, FunctionDefinition, Resource, Tool, ToolOutput, ToolSet, mime_type_tag,
+    validate_function_name,
+};
+use async_trait::async_trait;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::header::{ACCEPT, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{Mutex, RwLock, oneshot},
+    task::JoinHandle,
+};
+
+use crate::context::BaseCtx;
+
+/// The MCP protocol version requested by the client.
+pub const MCP_PROTOCOL_VERSION: &str = "2025-06-18";
+
+const SESSION_ID_HEADER: &str = "mcp-session-id";
+const PROTOCOL_VERSION_HEADER: &str = "mcp-protocol-version";
+
+/// A transport of JSON-RPC messages to an MCP server.
+#[async_trait]
+pub trait McpTransport: Send + Sync {
+    /// Sends a request and returns the result of its response.
+    async fn request(&self, method: &str, params: Value) -> Result<Value, BoxError>;
+
+    /// Sends a notification, which has no response.
+    async fn notify(&self, method: &str, params: Value) -> Result<(), BoxError>;
+}
+
+/// Returns the result of a JSON-RPC response, or its error.
+fn rpc_result(mut msg: Value) -> Result<Value, BoxError> {
+    if let Some(err) = msg.get("error") {
+        return Err(format!(
+            "MCP error {}: {}",
+            err.get("code").and_then(|v| v.as_i64()).unwrap_or_default(),
+            err.get("message")
+                .and_then(|v| v.as_str())
+                .unwrap_or("unknown error")
+        )
+        .into());
+    }
+    Ok(msg
+        .get_mut("result")
+        .map(Value::take)
+        .unwrap_or(Value::Null))
+}
+
+/// The pending requests, None when the connection is closed.
+type Pending = Arc<std::sync::Mutex<Option<HashMap<u64, oneshot::Sender<Value>>>>>;
+type Writer = Arc<Mutex<Box<dyn AsyncWrite + Send + Unpin>>>;
+
+/// The stdio transport: newline-delimited JSON-RPC messages over a pair of streams, usually
+/// the stdout and stdin of a spawned server process.
+pub struct StdioTransport {
+    writer: Writer,
+    pending: Pending,
+    next_id: AtomicU64,
+    reader: JoinHandle<()>,
+    // the server process is killed when the transport is dropped
+    _child: Option<tokio::process::Child>,
+}
+
+impl StdioTransport {
+    /// Creates a transport that reads messages from `reader` and writes them to `writer`.
+    pub fn new<R, W>(reader: R, writer: W) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let writer: Writer = Arc::new(Mutex::new(Box::new(writer)));
+        let pending: Pending = Arc::new(std::sync::Mutex::new(Some(HashMap::new())));
+        let reader = tokio::spawn(Self::read_loop(reader, writer.clone(), pending.clone()));
+        Self {
+            writer,
+            pending,
+            next_id: AtomicU64::new(1),
+            reader,
+            _child: None,
+        }
+    }
+
+    /// Spawns the server process of the command and connects to its stdin and stdout.
+    /// Its stderr is inherited.
+    pub fn spawn(mut command: tokio::process::Command) -> Result<Self, BoxError> {
+        let mut child = command
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or("failed to open the stdin of MCP server")?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or("failed to open the stdout of MCP server")?;
+        let mut transport = Self::new(stdout, stdin);
+        transport._child = Some(child);
+        Ok(transport)
+    }
+
+    async fn read_loop<R: AsyncRead + Unpin>(reader: R, writer: Writer, pending: Pending) {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let Ok(msg) = serde_json::from_str::<Value>(&line) else {
+                log::warn!("invalid MCP message: {}", line);
+                continue;
+            };
+            match (msg.get("id").cloned(), msg.get("method")) {
+                // a request from the server, only ping is supported
+                (Some(id), Some(method)) => {
+                    let res = if method == "ping" {
+                        json!({"jsonrpc": "2.0", "id": id, "result": {}})
+                    } else {
+                        json!({"jsonrpc": "2.0", "id": id, "error": {
+                            "code": -32601,
+                            "message": "method not found",
+                        }})
+                    };
+                    let _ = write_message(&writer, &res).await;
+                }
+                (Some(id), None) => {
+                    let tx = id
+                        .as_u64()
+                        .and_then(|id| pending.lock().unwrap().as_mut()?.remove(&id));
+                    if let Some(tx) = tx {
+                        let _ = tx.send(msg);
+                    }
+                }
+                // notifications are ignored
+                _ => {}
+            }
+        }
+        // fails the pending and later requests
+        pending.lock().unwrap().take();
+    }
+}
+
+impl Drop for StdioTransport {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+async fn write_message(writer: &Writer, msg: &Value) -> Result<(), BoxError> {
+    let mut line = serde_json::to_vec(msg)?;
+    line.push(b'\n');
+    let mut writer = writer.lock().await;
+    writer.write_all(&line).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[async_trait]
+impl McpTransport for StdioTransport {
+    async fn request(&self, method: &str, params: Value) -> Result<Value, BoxError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or("MCP server closed the connection")?
+            .insert(id, tx);
+        let msg = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        if let Err(err) = write_message(&self.writer, &msg).await {
+            if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+                pending.remove(&id);
+            }
+            return Err(err);
+        }
+        let res = rx.await.map_err(|_| "MCP server closed the connection")?;
+        rpc_result(res)
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<(), BoxError> {
+        let msg = json!({"jsonrpc": "2.0", "method": method, "params": params});
+        write_message(&self.writer, &msg).await
+    }
+}
+
+/// The Streamable HTTP transport: JSON-RPC messages are posted to the endpoint of the server,
+/// which responds with a JSON message or an SSE stream.
+pub struct HttpTransport {
+    client: reqwest::Client,
+    endpoint: String,
+    headers: HeaderMap,
+    session_id: RwLock<Option<String>>,
+    next_id: AtomicU64,
+}
+
+impl HttpTransport {
+    /// Creates a transport to the MCP endpoint of the server.
+    pub fn new(client: reqwest::Client, endpoint: &str) -> Self {
+        Self {
+            client,
+            endpoint: endpoint.to_string(),
+            headers: HeaderMap::new(),
+            session_id: RwLock::new(None),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Adds a header to all requests, e.g. an authorization header.
+    pub fn with_header(mut self, name: &str, value: &str) -> Result<Self, BoxError> {
+        self.headers
+            .insert(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+        Ok(self)
+    }
+
+    /// Posts a message, and returns the response with the id if any.
+    async fn post(&self, msg: &Value, id: Option<u64>) -> Result<Option<Value>, BoxError> {
+        let mut req = self
+            .client
+            .post(&self.endpoint)
+            .headers(self.headers.clone())
+            .header(ACCEPT, "application/json, text/event-stream")
+            .header(PROTOCOL_VERSION_HEADER, MCP_PROTOCOL_VERSION)
+            .json(msg);
+        if let Some(session_id) = self.session_id.read().await.as_ref() {
+            req = req.header(SESSION_ID_HEADER, session_id);
+        }
+
+        let res = req.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(format!("MCP request failed, status: {}, body: {}", status, body).into());
+        }
+        if let Some(session_id) = res
+            .headers()
+            .get(SESSION_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+        {
+            *self.session_id.write().await = Some(session_id.to_string());
+        }
+        let Some(id) = id else {
+            return Ok(None);
+        };
+
+        let is_sse = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        let body = res.text().await?;
+        if is_sse {
+            Ok(sse_response(&body, id))
+        } else {
+            Ok(Some(serde_json::from_str(&body)?))
+        }
+    }
+}
+
+/// Returns the JSON-RPC response with the id from the events of an SSE stream.
+fn sse_response(body: &str, id: u64) -> Option<Value> {
+    body.replace("\r\n", "\n").split("\n\n").find_map(|event| {
+        let data = event
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(|data| data.strip_prefix(' ').unwrap_or(data))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let msg: Value = serde_json::from_str(&data).ok()?;
+        (msg.get("id").and_then(|v| v.as_u64()) == Some(id) && msg.get("method").is_none())
+            .then_some(msg)
+    })
+}
+
+#[async_trait]
+impl McpTransport for HttpTransport {
+    async fn request(&self, method: &str, params: Value) -> Result<Value, BoxError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let msg = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let res = self
+            .post(&msg, Some(id))
+            .await?
+            .ok_or_else(|| format!("no response to MCP request {}", method))?;
+        rpc_result(res)
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<(), BoxError> {
+        let msg = json!({"jsonrpc": "2.0", "method": method, "params": params});
+        self.post(&msg, None).await?;
+        Ok(())
+    }
+}
+
+/// The name and version of an MCP server.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct McpServerInfo {
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+/// A tool exposed by an MCP server.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpToolInfo {
+    /// The name of the tool on the server.
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The JSON schema of the tool's arguments.
+    pub input_schema: Value,
+}
+
+/// A content item of a tool call result.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum McpContent {
+    Text {
+        text: String,
+    },
+    Image {
+        /// Base64-encoded data.
+        data: String,
+        mime_type: String,
+    },
+    Audio {
+        /// Base64-encoded data.
+        data: String,
+        mime_type: String,
+    },
+    /// An embedded resource, with either text or base64-encoded blob.
+    Resource {
+        resource: McpEmbeddedResource,
+    },
+    /// A link to a resource of the server.
+    ResourceLink {
+        uri: String,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        mime_type: Option<String>,
+    },
+    /// A content type unknown to the client, ignored.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A resource embedded in a tool call result.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpEmbeddedResource {
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+/// The result of a tool call.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpCallResult {
+    #[serde(default)]
+    pub content: Vec<McpContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_content: Option<Value>,
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+impl McpCallResult {
+    /// Converts the result into a tool output. The output is the structured content if any,
+    /// or the text contents joined by new lines. Images, audio and embedded blobs are
+    /// returned as resources. Returns an error with the text contents if the call failed.
+    pub fn into_output(self) -> Result<ToolOutput<Value>, BoxError> {
+        let mut texts: Vec<String> = Vec::new();
+        let mut resources: Vec<Resource> = Vec::new();
+        for content in self.content {
+            match content {
+                McpContent::Text { text } => texts.push(text),
+                McpContent::Image { data, mime_type } | McpContent::Audio { data, mime_type } => {
+                    let bytes = BASE64_STANDARD.decode(data)?;
+                    resources.push(Resource::from_bytes(Some(&mime_type), bytes)?);
+                }
+                McpContent::Resource { resource } => match (resource.text, resource.blob) {
+                    (Some(text), _) => texts.push(text),
+                    (None, Some(blob)) => {
+                        let bytes = BASE64_STANDARD.decode(blob)?;
+                        let mut r = Resource::from_bytes(resource.mime_type.as_deref(), bytes)?;
+                        r.uri = Some(resource.uri);
+                        resources.push(r);
+                    }
+                    (None, None) => {}
+                },
+                McpContent::ResourceLink {
+                    uri,
+                    name,
+                    description,
+                    mime_type,
+                } => resources.push(Resource {
+                    tag: mime_type
+                        .as_deref()
+                        .map(mime_type_tag)
+                        .unwrap_or("file")
+                        .to_string(),
+                    uri: Some(uri),
+                    name,
+                    description,
+                    mime_type,
+                    ..Default::default()
+                }),
+                McpContent::Unknown => {}
+            }
+        }
+
+        if self.is_error {
+            let msg = texts.join("\n");
+            return Err(if msg.is_empty() {
+                "MCP tool call failed".into()
+            } else {
+                msg.into()
+            });
+        }
+
+        let mut output = ToolOutput::new(
+            self.structured_content
+                .unwrap_or_else(|| Value::String(texts.join("\n"))),
+        );
+        if !resources.is_empty() {
+            output.resources = Some(resources);
+        }
+        output.usage.requests = 1;
+        Ok(output)
+    }
+}
+
+/// A client connected to an MCP server.
+pub struct McpClient {
+    name: String,
+    transport: Box<dyn McpTransport>,
+    server_info: McpServerInfo,
+    instructions: Option<String>,
+}
+
+impl McpClient {
+    /// Connects to the MCP server with the transport, and initializes the session.
+    /// The name of the client prefixes the names of the server's tools, so it must be a
+    /// valid function name.
+    pub async fn connect(
+        name: &str,
+        transport: impl McpTransport + 'static,
+    ) -> Result<Arc<Self>, BoxError> {
+        validate_function_name(name)
+            .map_err(|err| format!("invalid MCP client name {:?}: {}", name, err))?;
+        let res = transport
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": MCP_PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await?;
+        let server_info: McpServerInfo = res
+            .get("serverInfo")
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default();
+        let instructions = res
+            .get("instructions")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+        transport
+            .notify("notifications/initialized", json!({}))
+            .await?;
+
+        Ok(Arc::new(Self {
+            name: name.to_string(),
+            transport: Box::new(transport),
+            server_info,
+            instructions,
+        }))
+    }
+
+    /// Returns the name of the client.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the name and version of the server.
+    pub fn server_info(&self) -> &McpServerInfo {
+        &self.server_info
+    }
+
+    /// Returns the instructions of the server for the model, if any.
+    pub fn instructions(&self) -> Option<&str> {
+        self.instructions.as_deref()
+    }
+
+    /// Lists all tools of the server.
+    pub async fn list_tools(&self) -> Result<Vec<McpToolInfo>, BoxError> {
+        let mut tools: Vec<McpToolInfo> = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({"cursor": cursor}),
+                None => json!({}),
+            };
+            let mut res = self.transport.request("tools/list", params).await?;
+            let page: Vec<McpToolInfo> =
+                serde_json::from_value(res.get_mut("tools").map(Value::take).unwrap_or_default())?;
+            tools.extend(page);
+            cursor = res
+                .get("nextCursor")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string());
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// Calls a tool of the server by its name on the server.
+    pub async fn call_tool(
+        &self,
+        name: &str,
+        arguments: Map<String, Value>,
+    ) -> Result<McpCallResult, BoxError> {
+        let res = self
+            .transport
+            .request("tools/call", json!({"name": name, "arguments": arguments}))
+            .await?;
+        Ok(serde_json::from_value(res)?)
+    }
+
+    /// Lists the tools of the server and wraps them as local tools, to register with
+    /// `EngineBuilder::register_tools`.
+    pub async fn tool_set(self: &Arc<Self>) -> Result<ToolSet<BaseCtx>, BoxError> {
+        let mut set = ToolSet::new();
+        for info in self.list_tools().await? {
+            set.add(McpTool::new(self.clone(), info))?;
+        }
+        Ok(set)
+    }
+}
+
+/// A tool of an MCP server, called through the client.
+#[derive(Clone)]
+pub struct McpTool {
+    client: Arc<McpClient>,
+    name: String,
+    info: McpToolInfo,
+}
+
+impl McpTool {
+    /// Wraps the tool of the client's server. The local name of the tool is
+    /// `{client}_{tool}`, in lowercase, with other characters than letters, digits and
+    /// underscores replaced by underscores, and truncated to 64 characters.
+    pub fn new(client: Arc<McpClient>, info: McpToolInfo) -> Self {
+        let mut name: String = format!("{}_{}", client.name, info.name)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        name.truncate(64);
+        Self { client, name, info }
+    }
+}
+
+impl Tool<BaseCtx> for McpTool {
+    type Args = Map<String, Value>;
+    type Output = Value;
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn description(&self) -> String {
+        self.info.description.clone().unwrap_or_default()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.info.input_schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        _ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        self.client
+            .call_tool(&self.info.name, args)
+            .await?
+            .into_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineBuilder;
+
+    /// A fake MCP server with two pages of tools.
+    async fn serve(reader: impl AsyncRead + Unpin, mut writer: impl AsyncWrite + Unpin) {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let req: Value = serde_json::from_str(&line).unwrap();
+            let Some(id) = req.get("id").cloned() else {
+                continue;
+            };
+            let result = match req["method"].as_str().unwrap() {
+                "initialize" => json!({
+                    "protocolVersion": MCP_PROTOCOL_VERSION,
+                    "capabilities": {"tools": {}},
+                    "serverInfo": {"name": "fake", "version": "1.0"},
+                }),
+                "tools/list" if req["params"].get("cursor").is_none() => json!({
+                    "tools": [{
+                        "name": "echo",
+                        "description": "Echoes the text.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {"text": {"type": "string"}},
+                            "required": ["text"],
+                        },
+                    }],
+                    "nextCursor": "2",
+                }),
+                "tools/list" => json!({
+                    "tools": [{"name": "fail-always", "inputSchema": {"type": "object"}}],
+                }),
+                "tools/call" if req["params"]["name"] == "echo" => json!({
+                    "content": [
+                        {"type": "text", "text": req["params"]["arguments"]["text"]},
+                        {"type": "image", "data": "aGVsbG8=", "mimeType": "image/png"},
+                    ],
+                }),
+                _ => json!({
+                    "content": [{"type": "text", "text": "something went wrong"}],
+                    "isError": true,
+                }),
+            };
+            let res = json!({"jsonrpc": "2.0", "id": id, "result": result});
+            let mut data = serde_json::to_vec(&res).unwrap();
+            data.push(b'\n');
+            writer.write_all(&data).await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mcp_client() {
+        let (client_side, server_side) = tokio::io::duplex(4096);
+        let (client_reader, client_writer) = tokio::io::split(client_side);
+        let (server_reader, server_writer) = tokio::io::split(server_side);
+        tokio::spawn(serve(server_reader, server_writer));
+
+        let client = McpClient::connect("fake", StdioTransport::new(client_reader, client_writer))
+            .await
+            .unwrap();
+        assert_eq!(client.server_info().name, "fake");
+
+        let tools = client.tool_set().await.unwrap();
+        assert_eq!(
+            tools.set.keys().collect::<Vec<_>>(),
+            vec!["fake_echo", "fake_fail_always"]
+        );
+        let definition = tools.definition("fake_echo").unwrap();
+        assert_eq!(definition.description, "Echoes the text.");
+        assert_eq!(definition.parameters["required"], json!(["text"]));
+
+        let ctx = EngineBuilder::new().mock_ctx();
+        let output = tools
+            .get("fake_echo")
+            .unwrap()
+            .call(ctx.base.clone(), r#"{"text":"hi"}"#.to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(output.output, json!("hi"));
+        let resources = output.resources.unwrap();
+        assert_eq!(resources[0].tag, "image");
+        assert_eq!(resources[0].blob.as_ref().unwrap().as_ref(), b"hello");
+
+        let err = tools
+            .get("fake_fail_always")
+            .unwrap()
+            .call(ctx.base.clone(), "{}".to_string(), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("something went wrong"));
+
+        // requests fail once the server closes the connection
+        let (client_side, server_side) = tokio::io::duplex(4096);
+        let (client_reader, client_writer) = tokio::io::split(client_side);
+        drop(server_side);
+        let transport = StdioTransport::new(client_reader, client_writer);
+        assert!(transport.request("ping", json!({})).await.is_err());
+        assert!(transport.request("ping", json!({})).await.is_err());
+    }
+
+    #[test]
+    fn test_sse_response() {
+        let body = "event: message\ndata: {\"jsonrpc\":\"2.0\",\"method\":\"notifications/progress\"}\n\n\
+            event: message\ndata: {\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{\"tools\":[]}}\n\n";
+        let msg = sse_response(body, 2).unwrap();
+        assert_eq!(rpc_result(msg).unwrap(), json!({"tools": []}));
+        assert!(sse_response(body, 3).is_none());
+
+        let err = rpc_result(json!({"id": 1, "error": {"code": -32602, "message": "bad"}}));
+        assert_eq!(err.unwrap_err().to_string(), "MCP error -32602: bad");
+    }
+}
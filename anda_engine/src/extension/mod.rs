@@ -6,11 +6,19 @@
 //!
 //! # Key Components
 //!
+//! - **Adaptive Context**: Tunes the retrieval top-k and history window by the context that answers use
 //! - **Attention Management**: Controls how agents focus on and respond to content
+//! - **Calendar**: Creates calendar events as ICS files, in CalDAV or Google Calendar
+//! - **Chart**: Renders line, area, scatter and bar charts from series data as SVG images
 //! - **Character System**: Defines agent personalities and communication styles
+//! - **Data Tools**: Weather, market prices and news headlines with shared response caching
 //! - **Extraction Tools**: Enables structured data extraction from unstructured text
+//! - **Glossary**: Enforces exact brand and product terminology in outputs
 //! - **Google Web Search Tool**: Enables web searches and retrieve results.
+//! - **MCP Client**: Calls the tools of external Model Context Protocol servers
+//! - **Output Safety Filter**: Masks or blocks profanity and brand-unsafe content in outputs
 //! - **Document Segmentation**: Breaks down large documents into manageable chunks
+//! - **Translation**: Translates multilingual inputs for retrieval and answers back
 //!
 //! # Usage
 //!
@@ -21,8 +29,16 @@
 //! 4. Apply document segmentation for large content processing
 //!
 
+pub mod adaptive;
 pub mod attention;
+pub mod calendar;
 pub mod character;
+pub mod chart;
+pub mod data;
 pub mod extractor;
+pub mod glossary;
 pub mod google;
+pub mod mcp;
+pub mod safety;
 pub mod segmenter;
+pub mod translator;
@@ -0,0 +1,299 @@
+//! Output Safety Filter Module
+//!
+//! This module provides a profanity and brand-safety filter for agent outputs. It runs as
+//! an engine [`Hook`] after the agent has generated its answer and before the output is
+//! returned to the caller, so connectors (Twitter, Telegram, Discord...) only ever deliver
+//! filtered content.
+//!
+//! # Severity Tiers
+//! - [`Severity::Warn`]: the output is delivered unchanged, the match is logged
+//! - [`Severity::Mask`]: the matched text is replaced with `*`
+//! - [`Severity::Block`]: the whole output is replaced with the policy's block message
+//!
+//! Each agent can have its own [`SafetyPolicy`], falling back to the default policy.
+//! Phrases in a policy's allowlist override its terms, e.g. allowing "Hell's Kitchen"
+//! while masking "hell".
+//!
+//! # Example
+//! ```rust,ignore
+//! let filter = OutputFilter::new(SafetyPolicy::from_toml(r#"
+//! allowlist = ["Hell's Kitchen"]
+//!
+//! [[terms]]
+//! term = "hell"
+//! severity = "mask"
+//! "#)?)
+//! .with_agent_policy("kids_tutor", strict_policy);
+//!
+//! let mut hooks = Hooks::new();
+//! hooks.add(Box::new(filter));
+//! let engine = Engine::builder().with_hooks(Arc::new(hooks));
+//! ```
+
+use anda_core::{AgentOutput, BoxError};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::{context::AgentCtx, engine::Hook};
+
+/// The failed reason of a blocked output.
+pub static CONTENT_BLOCKED: &str = "CONTENT_BLOCKED";
+
+/// What to do when a filtered term is found, in increasing order of severity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Deliver the output unchanged and log the match.
+    #[default]
+    Warn,
+    /// Replace the matched text with `*`.
+    Mask,
+    /// Replace the whole output with the block message.
+    Block,
+}
+
+/// A filtered term.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilteredTerm {
+    /// The term, matched ASCII case-insensitively and at word boundaries.
+    pub term: String,
+
+    /// The severity of the term.
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+/// A term found in an output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterMatch {
+    /// The filtered term.
+    pub term: String,
+    /// The severity of the term.
+    pub severity: Severity,
+    /// The byte range of the match in the output.
+    pub range: Range<usize>,
+}
+
+/// The result of filtering an output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterResult {
+    /// The filtered content.
+    pub content: String,
+    /// The terms found in the original content.
+    pub matches: Vec<FilterMatch>,
+    /// Whether the output is blocked.
+    pub blocked: bool,
+}
+
+/// A set of filtered terms with allowlist overrides.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SafetyPolicy {
+    /// The filtered terms.
+    #[serde(default)]
+    pub terms: Vec<FilteredTerm>,
+
+    /// Phrases that are always allowed, even if they contain filtered terms.
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+
+    /// The content returned instead of a blocked output.
+    #[serde(default)]
+    pub block_message: Option<String>,
+}
+
+impl SafetyPolicy {
+    /// Creates a SafetyPolicy from a TOML string.
+    pub fn from_toml(content: &str) -> Result<Self, BoxError> {
+        let policy: Self = toml::from_str(content)?;
+        Ok(policy)
+    }
+
+    /// Finds the filtered terms in the text, skipping allowlisted phrases,
+    /// and applies the highest severity found.
+    pub fn filter(&self, text: &str) -> FilterResult {
+        let allowed: Vec<Range<usize>> = self
+            .allowlist
+            .iter()
+            .flat_map(|phrase| find_all(text, phrase))
+            .collect();
+
+        let mut matches: Vec<FilterMatch> = Vec::new();
+        for term in &self.terms {
+            for range in find_all(text, &term.term) {
+                if allowed
+                    .iter()
+                    .any(|a| a.start <= range.start && range.end <= a.end)
+                {
+                    continue;
+                }
+                matches.push(FilterMatch {
+                    term: term.term.clone(),
+                    severity: term.severity,
+                    range,
+                });
+            }
+        }
+        matches.sort_by_key(|m| m.range.start);
+
+        let blocked = matches.iter().any(|m| m.severity == Severity::Block);
+        let content = if blocked {
+            self.block_message
+                .clone()
+                .unwrap_or_else(|| "Sorry, I can't share that response.".to_string())
+        } else {
+            let mut content = text.to_string();
+            // mask from the end, so that earlier ranges stay valid
+            let mut end = text.len();
+            for m in matches.iter().rev() {
+                if m.severity == Severity::Mask && m.range.end <= end {
+                    end = m.range.start;
+                    let stars = "*".repeat(text[m.range.clone()].chars().count());
+                    content.replace_range(m.range.clone(), &stars);
+                }
+            }
+            content
+        };
+
+        FilterResult {
+            content,
+            matches,
+            blocked,
+        }
+    }
+}
+
+/// Returns the byte ranges of the non-overlapping occurrences of the pattern.
+fn find_all(text: &str, pattern: &str) -> Vec<Range<usize>> {
+    let pattern = pattern.trim().as_bytes();
+    let bytes = text.as_bytes();
+    let n = pattern.len();
+    let mut ranges = Vec::new();
+    if n == 0 {
+        return ranges;
+    }
+
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let mut i = 0;
+    while i + n <= bytes.len() {
+        if bytes[i..i + n].eq_ignore_ascii_case(pattern)
+            && text.is_char_boundary(i)
+            && text.is_char_boundary(i + n)
+            && !(is_word(pattern[0]) && i > 0 && is_word(bytes[i - 1]))
+            && !(is_word(pattern[n - 1]) && i + n < bytes.len() && is_word(bytes[i + n]))
+        {
+            ranges.push(i..i + n);
+            i += n;
+        } else {
+            i += 1;
+        }
+    }
+    ranges
+}
+
+/// An engine hook that filters agent outputs with per-agent safety policies.
+#[derive(Clone, Debug, Default)]
+pub struct OutputFilter {
+    default: SafetyPolicy,
+    agents: BTreeMap<String, SafetyPolicy>,
+}
+
+impl OutputFilter {
+    /// Creates a new OutputFilter with the default policy for all agents.
+    pub fn new(default: SafetyPolicy) -> Self {
+        Self {
+            default,
+            agents: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the policy of an agent, overriding the default policy.
+    pub fn with_agent_policy(mut self, agent: &str, policy: SafetyPolicy) -> Self {
+        self.agents.insert(agent.to_ascii_lowercase(), policy);
+        self
+    }
+
+    /// Returns the policy of the agent.
+    pub fn policy(&self, agent: &str) -> &SafetyPolicy {
+        self.agents.get(agent).unwrap_or(&self.default)
+    }
+}
+
+#[async_trait]
+impl Hook for OutputFilter {
+    async fn on_agent_end(
+        &self,
+        _ctx: &AgentCtx,
+        agent: &str,
+        mut output: AgentOutput,
+    ) -> Result<AgentOutput, BoxError> {
+        let rt = self.policy(agent).filter(&output.content);
+        if rt.matches.is_empty() {
+            return Ok(output);
+        }
+
+        let terms: Vec<&str> = rt.matches.iter().map(|m| m.term.as_str()).collect();
+        log::warn!(agent = agent, terms:? = terms, blocked = rt.blocked; "output filtered");
+        output.content = rt.content;
+        if rt.blocked {
+            output.failed_reason = Some(CONTENT_BLOCKED.to_string());
+            output.tool_calls = None;
+            output.resources = None;
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safety_policy() {
+        let policy = SafetyPolicy::from_toml(
+            r#"
+            allowlist = ["Hell's Kitchen"]
+            block_message = "blocked"
+
+            [[terms]]
+            term = "hell"
+            severity = "mask"
+
+            [[terms]]
+            term = "damn"
+
+            [[terms]]
+            term = "scam coin"
+            severity = "block"
+            "#,
+        )
+        .unwrap();
+
+        let rt = policy.filter("Damn, dinner at Hell's Kitchen was hell. Hello!");
+        assert!(!rt.blocked);
+        assert_eq!(
+            rt.content,
+            "Damn, dinner at Hell's Kitchen was ****. Hello!"
+        );
+        assert_eq!(
+            rt.matches
+                .iter()
+                .map(|m| (m.term.as_str(), m.severity))
+                .collect::<Vec<_>>(),
+            vec![("damn", Severity::Warn), ("hell", Severity::Mask)]
+        );
+
+        let rt = policy.filter("This is a SCAM coin, hell yes");
+        assert!(rt.blocked);
+        assert_eq!(rt.content, "blocked");
+        assert_eq!(rt.matches.len(), 2);
+
+        let rt = policy.filter("nothing to see");
+        assert!(rt.matches.is_empty());
+        assert_eq!(rt.content, "nothing to see");
+
+        let filter = OutputFilter::new(policy).with_agent_policy("Kids", SafetyPolicy::default());
+        assert!(filter.policy("kids").terms.is_empty());
+        assert_eq!(filter.policy("other").terms.len(), 3);
+    }
+}
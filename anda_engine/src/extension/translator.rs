@@ -0,0 +1,268 @@
+//! Automatic Translation Module
+//!
+//! This module provides a translation layer that lets agents backed by a single-language
+//! knowledge base serve multilingual users. Inputs in other languages are translated into
+//! the target language before retrieval and completion, and the final answer is translated
+//! back into the user's language.
+//!
+//! # Key Features
+//! - Language detection and translation in a single model call
+//! - Uses the agent's main model by default, or a dedicated translation provider
+//! - Inputs already in the target language are passed through unchanged
+//!
+//! # Main Components
+//! - [`Translator`]: The translation layer, also usable as an Agent
+//! - [`Translation`]: A translated input with its detected source language
+//!
+//! # Example
+//! ```rust,ignore
+//! let translator = Translator::new("en");
+//! let input = translator.translate_input(&ctx, "¿Qué es ICP?").await?;
+//! // retrieve and complete with `input.text`...
+//! let answer = translator.translate_output(&ctx, &answer, &input.source_lang).await?;
+//! ```
+
+use anda_core::{Agent, AgentOutput, BoxError, CompletionFeatures, CompletionRequest, Resource};
+use std::{fmt, sync::Arc};
+
+use crate::{context::AgentCtx, model::CompletionFeaturesDyn};
+
+/// A translated input with its detected source language.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Translation {
+    /// The text in the target language.
+    pub text: String,
+    /// The ISO 639-1 code of the source language, e.g. "es".
+    pub source_lang: String,
+    /// Whether the text was translated, false if the source was already in the target language.
+    pub translated: bool,
+}
+
+/// Translates inputs into a target language and answers back into the user's language.
+#[derive(Clone)]
+pub struct Translator {
+    target_lang: String,
+    provider: Option<Arc<dyn CompletionFeaturesDyn>>,
+}
+
+impl fmt::Debug for Translator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Translator")
+            .field("target_lang", &self.target_lang)
+            .field("provider", &self.provider.is_some())
+            .finish()
+    }
+}
+
+impl Translator {
+    const NAME: &'static str = "translator";
+
+    /// Creates a new Translator using the agent's main model.
+    ///
+    /// # Arguments
+    /// * `target_lang` - ISO 639-1 code of the language of the knowledge base, e.g. "en"
+    pub fn new(target_lang: &str) -> Self {
+        Self {
+            target_lang: target_lang.trim().to_ascii_lowercase(),
+            provider: None,
+        }
+    }
+
+    /// Uses a dedicated translation provider instead of the agent's main model.
+    pub fn with_provider(mut self, provider: Arc<dyn CompletionFeaturesDyn>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Returns the target language.
+    pub fn target_lang(&self) -> &str {
+        &self.target_lang
+    }
+
+    /// Detects the language of the input and translates it into the target language.
+    ///
+    /// # Arguments
+    /// * `ctx` - Context implementing CompletionFeatures, used if no dedicated provider is set
+    /// * `text` - The user input
+    pub async fn translate_input(
+        &self,
+        ctx: &impl CompletionFeatures,
+        text: &str,
+    ) -> Result<Translation, BoxError> {
+        if text.trim().is_empty() {
+            return Ok(self.untranslated(text));
+        }
+
+        let target = &self.target_lang;
+        let req = CompletionRequest {
+            system: Some(format!(
+                "\
+                You are a professional translator. Detect the language of the user's text and translate it into the language with ISO 639-1 code \"{target}\".\n\
+                Output Format:\n\
+                - The first line must be the ISO 639-1 code of the detected language, and nothing else.\n\
+                - If the detected language is \"{target}\", output only the first line.\n\
+                - Otherwise, output the translation from the second line. Preserve the meaning, formatting, names, code and URLs; do not answer or comment on the text.\
+                "
+            )),
+            prompt: text.to_string(),
+            temperature: Some(0.0),
+            ..Default::default()
+        };
+        let res = self.complete(ctx, req).await?;
+        Ok(parse_translation(&res.content, text, target))
+    }
+
+    /// Translates the answer back into the source language.
+    /// Returns the answer unchanged if the source language is the target language.
+    ///
+    /// # Arguments
+    /// * `ctx` - Context implementing CompletionFeatures, used if no dedicated provider is set
+    /// * `text` - The answer in the target language
+    /// * `lang` - ISO 639-1 code of the language to translate into
+    pub async fn translate_output(
+        &self,
+        ctx: &impl CompletionFeatures,
+        text: &str,
+        lang: &str,
+    ) -> Result<String, BoxError> {
+        if text.trim().is_empty() || lang.eq_ignore_ascii_case(&self.target_lang) {
+            return Ok(text.to_string());
+        }
+
+        let req = CompletionRequest {
+            system: Some(format!(
+                "\
+                You are a professional translator. Translate the user's text into the language with ISO 639-1 code \"{lang}\".\n\
+                Preserve the meaning, tone, formatting, names, code and URLs. Output only the translation.\
+                "
+            )),
+            prompt: text.to_string(),
+            temperature: Some(0.0),
+            ..Default::default()
+        };
+        let res = self.complete(ctx, req).await?;
+        let content = res.content.trim();
+        if content.is_empty() {
+            return Err("empty translation".into());
+        }
+        Ok(content.to_string())
+    }
+
+    fn untranslated(&self, text: &str) -> Translation {
+        Translation {
+            text: text.to_string(),
+            source_lang: self.target_lang.clone(),
+            translated: false,
+        }
+    }
+
+    async fn complete(
+        &self,
+        ctx: &impl CompletionFeatures,
+        req: CompletionRequest,
+    ) -> Result<AgentOutput, BoxError> {
+        let res = match &self.provider {
+            Some(provider) => provider.completion(req).await?,
+            None => ctx.completion(req, None).await?,
+        };
+        if let Some(reason) = res.failed_reason {
+            return Err(format!("translation failed: {}", reason).into());
+        }
+        Ok(res)
+    }
+}
+
+/// Parses the output of the input translation request: the language code on the first line,
+/// followed by the translation. Falls back to the original text if there is no translation.
+fn parse_translation(output: &str, original: &str, target_lang: &str) -> Translation {
+    let output = output.trim();
+    let (lang, text) = output.split_once('\n').unwrap_or((output, ""));
+    let lang = lang
+        .trim()
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_ascii_lowercase();
+    let text = text.trim();
+    let source_lang = if lang.is_empty() || lang.len() > 8 {
+        target_lang.to_string()
+    } else {
+        lang
+    };
+
+    if source_lang == target_lang || text.is_empty() {
+        Translation {
+            text: original.to_string(),
+            source_lang,
+            translated: false,
+        }
+    } else {
+        Translation {
+            text: text.to_string(),
+            source_lang,
+            translated: true,
+        }
+    }
+}
+
+impl Agent<AgentCtx> for Translator {
+    /// Returns the name "translator" of the translator agent
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    /// Returns the description of the translator agent
+    fn description(&self) -> String {
+        format!(
+            "Detect the language of a text and translate it into \"{}\".",
+            self.target_lang
+        )
+    }
+
+    /// Translates the prompt into the target language
+    ///
+    /// # Arguments
+    /// * `ctx` - Agent context
+    /// * `prompt` - Text to translate
+    /// * `resources` - Optional additional resources (not used in this implementation)
+    async fn run(
+        &self,
+        ctx: AgentCtx,
+        prompt: String,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<AgentOutput, BoxError> {
+        let res = self.translate_input(&ctx, &prompt).await?;
+        Ok(AgentOutput {
+            content: res.text,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_translation() {
+        let rt = parse_translation("es\nWhat is ICP?", "¿Qué es ICP?", "en");
+        assert_eq!(
+            rt,
+            Translation {
+                text: "What is ICP?".to_string(),
+                source_lang: "es".to_string(),
+                translated: true,
+            }
+        );
+
+        let rt = parse_translation(" EN ", "What is ICP?", "en");
+        assert!(!rt.translated);
+        assert_eq!(rt.text, "What is ICP?");
+        assert_eq!(rt.source_lang, "en");
+
+        let rt = parse_translation("`zh`\n\n什么是 ICP？\n第二行", "什么是 ICP？\n第二行", "zh");
+        assert!(!rt.translated);
+
+        let rt = parse_translation("", "hello", "en");
+        assert_eq!(rt.source_lang, "en");
+        assert!(!rt.translated);
+    }
+}
@@ -0,0 +1,515 @@
+//! Management of fleets of engines.
+//!
+//! Operators running many engines, e.g. one per TEE, manage them from a [`FleetController`]
+//! over the federation protocol, the signed CBOR RPCs that engines already serve to each
+//! other. The controller registers engines by endpoint, checks their health, pushes them a
+//! [`FleetConfig`] and collects their [`EngineMetrics`]:
+//!
+//! ```rust,ignore
+//! let fleet = Arc::new(FleetController::new(web3_client));
+//! fleet
+//!     .register("eu-1", "https://eu-1.example.com/default", region_eu.clone())
+//!     .await?;
+//! fleet.clone().spawn(Duration::from_secs(30), token);
+//!
+//! let config = FleetConfig {
+//!     version: 7,
+//!     agent_versions: BTreeMap::from([("assistant".into(), "2025-06-01".into())]),
+//!     ..Default::default()
+//! };
+//! let results = fleet.push_config(&region_eu, &config).await;
+//! ```
+//!
+//! Engines accept configs from their controller and managers only, so the principal of the
+//! controller's identity must be a manager of the engines. Configs are persisted by the
+//! engines, and an engine rejects a config older than its current one.
+
+use anda_core::{BoxError, HttpFeatures, Migrate, Value};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use structured_logger::unix_ms;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    analytics::ExporterStats, context::Information, model::ProviderErrorKind,
+    store::MaintenanceStats, telemetry::TelemetryStats,
+};
+
+/// The config pushed by a fleet controller to an engine.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct FleetConfig {
+    /// The version of the config. An engine rejects versions older than its current one.
+    pub version: u64,
+    /// The versions of the agents, overriding the ones set with
+    /// `EngineBuilder::with_agent_version`.
+    #[serde(default)]
+    pub agent_versions: BTreeMap<String, String>,
+    /// Free-form settings of the deployment.
+    #[serde(default)]
+    pub settings: BTreeMap<String, Value>,
+}
+
+impl Migrate for FleetConfig {
+    const VERSION: u32 = 1;
+}
+
+/// The health of an engine.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EngineHealth {
+    pub id: Principal,
+    pub name: String,
+    /// The version of the anda_engine crate.
+    pub version: String,
+    /// When the engine started, unix timestamp in milliseconds.
+    pub started_at: u64,
+    /// When the health was checked, unix timestamp in milliseconds.
+    pub checked_at: u64,
+    pub read_only: bool,
+    /// The version of the current [`FleetConfig`], 0 if none was pushed.
+    pub config_version: u64,
+    /// The versions of the agents.
+    pub agent_versions: BTreeMap<String, String>,
+    /// Model error counts per class since the engine started.
+    pub model_errors: BTreeMap<ProviderErrorKind, u64>,
+}
+
+/// The metrics of an engine.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EngineMetrics {
+    pub id: Principal,
+    pub name: String,
+    /// Model error counts per class.
+    pub model_errors: BTreeMap<ProviderErrorKind, u64>,
+    /// The progress and results of the store maintenance jobs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maintenance: Vec<MaintenanceStats>,
+    /// The state of the export of runs to an analytics warehouse.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_exporter: Option<ExporterStats>,
+    /// The state of the usage telemetry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telemetry: Option<TelemetryStats>,
+}
+
+/// The status of a fleet member, from its last health checks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberStatus {
+    /// Not checked yet.
+    #[default]
+    Unknown,
+    Healthy,
+    /// Failed the last checks, at least `max_failures` in a row.
+    Unhealthy,
+}
+
+/// An engine of a fleet.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FleetMember {
+    /// The name of the engine in the fleet.
+    pub name: String,
+    /// The RPC endpoint of the engine.
+    pub endpoint: String,
+    pub id: Principal,
+    /// Labels to select engines by, e.g. "region".
+    pub labels: BTreeMap<String, String>,
+    pub status: MemberStatus,
+    #[serde(default)]
+    pub health: Option<EngineHealth>,
+    /// When the engine last answered a health check, unix timestamp in milliseconds.
+    #[serde(default)]
+    pub last_seen: Option<u64>,
+    /// The number of failed health checks in a row.
+    #[serde(default)]
+    pub failures: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+impl FleetMember {
+    /// Returns true if the member has all the labels of the selector.
+    pub fn matches(&self, selector: &BTreeMap<String, String>) -> bool {
+        selector
+            .iter()
+            .all(|(k, v)| self.labels.get(k).is_some_and(|l| l == v))
+    }
+}
+
+/// Manages a fleet of engines over the federation protocol.
+pub struct FleetController<C: HttpFeatures> {
+    ctx: C,
+    members: RwLock<BTreeMap<String, FleetMember>>,
+    max_failures: u32,
+}
+
+impl<C> FleetController<C>
+where
+    C: HttpFeatures + Send + Sync,
+{
+    /// Creates a controller that calls engines with the identity of the context.
+    pub fn new(ctx: C) -> Self {
+        Self {
+            ctx,
+            members: RwLock::new(BTreeMap::new()),
+            max_failures: 3,
+        }
+    }
+
+    /// Sets the number of failed health checks in a row after which an engine is unhealthy.
+    /// Defaults to 3.
+    pub fn with_max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures.max(1);
+        self
+    }
+
+    /// Registers an engine by endpoint, replacing any engine of the same name.
+    pub async fn register(
+        &self,
+        name: &str,
+        endpoint: &str,
+        labels: BTreeMap<String, String>,
+    ) -> Result<FleetMember, BoxError> {
+        if name.is_empty() {
+            return Err("engine name is empty".into());
+        }
+        let info: Information = self
+            .ctx
+            .https_signed_rpc(endpoint, "information", &(true,))
+            .await?;
+        let member = FleetMember {
+            name: name.to_string(),
+            endpoint: endpoint.to_string(),
+            id: info.id,
+            labels,
+            status: MemberStatus::Unknown,
+            health: None,
+            last_seen: None,
+            failures: 0,
+            last_error: None,
+        };
+        self.members
+            .write()
+            .unwrap()
+            .insert(member.name.clone(), member.clone());
+        Ok(member)
+    }
+
+    /// Removes an engine from the fleet.
+    pub fn deregister(&self, name: &str) -> Option<FleetMember> {
+        self.members.write().unwrap().remove(name)
+    }
+
+    /// Returns an engine of the fleet.
+    pub fn member(&self, name: &str) -> Option<FleetMember> {
+        self.members.read().unwrap().get(name).cloned()
+    }
+
+    /// Returns the engines that have all the labels of the selector, all if it is empty.
+    pub fn members(&self, selector: &BTreeMap<String, String>) -> Vec<FleetMember> {
+        self.members
+            .read()
+            .unwrap()
+            .values()
+            .filter(|m| m.matches(selector))
+            .cloned()
+            .collect()
+    }
+
+    /// Checks the health of all the engines concurrently, and returns their updated state.
+    pub async fn check_health(&self) -> Vec<FleetMember> {
+        let members = self.members(&BTreeMap::new());
+        let results = futures::future::join_all(members.iter().map(|m| {
+            self.ctx
+                .https_signed_rpc::<EngineHealth>(&m.endpoint, "health", &())
+        }))
+        .await;
+
+        let now = unix_ms();
+        let mut guard = self.members.write().unwrap();
+        let mut checked = Vec::with_capacity(results.len());
+        for (member, res) in members.into_iter().zip(results) {
+            // the member may have been removed or replaced during the check
+            let Some(m) = guard
+                .get_mut(&member.name)
+                .filter(|m| m.endpoint == member.endpoint)
+            else {
+                continue;
+            };
+            match res {
+                Ok(health) if health.id == m.id => {
+                    m.status = MemberStatus::Healthy;
+                    m.health = Some(health);
+                    m.last_seen = Some(now);
+                    m.failures = 0;
+                    m.last_error = None;
+                }
+                res => {
+                    m.failures += 1;
+                    m.last_error = Some(match res {
+                        Ok(health) => format!(
+                            "unexpected engine ID, expected {}, got {}",
+                            m.id.to_text(),
+                            health.id.to_text()
+                        ),
+                        Err(err) => err.to_string(),
+                    });
+                    if m.failures >= self.max_failures {
+                        m.status = MemberStatus::Unhealthy;
+                    }
+                }
+            }
+            checked.push(m.clone());
+        }
+        checked
+    }
+
+    /// Pushes the config to the selected engines concurrently, and returns the results by
+    /// engine name.
+    pub async fn push_config(
+        &self,
+        selector: &BTreeMap<String, String>,
+        config: &FleetConfig,
+    ) -> BTreeMap<String, Result<(), String>> {
+        let members = self.members(selector);
+        let args = (config,);
+        let results = futures::future::join_all(members.iter().map(|m| {
+            self.ctx
+                .https_signed_rpc::<()>(&m.endpoint, "apply_fleet_config", &args)
+        }))
+        .await;
+        members
+            .into_iter()
+            .zip(results)
+            .map(|(m, res)| (m.name, res.map_err(|err| err.to_string())))
+            .collect()
+    }
+
+    /// Collects the metrics of the selected engines concurrently, by engine name.
+    pub async fn collect_metrics(
+        &self,
+        selector: &BTreeMap<String, String>,
+    ) -> BTreeMap<String, Result<EngineMetrics, String>> {
+        let members = self.members(selector);
+        let results = futures::future::join_all(members.iter().map(|m| {
+            self.ctx
+                .https_signed_rpc::<EngineMetrics>(&m.endpoint, "metrics", &())
+        }))
+        .await;
+        members
+            .into_iter()
+            .zip(results)
+            .map(|(m, res)| (m.name, res.map_err(|err| err.to_string())))
+            .collect()
+    }
+
+    /// Spawns a background task that checks the health of the engines every interval,
+    /// until the token is cancelled.
+    pub fn spawn(self: Arc<Self>, interval: Duration, token: CancellationToken) -> JoinHandle<()>
+    where
+        C: 'static,
+    {
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => return,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                for m in self.check_health().await {
+                    if m.status == MemberStatus::Unhealthy {
+                        log::warn!(
+                            name = m.name,
+                            endpoint = m.endpoint,
+                            failures = m.failures;
+                            "engine is unhealthy: {}",
+                            m.last_error.unwrap_or_default()
+                        );
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::{from_reader, into_writer};
+    use serde::de::DeserializeOwned;
+    use std::sync::Mutex;
+
+    fn cbor<T: Serialize>(v: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        into_writer(v, &mut buf).unwrap();
+        buf
+    }
+
+    /// Engines that answer the RPCs of the fleet, by endpoint.
+    #[derive(Default)]
+    struct MockEngines {
+        engines: BTreeMap<String, Principal>,
+        down: Mutex<Vec<String>>,
+        configs: Mutex<Vec<(String, FleetConfig)>>,
+    }
+
+    impl HttpFeatures for &MockEngines {
+        async fn https_call(
+            &self,
+            _url: &str,
+            _method: http::Method,
+            _headers: Option<http::HeaderMap>,
+            _body: Option<Vec<u8>>,
+        ) -> Result<reqwest::Response, BoxError> {
+            Err("not supported".into())
+        }
+
+        async fn https_signed_call(
+            &self,
+            _url: &str,
+            _method: http::Method,
+            _message_digest: [u8; 32],
+            _headers: Option<http::HeaderMap>,
+            _body: Option<Vec<u8>>,
+        ) -> Result<reqwest::Response, BoxError> {
+            Err("not supported".into())
+        }
+
+        async fn https_signed_rpc<T>(
+            &self,
+            endpoint: &str,
+            method: &str,
+            args: impl Serialize + Send,
+        ) -> Result<T, BoxError>
+        where
+            T: DeserializeOwned,
+        {
+            if self.down.lock().unwrap().iter().any(|e| e == endpoint) {
+                return Err(format!("{endpoint} is unreachable").into());
+            }
+            let id = *self.engines.get(endpoint).ok_or("engine not found")?;
+            let res = match method {
+                "information" => cbor(&Information {
+                    id,
+                    name: "engine".to_string(),
+                    description: String::new(),
+                    agents: vec![],
+                    tools: vec![],
+                    endpoint: String::new(),
+                    read_only: false,
+                }),
+                "health" => cbor(&EngineHealth {
+                    id,
+                    name: "engine".to_string(),
+                    version: "0.6.0".to_string(),
+                    started_at: 0,
+                    checked_at: unix_ms(),
+                    read_only: false,
+                    config_version: 0,
+                    agent_versions: BTreeMap::new(),
+                    model_errors: BTreeMap::new(),
+                }),
+                "metrics" => cbor(&EngineMetrics {
+                    id,
+                    name: "engine".to_string(),
+                    model_errors: BTreeMap::new(),
+                    maintenance: vec![],
+                    run_exporter: None,
+                    telemetry: None,
+                }),
+                "apply_fleet_config" => {
+                    let (config,): (FleetConfig,) = from_reader(cbor(&args).as_slice())?;
+                    self.configs
+                        .lock()
+                        .unwrap()
+                        .push((endpoint.to_string(), config));
+                    cbor(&())
+                }
+                _ => return Err(format!("{method} not implemented").into()),
+            };
+            Ok(from_reader(res.as_slice())?)
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fleet_controller() {
+        let eu = Principal::from_text("aaaaa-aa").unwrap();
+        let engines = MockEngines {
+            engines: BTreeMap::from([
+                ("https://eu.example.com".to_string(), eu),
+                ("https://us.example.com".to_string(), Principal::anonymous()),
+            ]),
+            ..Default::default()
+        };
+        let fleet = FleetController::new(&engines).with_max_failures(2);
+        let eu_labels = BTreeMap::from([("region".to_string(), "eu".to_string())]);
+        let member = fleet
+            .register("eu-1", "https://eu.example.com", eu_labels.clone())
+            .await
+            .unwrap();
+        assert_eq!(member.id, eu);
+        assert_eq!(member.status, MemberStatus::Unknown);
+        fleet
+            .register(
+                "us-1",
+                "https://us.example.com",
+                BTreeMap::from([("region".to_string(), "us".to_string())]),
+            )
+            .await
+            .unwrap();
+        assert!(
+            fleet
+                .register("x", "https://x.example.com", BTreeMap::new())
+                .await
+                .is_err()
+        );
+        assert_eq!(fleet.members(&BTreeMap::new()).len(), 2);
+        assert_eq!(fleet.members(&eu_labels)[0].name, "eu-1");
+
+        // health
+        let checked = fleet.check_health().await;
+        assert!(checked.iter().all(|m| m.status == MemberStatus::Healthy));
+        engines
+            .down
+            .lock()
+            .unwrap()
+            .push("https://us.example.com".to_string());
+        fleet.check_health().await;
+        let us_1 = fleet.member("us-1").unwrap();
+        assert_eq!(us_1.status, MemberStatus::Healthy);
+        assert_eq!(us_1.failures, 1);
+        assert!(us_1.last_error.unwrap().contains("unreachable"));
+        fleet.check_health().await;
+        assert_eq!(
+            fleet.member("us-1").unwrap().status,
+            MemberStatus::Unhealthy
+        );
+        assert_eq!(fleet.member("eu-1").unwrap().status, MemberStatus::Healthy);
+
+        // config
+        let config = FleetConfig {
+            version: 2,
+            agent_versions: BTreeMap::from([("assistant".to_string(), "v2".to_string())]),
+            ..Default::default()
+        };
+        let results = fleet.push_config(&BTreeMap::new(), &config).await;
+        assert!(results["eu-1"].is_ok());
+        assert!(results["us-1"].is_err());
+        let results = fleet.push_config(&eu_labels, &config).await;
+        assert_eq!(results.len(), 1);
+        let configs = engines.configs.lock().unwrap().clone();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0], ("https://eu.example.com".to_string(), config));
+
+        // metrics
+        let metrics = fleet.collect_metrics(&eu_labels).await;
+        assert_eq!(metrics["eu-1"].as_ref().unwrap().id, eu);
+
+        assert!(fleet.deregister("us-1").is_some());
+        assert!(fleet.member("us-1").is_none());
+    }
+}
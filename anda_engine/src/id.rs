@@ -0,0 +1,340 @@
+//! # ID Generation Module
+//!
+//! The engine creates the IDs of threads, reminders, approvals, API keys and tool calls
+//! with an [`IdGenerator`], so that they are all time-ordered and drawn from the same
+//! entropy source:
+//! - [`Ulid`]s, a 48-bit millisecond timestamp followed by 80 random bits, monotonic within
+//!   a millisecond. Their text form in Crockford's base32 sorts by time, and leaves out the
+//!   letters I, L, O and U, so IDs neither read as words nor get mistyped.
+//! - The same IDs in the UUIDv7 layout and text form, with [`IdFormat::UuidV7`].
+//! - [`Xid`]s for the threads, with a timestamp in seconds and random bytes.
+//!
+//! The entropy is pluggable: [`OsEntropy`] by default, or a [`SeededEntropy`] where the
+//! system randomness is not available, e.g. seeded with the 32 bytes of the management
+//! canister's `raw_rand` on the IC, or with a fixed seed to get deterministic IDs in tests.
+//!
+//! # Example
+//! ```rust,ignore
+//! let seed: [u8; 32] = raw_rand().await?;
+//! set_id_generator(IdGenerator::new(Arc::new(SeededEntropy::new(seed))))?;
+//! let thread = new_xid();
+//! ```
+
+use anda_core::{BoxError, Xid};
+use rand::RngCore;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::unix_ms;
+
+/// A source of random bytes for the IDs.
+pub trait Entropy: Send + Sync {
+    /// Fills the buffer with random bytes.
+    fn fill(&self, buf: &mut [u8]);
+}
+
+/// The randomness of the operating system.
+#[derive(Clone, Debug, Default)]
+pub struct OsEntropy;
+
+impl Entropy for OsEntropy {
+    fn fill(&self, buf: &mut [u8]) {
+        rand::thread_rng().fill_bytes(buf);
+    }
+}
+
+/// A deterministic stream of random bytes: the BLAKE3 keyed hashes of a counter, with the
+/// seed as the key.
+#[derive(Debug)]
+pub struct SeededEntropy {
+    seed: [u8; 32],
+    counter: Mutex<u64>,
+}
+
+impl SeededEntropy {
+    /// Creates the stream from a 32-byte seed, e.g. the output of `raw_rand`.
+    pub fn new(seed: [u8; 32]) -> Self {
+        Self {
+            seed,
+            counter: Mutex::new(0),
+        }
+    }
+}
+
+impl Entropy for SeededEntropy {
+    fn fill(&self, buf: &mut [u8]) {
+        let counter = {
+            let mut counter = self.counter.lock().unwrap();
+            *counter += 1;
+            *counter
+        };
+        let mut hasher = blake3::Hasher::new_keyed(&self.seed);
+        hasher.update(&counter.to_be_bytes());
+        hasher.finalize_xof().fill(buf);
+    }
+}
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ULID_LEN: usize = 26;
+const RANDOM_BITS: u32 = 80;
+const RANDOM_MASK: u128 = (1 << RANDOM_BITS) - 1;
+
+/// A 128-bit ID: a 48-bit Unix timestamp in milliseconds, then 80 random bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ulid(pub u128);
+
+impl Ulid {
+    /// Creates an ID from its timestamp and random bits.
+    pub fn from_parts(timestamp_ms: u64, random: u128) -> Self {
+        Self(((timestamp_ms as u128 & 0xffff_ffff_ffff) << RANDOM_BITS) | (random & RANDOM_MASK))
+    }
+
+    /// Returns the Unix timestamp of the ID in milliseconds.
+    pub fn timestamp_ms(&self) -> u64 {
+        (self.0 >> RANDOM_BITS) as u64
+    }
+
+    /// Returns the random bits of the ID.
+    pub fn random(&self) -> u128 {
+        self.0 & RANDOM_MASK
+    }
+
+    /// Returns the ID in the UUID text form, e.g. "0192a5f4-7c1e-7d2b-9f3a-4b5c6d7e8f90".
+    pub fn to_uuid_string(&self) -> String {
+        let hex = format!("{:032x}", self.0);
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+}
+
+impl fmt::Display for Ulid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = [0u8; ULID_LEN];
+        for (i, c) in buf.iter_mut().enumerate() {
+            let shift = 5 * (ULID_LEN - 1 - i);
+            *c = CROCKFORD[((self.0 >> shift) & 0x1f) as usize];
+        }
+        f.write_str(std::str::from_utf8(&buf).unwrap())
+    }
+}
+
+impl FromStr for Ulid {
+    type Err = BoxError;
+
+    /// Parses the Crockford's base32 text form, case-insensitively.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != ULID_LEN {
+            return Err(format!("invalid ULID length: {}", s.len()).into());
+        }
+        let mut value: u128 = 0;
+        for (i, c) in s.bytes().enumerate() {
+            let digit = CROCKFORD
+                .iter()
+                .position(|&d| d == c.to_ascii_uppercase())
+                .ok_or_else(|| format!("invalid ULID character: {}", c as char))?;
+            if i == 0 && digit > 7 {
+                return Err("ULID overflows 128 bits".into());
+            }
+            value = (value << 5) | digit as u128;
+        }
+        Ok(Self(value))
+    }
+}
+
+/// The text form of the IDs of an [`IdGenerator`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdFormat {
+    /// 26 characters in Crockford's base32, e.g. "01JA2Z8F3XG7Q9N4V6W5T8R2KD".
+    #[default]
+    Ulid,
+    /// The UUIDv7 layout in the UUID text form, with the version and variant bits set.
+    UuidV7,
+}
+
+/// Generates time-ordered IDs from an entropy source.
+pub struct IdGenerator {
+    entropy: Arc<dyn Entropy>,
+    format: IdFormat,
+    last: Mutex<Ulid>,
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self::new(Arc::new(OsEntropy))
+    }
+}
+
+impl IdGenerator {
+    /// Creates a generator of ULIDs from the entropy source.
+    pub fn new(entropy: Arc<dyn Entropy>) -> Self {
+        Self {
+            entropy,
+            format: IdFormat::Ulid,
+            last: Mutex::new(Ulid::default()),
+        }
+    }
+
+    /// Sets the text form of the IDs, [`IdFormat::Ulid`] by default.
+    pub fn with_format(mut self, format: IdFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Returns the text form of the IDs.
+    pub fn format(&self) -> IdFormat {
+        self.format
+    }
+
+    /// Generates an ID at the time. Within the same millisecond, or if the clock goes
+    /// back, the ID is the previous one plus 1, so the IDs of a generator are increasing.
+    pub fn ulid_at(&self, now_ms: u64) -> Ulid {
+        let mut random = [0u8; 16];
+        self.entropy.fill(&mut random[6..]);
+        let mut id = Ulid::from_parts(now_ms, u128::from_be_bytes(random));
+        if self.format == IdFormat::UuidV7 {
+            id = with_uuid_v7_bits(id);
+        }
+
+        let mut last = self.last.lock().unwrap();
+        if id.timestamp_ms() <= last.timestamp_ms() {
+            id = Ulid(last.0.wrapping_add(1));
+            if self.format == IdFormat::UuidV7 {
+                id = with_uuid_v7_bits(id);
+            }
+        }
+        *last = id;
+        id
+    }
+
+    /// Generates an ID now.
+    pub fn ulid(&self) -> Ulid {
+        self.ulid_at(unix_ms())
+    }
+
+    /// Generates an ID now, in the text form of the generator.
+    pub fn id(&self) -> String {
+        let id = self.ulid();
+        match self.format {
+            IdFormat::Ulid => id.to_string(),
+            IdFormat::UuidV7 => id.to_uuid_string(),
+        }
+    }
+
+    /// Generates a thread ID at the time: the Unix timestamp in seconds, then 8 random bytes.
+    pub fn xid_at(&self, now_ms: u64) -> Xid {
+        let mut id = [0u8; 12];
+        id[..4].copy_from_slice(&((now_ms / 1000) as u32).to_be_bytes());
+        self.entropy.fill(&mut id[4..]);
+        Xid(id)
+    }
+
+    /// Generates a thread ID now.
+    pub fn xid(&self) -> Xid {
+        self.xid_at(unix_ms())
+    }
+}
+
+fn with_uuid_v7_bits(id: Ulid) -> Ulid {
+    // version 7 in bits 76..80, variant 0b10 in bits 62..64
+    Ulid((id.0 & !(0xf << 76) & !(0x3 << 62)) | (0x7 << 76) | (0x2 << 62))
+}
+
+static ID_GENERATOR: OnceLock<IdGenerator> = OnceLock::new();
+
+/// Sets the generator of the engine's IDs. It can be set only once, before any ID is
+/// generated.
+pub fn set_id_generator(generator: IdGenerator) -> Result<(), BoxError> {
+    ID_GENERATOR
+        .set(generator)
+        .map_err(|_| "the ID generator is already set".into())
+}
+
+/// Returns the generator of the engine's IDs, a ULID generator with [`OsEntropy`] if not set.
+pub fn id_generator() -> &'static IdGenerator {
+    ID_GENERATOR.get_or_init(IdGenerator::default)
+}
+
+/// Generates an ID with the engine's generator.
+pub fn new_id() -> String {
+    id_generator().id()
+}
+
+/// Generates a thread ID with the engine's generator.
+pub fn new_xid() -> Xid {
+    id_generator().xid()
+}
+
+/// Generates a tool call ID with the engine's generator, for the models that do not
+/// return one.
+pub fn new_tool_call_id() -> String {
+    format!("call_{}", new_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ulid() {
+        let id = Ulid::from_parts(1_728_950_400_000, 0x1234_5678_9abc_def0_1234);
+        assert_eq!(id.timestamp_ms(), 1_728_950_400_000);
+        assert_eq!(id.random(), 0x1234_5678_9abc_def0_1234);
+        let text = id.to_string();
+        assert_eq!(text.len(), 26);
+        assert!(!text.contains(['I', 'L', 'O', 'U']));
+        assert_eq!(text.parse::<Ulid>().unwrap(), id);
+        assert_eq!(text.to_lowercase().parse::<Ulid>().unwrap(), id);
+        assert_eq!(Ulid(u128::MAX).to_string(), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+        assert!("8ZZZZZZZZZZZZZZZZZZZZZZZZZ".parse::<Ulid>().is_err());
+        assert!("01JA2Z8F3XG7Q9N4V6W5T8R2KU".parse::<Ulid>().is_err());
+        assert!("01JA2Z8F3X".parse::<Ulid>().is_err());
+
+        let later = Ulid::from_parts(1_728_950_400_001, 0);
+        assert!(later > id);
+        assert!(later.to_string() > text);
+    }
+
+    #[test]
+    fn test_id_generator() {
+        let seed = [7u8; 32];
+        let a = IdGenerator::new(Arc::new(SeededEntropy::new(seed)));
+        let b = IdGenerator::new(Arc::new(SeededEntropy::new(seed)));
+        let now = 1_728_950_400_000;
+        // deterministic with the same seed and clock
+        let id = a.ulid_at(now);
+        assert_eq!(id, b.ulid_at(now));
+        assert_eq!(id.timestamp_ms(), now);
+        assert_eq!(a.xid_at(now), b.xid_at(now));
+        assert_eq!(a.xid_at(now).0[..4], ((now / 1000) as u32).to_be_bytes());
+
+        // monotonic within a millisecond and when the clock goes back
+        let next = a.ulid_at(now);
+        assert_eq!(next.0, id.0 + 1);
+        assert_eq!(a.ulid_at(now - 10).0, id.0 + 2);
+        assert_eq!(a.ulid_at(now + 1).timestamp_ms(), now + 1);
+
+        let uuid =
+            IdGenerator::new(Arc::new(SeededEntropy::new(seed))).with_format(IdFormat::UuidV7);
+        let id = uuid.ulid_at(now);
+        assert_eq!(id.timestamp_ms(), now);
+        let text = id.to_uuid_string();
+        assert_eq!(text.len(), 36);
+        assert_eq!(&text[14..15], "7");
+        assert!(matches!(&text[19..20], "8" | "9" | "a" | "b"));
+        assert!(uuid.ulid_at(now) > id);
+
+        let id = IdGenerator::default().id();
+        assert_eq!(id.len(), 26);
+        assert!(new_tool_call_id().starts_with("call_"));
+        assert_ne!(new_xid(), new_xid());
+    }
+}
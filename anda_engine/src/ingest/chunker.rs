@@ -0,0 +1,616 @@
+//! Chunking of documents for embedding.
+//!
+//! A [`Chunker`] splits a text into units by its [`ChunkStrategy`], e.g. sentences or
+//! markdown blocks, and packs consecutive units into chunks of at most `chunk_tokens`
+//! tokens. Units larger than a chunk are split further, down to words and characters.
+//! Consecutive chunks can share `overlap_tokens` tokens of whole units, so that a chunk
+//! keeps some context of the previous one.
+
+use anda_core::{HeuristicTokenizer, Tokenizer};
+use serde::{Deserialize, Serialize};
+use std::{ops::Range, sync::Arc};
+
+/// How a [`Chunker`] splits texts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStrategy {
+    /// Chunks of words, regardless of the structure of the text.
+    FixedSize,
+    /// Chunks of whole sentences and paragraphs.
+    #[default]
+    Sentence,
+    /// Chunks of whole markdown blocks, never across headers. Chunks record the path of
+    /// their headers, fenced code blocks are not split if possible.
+    Markdown,
+    /// Chunks of whole top-level code blocks, e.g. functions, then of whole lines.
+    Code,
+}
+
+impl ChunkStrategy {
+    /// Returns the stable name of the strategy.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::FixedSize => "fixed_size",
+            Self::Sentence => "sentence",
+            Self::Markdown => "markdown",
+            Self::Code => "code",
+        }
+    }
+
+    fn splitters(&self) -> &'static [Splitter] {
+        use Splitter::*;
+        match self {
+            Self::FixedSize => &[Words, Chars],
+            Self::Sentence => &[Sentences, Words, Chars],
+            Self::Markdown => &[Blocks, Sentences, Words, Chars],
+            Self::Code => &[CodeBlocks, Lines, Words, Chars],
+        }
+    }
+}
+
+/// A chunk of a text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Chunk {
+    /// The text of the chunk, without leading and trailing whitespace.
+    pub text: String,
+    /// The byte offset of the chunk in the text.
+    pub start: usize,
+    /// The byte offset of the end of the chunk in the text, exclusive.
+    pub end: usize,
+    /// The path of the markdown headers of the chunk, e.g. "Guide > Install".
+    pub heading: Option<String>,
+}
+
+/// Splits texts into chunks of a maximum number of tokens.
+#[derive(Clone)]
+pub struct Chunker {
+    strategy: ChunkStrategy,
+    chunk_tokens: usize,
+    overlap_tokens: usize,
+    tokenizer: Arc<dyn Tokenizer>,
+}
+
+impl std::fmt::Debug for Chunker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Chunker")
+            .field("strategy", &self.strategy)
+            .field("chunk_tokens", &self.chunk_tokens)
+            .field("overlap_tokens", &self.overlap_tokens)
+            .finish()
+    }
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(ChunkStrategy::default(), 500)
+    }
+}
+
+impl Chunker {
+    /// Creates a chunker of chunks of at most `chunk_tokens` tokens, without overlap,
+    /// counted by the [`HeuristicTokenizer`].
+    pub fn new(strategy: ChunkStrategy, chunk_tokens: usize) -> Self {
+        Self {
+            strategy,
+            chunk_tokens: chunk_tokens.max(1),
+            overlap_tokens: 0,
+            tokenizer: Arc::new(HeuristicTokenizer),
+        }
+    }
+
+    /// Sets the maximum number of tokens shared by consecutive chunks,
+    /// at most half of a chunk.
+    pub fn with_overlap(mut self, overlap_tokens: usize) -> Self {
+        self.overlap_tokens = overlap_tokens.min(self.chunk_tokens / 2);
+        self
+    }
+
+    /// Sets the tokenizer of the embedding model.
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    pub fn strategy(&self) -> ChunkStrategy {
+        self.strategy
+    }
+
+    /// Splits the text into chunks.
+    pub fn chunk(&self, text: &str) -> Vec<Chunk> {
+        let sections = if self.strategy == ChunkStrategy::Markdown {
+            markdown_sections(text)
+        } else {
+            vec![(0..text.len(), None)]
+        };
+
+        let mut chunks = Vec::new();
+        for (range, heading) in sections {
+            let mut units = Vec::new();
+            self.split(text, range, self.strategy.splitters(), &mut units);
+            self.pack(text, &units, heading, &mut chunks);
+        }
+        chunks
+    }
+
+    fn tokens(&self, text: &str, range: &Range<usize>) -> usize {
+        self.tokenizer.count_tokens(&text[range.clone()])
+    }
+
+    /// Splits the range into units of at most `chunk_tokens` tokens, with the splitters in
+    /// order. Returns the units with their tokens.
+    fn split(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        splitters: &[Splitter],
+        out: &mut Vec<(Range<usize>, usize)>,
+    ) {
+        let Some((splitter, rest)) = splitters.split_first() else {
+            return;
+        };
+        let units = if *splitter == Splitter::Chars {
+            self.char_units(text, range)
+        } else {
+            splitter.split(text, range)
+        };
+        for unit in units {
+            let tokens = self.tokens(text, &unit);
+            if tokens <= self.chunk_tokens || rest.is_empty() {
+                out.push((unit, tokens));
+            } else {
+                self.split(text, unit, rest, out);
+            }
+        }
+    }
+
+    /// Splits the range into the longest runs of characters of at most `chunk_tokens` tokens.
+    fn char_units(&self, text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut bounds: Vec<usize> = text[range.clone()]
+            .char_indices()
+            .map(|(i, _)| range.start + i)
+            .collect();
+        bounds.push(range.end);
+
+        let mut units = Vec::new();
+        let mut from = 0;
+        while from + 1 < bounds.len() {
+            // the longest run within the budget, of at least one character
+            let (mut lo, mut hi) = (from + 1, bounds.len());
+            while lo + 1 < hi {
+                let mid = (lo + hi) / 2;
+                if self.tokens(text, &(bounds[from]..bounds[mid])) <= self.chunk_tokens {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            units.push(bounds[from]..bounds[lo]);
+            from = lo;
+        }
+        units
+    }
+
+    /// Packs consecutive units into chunks.
+    fn pack(
+        &self,
+        text: &str,
+        units: &[(Range<usize>, usize)],
+        heading: Option<String>,
+        chunks: &mut Vec<Chunk>,
+    ) {
+        let mut emit = |current: &[(Range<usize>, usize)]| {
+            let (Some(first), Some(last)) = (current.first(), current.last()) else {
+                return;
+            };
+            let raw = &text[first.0.start..last.0.end];
+            let trimmed = raw.trim_start();
+            let start = first.0.start + (raw.len() - trimmed.len());
+            let trimmed = trimmed.trim_end();
+            if !trimmed.is_empty() {
+                chunks.push(Chunk {
+                    text: trimmed.to_string(),
+                    start,
+                    end: start + trimmed.len(),
+                    heading: heading.clone(),
+                });
+            }
+        };
+
+        let mut current: Vec<(Range<usize>, usize)> = Vec::new();
+        let mut tokens = 0;
+        for (unit, unit_tokens) in units {
+            if !current.is_empty() && tokens + unit_tokens > self.chunk_tokens {
+                emit(&current);
+                // keeps the trailing units of the overlap for the next chunk
+                let mut keep = 0;
+                let mut kept_tokens = 0;
+                for (_, t) in current.iter().rev() {
+                    if keep + 1 >= current.len() || kept_tokens + t > self.overlap_tokens {
+                        break;
+                    }
+                    keep += 1;
+                    kept_tokens += t;
+                }
+                // the overlap must leave room for the unit
+                while keep > 0 && kept_tokens + unit_tokens > self.chunk_tokens {
+                    kept_tokens -= current[current.len() - keep].1;
+                    keep -= 1;
+                }
+                current.drain(..current.len() - keep);
+                tokens = kept_tokens;
+            }
+            current.push((unit.clone(), *unit_tokens));
+            tokens += unit_tokens;
+        }
+        emit(&current);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Splitter {
+    /// Markdown blocks separated by blank lines, fenced code blocks are one block.
+    Blocks,
+    /// Top-level code blocks, starting at unindented lines after blank lines.
+    CodeBlocks,
+    Lines,
+    Sentences,
+    Words,
+    Chars,
+}
+
+impl Splitter {
+    /// Splits the range into contiguous units, with their trailing whitespace.
+    fn split(&self, text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+        let s = &text[range.clone()];
+        let bounds = match self {
+            Self::Blocks => block_bounds(s),
+            Self::CodeBlocks => code_block_bounds(s),
+            Self::Lines => line_starts(s).into_iter().skip(1).collect(),
+            Self::Sentences => sentence_bounds(s),
+            Self::Words => word_bounds(s),
+            Self::Chars => s.char_indices().skip(1).map(|(i, _)| i).collect(),
+        };
+
+        let mut units = Vec::with_capacity(bounds.len() + 1);
+        let mut start = 0;
+        for end in bounds.into_iter().chain([s.len()]) {
+            if end > start {
+                units.push(range.start + start..range.start + end);
+                start = end;
+            }
+        }
+        units
+    }
+}
+
+/// Returns the byte offsets of the starts of the lines.
+fn line_starts(s: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(s.match_indices('\n').map(|(i, _)| i + 1))
+        .filter(|i| *i < s.len())
+        .collect()
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+fn lines(s: &str) -> Vec<(usize, &str)> {
+    line_starts(s)
+        .into_iter()
+        .map(|i| (i, s[i..].split_inclusive('\n').next().unwrap_or_default()))
+        .collect()
+}
+
+fn fence_marker(line: &str) -> Option<&'static str> {
+    let line = line.trim_start();
+    if line.starts_with("```") {
+        Some("```")
+    } else if line.starts_with("~~~") {
+        Some("~~~")
+    } else {
+        None
+    }
+}
+
+fn block_bounds(s: &str) -> Vec<usize> {
+    let mut bounds = Vec::new();
+    let mut fence: Option<&str> = None;
+    let mut prev_blank = false;
+    for (i, line) in lines(s) {
+        let blank = is_blank(line);
+        if fence.is_none() && prev_blank && !blank {
+            bounds.push(i);
+        }
+        match (fence, fence_marker(line)) {
+            (None, Some(marker)) => {
+                // a fence starts a block
+                if !prev_blank && i > 0 {
+                    bounds.push(i);
+                }
+                fence = Some(marker);
+            }
+            (Some(open), Some(marker)) if open == marker => fence = None,
+            _ => {}
+        }
+        prev_blank = blank;
+    }
+    bounds.dedup();
+    bounds
+}
+
+fn code_block_bounds(s: &str) -> Vec<usize> {
+    let mut bounds = Vec::new();
+    let mut prev_blank = false;
+    for (i, line) in lines(s) {
+        let blank = is_blank(line);
+        let closing = matches!(line.trim(), "}" | ")" | "]" | "};" | ");" | "end");
+        if prev_blank && !blank && !line.starts_with(char::is_whitespace) && !closing {
+            bounds.push(i);
+        }
+        prev_blank = blank;
+    }
+    bounds
+}
+
+/// Returns whether the character belongs to a script written without spaces.
+fn is_unspaced(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF     // Hiragana, Katakana
+        | 0x3400..=0x4DBF   // CJK Extension A
+        | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+        | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+        | 0x20000..=0x2FFFF // CJK Extensions B to F
+    )
+}
+
+fn word_bounds(s: &str) -> Vec<usize> {
+    let mut bounds = Vec::new();
+    let mut brk = false;
+    for (i, c) in s.char_indices() {
+        if c.is_whitespace() {
+            brk = true;
+            continue;
+        }
+        if (brk || is_unspaced(c)) && i > 0 {
+            bounds.push(i);
+        }
+        brk = is_unspaced(c);
+    }
+    bounds.dedup();
+    bounds
+}
+
+fn sentence_bounds(s: &str) -> Vec<usize> {
+    let mut bounds = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        let end = match c {
+            '.' | '!' | '?' | ';' | '。' | '！' | '？' | '；' | '\n' => {
+                while let Some((_, c)) = chars.peek()
+                    && matches!(c, '"' | '\'' | ')' | ']' | '”' | '’' | '」' | '）')
+                {
+                    chars.next();
+                }
+                match chars.peek() {
+                    Some((_, c)) if c.is_whitespace() => {}
+                    // CJK punctuation ends a sentence without a space
+                    Some(_) if !matches!(c, '。' | '！' | '？' | '；') => continue,
+                    _ => {}
+                }
+                let mut newlines = usize::from(c == '\n');
+                while let Some((_, c)) = chars.peek()
+                    && c.is_whitespace()
+                {
+                    newlines += usize::from(*c == '\n');
+                    chars.next();
+                }
+                // a single line break is not a sentence end, a blank line is
+                if c == '\n' && newlines < 2 {
+                    continue;
+                }
+                chars.peek().map(|(i, _)| *i)
+            }
+            _ => continue,
+        };
+        if let Some(end) = end {
+            bounds.push(end);
+        }
+    }
+    bounds
+}
+
+/// Splits a markdown text into sections at its headers, outside of fenced code blocks,
+/// with the paths of their headers.
+fn markdown_sections(s: &str) -> Vec<(Range<usize>, Option<String>)> {
+    let mut sections = Vec::new();
+    let mut path: Vec<(usize, String)> = Vec::new();
+    let mut start = 0;
+    let mut heading: Option<String> = None;
+    let mut fence: Option<&str> = None;
+    for (i, line) in lines(s) {
+        match (fence, fence_marker(line)) {
+            (None, Some(marker)) => {
+                fence = Some(marker);
+                continue;
+            }
+            (Some(open), Some(marker)) if open == marker => {
+                fence = None;
+                continue;
+            }
+            (Some(_), _) => continue,
+            _ => {}
+        }
+
+        let Some((level, title)) = markdown_header(line) else {
+            continue;
+        };
+        if i > start {
+            sections.push((start..i, heading.take()));
+        }
+        while path.last().is_some_and(|(l, _)| *l >= level) {
+            path.pop();
+        }
+        path.push((level, title));
+        heading = Some(
+            path.iter()
+                .map(|(_, t)| t.as_str())
+                .collect::<Vec<_>>()
+                .join(" > "),
+        );
+        start = i;
+    }
+    if start < s.len() {
+        sections.push((start..s.len(), heading));
+    }
+    sections
+}
+
+fn markdown_header(line: &str) -> Option<(usize, String)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let line = line.trim();
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct WordTokenizer;
+
+    impl Tokenizer for WordTokenizer {
+        fn count_tokens(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+    }
+
+    fn chunker(strategy: ChunkStrategy, tokens: usize) -> Chunker {
+        Chunker::new(strategy, tokens).with_tokenizer(Arc::new(WordTokenizer))
+    }
+
+    fn texts(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_fixed_size() {
+        let text = "one two three four five six seven";
+        let chunks = chunker(ChunkStrategy::FixedSize, 3).chunk(text);
+        assert_eq!(
+            texts(&chunks),
+            vec!["one two three", "four five six", "seven"]
+        );
+        for chunk in &chunks {
+            assert_eq!(&text[chunk.start..chunk.end], chunk.text);
+        }
+
+        let chunks = chunker(ChunkStrategy::FixedSize, 4)
+            .with_overlap(1)
+            .chunk(text);
+        assert_eq!(
+            texts(&chunks),
+            vec!["one two three four", "four five six seven"]
+        );
+
+        assert!(Chunker::default().chunk("").is_empty());
+        assert!(Chunker::default().chunk(" \n ").is_empty());
+    }
+
+    #[test]
+    fn test_sentence() {
+        let text = "The cat sat. It was happy! Was it?\nYes it was.\n\nA new paragraph";
+        let chunks = chunker(ChunkStrategy::Sentence, 6).chunk(text);
+        assert_eq!(
+            texts(&chunks),
+            vec![
+                "The cat sat. It was happy!",
+                "Was it?\nYes it was.",
+                "A new paragraph"
+            ]
+        );
+
+        let chunks = chunker(ChunkStrategy::Sentence, 6)
+            .with_overlap(3)
+            .chunk(text);
+        assert_eq!(
+            texts(&chunks),
+            vec![
+                "The cat sat. It was happy!",
+                "It was happy! Was it?",
+                "Was it?\nYes it was.",
+                "Yes it was.\n\nA new paragraph"
+            ]
+        );
+
+        // a long sentence is split into words
+        let chunks = chunker(ChunkStrategy::Sentence, 2).chunk("a b c d e. f");
+        assert_eq!(texts(&chunks), vec!["a b", "c d", "e. f"]);
+
+        // CJK sentences and characters
+        let chunks = Chunker::new(ChunkStrategy::Sentence, 4).chunk("我很好。你呢？");
+        assert_eq!(texts(&chunks), vec!["我很好。", "你呢？"]);
+        let chunks = Chunker::new(ChunkStrategy::FixedSize, 2).chunk("我很好");
+        assert_eq!(texts(&chunks), vec!["我很", "好"]);
+    }
+
+    #[test]
+    fn test_markdown() {
+        let text = "Intro text.\n\n# Guide\n\nRead this.\n\n## Install\n\nRun it:\n\n```sh\n# not a header\n\ncargo add anda\n```\n\n# FAQ\nNone.\n";
+        let chunks = chunker(ChunkStrategy::Markdown, 20).chunk(text);
+        assert_eq!(
+            texts(&chunks),
+            vec![
+                "Intro text.",
+                "# Guide\n\nRead this.",
+                "## Install\n\nRun it:\n\n```sh\n# not a header\n\ncargo add anda\n```",
+                "# FAQ\nNone."
+            ]
+        );
+        let headings: Vec<_> = chunks.iter().map(|c| c.heading.as_deref()).collect();
+        assert_eq!(
+            headings,
+            vec![None, Some("Guide"), Some("Guide > Install"), Some("FAQ")]
+        );
+
+        // the fenced code block is kept whole
+        let chunks = chunker(ChunkStrategy::Markdown, 10).chunk(text);
+        assert!(chunks.iter().any(|c| c.text.starts_with("```sh")
+            && c.text.ends_with("```")
+            && c.heading.as_deref() == Some("Guide > Install")));
+    }
+
+    #[test]
+    fn test_code() {
+        let text = "use std::io;\n\nfn a() {\n    one();\n\n    two();\n}\n\n/// Doc\nfn b() {\n    three();\n}\n";
+        let chunks = chunker(ChunkStrategy::Code, 7).chunk(text);
+        assert_eq!(
+            texts(&chunks),
+            vec![
+                "use std::io;",
+                "fn a() {\n    one();\n\n    two();\n}",
+                "/// Doc\nfn b() {\n    three();\n}"
+            ]
+        );
+
+        // a long block is split into lines
+        let chunks = chunker(ChunkStrategy::Code, 3).chunk(text);
+        assert!(
+            chunks
+                .iter()
+                .all(|c| WordTokenizer.count_tokens(&c.text) <= 3)
+        );
+        assert_eq!(chunks[1].text, "fn a() {");
+        assert_eq!(chunks[2].text, "one();\n\n    two();\n}");
+    }
+}
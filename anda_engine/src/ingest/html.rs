@@ -0,0 +1,543 @@
+use anda_core::decode_html_entities;
+
+/// The text and metadata extracted from an HTML page.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HtmlContent {
+    /// The `<title>` of the page, or its `og:title` meta.
+    pub title: Option<String>,
+    /// The `description` meta of the page.
+    pub description: Option<String>,
+    /// The primary language subtag of the `lang` attribute of the page, lowercased.
+    pub lang: Option<String>,
+    /// The texts of the `<h1>` to `<h6>` headings, in document order.
+    pub headings: Vec<String>,
+    /// The readable text, in markdown: headings are prefixed with `#`, list items with
+    /// `-` or their number, table cells are separated by `|` and preformatted blocks
+    /// are fenced with "```".
+    pub text: String,
+}
+
+/// Elements whose content is not readable text, or is page boilerplate.
+const SKIPPED: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "math", "canvas", "iframe", "object",
+    "select", "button", "nav", "aside", "footer",
+];
+
+/// Elements whose content is not parsed as HTML.
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+/// Extracts the readable text and the metadata of an HTML page. The extraction is
+/// lenient: unclosed or misnested elements do not fail, and scripts, styles, forms
+/// controls and navigation are skipped.
+pub fn extract_html(html: &str) -> HtmlContent {
+    let mut content = HtmlContent::default();
+    let mut w = Writer::default();
+    // the kinds of the open lists: None for `ul`, the next number for `ol`
+    let mut lists: Vec<Option<usize>> = Vec::new();
+    let mut cells = 0usize;
+    let mut heading: Option<usize> = None;
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        let Some(i) = rest.find('<') else {
+            w.text(rest);
+            break;
+        };
+        w.text(&rest[..i]);
+        rest = &rest[i..];
+
+        if let Some(r) = rest.strip_prefix("<!--") {
+            rest = r.find("-->").map(|j| &r[j + 3..]).unwrap_or("");
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map(|j| &rest[j + 1..]).unwrap_or("");
+            continue;
+        }
+        let Some(tag) = parse_tag(rest) else {
+            w.text("<");
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[tag.len..];
+        let name = tag.name.as_str();
+
+        if tag.closing {
+            match name {
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    if let Some(start) = heading.take() {
+                        let text = w.out[start..].trim().to_string();
+                        if !text.is_empty() {
+                            content.headings.push(text);
+                        }
+                    }
+                    w.block(2);
+                }
+                "ul" | "ol" => {
+                    lists.pop();
+                    w.block(if lists.is_empty() { 2 } else { 1 });
+                }
+                "pre" if w.pre > 0 => {
+                    w.pre -= 1;
+                    if !w.out.ends_with('\n') {
+                        w.out.push('\n');
+                    }
+                    w.out.push_str("```");
+                    w.block(2);
+                }
+                _ => w.block(block_newlines(name)),
+            }
+            continue;
+        }
+
+        if SKIPPED.contains(&name) && !tag.self_closing {
+            rest = skip_element(rest, name);
+            continue;
+        }
+        if RAW_TEXT.contains(&name) && !tag.self_closing {
+            let (text, r) = raw_text(rest, name);
+            rest = r;
+            if name == "title" {
+                let title = collapse_whitespace(&decode_html_entities(text));
+                if content.title.is_none() && !title.is_empty() {
+                    content.title = Some(title);
+                }
+            } else {
+                w.block(1);
+                w.text(text);
+                w.block(1);
+            }
+            continue;
+        }
+
+        match name {
+            "html" => {
+                if let Some(lang) = tag.attr("lang") {
+                    let lang = lang.split(['-', '_']).next().unwrap_or_default().trim();
+                    if !lang.is_empty() {
+                        content.lang = Some(lang.to_ascii_lowercase());
+                    }
+                }
+            }
+            "meta" => {
+                let key = tag.attr("name").or_else(|| tag.attr("property"));
+                let value = tag.attr("content").map(|v| collapse_whitespace(&v));
+                match (key.as_deref(), value) {
+                    (Some("description"), Some(v)) if !v.is_empty() => {
+                        content.description = Some(v);
+                    }
+                    (Some("og:title"), Some(v)) if !v.is_empty() && content.title.is_none() => {
+                        content.title = Some(v);
+                    }
+                    _ => {}
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = (name.as_bytes()[1] - b'0') as usize;
+                w.block(2);
+                w.push(&"#".repeat(level));
+                w.out.push(' ');
+                heading = Some(w.out.len());
+            }
+            "ul" | "ol" => {
+                w.block(if lists.is_empty() { 2 } else { 1 });
+                lists.push(if name == "ol" { Some(1) } else { None });
+            }
+            "li" => {
+                w.block(1);
+                let indent = "  ".repeat(lists.len().saturating_sub(1));
+                let marker = match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{indent}{}. ", *n - 1)
+                    }
+                    _ => format!("{indent}- "),
+                };
+                w.push(&marker);
+            }
+            "tr" => {
+                w.block(1);
+                cells = 0;
+            }
+            "td" | "th" => {
+                if cells > 0 {
+                    w.push(" | ");
+                }
+                cells += 1;
+            }
+            "br" => w.line_break(),
+            "pre" => {
+                w.block(2);
+                w.push("```");
+                w.out.push('\n');
+                w.pre += 1;
+                // a newline right after `<pre>` is ignored
+                rest = rest
+                    .strip_prefix("\r\n")
+                    .or_else(|| rest.strip_prefix('\n'))
+                    .unwrap_or(rest);
+            }
+            _ => w.block(block_newlines(name)),
+        }
+    }
+
+    if let Some(start) = heading {
+        let text = w.out[start..].trim().to_string();
+        if !text.is_empty() {
+            content.headings.push(text);
+        }
+    }
+    content.text = w.finish();
+    content
+}
+
+/// The number of newlines an element starts and ends with: 2 for paragraphs, 1 for
+/// lines and 0 for inline elements.
+fn block_newlines(name: &str) -> usize {
+    match name {
+        "p" | "section" | "article" | "main" | "header" | "blockquote" | "figure" | "table"
+        | "hr" | "dl" | "form" | "fieldset" | "address" | "details" | "h1" | "h2" | "h3" | "h4"
+        | "h5" | "h6" => 2,
+        "div" | "li" | "tr" | "dt" | "dd" | "figcaption" | "caption" | "summary" | "legend"
+        | "body" | "center" => 1,
+        _ => 0,
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+    newlines: usize,
+    pre: usize,
+}
+
+impl Writer {
+    fn block(&mut self, n: usize) {
+        if !self.out.is_empty() {
+            self.newlines = self.newlines.max(n);
+        }
+    }
+
+    fn line_break(&mut self) {
+        if !self.out.is_empty() {
+            self.newlines += 1;
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.newlines > 0 {
+            self.out
+                .truncate(self.out.trim_end_matches([' ', '\t']).len());
+            for _ in 0..self.newlines {
+                self.out.push('\n');
+            }
+            self.newlines = 0;
+        }
+        self.out.push_str(text);
+    }
+
+    fn text(&mut self, raw: &str) {
+        if raw.is_empty() {
+            return;
+        }
+        let text = decode_html_entities(raw);
+        if self.pre > 0 {
+            self.push(&text);
+            return;
+        }
+
+        let mut collapsed = String::with_capacity(text.len());
+        for (i, word) in text.split_ascii_whitespace().enumerate() {
+            if i > 0 {
+                collapsed.push(' ');
+            }
+            collapsed.push_str(word);
+        }
+        let leading = text.starts_with(|c: char| c.is_ascii_whitespace());
+        let trailing = text.ends_with(|c: char| c.is_ascii_whitespace());
+        if collapsed.is_empty() {
+            // whitespace between elements separates words
+            if leading && self.newlines == 0 && !self.out.is_empty() && !self.ends_with_space() {
+                self.out.push(' ');
+            }
+            return;
+        }
+        if leading && self.newlines == 0 && !self.out.is_empty() && !self.ends_with_space() {
+            self.out.push(' ');
+        }
+        self.push(&collapsed);
+        if trailing {
+            self.out.push(' ');
+        }
+    }
+
+    fn ends_with_space(&self) -> bool {
+        self.out.ends_with(|c: char| c.is_whitespace())
+    }
+
+    fn finish(self) -> String {
+        let mut text = String::with_capacity(self.out.len());
+        let mut blank = 0;
+        for line in self.out.lines() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                blank += 1;
+                continue;
+            }
+            if !text.is_empty() {
+                text.push_str(if blank > 0 { "\n\n" } else { "\n" });
+            }
+            blank = 0;
+            text.push_str(line);
+        }
+        text
+    }
+}
+
+struct Tag {
+    name: String,
+    attrs: Vec<(String, String)>,
+    closing: bool,
+    self_closing: bool,
+    /// The length of the tag in bytes, including `<` and `>`.
+    len: usize,
+}
+
+impl Tag {
+    fn attr(&self, name: &str) -> Option<String> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| decode_html_entities(v).into_owned())
+    }
+}
+
+/// Parses the tag at the start of the input, which starts with `<`. Returns None if
+/// it is not a tag, such as a `<` in text.
+fn parse_tag(input: &str) -> Option<Tag> {
+    let bytes = input.as_bytes();
+    let mut i = 1;
+    let closing = bytes.get(i) == Some(&b'/');
+    if closing {
+        i += 1;
+    }
+    if !bytes.get(i).is_some_and(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+    let start = i;
+    while i < bytes.len()
+        && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'-' || bytes[i] == b':')
+    {
+        i += 1;
+    }
+    let name = input[start..i].to_ascii_lowercase();
+
+    let mut attrs = Vec::new();
+    let mut self_closing = false;
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        match bytes.get(i) {
+            None => break,
+            Some(b'>') => {
+                i += 1;
+                break;
+            }
+            Some(b'/') => {
+                i += 1;
+                self_closing = bytes.get(i) == Some(&b'>');
+                continue;
+            }
+            _ => {}
+        }
+        let key_start = i;
+        while i < bytes.len()
+            && !matches!(bytes[i], b'=' | b'>' | b'/')
+            && !bytes[i].is_ascii_whitespace()
+        {
+            i += 1;
+        }
+        let key = input[key_start..i].to_ascii_lowercase();
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let mut value = "";
+        if bytes.get(i) == Some(&b'=') {
+            i += 1;
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            match bytes.get(i) {
+                Some(&q) if q == b'"' || q == b'\'' => {
+                    let end = input[i + 1..]
+                        .find(q as char)
+                        .map_or(bytes.len(), |j| i + 1 + j);
+                    value = &input[i + 1..end];
+                    i = (end + 1).min(bytes.len());
+                }
+                _ => {
+                    let start = i;
+                    while i < bytes.len() && bytes[i] != b'>' && !bytes[i].is_ascii_whitespace() {
+                        i += 1;
+                    }
+                    value = &input[start..i];
+                }
+            }
+        }
+        if !key.is_empty() {
+            attrs.push((key, value.to_string()));
+        }
+    }
+
+    Some(Tag {
+        name,
+        attrs,
+        closing,
+        self_closing,
+        len: i,
+    })
+}
+
+/// Finds the closing tag of an element, case-insensitively. Returns the byte offset
+/// of `</name` and of the end of the closing tag.
+fn find_closing(input: &str, name: &str) -> Option<(usize, usize)> {
+    let lower = input.to_ascii_lowercase();
+    let pattern = format!("</{name}");
+    let mut from = 0;
+    while let Some(j) = lower[from..].find(&pattern) {
+        let start = from + j;
+        let after = start + pattern.len();
+        if lower[after..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+            || after == lower.len()
+        {
+            let end = lower[after..]
+                .find('>')
+                .map_or(lower.len(), |k| after + k + 1);
+            return Some((start, end));
+        }
+        from = after;
+    }
+    None
+}
+
+/// Returns the raw text of an element and the input after its closing tag.
+fn raw_text<'a>(input: &'a str, name: &str) -> (&'a str, &'a str) {
+    match find_closing(input, name) {
+        Some((start, end)) => (&input[..start], &input[end..]),
+        None => (input, ""),
+    }
+}
+
+/// Returns the input after the closing tag of an element, skipping nested elements
+/// of the same name.
+fn skip_element<'a>(input: &'a str, name: &str) -> &'a str {
+    if RAW_TEXT.contains(&name) {
+        return raw_text(input, name).1;
+    }
+
+    let lower = input.to_ascii_lowercase();
+    let open = format!("<{name}");
+    let mut depth = 1;
+    let mut pos = 0;
+    while let Some((start, end)) = find_closing(&input[pos..], name) {
+        depth += lower[pos..pos + start]
+            .match_indices(&open)
+            .filter(|(j, _)| {
+                lower[pos + j + open.len()..]
+                    .starts_with(|c: char| c == '>' || c == '/' || c.is_ascii_whitespace())
+            })
+            .count();
+        depth -= 1;
+        pos += end;
+        if depth == 0 {
+            return &input[pos..];
+        }
+    }
+    ""
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_html() {
+        let html = r#"<!DOCTYPE html>
+<html lang="en-US">
+<head>
+  <meta charset="utf-8">
+  <title>Anda &amp; Friends</title>
+  <meta name="description" content="An agent   framework.">
+  <style>body { color: red; }</style>
+  <script>if (a < b) { document.write("<p>no</p>"); }</script>
+</head>
+<body>
+  <nav><ul><li><a href="/">Home</a></li><nav>inner</nav></ul></nav>
+  <h1>Getting <em>started</em></h1>
+  <p>Anda is a <b>framework</b>
+     for AI agents.<br>It runs on ICP.</p>
+  <!-- a comment <p>hidden</p> -->
+  <h2>Install</h2>
+  <ol><li>Add the crate</li><li>Build
+    <ul><li>debug</li></ul></li></ol>
+  <table><tr><th>Name</th><th>Value</th></tr><tr><td>a</td><td>1 &lt; 2</td></tr></table>
+  <pre><code>fn main() {
+    println!("hi");
+}</code></pre>
+  <p>5 < 6 and done</p>
+  <footer>Copyright</footer>
+</body>
+</html>"#;
+        let content = extract_html(html);
+        assert_eq!(content.title.as_deref(), Some("Anda & Friends"));
+        assert_eq!(content.description.as_deref(), Some("An agent framework."));
+        assert_eq!(content.lang.as_deref(), Some("en"));
+        assert_eq!(content.headings, vec!["Getting started", "Install"]);
+        assert_eq!(
+            content.text,
+            r#"# Getting started
+
+Anda is a framework for AI agents.
+It runs on ICP.
+
+## Install
+
+1. Add the crate
+2. Build
+  - debug
+
+Name | Value
+a | 1 < 2
+
+```
+fn main() {
+    println!("hi");
+}
+```
+
+5 < 6 and done"#
+        );
+    }
+
+    #[test]
+    fn test_extract_html_lenient() {
+        let content = extract_html(
+            "<meta property='og:title' content='OG'>text <i>without</i> tags<p>unclosed",
+        );
+        assert_eq!(content.title.as_deref(), Some("OG"));
+        assert_eq!(content.text, "text without tags\n\nunclosed");
+
+        let content =
+            extract_html("<H3 class=x>Title</h3><DIV>a</DIV><div>b</div><script>never closed");
+        assert_eq!(content.headings, vec!["Title"]);
+        assert_eq!(content.text, "### Title\n\na\nb");
+    }
+}
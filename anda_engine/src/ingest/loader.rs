@@ -0,0 +1,263 @@
+use anda_core::{
+    BoxError, Document, META_AUTHOR, META_ENCODING, META_LANG, META_SOURCE, Resource, decode_text,
+    sniff_mime_type,
+};
+use std::collections::BTreeMap;
+
+use super::{extract_html, extract_pdf};
+
+/// The metadata key of the title of a loaded document.
+pub const META_TITLE: &str = "title";
+
+/// The metadata key of the description of a loaded document.
+pub const META_DESCRIPTION: &str = "description";
+
+/// The metadata key of the headings of a loaded document, one per line.
+pub const META_HEADINGS: &str = "headings";
+
+/// The metadata key of the MIME type of the resource of a loaded document.
+pub const META_MIME_TYPE: &str = "mime_type";
+
+/// The metadata key of the number of pages of a loaded document.
+pub const META_PAGES: &str = "pages";
+
+/// Converts resources of some MIME types into documents.
+pub trait DocumentLoader: Send + Sync {
+    /// Returns true if the loader can load resources of the MIME type, without parameters.
+    fn supports(&self, mime_type: &str) -> bool;
+
+    /// Loads the text and the metadata of an inline resource.
+    fn load(&self, resource: &Resource) -> Result<Document, BoxError>;
+}
+
+/// Loads HTML pages as markdown text, with their title, description, language and
+/// headings as metadata.
+#[derive(Clone, Debug, Default)]
+pub struct HtmlLoader;
+
+impl DocumentLoader for HtmlLoader {
+    fn supports(&self, mime_type: &str) -> bool {
+        matches!(mime_type, "text/html" | "application/xhtml+xml")
+    }
+
+    fn load(&self, resource: &Resource) -> Result<Document, BoxError> {
+        let (html, encoding) = decode_text(blob(resource)?);
+        let content = extract_html(&html);
+
+        let mut doc = new_document(resource, "text/html");
+        doc.text = content.text;
+        let meta = &mut doc.metadata;
+        meta.insert(META_ENCODING.to_string(), encoding.as_str().to_string());
+        if let Some(title) = content.title {
+            meta.insert(META_TITLE.to_string(), title);
+        }
+        if let Some(description) = content.description {
+            meta.insert(META_DESCRIPTION.to_string(), description);
+        }
+        if let Some(lang) = content.lang {
+            meta.insert(META_LANG.to_string(), lang);
+        }
+        if !content.headings.is_empty() {
+            meta.insert(META_HEADINGS.to_string(), content.headings.join("\n"));
+        }
+        Ok(doc)
+    }
+}
+
+/// Loads the text of PDF files, with their title, author, outline and number of pages
+/// as metadata. See [`extract_pdf`] for the supported files.
+#[derive(Clone, Debug, Default)]
+pub struct PdfLoader;
+
+impl DocumentLoader for PdfLoader {
+    fn supports(&self, mime_type: &str) -> bool {
+        mime_type == "application/pdf"
+    }
+
+    fn load(&self, resource: &Resource) -> Result<Document, BoxError> {
+        let content = extract_pdf(blob(resource)?)?;
+
+        let mut doc = new_document(resource, "application/pdf");
+        doc.text = content.text();
+        let meta = &mut doc.metadata;
+        meta.insert(META_PAGES.to_string(), content.pages.len().to_string());
+        if let Some(title) = content.title {
+            meta.insert(META_TITLE.to_string(), title);
+        }
+        if let Some(author) = content.author {
+            meta.insert(META_AUTHOR.to_string(), author);
+        }
+        if !content.headings.is_empty() {
+            meta.insert(META_HEADINGS.to_string(), content.headings.join("\n"));
+        }
+        Ok(doc)
+    }
+}
+
+/// Loads plain text, markdown and other textual resources as they are.
+#[derive(Clone, Debug, Default)]
+pub struct TextLoader;
+
+impl DocumentLoader for TextLoader {
+    fn supports(&self, mime_type: &str) -> bool {
+        mime_type.starts_with("text/")
+            || matches!(
+                mime_type,
+                "application/json" | "application/toml" | "application/yaml" | "application/xml"
+            )
+    }
+
+    fn load(&self, resource: &Resource) -> Result<Document, BoxError> {
+        let (text, encoding) = decode_text(blob(resource)?);
+        let mut doc = new_document(resource, "text/plain");
+        doc.text = text;
+        doc.metadata
+            .insert(META_ENCODING.to_string(), encoding.as_str().to_string());
+        Ok(doc)
+    }
+}
+
+/// Loads an inline resource into a document with the first of the HTML, PDF and text
+/// loaders that supports its MIME type. The MIME type is sniffed from the content if
+/// the resource does not declare it.
+pub fn load_document(resource: &Resource) -> Result<Document, BoxError> {
+    let loaders: [&dyn DocumentLoader; 3] = [&HtmlLoader, &PdfLoader, &TextLoader];
+    let mime_type = resource_mime_type(resource)?;
+    match loaders.iter().find(|l| l.supports(&mime_type)) {
+        Some(loader) => loader.load(resource),
+        None => Err(format!("unsupported MIME type: {mime_type}").into()),
+    }
+}
+
+/// Returns the MIME type of a resource without parameters, sniffed from its content if
+/// it is not declared. Sniffed text that starts with an HTML tag is "text/html".
+fn resource_mime_type(resource: &Resource) -> Result<String, BoxError> {
+    if let Some(mime_type) = &resource.mime_type {
+        let mime_type = mime_type.split(';').next().unwrap_or_default().trim();
+        if !mime_type.is_empty() {
+            return Ok(mime_type.to_ascii_lowercase());
+        }
+    }
+
+    let data = blob(resource)?;
+    let mime_type = sniff_mime_type(data);
+    if mime_type == "text/plain" {
+        let head = String::from_utf8_lossy(&data[..data.len().min(256)]);
+        let head = head.trim_start().to_ascii_lowercase();
+        if head.starts_with("<!doctype html") || head.starts_with("<html") {
+            return Ok("text/html".to_string());
+        }
+    }
+    Ok(mime_type.to_string())
+}
+
+fn blob(resource: &Resource) -> Result<&[u8], BoxError> {
+    match &resource.blob {
+        Some(blob) => Ok(blob.as_ref()),
+        None => Err(format!(
+            "resource {:?} has no inline content",
+            resource.name.as_ref().or(resource.uri.as_ref())
+        )
+        .into()),
+    }
+}
+
+/// Returns an empty document identified by the name or the URI of the resource.
+fn new_document(resource: &Resource, mime_type: &str) -> Document {
+    let mut metadata = BTreeMap::new();
+    let mime_type = resource
+        .mime_type
+        .as_deref()
+        .and_then(|m| m.split(';').next())
+        .map(|m| m.trim())
+        .filter(|m| !m.is_empty())
+        .unwrap_or(mime_type);
+    metadata.insert(META_MIME_TYPE.to_string(), mime_type.to_string());
+    if let Some(uri) = &resource.uri {
+        metadata.insert(META_SOURCE.to_string(), uri.clone());
+    }
+    Document {
+        id: resource
+            .name
+            .clone()
+            .or_else(|| resource.uri.clone())
+            .unwrap_or_else(|| resource.tag.clone()),
+        text: String::new(),
+        metadata,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::pdf::tests::{build_pdf, stream};
+    use crate::ingest::{ChunkStrategy, Chunker, IngestPipeline, META_HEADING};
+
+    fn resource(name: &str, mime_type: Option<&str>, data: &[u8]) -> Resource {
+        Resource {
+            tag: "doc".to_string(),
+            name: Some(name.to_string()),
+            uri: Some(format!("https://example.com/{name}")),
+            mime_type: mime_type.map(|m| m.to_string()),
+            blob: Some(data.to_vec().into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_load_document() {
+        let html = b"<!doctype html><html lang=fr><title>Guide</title><h1>Intro</h1><p>Bonjour &#233;tudiants, voici le guide.</p><h2>Usage</h2><p>Lancez le moteur.</p></html>";
+        let doc = load_document(&resource("guide.html", None, html)).unwrap();
+        assert_eq!(doc.id, "guide.html");
+        assert_eq!(
+            doc.text,
+            "# Intro\n\nBonjour étudiants, voici le guide.\n\n## Usage\n\nLancez le moteur."
+        );
+        assert_eq!(doc.metadata[META_TITLE], "Guide");
+        assert_eq!(doc.metadata[META_HEADINGS], "Intro\nUsage");
+        assert_eq!(doc.metadata[META_LANG], "fr");
+        assert_eq!(doc.metadata[META_MIME_TYPE], "text/html");
+        assert_eq!(doc.metadata[META_SOURCE], "https://example.com/guide.html");
+
+        // the markdown headings of HTML pages are chunk headings
+        let chunks =
+            IngestPipeline::new(Chunker::new(ChunkStrategy::Markdown, 12)).chunk_document(&doc);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].meta[META_HEADING], "Intro > Usage");
+        assert_eq!(chunks[1].meta[META_TITLE], "Guide");
+
+        let pdf = build_pdf(
+            &[
+                b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+                b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+                b"<< /Type /Page /Contents 4 0 R >>".to_vec(),
+                stream("", b"BT (Annual report) Tj ET"),
+                b"<< /Title (Report) /Author (alice) >>".to_vec(),
+            ],
+            1,
+            Some(5),
+        );
+        let doc = load_document(&resource("report", Some("application/pdf"), &pdf)).unwrap();
+        assert_eq!(doc.text, "Annual report");
+        assert_eq!(doc.metadata[META_TITLE], "Report");
+        assert_eq!(doc.metadata[META_AUTHOR], "alice");
+        assert_eq!(doc.metadata[META_PAGES], "1");
+        assert!(!doc.metadata.contains_key(META_HEADINGS));
+
+        let doc = load_document(&resource(
+            "notes.md",
+            Some("text/markdown; charset=utf-8"),
+            b"# Notes",
+        ))
+        .unwrap();
+        assert_eq!(doc.text, "# Notes");
+        assert_eq!(doc.metadata[META_MIME_TYPE], "text/markdown");
+        assert_eq!(doc.metadata[META_ENCODING], "utf-8");
+
+        let err = load_document(&resource("logo.png", None, b"\x89PNG\r\n\x1a\n")).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported MIME type: image/png");
+        let mut remote = resource("remote.pdf", Some("application/pdf"), b"");
+        remote.blob = None;
+        assert!(load_document(&remote).is_err());
+    }
+}
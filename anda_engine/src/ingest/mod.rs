@@ -0,0 +1,348 @@
+//! Ingestion of documents into knowledge stores.
+//!
+//! An [`IngestPipeline`] normalizes raw [`Document`]s, splits them into chunks with a
+//! [`Chunker`], embeds the chunks and adds them to a knowledge store. Each chunk records
+//! its provenance in its metadata:
+//! - [`META_SOURCE`]: the source of the document, its `source` metadata or its ID;
+//! - [`META_DOCUMENT`]: the ID of the document;
+//! - [`META_CHUNK`] and [`META_CHUNKS`]: the index of the chunk and the number of chunks;
+//! - [`META_OFFSET`]: the byte range `[start, end)` of the chunk in the normalized text;
+//! - [`META_HEADING`]: the path of the markdown headers of the chunk, if any;
+//! - [`META_CHUNKER`]: the name of the [`ChunkStrategy`].
+//!
+//! The other metadata of the document is copied to its chunks.
+//!
+//! Documents can be loaded from HTML, PDF and text resources with [`load_document`] or
+//! a [`DocumentLoader`], which extract their text and their title and headings as
+//! metadata.
+//!
+//! # Example
+//! ```rust,ignore
+//! let chunker = Chunker::new(ChunkStrategy::Markdown, 400).with_overlap(50);
+//! let pipeline = IngestPipeline::new(chunker).with_user("docs");
+//! let docs = resources.iter().map(load_document).collect::<Result<Vec<_>, _>>()?;
+//! let stats = pipeline.ingest(&ctx, &knowledge_store, docs).await?;
+//! ```
+
+use anda_core::{
+    BoxError, Document, EmbeddingFeatures, KnowledgeFeatures, KnowledgeInput, META_LANG,
+    META_SOURCE, Usage, Value, detect_language, normalize_text,
+};
+use std::collections::BTreeMap;
+
+mod chunker;
+mod html;
+mod loader;
+mod pdf;
+
+pub use chunker::*;
+pub use html::*;
+pub use loader::*;
+pub use pdf::*;
+
+/// The metadata key of the ID of the document of a chunk.
+pub const META_DOCUMENT: &str = "document";
+
+/// The metadata key of the index of a chunk in its document.
+pub const META_CHUNK: &str = "chunk";
+
+/// The metadata key of the number of chunks of the document of a chunk.
+pub const META_CHUNKS: &str = "chunks";
+
+/// The metadata key of the byte range of a chunk in its document.
+pub const META_OFFSET: &str = "offset";
+
+/// The metadata key of the path of the markdown headers of a chunk.
+pub const META_HEADING: &str = "heading";
+
+/// The metadata key of the chunking strategy of a chunk.
+pub const META_CHUNKER: &str = "chunker";
+
+/// The result of an ingestion.
+#[derive(Clone, Debug, Default)]
+pub struct IngestStats {
+    pub documents: usize,
+    pub chunks: usize,
+    /// The usage of the embedding model.
+    pub usage: Usage,
+}
+
+/// Chunks, embeds and stores documents.
+#[derive(Clone, Debug, Default)]
+pub struct IngestPipeline {
+    chunker: Chunker,
+    user: String,
+    batch_size: usize,
+}
+
+impl IngestPipeline {
+    /// Creates a pipeline that chunks documents with the chunker.
+    pub fn new(chunker: Chunker) -> Self {
+        Self {
+            chunker,
+            user: String::new(),
+            batch_size: 64,
+        }
+    }
+
+    /// Sets the user of the chunks of documents without a `user` metadata.
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = user.to_string();
+        self
+    }
+
+    /// Sets the number of chunks embedded and stored at once. Defaults to 64.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Splits a document into chunks with their provenance metadata, not embedded yet.
+    pub fn chunk_document(&self, doc: &Document) -> Vec<KnowledgeInput> {
+        let text = normalize_text(&doc.text);
+        let mut meta: BTreeMap<String, Value> = doc
+            .metadata
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect();
+        let user = match meta.remove("user") {
+            Some(Value::String(user)) => user,
+            _ => self.user.clone(),
+        };
+        meta.entry(META_SOURCE.to_string())
+            .or_insert_with(|| doc.id.clone().into());
+        meta.insert(META_DOCUMENT.to_string(), doc.id.clone().into());
+        meta.insert(
+            META_CHUNKER.to_string(),
+            self.chunker.strategy().as_str().into(),
+        );
+        // the language of a whole document is detected better than of its chunks
+        if !meta.contains_key(META_LANG)
+            && let Some(lang) = detect_language(&text)
+        {
+            meta.insert(META_LANG.to_string(), lang.into());
+        }
+
+        let chunks = self.chunker.chunk(&text);
+        let count = chunks.len();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut meta = meta.clone();
+                meta.insert(META_CHUNK.to_string(), i.into());
+                meta.insert(META_CHUNKS.to_string(), count.into());
+                meta.insert(META_OFFSET.to_string(), vec![chunk.start, chunk.end].into());
+                if let Some(heading) = chunk.heading {
+                    meta.insert(META_HEADING.to_string(), heading.into());
+                }
+                KnowledgeInput {
+                    user: user.clone(),
+                    text: chunk.text,
+                    meta,
+                    vec: Vec::new(),
+                }
+            })
+            .collect()
+    }
+
+    /// Chunks the documents, embeds the chunks with the embedder and adds them to the store,
+    /// in batches. Chunks of a failed batch and of the following ones are not stored.
+    pub async fn ingest<E, K>(
+        &self,
+        embedder: &E,
+        store: &K,
+        docs: Vec<Document>,
+    ) -> Result<IngestStats, BoxError>
+    where
+        E: EmbeddingFeatures + Sync,
+        K: KnowledgeFeatures + Sync,
+    {
+        let mut stats = IngestStats {
+            documents: docs.len(),
+            ..Default::default()
+        };
+        let chunks: Vec<KnowledgeInput> = docs
+            .iter()
+            .flat_map(|doc| self.chunk_document(doc))
+            .collect();
+
+        let mut chunks = chunks.into_iter().peekable();
+        while chunks.peek().is_some() {
+            let mut batch: Vec<KnowledgeInput> = chunks.by_ref().take(self.batch_size).collect();
+            let (embeddings, usage) = embedder
+                .embed(batch.iter().map(|c| c.text.clone()).collect::<Vec<_>>())
+                .await?;
+            stats.usage.accumulate(&usage);
+            if embeddings.len() != batch.len() {
+                return Err(format!(
+                    "invalid embeddings, expected {}, got {}",
+                    batch.len(),
+                    embeddings.len()
+                )
+                .into());
+            }
+            for (chunk, embedding) in batch.iter_mut().zip(embeddings) {
+                chunk.vec = embedding.vec;
+            }
+            let n = batch.len();
+            store.knowledge_add(batch).await?;
+            stats.chunks += n;
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::{Embedding, Knowledge, KnowledgeFilter};
+    use std::sync::Mutex;
+
+    struct MockEmbedder;
+
+    impl EmbeddingFeatures for MockEmbedder {
+        fn ndims(&self) -> usize {
+            2
+        }
+
+        async fn embed(
+            &self,
+            texts: impl IntoIterator<Item = String> + Send,
+        ) -> Result<(Vec<Embedding>, Usage), BoxError> {
+            let embeddings: Vec<Embedding> = texts
+                .into_iter()
+                .map(|text| Embedding {
+                    vec: vec![text.len() as f32, 1.0],
+                    text,
+                })
+                .collect();
+            let usage = Usage {
+                input_tokens: embeddings.len() as u64,
+                requests: 1,
+                ..Default::default()
+            };
+            Ok((embeddings, usage))
+        }
+
+        async fn embed_query(&self, text: &str) -> Result<(Embedding, Usage), BoxError> {
+            let (mut embeddings, usage) = self.embed(vec![text.to_string()]).await?;
+            Ok((embeddings.pop().unwrap(), usage))
+        }
+    }
+
+    #[derive(Default)]
+    struct MockStore {
+        added: Mutex<Vec<Vec<KnowledgeInput>>>,
+    }
+
+    impl KnowledgeFeatures for MockStore {
+        async fn knowledge_top_n(
+            &self,
+            _query: &str,
+            _n: usize,
+            _user: Option<String>,
+        ) -> Result<Vec<Knowledge>, BoxError> {
+            Ok(vec![])
+        }
+
+        async fn knowledge_search(
+            &self,
+            _query: &str,
+            _n: usize,
+            _filter: &KnowledgeFilter,
+        ) -> Result<Vec<Knowledge>, BoxError> {
+            Ok(vec![])
+        }
+
+        async fn knowledge_latest_n(
+            &self,
+            _last_seconds: u32,
+            _n: usize,
+            _user: Option<String>,
+        ) -> Result<Vec<Knowledge>, BoxError> {
+            Ok(vec![])
+        }
+
+        async fn knowledge_add(&self, docs: Vec<KnowledgeInput>) -> Result<(), BoxError> {
+            self.added.lock().unwrap().push(docs);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_chunk_document() {
+        let pipeline =
+            IngestPipeline::new(Chunker::new(ChunkStrategy::Markdown, 20)).with_user("docs");
+        let doc = Document {
+            id: "guide.md".to_string(),
+            text: "# Guide\r\nThe engine runs agents &amp; tools.\r\n\r\n## Install\r\nAdd the crate to the project."
+                .to_string(),
+            metadata: BTreeMap::from([("version".to_string(), "1".to_string())]),
+        };
+        let chunks = pipeline.chunk_document(&doc);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].user, "docs");
+        assert_eq!(chunks[0].text, "# Guide\nThe engine runs agents & tools.");
+        assert_eq!(chunks[1].text, "## Install\nAdd the crate to the project.");
+
+        let meta = &chunks[1].meta;
+        assert_eq!(meta[META_SOURCE], "guide.md");
+        assert_eq!(meta[META_DOCUMENT], "guide.md");
+        assert_eq!(meta[META_CHUNK], 1);
+        assert_eq!(meta[META_CHUNKS], 2);
+        assert_eq!(meta[META_HEADING], "Guide > Install");
+        assert_eq!(meta[META_CHUNKER], "markdown");
+        assert_eq!(meta[META_LANG], "en");
+        assert_eq!(meta["version"], "1");
+        let text = normalize_text(&doc.text);
+        let offset: Vec<usize> = serde_json::from_value(meta[META_OFFSET].clone()).unwrap();
+        assert_eq!(&text[offset[0]..offset[1]], chunks[1].text);
+
+        let doc = Document {
+            id: "note".to_string(),
+            text: "short".to_string(),
+            metadata: BTreeMap::from([
+                ("user".to_string(), "alice".to_string()),
+                (META_SOURCE.to_string(), "https://example.com".to_string()),
+            ]),
+        };
+        let chunks = pipeline.chunk_document(&doc);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].user, "alice");
+        assert!(!chunks[0].meta.contains_key("user"));
+        assert!(!chunks[0].meta.contains_key(META_HEADING));
+        assert_eq!(chunks[0].meta[META_SOURCE], "https://example.com");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_ingest() {
+        let pipeline =
+            IngestPipeline::new(Chunker::new(ChunkStrategy::FixedSize, 2)).with_batch_size(2);
+        let store = MockStore::default();
+        let docs = vec![
+            Document {
+                id: "a".to_string(),
+                text: "one two three four five".to_string(),
+                ..Default::default()
+            },
+            Document {
+                id: "b".to_string(),
+                text: "six".to_string(),
+                ..Default::default()
+            },
+        ];
+        let stats = pipeline.ingest(&MockEmbedder, &store, docs).await.unwrap();
+        assert_eq!(stats.documents, 2);
+        assert_eq!(stats.chunks, 4);
+        assert_eq!(stats.usage.requests, 2);
+
+        let added = store.added.lock().unwrap();
+        let sizes: Vec<usize> = added.iter().map(|b| b.len()).collect();
+        assert_eq!(sizes, vec![2, 2]);
+        let chunk = &added[1][0];
+        assert_eq!(chunk.text, "five");
+        assert_eq!(chunk.vec, vec![4.0, 1.0]);
+        assert_eq!(added[1][1].meta[META_DOCUMENT], "b");
+    }
+}
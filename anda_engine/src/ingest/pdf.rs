@@ -0,0 +1,1169 @@
+use anda_core::{BoxError, windows_1252_char};
+use flate2::read::ZlibDecoder;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Read,
+};
+
+/// The text and metadata extracted from a PDF file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PdfContent {
+    /// The `/Title` of the document information dictionary.
+    pub title: Option<String>,
+    /// The `/Author` of the document information dictionary.
+    pub author: Option<String>,
+    /// The titles of the outline (bookmarks) items, depth first.
+    pub headings: Vec<String>,
+    /// The text of each page.
+    pub pages: Vec<String>,
+}
+
+impl PdfContent {
+    /// Returns the text of the non-empty pages, separated by blank lines.
+    pub fn text(&self) -> String {
+        self.pages
+            .iter()
+            .filter(|p| !p.is_empty())
+            .map(|p| p.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// Extracts the text and the metadata of a PDF file.
+///
+/// This is a best-effort extractor for text-based PDFs: it reads the objects of the
+/// file, including object streams, decodes the Flate, ASCII85 and ASCIIHex filters,
+/// and maps the glyphs of the text operators to Unicode with the `ToUnicode` maps of
+/// the fonts, or with WinAnsi for simple fonts without one. Encrypted files and
+/// scanned pages are not supported.
+pub fn extract_pdf(data: &[u8]) -> Result<PdfContent, BoxError> {
+    let pdf = Pdf::parse(data)?;
+    let pages = pdf.pages();
+    if pages.is_empty() {
+        return Err("no pages found in PDF".into());
+    }
+
+    let mut content = PdfContent::default();
+    if let Object::Dict(info) = pdf.get(&pdf.trailer, "Info") {
+        content.title = pdf.text_string(info, "Title");
+        content.author = pdf.text_string(info, "Author");
+    }
+    if let Object::Dict(root) = pdf.get(&pdf.trailer, "Root")
+        && let Object::Dict(outlines) = pdf.get(root, "Outlines")
+    {
+        pdf.outline(outlines, &mut HashSet::new(), &mut content.headings);
+    }
+
+    for (page, resources) in pages {
+        let mut writer = TextWriter::default();
+        let data = match pdf.get(page, "Contents") {
+            Object::Array(items) => items
+                .iter()
+                .filter_map(|item| pdf.stream_data(pdf.resolve(item)))
+                .collect::<Vec<_>>()
+                .join(&b'\n'),
+            obj => pdf.stream_data(obj).unwrap_or_default(),
+        };
+        pdf.show_text(&data, resources, 0, &mut writer);
+        content.pages.push(writer.finish());
+    }
+    Ok(content)
+}
+
+type Dict = BTreeMap<String, Object>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Object {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(Vec<u8>),
+    Name(String),
+    Array(Vec<Object>),
+    Dict(Dict),
+    /// A reference to an indirect object by its number. Generations are ignored: the
+    /// last definition of an object number wins.
+    Ref(u32),
+    Stream(Dict, Vec<u8>),
+    /// An operator of a content stream, or an unknown keyword.
+    Operator(String),
+}
+
+impl Object {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Object::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn as_name(&self) -> Option<&str> {
+        match self {
+            Object::Name(n) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+static NULL: Object = Object::Null;
+
+/// The depth limit of nested page tree nodes, outline items and form XObjects.
+const MAX_DEPTH: usize = 32;
+
+struct Pdf {
+    objects: HashMap<u32, Object>,
+    trailer: Dict,
+}
+
+impl Pdf {
+    fn parse(data: &[u8]) -> Result<Self, BoxError> {
+        if find(&data[..data.len().min(1024)], b"%PDF-", 0).is_none() {
+            return Err("not a PDF file".into());
+        }
+
+        let mut objects: HashMap<u32, Object> = HashMap::new();
+        let mut trailer = Dict::new();
+        let mut pos = 0;
+        while let Some(k) = find(data, b"obj", pos) {
+            pos = k + 3;
+            let Some(num) = object_number(data, k) else {
+                continue;
+            };
+            let mut lexer = Lexer::new(data, pos);
+            let Some(obj) = lexer.parse_object() else {
+                continue;
+            };
+            let obj = match obj {
+                Object::Dict(dict) => match lexer.stream_data(&dict) {
+                    Some(stream) => Object::Stream(dict, stream),
+                    None => Object::Dict(dict),
+                },
+                obj => obj,
+            };
+            if let Object::Stream(dict, _) = &obj
+                && dict.get("Type").and_then(|t| t.as_name()) == Some("XRef")
+            {
+                trailer.extend(dict.clone());
+            }
+            objects.insert(num, obj);
+            pos = lexer.pos;
+        }
+
+        // trailers of classic cross-reference tables, later ones update earlier ones
+        let mut pos = 0;
+        while let Some(k) = find(data, b"trailer", pos) {
+            pos = k + 7;
+            if let Some(Object::Dict(dict)) = Lexer::new(data, pos).parse_object() {
+                trailer.extend(dict);
+            }
+        }
+
+        let mut pdf = Pdf { objects, trailer };
+        if pdf.trailer.contains_key("Encrypt") {
+            return Err("encrypted PDF is not supported".into());
+        }
+        pdf.expand_object_streams();
+        if !matches!(pdf.get(&pdf.trailer, "Root"), Object::Dict(_)) {
+            let catalog = pdf.objects.iter().find_map(|(num, obj)| match obj {
+                Object::Dict(dict)
+                    if dict.get("Type").and_then(|t| t.as_name()) == Some("Catalog") =>
+                {
+                    Some(*num)
+                }
+                _ => None,
+            });
+            match catalog {
+                Some(num) => {
+                    pdf.trailer.insert("Root".to_string(), Object::Ref(num));
+                }
+                None => return Err("no document catalog found in PDF".into()),
+            }
+        }
+        Ok(pdf)
+    }
+
+    /// Adds the objects compressed in object streams.
+    fn expand_object_streams(&mut self) {
+        let mut expanded = Vec::new();
+        for obj in self.objects.values() {
+            let Object::Stream(dict, _) = obj else {
+                continue;
+            };
+            if dict.get("Type").and_then(|t| t.as_name()) != Some("ObjStm") {
+                continue;
+            }
+            let (Some(n), Some(first)) = (
+                self.get(dict, "N").as_f64(),
+                self.get(dict, "First").as_f64(),
+            ) else {
+                continue;
+            };
+            let Some(data) = self.stream_data(obj) else {
+                continue;
+            };
+            let mut header = Lexer::new(&data, 0);
+            for _ in 0..n as usize {
+                let (Some(Token::Number(num, _)), Some(Token::Number(offset, _))) =
+                    (header.next_token(), header.next_token())
+                else {
+                    break;
+                };
+                let mut lexer = Lexer::new(&data, first as usize + offset as usize);
+                if let Some(obj) = lexer.parse_object() {
+                    expanded.push((num as u32, obj));
+                }
+            }
+        }
+        for (num, obj) in expanded {
+            self.objects.entry(num).or_insert(obj);
+        }
+    }
+
+    fn resolve<'a>(&'a self, obj: &'a Object) -> &'a Object {
+        let mut obj = obj;
+        for _ in 0..MAX_DEPTH {
+            match obj {
+                Object::Ref(num) => obj = self.objects.get(num).unwrap_or(&NULL),
+                _ => return obj,
+            }
+        }
+        &NULL
+    }
+
+    fn get<'a>(&'a self, dict: &'a Dict, key: &str) -> &'a Object {
+        dict.get(key).map_or(&NULL, |obj| self.resolve(obj))
+    }
+
+    fn text_string(&self, dict: &Dict, key: &str) -> Option<String> {
+        match self.get(dict, key) {
+            Object::String(s) => {
+                let text = decode_text_string(s);
+                let text = text.trim();
+                (!text.is_empty()).then(|| text.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the decoded data of a stream, or None if it uses an unsupported filter.
+    fn stream_data(&self, obj: &Object) -> Option<Vec<u8>> {
+        let Object::Stream(dict, data) = self.resolve(obj) else {
+            return None;
+        };
+        let filters = match self.get(dict, "Filter") {
+            Object::Name(name) => vec![name.as_str()],
+            Object::Array(items) => items
+                .iter()
+                .filter_map(|f| self.resolve(f).as_name())
+                .collect(),
+            _ => vec![],
+        };
+
+        let mut data = data.clone();
+        for filter in filters {
+            data = match filter {
+                "FlateDecode" | "Fl" => inflate(&data)?,
+                "ASCII85Decode" | "A85" => ascii85_decode(&data),
+                "ASCIIHexDecode" | "AHx" => hex_decode(&data),
+                _ => return None,
+            };
+        }
+        Some(data)
+    }
+
+    /// Returns the leaf nodes of the page tree with their inherited resources.
+    fn pages(&self) -> Vec<(&Dict, Option<&Dict>)> {
+        let mut pages = Vec::new();
+        if let Object::Dict(root) = self.get(&self.trailer, "Root")
+            && let Some(node) = root.get("Pages")
+        {
+            self.collect_pages(node, None, &mut HashSet::new(), &mut pages, 0);
+        }
+        pages
+    }
+
+    fn collect_pages<'a>(
+        &'a self,
+        node: &'a Object,
+        resources: Option<&'a Dict>,
+        visited: &mut HashSet<u32>,
+        pages: &mut Vec<(&'a Dict, Option<&'a Dict>)>,
+        depth: usize,
+    ) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        if let Object::Ref(num) = node
+            && !visited.insert(*num)
+        {
+            return;
+        }
+        let Object::Dict(dict) = self.resolve(node) else {
+            return;
+        };
+        let resources = match self.get(dict, "Resources") {
+            Object::Dict(r) => Some(r),
+            _ => resources,
+        };
+        match self.get(dict, "Kids") {
+            Object::Array(kids) => {
+                for kid in kids {
+                    self.collect_pages(kid, resources, visited, pages, depth + 1);
+                }
+            }
+            _ => pages.push((dict, resources)),
+        }
+    }
+
+    fn outline(&self, parent: &Dict, visited: &mut HashSet<u32>, titles: &mut Vec<String>) {
+        let mut next = parent.get("First");
+        while let Some(item) = next {
+            if titles.len() >= 1000 || visited.len() > 1000 {
+                return;
+            }
+            if let Object::Ref(num) = item
+                && !visited.insert(*num)
+            {
+                return;
+            }
+            let Object::Dict(dict) = self.resolve(item) else {
+                return;
+            };
+            if let Some(title) = self.text_string(dict, "Title") {
+                titles.push(title);
+            }
+            self.outline(dict, visited, titles);
+            next = dict.get("Next");
+        }
+    }
+
+    fn fonts(&self, resources: Option<&Dict>) -> HashMap<String, Font> {
+        let mut fonts = HashMap::new();
+        let Some(Object::Dict(dict)) = resources.map(|r| self.get(r, "Font")) else {
+            return fonts;
+        };
+        for (name, font) in dict {
+            let Object::Dict(font) = self.resolve(font) else {
+                continue;
+            };
+            let composite = self.get(font, "Subtype").as_name() == Some("Type0");
+            let cmap = font
+                .get("ToUnicode")
+                .and_then(|obj| self.stream_data(obj))
+                .map(|data| CMap::parse(&data, if composite { 2 } else { 1 }));
+            fonts.insert(name.clone(), Font { cmap, composite });
+        }
+        fonts
+    }
+
+    /// Interprets the text operators of a content stream.
+    fn show_text(&self, data: &[u8], resources: Option<&Dict>, depth: usize, w: &mut TextWriter) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        let fonts = self.fonts(resources);
+        let mut font: Option<&Font> = None;
+        let mut operands: Vec<Object> = Vec::new();
+        let mut y = 0.0;
+        let mut lexer = Lexer::new(data, 0);
+        while let Some(obj) = lexer.parse_object() {
+            let Object::Operator(op) = obj else {
+                operands.push(obj);
+                continue;
+            };
+            let num = |i: usize| operands.get(i).and_then(|o| o.as_f64()).unwrap_or(0.0);
+            match op.as_str() {
+                "Tf" => {
+                    font = operands
+                        .first()
+                        .and_then(|o| o.as_name())
+                        .and_then(|n| fonts.get(n))
+                }
+                "Td" | "TD" => {
+                    let (tx, ty) = (num(0), num(1));
+                    if ty.abs() > 0.01 {
+                        y += ty;
+                        w.newline();
+                    } else if tx > 0.0 {
+                        w.space();
+                    }
+                }
+                "Tm" => {
+                    let f = num(5);
+                    if (f - y).abs() > 0.01 {
+                        w.newline();
+                    } else {
+                        w.space();
+                    }
+                    y = f;
+                }
+                "T*" => w.newline(),
+                "Tj" => {
+                    if let Some(Object::String(s)) = operands.first() {
+                        w.push(&decode_glyphs(font, s));
+                    }
+                }
+                "'" | "\"" => {
+                    w.newline();
+                    if let Some(Object::String(s)) = operands.last() {
+                        w.push(&decode_glyphs(font, s));
+                    }
+                }
+                "TJ" => {
+                    if let Some(Object::Array(items)) = operands.first() {
+                        for item in items {
+                            match item {
+                                Object::String(s) => w.push(&decode_glyphs(font, s)),
+                                // a large negative adjustment, in thousandths of an em, is a word gap
+                                Object::Number(n) if *n < -200.0 => w.space(),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                "ET" => w.space(),
+                "Do" => {
+                    if let Some(name) = operands.first().and_then(|o| o.as_name())
+                        && let Some(Object::Dict(xobjects)) =
+                            resources.map(|r| self.get(r, "XObject"))
+                        && let Object::Stream(form, _) = self.get(xobjects, name)
+                        && self.get(form, "Subtype").as_name() == Some("Form")
+                        && let Some(data) = self.stream_data(self.get(xobjects, name))
+                    {
+                        let form_resources = match self.get(form, "Resources") {
+                            Object::Dict(r) => Some(r),
+                            _ => resources,
+                        };
+                        w.newline();
+                        self.show_text(&data, form_resources, depth + 1, w);
+                    }
+                }
+                // the binary data of an inline image ends with "EI"
+                "ID" => lexer.skip_inline_image(),
+                _ => {}
+            }
+            operands.clear();
+        }
+    }
+}
+
+struct Font {
+    cmap: Option<CMap>,
+    composite: bool,
+}
+
+/// A `ToUnicode` map from character codes to text.
+struct CMap {
+    map: HashMap<u32, String>,
+    code_len: usize,
+}
+
+impl CMap {
+    fn parse(data: &[u8], default_code_len: usize) -> Self {
+        let mut map = HashMap::new();
+        let mut code_len = 0;
+        let mut lexer = Lexer::new(data, 0);
+        let mut values: Vec<Object> = Vec::new();
+        while let Some(obj) = lexer.parse_object() {
+            let Object::Operator(op) = obj else {
+                values.push(obj);
+                continue;
+            };
+            match op.as_str() {
+                "endcodespacerange" => {
+                    if let Some(Object::String(lo)) = values.first() {
+                        code_len = lo.len();
+                    }
+                }
+                "endbfchar" => {
+                    for pair in values.chunks_exact(2) {
+                        if let [Object::String(src), Object::String(dst)] = pair {
+                            map.insert(code(src), utf16_be(dst));
+                        }
+                    }
+                }
+                "endbfrange" => {
+                    for range in values.chunks_exact(3) {
+                        let [Object::String(lo), Object::String(hi), dst] = range else {
+                            continue;
+                        };
+                        let (lo, hi) = (code(lo), code(hi));
+                        for (i, c) in (lo..=hi.min(lo.saturating_add(0xFFFF))).enumerate() {
+                            let text = match dst {
+                                Object::String(dst) => {
+                                    let mut units: Vec<u16> = dst
+                                        .chunks(2)
+                                        .map(|c| {
+                                            u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])
+                                        })
+                                        .collect();
+                                    if let Some(last) = units.last_mut() {
+                                        *last = last.wrapping_add(i as u16);
+                                    }
+                                    String::from_utf16_lossy(&units)
+                                }
+                                Object::Array(items) => match items.get(i) {
+                                    Some(Object::String(s)) => utf16_be(s),
+                                    _ => continue,
+                                },
+                                _ => continue,
+                            };
+                            map.insert(c, text);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            values.clear();
+        }
+        CMap {
+            map,
+            code_len: if code_len == 0 {
+                default_code_len
+            } else {
+                code_len
+            },
+        }
+    }
+}
+
+fn code(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32)
+}
+
+fn utf16_be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Decodes the glyphs of a text string shown with the font.
+fn decode_glyphs(font: Option<&Font>, bytes: &[u8]) -> String {
+    match font {
+        Some(Font {
+            cmap: Some(cmap), ..
+        }) => bytes
+            .chunks(cmap.code_len)
+            .filter_map(|c| match cmap.map.get(&code(c)) {
+                Some(text) => Some(text.clone()),
+                None if cmap.code_len == 1 => Some(windows_1252_char(c[0]).to_string()),
+                None => None,
+            })
+            .collect(),
+        // the codes of composite fonts without a map are glyph IDs
+        Some(Font {
+            composite: true, ..
+        }) => String::new(),
+        _ => bytes.iter().map(|&b| windows_1252_char(b)).collect(),
+    }
+}
+
+/// Decodes a PDF text string: UTF-16BE or UTF-8 with a byte order mark, or
+/// PDFDocEncoding, which is approximated by Windows-1252.
+fn decode_text_string(bytes: &[u8]) -> String {
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        utf16_be(rest)
+    } else if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        String::from_utf8_lossy(rest).into_owned()
+    } else {
+        bytes.iter().map(|&b| windows_1252_char(b)).collect()
+    }
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+}
+
+impl TextWriter {
+    fn push(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\0' || (c.is_control() && c != '\n' && c != '\t') {
+                continue;
+            }
+            self.out.push(c);
+        }
+    }
+
+    fn space(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with(char::is_whitespace) {
+            self.out.push(' ');
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn finish(self) -> String {
+        self.out
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// A number, and whether it is an integer.
+    Number(f64, bool),
+    String(Vec<u8>),
+    Name(String),
+    Keyword(String),
+    ArrayStart,
+    ArrayEnd,
+    DictStart,
+    DictEnd,
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b'\0' | b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(
+        b,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+fn is_regular(b: u8) -> bool {
+    !is_whitespace(b) && !is_delimiter(b)
+}
+
+struct Lexer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if is_whitespace(b) {
+                self.pos += 1;
+            } else if b == b'%' {
+                while let Some(b) = self.peek() {
+                    if b == b'\n' || b == b'\r' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn regular(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(is_regular) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        self.skip_whitespace();
+        let b = self.peek()?;
+        match b {
+            b'(' => Some(Token::String(self.literal_string())),
+            b'<' if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                Some(Token::DictStart)
+            }
+            b'<' => {
+                self.pos += 1;
+                let end = self.data[self.pos..]
+                    .iter()
+                    .position(|&b| b == b'>')
+                    .map_or(self.data.len(), |i| self.pos + i);
+                let hex = hex_decode(&self.data[self.pos..end]);
+                self.pos = (end + 1).min(self.data.len());
+                Some(Token::String(hex))
+            }
+            b'>' if self.data.get(self.pos + 1) == Some(&b'>') => {
+                self.pos += 2;
+                Some(Token::DictEnd)
+            }
+            b'[' => {
+                self.pos += 1;
+                Some(Token::ArrayStart)
+            }
+            b']' => {
+                self.pos += 1;
+                Some(Token::ArrayEnd)
+            }
+            b'/' => {
+                self.pos += 1;
+                let raw = self.regular();
+                let mut name = Vec::with_capacity(raw.len());
+                let mut i = 0;
+                while i < raw.len() {
+                    if raw[i] == b'#'
+                        && let Some(b) = raw
+                            .get(i + 1..i + 3)
+                            .and_then(|h| std::str::from_utf8(h).ok())
+                            .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    {
+                        name.push(b);
+                        i += 3;
+                    } else {
+                        name.push(raw[i]);
+                        i += 1;
+                    }
+                }
+                Some(Token::Name(String::from_utf8_lossy(&name).into_owned()))
+            }
+            b'+' | b'-' | b'.' | b'0'..=b'9' => {
+                let raw = self.regular();
+                let text = std::str::from_utf8(raw).unwrap_or_default();
+                if raw
+                    .iter()
+                    .all(|b| b.is_ascii_digit() || matches!(b, b'+' | b'-' | b'.'))
+                {
+                    let number = text.parse::<f64>().unwrap_or(0.0);
+                    Some(Token::Number(number, !text.contains('.')))
+                } else {
+                    Some(Token::Keyword(text.to_string()))
+                }
+            }
+            b if is_regular(b) => {
+                let raw = self.regular();
+                Some(Token::Keyword(String::from_utf8_lossy(raw).into_owned()))
+            }
+            b => {
+                // a stray delimiter
+                self.pos += 1;
+                Some(Token::Keyword((b as char).to_string()))
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut depth = 0;
+        self.pos += 1;
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'(' => {
+                    depth += 1;
+                    out.push(b);
+                }
+                b')' if depth == 0 => break,
+                b')' => {
+                    depth -= 1;
+                    out.push(b);
+                }
+                b'\\' => {
+                    let Some(e) = self.peek() else {
+                        break;
+                    };
+                    self.pos += 1;
+                    match e {
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0C),
+                        b'0'..=b'7' => {
+                            let mut value = (e - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(d @ b'0'..=b'7') => {
+                                        value = value * 8 + (d - b'0') as u32;
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            out.push(value as u8);
+                        }
+                        // a line continuation
+                        b'\r' => {
+                            if self.peek() == Some(b'\n') {
+                                self.pos += 1;
+                            }
+                        }
+                        b'\n' => {}
+                        e => out.push(e),
+                    }
+                }
+                b => out.push(b),
+            }
+        }
+        out
+    }
+
+    /// Parses the next object. Keywords are returned as operators.
+    fn parse_object(&mut self) -> Option<Object> {
+        let token = self.next_token()?;
+        self.parse_token(token, 0)
+    }
+
+    fn parse_token(&mut self, token: Token, depth: usize) -> Option<Object> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        Some(match token {
+            Token::Number(n, true) if n >= 0.0 => {
+                // "num gen R" is a reference
+                let pos = self.pos;
+                if let Some(Token::Number(_, true)) = self.next_token()
+                    && self.next_token() == Some(Token::Keyword("R".to_string()))
+                {
+                    return Some(Object::Ref(n as u32));
+                }
+                self.pos = pos;
+                Object::Number(n)
+            }
+            Token::Number(n, _) => Object::Number(n),
+            Token::String(s) => Object::String(s),
+            Token::Name(n) => Object::Name(n),
+            Token::Keyword(k) => match k.as_str() {
+                "true" => Object::Bool(true),
+                "false" => Object::Bool(false),
+                "null" => Object::Null,
+                _ => Object::Operator(k),
+            },
+            Token::ArrayStart => {
+                let mut items = Vec::new();
+                loop {
+                    match self.next_token() {
+                        None | Some(Token::ArrayEnd) => break,
+                        Some(token) => items.extend(self.parse_token(token, depth + 1)),
+                    }
+                }
+                Object::Array(items)
+            }
+            Token::DictStart => {
+                let mut dict = Dict::new();
+                loop {
+                    match self.next_token() {
+                        None | Some(Token::DictEnd) => break,
+                        Some(Token::Name(key)) => {
+                            let Some(token) = self.next_token() else {
+                                break;
+                            };
+                            if token == Token::DictEnd {
+                                break;
+                            }
+                            if let Some(value) = self.parse_token(token, depth + 1) {
+                                dict.insert(key, value);
+                            }
+                        }
+                        Some(_) => {}
+                    }
+                }
+                Object::Dict(dict)
+            }
+            Token::ArrayEnd | Token::DictEnd => Object::Null,
+        })
+    }
+
+    /// Reads the raw data of a stream after its dictionary, if any.
+    fn stream_data(&mut self, dict: &Dict) -> Option<Vec<u8>> {
+        self.skip_whitespace();
+        if !self.data[self.pos..].starts_with(b"stream") {
+            return None;
+        }
+        let mut start = self.pos + 6;
+        if self.data.get(start) == Some(&b'\r') {
+            start += 1;
+        }
+        if self.data.get(start) == Some(&b'\n') {
+            start += 1;
+        }
+
+        // the length may be an indirect object, which is not parsed yet
+        if let Some(Object::Number(len)) = dict.get("Length") {
+            let end = start + *len as usize;
+            if end <= self.data.len() {
+                let mut after = Lexer::new(self.data, end);
+                after.skip_whitespace();
+                if self.data[after.pos..].starts_with(b"endstream") {
+                    self.pos = after.pos + 9;
+                    return Some(self.data[start..end].to_vec());
+                }
+            }
+        }
+        let end = find(self.data, b"endstream", start)?;
+        self.pos = end + 9;
+        let mut data = &self.data[start..end];
+        if let Some(d) = data.strip_suffix(b"\n") {
+            data = d;
+        }
+        if let Some(d) = data.strip_suffix(b"\r") {
+            data = d;
+        }
+        Some(data.to_vec())
+    }
+
+    fn skip_inline_image(&mut self) {
+        let mut pos = self.pos;
+        while let Some(k) = find(self.data, b"EI", pos) {
+            let before = k == 0 || is_whitespace(self.data[k - 1]);
+            let after = self.data.get(k + 2).is_none_or(|&b| is_whitespace(b));
+            if before && after {
+                self.pos = k + 2;
+                return;
+            }
+            pos = k + 2;
+        }
+        self.pos = self.data.len();
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from >= haystack.len() {
+        return None;
+    }
+    haystack[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| from + i)
+}
+
+/// Returns the object number of an "num gen obj" header whose "obj" is at `k`.
+fn object_number(data: &[u8], k: usize) -> Option<u32> {
+    if data.get(k + 3).is_some_and(|&b| is_regular(b)) {
+        return None;
+    }
+    let mut i = k;
+    let skip_whitespace = |i: &mut usize| -> bool {
+        let start = *i;
+        while *i > 0 && is_whitespace(data[*i - 1]) {
+            *i -= 1;
+        }
+        *i < start
+    };
+    let skip_digits = |i: &mut usize| -> usize {
+        let end = *i;
+        while *i > 0 && data[*i - 1].is_ascii_digit() {
+            *i -= 1;
+        }
+        end - *i
+    };
+    if !skip_whitespace(&mut i) || skip_digits(&mut i) == 0 || !skip_whitespace(&mut i) {
+        return None;
+    }
+    let end = i;
+    if skip_digits(&mut i) == 0 || (i > 0 && is_regular(data[i - 1])) {
+        return None;
+    }
+    std::str::from_utf8(&data[i..end]).ok()?.parse().ok()
+}
+
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    match ZlibDecoder::new(data).read_to_end(&mut out) {
+        Ok(_) => Some(out),
+        // keeps the data of a truncated stream
+        Err(_) if !out.is_empty() => Some(out),
+        Err(_) => None,
+    }
+}
+
+fn hex_decode(data: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = data
+        .iter()
+        .take_while(|&&b| b != b'>')
+        .filter_map(|&b| (b as char).to_digit(16).map(|d| d as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|c| (c[0] << 4) | c.get(1).copied().unwrap_or(0))
+        .collect()
+}
+
+fn ascii85_decode(data: &[u8]) -> Vec<u8> {
+    let data = data.strip_prefix(b"<~").unwrap_or(data);
+    let mut out = Vec::with_capacity(data.len() * 4 / 5);
+    let mut group = [0u32; 5];
+    let mut n = 0;
+    for &b in data {
+        match b {
+            b'~' => break,
+            b'z' if n == 0 => out.extend_from_slice(&[0; 4]),
+            b'!'..=b'u' => {
+                group[n] = (b - b'!') as u32;
+                n += 1;
+                if n == 5 {
+                    let value = group
+                        .iter()
+                        .fold(0u32, |acc, &d| acc.wrapping_mul(85).wrapping_add(d));
+                    out.extend_from_slice(&value.to_be_bytes());
+                    n = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+    if n > 1 {
+        for d in group.iter_mut().skip(n) {
+            *d = 84;
+        }
+        let value = group
+            .iter()
+            .fold(0u32, |acc, &d| acc.wrapping_mul(85).wrapping_add(d));
+        out.extend_from_slice(&value.to_be_bytes()[..n - 1]);
+    }
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flate2::{Compression, write::ZlibEncoder};
+    use std::io::Write;
+
+    /// Builds a PDF file from objects numbered from 1, with a cross-reference table.
+    pub(crate) fn build_pdf(objects: &[Vec<u8>], root: u32, info: Option<u32>) -> Vec<u8> {
+        let mut pdf = b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, obj) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            pdf.extend_from_slice(obj);
+            pdf.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(
+            format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+        );
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+        }
+        let info = info.map(|i| format!(" /Info {i} 0 R")).unwrap_or_default();
+        pdf.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root {root} 0 R{info} >>\nstartxref\n{xref}\n%%EOF\n",
+                objects.len() + 1
+            )
+            .as_bytes(),
+        );
+        pdf
+    }
+
+    pub(crate) fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+        let mut obj = format!("<< {dict} /Length {} >>\nstream\n", data.len()).into_bytes();
+        obj.extend_from_slice(data);
+        obj.extend_from_slice(b"\nendstream");
+        obj
+    }
+
+    pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_extract_pdf() {
+        let page1 = b"BT /F1 24 Tf 72 720 Td (Hello, \\(PDF\\) world!) Tj 0 -30 Td [(Se) 20 (cond) -300 (line)] TJ T* (caf\\351) Tj ET";
+        let page2 = b"BT /F2 12 Tf 1 0 0 1 72 700 Tm <00010002> Tj 1 0 0 1 72 680 Tm <0003> Tj ET\nq /Fm1 Do Q";
+        let cmap = b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap
+1 begincodespacerange <0000> <FFFF> endcodespacerange
+2 beginbfchar <0001> <4E2D> <0002> <6587> endbfchar
+1 beginbfrange <0003> <0003> <0041> endbfrange
+endcmap CMapName currentdict /CMap defineresource pop end end";
+        let objects = vec![
+            // 1: catalog
+            b"<< /Type /Catalog /Pages 2 0 R /Outlines 9 0 R >>".to_vec(),
+            // 2: page tree with inherited resources
+            b"<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 /Resources << /Font << /F1 5 0 R /F2 6 0 R >> >> >>".to_vec(),
+            // 3, 4: pages
+            b"<< /Type /Page /Parent 2 0 R /Contents 7 0 R >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /Contents [8 0 R] /Resources << /Font << /F2 6 0 R >> /XObject << /Fm1 12 0 R >> >> >>".to_vec(),
+            // 5, 6: fonts
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_vec(),
+            b"<< /Type /Font /Subtype /Type0 /BaseFont /SimSun /ToUnicode 11 0 R >>".to_vec(),
+            // 7, 8: contents, the second one compressed
+            stream("", page1),
+            stream("/Filter /FlateDecode", &deflate(page2)),
+            // 9, 10: outlines
+            b"<< /Type /Outlines /First 10 0 R /Count 1 >>".to_vec(),
+            b"<< /Title <FEFF0049006E00740072006F> /Parent 9 0 R >>".to_vec(),
+            // 11: ToUnicode map
+            stream("", cmap),
+            // 12: form XObject
+            stream(
+                "/Type /XObject /Subtype /Form /Resources << /Font << /F1 5 0 R >> >>",
+                b"BT /F1 10 Tf 0 0 Td (Footer) Tj ET",
+            ),
+            // 13: document information
+            b"<< /Title (Test \\(1\\)) /Author (Anda) /Producer (hand) >>".to_vec(),
+        ];
+        let data = build_pdf(&objects, 1, Some(13));
+
+        let content = extract_pdf(&data).unwrap();
+        assert_eq!(content.title.as_deref(), Some("Test (1)"));
+        assert_eq!(content.author.as_deref(), Some("Anda"));
+        assert_eq!(content.headings, vec!["Intro"]);
+        assert_eq!(
+            content.pages,
+            vec![
+                "Hello, (PDF) world!\nSecond line\ncafé".to_string(),
+                "中文\nA\nFooter".to_string()
+            ]
+        );
+        assert_eq!(
+            content.text(),
+            format!("{}\n\n{}", content.pages[0], content.pages[1])
+        );
+
+        assert!(extract_pdf(b"<html></html>").is_err());
+        let encrypted = String::from_utf8_lossy(&data).replace("/Size", "/Encrypt 99 0 R /Size");
+        assert!(
+            extract_pdf(encrypted.as_bytes())
+                .unwrap_err()
+                .to_string()
+                .contains("encrypted")
+        );
+    }
+
+    #[test]
+    fn test_object_stream() {
+        // the objects 4 and 5 are compressed in the object stream 2
+        let header = "4 0 5 50 ";
+        let body = format!(
+            "{:<50}{}",
+            "<< /Type /Pages /Kids [5 0 R] /Count 1 >>", "<< /Type /Page /Contents 3 0 R >>"
+        );
+        let objstm = format!("{header}{body}");
+        let objects = vec![
+            b"<< /Type /Catalog /Pages 4 0 R >>".to_vec(),
+            stream(
+                &format!(
+                    "/Type /ObjStm /N 2 /First {} /Filter [/FlateDecode]",
+                    header.len()
+                ),
+                &deflate(objstm.as_bytes()),
+            ),
+            stream(
+                "/Filter /ASCII85Decode",
+                b"<~6<#'U87cURD]iY4Df$U_;aX,J3&N--<,*OE;u~>",
+            ),
+        ];
+        let data = build_pdf(&objects, 1, None);
+
+        let content = extract_pdf(&data).unwrap();
+        assert_eq!(content.pages, vec!["Hello from ASCII85".to_string()]);
+        assert_eq!(content.title, None);
+    }
+}
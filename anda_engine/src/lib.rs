@@ -1,11 +1,23 @@
 use rand::Rng;
 
+pub mod analytics;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
 pub mod context;
 pub mod engine;
 pub mod extension;
+pub mod fleet;
+pub mod id;
+pub mod ingest;
 pub mod management;
+pub mod memory;
 pub mod model;
+pub mod mtls;
+pub mod redaction;
+pub mod render;
+pub mod rollout;
 pub mod store;
+pub mod telemetry;
 
 /// Gets current unix timestamp in milliseconds
 pub use structured_logger::unix_ms;
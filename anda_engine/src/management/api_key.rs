@@ -0,0 +1,355 @@
+//! API keys for third-party callers.
+//!
+//! Operators create [`ApiKey`]s for external developers, scoped to the agents and tools
+//! they may call, with optional per-minute rate limits and daily quotas. A key is shown
+//! only once, when it is created or rotated: the store keeps its SHA3-256 digest. Each key
+//! calls the engine as its own principal, see [`ApiKey::principal_of`].
+
+use anda_core::{BoxError, ByteArrayB64, Xid};
+use candid::Principal;
+use ic_cose::rand_bytes;
+use ic_cose_types::cose::sha3_256;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, str::FromStr};
+
+use crate::id::new_xid;
+
+const MINUTE_MS: u64 = 60 * 1000;
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// The prefix of API key secrets.
+pub static API_KEY_PREFIX: &str = "ak_";
+
+/// The agents and tools an API key can call, by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKeyScope {
+    #[serde(default)]
+    pub agents: BTreeSet<String>,
+
+    #[serde(default)]
+    pub tools: BTreeSet<String>,
+}
+
+impl ApiKeyScope {
+    /// Adds an agent to the scope.
+    pub fn with_agent(mut self, name: &str) -> Self {
+        self.agents.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Adds a tool to the scope.
+    pub fn with_tool(mut self, name: &str) -> Self {
+        self.tools.insert(name.to_ascii_lowercase());
+        self
+    }
+}
+
+/// The limits of an API key. `None` means unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKeyLimits {
+    /// The maximum number of calls per minute.
+    #[serde(default)]
+    pub rate_per_minute: Option<u64>,
+
+    /// The maximum number of calls per day (UTC).
+    #[serde(default)]
+    pub daily_quota: Option<u64>,
+}
+
+/// An API key, without its secret.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: Xid,
+
+    /// A label for operators, e.g. the developer or the application.
+    pub name: String,
+
+    pub scope: ApiKeyScope,
+
+    pub limits: ApiKeyLimits,
+
+    /// The SHA3-256 digest of the current secret.
+    pub secret_hash: ByteArrayB64<32>,
+
+    /// The manager who created the key.
+    pub created_by: Principal,
+
+    pub created_at: u64,
+
+    /// When the secret was last rotated, unix timestamp in milliseconds.
+    #[serde(default)]
+    pub rotated_at: Option<u64>,
+
+    /// When the key expires, unix timestamp in milliseconds.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+
+    /// When the key was revoked, unix timestamp in milliseconds.
+    #[serde(default)]
+    pub revoked_at: Option<u64>,
+}
+
+/// The arguments to create an API key.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateApiKeyArgs {
+    pub name: String,
+    pub scope: ApiKeyScope,
+    #[serde(default)]
+    pub limits: ApiKeyLimits,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+/// The target of a call authorized by an API key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiKeyTarget<'a> {
+    Agent(&'a str),
+    Tool(&'a str),
+    /// Reading the remaining quota of the key, allowed by any key.
+    Quota,
+}
+
+impl ApiKey {
+    /// Creates a key and returns it with its secret.
+    pub fn new(args: CreateApiKeyArgs, created_by: Principal, now_ms: u64) -> (Self, String) {
+        let id = new_xid();
+        let secret = Self::new_secret(&id);
+        let key = Self {
+            id,
+            name: args.name,
+            scope: ApiKeyScope {
+                agents: args
+                    .scope
+                    .agents
+                    .iter()
+                    .map(|n| n.to_ascii_lowercase())
+                    .collect(),
+                tools: args
+                    .scope
+                    .tools
+                    .iter()
+                    .map(|n| n.to_ascii_lowercase())
+                    .collect(),
+            },
+            limits: args.limits,
+            secret_hash: sha3_256(secret.as_bytes()).into(),
+            created_by,
+            created_at: now_ms,
+            rotated_at: None,
+            expires_at: args.expires_at,
+            revoked_at: None,
+        };
+        (key, secret)
+    }
+
+    /// Returns the principal an API key calls the engine as.
+    pub fn principal_of(id: &Xid) -> Principal {
+        Principal::self_authenticating(format!("apikey:{}", id))
+    }
+
+    /// Returns the ID of the key from its secret, without verifying it.
+    pub fn id_of(secret: &str) -> Result<Xid, BoxError> {
+        let id = secret
+            .strip_prefix(API_KEY_PREFIX)
+            .and_then(|s| s.split_once('_'))
+            .map(|(id, _)| id)
+            .ok_or("invalid API key")?;
+        Xid::from_str(id).map_err(|_| "invalid API key".into())
+    }
+
+    /// Replaces the secret of the key and returns the new one.
+    pub fn rotate(&mut self, now_ms: u64) -> String {
+        let secret = Self::new_secret(&self.id);
+        self.secret_hash = sha3_256(secret.as_bytes()).into();
+        self.rotated_at = Some(now_ms);
+        secret
+    }
+
+    /// Checks the secret, the expiry, the revocation and the scope of the key.
+    pub fn verify(&self, secret: &str, target: ApiKeyTarget, now_ms: u64) -> Result<(), BoxError> {
+        if *self.secret_hash != sha3_256(secret.as_bytes()) {
+            return Err("invalid API key".into());
+        }
+        if self.revoked_at.is_some() {
+            return Err(format!("API key {} is revoked", self.id).into());
+        }
+        if self.expires_at.is_some_and(|t| t <= now_ms) {
+            return Err(format!("API key {} is expired", self.id).into());
+        }
+        let allowed = match target {
+            ApiKeyTarget::Agent(name) => self.scope.agents.contains(name),
+            ApiKeyTarget::Tool(name) => self.scope.tools.contains(name),
+            ApiKeyTarget::Quota => true,
+        };
+        if !allowed {
+            return Err(format!("API key {} is not allowed to call {:?}", self.id, target).into());
+        }
+        Ok(())
+    }
+
+    fn new_secret(id: &Xid) -> String {
+        let random: String = rand_bytes::<24>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        format!("{}{}_{}", API_KEY_PREFIX, id, random)
+    }
+}
+
+/// The usage of an API key in the current minute and day (UTC).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKeyUsage {
+    /// The minute, in minutes since the unix epoch.
+    pub minute: u64,
+    pub minute_calls: u64,
+
+    /// The day, in days since the unix epoch.
+    pub day: u64,
+    pub day_calls: u64,
+
+    /// The total number of calls.
+    pub total_calls: u64,
+
+    #[serde(default)]
+    pub last_used_at: Option<u64>,
+}
+
+impl ApiKeyUsage {
+    /// Resets the counters of the previous minute and day.
+    pub fn refresh(&mut self, now_ms: u64) {
+        let minute = now_ms / MINUTE_MS;
+        if self.minute != minute {
+            self.minute = minute;
+            self.minute_calls = 0;
+        }
+        let day = now_ms / DAY_MS;
+        if self.day != day {
+            self.day = day;
+            self.day_calls = 0;
+        }
+    }
+
+    /// Records a call if it fits in the limits, or returns an error without recording it.
+    pub fn consume(&mut self, limits: &ApiKeyLimits, now_ms: u64) -> Result<(), BoxError> {
+        self.refresh(now_ms);
+        if let Some(max) = limits.rate_per_minute
+            && self.minute_calls >= max
+        {
+            return Err(format!("rate limit exceeded: {} calls per minute", max).into());
+        }
+        if let Some(max) = limits.daily_quota
+            && self.day_calls >= max
+        {
+            return Err(format!("daily quota exceeded: {} calls", max).into());
+        }
+        self.minute_calls += 1;
+        self.day_calls += 1;
+        self.total_calls += 1;
+        self.last_used_at = Some(now_ms);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::EngineBuilder,
+        management::{ManagementBuilder, Visibility},
+    };
+    use structured_logger::unix_ms;
+
+    #[test]
+    fn test_api_key_usage() {
+        let limits = ApiKeyLimits {
+            rate_per_minute: Some(2),
+            daily_quota: Some(3),
+        };
+        let now_ms = 100 * DAY_MS;
+        let mut usage = ApiKeyUsage::default();
+        usage.consume(&limits, now_ms).unwrap();
+        usage.consume(&limits, now_ms + 1).unwrap();
+        assert!(usage.consume(&limits, now_ms + 2).is_err());
+        usage.consume(&limits, now_ms + MINUTE_MS).unwrap();
+        assert!(usage.consume(&limits, now_ms + 2 * MINUTE_MS).is_err());
+        usage.consume(&limits, now_ms + DAY_MS).unwrap();
+        assert_eq!(usage.total_calls, 4);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_api_keys() {
+        let ctx = EngineBuilder::new().mock_ctx();
+        let management =
+            ManagementBuilder::new(Visibility::Private, Principal::anonymous()).build(&ctx.base);
+
+        let (key, secret) = management
+            .create_api_key(
+                Principal::anonymous(),
+                CreateApiKeyArgs {
+                    name: "partner".to_string(),
+                    scope: ApiKeyScope::default().with_agent("Assistant"),
+                    limits: ApiKeyLimits {
+                        rate_per_minute: None,
+                        daily_quota: Some(1),
+                    },
+                    expires_at: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert!(secret.starts_with(API_KEY_PREFIX));
+        assert_eq!(ApiKey::id_of(&secret).unwrap(), key.id);
+
+        let principal = management
+            .authorize_api_key(&secret, ApiKeyTarget::Agent("assistant"))
+            .await
+            .unwrap();
+        assert_eq!(principal, ApiKey::principal_of(&key.id));
+        assert!(
+            management
+                .authorize_api_key(&secret, ApiKeyTarget::Agent("assistant"))
+                .await
+                .is_err()
+        );
+        assert!(
+            management
+                .authorize_api_key(&secret, ApiKeyTarget::Tool("assistant"))
+                .await
+                .is_err()
+        );
+        assert_eq!(management.get_api_key_usage(&key.id).await.day_calls, 1);
+        assert_eq!(
+            management
+                .verify_api_key(&secret, ApiKeyTarget::Quota)
+                .await
+                .unwrap(),
+            principal
+        );
+
+        let rotated = management.rotate_api_key(&key.id).await.unwrap();
+        assert_ne!(rotated, secret);
+        let err = management
+            .authorize_api_key(&secret, ApiKeyTarget::Agent("assistant"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid API key");
+        assert!(
+            management
+                .verify_api_key(&secret, ApiKeyTarget::Quota)
+                .await
+                .is_err()
+        );
+
+        let key = management.revoke_api_key(&key.id).await.unwrap();
+        assert!(key.revoked_at.is_some_and(|t| t <= unix_ms()));
+        assert!(
+            management
+                .authorize_api_key(&rotated, ApiKeyTarget::Agent("assistant"))
+                .await
+                .unwrap_err()
+                .to_string()
+                .contains("revoked")
+        );
+        assert_eq!(management.list_api_keys().await.unwrap().len(), 1);
+    }
+}
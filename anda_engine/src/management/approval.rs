@@ -0,0 +1,254 @@
+//! Multi-operator approval of high-risk tool calls.
+//!
+//! Tools listed in an [`ApprovalPolicy`] (e.g. treasury transfers) are not executed when
+//! called. The call is stored as a [`PendingToolCall`] and the caller gets its approval ID
+//! back. Operators sign [`PendingToolCall::message`], the SHA3-256 digest of the canonical
+//! CBOR of the call, with their Ed25519 keys; once `threshold` distinct operators have
+//! signed, the engine executes the call. Calls that are not approved before their expiry
+//! are discarded.
+
+use anda_core::{BoxError, ByteArrayB64, Migrate, Resource, ToolOutput, Xid};
+use candid::Principal;
+use ic_cose_types::{
+    cose::{ed25519::ed25519_verify, sha3_256},
+    to_cbor_bytes,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeSet, time::Duration};
+use structured_logger::unix_ms;
+
+use crate::id::new_xid;
+
+/// The tools that require approval and the operators who can approve them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalPolicy {
+    /// The names of the tools that require approval.
+    pub tools: BTreeSet<String>,
+
+    /// The Ed25519 public keys of the operators.
+    pub operators: BTreeSet<ByteArrayB64<32>>,
+
+    /// The number of operator signatures required to execute a call.
+    pub threshold: usize,
+
+    /// How long a call waits for approval, in milliseconds.
+    pub ttl_ms: u64,
+}
+
+impl ApprovalPolicy {
+    /// Creates a t-of-n policy with the operators' public keys.
+    pub fn new(operators: BTreeSet<[u8; 32]>, threshold: usize, ttl: Duration) -> Self {
+        Self {
+            tools: BTreeSet::new(),
+            operators: operators.into_iter().map(ByteArrayB64::from).collect(),
+            threshold,
+            ttl_ms: ttl.as_millis() as u64,
+        }
+    }
+
+    /// Adds a tool that requires approval.
+    pub fn with_tool(mut self, name: &str) -> Self {
+        self.tools.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Returns true if calls to the tool require approval.
+    pub fn requires_approval(&self, tool: &str) -> bool {
+        self.tools.contains(tool)
+    }
+
+    /// Checks that the threshold can be met by the operators.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        if self.threshold == 0 || self.threshold > self.operators.len() {
+            return Err(format!(
+                "invalid approval threshold {} for {} operators",
+                self.threshold,
+                self.operators.len()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// An operator's signature of a pending call.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Approval {
+    /// The Ed25519 public key of the operator.
+    pub public_key: ByteArrayB64<32>,
+
+    /// The Ed25519 signature over [`PendingToolCall::message`].
+    pub signature: ByteArrayB64<64>,
+
+    /// The unix timestamp in milliseconds.
+    pub approved_at: u64,
+}
+
+/// A tool call waiting for operator approval.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingToolCall {
+    /// The approval ID.
+    pub id: Xid,
+
+    /// The tool name.
+    pub tool: String,
+
+    /// The arguments of the call as a JSON string.
+    pub args: String,
+
+    /// The resources of the call.
+    pub resources: Option<Vec<Resource>>,
+
+    /// The caller of the tool.
+    pub caller: Principal,
+
+    /// The unix timestamp in milliseconds.
+    pub created_at: u64,
+
+    /// The call is discarded if not approved by this time, in unix milliseconds.
+    pub expires_at: u64,
+
+    /// The signatures collected so far.
+    pub approvals: Vec<Approval>,
+}
+
+impl Migrate for PendingToolCall {
+    const VERSION: u32 = 1;
+}
+
+impl PendingToolCall {
+    /// Creates a pending call that expires after the policy's TTL.
+    pub fn new(
+        policy: &ApprovalPolicy,
+        caller: Principal,
+        tool: String,
+        args: String,
+        resources: Option<Vec<Resource>>,
+    ) -> Self {
+        let now = unix_ms();
+        Self {
+            id: new_xid(),
+            tool,
+            args,
+            resources,
+            caller,
+            created_at: now,
+            expires_at: now + policy.ttl_ms,
+            approvals: Vec::new(),
+        }
+    }
+
+    /// Returns the message to be signed by the operators: the SHA3-256 digest of the CBOR of
+    /// `(id, tool, args, resources, caller, expires_at)`.
+    pub fn message(&self) -> [u8; 32] {
+        sha3_256(&to_cbor_bytes(&(
+            &self.id,
+            &self.tool,
+            &self.args,
+            &self.resources,
+            &self.caller,
+            self.expires_at,
+        )))
+    }
+
+    /// Returns true if the call has expired.
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.expires_at <= now_ms
+    }
+
+    /// Verifies and records an operator's signature.
+    /// Returns true if the call has reached the policy's threshold.
+    pub fn approve(
+        &mut self,
+        policy: &ApprovalPolicy,
+        public_key: [u8; 32],
+        signature: [u8; 64],
+        now_ms: u64,
+    ) -> Result<bool, BoxError> {
+        if self.is_expired(now_ms) {
+            return Err(format!("tool call {} has expired", self.id).into());
+        }
+
+        let public_key = ByteArrayB64::from(public_key);
+        if !policy.operators.contains(&public_key) {
+            return Err("public key is not an operator".into());
+        }
+
+        ed25519_verify(&public_key, &self.message(), &signature)?;
+        if !self.approvals.iter().any(|a| a.public_key == public_key) {
+            self.approvals.push(Approval {
+                public_key,
+                signature: signature.into(),
+                approved_at: now_ms,
+            });
+        }
+        Ok(self.approvals.len() >= policy.threshold)
+    }
+
+    /// Returns the output given to the caller while the call waits for approval.
+    pub fn pending_output(&self) -> ToolOutput<serde_json::Value> {
+        ToolOutput::new(json!({
+            "status": "pending_approval",
+            "approval_id": self.id.to_string(),
+            "expires_at": self.expires_at,
+            "message": format!(
+                "The call to {} requires operator approval and will be executed once approved.",
+                self.tool
+            ),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_consensus::SigningKey;
+
+    #[test]
+    fn test_pending_tool_call() {
+        let keys: Vec<SigningKey> = (0..3u8).map(|i| SigningKey::from([i; 32])).collect();
+        let policy = ApprovalPolicy::new(
+            keys.iter()
+                .map(|k| k.verification_key().to_bytes())
+                .collect(),
+            2,
+            Duration::from_secs(60),
+        )
+        .with_tool("Treasury_Transfer");
+        policy.validate().unwrap();
+        assert!(policy.requires_approval("treasury_transfer"));
+        assert!(!policy.requires_approval("other"));
+
+        let mut call = PendingToolCall::new(
+            &policy,
+            Principal::anonymous(),
+            "treasury_transfer".to_string(),
+            r#"{"amount":100}"#.to_string(),
+            None,
+        );
+        let now = unix_ms();
+        let msg = call.message();
+        let sign = |k: &SigningKey| (k.verification_key().to_bytes(), k.sign(&msg).to_bytes());
+
+        let (pk, _) = sign(&keys[0]);
+        assert!(call.approve(&policy, pk, [0u8; 64], now).is_err());
+        let outsider = SigningKey::from([9u8; 32]);
+        let (pk, sig) = sign(&outsider);
+        assert!(call.approve(&policy, pk, sig, now).is_err());
+
+        let (pk, sig) = sign(&keys[0]);
+        assert!(!call.approve(&policy, pk, sig, now).unwrap());
+        // the same operator can not approve twice
+        assert!(!call.approve(&policy, pk, sig, now).unwrap());
+        assert_eq!(call.approvals.len(), 1);
+
+        let (pk, sig) = sign(&keys[2]);
+        assert!(call.approve(&policy, pk, sig, call.expires_at).is_err());
+        assert!(call.approve(&policy, pk, sig, now).unwrap());
+
+        let mut other = call.clone();
+        other.args = r#"{"amount":1000}"#.to_string();
+        assert_ne!(other.message(), msg);
+    }
+}
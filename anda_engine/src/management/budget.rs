@@ -0,0 +1,210 @@
+use anda_core::BoxError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// The daily limits of a side-effecting tool for an agent.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ActionBudget {
+    /// The maximum number of calls per day, e.g. max posts or max emails.
+    #[serde(default)]
+    pub max_calls: Option<u64>,
+
+    /// The maximum total value per day, e.g. the total transfer amount.
+    #[serde(default)]
+    pub max_value: Option<f64>,
+
+    /// The argument holding the value of a call, e.g. "amount".
+    /// It can be a number or a numeric string.
+    #[serde(default)]
+    pub value_field: Option<String>,
+}
+
+impl ActionBudget {
+    /// Returns the value of a call from its JSON arguments.
+    /// The value must be a finite, non-negative number.
+    pub fn value_of(&self, args: &str) -> Result<f64, BoxError> {
+        let field = match &self.value_field {
+            Some(field) => field,
+            None => return Ok(0.0),
+        };
+        let args: Value = serde_json::from_str(args)?;
+        let value = match args.get(field) {
+            Some(Value::Number(n)) => n
+                .as_f64()
+                .ok_or_else(|| format!("invalid value of {:?}", field))?,
+            Some(Value::String(s)) => s
+                .trim()
+                .parse::<f64>()
+                .map_err(|err| format!("invalid value of {:?}: {}", field, err))?,
+            _ => return Err(format!("missing value of {:?}", field).into()),
+        };
+        check_value(value).map_err(|err| format!("invalid value of {:?}: {}", field, err))?;
+        Ok(value)
+    }
+}
+
+fn check_value(value: f64) -> Result<(), String> {
+    if !value.is_finite() || value < 0.0 {
+        return Err(format!("{} is not a finite non-negative number", value));
+    }
+    Ok(())
+}
+
+/// Daily action budgets of agents, by agent name and tool name.
+///
+/// # Example
+/// ```toml
+/// [budgets.twitter_bot.post_tweet]
+/// max_calls = 50
+///
+/// [budgets.treasurer.icp_ledger_transfer]
+/// max_calls = 10
+/// max_value = 100.0
+/// value_field = "amount"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BudgetPolicy {
+    pub budgets: BTreeMap<String, BTreeMap<String, ActionBudget>>,
+}
+
+impl BudgetPolicy {
+    /// Creates a BudgetPolicy from a TOML string.
+    pub fn from_toml(content: &str) -> Result<Self, BoxError> {
+        let policy: Self = toml::from_str(content)?;
+        Ok(policy)
+    }
+
+    /// Sets the daily budget of a tool for an agent.
+    pub fn with_budget(mut self, agent: &str, tool: &str, budget: ActionBudget) -> Self {
+        self.budgets
+            .entry(agent.to_ascii_lowercase())
+            .or_default()
+            .insert(tool.to_ascii_lowercase(), budget);
+        self
+    }
+
+    /// Returns the budget of the tool for the agent, if any.
+    pub fn get(&self, agent: &str, tool: &str) -> Option<&ActionBudget> {
+        self.budgets.get(agent).and_then(|tools| tools.get(tool))
+    }
+}
+
+/// The usage of a tool in a day.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ActionUsage {
+    pub calls: u64,
+    pub value: f64,
+}
+
+/// The usage of an agent's budgets in a day (UTC).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BudgetUsage {
+    /// The day, in days since the unix epoch.
+    pub day: u64,
+
+    /// The usage by tool name.
+    pub tools: BTreeMap<String, ActionUsage>,
+}
+
+impl BudgetUsage {
+    /// Resets the usage if it is from a previous day.
+    pub fn refresh(&mut self, now_ms: u64) {
+        let day = now_ms / DAY_MS;
+        if self.day != day {
+            self.day = day;
+            self.tools.clear();
+        }
+    }
+
+    /// Records a call if it fits in the budget, or returns an error without recording it.
+    pub fn consume(
+        &mut self,
+        tool: &str,
+        budget: &ActionBudget,
+        value: f64,
+        now_ms: u64,
+    ) -> Result<(), BoxError> {
+        check_value(value).map_err(|err| format!("invalid value of {}: {}", tool, err))?;
+        self.refresh(now_ms);
+        let usage = self.tools.entry(tool.to_string()).or_default();
+        if let Some(max) = budget.max_calls
+            && usage.calls >= max
+        {
+            return Err(format!("daily budget of {} exceeded: {} calls", tool, max).into());
+        }
+        if let Some(max) = budget.max_value
+            && usage.value + value > max
+        {
+            return Err(format!(
+                "daily budget of {} exceeded: {} + {} > {}",
+                tool, usage.value, value, max
+            )
+            .into());
+        }
+        usage.calls += 1;
+        usage.value += value;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_usage() {
+        let policy = BudgetPolicy::from_toml(
+            r#"
+            [budgets.twitter_bot.post_tweet]
+            max_calls = 2
+
+            [budgets.treasurer.transfer]
+            max_value = 100.0
+            value_field = "amount"
+            "#,
+        )
+        .unwrap();
+        assert!(policy.get("twitter_bot", "transfer").is_none());
+
+        let now = 10 * DAY_MS + 1;
+        let budget = policy.get("twitter_bot", "post_tweet").unwrap();
+        let mut usage = BudgetUsage::default();
+        usage.consume("post_tweet", budget, 0.0, now).unwrap();
+        usage.consume("post_tweet", budget, 0.0, now).unwrap();
+        assert!(usage.consume("post_tweet", budget, 0.0, now).is_err());
+        assert_eq!(usage.tools["post_tweet"].calls, 2);
+        // a new day
+        usage
+            .consume("post_tweet", budget, 0.0, now + DAY_MS)
+            .unwrap();
+        assert_eq!(usage.day, 11);
+        assert_eq!(usage.tools["post_tweet"].calls, 1);
+
+        let budget = policy.get("treasurer", "transfer").unwrap();
+        assert_eq!(budget.value_of(r#"{"amount": 60}"#).unwrap(), 60.0);
+        assert_eq!(budget.value_of(r#"{"amount": "40.5"}"#).unwrap(), 40.5);
+        assert!(budget.value_of(r#"{"to": "alice"}"#).is_err());
+        assert!(budget.value_of(r#"{"amount": -1000}"#).is_err());
+        assert!(budget.value_of(r#"{"amount": "-0.5"}"#).is_err());
+        assert!(budget.value_of(r#"{"amount": "NaN"}"#).is_err());
+        assert!(budget.value_of(r#"{"amount": "inf"}"#).is_err());
+        assert!(budget.value_of(r#"{"amount": 1e400}"#).is_err());
+
+        let mut usage = BudgetUsage::default();
+        usage.consume("transfer", budget, 60.0, now).unwrap();
+        assert!(usage.consume("transfer", budget, 40.5, now).is_err());
+        assert!(usage.consume("transfer", budget, -1000.0, now).is_err());
+        assert!(usage.consume("transfer", budget, f64::NAN, now).is_err());
+        assert!(
+            usage
+                .consume("transfer", budget, f64::INFINITY, now)
+                .is_err()
+        );
+        usage.consume("transfer", budget, 40.0, now).unwrap();
+        assert_eq!(usage.tools["transfer"].value, 100.0);
+        assert_eq!(usage.tools["transfer"].calls, 2);
+    }
+}
@@ -0,0 +1,40 @@
+use anda_core::{BoxError, CacheStoreFeatures, UpdateVersion, Versioned};
+
+use super::Management;
+use crate::fleet::FleetConfig;
+
+impl Management {
+    const FLEET_CONFIG_PATH: &str = "FLEET_config.cbor";
+
+    /// Returns the config pushed by a fleet controller, and its version in the store.
+    pub async fn get_fleet_config(&self) -> (FleetConfig, Option<UpdateVersion>) {
+        match self
+            .ctx
+            .cache_store_get::<Versioned<FleetConfig>>(Self::FLEET_CONFIG_PATH)
+            .await
+        {
+            Ok((Versioned(config), ver)) => (config, Some(ver)),
+            Err(_) => (FleetConfig::default(), None),
+        }
+    }
+
+    /// Saves a config pushed by a fleet controller. It must not be older than the current
+    /// one. Returns false if it is the current one.
+    pub(crate) async fn save_fleet_config(&self, config: &FleetConfig) -> Result<bool, BoxError> {
+        let (current, ver) = self.get_fleet_config().await;
+        if &current == config {
+            return Ok(false);
+        }
+        if config.version <= current.version {
+            return Err(format!(
+                "stale fleet config version {}, current is {}",
+                config.version, current.version
+            )
+            .into());
+        }
+        self.ctx
+            .cache_store_set(Self::FLEET_CONFIG_PATH, Versioned(config.clone()), ver)
+            .await?;
+        Ok(true)
+    }
+}
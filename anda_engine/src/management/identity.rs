@@ -0,0 +1,611 @@
+use anda_core::{
+    BoxError, CacheStoreFeatures, FunctionDefinition, Migrate, Resource, StateFeatures, Tool,
+    ToolOutput, Value, Versioned, gen_schema_for,
+};
+use candid::Principal;
+use ic_cose_types::ANONYMOUS;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, sync::Arc};
+use structured_logger::unix_ms;
+
+use super::Management;
+use crate::context::BaseCtx;
+
+/// How long a link challenge can be confirmed, in milliseconds.
+pub const LINK_CHALLENGE_TTL_MS: u64 = 10 * 60 * 1000;
+
+const CODE_LEN: usize = 8;
+// no 0/O and 1/I, the code is typed by hand
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// The connector accounts linked to a user's ICP principal, the logical user.
+///
+/// Accounts are the usernames of the connectors, see [`anda_core::RequestMeta::user`],
+/// e.g. "telegram:12345", "discord:67890" or "x:alice".
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkedAccounts {
+    pub user: Option<Principal>,
+
+    pub accounts: BTreeSet<String>,
+
+    /// The Unix timestamp of the last change, in milliseconds.
+    pub updated_at: u64,
+}
+
+impl Migrate for LinkedAccounts {
+    const VERSION: u32 = 1;
+}
+
+/// The link of an account to a user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountLink {
+    pub user: Principal,
+
+    /// The Unix timestamp when the account was linked, in milliseconds.
+    pub linked_at: u64,
+}
+
+impl Migrate for AccountLink {
+    const VERSION: u32 = 1;
+}
+
+/// A pending link, started from one side (the principal or the account) and confirmed
+/// with its code from the other side.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkChallenge {
+    pub code: String,
+
+    /// The principal that started the link, if started by the principal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<Principal>,
+
+    /// The account that started the link, if started from a connector.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+
+    /// The Unix timestamp when the challenge expires, in milliseconds.
+    pub expires_at: u64,
+}
+
+impl Migrate for LinkChallenge {
+    const VERSION: u32 = 1;
+}
+
+/// The identity of a request: the verified principal of the caller, or the account
+/// of a connector's user.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RequestIdentity {
+    Principal(Principal),
+    Account(String),
+}
+
+impl RequestIdentity {
+    /// Returns the identity of the request. `RequestMeta::user` is the caller's principal
+    /// for direct callers, as set by `anda_engine_server`, and a connector account otherwise.
+    pub fn of(caller: &Principal, user: Option<&str>) -> Option<Self> {
+        match user {
+            Some(user) if !user.is_empty() && user != caller.to_text() => {
+                Some(Self::Account(user.to_string()))
+            }
+            _ if caller != &ANONYMOUS => Some(Self::Principal(*caller)),
+            _ => None,
+        }
+    }
+}
+
+impl Management {
+    fn linked_accounts_path(user: &Principal) -> String {
+        format!("IDU_{}.cbor", user.to_text())
+    }
+
+    fn account_link_path(account: &str) -> String {
+        use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+        format!("IDA_{}.cbor", BASE64_URL_SAFE_NO_PAD.encode(account))
+    }
+
+    fn link_challenge_path(code: &str) -> String {
+        format!("IDC_{}.cbor", code)
+    }
+
+    /// Returns the accounts linked to the user.
+    pub async fn get_linked_accounts(&self, user: &Principal) -> LinkedAccounts {
+        match self
+            .ctx
+            .cache_store_get::<Versioned<LinkedAccounts>>(&Self::linked_accounts_path(user))
+            .await
+        {
+            Ok((Versioned(links), _)) => links,
+            Err(_) => LinkedAccounts {
+                user: Some(*user),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Returns the principal that the account is linked to.
+    pub async fn resolve_account(&self, account: &str) -> Option<Principal> {
+        self.ctx
+            .cache_store_get::<Versioned<AccountLink>>(&Self::account_link_path(account))
+            .await
+            .ok()
+            .map(|(Versioned(link), _)| link.user)
+    }
+
+    /// Starts a link from one side, returns the challenge to confirm from the other side.
+    pub(crate) async fn start_link(
+        &self,
+        identity: RequestIdentity,
+        now_ms: u64,
+    ) -> Result<LinkChallenge, BoxError> {
+        let code: String = {
+            let mut rng = rand::thread_rng();
+            (0..CODE_LEN)
+                .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+                .collect()
+        };
+        let (user, account) = match identity {
+            RequestIdentity::Principal(user) => (Some(user), None),
+            RequestIdentity::Account(account) => (None, Some(account)),
+        };
+        let challenge = LinkChallenge {
+            code,
+            user,
+            account,
+            expires_at: now_ms + LINK_CHALLENGE_TTL_MS,
+        };
+        self.ctx
+            .cache_store_set(
+                &Self::link_challenge_path(&challenge.code),
+                Versioned(challenge.clone()),
+                None,
+            )
+            .await?;
+        Ok(challenge)
+    }
+
+    /// Confirms a challenge from the other side, and links the account to the principal.
+    pub(crate) async fn confirm_link(
+        &self,
+        identity: RequestIdentity,
+        code: &str,
+        now_ms: u64,
+    ) -> Result<LinkedAccounts, BoxError> {
+        let code = code.trim().to_ascii_uppercase();
+        let path = Self::link_challenge_path(&code);
+        let (Versioned(challenge), _) = self
+            .ctx
+            .cache_store_get::<Versioned<LinkChallenge>>(&path)
+            .await
+            .map_err(|_| "invalid or expired code")?;
+        // a code can be tried once
+        self.ctx.cache_store_delete(&path).await?;
+        if challenge.expires_at <= now_ms {
+            return Err("invalid or expired code".into());
+        }
+
+        let (user, account) = match (identity, challenge.user, challenge.account) {
+            (RequestIdentity::Principal(user), None, Some(account)) => (user, account),
+            (RequestIdentity::Account(account), Some(user), None) => (user, account),
+            _ => return Err("the code should be confirmed from the other account".into()),
+        };
+        self.link_account(&user, &account, now_ms).await
+    }
+
+    /// Links the account to the user, without verification.
+    pub(crate) async fn link_account(
+        &self,
+        user: &Principal,
+        account: &str,
+        now_ms: u64,
+    ) -> Result<LinkedAccounts, BoxError> {
+        let account_path = Self::account_link_path(account);
+        match self
+            .ctx
+            .cache_store_get::<Versioned<AccountLink>>(&account_path)
+            .await
+        {
+            Ok((Versioned(link), _)) if &link.user == user => {
+                return Ok(self.get_linked_accounts(user).await);
+            }
+            Ok(_) => {
+                return Err(format!(
+                    "account {} is linked to another user, unlink it first",
+                    account
+                )
+                .into());
+            }
+            Err(_) => {}
+        }
+
+        let user_path = Self::linked_accounts_path(user);
+        let (mut links, ver) = match self
+            .ctx
+            .cache_store_get::<Versioned<LinkedAccounts>>(&user_path)
+            .await
+        {
+            Ok((Versioned(links), ver)) => (links, Some(ver)),
+            Err(_) => (
+                LinkedAccounts {
+                    user: Some(*user),
+                    ..Default::default()
+                },
+                None,
+            ),
+        };
+        links.accounts.insert(account.to_string());
+        links.updated_at = now_ms;
+        self.ctx
+            .cache_store_set(&user_path, Versioned(links.clone()), ver)
+            .await?;
+        self.ctx
+            .cache_store_set(
+                &account_path,
+                Versioned(AccountLink {
+                    user: *user,
+                    linked_at: now_ms,
+                }),
+                None,
+            )
+            .await?;
+        Ok(links)
+    }
+
+    /// Unlinks the account from the user.
+    pub(crate) async fn unlink_account(
+        &self,
+        user: &Principal,
+        account: &str,
+        now_ms: u64,
+    ) -> Result<LinkedAccounts, BoxError> {
+        let user_path = Self::linked_accounts_path(user);
+        let (Versioned(mut links), ver) = self
+            .ctx
+            .cache_store_get::<Versioned<LinkedAccounts>>(&user_path)
+            .await
+            .map_err(|_| format!("account {} is not linked", account))?;
+        if !links.accounts.remove(account) {
+            return Err(format!("account {} is not linked", account).into());
+        }
+        links.updated_at = now_ms;
+        self.ctx
+            .cache_store_set(&user_path, Versioned(links.clone()), Some(ver))
+            .await?;
+        self.ctx
+            .cache_store_delete(&Self::account_link_path(account))
+            .await?;
+        Ok(links)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct IdentityToolArgs {
+    /// The method to call.
+    pub method: IdentityToolMethod,
+
+    /// The code of the link to confirm, required by confirm_link.
+    pub code: Option<String>,
+
+    /// The account to unlink, e.g. "telegram:12345". Required by unlink when called by the
+    /// principal, an account can only unlink itself.
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityToolMethod {
+    StartLink,
+    ConfirmLink,
+    Unlink,
+    ListLinks,
+}
+
+/// The output of the identity tool.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct IdentityToolOutput {
+    /// The code to confirm from the other account, returned by start_link.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+
+    /// The Unix timestamp when the code expires, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+
+    /// The linked accounts of the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<LinkedAccounts>,
+}
+
+/// Represents a tool to link the connector accounts of a user to their ICP principal, so
+/// that the same person is recognized on every platform.
+///
+/// The link is verified with a challenge: `start_link` returns a one-time code from one
+/// side, e.g. the Telegram account, and `confirm_link` with the code from the other side,
+/// e.g. the web app signed in with the principal, within [`LINK_CHALLENGE_TTL_MS`].
+pub struct IdentityTool {
+    management: Arc<Management>,
+    schema: Value,
+}
+
+impl IdentityTool {
+    pub const NAME: &'static str = "sys_identity";
+
+    pub fn new(management: Arc<Management>) -> Self {
+        let schema = gen_schema_for::<IdentityToolArgs>();
+        Self { management, schema }
+    }
+}
+
+impl Tool<BaseCtx> for IdentityTool {
+    type Args = IdentityToolArgs;
+    type Output = IdentityToolOutput;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Links the user's accounts on other platforms and their ICP principal to one identity. start_link returns a one-time code that the user confirms with confirm_link from their other account within 10 minutes. Also lists and unlinks accounts.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        if resources.is_some() {
+            return Err("resources are not supported".into());
+        }
+        let identity = RequestIdentity::of(&ctx.caller(), ctx.meta().user.as_deref())
+            .ok_or("anonymous user is not allowed")?;
+
+        let now_ms = unix_ms();
+        let output = match args.method {
+            IdentityToolMethod::StartLink => {
+                let challenge = self.management.start_link(identity, now_ms).await?;
+                IdentityToolOutput {
+                    code: Some(challenge.code),
+                    expires_at: Some(challenge.expires_at),
+                    links: None,
+                }
+            }
+
+            IdentityToolMethod::ConfirmLink => {
+                let code = args.code.as_deref().ok_or("code is required")?;
+                let links = self.management.confirm_link(identity, code, now_ms).await?;
+                IdentityToolOutput {
+                    links: Some(links),
+                    ..Default::default()
+                }
+            }
+
+            IdentityToolMethod::Unlink => {
+                let (user, account) = match identity {
+                    RequestIdentity::Principal(user) => {
+                        (user, args.account.ok_or("account is required")?)
+                    }
+                    RequestIdentity::Account(account) => {
+                        let user = self
+                            .management
+                            .resolve_account(&account)
+                            .await
+                            .ok_or("account is not linked")?;
+                        (user, account)
+                    }
+                };
+                let links = self
+                    .management
+                    .unlink_account(&user, &account, now_ms)
+                    .await?;
+                IdentityToolOutput {
+                    links: Some(links),
+                    ..Default::default()
+                }
+            }
+
+            IdentityToolMethod::ListLinks => {
+                let links = match identity {
+                    RequestIdentity::Principal(user) => {
+                        self.management.get_linked_accounts(&user).await
+                    }
+                    RequestIdentity::Account(account) => {
+                        match self.management.resolve_account(&account).await {
+                            Some(user) => self.management.get_linked_accounts(&user).await,
+                            None => LinkedAccounts::default(),
+                        }
+                    }
+                };
+                IdentityToolOutput {
+                    links: Some(links),
+                    ..Default::default()
+                }
+            }
+        };
+        Ok(ToolOutput::new(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::EngineBuilder,
+        management::{ManagementBuilder, Visibility},
+    };
+    use anda_core::RequestMeta;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_identity_tool() {
+        let ctx = EngineBuilder::new().mock_ctx();
+        let management = Arc::new(
+            ManagementBuilder::new(Visibility::Private, Principal::anonymous()).build(&ctx.base),
+        );
+        let tool = IdentityTool::new(management.clone());
+        let alice =
+            Principal::from_text("77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe")
+                .unwrap();
+        let bot = Principal::management_canister();
+        let tool_ctx = |caller: Principal, user: &str| {
+            ctx.child_base_with(
+                caller,
+                IdentityTool::NAME,
+                RequestMeta {
+                    user: Some(user.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let alice_ctx = tool_ctx(alice, &alice.to_text());
+        let telegram_ctx = tool_ctx(bot, "telegram:123");
+        let discord_ctx = tool_ctx(bot, "discord:456");
+        let args = |method: IdentityToolMethod, code: Option<String>| IdentityToolArgs {
+            method,
+            code,
+            account: None,
+        };
+
+        // started from the Telegram account, confirmed by the principal
+        let res = tool
+            .call(
+                telegram_ctx.clone(),
+                args(IdentityToolMethod::StartLink, None),
+                None,
+            )
+            .await
+            .unwrap();
+        let code = res.output.code.unwrap();
+        assert_eq!(code.len(), CODE_LEN);
+        // the code is confirmed from the other side only
+        assert!(
+            tool.call(
+                discord_ctx.clone(),
+                args(IdentityToolMethod::ConfirmLink, Some(code.clone())),
+                None,
+            )
+            .await
+            .is_err()
+        );
+        let res = tool
+            .call(
+                telegram_ctx.clone(),
+                args(IdentityToolMethod::StartLink, None),
+                None,
+            )
+            .await
+            .unwrap();
+        let code = res.output.code.unwrap();
+        let res = tool
+            .call(
+                alice_ctx.clone(),
+                args(
+                    IdentityToolMethod::ConfirmLink,
+                    Some(code.to_ascii_lowercase()),
+                ),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            res.output.links.unwrap().accounts,
+            BTreeSet::from(["telegram:123".to_string()])
+        );
+        assert_eq!(
+            management.resolve_account("telegram:123").await,
+            Some(alice)
+        );
+        // a code is used once
+        assert!(
+            tool.call(
+                alice_ctx.clone(),
+                args(IdentityToolMethod::ConfirmLink, Some(code)),
+                None,
+            )
+            .await
+            .is_err()
+        );
+
+        // started by the principal, confirmed from the Discord account
+        let res = tool
+            .call(
+                alice_ctx.clone(),
+                args(IdentityToolMethod::StartLink, None),
+                None,
+            )
+            .await
+            .unwrap();
+        tool.call(
+            discord_ctx.clone(),
+            args(IdentityToolMethod::ConfirmLink, res.output.code),
+            None,
+        )
+        .await
+        .unwrap();
+        let res = tool
+            .call(
+                telegram_ctx.clone(),
+                args(IdentityToolMethod::ListLinks, None),
+                None,
+            )
+            .await
+            .unwrap();
+        let links = res.output.links.unwrap();
+        assert_eq!(links.user, Some(alice));
+        assert_eq!(links.accounts.len(), 2);
+
+        // an account is linked to one user
+        assert!(
+            management
+                .link_account(&bot, "telegram:123", unix_ms())
+                .await
+                .is_err()
+        );
+
+        // an account unlinks itself, the principal unlinks any of its accounts
+        tool.call(
+            telegram_ctx.clone(),
+            args(IdentityToolMethod::Unlink, None),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(management.resolve_account("telegram:123").await, None);
+        let res = tool
+            .call(
+                alice_ctx.clone(),
+                IdentityToolArgs {
+                    method: IdentityToolMethod::Unlink,
+                    code: None,
+                    account: Some("discord:456".to_string()),
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(res.output.links.unwrap().accounts.is_empty());
+
+        let anonymous_ctx = ctx
+            .child_base_with(ANONYMOUS, IdentityTool::NAME, RequestMeta::default())
+            .unwrap();
+        assert!(
+            tool.call(
+                anonymous_ctx,
+                args(IdentityToolMethod::StartLink, None),
+                None
+            )
+            .await
+            .is_err()
+        );
+    }
+}
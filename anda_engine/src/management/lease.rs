@@ -0,0 +1,350 @@
+use anda_core::{BoxError, OsVersion, Path, PutMode, UpdateVersion};
+use ciborium::from_reader;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use structured_logger::unix_ms;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::SYSTEM_PATH;
+use crate::store::Store;
+
+/// A time-bounded claim on a shared resource (e.g. a thread), held by one engine instance.
+///
+/// Leases are persisted in the shared object store so that multiple engine instances
+/// can coordinate with each other. A lease that is not renewed before `expires_at`
+/// can be taken over by another instance, which provides failover when the holder crashes.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Lease {
+    /// The key of the leased resource, e.g. "TH_9z4e2mr0ui3e8a215n4g".
+    pub key: String,
+
+    /// The engine instance, or a run of it, that holds the lease.
+    pub holder: String,
+
+    /// The time when the lease was acquired, in milliseconds.
+    pub acquired_at: u64,
+
+    /// The time when the lease expires, in milliseconds.
+    pub expires_at: u64,
+
+    /// The version of the lease object in the store, used for atomic updates.
+    #[serde(skip)]
+    pub version: Option<UpdateVersion>,
+}
+
+impl Lease {
+    /// Returns true if the lease has expired at the given time.
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.expires_at <= now_ms
+    }
+}
+
+/// Coordinates leases across engine instances that share the same store.
+///
+/// All operations go directly to the store (bypassing the local cache) and rely on
+/// conditional puts, so the store backend must support [`PutMode::Create`] and [`PutMode::Update`].
+#[derive(Clone)]
+pub struct LeaseManager {
+    store: Store,
+    namespace: Path,
+    holder: String,
+    ttl: Duration,
+}
+
+impl LeaseManager {
+    /// Creates a new lease manager.
+    ///
+    /// # Arguments
+    /// * `store` - The store shared by all engine instances;
+    /// * `holder` - A unique identifier of this engine instance;
+    /// * `ttl` - How long a lease stays valid without renewal.
+    pub fn new(store: Store, holder: String, ttl: Duration) -> Self {
+        Self {
+            store,
+            namespace: Path::from(SYSTEM_PATH).child("leases"),
+            holder,
+            ttl,
+        }
+    }
+
+    /// Returns the identifier of this engine instance.
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Returns a lease manager whose holder is a run of this engine instance, so that
+    /// concurrent runs on the same instance do not share their leases.
+    pub fn for_run(&self, run: &str) -> Self {
+        Self {
+            holder: format!("{}:{}", self.holder, run),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the lease time-to-live.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn lease_path(key: &str) -> Path {
+        Path::from(format!("{}.cbor", key))
+    }
+
+    /// Retrieves the current lease for the key from the store.
+    pub async fn get(&self, key: &str) -> Result<Lease, BoxError> {
+        let (data, meta) = self
+            .store
+            .store_get(&self.namespace, &Self::lease_path(key))
+            .await?;
+        let mut lease: Lease = from_reader(&data[..])?;
+        lease.version = Some(UpdateVersion {
+            e_tag: meta.e_tag,
+            version: meta.version,
+        });
+        Ok(lease)
+    }
+
+    /// Acquires the lease for the key.
+    ///
+    /// It succeeds if the lease does not exist, has expired, or is already held by this instance.
+    /// Returns an error if another instance holds a live lease or wins the race to claim it.
+    pub async fn acquire(&self, key: &str) -> Result<Lease, BoxError> {
+        let now_ms = unix_ms();
+        let mut lease = Lease {
+            key: key.to_string(),
+            holder: self.holder.clone(),
+            acquired_at: now_ms,
+            expires_at: now_ms + self.ttl.as_millis() as u64,
+            version: None,
+        };
+
+        let mode = match self.get(key).await {
+            Ok(prev) => {
+                if prev.holder != self.holder && !prev.is_expired(now_ms) {
+                    return Err(format!(
+                        "lease {} is held by {} until {}",
+                        key, prev.holder, prev.expires_at
+                    )
+                    .into());
+                }
+                if prev.holder == self.holder {
+                    lease.acquired_at = prev.acquired_at;
+                }
+                Self::update_mode(prev.version)
+            }
+            Err(_) => PutMode::Create,
+        };
+
+        lease.version = Some(
+            self.put(&lease, mode)
+                .await
+                .map_err(|err| format!("failed to acquire lease {}: {}", key, err))?,
+        );
+        Ok(lease)
+    }
+
+    /// Extends the expiration of a lease held by this instance.
+    /// Returns an error if the lease was taken over by another instance.
+    pub async fn renew(&self, lease: &mut Lease) -> Result<(), BoxError> {
+        if lease.holder != self.holder {
+            return Err(format!("lease {} is not held by {}", lease.key, self.holder).into());
+        }
+
+        let mut next = lease.clone();
+        next.expires_at = unix_ms() + self.ttl.as_millis() as u64;
+        let ver = self
+            .put(&next, Self::update_mode(lease.version.clone()))
+            .await
+            .map_err(|err| format!("failed to renew lease {}: {}", lease.key, err))?;
+        next.version = Some(ver);
+        *lease = next;
+        Ok(())
+    }
+
+    /// Releases a lease held by this instance so that others can claim it immediately.
+    ///
+    /// The lease is expired rather than deleted, by a conditional put on its version, so
+    /// a lease taken over by another instance in the meantime is never released.
+    pub async fn release(&self, lease: Lease) -> Result<(), BoxError> {
+        if lease.holder != self.holder || lease.version.is_none() {
+            return Err(format!("lease {} is not held by {}", lease.key, self.holder).into());
+        }
+
+        let mut next = lease.clone();
+        next.expires_at = 0;
+        self.put(&next, Self::update_mode(lease.version))
+            .await
+            .map_err(|err| format!("failed to release lease {}: {}", lease.key, err))?;
+        Ok(())
+    }
+
+    /// Keeps renewing the lease in background until the cancellation token is triggered.
+    /// The returned handle yields the latest lease, which should be released by the caller.
+    pub fn keep_alive(&self, mut lease: Lease, token: CancellationToken) -> JoinHandle<Lease> {
+        let manager = self.clone();
+        let interval = (self.ttl / 3).max(Duration::from_millis(100));
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {
+                        if let Err(err) = manager.renew(&mut lease).await {
+                            log::error!(key = lease.key; "{}", err);
+                            break;
+                        }
+                    }
+                }
+            }
+            lease
+        })
+    }
+
+    /// Keeps renewing the lease in background, like [`LeaseManager::keep_alive`], until the
+    /// returned guard is released or dropped. Dropping the guard, e.g. on an early return,
+    /// stops the renewal and releases the lease in background.
+    pub fn hold(&self, lease: Lease, token: CancellationToken) -> LeaseGuard {
+        LeaseGuard {
+            manager: self.clone(),
+            handle: Some(self.keep_alive(lease, token.clone())),
+            token,
+        }
+    }
+
+    fn update_mode(version: Option<UpdateVersion>) -> PutMode {
+        match version {
+            Some(ver) => PutMode::Update(OsVersion {
+                e_tag: ver.e_tag,
+                version: ver.version,
+            }),
+            None => PutMode::Overwrite,
+        }
+    }
+
+    async fn put(&self, lease: &Lease, mode: PutMode) -> Result<UpdateVersion, BoxError> {
+        let res = self
+            .store
+            .store_put(
+                &self.namespace,
+                &Self::lease_path(&lease.key),
+                mode,
+                to_cbor_bytes(lease).into(),
+            )
+            .await?;
+        Ok(UpdateVersion {
+            e_tag: res.e_tag,
+            version: res.version,
+        })
+    }
+}
+
+/// A lease kept alive by [`LeaseManager::hold`], released when the guard is released or dropped.
+pub struct LeaseGuard {
+    manager: LeaseManager,
+    handle: Option<JoinHandle<Lease>>,
+    token: CancellationToken,
+}
+
+impl LeaseGuard {
+    /// Stops renewing the lease and releases it.
+    pub async fn release(mut self) -> Result<(), BoxError> {
+        self.token.cancel();
+        match self.handle.take() {
+            Some(handle) => self.manager.release(handle.await?).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LeaseGuard {
+    fn drop(&mut self) {
+        self.token.cancel();
+        if let Some(handle) = self.handle.take()
+            && let Ok(rt) = tokio::runtime::Handle::try_current()
+        {
+            let manager = self.manager.clone();
+            rt.spawn(async move {
+                if let Ok(lease) = handle.await
+                    && let Err(err) = manager.release(lease).await
+                {
+                    log::warn!("failed to release lease: {}", err);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_lease() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let a = LeaseManager::new(store.clone(), "a".to_string(), Duration::from_millis(200));
+        let b = LeaseManager::new(store, "b".to_string(), Duration::from_millis(200));
+
+        let mut lease = a.acquire("TH_1").await.unwrap();
+        assert_eq!(lease.holder, "a");
+        assert!(b.acquire("TH_1").await.is_err());
+        // re-entrant for the same holder
+        let lease2 = a.acquire("TH_1").await.unwrap();
+        assert_eq!(lease2.acquired_at, lease.acquired_at);
+        // stale version should not overwrite
+        assert!(a.renew(&mut lease).await.is_err());
+        lease = lease2;
+        let stale = lease.clone();
+        a.renew(&mut lease).await.unwrap();
+
+        // a stale version should not release the lease
+        assert!(a.release(stale).await.is_err());
+        a.release(lease).await.unwrap();
+        let lease = b.acquire("TH_1").await.unwrap();
+        assert_eq!(lease.holder, "b");
+
+        // failover after expiration
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let expired = b.get("TH_1").await.unwrap();
+        let lease = a.acquire("TH_1").await.unwrap();
+        assert_eq!(lease.holder, "a");
+        assert!(b.release(lease.clone()).await.is_err());
+        // the expired lease of b should not release the lease taken over by a
+        assert!(b.release(expired).await.is_err());
+        assert_eq!(b.get("TH_1").await.unwrap().holder, "a");
+        a.release(lease).await.unwrap();
+
+        // runs on the same instance do not share leases
+        let run1 = a.for_run("1");
+        let run2 = a.for_run("2");
+        let lease = run1.acquire("TH_2").await.unwrap();
+        assert_eq!(lease.holder, "a:1");
+        assert!(run2.acquire("TH_2").await.is_err());
+        assert!(a.acquire("TH_2").await.is_err());
+        run1.release(lease).await.unwrap();
+        let lease = run2.acquire("TH_2").await.unwrap();
+        run2.release(lease).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_lease_guard() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let a = LeaseManager::new(store.clone(), "a".to_string(), Duration::from_secs(60));
+        let b = LeaseManager::new(store, "b".to_string(), Duration::from_secs(60));
+
+        let guard = a.hold(a.acquire("TH_1").await.unwrap(), CancellationToken::new());
+        assert!(b.acquire("TH_1").await.is_err());
+        guard.release().await.unwrap();
+        let lease = b.acquire("TH_1").await.unwrap();
+        b.release(lease).await.unwrap();
+
+        // dropped on an early return
+        let guard = a.hold(a.acquire("TH_1").await.unwrap(), CancellationToken::new());
+        drop(guard);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let lease = b.acquire("TH_1").await.unwrap();
+        assert_eq!(lease.holder, "b");
+    }
+}
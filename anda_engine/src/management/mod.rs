@@ -1,22 +1,54 @@
 use anda_core::{
-    ANONYMOUS, BaseContext, BoxError, CacheStoreFeatures, MyThreads, RequestMeta, ThreadMeta,
-    ToolInput, UpdateVersion, Xid,
+    ANONYMOUS, BaseContext, BoxError, CacheStoreFeatures, KeyEpoch, KeyRotation, KvFeatures,
+    MyThreads, RequestMeta, Resource, ThreadMeta, ToolInput, UpdateVersion, Versioned, Xid,
 };
 use candid::Principal;
+use serde_bytes::ByteBuf;
 use serde_json::json;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use structured_logger::unix_ms;
 
-use crate::context::BaseCtx;
-
+use crate::{context::BaseCtx, id::new_xid};
+
+mod api_key;
+mod approval;
+mod budget;
+mod fleet;
+mod identity;
+mod lease;
+mod quota;
+mod reminder;
+mod session;
 mod state;
 mod thread;
-
+mod workflow;
+
+pub use api_key::*;
+pub use approval::*;
+pub use budget::*;
+pub use identity::*;
+pub use lease::*;
+pub use quota::*;
+pub use reminder::*;
+pub use session::*;
 pub use state::*;
 pub use thread::*;
+pub use workflow::*;
 
 pub static SYSTEM_PATH: &str = "_";
 
+/// The key-value namespace of the quota usage of callers, see [`QuotaUsage`].
+const QUOTA_NAMESPACE: &str = "quotas";
+
+/// How many times a quota update is retried on concurrent requests of a caller.
+const QUOTA_UPDATE_RETRIES: usize = 16;
+
+/// The key-value namespace of the budget usage of agents, see [`BudgetUsage`].
+const BUDGET_NAMESPACE: &str = "budgets";
+
+/// How many times a budget update is retried on concurrent tool calls of an agent.
+const BUDGET_UPDATE_RETRIES: usize = 16;
+
 #[derive(Clone)]
 /// Represents system management tools for the Anda engine.
 pub struct Management {
@@ -24,6 +56,9 @@ pub struct Management {
     controller: Principal,
     managers: BTreeSet<Principal>,
     visibility: Visibility, // 0: private, 1: protected, 2: public
+    approval: Option<ApprovalPolicy>,
+    budgets: Option<BudgetPolicy>,
+    quotas: Option<QuotaPolicy>,
 }
 
 /// The visibility of the engine.
@@ -50,6 +85,15 @@ pub struct ManagementBuilder {
 
     /// The managers of the engine.
     pub(crate) managers: BTreeSet<Principal>,
+
+    /// The policy of tool calls that require operator approval.
+    pub(crate) approval: Option<ApprovalPolicy>,
+
+    /// The daily action budgets of agents.
+    pub(crate) budgets: Option<BudgetPolicy>,
+
+    /// The quotas of callers.
+    pub(crate) quotas: Option<QuotaPolicy>,
 }
 
 impl ManagementBuilder {
@@ -58,6 +102,9 @@ impl ManagementBuilder {
             controller,
             managers: BTreeSet::new(),
             visibility,
+            approval: None,
+            budgets: None,
+            quotas: None,
         }
     }
 
@@ -67,6 +114,25 @@ impl ManagementBuilder {
         self
     }
 
+    /// Requires t-of-n operator approval for the tools in the policy.
+    pub fn with_approval_policy(mut self, policy: ApprovalPolicy) -> Result<Self, BoxError> {
+        policy.validate()?;
+        self.approval = Some(policy);
+        Ok(self)
+    }
+
+    /// Limits the daily calls and values of side-effecting tools per agent.
+    pub fn with_budget_policy(mut self, policy: BudgetPolicy) -> Self {
+        self.budgets = Some(policy);
+        self
+    }
+
+    /// Limits the requests per minute and the tokens per day of callers.
+    pub fn with_quota_policy(mut self, policy: QuotaPolicy) -> Self {
+        self.quotas = Some(policy);
+        self
+    }
+
     pub fn build(self, ctx: &BaseCtx) -> Management {
         Management {
             ctx: ctx
@@ -77,12 +143,16 @@ impl ManagementBuilder {
                         engine: None,
                         thread: None,
                         user: Some(ctx.name.clone()),
+                        ..Default::default()
                     },
                 )
                 .expect("failed to create system context"),
             controller: self.controller,
             managers: self.managers,
             visibility: self.visibility,
+            approval: self.approval,
+            budgets: self.budgets,
+            quotas: self.quotas,
         }
     }
 }
@@ -96,10 +166,34 @@ impl Management {
         format!("TH_{}.meta.cbor", thread_id.xid())
     }
 
+    fn thread_records_path(thread_id: &Xid) -> String {
+        format!("TH_{}.records.cbor", thread_id.xid())
+    }
+
     fn my_threads_path(id: &Principal) -> String {
         format!("MYTH_{}.cbor", id.to_text())
     }
 
+    fn peer_keys_path(peer: &Principal) -> String {
+        format!("PK_{}.cbor", peer.to_text())
+    }
+
+    fn pending_tool_calls_path() -> &'static str {
+        "APPROVALS.cbor"
+    }
+
+    fn reminders_path() -> &'static str {
+        "REMINDERS.cbor"
+    }
+
+    fn api_keys_path() -> &'static str {
+        "APIKEYS.cbor"
+    }
+
+    fn api_key_usage_path(id: &Xid) -> String {
+        format!("AKU_{}.cbor", id)
+    }
+
     /// Returns true if the caller is the controller of the engine.
     pub fn is_controller(&self, caller: &Principal) -> bool {
         caller == &self.controller
@@ -126,7 +220,10 @@ impl Management {
     /// It does not check the permission of the caller for the thread.
     pub async fn get_user_state(&self, user: &Principal) -> Result<UserState, BoxError> {
         let state_key = Self::user_state_path(user);
-        let (mut val, version) = self.ctx.cache_store_get::<UserState>(&state_key).await?;
+        let (Versioned(mut val), version) = self
+            .ctx
+            .cache_store_get::<Versioned<UserState>>(&state_key)
+            .await?;
         // the version in the user state is prev version, we need to update it here
         val.version = Some(version);
         Ok(val)
@@ -150,7 +247,9 @@ impl Management {
     ) -> Result<UpdateVersion, BoxError> {
         let state_key = Self::user_state_path(&state.user);
         let ver = state.version.clone();
-        self.ctx.cache_store_set(&state_key, state, ver).await
+        self.ctx
+            .cache_store_set(&state_key, Versioned(state), ver)
+            .await
     }
 
     /// Deletes the user state from the cache store.
@@ -163,7 +262,10 @@ impl Management {
     /// It does not check the permission of the caller for the thread.
     pub async fn get_thread_meta(&self, thread_id: &Xid) -> Result<ThreadMeta, BoxError> {
         let thread_key = Self::thread_meta_path(thread_id);
-        let (mut meta, version) = self.ctx.cache_store_get::<ThreadMeta>(&thread_key).await?;
+        let (Versioned(mut meta), version) = self
+            .ctx
+            .cache_store_get::<Versioned<ThreadMeta>>(&thread_key)
+            .await?;
         // the version in the metadata is prev version, we need to update it here
         meta.version = Some(version);
         Ok(meta)
@@ -178,7 +280,7 @@ impl Management {
     ) -> Result<ThreadMeta, BoxError> {
         match thread_id {
             // Create a new thread if the thread_id is not provided.
-            None => Ok(ThreadMeta::new(Xid::new(), self.ctx.id, *caller, unix_ms())),
+            None => Ok(ThreadMeta::new(new_xid(), self.ctx.id, *caller, unix_ms())),
             Some(id) => {
                 match self.get_thread_meta(id).await {
                     Ok(thread) => {
@@ -225,7 +327,7 @@ impl Management {
 
                         // Create a new thread with parent if the thread does not exist.
                         let mut thread =
-                            ThreadMeta::new(Xid::new(), self.ctx.id, *caller, unix_ms());
+                            ThreadMeta::new(new_xid(), self.ctx.id, *caller, unix_ms());
                         thread.parent = Some(id.to_owned());
                         Ok(thread)
                     }
@@ -242,7 +344,35 @@ impl Management {
         let thread_key = Self::thread_meta_path(&thread.id);
         let ver = thread.version.clone();
         thread.updated_at = unix_ms();
-        self.ctx.cache_store_set(&thread_key, thread, ver).await
+        self.ctx
+            .cache_store_set(&thread_key, Versioned(thread), ver)
+            .await
+    }
+
+    /// Appends an encrypted record to the thread's records.
+    pub(crate) async fn append_thread_record(
+        &self,
+        thread_id: &Xid,
+        record: Vec<u8>,
+    ) -> Result<(), BoxError> {
+        let key = Self::thread_records_path(thread_id);
+        let (mut records, ver) = match self.ctx.cache_store_get::<Vec<ByteBuf>>(&key).await {
+            Ok((records, ver)) => (records, Some(ver)),
+            Err(_) => (Vec::new(), None),
+        };
+        records.push(ByteBuf::from(record));
+        self.ctx.cache_store_set(&key, records, ver).await?;
+        Ok(())
+    }
+
+    /// Retrieves the encrypted records of the thread.
+    /// It does not check the permission of the caller for the thread.
+    pub async fn get_thread_records(&self, thread_id: &Xid) -> Result<Vec<ByteBuf>, BoxError> {
+        let key = Self::thread_records_path(thread_id);
+        match self.ctx.cache_store_get::<Vec<ByteBuf>>(&key).await {
+            Ok((records, _)) => Ok(records),
+            Err(_) => Ok(Vec::new()),
+        }
     }
 
     /// Deletes the thread metadata from the cache store.
@@ -254,6 +384,11 @@ impl Management {
         match self.get_thread_meta(thread_id).await {
             Ok(thread) => {
                 if thread.has_permission(caller) {
+                    let _ = self
+                        .ctx
+                        .cache_store_delete(&Self::thread_records_path(&thread.id))
+                        .await;
+                    let _ = self.ctx.delete_thread_states(&thread.id).await;
                     self.ctx
                         .cache_store_delete(&Self::thread_meta_path(&thread.id))
                         .await
@@ -273,8 +408,12 @@ impl Management {
     /// Loads my threads index that participating in.
     pub(crate) async fn load_my_threads(&self) -> Result<MyThreads, BoxError> {
         let my_threads_key = Self::my_threads_path(&self.ctx.id);
-        match self.ctx.cache_store_get::<MyThreads>(&my_threads_key).await {
-            Ok((mut threads, ver)) => {
+        match self
+            .ctx
+            .cache_store_get::<Versioned<MyThreads>>(&my_threads_key)
+            .await
+        {
+            Ok((Versioned(mut threads), ver)) => {
                 threads.version = Some(ver);
                 Ok(threads)
             }
@@ -290,7 +429,525 @@ impl Management {
         let my_threads_key = Self::my_threads_path(&self.ctx.id);
         let ver = threads.version.clone();
         self.ctx
-            .cache_store_set(&my_threads_key, threads, ver)
+            .cache_store_set(&my_threads_key, Versioned(threads), ver)
+            .await
+    }
+
+    /// Returns true if calls to the tool require operator approval.
+    pub fn requires_approval(&self, tool: &str) -> bool {
+        self.approval
+            .as_ref()
+            .is_some_and(|p| p.requires_approval(tool))
+    }
+
+    /// Loads the scheduled reminders of all users and their version, empty if there are none.
+    async fn load_reminders(&self) -> Result<(Vec<Reminder>, Option<UpdateVersion>), BoxError> {
+        match self
+            .ctx
+            .cache_store_get::<Versioned<Vec<Reminder>>>(Self::reminders_path())
+            .await
+        {
+            Ok((Versioned(reminders), ver)) => Ok((reminders, Some(ver))),
+            Err(_) => Ok((Vec::new(), None)),
+        }
+    }
+
+    /// Lists the reminders of the user, the earliest due first.
+    pub async fn list_reminders(&self, user: &Principal) -> Result<Vec<Reminder>, BoxError> {
+        let (mut reminders, _) = self.load_reminders().await?;
+        reminders.retain(|r| &r.caller == user);
+        Ok(reminders)
+    }
+
+    /// Adds a reminder, delivered by the engine when it is due.
+    pub(crate) async fn add_reminder(&self, reminder: Reminder) -> Result<(), BoxError> {
+        let (mut reminders, ver) = self.load_reminders().await?;
+        let idx = reminders.partition_point(|r| r.due_at <= reminder.due_at);
+        reminders.insert(idx, reminder);
+        self.ctx
+            .cache_store_set(Self::reminders_path(), Versioned(reminders), ver)
+            .await?;
+        Ok(())
+    }
+
+    /// Cancels a reminder of the user.
+    pub(crate) async fn cancel_reminder(
+        &self,
+        user: &Principal,
+        id: &Xid,
+    ) -> Result<Reminder, BoxError> {
+        let (mut reminders, ver) = self.load_reminders().await?;
+        let idx = reminders
+            .iter()
+            .position(|r| &r.id == id && &r.caller == user)
+            .ok_or_else(|| format!("reminder {} not found", id))?;
+        let reminder = reminders.remove(idx);
+        self.ctx
+            .cache_store_set(Self::reminders_path(), Versioned(reminders), ver)
+            .await?;
+        Ok(reminder)
+    }
+
+    /// Removes and returns the reminders due at the time. The removal is versioned, so a
+    /// reminder is taken by only one of the engine instances sharing the store.
+    pub(crate) async fn take_due_reminders(&self, now_ms: u64) -> Result<Vec<Reminder>, BoxError> {
+        let (mut reminders, ver) = self.load_reminders().await?;
+        let n = reminders.partition_point(|r| r.due_at <= now_ms);
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let due: Vec<Reminder> = reminders.drain(..n).collect();
+        self.ctx
+            .cache_store_set(Self::reminders_path(), Versioned(reminders), ver)
+            .await?;
+        Ok(due)
+    }
+
+    async fn load_pending_tool_calls(
+        &self,
+    ) -> Result<(Vec<PendingToolCall>, Option<UpdateVersion>), BoxError> {
+        let now_ms = unix_ms();
+        match self
+            .ctx
+            .cache_store_get::<Versioned<Vec<PendingToolCall>>>(Self::pending_tool_calls_path())
+            .await
+        {
+            Ok((Versioned(mut calls), ver)) => {
+                calls.retain(|c| !c.is_expired(now_ms));
+                Ok((calls, Some(ver)))
+            }
+            Err(_) => Ok((Vec::new(), None)),
+        }
+    }
+
+    /// Retrieves the tool calls waiting for operator approval.
+    pub async fn pending_tool_calls(&self) -> Result<Vec<PendingToolCall>, BoxError> {
+        let (calls, _) = self.load_pending_tool_calls().await?;
+        Ok(calls)
+    }
+
+    /// Holds a tool call until it is approved by the operators.
+    pub(crate) async fn request_approval(
+        &self,
+        caller: Principal,
+        tool: String,
+        args: String,
+        resources: Option<Vec<Resource>>,
+    ) -> Result<PendingToolCall, BoxError> {
+        let policy = self
+            .approval
+            .as_ref()
+            .ok_or("approval policy is not configured")?;
+        let call = PendingToolCall::new(policy, caller, tool, args, resources);
+        let (mut calls, ver) = self.load_pending_tool_calls().await?;
+        calls.push(call.clone());
+        self.ctx
+            .cache_store_set(Self::pending_tool_calls_path(), Versioned(calls), ver)
+            .await?;
+        log::info!(id = call.id.to_string(), tool = call.tool, caller = caller.to_text(); "tool call pending approval");
+        Ok(call)
+    }
+
+    /// Records an operator's signature of a pending call.
+    /// Returns the call, removed from the pending calls, once it has reached the threshold.
+    pub(crate) async fn approve_tool_call(
+        &self,
+        id: &Xid,
+        public_key: [u8; 32],
+        signature: [u8; 64],
+    ) -> Result<Option<PendingToolCall>, BoxError> {
+        let policy = self
+            .approval
+            .as_ref()
+            .ok_or("approval policy is not configured")?;
+        let (mut calls, ver) = self.load_pending_tool_calls().await?;
+        let idx = calls
+            .iter()
+            .position(|c| &c.id == id)
+            .ok_or_else(|| format!("pending tool call {} not found", id))?;
+        let approved = calls[idx].approve(policy, public_key, signature, unix_ms())?;
+        let call = if approved {
+            Some(calls.remove(idx))
+        } else {
+            None
+        };
+        self.ctx
+            .cache_store_set(Self::pending_tool_calls_path(), Versioned(calls), ver)
+            .await?;
+        Ok(call)
+    }
+
+    /// Retrieves the known keys of a peer engine, by purpose.
+    pub async fn get_peer_keys(&self, peer: &Principal) -> BTreeMap<String, Vec<KeyEpoch>> {
+        match self
+            .ctx
+            .cache_store_get::<BTreeMap<String, Vec<KeyEpoch>>>(&Self::peer_keys_path(peer))
+            .await
+        {
+            Ok((keys, _)) => keys,
+            Err(_) => BTreeMap::new(),
+        }
+    }
+
+    /// Saves a verified key rotation of a peer engine.
+    /// If the peer's key of the purpose is known, the rotation must chain from it.
+    pub(crate) async fn save_peer_key_rotation(
+        &self,
+        rotation: KeyRotation,
+    ) -> Result<(), BoxError> {
+        let key = Self::peer_keys_path(&rotation.engine);
+        let (mut keys, ver) = match self
+            .ctx
+            .cache_store_get::<BTreeMap<String, Vec<KeyEpoch>>>(&key)
+            .await
+        {
+            Ok((keys, ver)) => (keys, Some(ver)),
+            Err(_) => (BTreeMap::new(), None),
+        };
+        if let Some(known) = keys.get(&rotation.purpose).and_then(|k| k.last())
+            && known.public_key != rotation.previous.public_key
+        {
+            if known.public_key == rotation.current.public_key {
+                return Ok(());
+            }
+            return Err(format!(
+                "key rotation of {} does not chain from the known key of epoch {}",
+                rotation.purpose, known.epoch
+            )
+            .into());
+        }
+
+        log::info!(peer = rotation.engine.to_text(), purpose = rotation.purpose, epoch = rotation.current.epoch; "peer key rotated");
+        keys.insert(rotation.purpose, vec![rotation.previous, rotation.current]);
+        self.ctx.cache_store_set(&key, keys, ver).await?;
+        Ok(())
+    }
+
+    /// Retrieves today's budget usage of the agent.
+    pub async fn get_budget_usage(&self, agent: &str) -> Result<BudgetUsage, BoxError> {
+        let mut usage = match self
+            .ctx
+            .kv::<BudgetUsage>(BUDGET_NAMESPACE)
+            .get(agent)
+            .await?
+        {
+            Some((usage, _)) => usage,
+            None => BudgetUsage::default(),
+        };
+        usage.refresh(unix_ms());
+        Ok(usage)
+    }
+
+    /// Charges a tool call of the agent to its daily budget, if the tool has one.
+    /// Returns an error if the budget is exhausted.
+    pub(crate) async fn consume_budget(
+        &self,
+        agent: &str,
+        tool: &str,
+        args: &str,
+    ) -> Result<(), BoxError> {
+        let budget = match self.budgets.as_ref().and_then(|p| p.get(agent, tool)) {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        let value = budget.value_of(args)?;
+        let now_ms = unix_ms();
+        if let Err(err) = self
+            .ctx
+            .kv::<BudgetUsage>(BUDGET_NAMESPACE)
+            .try_update(agent, BUDGET_UPDATE_RETRIES, |usage| {
+                let mut usage = usage.unwrap_or_default();
+                usage.consume(tool, budget, value, now_ms)?;
+                Ok(usage)
+            })
+            .await
+        {
+            log::warn!(agent = agent, tool = tool; "{}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    async fn load_api_keys(&self) -> Result<(Vec<ApiKey>, Option<UpdateVersion>), BoxError> {
+        match self
+            .ctx
+            .cache_store_get::<Vec<ApiKey>>(Self::api_keys_path())
+            .await
+        {
+            Ok((keys, ver)) => Ok((keys, Some(ver))),
+            Err(_) => Ok((Vec::new(), None)),
+        }
+    }
+
+    /// Lists the API keys, including the revoked ones.
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>, BoxError> {
+        let (keys, _) = self.load_api_keys().await?;
+        Ok(keys)
+    }
+
+    /// Creates an API key. Returns the key and its secret, which is not stored.
+    pub(crate) async fn create_api_key(
+        &self,
+        created_by: Principal,
+        args: CreateApiKeyArgs,
+    ) -> Result<(ApiKey, String), BoxError> {
+        if args.scope.agents.is_empty() && args.scope.tools.is_empty() {
+            return Err("API key scope is empty".into());
+        }
+        let (mut keys, ver) = self.load_api_keys().await?;
+        let (key, secret) = ApiKey::new(args, created_by, unix_ms());
+        keys.push(key.clone());
+        self.ctx
+            .cache_store_set(Self::api_keys_path(), keys, ver)
+            .await?;
+        log::info!(id = key.id.to_string(), name = key.name, created_by = created_by.to_text(); "API key created");
+        Ok((key, secret))
+    }
+
+    /// Replaces the secret of an API key and returns the new one.
+    /// The previous secret stops working immediately.
+    pub(crate) async fn rotate_api_key(&self, id: &Xid) -> Result<String, BoxError> {
+        let (mut keys, ver) = self.load_api_keys().await?;
+        let key = keys
+            .iter_mut()
+            .find(|k| &k.id == id && k.revoked_at.is_none())
+            .ok_or_else(|| format!("API key {} not found", id))?;
+        let secret = key.rotate(unix_ms());
+        self.ctx
+            .cache_store_set(Self::api_keys_path(), keys, ver)
+            .await?;
+        log::info!(id = id.to_string(); "API key rotated");
+        Ok(secret)
+    }
+
+    /// Revokes an API key. Revoked keys are kept for auditing.
+    pub(crate) async fn revoke_api_key(&self, id: &Xid) -> Result<ApiKey, BoxError> {
+        let (mut keys, ver) = self.load_api_keys().await?;
+        let key = keys
+            .iter_mut()
+            .find(|k| &k.id == id)
+            .ok_or_else(|| format!("API key {} not found", id))?;
+        key.revoked_at.get_or_insert_with(unix_ms);
+        let key = key.clone();
+        self.ctx
+            .cache_store_set(Self::api_keys_path(), keys, ver)
+            .await?;
+        log::info!(id = id.to_string(); "API key revoked");
+        Ok(key)
+    }
+
+    /// Retrieves the current usage of an API key.
+    pub async fn get_api_key_usage(&self, id: &Xid) -> ApiKeyUsage {
+        match self
+            .ctx
+            .cache_store_get::<ApiKeyUsage>(&Self::api_key_usage_path(id))
+            .await
+        {
+            Ok((mut usage, _)) => {
+                usage.refresh(unix_ms());
+                usage
+            }
+            Err(_) => ApiKeyUsage::default(),
+        }
+    }
+
+    /// Returns the limits of the caller, None if the caller has no quota.
+    /// The controller and managers have no quota.
+    fn quota_limits(&self, caller: &Principal) -> Option<&QuotaLimits> {
+        match &self.quotas {
+            Some(policy) if !self.is_manager(caller) => Some(policy.get(caller)),
+            _ => None,
+        }
+    }
+
+    /// Retrieves the remaining quota of the caller, None if the caller has no quota.
+    pub async fn get_remaining_quota(&self, caller: &Principal) -> Option<RemainingQuota> {
+        let limits = self.quota_limits(caller)?;
+        let usage = match self
+            .ctx
+            .kv::<QuotaUsage>(QUOTA_NAMESPACE)
+            .get(&caller.to_text())
+            .await
+        {
+            Ok(Some((usage, _))) => usage,
+            _ => QuotaUsage::default(),
+        };
+        Some(usage.remaining(limits, unix_ms()))
+    }
+
+    /// Charges a request to the quota of the caller, if the caller has one.
+    /// Returns an error if the quota is exhausted.
+    pub(crate) async fn consume_quota(&self, caller: &Principal) -> Result<(), BoxError> {
+        let limits = match self.quota_limits(caller) {
+            Some(limits) => limits,
+            None => return Ok(()),
+        };
+        let now_ms = unix_ms();
+        if let Err(err) = self
+            .ctx
+            .kv::<QuotaUsage>(QUOTA_NAMESPACE)
+            .try_update(&caller.to_text(), QUOTA_UPDATE_RETRIES, |usage| {
+                let mut usage = usage.unwrap_or_default();
+                usage.consume(limits, now_ms)?;
+                Ok(usage)
+            })
+            .await
+        {
+            log::warn!(caller = caller.to_text(); "{}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Charges the tokens of a request to the quota of the caller, if the caller has one.
+    pub(crate) async fn charge_quota_tokens(
+        &self,
+        caller: &Principal,
+        tokens: u64,
+    ) -> Result<(), BoxError> {
+        if tokens == 0 || self.quota_limits(caller).is_none() {
+            return Ok(());
+        }
+        let now_ms = unix_ms();
+        self.ctx
+            .kv::<QuotaUsage>(QUOTA_NAMESPACE)
+            .update(&caller.to_text(), QUOTA_UPDATE_RETRIES, |usage| {
+                let mut usage = usage.unwrap_or_default();
+                usage.add_tokens(tokens, now_ms);
+                usage
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Verifies an API key for the call, without charging it to the key's limits.
+    /// Returns the principal the key calls the engine as.
+    pub(crate) async fn verify_api_key(
+        &self,
+        secret: &str,
+        target: ApiKeyTarget<'_>,
+    ) -> Result<Principal, BoxError> {
+        let key = self.find_api_key(secret, target, unix_ms()).await?;
+        Ok(ApiKey::principal_of(&key.id))
+    }
+
+    async fn find_api_key(
+        &self,
+        secret: &str,
+        target: ApiKeyTarget<'_>,
+        now_ms: u64,
+    ) -> Result<ApiKey, BoxError> {
+        let id = ApiKey::id_of(secret)?;
+        let (keys, _) = self.load_api_keys().await?;
+        let key = keys
+            .into_iter()
+            .find(|k| k.id == id)
+            .ok_or("invalid API key")?;
+        key.verify(secret, target, now_ms)?;
+        Ok(key)
+    }
+
+    /// Verifies an API key for the call and charges it to the key's limits.
+    /// Returns the principal the key calls the engine as.
+    pub(crate) async fn authorize_api_key(
+        &self,
+        secret: &str,
+        target: ApiKeyTarget<'_>,
+    ) -> Result<Principal, BoxError> {
+        let now_ms = unix_ms();
+        let key = self.find_api_key(secret, target, now_ms).await?;
+        let id = key.id;
+
+        let usage_key = Self::api_key_usage_path(&id);
+        let (mut usage, ver) = match self.ctx.cache_store_get::<ApiKeyUsage>(&usage_key).await {
+            Ok((usage, ver)) => (usage, Some(ver)),
+            Err(_) => (ApiKeyUsage::default(), None),
+        };
+        if let Err(err) = usage.consume(&key.limits, now_ms) {
+            log::warn!(id = id.to_string(); "{}", err);
+            return Err(err);
+        }
+        self.ctx.cache_store_set(&usage_key, usage, ver).await?;
+        Ok(ApiKey::principal_of(&id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineBuilder;
+    use anda_core::{Path, PutMode, StoreFeatures, VERSIONED_TAG};
+    use ic_cose_types::to_cbor_bytes;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_legacy_thread_meta() {
+        let ctx = EngineBuilder::new().mock_ctx();
+        let management =
+            ManagementBuilder::new(Visibility::Private, Principal::anonymous()).build(&ctx.base);
+
+        // stored by an engine before thread metadata was versioned
+        let thread = ThreadMeta::new(Xid::new(), ctx.base.id, Principal::anonymous(), unix_ms());
+        let path = Path::from(Management::thread_meta_path(&thread.id));
+        management
+            .ctx
+            .store_put(&path, PutMode::Overwrite, to_cbor_bytes(&thread).into())
+            .await
+            .unwrap();
+
+        let mut meta = management.get_thread_meta(&thread.id).await.unwrap();
+        assert_eq!(meta.initiator, thread.initiator);
+        meta.description = Some("upgraded".to_string());
+        management.save_thread_meta(meta).await.unwrap();
+
+        let (data, _) = management.ctx.store_get(&path).await.unwrap();
+        let value: ciborium::Value = ciborium::from_reader(&data[..]).unwrap();
+        assert!(matches!(value, ciborium::Value::Tag(VERSIONED_TAG, _)));
+        let meta = management.get_thread_meta(&thread.id).await.unwrap();
+        assert_eq!(meta.description.as_deref(), Some("upgraded"));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_consume_budget() {
+        let ctx = EngineBuilder::new().mock_ctx();
+        let policy = BudgetPolicy::default().with_budget(
+            "treasurer",
+            "transfer",
+            ActionBudget {
+                max_calls: Some(3),
+                ..Default::default()
+            },
+        );
+        let management = ManagementBuilder::new(Visibility::Private, Principal::anonymous())
+            .with_budget_policy(policy)
+            .build(&ctx.base);
+
+        // concurrent calls must not overspend the budget
+        let results = futures::future::join_all(
+            (0..5).map(|_| management.consume_budget("treasurer", "transfer", "{}")),
+        )
+        .await;
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 3);
+        let usage = management.get_budget_usage("treasurer").await.unwrap();
+        assert_eq!(usage.tools["transfer"].calls, 3);
+        // tools without a budget are not charged
+        management
+            .consume_budget("treasurer", "balance", "{}")
+            .await
+            .unwrap();
+
+        // an unreadable usage is an error, not an empty usage
+        let path = Path::from("kv/budgets/treasurer");
+        management
+            .ctx
+            .store_put(&path, PutMode::Overwrite, b"invalid".to_vec().into())
             .await
+            .unwrap();
+        assert!(management.get_budget_usage("treasurer").await.is_err());
+        assert!(
+            management
+                .consume_budget("treasurer", "transfer", "{}")
+                .await
+                .is_err()
+        );
     }
 }
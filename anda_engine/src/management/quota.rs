@@ -0,0 +1,267 @@
+//! Per-caller quotas.
+//!
+//! A [`QuotaPolicy`] limits the requests per minute and the model tokens per day of each
+//! caller principal, so that a single user can't drain a public engine. The default limits
+//! apply to every caller, except the controller and managers, and can be overridden per
+//! principal. All anonymous callers share the quota of the anonymous principal, and the
+//! requests of accounts linked to a person are charged to the person.
+//!
+//! The usage is updated by compare and swap, so that concurrent requests of a caller, on
+//! one engine or several sharing the store, are all counted.
+//!
+//! A request is rejected when the caller has no requests left in the current minute or no
+//! tokens left today. The tokens of a run are charged after it, so the last run of the day
+//! can exceed the token quota.
+
+use anda_core::BoxError;
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const MINUTE_MS: u64 = 60 * 1000;
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// The limits of a caller. `None` means unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaLimits {
+    /// The maximum number of agent runs and tool calls per minute.
+    #[serde(default)]
+    pub requests_per_minute: Option<u64>,
+
+    /// The maximum number of model tokens, input and output, per day (UTC).
+    #[serde(default)]
+    pub tokens_per_day: Option<u64>,
+}
+
+/// The quotas of callers.
+///
+/// # Example
+/// ```toml
+/// [default]
+/// requests_per_minute = 10
+/// tokens_per_day = 200000
+///
+/// [principals.2vxsx-fae]
+/// requests_per_minute = 5
+/// tokens_per_day = 50000
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QuotaPolicy {
+    /// The limits of callers without their own limits.
+    #[serde(default)]
+    pub default: QuotaLimits,
+
+    /// The limits by principal text.
+    #[serde(default)]
+    pub principals: BTreeMap<String, QuotaLimits>,
+}
+
+impl QuotaPolicy {
+    /// Creates a QuotaPolicy from a TOML string.
+    pub fn from_toml(content: &str) -> Result<Self, BoxError> {
+        let policy: Self = toml::from_str(content)?;
+        for principal in policy.principals.keys() {
+            Principal::from_text(principal)
+                .map_err(|err| format!("invalid principal {:?}: {}", principal, err))?;
+        }
+        Ok(policy)
+    }
+
+    /// Sets the default limits.
+    pub fn with_default(mut self, limits: QuotaLimits) -> Self {
+        self.default = limits;
+        self
+    }
+
+    /// Sets the limits of a principal.
+    pub fn with_principal(mut self, principal: &Principal, limits: QuotaLimits) -> Self {
+        self.principals.insert(principal.to_text(), limits);
+        self
+    }
+
+    /// Returns the limits of the principal.
+    pub fn get(&self, principal: &Principal) -> &QuotaLimits {
+        self.principals
+            .get(&principal.to_text())
+            .unwrap_or(&self.default)
+    }
+}
+
+/// The usage of a caller in the current minute and day (UTC).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    /// The minute, in minutes since the unix epoch.
+    pub minute: u64,
+    pub minute_requests: u64,
+
+    /// The day, in days since the unix epoch.
+    pub day: u64,
+    pub day_tokens: u64,
+}
+
+impl QuotaUsage {
+    /// Resets the counters of the previous minute and day.
+    pub fn refresh(&mut self, now_ms: u64) {
+        let minute = now_ms / MINUTE_MS;
+        if self.minute != minute {
+            self.minute = minute;
+            self.minute_requests = 0;
+        }
+        let day = now_ms / DAY_MS;
+        if self.day != day {
+            self.day = day;
+            self.day_tokens = 0;
+        }
+    }
+
+    /// Records a request if it fits in the limits, or returns an error without recording it.
+    pub fn consume(&mut self, limits: &QuotaLimits, now_ms: u64) -> Result<(), BoxError> {
+        self.refresh(now_ms);
+        if let Some(max) = limits.requests_per_minute
+            && self.minute_requests >= max
+        {
+            return Err(format!("rate limit exceeded: {} requests per minute", max).into());
+        }
+        if let Some(max) = limits.tokens_per_day
+            && self.day_tokens >= max
+        {
+            return Err(format!("daily quota exceeded: {} tokens", max).into());
+        }
+        self.minute_requests += 1;
+        Ok(())
+    }
+
+    /// Records the tokens of a request.
+    pub fn add_tokens(&mut self, tokens: u64, now_ms: u64) {
+        self.refresh(now_ms);
+        self.day_tokens = self.day_tokens.saturating_add(tokens);
+    }
+
+    /// Returns the remaining quota under the limits.
+    pub fn remaining(&self, limits: &QuotaLimits, now_ms: u64) -> RemainingQuota {
+        let mut usage = self.clone();
+        usage.refresh(now_ms);
+        RemainingQuota {
+            requests_per_minute: limits
+                .requests_per_minute
+                .map(|max| max.saturating_sub(usage.minute_requests)),
+            tokens_per_day: limits
+                .tokens_per_day
+                .map(|max| max.saturating_sub(usage.day_tokens)),
+            minute_reset_at: (usage.minute + 1) * MINUTE_MS,
+            day_reset_at: (usage.day + 1) * DAY_MS,
+        }
+    }
+}
+
+/// The remaining quota of a caller. `None` means unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemainingQuota {
+    /// The requests left in the current minute.
+    pub requests_per_minute: Option<u64>,
+
+    /// The tokens left today.
+    pub tokens_per_day: Option<u64>,
+
+    /// When the requests are reset, unix timestamp in milliseconds.
+    pub minute_reset_at: u64,
+
+    /// When the tokens are reset, unix timestamp in milliseconds.
+    pub day_reset_at: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::EngineBuilder,
+        management::{ManagementBuilder, Visibility},
+    };
+
+    #[test]
+    fn test_quota_usage() {
+        let user = Principal::from_text("2vxsx-fae").unwrap();
+        let policy = QuotaPolicy::from_toml(
+            r#"
+            [default]
+            requests_per_minute = 2
+            tokens_per_day = 1000
+
+            [principals.2vxsx-fae]
+            tokens_per_day = 100
+            "#,
+        )
+        .unwrap();
+        assert!(QuotaPolicy::from_toml("[principals.alice]").is_err());
+        assert_eq!(
+            policy.get(&Principal::management_canister()).tokens_per_day,
+            Some(1000)
+        );
+        let limits = policy.get(&user);
+        assert_eq!(limits.requests_per_minute, None);
+
+        let now_ms = 100 * DAY_MS;
+        let mut usage = QuotaUsage::default();
+        let limits = &policy.default;
+        usage.consume(limits, now_ms).unwrap();
+        usage.consume(limits, now_ms + 1).unwrap();
+        assert!(usage.consume(limits, now_ms + 2).is_err());
+        assert_eq!(
+            usage.remaining(limits, now_ms + 2),
+            RemainingQuota {
+                requests_per_minute: Some(0),
+                tokens_per_day: Some(1000),
+                minute_reset_at: now_ms + MINUTE_MS,
+                day_reset_at: now_ms + DAY_MS,
+            }
+        );
+        usage.consume(limits, now_ms + MINUTE_MS).unwrap();
+
+        usage.add_tokens(1200, now_ms + MINUTE_MS);
+        assert_eq!(
+            usage.remaining(limits, now_ms + MINUTE_MS).tokens_per_day,
+            Some(0)
+        );
+        let err = usage.consume(limits, now_ms + 2 * MINUTE_MS).unwrap_err();
+        assert!(err.to_string().contains("daily quota exceeded"));
+        // a new day
+        usage.consume(limits, now_ms + DAY_MS).unwrap();
+        assert_eq!(usage.day_tokens, 0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_quotas() {
+        let ctx = EngineBuilder::new().mock_ctx();
+        let controller = Principal::management_canister();
+        let user = Principal::anonymous();
+        let management = ManagementBuilder::new(Visibility::Public, controller)
+            .with_quota_policy(QuotaPolicy::default().with_default(QuotaLimits {
+                requests_per_minute: Some(1),
+                tokens_per_day: Some(100),
+            }))
+            .build(&ctx.base);
+
+        assert_eq!(management.get_remaining_quota(&controller).await, None);
+        management.consume_quota(&controller).await.unwrap();
+        management.consume_quota(&controller).await.unwrap();
+
+        let remaining = management.get_remaining_quota(&user).await.unwrap();
+        assert_eq!(remaining.requests_per_minute, Some(1));
+        assert_eq!(remaining.tokens_per_day, Some(100));
+        management.consume_quota(&user).await.unwrap();
+        management.charge_quota_tokens(&user, 60).await.unwrap();
+        let remaining = management.get_remaining_quota(&user).await.unwrap();
+        assert_eq!(remaining.requests_per_minute, Some(0));
+        assert_eq!(remaining.tokens_per_day, Some(40));
+        assert!(management.consume_quota(&user).await.is_err());
+
+        // concurrent first requests are all counted
+        let other = Principal::from_slice(&[1, 2, 3]);
+        let charges = (0..8).map(|_| management.charge_quota_tokens(&other, 10));
+        for res in futures::future::join_all(charges).await {
+            res.unwrap();
+        }
+        let remaining = management.get_remaining_quota(&other).await.unwrap();
+        assert_eq!(remaining.tokens_per_day, Some(20));
+    }
+}
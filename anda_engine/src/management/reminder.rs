@@ -0,0 +1,266 @@
+use anda_core::{
+    BoxError, FunctionDefinition, Migrate, Resource, StateFeatures, Tool, ToolOutput, Value, Xid,
+    gen_schema_for,
+};
+use async_trait::async_trait;
+use candid::Principal;
+use ic_cose_types::ANONYMOUS;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, sync::Arc};
+use structured_logger::unix_ms;
+
+use super::Management;
+use crate::id::new_xid;
+use crate::{context::BaseCtx, extension::calendar::parse_datetime};
+
+/// A reminder set by a user, delivered back to the thread and connector it was set from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Reminder {
+    pub id: Xid,
+
+    /// The message to deliver.
+    pub message: String,
+
+    /// When to deliver the reminder, unix timestamp in milliseconds.
+    pub due_at: u64,
+
+    /// The user who set the reminder.
+    pub caller: Principal,
+
+    /// The thread the reminder was set from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<Xid>,
+
+    /// The username in the connector the reminder was set from, see [`anda_core::RequestMeta::user`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    pub created_at: u64,
+}
+
+impl Migrate for Reminder {
+    const VERSION: u32 = 1;
+}
+
+/// Delivers due reminders to the connector they were set from, e.g. a Telegram or
+/// Discord bot, by the reminder's user and thread.
+///
+/// It also delivers the follow-ups of suspended workflows resumed by the engine, as
+/// reminders with the messages of the next steps.
+#[async_trait]
+pub trait ReminderNotifier: Send + Sync {
+    async fn notify(&self, ctx: &BaseCtx, reminder: &Reminder) -> Result<(), BoxError>;
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ReminderToolArgs {
+    /// The method to call.
+    pub method: ReminderToolMethod,
+
+    /// The message of the reminder, required to set a reminder.
+    pub message: Option<String>,
+
+    /// When to remind, in RFC 3339 format, e.g. "2026-10-16T09:00:00+08:00".
+    pub due_at: Option<String>,
+
+    /// When to remind, in minutes from now. Used if due_at is not provided.
+    pub in_minutes: Option<u64>,
+
+    /// The reminder ID, required to cancel a reminder.
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReminderToolMethod {
+    SetReminder,
+    ListReminders,
+    CancelReminder,
+}
+
+/// Represents a tool to manage the reminders of the user.
+pub struct ReminderTool {
+    management: Arc<Management>,
+    schema: Value,
+}
+
+impl ReminderTool {
+    pub const NAME: &'static str = "sys_reminders";
+
+    pub fn new(management: Arc<Management>) -> Self {
+        let schema = gen_schema_for::<ReminderToolArgs>();
+        Self { management, schema }
+    }
+}
+
+impl Tool<BaseCtx> for ReminderTool {
+    type Args = ReminderToolArgs;
+    type Output = Vec<Reminder>;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Sets, lists and cancels reminders for the user. A reminder is delivered back to this conversation when it is due.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        if resources.is_some() {
+            return Err("resources are not supported".into());
+        }
+        let caller = ctx.caller();
+        if caller == ANONYMOUS {
+            return Err("anonymous user is not allowed".into());
+        }
+
+        let now_ms = unix_ms();
+        match args.method {
+            ReminderToolMethod::SetReminder => {
+                let message = args
+                    .message
+                    .filter(|m| !m.trim().is_empty())
+                    .ok_or("message is required")?;
+                let due_at = match (&args.due_at, args.in_minutes) {
+                    (Some(due_at), _) => parse_datetime(due_at)?,
+                    (None, Some(minutes)) => now_ms + minutes * 60 * 1000,
+                    (None, None) => return Err("due_at or in_minutes is required".into()),
+                };
+                if due_at <= now_ms {
+                    return Err("the reminder should be due in the future".into());
+                }
+                let meta = ctx.meta();
+                let reminder = Reminder {
+                    id: new_xid(),
+                    message,
+                    due_at,
+                    caller,
+                    thread: meta.thread.clone(),
+                    user: meta.user.clone(),
+                    created_at: now_ms,
+                };
+                self.management.add_reminder(reminder.clone()).await?;
+                Ok(ToolOutput::new(vec![reminder]))
+            }
+
+            ReminderToolMethod::ListReminders => {
+                let reminders = self.management.list_reminders(&caller).await?;
+                Ok(ToolOutput::new(reminders))
+            }
+
+            ReminderToolMethod::CancelReminder => {
+                let id = Xid::from_str(args.id.as_deref().ok_or("id is required")?)?;
+                let reminder = self.management.cancel_reminder(&caller, &id).await?;
+                Ok(ToolOutput::new(vec![reminder]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::EngineBuilder,
+        management::{ManagementBuilder, Visibility},
+    };
+    use anda_core::RequestMeta;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_reminder_tool() {
+        let ctx = EngineBuilder::new().mock_ctx();
+        let management = Arc::new(
+            ManagementBuilder::new(Visibility::Private, Principal::anonymous()).build(&ctx.base),
+        );
+        let tool = ReminderTool::new(management.clone());
+        let alice =
+            Principal::from_text("77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe")
+                .unwrap();
+        let thread = Xid::new();
+        let tool_ctx = ctx
+            .child_base_with(
+                alice,
+                ReminderTool::NAME,
+                RequestMeta {
+                    thread: Some(thread.clone()),
+                    user: Some("alice".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let set = |message: &str, in_minutes: u64| ReminderToolArgs {
+            method: ReminderToolMethod::SetReminder,
+            message: Some(message.to_string()),
+            due_at: None,
+            in_minutes: Some(in_minutes),
+            id: None,
+        };
+        let res = tool
+            .call(tool_ctx.clone(), set("stand-up", 10), None)
+            .await
+            .unwrap();
+        assert_eq!(res.output[0].thread, Some(thread));
+        assert_eq!(res.output[0].user.as_deref(), Some("alice"));
+        tool.call(tool_ctx.clone(), set("lunch", 5), None)
+            .await
+            .unwrap();
+
+        let list = ReminderToolArgs {
+            method: ReminderToolMethod::ListReminders,
+            message: None,
+            due_at: None,
+            in_minutes: None,
+            id: None,
+        };
+        let res = tool
+            .call(tool_ctx.clone(), list.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            res.output
+                .iter()
+                .map(|r| r.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["lunch", "stand-up"]
+        );
+
+        let due = management
+            .take_due_reminders(unix_ms() + 6 * 60 * 1000)
+            .await
+            .unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].message, "lunch");
+
+        let res = tool
+            .call(
+                tool_ctx.clone(),
+                ReminderToolArgs {
+                    method: ReminderToolMethod::CancelReminder,
+                    id: Some(res.output[1].id.to_string()),
+                    ..list.clone()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.output[0].message, "stand-up");
+        let res = tool.call(tool_ctx, list, None).await.unwrap();
+        assert!(res.output.is_empty());
+    }
+}
@@ -0,0 +1,202 @@
+use anda_core::{BoxError, CacheStoreFeatures, Migrate, Versioned, Xid};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::Management;
+
+/// The commands that start a new session by default.
+pub const DEFAULT_RESET_COMMANDS: [&str; 2] = ["/new", "/reset"];
+
+/// The session policy of a connector, e.g. a Telegram or Discord bot.
+///
+/// Requests from the connector's users that do not set a thread are routed to the user's
+/// current session thread, which the engine resets:
+/// - after the session is idle for `idle_timeout`;
+/// - when it reaches `max_turns` turns;
+/// - when the user sends one of the `reset_commands`, e.g. "/new". The rest of the
+///   message, if any, is the prompt of the first turn of the new session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionPolicy {
+    /// How long a session can be idle before it is reset, if limited.
+    pub idle_timeout: Option<Duration>,
+
+    /// The maximum number of turns of a session, if limited.
+    pub max_turns: Option<u32>,
+
+    /// The commands that reset the session, matched case-insensitively at the start of
+    /// the prompt. [`DEFAULT_RESET_COMMANDS`] by default.
+    pub reset_commands: Vec<String>,
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self {
+            idle_timeout: None,
+            max_turns: None,
+            reset_commands: DEFAULT_RESET_COMMANDS
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl SessionPolicy {
+    /// Sets how long a session can be idle before it is reset.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Sets the maximum number of turns of a session.
+    pub fn with_max_turns(mut self, max_turns: u32) -> Self {
+        self.max_turns = Some(max_turns);
+        self
+    }
+
+    /// Sets the commands that reset the session.
+    pub fn with_reset_commands(mut self, commands: Vec<String>) -> Self {
+        self.reset_commands = commands;
+        self
+    }
+
+    /// Returns the rest of the prompt if it starts with a reset command.
+    pub fn strip_reset_command<'a>(&self, prompt: &'a str) -> Option<&'a str> {
+        let prompt = prompt.trim_start();
+        self.reset_commands.iter().find_map(|cmd| {
+            let head = prompt.get(..cmd.len())?;
+            let rest = &prompt[cmd.len()..];
+            if head.eq_ignore_ascii_case(cmd)
+                && (rest.is_empty() || rest.starts_with(char::is_whitespace))
+            {
+                Some(rest.trim())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns true if the session should be reset before the next turn at `now_ms`.
+    pub fn is_expired(&self, session: &Session, now_ms: u64) -> bool {
+        if let Some(idle_timeout) = self.idle_timeout
+            && now_ms.saturating_sub(session.last_active_at) >= idle_timeout.as_millis() as u64
+        {
+            return true;
+        }
+        matches!(self.max_turns, Some(max_turns) if session.turns >= max_turns)
+    }
+}
+
+/// The current session of a connector's user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// The thread of the session.
+    pub thread: Xid,
+
+    /// The Unix timestamp when the session started, in milliseconds.
+    pub started_at: u64,
+
+    /// The Unix timestamp of the last turn, in milliseconds.
+    pub last_active_at: u64,
+
+    /// The number of turns of the session.
+    pub turns: u32,
+}
+
+impl Migrate for Session {
+    const VERSION: u32 = 1;
+}
+
+impl Management {
+    fn session_path(caller: &Principal, account: &str) -> String {
+        use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+        format!(
+            "SES_{}_{}.cbor",
+            caller.to_text(),
+            BASE64_URL_SAFE_NO_PAD.encode(account)
+        )
+    }
+
+    /// Returns the current session of the connector's account.
+    pub async fn get_session(&self, caller: &Principal, account: &str) -> Option<Session> {
+        self.ctx
+            .cache_store_get::<Versioned<Session>>(&Self::session_path(caller, account))
+            .await
+            .ok()
+            .map(|(Versioned(session), _)| session)
+    }
+
+    /// Records a turn of the session in the thread, which starts a new session if the thread
+    /// is not the session's one.
+    pub(crate) async fn record_session_turn(
+        &self,
+        caller: &Principal,
+        account: &str,
+        thread: &Xid,
+        now_ms: u64,
+    ) -> Result<Session, BoxError> {
+        let session = match self.get_session(caller, account).await {
+            Some(mut session) if &session.thread == thread => {
+                session.last_active_at = now_ms;
+                session.turns += 1;
+                session
+            }
+            _ => Session {
+                thread: thread.clone(),
+                started_at: now_ms,
+                last_active_at: now_ms,
+                turns: 1,
+            },
+        };
+        self.ctx
+            .cache_store_set(
+                &Self::session_path(caller, account),
+                Versioned(session.clone()),
+                None,
+            )
+            .await?;
+        Ok(session)
+    }
+
+    /// Ends the current session of the connector's account.
+    pub(crate) async fn end_session(
+        &self,
+        caller: &Principal,
+        account: &str,
+    ) -> Result<(), BoxError> {
+        self.ctx
+            .cache_store_delete(&Self::session_path(caller, account))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_policy() {
+        let policy = SessionPolicy::default()
+            .with_idle_timeout(Duration::from_secs(3600))
+            .with_max_turns(3);
+        assert_eq!(policy.strip_reset_command("/new"), Some(""));
+        assert_eq!(policy.strip_reset_command("  /NEW  hello "), Some("hello"));
+        assert_eq!(policy.strip_reset_command("/reset"), Some(""));
+        assert_eq!(policy.strip_reset_command("/news"), None);
+        assert_eq!(policy.strip_reset_command("what is /new?"), None);
+        assert_eq!(policy.strip_reset_command("/"), None);
+
+        let mut session = Session {
+            thread: Xid::new(),
+            started_at: 0,
+            last_active_at: 1000,
+            turns: 1,
+        };
+        assert!(!policy.is_expired(&session, 1000 + 3_599_999));
+        assert!(policy.is_expired(&session, 1000 + 3_600_000));
+        session.turns = 3;
+        assert!(policy.is_expired(&session, 1000));
+        assert!(!SessionPolicy::default().is_expired(&session, u64::MAX));
+    }
+}
@@ -1,6 +1,6 @@
 use anda_core::{
-    BoxError, FunctionDefinition, Resource, StateFeatures, Tool, ToolOutput, UpdateVersion, Value,
-    gen_schema_for,
+    BoxError, FunctionDefinition, Migrate, Resource, StateFeatures, Tool, ToolOutput,
+    UpdateVersion, Value, gen_schema_for,
 };
 use candid::{CandidType, Principal};
 use ic_cose_types::ANONYMOUS;
@@ -50,6 +50,10 @@ pub struct UserState {
     pub version: Option<UpdateVersion>,
 }
 
+impl Migrate for UserState {
+    const VERSION: u32 = 1;
+}
+
 #[derive(Debug, Clone)]
 pub struct UserStateWrapper {
     pub(crate) state: UserState,
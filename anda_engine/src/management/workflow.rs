@@ -0,0 +1,105 @@
+use anda_core::{BoxError, CacheStoreFeatures, Migrate, UpdateVersion, Versioned, Xid};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+
+use super::Management;
+
+/// A workflow suspended in a thread until a webhook event or a timestamp, see
+/// [`anda_core::WorkflowWait`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SuspendedWorkflow {
+    /// The thread of the workflow.
+    pub thread: Xid,
+
+    /// The name of the workflow.
+    pub workflow: String,
+
+    /// The user who runs the workflow.
+    pub caller: Principal,
+
+    /// The username in the connector the workflow runs from, see [`anda_core::RequestMeta::user`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// The webhook event that resumes the workflow, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+
+    /// When the wait ends, unix timestamp in milliseconds, if limited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+
+    pub created_at: u64,
+}
+
+impl Migrate for SuspendedWorkflow {
+    const VERSION: u32 = 1;
+}
+
+impl Management {
+    fn suspended_workflows_path() -> &'static str {
+        "WORKFLOWS.cbor"
+    }
+
+    async fn load_suspended_workflows(
+        &self,
+    ) -> Result<(Vec<SuspendedWorkflow>, Option<UpdateVersion>), BoxError> {
+        match self
+            .ctx
+            .cache_store_get::<Versioned<Vec<SuspendedWorkflow>>>(Self::suspended_workflows_path())
+            .await
+        {
+            Ok((Versioned(list), ver)) => Ok((list, Some(ver))),
+            Err(_) => Ok((Vec::new(), None)),
+        }
+    }
+
+    /// Lists the suspended workflows, the earliest due first, then the ones without time limit.
+    pub async fn list_suspended_workflows(&self) -> Result<Vec<SuspendedWorkflow>, BoxError> {
+        let (list, _) = self.load_suspended_workflows().await?;
+        Ok(list)
+    }
+
+    /// Sets the suspended workflow of the thread, replacing the current one if any, or
+    /// removes it if `suspended` is None.
+    pub(crate) async fn set_suspended_workflow(
+        &self,
+        thread: &Xid,
+        suspended: Option<SuspendedWorkflow>,
+    ) -> Result<(), BoxError> {
+        let (mut list, ver) = self.load_suspended_workflows().await?;
+        let len = list.len();
+        list.retain(|s| &s.thread != thread);
+        if list.len() == len && suspended.is_none() {
+            return Ok(());
+        }
+        if let Some(suspended) = suspended {
+            let due = suspended.until.unwrap_or(u64::MAX);
+            let idx = list.partition_point(|s| s.until.unwrap_or(u64::MAX) <= due);
+            list.insert(idx, suspended);
+        }
+        self.ctx
+            .cache_store_set(Self::suspended_workflows_path(), Versioned(list), ver)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes and returns the suspended workflows due at the time. The removal is
+    /// versioned, so a workflow is resumed by only one of the engine instances sharing
+    /// the store.
+    pub(crate) async fn take_due_workflows(
+        &self,
+        now_ms: u64,
+    ) -> Result<Vec<SuspendedWorkflow>, BoxError> {
+        let (mut list, ver) = self.load_suspended_workflows().await?;
+        let n = list.partition_point(|s| s.until.is_some_and(|until| until <= now_ms));
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let due: Vec<SuspendedWorkflow> = list.drain(..n).collect();
+        self.ctx
+            .cache_store_set(Self::suspended_workflows_path(), Versioned(list), ver)
+            .await?;
+        Ok(due)
+    }
+}
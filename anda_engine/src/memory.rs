@@ -0,0 +1,329 @@
+//! # Conversation Memory Module
+//!
+//! A [`Memory`] keeps the history of each thread, so that agents continue the conversation
+//! of a thread without managing their own chat history. The engine loads the thread's
+//! history into the completion requests of the agent that serves the thread, when they
+//! have no chat history of their own, and records each turn: the prompt and the final reply.
+//!
+//! When the tokens of the history exceed the memory's budget, the older turns are summarized
+//! with the summarizer model, usually a cheaper one than the agent's, and replaced in the
+//! history by the summary. The most recent messages are always kept as they are. The
+//! compacted history is returned in [`AgentOutput::full_history`](anda_core::AgentOutput),
+//! and the usage of the summarization is added to the output's usage.
+//!
+//! The memory of a thread is kept in the store of the agent, so every agent of a thread
+//! has its own memory. It is updated by compare and swap, so that concurrent turns of a
+//! thread do not lose each other's messages. The memory is kept in plaintext, so it can't
+//! be enabled together with thread encryption.
+//!
+//! # Example
+//! ```rust,ignore
+//! let memory = Memory::new(8000)
+//!     .with_keep_messages(6)
+//!     .with_summarizer(Model::with_completer(Arc::new(openai.completion_model("gpt-4o-mini"))));
+//! let engine = Engine::builder().with_memory(memory);
+//! ```
+
+use anda_core::{
+    BoxError, CacheFeatures, CacheStoreFeatures, CompletionRequest, Migrate, Path, PutMode,
+    StoreFeatures, UpdateVersion, Usage, Value, Versioned, Xid,
+};
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use structured_logger::unix_ms;
+
+use crate::model::Model;
+
+/// The number of recent messages kept when the older ones are summarized.
+pub const DEFAULT_KEEP_MESSAGES: usize = 6;
+
+/// Conversation memory of threads, summarized above a token budget.
+#[derive(Clone)]
+pub struct Memory {
+    max_tokens: usize,
+    keep_messages: usize,
+    summarizer: Option<Model>,
+}
+
+impl Memory {
+    /// Creates a memory that summarizes the history of a thread above `max_tokens`.
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            keep_messages: DEFAULT_KEEP_MESSAGES,
+            summarizer: None,
+        }
+    }
+
+    /// Sets the number of recent messages kept as they are when the history is summarized,
+    /// [`DEFAULT_KEEP_MESSAGES`] by default. The kept messages start at a user message,
+    /// so a few more can be kept.
+    pub fn with_keep_messages(mut self, keep_messages: usize) -> Self {
+        self.keep_messages = keep_messages;
+        self
+    }
+
+    /// Sets the model that summarizes the history, the agent's model by default.
+    pub fn with_summarizer(mut self, summarizer: Model) -> Self {
+        self.summarizer = Some(summarizer);
+        self
+    }
+
+    /// Returns the token budget of the history of a thread.
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    /// Loads the memory of the thread, empty if there is none.
+    pub async fn load<C: CacheStoreFeatures>(&self, ctx: &C, thread: &Xid) -> ThreadMemory {
+        ctx.cache_store_get::<Versioned<ThreadMemory>>(&memory_path(thread))
+            .await
+            .map(|(Versioned(mut memory), ver)| {
+                memory.version = Some(ver);
+                memory
+            })
+            .unwrap_or_default()
+    }
+
+    /// Saves the memory of the thread if it was not changed since it was loaded.
+    /// Returns a conflict error otherwise, the memory should be loaded again and updated.
+    pub async fn save<C: CacheStoreFeatures>(
+        &self,
+        ctx: &C,
+        thread: &Xid,
+        mut memory: ThreadMemory,
+    ) -> Result<(), BoxError> {
+        let key = memory_path(thread);
+        memory.updated_at = unix_ms();
+        let res = match memory.version.take() {
+            Some(ver) => ctx
+                .cache_store_set(&key, Versioned(memory), Some(ver))
+                .await
+                .map(|_| ()),
+            None => ctx
+                .store_put(
+                    &Path::from(key.as_str()),
+                    PutMode::Create,
+                    to_cbor_bytes(&Versioned(memory)).into(),
+                )
+                .await
+                .map(|_| ()),
+        };
+        if res.is_err() {
+            // the next load reads the latest memory from the store
+            ctx.cache_delete(&key).await;
+        }
+        res
+    }
+
+    /// Summarizes the older messages of the memory if its history exceeds the budget.
+    /// Returns the usage of the summarization, or None if the history fits.
+    pub async fn compact(
+        &self,
+        model: &Model,
+        memory: &mut ThreadMemory,
+    ) -> Result<Option<Usage>, BoxError> {
+        let model = self.summarizer.as_ref().unwrap_or(model);
+        if memory.count_tokens(model) <= self.max_tokens
+            || memory.messages.len() <= self.keep_messages
+        {
+            return Ok(None);
+        }
+
+        // keep whole turns: the kept messages start at a user message
+        let mut split = memory.messages.len() - self.keep_messages;
+        while split > 0
+            && memory.messages[split].get("role").and_then(|v| v.as_str()) != Some("user")
+        {
+            split -= 1;
+        }
+        if split == 0 {
+            return Ok(None);
+        }
+
+        let (summary, usage) = self
+            .summarize(model, memory.summary.as_deref(), &memory.messages[..split])
+            .await?;
+        memory.summary = Some(summary);
+        memory.messages.drain(..split);
+        memory.summarized += split as u64;
+        Ok(Some(usage))
+    }
+
+    async fn summarize(
+        &self,
+        model: &Model,
+        summary: Option<&str>,
+        messages: &[Value],
+    ) -> Result<(String, Usage), BoxError> {
+        let mut transcript = String::new();
+        if let Some(summary) = summary {
+            transcript.push_str(&format!(
+                "Summary of the earlier conversation:\n{}\n\n",
+                summary
+            ));
+        }
+        for msg in messages {
+            let role = msg.get("role").and_then(|v| v.as_str()).unwrap_or("user");
+            let content = match msg.get("content") {
+                Some(Value::String(text)) => text.clone(),
+                Some(Value::Null) | None => continue,
+                Some(content) => content.to_string(),
+            };
+            transcript.push_str(&format!("{}: {}\n", role, content));
+        }
+
+        let req = CompletionRequest {
+            system: Some(
+                "Summarize the conversation, including the summary of its earlier part if any. \
+                Keep the user's goals and preferences, the decisions made, the key facts, names, \
+                numbers and identifiers, and the open questions. Output only the summary."
+                    .to_string(),
+            ),
+            prompt: transcript,
+            // the summary takes at most a quarter of the budget
+            max_tokens: Some(self.max_tokens / 4 + 1),
+            ..Default::default()
+        };
+        let output = model.completion(req).await?;
+        if let Some(reason) = output.failed_reason {
+            return Err(reason.into());
+        }
+        Ok((output.content, output.usage))
+    }
+}
+
+fn memory_path(thread: &Xid) -> String {
+    format!("MEM_{}", thread)
+}
+
+/// The memory of a thread: the summary of its older turns and the recent messages.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ThreadMemory {
+    /// The summary of the older turns, if they were summarized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+
+    /// The messages after the summary, in the chat history format of the completion request.
+    pub messages: Vec<Value>,
+
+    /// The number of messages replaced by the summary.
+    pub summarized: u64,
+
+    /// The Unix timestamp of the last update, in milliseconds.
+    pub updated_at: u64,
+
+    /// The version of the memory in the store, used for atomic updates.
+    #[serde(skip)]
+    pub version: Option<UpdateVersion>,
+}
+
+impl Migrate for ThreadMemory {
+    const VERSION: u32 = 1;
+}
+
+impl ThreadMemory {
+    /// Returns the chat history to send to the model: the summary as a system message,
+    /// then the recent messages.
+    pub fn history(&self) -> Vec<Value> {
+        let mut history = Vec::with_capacity(self.messages.len() + 1);
+        if let Some(summary) = &self.summary {
+            history.push(json!({
+                "role": "system",
+                "content": format!("Summary of the earlier conversation:\n{}", summary),
+            }));
+        }
+        history.extend(self.messages.iter().cloned());
+        history
+    }
+
+    /// Records a turn: the user's prompt and the assistant's reply.
+    pub fn push_turn(&mut self, prompt: &str, reply: &str) {
+        if !prompt.is_empty() {
+            self.messages
+                .push(json!({"role": "user", "content": prompt}));
+        }
+        self.messages
+            .push(json!({"role": "assistant", "content": reply}));
+    }
+
+    /// Returns the number of tokens of the history.
+    pub fn count_tokens(&self, model: &Model) -> usize {
+        self.history()
+            .iter()
+            .map(|msg| model.count_message_tokens(msg))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_compact() {
+        let model = Model::mock_implemented();
+        let memory = Memory::new(100).with_keep_messages(3);
+        let mut tm = ThreadMemory::default();
+        tm.push_turn("hello", "Hi, how can I help?");
+        assert!(memory.compact(&model, &mut tm).await.unwrap().is_none());
+
+        for i in 0..10 {
+            tm.push_turn(&format!("question {}", i), &"answer ".repeat(10));
+        }
+        assert!(tm.count_tokens(&model) > memory.max_tokens());
+        let usage = memory.compact(&model, &mut tm).await.unwrap();
+        assert!(usage.is_some());
+        // at least 3 messages are kept, from a user message
+        assert_eq!(tm.messages.len(), 4);
+        assert_eq!(tm.messages[0]["content"], "question 8");
+        assert_eq!(tm.summarized, 18);
+        // the mock model echoes the transcript
+        let summary = tm.summary.clone().unwrap();
+        assert!(summary.starts_with("user: hello\nassistant: Hi, how can I help?\n"));
+        assert!(summary.contains("user: question 7\n"));
+        assert!(!summary.contains("question 8"));
+
+        let history = tm.history();
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[0]["role"], "system");
+
+        // the previous summary is summarized with the next turns
+        for i in 10..13 {
+            tm.push_turn(&format!("question {}", i), "ok");
+        }
+        memory.compact(&model, &mut tm).await.unwrap().unwrap();
+        let summary = tm.summary.clone().unwrap();
+        assert!(summary.starts_with("Summary of the earlier conversation:\nuser: hello\n"));
+        assert_eq!(tm.messages[0]["content"], "question 11");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_save() {
+        let ctx = crate::engine::EngineBuilder::new().mock_ctx();
+        let memory = Memory::new(100);
+        let thread = Xid::new();
+
+        let mut tm = memory.load(&ctx, &thread).await;
+        assert!(tm.version.is_none());
+        let mut concurrent = tm.clone();
+        tm.push_turn("hello", "hi");
+        memory.save(&ctx, &thread, tm).await.unwrap();
+        // concurrent turns must not overwrite each other
+        concurrent.push_turn("hey", "hi");
+        let err = memory
+            .save(&ctx, &thread, concurrent.clone())
+            .await
+            .unwrap_err();
+        assert!(crate::store::is_conflict(&err), "{err}");
+
+        let mut tm = memory.load(&ctx, &thread).await;
+        assert_eq!(tm.messages.len(), 2);
+        let stale = tm.clone();
+        tm.push_turn("hey", "hi");
+        memory.save(&ctx, &thread, tm).await.unwrap();
+        assert!(memory.save(&ctx, &thread, stale).await.is_err());
+        assert_eq!(memory.load(&ctx, &thread).await.messages.len(), 4);
+    }
+}
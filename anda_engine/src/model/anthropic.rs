@@ -0,0 +1,941 @@
+//! Anthropic API client implementation for Anda Engine
+//!
+//! This module provides integration with Anthropic's Messages API, including:
+//! - Client configuration and management
+//! - Completion model handling, with tool use and streaming
+//! - Conversion between Anda's chat history and Anthropic's content blocks
+//!
+//! The chat history of requests and outputs is in the OpenAI format, as with the other
+//! providers, so that the tool calls of the engine and the chat histories of threads work
+//! unchanged with Claude models. It is converted to Anthropic messages when sending:
+//! - system messages become the `system` parameter;
+//! - assistant tool calls become `tool_use` blocks, and tool messages `tool_result` blocks;
+//! - consecutive messages of the same role are merged.
+//!
+//! Anthropic has no response format parameter: a requested JSON schema is added to the system
+//! prompt instead.
+
+use anda_core::{
+    AgentOutput, BoxError, BoxPinFut, CONTENT_TYPE_JSON, CompletionFeatures, CompletionRequest,
+    ContentPart, Message, ModelCapabilities, Resource, ToolCall, Usage as ModelUsage,
+};
+use futures::stream::BoxStream;
+use log::{Level::Debug, log_enabled};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{
+    CompletionFeaturesDyn, ProviderError, ProviderErrorKind,
+    stream::{EventParser, event_stream, take_data_lines},
+};
+use crate::APP_USER_AGENT;
+
+// ================================================================
+// Main Anthropic Client
+// ================================================================
+const API_BASE_URL: &str = "https://api.anthropic.com/v1";
+const API_VERSION: &str = "2023-06-01";
+
+/// The maximum number of output tokens if the request has none, as Anthropic requires it.
+const DEFAULT_MAX_TOKENS: usize = 4096;
+
+/// `claude-sonnet-4-0` completion model
+pub const CLAUDE_SONNET_4: &str = "claude-sonnet-4-0";
+/// `claude-opus-4-0` completion model
+pub const CLAUDE_OPUS_4: &str = "claude-opus-4-0";
+/// `claude-3-5-haiku-latest` completion model
+pub const CLAUDE_3_5_HAIKU: &str = "claude-3-5-haiku-latest";
+
+/// Anthropic API client configuration and HTTP client
+#[derive(Clone)]
+pub struct Client {
+    endpoint: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Creates a new Anthropic client instance with the provided API key
+    ///
+    /// # Arguments
+    /// * `api_key` - Anthropic API key for authentication
+    /// * `endpoint` - API endpoint, the official one if `None` or empty
+    pub fn new(api_key: &str, endpoint: Option<String>) -> Self {
+        let endpoint = endpoint.unwrap_or_else(|| API_BASE_URL.to_string());
+        let endpoint = if endpoint.is_empty() {
+            API_BASE_URL.to_string()
+        } else {
+            endpoint
+        };
+        Self {
+            endpoint,
+            http: reqwest::Client::builder()
+                .use_rustls_tls()
+                .https_only(true)
+                .http2_keep_alive_interval(Some(Duration::from_secs(25)))
+                .http2_keep_alive_timeout(Duration::from_secs(15))
+                .http2_keep_alive_while_idle(true)
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(180))
+                .gzip(true)
+                .user_agent(APP_USER_AGENT)
+                .default_headers({
+                    let mut headers = reqwest::header::HeaderMap::new();
+                    let ct: http::HeaderValue = CONTENT_TYPE_JSON.parse().unwrap();
+                    headers.insert(http::header::CONTENT_TYPE, ct.clone());
+                    headers.insert(http::header::ACCEPT, ct);
+                    headers.insert("x-api-key", api_key.parse().expect("API key should parse"));
+                    headers.insert("anthropic-version", API_VERSION.parse().unwrap());
+                    headers
+                })
+                .build()
+                .expect("Anthropic reqwest client should build"),
+        }
+    }
+
+    /// Creates a POST request builder for the given API path
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let url = format!("{}{}", self.endpoint, path);
+        self.http.post(url)
+    }
+
+    /// Creates a completion model with the given name
+    ///
+    /// # Arguments
+    /// * `model` - Name of the completion model to use, [`CLAUDE_SONNET_4`] if empty
+    pub fn completion_model(&self, model: &str) -> CompletionModel {
+        CompletionModel::new(
+            self.clone(),
+            if model.is_empty() {
+                CLAUDE_SONNET_4
+            } else {
+                model
+            },
+        )
+    }
+}
+
+/// Token usage information from Anthropic API
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u64>,
+}
+
+impl Usage {
+    /// Returns the input tokens, including the tokens written to and read from the cache.
+    fn total_input_tokens(&self) -> u64 {
+        self.input_tokens
+            + self.cache_creation_input_tokens.unwrap_or_default()
+            + self.cache_read_input_tokens.unwrap_or_default()
+    }
+}
+
+/// A content block of an Anthropic message.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    /// Other blocks, e.g. thinking, which are not returned to the engine.
+    #[serde(other)]
+    Other,
+}
+
+/// Response structure for Anthropic messages API
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompletionResponse {
+    pub id: String,
+    pub model: String,
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<String>,
+    pub usage: Usage,
+}
+
+impl CompletionResponse {
+    fn try_into(self, mut full_history: Vec<Value>) -> Result<AgentOutput, BoxError> {
+        let mut content = String::new();
+        let mut tool_calls = Vec::new();
+        for block in self.content {
+            match block {
+                ContentBlock::Text { text } => content.push_str(&text),
+                ContentBlock::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                    id,
+                    name,
+                    args: input.to_string(),
+                    result: None,
+                }),
+                ContentBlock::Other => {}
+            }
+        }
+        full_history.push(assistant_message(&content, &tool_calls));
+
+        Ok(AgentOutput {
+            content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            failed_reason: failed_reason(self.stop_reason),
+            full_history: Some(full_history),
+            model: Some(self.model),
+            usage: ModelUsage {
+                input_tokens: self.usage.total_input_tokens(),
+                output_tokens: self.usage.output_tokens,
+                requests: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+}
+
+/// Returns the failed reason of a stop reason, None for a normal stop.
+fn failed_reason(stop_reason: Option<String>) -> Option<String> {
+    match stop_reason {
+        Some(reason) if matches!(reason.as_str(), "end_turn" | "tool_use" | "stop_sequence") => {
+            None
+        }
+        Some(reason) => Some(reason),
+        None => Some("response without a stop reason".to_string()),
+    }
+}
+
+/// Returns the assistant message with the tool calls in the OpenAI format of the chat history.
+fn assistant_message(content: &str, tool_calls: &[ToolCall]) -> Value {
+    let mut message = json!({
+        "role": "assistant",
+        "content": content,
+    });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = tool_calls
+            .iter()
+            .map(|tc| {
+                json!({
+                    "id": tc.id,
+                    "type": "function",
+                    "function": {"name": tc.name, "arguments": tc.args},
+                })
+            })
+            .collect();
+    }
+    message
+}
+
+/// Converts a chat history in the OpenAI format to the system prompt and the messages
+/// of the Anthropic format.
+pub fn to_anthropic_messages(history: &[Value]) -> (Vec<String>, Vec<Value>) {
+    let mut system: Vec<String> = Vec::new();
+    let mut messages: Vec<Value> = Vec::new();
+    let mut push = |role: &str, blocks: Vec<Value>| {
+        if blocks.is_empty() {
+            return;
+        }
+        if let Some(last) = messages.last_mut()
+            && last["role"] == role
+            && let Some(content) = last["content"].as_array_mut()
+        {
+            content.extend(blocks);
+            return;
+        }
+        messages.push(json!({"role": role, "content": blocks}));
+    };
+
+    for msg in history {
+        let content = &msg["content"];
+        match msg["role"].as_str().unwrap_or_default() {
+            "system" | "developer" => {
+                let text = content_text(content);
+                if !text.is_empty() {
+                    system.push(text);
+                }
+            }
+            "assistant" => {
+                let mut blocks = content_blocks(content);
+                if let Some(calls) = msg["tool_calls"].as_array() {
+                    for call in calls {
+                        let args = call["function"]["arguments"].as_str().unwrap_or_default();
+                        blocks.push(json!({
+                            "type": "tool_use",
+                            "id": call["id"],
+                            "name": call["function"]["name"],
+                            "input": serde_json::from_str::<Value>(args)
+                                .ok()
+                                .filter(|v| v.is_object())
+                                .unwrap_or_else(|| json!({})),
+                        }));
+                    }
+                }
+                push("assistant", blocks);
+            }
+            "tool" => push(
+                "user",
+                vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": msg["tool_call_id"],
+                    "content": content_text(content),
+                })],
+            ),
+            _ => push("user", content_blocks(content)),
+        }
+    }
+    (system, messages)
+}
+
+/// Converts the content of a message to Anthropic content blocks. Parts that are already
+/// Anthropic blocks are kept.
+fn content_blocks(content: &Value) -> Vec<Value> {
+    match content {
+        Value::Null => Vec::new(),
+        Value::String(text) if text.is_empty() => Vec::new(),
+        Value::String(text) => vec![json!({"type": "text", "text": text})],
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(
+                |part| match serde_json::from_value::<ContentPart>(part.clone()) {
+                    Ok(part) => part.to_anthropic(),
+                    Err(_) => Some(part.clone()),
+                },
+            )
+            .collect(),
+        other => vec![json!({"type": "text", "text": other.to_string()})],
+    }
+}
+
+/// Returns the text of the content of a message.
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        other => other.to_string(),
+    }
+}
+
+/// Returns the instruction for a response format of the OpenAI API.
+fn response_format_instruction(format: &Value) -> Option<String> {
+    match format["type"].as_str() {
+        Some("json_schema") => Some(format!(
+            "Respond only with a JSON object that conforms to this JSON schema:\n{}",
+            format["json_schema"]["schema"]
+        )),
+        Some("json_object") => Some("Respond only with a JSON object.".to_string()),
+        _ => None,
+    }
+}
+
+/// Completion model implementation for Anthropic API
+#[derive(Clone)]
+pub struct CompletionModel {
+    client: Client,
+    pub model: String,
+}
+
+impl CompletionModel {
+    /// Creates a new completion model instance
+    ///
+    /// # Arguments
+    /// * `client` - Anthropic client instance
+    /// * `model` - Name of the completion model
+    pub fn new(client: Client, model: &str) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+        }
+    }
+
+    /// Builds the JSON body of a messages request, and the full history of messages in
+    /// the OpenAI format that the response is appended to.
+    pub fn request_body(&self, mut req: CompletionRequest) -> (Value, Vec<Value>) {
+        let mut full_history = if let Some(system) = &req.system {
+            vec![json!(Message {
+                role: "system".into(),
+                content: system.to_owned().into(),
+                ..Default::default()
+            })]
+        } else {
+            vec![]
+        };
+
+        full_history.append(&mut req.chat_history);
+
+        if !req.content_parts.is_empty() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: json!(req.user_content_parts()),
+                ..Default::default()
+            }));
+        } else if let Some(prompt) = req.prompt_with_context() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: prompt.into(),
+                ..Default::default()
+            }));
+        }
+
+        let (mut system, messages) = to_anthropic_messages(&full_history);
+        if let Some(instruction) = req
+            .response_format
+            .as_ref()
+            .and_then(response_format_instruction)
+        {
+            system.push(instruction);
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": req.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "messages": messages,
+        });
+
+        let obj = body.as_object_mut().unwrap();
+        if !system.is_empty() {
+            obj.insert("system".to_string(), Value::from(system.join("\n\n")));
+        }
+
+        if let Some(temperature) = req.temperature {
+            obj.insert("temperature".to_string(), Value::from(temperature));
+        }
+
+        if let Some(stop) = req.stop {
+            obj.insert("stop_sequences".to_string(), Value::from(stop));
+        }
+
+        if !req.tools.is_empty() {
+            obj.insert(
+                "tools".to_string(),
+                req.tools
+                    .into_iter()
+                    .map(|tool| {
+                        json!({
+                            "name": tool.name,
+                            "description": tool.description,
+                            "input_schema": tool.parameters,
+                        })
+                    })
+                    .collect(),
+            );
+            obj.insert(
+                "tool_choice".to_string(),
+                if req.tool_choice_required {
+                    json!({"type": "any"})
+                } else {
+                    json!({"type": "auto"})
+                },
+            );
+        }
+
+        (body, full_history)
+    }
+}
+
+impl CompletionFeatures for CompletionModel {
+    async fn completion(
+        &self,
+        req: CompletionRequest,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<AgentOutput, BoxError> {
+        CompletionFeaturesDyn::completion(self, req).await
+    }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+        _resources: Option<Vec<Resource>>,
+    ) -> BoxStream<'_, Result<AgentOutput, BoxError>> {
+        CompletionFeaturesDyn::completion_stream(self, req)
+    }
+}
+
+impl CompletionFeaturesDyn for CompletionModel {
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (body, full_history) = self.request_body(req);
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            if log_enabled!(Debug)
+                && let Ok(val) = serde_json::to_string(&body)
+            {
+                log::debug!(request = val; "Anthropic messages request");
+            }
+
+            let response = client.post("/messages").json(&body).send().await?;
+            if response.status().is_success() {
+                let text = response.text().await?;
+                match serde_json::from_str::<CompletionResponse>(&text) {
+                    Ok(res) => {
+                        if log_enabled!(Debug)
+                            && let Ok(val) = serde_json::to_string(&res)
+                        {
+                            log::debug!(response = val; "Anthropic messages response");
+                        }
+                        res.try_into(full_history)
+                    }
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("Anthropic messages error: {}, body: {}", err, text),
+                    )
+                    .into()),
+                }
+            } else {
+                let status = response.status().as_u16();
+                let msg = response.text().await?;
+                Err(ProviderError::from_response("Anthropic messages error", status, msg).into())
+            }
+        })
+    }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        let (mut body, full_history) = self.request_body(req);
+        body["stream"] = Value::Bool(true);
+        event_stream(
+            "Anthropic messages error",
+            self.model.clone(),
+            self.client.post("/messages").json(&body),
+            MessageEventParser::new(full_history),
+        )
+    }
+
+    /// Claude models see images and have a context of 200K tokens. The response format is
+    /// followed by instructions in the system message.
+    fn capabilities(&self) -> ModelCapabilities {
+        ModelCapabilities {
+            max_context: Some(200_000),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: StreamMessage,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: BlockDelta,
+    },
+    MessageDelta {
+        delta: MessageDelta,
+        usage: Option<Usage>,
+    },
+    MessageStop,
+    Error {
+        error: Value,
+    },
+    /// `ping` and `content_block_stop`
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamMessage {
+    usage: Usage,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+/// Parses the events of a messages stream, keeping the state needed for the last output.
+struct MessageEventParser {
+    buf: Vec<u8>,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    /// The index of the tool call of each `tool_use` block.
+    tool_blocks: BTreeMap<usize, usize>,
+    usage: Usage,
+    stop_reason: Option<String>,
+    full_history: Vec<Value>,
+    done: bool,
+}
+
+impl MessageEventParser {
+    fn new(full_history: Vec<Value>) -> Self {
+        Self {
+            buf: Vec::new(),
+            content: String::new(),
+            tool_calls: Vec::new(),
+            tool_blocks: BTreeMap::new(),
+            usage: Usage::default(),
+            stop_reason: None,
+            full_history,
+            done: false,
+        }
+    }
+
+    fn apply(&mut self, event: StreamEvent) -> Result<Option<AgentOutput>, BoxError> {
+        match event {
+            StreamEvent::MessageStart { message } => self.usage = message.usage,
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: ContentBlock::ToolUse { id, name, .. },
+            } => {
+                self.tool_blocks.insert(index, self.tool_calls.len());
+                let call = ToolCall {
+                    id,
+                    name,
+                    ..Default::default()
+                };
+                self.tool_calls.push(call.clone());
+                return Ok(Some(AgentOutput {
+                    tool_calls: Some(vec![call]),
+                    ..Default::default()
+                }));
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => match delta {
+                BlockDelta::TextDelta { text } if !text.is_empty() => {
+                    self.content.push_str(&text);
+                    return Ok(Some(AgentOutput {
+                        content: text,
+                        ..Default::default()
+                    }));
+                }
+                BlockDelta::InputJsonDelta { partial_json } => {
+                    if let Some(tc) = self
+                        .tool_blocks
+                        .get(&index)
+                        .and_then(|i| self.tool_calls.get_mut(*i))
+                    {
+                        tc.args.push_str(&partial_json);
+                        return Ok(Some(AgentOutput {
+                            tool_calls: Some(vec![ToolCall {
+                                id: tc.id.clone(),
+                                name: tc.name.clone(),
+                                args: partial_json,
+                                result: None,
+                            }]),
+                            ..Default::default()
+                        }));
+                    }
+                }
+                _ => {}
+            },
+            StreamEvent::MessageDelta { delta, usage } => {
+                if delta.stop_reason.is_some() {
+                    self.stop_reason = delta.stop_reason;
+                }
+                if let Some(usage) = usage {
+                    self.usage.output_tokens = usage.output_tokens;
+                }
+            }
+            StreamEvent::MessageStop => self.done = true,
+            StreamEvent::Error { error } => {
+                let msg = error.to_string();
+                return Err(ProviderError::new(
+                    ProviderErrorKind::from_message(&msg),
+                    format!("Anthropic messages error: {}", msg),
+                )
+                .into());
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+}
+
+impl EventParser for MessageEventParser {
+    fn feed(&mut self, bytes: &[u8]) -> Result<Vec<AgentOutput>, BoxError> {
+        self.buf.extend_from_slice(bytes);
+        let mut outputs = Vec::new();
+        for data in take_data_lines(&mut self.buf)? {
+            let event: StreamEvent = serde_json::from_str(&data).map_err(|err| {
+                ProviderError::new(
+                    ProviderErrorKind::Server,
+                    format!("Anthropic messages error: {}, data: {}", err, data),
+                )
+            })?;
+            if let Some(output) = self.apply(event)? {
+                outputs.push(output);
+            }
+        }
+        Ok(outputs)
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn finish(mut self) -> AgentOutput {
+        // tools without parameters have no input deltas
+        for tc in &mut self.tool_calls {
+            if tc.args.is_empty() {
+                tc.args = "{}".to_string();
+            }
+        }
+        self.full_history
+            .push(assistant_message(&self.content, &self.tool_calls));
+        AgentOutput {
+            usage: ModelUsage {
+                input_tokens: self.usage.total_input_tokens(),
+                output_tokens: self.usage.output_tokens,
+                requests: 1,
+                ..Default::default()
+            },
+            failed_reason: if self.done {
+                failed_reason(self.stop_reason)
+            } else {
+                Some("stream ended without a stop event".to_string())
+            },
+            full_history: Some(self.full_history),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENTS: &str = r#"event: message_start
+data: {"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","content":[],"model":"claude-sonnet-4-0","stop_reason":null,"usage":{"input_tokens":25,"output_tokens":1,"cache_read_input_tokens":5}}}
+
+event: content_block_start
+data: {"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}
+
+event: ping
+data: {"type": "ping"}
+
+event: content_block_delta
+data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Let me check"}}
+
+event: content_block_stop
+data: {"type":"content_block_stop","index":0}
+
+event: content_block_start
+data: {"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{}}}
+
+event: content_block_delta
+data: {"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"city\":"}}
+
+event: content_block_delta
+data: {"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"\"Paris\"}"}}
+
+event: content_block_stop
+data: {"type":"content_block_stop","index":1}
+
+event: message_delta
+data: {"type":"message_delta","delta":{"stop_reason":"tool_use","stop_sequence":null},"usage":{"output_tokens":30}}
+
+event: message_stop
+data: {"type":"message_stop"}
+
+"#;
+
+    #[test]
+    fn test_to_anthropic_messages() {
+        let history = vec![
+            json!({"role": "system", "content": "You are a helpful assistant."}),
+            json!({"role": "user", "content": "What is the weather in Paris and Berlin?"}),
+            json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                    {"id": "call_2", "type": "function", "function": {"name": "get_weather", "arguments": ""}},
+                ],
+            }),
+            json!({"role": "tool", "tool_call_id": "call_1", "content": "Sunny"}),
+            json!({"role": "tool", "tool_call_id": "call_2", "content": "Rainy"}),
+            json!({"role": "user", "content": [
+                {"type": "text", "text": "And this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+                {"type": "input_audio", "input_audio": {"data": "AAAA", "format": "wav"}},
+            ]}),
+        ];
+        let (system, messages) = to_anthropic_messages(&history);
+        assert_eq!(system, vec!["You are a helpful assistant."]);
+        assert_eq!(
+            Value::Array(messages),
+            json!([
+                {"role": "user", "content": [{"type": "text", "text": "What is the weather in Paris and Berlin?"}]},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "call_1", "name": "get_weather", "input": {"city": "Paris"}},
+                    {"type": "tool_use", "id": "call_2", "name": "get_weather", "input": {}},
+                ]},
+                // tool results and the next user message are merged into one user message
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "call_1", "content": "Sunny"},
+                    {"type": "tool_result", "tool_use_id": "call_2", "content": "Rainy"},
+                    {"type": "text", "text": "And this?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}},
+                ]},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_completion_response() {
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-0",
+            "content": [
+                {"type": "thinking", "thinking": "...", "signature": "..."},
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}},
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 20, "output_tokens": 10},
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert_eq!(output.content, "Let me check.");
+        assert!(output.failed_reason.is_none());
+        assert_eq!(output.usage.input_tokens, 20);
+        assert_eq!(output.model.as_deref(), Some("claude-sonnet-4-0"));
+        let tool_calls = output.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].id, "toolu_1");
+        assert_eq!(tool_calls[0].args, r#"{"city":"Paris"}"#);
+        let history = output.full_history.unwrap();
+        assert_eq!(
+            history[0]["tool_calls"][0]["function"]["name"],
+            "get_weather"
+        );
+
+        // the history converts back to the same tool use
+        let (_, messages) = to_anthropic_messages(&history);
+        assert_eq!(
+            messages[0]["content"][1],
+            json!({"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}})
+        );
+
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "id": "msg_2",
+            "model": "claude-sonnet-4-0",
+            "content": [{"type": "text", "text": "Once upon"}],
+            "stop_reason": "max_tokens",
+            "usage": {"input_tokens": 20, "output_tokens": 10},
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert_eq!(output.failed_reason.as_deref(), Some("max_tokens"));
+    }
+
+    #[test]
+    fn test_request_body() {
+        let model = Client::new("test", None).completion_model("");
+        let (body, history) = model.request_body(CompletionRequest {
+            system: Some("You are a helpful assistant.".to_string()),
+            prompt: "Hello!".to_string(),
+            response_format: Some(json!({
+                "type": "json_schema",
+                "json_schema": {"name": "greeting", "schema": {"type": "object"}},
+            })),
+            stop: Some(vec!["\n\n".to_string()]),
+            ..Default::default()
+        });
+        assert_eq!(body["model"], CLAUDE_SONNET_4);
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(
+            body["system"],
+            "You are a helpful assistant.\n\nRespond only with a JSON object that conforms to this JSON schema:\n{\"type\":\"object\"}"
+        );
+        assert_eq!(body["stop_sequences"], json!(["\n\n"]));
+        assert_eq!(body["messages"][0]["role"], "user");
+        // the full history keeps the system message for the next turns
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["role"], "system");
+    }
+
+    #[test]
+    fn test_message_event_parser() {
+        let mut parser = MessageEventParser::new(vec![json!({"role": "user", "content": "Hi"})]);
+        let mut outputs = Vec::new();
+        // feeds the events in pieces that split lines
+        for piece in EVENTS.as_bytes().chunks(7) {
+            outputs.extend(parser.feed(piece).unwrap());
+        }
+        assert!(parser.is_done());
+        assert_eq!(outputs.len(), 4);
+
+        let mut output = AgentOutput::default();
+        for delta in outputs {
+            output.accumulate(delta);
+        }
+        output.accumulate(parser.finish());
+        assert_eq!(output.content, "Let me check");
+        assert!(output.failed_reason.is_none());
+        assert_eq!(output.usage.input_tokens, 30);
+        assert_eq!(output.usage.output_tokens, 30);
+        let tool_calls = output.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id, "toolu_1");
+        assert_eq!(tool_calls[0].args, r#"{"city":"Paris"}"#);
+        let history = output.full_history.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            history[1]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Paris"}"#
+        );
+
+        let mut parser = MessageEventParser::new(vec![]);
+        let err = parser
+            .feed(b"data: {\"type\":\"error\",\"error\":{\"type\":\"rate_limit_error\",\"message\":\"Rate limited\"}}\n")
+            .unwrap_err();
+        assert_eq!(ProviderErrorKind::of(&err), ProviderErrorKind::Quota);
+
+        let parser = MessageEventParser::new(vec![]);
+        assert!(parser.finish().failed_reason.is_some());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn test_anthropic() {
+        dotenv::dotenv().ok();
+
+        let api_key = std::env::var("ANTHROPIC_API_KEY").expect("ANTHROPIC_API_KEY is not set");
+        let model = Client::new(&api_key, None).completion_model(CLAUDE_3_5_HAIKU);
+        let res = CompletionFeatures::completion(
+            &model,
+            CompletionRequest {
+                prompt: "Say hello in one word.".to_string(),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        println!("{:?}", res);
+    }
+}
@@ -0,0 +1,221 @@
+//! Batch embedding with automatic request splitting.
+//!
+//! [`embed_batch`] embeds any number of texts: it splits them into batches of at most
+//! [`EmbeddingFeaturesDyn::max_batch_size`] texts, runs the batches with bounded concurrency,
+//! and reassembles the embeddings in the order of the input. A batch that fails with a
+//! retryable error (see [`ProviderErrorKind::is_retryable`]) is retried with exponential
+//! backoff, without embedding the other batches again.
+//!
+//! # Usage
+//! ```rust,ignore
+//! let (embeddings, usage) = model.embed_batch(chunks, &EmbedBatchOptions::default()).await?;
+//! ```
+
+use anda_core::{BoxError, Embedding, Usage};
+use futures::{StreamExt, TryStreamExt};
+use std::time::Duration;
+
+use super::{EmbeddingFeaturesDyn, ProviderErrorKind};
+
+/// The batch size of embedders that do not declare their limit.
+pub const DEFAULT_EMBED_BATCH_SIZE: usize = 96;
+
+/// Options of [`embed_batch`].
+#[derive(Clone, Debug)]
+pub struct EmbedBatchOptions {
+    /// The maximum number of texts per request, capped by the limit of the embedder.
+    pub batch_size: Option<usize>,
+    /// The maximum number of concurrent requests, 4 by default.
+    pub concurrency: usize,
+    /// The maximum number of retries of a failed batch, 3 by default.
+    pub max_retries: usize,
+    /// The delay before the first retry, doubled on each retry. 1 second by default.
+    pub retry_delay: Duration,
+}
+
+impl Default for EmbedBatchOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: None,
+            concurrency: 4,
+            max_retries: 3,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Embeds the texts in batches, returning the embeddings in the order of the texts and the
+/// accumulated usage of all requests. Fails if a batch still fails after its retries.
+pub async fn embed_batch(
+    embedder: &dyn EmbeddingFeaturesDyn,
+    texts: Vec<String>,
+    opts: &EmbedBatchOptions,
+) -> Result<(Vec<Embedding>, Usage), BoxError> {
+    if texts.is_empty() {
+        return Ok((vec![], Usage::default()));
+    }
+
+    let batch_size = opts
+        .batch_size
+        .unwrap_or(usize::MAX)
+        .min(embedder.max_batch_size())
+        .max(1);
+    let total = texts.len();
+    let batches: Vec<Vec<String>> = texts
+        .chunks(batch_size)
+        .map(|batch| batch.to_vec())
+        .collect();
+
+    let results: Vec<(Vec<Embedding>, Usage)> = futures::stream::iter(batches)
+        .enumerate()
+        .map(|(i, batch)| embed_with_retry(embedder, i, batch, opts))
+        .buffered(opts.concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    let mut embeddings = Vec::with_capacity(total);
+    let mut usage = Usage::default();
+    for (batch, u) in results {
+        embeddings.extend(batch);
+        usage.accumulate(&u);
+    }
+    Ok((embeddings, usage))
+}
+
+async fn embed_with_retry(
+    embedder: &dyn EmbeddingFeaturesDyn,
+    index: usize,
+    batch: Vec<String>,
+    opts: &EmbedBatchOptions,
+) -> Result<(Vec<Embedding>, Usage), BoxError> {
+    let mut delay = opts.retry_delay;
+    let mut attempt = 0;
+    loop {
+        let err = match embedder.embed(batch.clone()).await {
+            Ok((embeddings, usage)) if embeddings.len() == batch.len() => {
+                return Ok((embeddings, usage));
+            }
+            Ok((embeddings, _)) => {
+                return Err(format!(
+                    "batch {}: expected {} embeddings, got {}",
+                    index,
+                    batch.len(),
+                    embeddings.len()
+                )
+                .into());
+            }
+            Err(err) => err,
+        };
+
+        if attempt >= opts.max_retries || !ProviderErrorKind::of(&err).is_retryable() {
+            return Err(
+                format!("batch {} failed after {} retries: {}", index, attempt, err).into(),
+            );
+        }
+        log::warn!(batch = index, attempt = attempt + 1; "embedding failed, retrying: {}", err);
+        tokio::time::sleep(delay).await;
+        delay = delay.saturating_mul(2);
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderError;
+    use anda_core::BoxPinFut;
+    use std::sync::Mutex;
+
+    /// Embeds texts as their lengths, failing the first request of each batch that
+    /// contains a text starting with "flaky".
+    #[derive(Default)]
+    struct FlakyEmbedder {
+        requests: Mutex<Vec<usize>>,
+        failed: Mutex<bool>,
+    }
+
+    impl EmbeddingFeaturesDyn for FlakyEmbedder {
+        fn ndims(&self) -> usize {
+            1
+        }
+
+        fn max_batch_size(&self) -> usize {
+            3
+        }
+
+        fn embed(
+            &self,
+            texts: Vec<String>,
+        ) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+            self.requests.lock().unwrap().push(texts.len());
+            let mut failed = self.failed.lock().unwrap();
+            if !*failed && texts.iter().any(|t| t.starts_with("flaky")) {
+                *failed = true;
+                return Box::pin(futures::future::ready(Err(ProviderError::new(
+                    ProviderErrorKind::Server,
+                    "overloaded".to_string(),
+                )
+                .into())));
+            }
+            let embeddings = texts
+                .into_iter()
+                .map(|text| Embedding {
+                    vec: vec![text.len() as f32],
+                    text,
+                })
+                .collect();
+            Box::pin(futures::future::ready(Ok((
+                embeddings,
+                Usage {
+                    input_tokens: 1,
+                    output_tokens: 0,
+                    requests: 1,
+                    ..Default::default()
+                },
+            ))))
+        }
+
+        fn embed_query(&self, _text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+            Box::pin(futures::future::ready(Err("not implemented".into())))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_embed_batch() {
+        let embedder = FlakyEmbedder::default();
+        let texts: Vec<String> = ["a", "bb", "ccc", "flaky", "e", "ffffff", "g"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        let opts = EmbedBatchOptions {
+            retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let (embeddings, usage) = embed_batch(&embedder, texts.clone(), &opts).await.unwrap();
+        assert_eq!(
+            embeddings
+                .iter()
+                .map(|e| e.text.clone())
+                .collect::<Vec<_>>(),
+            texts
+        );
+        assert_eq!(
+            embeddings.iter().map(|e| e.vec[0]).collect::<Vec<_>>(),
+            vec![1.0, 2.0, 3.0, 5.0, 1.0, 6.0, 1.0]
+        );
+        // 3 batches, the second one retried once
+        assert_eq!(usage.requests, 3);
+        let mut requests = embedder.requests.lock().unwrap().clone();
+        requests.sort();
+        assert_eq!(requests, vec![1, 3, 3, 3]);
+
+        let opts = EmbedBatchOptions {
+            batch_size: Some(2),
+            max_retries: 0,
+            ..opts
+        };
+        let embedder = FlakyEmbedder::default();
+        let err = embed_batch(&embedder, texts, &opts).await.unwrap_err();
+        assert!(err.to_string().contains("batch 1 failed"));
+    }
+}
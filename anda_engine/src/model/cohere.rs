@@ -1,16 +1,16 @@
 //! Cohere API client and Anda integration
 //!
 //! This module provides a client for interacting with Cohere's API, specifically
-//! focused on text embedding functionality. It includes support for various
-//! Cohere embedding models and handles API communication, error handling,
-//! and response parsing.
+//! focused on text embedding and reranking functionality. It includes support for
+//! various Cohere embedding and rerank models and handles API communication, error
+//! handling, and response parsing.
 
 use anda_core::{BoxError, BoxPinFut, CONTENT_TYPE_JSON, Embedding, Usage};
 use serde::Deserialize;
 use serde_json::json;
 use std::time::Duration;
 
-use super::EmbeddingFeaturesDyn;
+use super::{EmbeddingFeaturesDyn, ProviderError, ProviderErrorKind, Reranked, Reranker};
 use crate::APP_USER_AGENT;
 
 // ================================================================
@@ -30,6 +30,16 @@ pub const EMBED_MULTILINGUAL_V3: &str = "embed-multilingual-v3.0";
 /// `embed-multilingual-light-v3.0` embedding model
 pub const EMBED_MULTILINGUAL_LIGHT_V3: &str = "embed-multilingual-light-v3.0";
 
+// ================================================================
+// Cohere Rerank API
+// ================================================================
+/// `rerank-v3.5` rerank model
+pub const RERANK_V3_5: &str = "rerank-v3.5";
+/// `rerank-english-v3.0` rerank model
+pub const RERANK_ENGLISH_V3: &str = "rerank-english-v3.0";
+/// `rerank-multilingual-v3.0` rerank model
+pub const RERANK_MULTILINGUAL_V3: &str = "rerank-multilingual-v3.0";
+
 /// Cohere API client configuration and HTTP client
 #[derive(Clone)]
 pub struct Client {
@@ -97,6 +107,14 @@ impl Client {
         };
         EmbeddingModel::new(self.clone(), model, ndims)
     }
+
+    /// Creates a rerank model instance
+    ///
+    /// # Arguments
+    /// * `model` - Model identifier (e.g., RERANK_V3_5)
+    pub fn rerank_model(&self, model: &str) -> RerankModel {
+        RerankModel::new(self.clone(), model)
+    }
 }
 
 /// Response structure for Cohere's embedding API
@@ -135,6 +153,7 @@ impl EmbeddingResponse {
                 input_tokens: m.billed_units.input_tokens as u64,
                 output_tokens: m.billed_units.output_tokens as u64,
                 requests: 1,
+                ..Default::default()
             }),
         ))
     }
@@ -229,6 +248,10 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
         self.ndims
     }
 
+    fn max_batch_size(&self) -> usize {
+        MAX_DOCUMENTS
+    }
+
     /// Generates embeddings for a batch of texts
     ///
     /// # Arguments
@@ -262,11 +285,16 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
             if response.status().is_success() {
                 match response.json::<EmbeddingResponse>().await {
                     Ok(res) => res.try_into(texts),
-                    Err(err) => Err(format!("Cohere embeddings error: {}", err).into()),
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("Cohere embeddings error: {}", err),
+                    )
+                    .into()),
                 }
             } else {
+                let status = response.status().as_u16();
                 let msg = response.text().await?;
-                Err(format!("Cohere embeddings error: {}", msg).into())
+                Err(ProviderError::from_response("Cohere embeddings error", status, msg).into())
             }
         })
     }
@@ -301,19 +329,134 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
                             input_tokens: m.billed_units.input_tokens as u64,
                             output_tokens: m.billed_units.output_tokens as u64,
                             requests: 1,
+                            ..Default::default()
                         });
                         Ok((Embedding { text, vec: data }, usage))
                     }
-                    Err(err) => Err(format!("Cohere embeddings error: {}", err).into()),
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("Cohere embeddings error: {}", err),
+                    )
+                    .into()),
                 }
             } else {
+                let status = response.status().as_u16();
                 let msg = response.text().await?;
-                Err(format!("Cohere embeddings error: {}", msg).into())
+                Err(ProviderError::from_response("Cohere embeddings error", status, msg).into())
             }
         })
     }
 }
 
+/// Response structure for Cohere's rerank API
+#[derive(Debug, Deserialize)]
+pub struct RerankResponse {
+    /// The documents ordered by descending relevance
+    pub results: Vec<RerankResult>,
+    /// Metadata about the API response
+    #[serde(default)]
+    pub meta: Option<Meta>,
+}
+
+/// A reranked document of Cohere's rerank API
+#[derive(Debug, Deserialize)]
+pub struct RerankResult {
+    /// The index of the document in the request
+    pub index: usize,
+    /// The relevance score in the range [0.0, 1.0]
+    pub relevance_score: f32,
+}
+
+/// Cohere rerank model wrapper
+#[derive(Clone)]
+pub struct RerankModel {
+    /// Model identifier
+    pub model: String,
+    /// Client instance for API communication
+    client: Client,
+}
+
+impl RerankModel {
+    /// Creates a new rerank model instance
+    ///
+    /// # Arguments
+    /// * `client` - Cohere API client
+    /// * `model` - Model identifier
+    pub fn new(client: Client, model: &str) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+        }
+    }
+}
+
+/// Maximum number of documents per rerank call recommended by Cohere.
+const MAX_RERANK_DOCUMENTS: usize = 1000;
+impl Reranker for RerankModel {
+    /// Reranks the documents against the query
+    ///
+    /// https://docs.cohere.com/reference/rerank
+    fn rerank(
+        &self,
+        query: String,
+        documents: Vec<String>,
+        top_n: usize,
+    ) -> BoxPinFut<Result<Vec<Reranked>, BoxError>> {
+        let model = self.model.clone();
+        let client = self.client.clone();
+        Box::pin(async move {
+            if documents.is_empty() || top_n == 0 {
+                return Ok(Vec::new());
+            }
+            if documents.len() > MAX_RERANK_DOCUMENTS {
+                return Err(format!("Too many documents, max is {}", MAX_RERANK_DOCUMENTS).into());
+            }
+
+            let response = client
+                .post("/v2/rerank")
+                .json(&json!({
+                    "model": model,
+                    "query": query,
+                    "documents": documents,
+                    "top_n": top_n.min(documents.len()),
+                }))
+                .send()
+                .await?;
+
+            if response.status().is_success() {
+                match response.json::<RerankResponse>().await {
+                    Ok(res) => Ok(res.into_reranked(documents.len())),
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("Cohere rerank error: {}", err),
+                    )
+                    .into()),
+                }
+            } else {
+                let status = response.status().as_u16();
+                let msg = response.text().await?;
+                Err(ProviderError::from_response("Cohere rerank error", status, msg).into())
+            }
+        })
+    }
+}
+
+impl RerankResponse {
+    fn into_reranked(self, documents: usize) -> Vec<Reranked> {
+        let mut ranked: Vec<Reranked> = self
+            .results
+            .into_iter()
+            .filter(|r| r.index < documents)
+            .map(|r| Reranked {
+                index: r.index,
+                score: r.relevance_score,
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        ranked
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -335,4 +478,47 @@ mod tests {
         let res = model.embed(vec![req.system.unwrap()]).await.unwrap();
         println!("{:?}", res);
     }
+
+    #[test]
+    fn test_rerank_response() {
+        let res: RerankResponse = serde_json::from_value(json!({
+            "id": "07734bd2-2473-4f07-94e1-0d9f0e6843cf",
+            "results": [
+                {"index": 3, "relevance_score": 0.999071},
+                {"index": 4, "relevance_score": 0.7867867},
+                {"index": 9, "relevance_score": 0.5},
+                {"index": 0, "relevance_score": 0.32713068}
+            ],
+            "meta": {"api_version": {"version": "2"}, "billed_units": {"search_units": 1}}
+        }))
+        .unwrap();
+        let ranked = res.into_reranked(5);
+        assert_eq!(
+            ranked.iter().map(|r| r.index).collect::<Vec<_>>(),
+            vec![3, 4, 0]
+        );
+        assert_eq!(ranked[0].score, 0.999071);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn test_rerank() {
+        dotenv::dotenv().ok();
+
+        let api_key = std::env::var("COHERE_API_KEY").expect("COHERE_API_KEY is not set");
+        let model = Client::new(&api_key).rerank_model(RERANK_V3_5);
+        let res = model
+            .rerank(
+                "What is the capital of the United States?".to_string(),
+                vec![
+                    "Carson City is the capital city of the American state of Nevada.".to_string(),
+                    "Washington, D.C. is the capital of the United States.".to_string(),
+                ],
+                1,
+            )
+            .await
+            .unwrap();
+        println!("{:?}", res);
+        assert_eq!(res[0].index, 1);
+    }
 }
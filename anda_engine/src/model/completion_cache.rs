@@ -0,0 +1,368 @@
+//! Completion cache keyed by the request.
+//!
+//! [`CachedCompleter`] wraps a [`CompletionFeaturesDyn`] so that a request completed before
+//! is answered from an in-memory cache, and optionally from a persistent [`Store`], instead
+//! of calling the provider again. It suits idempotent tool-augmented queries, and replaying
+//! test suites without hitting the provider.
+//!
+//! Entries are keyed by the BLAKE3 hash of the model name and the serialized
+//! [`CompletionRequest`], including its caller, so answers are not shared between callers.
+//! By default only deterministic requests, with a temperature of 0, are cached, and failed
+//! completions are never cached. Cached completions are returned with an empty usage, since
+//! they cost nothing. Streaming requests are served from the cache, but not cached.
+//!
+//! # Usage
+//! ```rust,ignore
+//! let completer = CachedCompleter::new(
+//!     Arc::new(openai.completion_model("gpt-4o-mini")),
+//!     "gpt-4o-mini",
+//!     CompletionCacheOptions::default(),
+//! )
+//! .with_store(store, Path::from("completions/gpt-4o-mini"));
+//! let model = Model::with_completer(Arc::new(completer));
+//! ```
+
+use anda_core::{
+    AgentOutput, BoxError, BoxPinFut, CompletionRequest, ModelCapabilities, Path, Usage,
+};
+use ciborium::from_reader;
+use futures::{StreamExt, stream::BoxStream};
+use ic_cose_types::to_cbor_bytes;
+use moka::future::Cache;
+use object_store::PutMode;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use structured_logger::unix_ms;
+
+use super::CompletionFeaturesDyn;
+use crate::store::{Store, is_not_found};
+
+/// Options of a [`CachedCompleter`].
+#[derive(Clone, Debug)]
+pub struct CompletionCacheOptions {
+    /// The maximum number of completions in memory, 10,000 by default.
+    pub max_entries: u64,
+    /// The maximum size of a cached completion in bytes, larger ones are not cached.
+    /// 64 KiB by default.
+    pub max_entry_bytes: usize,
+    /// How long a completion is cached, 1 hour by default.
+    pub ttl: Duration,
+    /// Whether only requests with a temperature of 0 are cached, true by default.
+    pub deterministic_only: bool,
+}
+
+impl Default for CompletionCacheOptions {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_entry_bytes: 64 * 1024,
+            ttl: Duration::from_secs(3600),
+            deterministic_only: true,
+        }
+    }
+}
+
+/// The hit and miss counters of a [`CachedCompleter`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CompletionCacheStats {
+    /// Requests answered from the cache, in memory or in the store.
+    pub hits: u64,
+    /// Cacheable requests completed by the inner completer.
+    pub misses: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// A completion persisted in the store.
+#[derive(Deserialize, Serialize)]
+struct StoredCompletion {
+    /// When the completion was cached, unix timestamp in milliseconds.
+    cached_at: u64,
+    output: AgentOutput,
+}
+
+/// A [`CompletionFeaturesDyn`] that caches the completions of the inner completer.
+#[derive(Clone)]
+pub struct CachedCompleter {
+    inner: Arc<dyn CompletionFeaturesDyn>,
+    model: String,
+    opts: CompletionCacheOptions,
+    cache: Cache<blake3::Hash, Arc<AgentOutput>>,
+    store: Option<(Store, Path)>,
+    counters: Arc<Counters>,
+}
+
+impl CachedCompleter {
+    /// Creates a cache of the completions of the model.
+    pub fn new(
+        inner: Arc<dyn CompletionFeaturesDyn>,
+        model: &str,
+        opts: CompletionCacheOptions,
+    ) -> Self {
+        Self {
+            inner,
+            model: model.to_string(),
+            cache: Cache::builder()
+                .max_capacity(opts.max_entries)
+                .time_to_live(opts.ttl)
+                .build(),
+            opts,
+            store: None,
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Persists the completions in the namespace of the store, so that they survive restarts.
+    pub fn with_store(mut self, store: Store, namespace: Path) -> Self {
+        self.store = Some((store, namespace));
+        self
+    }
+
+    /// Returns the hit and miss counters since the cache was created.
+    pub fn stats(&self) -> CompletionCacheStats {
+        CompletionCacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the key of the request, None if the request is not cacheable.
+    fn key(&self, req: &CompletionRequest) -> Option<blake3::Hash> {
+        if self.opts.deterministic_only && req.temperature != Some(0.0) {
+            return None;
+        }
+        let data = serde_json::to_vec(req).ok()?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.model.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(&data);
+        Some(hasher.finalize())
+    }
+
+    async fn get(&self, key: &blake3::Hash) -> Result<Option<Arc<AgentOutput>>, BoxError> {
+        if let Some(output) = self.cache.get(key).await {
+            return Ok(Some(output));
+        }
+
+        let Some((store, namespace)) = &self.store else {
+            return Ok(None);
+        };
+        let path = Path::from(key.to_hex().as_str());
+        match store.store_get(namespace, &path).await {
+            Ok((data, _)) => {
+                let stored: StoredCompletion = from_reader(&data[..])?;
+                if unix_ms().saturating_sub(stored.cached_at) >= self.opts.ttl.as_millis() as u64 {
+                    return Ok(None);
+                }
+                let output = Arc::new(stored.output);
+                self.cache.insert(*key, output.clone()).await;
+                Ok(Some(output))
+            }
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn put(&self, key: blake3::Hash, output: AgentOutput) {
+        let stored = StoredCompletion {
+            cached_at: unix_ms(),
+            output,
+        };
+        let data = to_cbor_bytes(&stored);
+        if data.len() > self.opts.max_entry_bytes {
+            return;
+        }
+        if let Some((store, namespace)) = &self.store {
+            let path = Path::from(key.to_hex().as_str());
+            if let Err(err) = store
+                .store_put(namespace, &path, PutMode::Overwrite, data.into())
+                .await
+            {
+                log::warn!(namespace = namespace.as_ref(); "failed to persist completion: {}", err);
+            }
+        }
+        self.cache.insert(key, Arc::new(stored.output)).await;
+    }
+
+    /// Returns the cached completion of the request, if any.
+    async fn lookup(&self, key: &blake3::Hash) -> Option<AgentOutput> {
+        match self.get(key).await {
+            Ok(Some(output)) => {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                Some(AgentOutput {
+                    usage: Usage::default(),
+                    ..output.as_ref().clone()
+                })
+            }
+            Ok(None) => None,
+            Err(err) => {
+                log::warn!(model = self.model.as_str(); "failed to read cached completion: {}", err);
+                None
+            }
+        }
+    }
+
+    async fn completion_cached(&self, req: CompletionRequest) -> Result<AgentOutput, BoxError> {
+        let Some(key) = self.key(&req) else {
+            return self.inner.completion(req).await;
+        };
+        if let Some(output) = self.lookup(&key).await {
+            return Ok(output);
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        let output = self.inner.completion(req).await?;
+        if output.failed_reason.is_none() {
+            self.put(key, output.clone()).await;
+        }
+        Ok(output)
+    }
+}
+
+impl CompletionFeaturesDyn for CachedCompleter {
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.completion_cached(req).await })
+    }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        let this = self.clone();
+        Box::pin(
+            futures::stream::once(async move {
+                if let Some(key) = this.key(&req)
+                    && let Some(output) = this.lookup(&key).await
+                {
+                    return futures::stream::once(futures::future::ready(Ok(output))).boxed();
+                }
+                this.inner.completion_stream(req)
+            })
+            .flatten(),
+        )
+    }
+
+    fn capabilities(&self) -> ModelCapabilities {
+        self.inner.capabilities()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    /// Answers with the prompt and the number of completions so far.
+    #[derive(Default)]
+    struct CountingCompleter {
+        completed: AtomicU64,
+    }
+
+    impl CompletionFeaturesDyn for CountingCompleter {
+        fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+            let n = self.completed.fetch_add(1, Ordering::Relaxed) + 1;
+            Box::pin(futures::future::ready(Ok(AgentOutput {
+                content: format!("{} #{}", req.prompt, n),
+                failed_reason: req.prompt.starts_with("fail").then(|| "failed".to_string()),
+                usage: Usage {
+                    input_tokens: 10,
+                    output_tokens: 5,
+                    requests: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })))
+        }
+    }
+
+    fn request(prompt: &str, temperature: Option<f64>) -> CompletionRequest {
+        CompletionRequest {
+            prompt: prompt.to_string(),
+            temperature,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_cached_completer() {
+        let inner = Arc::new(CountingCompleter::default());
+        let store = Store::new(Arc::new(InMemory::new()));
+        let namespace = Path::from("completions");
+        let completer = CachedCompleter::new(inner.clone(), "m1", Default::default())
+            .with_store(store.clone(), namespace.clone());
+
+        let output = completer
+            .completion(request("hi", Some(0.0)))
+            .await
+            .unwrap();
+        assert_eq!(output.content, "hi #1");
+        assert_eq!(output.usage.requests, 1);
+        let output = completer
+            .completion(request("hi", Some(0.0)))
+            .await
+            .unwrap();
+        assert_eq!(output.content, "hi #1");
+        assert_eq!(output.usage.requests, 0);
+
+        // not deterministic, or failed
+        let output = completer.completion(request("hi", None)).await.unwrap();
+        assert_eq!(output.content, "hi #2");
+        completer
+            .completion(request("fail", Some(0.0)))
+            .await
+            .unwrap();
+        let output = completer
+            .completion(request("fail", Some(0.0)))
+            .await
+            .unwrap();
+        assert_eq!(output.content, "fail #4");
+
+        let outputs: Vec<_> = completer
+            .completion_stream(request("hi", Some(0.0)))
+            .collect()
+            .await;
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].as_ref().unwrap().content, "hi #1");
+        assert_eq!(
+            completer.stats(),
+            CompletionCacheStats { hits: 2, misses: 3 }
+        );
+
+        // another model, and a new cache over the same store
+        let other = CachedCompleter::new(inner.clone(), "m2", Default::default());
+        let output = other.completion(request("hi", Some(0.0))).await.unwrap();
+        assert_eq!(output.content, "hi #5");
+        let restarted = CachedCompleter::new(inner.clone(), "m1", Default::default())
+            .with_store(store, namespace);
+        let output = restarted
+            .completion(request("hi", Some(0.0)))
+            .await
+            .unwrap();
+        assert_eq!(output.content, "hi #1");
+        assert_eq!(inner.completed.load(Ordering::Relaxed), 5);
+
+        let uncached = CachedCompleter::new(
+            inner.clone(),
+            "m1",
+            CompletionCacheOptions {
+                max_entry_bytes: 10,
+                deterministic_only: false,
+                ..Default::default()
+            },
+        );
+        uncached.completion(request("hi", None)).await.unwrap();
+        let output = uncached.completion(request("hi", None)).await.unwrap();
+        assert_eq!(output.content, "hi #7");
+    }
+}
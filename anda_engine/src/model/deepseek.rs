@@ -7,14 +7,17 @@
 
 use anda_core::{
     AgentOutput, BoxError, BoxPinFut, CONTENT_TYPE_JSON, CompletionFeatures, CompletionRequest,
-    FunctionDefinition, Message, Resource, ToolCall, Usage as ModelUsage,
+    FunctionDefinition, Message, ModelCapabilities, Resource, ToolCall, Usage as ModelUsage,
 };
+use futures::stream::BoxStream;
 use log::{Level::Debug, log_enabled};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use std::time::Duration;
 
-use super::CompletionFeaturesDyn;
+use super::{
+    CompletionFeaturesDyn, ProviderError, ProviderErrorKind, stream::chat_completion_stream,
+};
 use crate::APP_USER_AGENT;
 
 // ================================================================
@@ -145,6 +148,7 @@ impl CompletionResponse {
                     .collect()
             }),
             full_history: Some(full_history),
+            model: Some(self.model),
             usage: self
                 .usage
                 .as_ref()
@@ -152,6 +156,7 @@ impl CompletionResponse {
                     input_tokens: u.prompt_tokens as u64,
                     output_tokens: u.completion_tokens as u64,
                     requests: 1,
+                    ..Default::default()
                 })
                 .unwrap_or_default(),
             ..Default::default()
@@ -236,6 +241,89 @@ impl CompletionModel {
             model: model.to_string(),
         }
     }
+
+    /// Builds the JSON body of a chat completions request, and the full history of
+    /// messages that the response is appended to.
+    pub fn request_body(&self, mut req: CompletionRequest) -> (Value, Vec<Value>) {
+        // Add system to chat history (if available)
+        let mut full_history = if let Some(system) = &req.system {
+            vec![json!(Message {
+                role: "system".into(),
+                content: system.to_owned().into(),
+                name: req.system_name.clone(),
+                ..Default::default()
+            })]
+        } else {
+            vec![]
+        };
+
+        // Extend existing chat history
+        full_history.append(&mut req.chat_history);
+
+        if !req.content_parts.is_empty() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: json!(req.user_content_parts()),
+                name: req.prompter_name,
+                ..Default::default()
+            }));
+        } else if let Some(prompt) = req.prompt_with_context() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: prompt.into(),
+                name: req.prompter_name,
+                ..Default::default()
+            }));
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "messages": full_history.clone(),
+        });
+
+        let obj = body.as_object_mut().unwrap();
+        if let Some(temperature) = req.temperature {
+            obj.insert("temperature".to_string(), Value::from(temperature));
+        }
+
+        if let Some(max_tokens) = req.max_tokens {
+            obj.insert("max_tokens".to_string(), Value::from(max_tokens));
+        }
+
+        if req.response_format.is_some() {
+            // DeepSeek only supports `{"type": "json_object"}`
+            obj.insert(
+                "response_format".to_string(),
+                json!({"type": "json_object"}),
+            );
+        }
+
+        if let Some(stop) = req.stop {
+            obj.insert("stop".to_string(), Value::from(stop));
+        }
+
+        if !req.tools.is_empty() {
+            obj.insert(
+                "tools".to_string(),
+                json!(
+                    req.tools
+                        .into_iter()
+                        .map(ToolDefinition::from)
+                        .collect::<Vec<_>>()
+                ),
+            );
+            obj.insert(
+                "tool_choice".to_string(),
+                if req.tool_choice_required {
+                    Value::from("required")
+                } else {
+                    Value::from("auto")
+                },
+            );
+        };
+
+        (body, full_history)
+    }
 }
 
 impl CompletionFeatures for CompletionModel {
@@ -246,98 +334,29 @@ impl CompletionFeatures for CompletionModel {
     ) -> Result<AgentOutput, BoxError> {
         CompletionFeaturesDyn::completion(self, req).await
     }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+        _resources: Option<Vec<Resource>>,
+    ) -> BoxStream<'_, Result<AgentOutput, BoxError>> {
+        CompletionFeaturesDyn::completion_stream(self, req)
+    }
 }
 
 impl CompletionFeaturesDyn for CompletionModel {
-    fn completion(&self, mut req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
-        let model = self.model.clone();
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (body, full_history) = self.request_body(req);
         let client = self.client.clone();
 
         Box::pin(async move {
-            // Add system to chat history (if available)
-            let mut full_history = if let Some(system) = &req.system {
-                vec![json!(Message {
-                    role: "system".into(),
-                    content: system.to_owned().into(),
-                    name: req.system_name.clone(),
-                    ..Default::default()
-                })]
-            } else {
-                vec![]
-            };
-
-            // Extend existing chat history
-            full_history.append(&mut req.chat_history);
-
-            if !req.content_parts.is_empty() {
-                full_history.push(json!(Message {
-                    role: "user".into(),
-                    content: json!(req.content_parts),
-                    name: req.prompter_name,
-                    ..Default::default()
-                }));
-            } else if let Some(prompt) = req.prompt_with_context() {
-                full_history.push(json!(Message {
-                    role: "user".into(),
-                    content: prompt.into(),
-                    name: req.prompter_name,
-                    ..Default::default()
-                }));
-            }
-
-            let mut body = json!({
-                "model": model,
-                "messages": full_history.clone(),
-            });
-
-            let body = body.as_object_mut().unwrap();
-            if let Some(temperature) = req.temperature {
-                body.insert("temperature".to_string(), Value::from(temperature));
-            }
-
-            if let Some(max_tokens) = req.max_tokens {
-                body.insert("max_tokens".to_string(), Value::from(max_tokens));
-            }
-
-            if req.response_format.is_some() {
-                // DeepSeek only supports `{"type": "json_object"}`
-                body.insert(
-                    "response_format".to_string(),
-                    json!({"type": "json_object"}),
-                );
-            }
-
-            if let Some(stop) = req.stop {
-                body.insert("stop".to_string(), Value::from(stop));
-            }
-
-            if !req.tools.is_empty() {
-                body.insert(
-                    "tools".to_string(),
-                    json!(
-                        req.tools
-                            .into_iter()
-                            .map(ToolDefinition::from)
-                            .collect::<Vec<_>>()
-                    ),
-                );
-                body.insert(
-                    "tool_choice".to_string(),
-                    if req.tool_choice_required {
-                        Value::from("required")
-                    } else {
-                        Value::from("auto")
-                    },
-                );
-            };
-
             if log_enabled!(Debug) {
                 if let Ok(val) = serde_json::to_string(&body) {
                     log::debug!(request = val; "DeepSeek completions request");
                 }
             }
 
-            let response = client.post("/chat/completions").json(body).send().await?;
+            let response = client.post("/chat/completions").json(&body).send().await?;
             if response.status().is_success() {
                 let text = response.text().await?;
                 match serde_json::from_str::<CompletionResponse>(&text) {
@@ -349,16 +368,41 @@ impl CompletionFeaturesDyn for CompletionModel {
                         }
                         res.try_into(full_history)
                     }
-                    Err(err) => {
-                        Err(format!("DeepSeek completions error: {}, body: {}", err, text).into())
-                    }
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("DeepSeek completions error: {}, body: {}", err, text),
+                    )
+                    .into()),
                 }
             } else {
+                let status = response.status().as_u16();
                 let msg = response.text().await?;
-                Err(format!("DeepSeek completions error: {}", msg).into())
+                Err(ProviderError::from_response("DeepSeek completions error", status, msg).into())
             }
         })
     }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        let (body, full_history) = self.request_body(req);
+        chat_completion_stream(
+            "DeepSeek completions error",
+            self.model.clone(),
+            self.client.post("/chat/completions"),
+            body,
+            full_history,
+        )
+    }
+
+    /// DeepSeek models are text-only.
+    fn capabilities(&self) -> ModelCapabilities {
+        ModelCapabilities {
+            images: false,
+            ..Default::default()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -387,4 +431,29 @@ mod tests {
         println!("{}", res.content);
         println!("Took: {:?}", now.elapsed());
     }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn test_deepseek_stream() {
+        use futures::StreamExt;
+
+        dotenv::dotenv().ok();
+
+        let api_key = std::env::var("DEEPSEEK_API_KEY").expect("DEEKSEEK_API_KEY is not set");
+        let model = Client::new(&api_key, None).completion_model(DEEKSEEK_V3);
+        let req = CompletionRequest {
+            prompt: "Count from 1 to 10.".to_string(),
+            ..Default::default()
+        };
+        let mut stream = CompletionFeatures::completion_stream(&model, req, None);
+        let mut output = AgentOutput::default();
+        while let Some(delta) = stream.next().await {
+            let delta = delta.unwrap();
+            print!("{}", delta.content);
+            output.accumulate(delta);
+        }
+        println!();
+        assert!(output.failed_reason.is_none());
+        assert!(output.usage.output_tokens > 0);
+    }
 }
@@ -0,0 +1,322 @@
+//! Embedding cache keyed by content hash.
+//!
+//! [`CachedEmbedder`] wraps an [`EmbeddingFeaturesDyn`] so that texts embedded before are
+//! served from an in-memory LRU cache, and optionally from a persistent [`Store`], instead of
+//! calling the embedding API again. Repeated ingestion of identical chunks costs nothing.
+//!
+//! Entries are keyed by the BLAKE3 hash of the text, the dimensions of the model and whether
+//! the text is a document or a query, since some providers embed queries differently.
+//! Use a distinct store namespace per embedding model.
+//!
+//! # Usage
+//! ```rust,ignore
+//! let embedder = CachedEmbedder::new(Arc::new(embedder), 100_000)
+//!     .with_store(store, Path::from("embeddings/text-embedding-3-small"));
+//! let model = Model::new(completer, Arc::new(embedder));
+//! ```
+
+use anda_core::{BoxError, BoxPinFut, Embedding, Path, Usage};
+use ciborium::from_reader;
+use ic_cose_types::to_cbor_bytes;
+use moka::future::Cache;
+use object_store::PutMode;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use super::EmbeddingFeaturesDyn;
+use crate::store::{Store, is_not_found};
+
+/// The hit and miss counters of a [`CachedEmbedder`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EmbeddingCacheStats {
+    /// Texts served from the cache, in memory or in the store.
+    pub hits: u64,
+    /// Texts embedded by the inner embedder.
+    pub misses: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// An [`EmbeddingFeaturesDyn`] that caches the embeddings of the inner embedder.
+#[derive(Clone)]
+pub struct CachedEmbedder {
+    inner: Arc<dyn EmbeddingFeaturesDyn>,
+    cache: Cache<blake3::Hash, Arc<Vec<f32>>>,
+    store: Option<(Store, Path)>,
+    counters: Arc<Counters>,
+}
+
+impl CachedEmbedder {
+    /// Creates a cache holding at most `max_capacity` embeddings in memory.
+    pub fn new(inner: Arc<dyn EmbeddingFeaturesDyn>, max_capacity: u64) -> Self {
+        Self {
+            inner,
+            cache: Cache::new(max_capacity),
+            store: None,
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Persists the embeddings in the namespace of the store, so that they survive restarts.
+    pub fn with_store(mut self, store: Store, namespace: Path) -> Self {
+        self.store = Some((store, namespace));
+        self
+    }
+
+    /// Returns the hit and miss counters since the cache was created.
+    pub fn stats(&self) -> EmbeddingCacheStats {
+        EmbeddingCacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn key(&self, text: &str, query: bool) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(if query { b"q" } else { b"d" });
+        hasher.update(&(self.inner.ndims() as u64).to_le_bytes());
+        hasher.update(text.as_bytes());
+        hasher.finalize()
+    }
+
+    async fn get(&self, key: &blake3::Hash) -> Result<Option<Arc<Vec<f32>>>, BoxError> {
+        if let Some(vec) = self.cache.get(key).await {
+            return Ok(Some(vec));
+        }
+
+        let Some((store, namespace)) = &self.store else {
+            return Ok(None);
+        };
+        let path = Path::from(key.to_hex().as_str());
+        match store.store_get(namespace, &path).await {
+            Ok((data, _)) => {
+                let vec: Vec<f32> = from_reader(&data[..])?;
+                if vec.len() != self.inner.ndims() {
+                    return Ok(None);
+                }
+                let vec = Arc::new(vec);
+                self.cache.insert(*key, vec.clone()).await;
+                Ok(Some(vec))
+            }
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn put(&self, key: blake3::Hash, vec: Arc<Vec<f32>>) {
+        if let Some((store, namespace)) = &self.store {
+            let path = Path::from(key.to_hex().as_str());
+            if let Err(err) = store
+                .store_put(
+                    namespace,
+                    &path,
+                    PutMode::Overwrite,
+                    to_cbor_bytes(vec.as_ref()).into(),
+                )
+                .await
+            {
+                log::warn!(namespace = namespace.as_ref(); "failed to persist embedding: {}", err);
+            }
+        }
+        self.cache.insert(key, vec).await;
+    }
+
+    async fn embed_cached(&self, texts: Vec<String>) -> Result<(Vec<Embedding>, Usage), BoxError> {
+        let keys: Vec<blake3::Hash> = texts.iter().map(|text| self.key(text, false)).collect();
+        let mut found: HashMap<blake3::Hash, Arc<Vec<f32>>> = HashMap::new();
+        let mut missing: Vec<String> = Vec::new();
+        let mut missing_keys: Vec<blake3::Hash> = Vec::new();
+        for (text, key) in texts.iter().zip(keys.iter()) {
+            if found.contains_key(key) || missing_keys.contains(key) {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            match self.get(key).await? {
+                Some(vec) => {
+                    self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                    found.insert(*key, vec);
+                }
+                None => {
+                    self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                    missing.push(text.clone());
+                    missing_keys.push(*key);
+                }
+            }
+        }
+
+        let mut usage = Usage::default();
+        if !missing.is_empty() {
+            let (embeddings, u) = self.inner.embed(missing).await?;
+            if embeddings.len() != missing_keys.len() {
+                return Err(format!(
+                    "expected {} embeddings, got {}",
+                    missing_keys.len(),
+                    embeddings.len()
+                )
+                .into());
+            }
+            usage = u;
+            for (key, embedding) in missing_keys.into_iter().zip(embeddings) {
+                let vec = Arc::new(embedding.vec);
+                self.put(key, vec.clone()).await;
+                found.insert(key, vec);
+            }
+        }
+
+        let embeddings = texts
+            .into_iter()
+            .zip(keys)
+            .map(|(text, key)| Embedding {
+                text,
+                vec: found[&key].as_ref().clone(),
+            })
+            .collect();
+        Ok((embeddings, usage))
+    }
+
+    async fn embed_query_cached(&self, text: String) -> Result<(Embedding, Usage), BoxError> {
+        let key = self.key(&text, true);
+        if let Some(vec) = self.get(&key).await? {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((
+                Embedding {
+                    text,
+                    vec: vec.as_ref().clone(),
+                },
+                Usage::default(),
+            ));
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        let (embedding, usage) = self.inner.embed_query(text).await?;
+        self.put(key, Arc::new(embedding.vec.clone())).await;
+        Ok((embedding, usage))
+    }
+}
+
+impl EmbeddingFeaturesDyn for CachedEmbedder {
+    fn ndims(&self) -> usize {
+        self.inner.ndims()
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.inner.max_batch_size()
+    }
+
+    fn embed(&self, texts: Vec<String>) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.embed_cached(texts).await })
+    }
+
+    fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.embed_query_cached(text).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    struct CountingEmbedder {
+        embedded: AtomicU64,
+    }
+
+    impl EmbeddingFeaturesDyn for CountingEmbedder {
+        fn ndims(&self) -> usize {
+            2
+        }
+
+        fn embed(
+            &self,
+            texts: Vec<String>,
+        ) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+            self.embedded
+                .fetch_add(texts.len() as u64, Ordering::Relaxed);
+            let embeddings = texts
+                .into_iter()
+                .map(|text| Embedding {
+                    vec: vec![text.len() as f32, 1.0],
+                    text,
+                })
+                .collect();
+            Box::pin(futures::future::ready(Ok((
+                embeddings,
+                Usage {
+                    input_tokens: 1,
+                    output_tokens: 0,
+                    requests: 1,
+                    ..Default::default()
+                },
+            ))))
+        }
+
+        fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+            self.embedded.fetch_add(1, Ordering::Relaxed);
+            Box::pin(futures::future::ready(Ok((
+                Embedding {
+                    vec: vec![text.len() as f32, 0.0],
+                    text,
+                },
+                Usage::default(),
+            ))))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_cached_embedder() {
+        let inner = Arc::new(CountingEmbedder {
+            embedded: AtomicU64::new(0),
+        });
+        let store = Store::new(Arc::new(InMemory::new()));
+        let namespace = Path::from("embeddings");
+        let embedder =
+            CachedEmbedder::new(inner.clone(), 100).with_store(store.clone(), namespace.clone());
+
+        let texts = vec!["a".to_string(), "bb".to_string(), "a".to_string()];
+        let (embeddings, usage) = embedder.embed(texts).await.unwrap();
+        assert_eq!(usage.requests, 1);
+        assert_eq!(inner.embedded.load(Ordering::Relaxed), 2);
+        assert_eq!(embeddings.len(), 3);
+        assert_eq!(embeddings[0].vec, vec![1.0, 1.0]);
+        assert_eq!(embeddings[1].vec, vec![2.0, 1.0]);
+        assert_eq!(embeddings[2].text, "a");
+
+        let (embeddings, usage) = embedder
+            .embed(vec!["bb".to_string(), "ccc".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(usage.requests, 1);
+        assert_eq!(inner.embedded.load(Ordering::Relaxed), 3);
+        assert_eq!(embeddings[1].vec, vec![3.0, 1.0]);
+        assert_eq!(embedder.stats(), EmbeddingCacheStats { hits: 2, misses: 3 });
+
+        // queries are cached apart from documents
+        let (embedding, _) = embedder.embed_query("a".to_string()).await.unwrap();
+        assert_eq!(embedding.vec, vec![1.0, 0.0]);
+        embedder.embed_query("a".to_string()).await.unwrap();
+        assert_eq!(inner.embedded.load(Ordering::Relaxed), 4);
+
+        // a new cache on the same store hits the persisted embeddings
+        let embedder = CachedEmbedder::new(inner.clone(), 100).with_store(store, namespace);
+        let (embeddings, usage) = embedder
+            .embed(vec!["a".to_string(), "ccc".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(usage.requests, 0);
+        assert_eq!(inner.embedded.load(Ordering::Relaxed), 4);
+        assert_eq!(embeddings[1].vec, vec![3.0, 1.0]);
+        assert_eq!(embedder.stats(), EmbeddingCacheStats { hits: 2, misses: 0 });
+    }
+}
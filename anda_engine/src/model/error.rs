@@ -0,0 +1,284 @@
+//! Provider error classification.
+//!
+//! Model providers report failures in many shapes: HTTP status codes, provider-specific
+//! error codes and free-form messages. [`ProviderErrorKind`] maps them to a small, stable
+//! taxonomy so that alerting can tell a bad API key from a provider outage, and
+//! [`ProviderErrorCounts`] keeps per-class counters for metrics.
+
+use anda_core::BoxError;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The class of a model provider error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderErrorKind {
+    /// Invalid, expired or unauthorized API key.
+    Auth,
+    /// Rate limited, or out of quota or credits.
+    Quota,
+    /// The request exceeds the model's context length.
+    ContextLength,
+    /// The request or response was blocked by the provider's content policy.
+    ContentFilter,
+    /// Failed to reach the provider: DNS, connection, TLS or timeout errors.
+    Network,
+    /// The provider failed: 5xx responses, overloaded, or malformed responses.
+    Server,
+    /// Any other error, e.g. an invalid request.
+    Other,
+}
+
+impl ProviderErrorKind {
+    /// All classes, in order.
+    pub const ALL: [ProviderErrorKind; 7] = [
+        Self::Auth,
+        Self::Quota,
+        Self::ContextLength,
+        Self::ContentFilter,
+        Self::Network,
+        Self::Server,
+        Self::Other,
+    ];
+
+    /// Returns the stable name of the class.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::Quota => "quota",
+            Self::ContextLength => "context_length",
+            Self::ContentFilter => "content_filter",
+            Self::Network => "network",
+            Self::Server => "server",
+            Self::Other => "other",
+        }
+    }
+
+    /// Returns whether retrying the same request later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Quota | Self::Network | Self::Server)
+    }
+
+    /// Classifies an HTTP error response from a provider.
+    pub fn from_response(status: u16, body: &str) -> Self {
+        match status {
+            401 | 403 => Self::Auth,
+            402 | 429 => Self::Quota,
+            408 => Self::Network,
+            500..=599 => Self::Server,
+            _ => Self::from_message(body),
+        }
+    }
+
+    /// Classifies an error by its message, for errors without a status code.
+    pub fn from_message(msg: &str) -> Self {
+        let msg = msg.to_ascii_lowercase();
+        let has = |patterns: &[&str]| patterns.iter().any(|p| msg.contains(p));
+        if has(&[
+            "context_length",
+            "context length",
+            "context window",
+            "maximum context",
+            "too many tokens",
+            "prompt is too long",
+            "reduce the length",
+            "string_above_max_length",
+        ]) {
+            Self::ContextLength
+        } else if has(&[
+            "content_filter",
+            "content filter",
+            "content_policy",
+            "content policy",
+            "safety system",
+            "flagged",
+        ]) {
+            Self::ContentFilter
+        } else if has(&[
+            "invalid_api_key",
+            "invalid api key",
+            "incorrect api key",
+            "unauthorized",
+            "authentication",
+            "permission denied",
+        ]) {
+            Self::Auth
+        } else if has(&[
+            "insufficient_quota",
+            "quota",
+            "rate limit",
+            "rate_limit",
+            "too many requests",
+            "insufficient balance",
+        ]) {
+            Self::Quota
+        } else if has(&["overloaded", "internal server error", "service unavailable"]) {
+            Self::Server
+        } else {
+            Self::Other
+        }
+    }
+
+    /// Classifies any error returned by a model, using the [`ProviderError`] class if present.
+    pub fn of(err: &BoxError) -> Self {
+        if let Some(err) = err.downcast_ref::<ProviderError>() {
+            return err.kind;
+        }
+        if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+            return Self::from_reqwest(err);
+        }
+        Self::from_message(&err.to_string())
+    }
+
+    fn from_reqwest(err: &reqwest::Error) -> Self {
+        match err.status() {
+            Some(status) => Self::from_response(status.as_u16(), &err.to_string()),
+            None if err.is_decode() => Self::Server,
+            None => Self::Network,
+        }
+    }
+}
+
+impl fmt::Display for ProviderErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error returned by a model provider, with its class.
+#[derive(Clone, Debug)]
+pub struct ProviderError {
+    /// The class of the error.
+    pub kind: ProviderErrorKind,
+    /// The HTTP status code, if the provider responded.
+    pub status: Option<u16>,
+    /// The error message.
+    pub message: String,
+}
+
+impl ProviderError {
+    /// Creates a new provider error.
+    pub fn new(kind: ProviderErrorKind, message: String) -> Self {
+        Self {
+            kind,
+            status: None,
+            message,
+        }
+    }
+
+    /// Creates an error from an HTTP error response, e.g.
+    /// `ProviderError::from_response("OpenAI completions error", 429, body)`.
+    pub fn from_response(context: &str, status: u16, body: String) -> Self {
+        Self {
+            kind: ProviderErrorKind::from_response(status, &body),
+            status: Some(status),
+            message: format!("{}: {}", context, body),
+        }
+    }
+
+    /// Creates an error from a failed HTTP request.
+    pub fn from_reqwest(context: &str, err: reqwest::Error) -> Self {
+        Self {
+            kind: ProviderErrorKind::from_reqwest(&err),
+            status: err.status().map(|s| s.as_u16()),
+            message: format!("{}: {}", context, err),
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+/// Counters of model errors per [`ProviderErrorKind`].
+#[derive(Debug, Default)]
+pub struct ProviderErrorCounts {
+    counts: [AtomicU64; ProviderErrorKind::ALL.len()],
+}
+
+impl ProviderErrorCounts {
+    /// Classifies the error and increments its counter, returning its class.
+    pub fn record(&self, err: &BoxError) -> ProviderErrorKind {
+        let kind = ProviderErrorKind::of(err);
+        self.counts[kind as usize].fetch_add(1, Ordering::Relaxed);
+        kind
+    }
+
+    /// Returns the count of the given class.
+    pub fn get(&self, kind: ProviderErrorKind) -> u64 {
+        self.counts[kind as usize].load(Ordering::Relaxed)
+    }
+
+    /// Returns the counts of all classes.
+    pub fn snapshot(&self) -> BTreeMap<ProviderErrorKind, u64> {
+        ProviderErrorKind::ALL
+            .iter()
+            .map(|k| (*k, self.get(*k)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        use ProviderErrorKind::*;
+
+        assert_eq!(ProviderErrorKind::from_response(401, ""), Auth);
+        assert_eq!(ProviderErrorKind::from_response(429, ""), Quota);
+        assert_eq!(ProviderErrorKind::from_response(503, ""), Server);
+        assert_eq!(
+            ProviderErrorKind::from_response(
+                400,
+                r#"{"error":{"message":"This model's maximum context length is 128000 tokens.","code":"context_length_exceeded"}}"#
+            ),
+            ContextLength
+        );
+        assert_eq!(
+            ProviderErrorKind::from_response(400, r#"{"error":{"code":"content_filter"}}"#),
+            ContentFilter
+        );
+        assert_eq!(
+            ProviderErrorKind::from_response(400, "invalid temperature"),
+            Other
+        );
+
+        let err: BoxError = ProviderError::from_response(
+            "OpenAI completions error",
+            401,
+            "Incorrect API key provided".to_string(),
+        )
+        .into();
+        assert_eq!(ProviderErrorKind::of(&err), Auth);
+        assert_eq!(
+            err.to_string(),
+            "OpenAI completions error: Incorrect API key provided"
+        );
+        let err: BoxError = "prompt is too long: 210000 tokens".into();
+        assert_eq!(ProviderErrorKind::of(&err), ContextLength);
+
+        let counts = ProviderErrorCounts::default();
+        counts.record(&err);
+        counts.record(&err);
+        counts.record(&"boom".into());
+        assert_eq!(counts.get(ContextLength), 2);
+        let snapshot = counts.snapshot();
+        assert_eq!(snapshot.len(), 7);
+        assert_eq!(snapshot[&Other], 1);
+        assert_eq!(snapshot[&Auth], 0);
+        assert_eq!(
+            serde_json::to_string(&snapshot).unwrap(),
+            r#"{"auth":0,"quota":0,"context_length":2,"content_filter":0,"network":0,"server":0,"other":1}"#
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Provider fallback chain.
+//!
+//! A [`FallbackProvider`] wraps an ordered list of completion providers, e.g. a hosted model
+//! first and a local one last. A request is sent to the first provider, and transparently
+//! retried with the next one when it fails with a retryable error: rate limits (429), server
+//! errors (5xx), network errors and timeouts (see [`ProviderErrorKind::is_retryable`]). Other
+//! errors, e.g. an invalid request or a context too long, are returned at once, as the next
+//! providers would fail the same way.
+//!
+//! The name of the provider that served the request is set in [`AgentOutput::provider`].
+//!
+//! # Example
+//! ```rust,ignore
+//! let fallback = FallbackProvider::new()
+//!     .with_provider("openai", Arc::new(openai.completion_model("gpt-4o")))
+//!     .with_provider("anthropic", Arc::new(anthropic.completion_model("")))
+//!     .with_provider("ollama", Arc::new(ollama.completion_model("llama3.2")))
+//!     .with_timeout(Duration::from_secs(60));
+//! let model = Model::with_completer(Arc::new(fallback));
+//! ```
+
+use anda_core::{AgentOutput, BoxError, BoxPinFut, CompletionRequest, ModelCapabilities};
+use futures::{StreamExt, stream::BoxStream};
+use std::{sync::Arc, time::Duration};
+
+use super::{CompletionFeaturesDyn, ProviderError, ProviderErrorKind};
+
+/// A named provider of the chain.
+#[derive(Clone)]
+struct NamedProvider {
+    name: String,
+    completer: Arc<dyn CompletionFeaturesDyn>,
+}
+
+/// A completion provider that fails over to the next provider of an ordered list.
+#[derive(Clone, Default)]
+pub struct FallbackProvider {
+    providers: Vec<NamedProvider>,
+    timeout: Option<Duration>,
+}
+
+impl FallbackProvider {
+    /// Creates an empty chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a provider to the chain, tried after the ones added before.
+    pub fn with_provider(mut self, name: &str, completer: Arc<dyn CompletionFeaturesDyn>) -> Self {
+        self.providers.push(NamedProvider {
+            name: name.to_string(),
+            completer,
+        });
+        self
+    }
+
+    /// Sets the timeout of each attempt. A provider that times out fails with a network
+    /// error, and the next one is tried. For streams, it applies to the first output.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the names of the providers, in order.
+    pub fn providers(&self) -> Vec<&str> {
+        self.providers.iter().map(|p| p.name.as_str()).collect()
+    }
+}
+
+fn timeout_error(name: &str, timeout: Duration) -> BoxError {
+    ProviderError::new(
+        ProviderErrorKind::Network,
+        format!("provider {} timed out after {:?}", name, timeout),
+    )
+    .into()
+}
+
+impl CompletionFeaturesDyn for FallbackProvider {
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut last_err: BoxError = "no provider in the fallback chain".into();
+            for (i, provider) in this.providers.iter().enumerate() {
+                let fut = provider.completer.completion(req.clone());
+                let res = match this.timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, fut)
+                        .await
+                        .unwrap_or_else(|_| Err(timeout_error(&provider.name, timeout))),
+                    None => fut.await,
+                };
+                match res {
+                    Ok(mut output) => {
+                        output.provider = Some(provider.name.clone());
+                        return Ok(output);
+                    }
+                    Err(err) => {
+                        if !ProviderErrorKind::of(&err).is_retryable()
+                            || i + 1 == this.providers.len()
+                        {
+                            return Err(err);
+                        }
+                        log::warn!(provider = provider.name; "provider failed, trying the next one: {}", err);
+                        last_err = err;
+                    }
+                }
+            }
+            Err(last_err)
+        })
+    }
+
+    /// Fails over until a provider yields its first output. Providers are not failed over
+    /// once the stream has started. The provider is set in the first output.
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        let this = self.clone();
+        let started = async move {
+            let mut last_err: BoxError = "no provider in the fallback chain".into();
+            for (i, provider) in this.providers.iter().enumerate() {
+                let mut stream = provider.completer.completion_stream(req.clone());
+                let first = match this.timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, stream.next())
+                        .await
+                        .unwrap_or_else(|_| Some(Err(timeout_error(&provider.name, timeout)))),
+                    None => stream.next().await,
+                };
+                match first {
+                    Some(Ok(mut output)) => {
+                        output.provider = Some(provider.name.clone());
+                        let first = futures::stream::once(futures::future::ready(Ok(output)));
+                        return first.chain(stream).boxed();
+                    }
+                    Some(Err(err))
+                        if ProviderErrorKind::of(&err).is_retryable()
+                            && i + 1 < this.providers.len() =>
+                    {
+                        log::warn!(provider = provider.name; "provider failed, trying the next one: {}", err);
+                        last_err = err;
+                    }
+                    Some(Err(err)) => {
+                        return futures::stream::once(futures::future::ready(Err(err))).boxed();
+                    }
+                    None => return futures::stream::empty().boxed(),
+                }
+            }
+            futures::stream::once(futures::future::ready(Err(last_err))).boxed()
+        };
+        futures::stream::once(started).flatten().boxed()
+    }
+
+    /// Returns the capabilities supported by all providers, since any of them may serve
+    /// a request.
+    fn capabilities(&self) -> ModelCapabilities {
+        self.providers
+            .iter()
+            .map(|p| p.completer.capabilities())
+            .reduce(|a, b| a.intersect(&b))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::MockImplemented;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Failing {
+        kind: ProviderErrorKind,
+        calls: AtomicUsize,
+    }
+
+    impl Failing {
+        fn new(kind: ProviderErrorKind) -> Arc<Self> {
+            Arc::new(Self {
+                kind,
+                calls: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl CompletionFeaturesDyn for Failing {
+        fn completion(&self, _req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(futures::future::ready(Err(ProviderError::new(
+                self.kind,
+                "failed".to_string(),
+            )
+            .into())))
+        }
+    }
+
+    struct Hanging;
+
+    impl CompletionFeaturesDyn for Hanging {
+        fn completion(&self, _req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+            Box::pin(futures::future::pending())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fallback_provider() {
+        let quota = Failing::new(ProviderErrorKind::Quota);
+        let server = Failing::new(ProviderErrorKind::Server);
+        let fallback = FallbackProvider::new()
+            .with_provider("primary", quota.clone())
+            .with_provider("secondary", server.clone())
+            .with_provider("local", Arc::new(MockImplemented));
+        assert_eq!(fallback.providers(), vec!["primary", "secondary", "local"]);
+
+        let req = CompletionRequest {
+            prompt: "hello".to_string(),
+            ..Default::default()
+        };
+        let output = fallback.completion(req.clone()).await.unwrap();
+        assert_eq!(output.content, "hello");
+        assert_eq!(output.provider.as_deref(), Some("local"));
+        assert_eq!(quota.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(server.calls.load(Ordering::SeqCst), 1);
+
+        let mut stream = fallback.completion_stream(req.clone());
+        let output = stream.next().await.unwrap().unwrap();
+        assert_eq!(output.provider.as_deref(), Some("local"));
+        assert!(stream.next().await.is_none());
+
+        // errors that are not retryable are returned at once
+        let invalid = Failing::new(ProviderErrorKind::ContextLength);
+        let fallback = FallbackProvider::new()
+            .with_provider("primary", invalid.clone())
+            .with_provider("local", Arc::new(MockImplemented));
+        let err = fallback.completion(req.clone()).await.unwrap_err();
+        assert_eq!(
+            ProviderErrorKind::of(&err),
+            ProviderErrorKind::ContextLength
+        );
+        let mut stream = fallback.completion_stream(req.clone());
+        assert!(stream.next().await.unwrap().is_err());
+        assert_eq!(invalid.calls.load(Ordering::SeqCst), 2);
+
+        // the last error is returned when all providers fail
+        let fallback = FallbackProvider::new()
+            .with_provider("primary", Failing::new(ProviderErrorKind::Server))
+            .with_provider("secondary", Failing::new(ProviderErrorKind::Quota));
+        let err = fallback.completion(req.clone()).await.unwrap_err();
+        assert_eq!(ProviderErrorKind::of(&err), ProviderErrorKind::Quota);
+        assert!(FallbackProvider::new().completion(req).await.is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fallback_timeout() {
+        let fallback = FallbackProvider::new()
+            .with_provider("slow", Arc::new(Hanging))
+            .with_provider("local", Arc::new(MockImplemented))
+            .with_timeout(Duration::from_millis(50));
+        let req = CompletionRequest {
+            prompt: "hello".to_string(),
+            ..Default::default()
+        };
+        let output = fallback.completion(req.clone()).await.unwrap();
+        assert_eq!(output.provider.as_deref(), Some("local"));
+        let output = fallback
+            .completion_stream(req)
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(output.provider.as_deref(), Some("local"));
+    }
+}
@@ -0,0 +1,953 @@
+//! Google Gemini API client implementation for Anda Engine
+//!
+//! This module provides integration with the `generateContent` method of the Gemini API
+//! (v1beta), including:
+//! - Client configuration and management
+//! - Completion model handling, with function calling, safety settings and streaming
+//! - Conversion between Anda's chat history and Gemini's contents
+//!
+//! The chat history of requests and outputs is in the OpenAI format, as with the other
+//! providers. It is converted to Gemini contents when sending:
+//! - system messages become the `systemInstruction`;
+//! - assistant messages become `model` contents, with tool calls as `functionCall` parts;
+//! - tool messages become `functionResponse` parts, named after their tool calls;
+//! - consecutive messages of the same role are merged.
+//!
+//! Tool parameters and response schemas are sent as JSON Schema (`parametersJsonSchema` and
+//! `responseJsonSchema`), so the schemas of tools need no conversion to the OpenAPI subset.
+//! Gemini does not always return IDs of function calls, they are generated if missing.
+
+use anda_core::{
+    AgentOutput, BoxError, BoxPinFut, CONTENT_TYPE_JSON, CompletionFeatures, CompletionRequest,
+    ContentPart, Message, ModelCapabilities, Resource, ToolCall, Usage as ModelUsage,
+};
+use futures::stream::BoxStream;
+use log::{Level::Debug, log_enabled};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{
+    CompletionFeaturesDyn, ProviderError, ProviderErrorKind,
+    stream::{EventParser, event_stream, take_data_lines},
+};
+use crate::{APP_USER_AGENT, id::new_tool_call_id};
+
+// ================================================================
+// Main Gemini Client
+// ================================================================
+const API_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// `gemini-2.5-flash` completion model
+pub const GEMINI_2_5_FLASH: &str = "gemini-2.5-flash";
+/// `gemini-2.5-pro` completion model
+pub const GEMINI_2_5_PRO: &str = "gemini-2.5-pro";
+/// `gemini-2.0-flash` completion model
+pub const GEMINI_2_0_FLASH: &str = "gemini-2.0-flash";
+
+/// Gemini API client configuration and HTTP client
+#[derive(Clone)]
+pub struct Client {
+    endpoint: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Creates a new Gemini client instance with the provided API key
+    ///
+    /// # Arguments
+    /// * `api_key` - Gemini API key for authentication
+    /// * `endpoint` - API endpoint, the official one if `None` or empty
+    pub fn new(api_key: &str, endpoint: Option<String>) -> Self {
+        let endpoint = endpoint.unwrap_or_else(|| API_BASE_URL.to_string());
+        let endpoint = if endpoint.is_empty() {
+            API_BASE_URL.to_string()
+        } else {
+            endpoint
+        };
+        Self {
+            endpoint,
+            http: reqwest::Client::builder()
+                .use_rustls_tls()
+                .https_only(true)
+                .http2_keep_alive_interval(Some(Duration::from_secs(25)))
+                .http2_keep_alive_timeout(Duration::from_secs(15))
+                .http2_keep_alive_while_idle(true)
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(180))
+                .gzip(true)
+                .user_agent(APP_USER_AGENT)
+                .default_headers({
+                    let mut headers = reqwest::header::HeaderMap::new();
+                    let ct: http::HeaderValue = CONTENT_TYPE_JSON.parse().unwrap();
+                    headers.insert(http::header::CONTENT_TYPE, ct.clone());
+                    headers.insert(http::header::ACCEPT, ct);
+                    headers.insert(
+                        "x-goog-api-key",
+                        api_key.parse().expect("API key should parse"),
+                    );
+                    headers
+                })
+                .build()
+                .expect("Gemini reqwest client should build"),
+        }
+    }
+
+    /// Creates a POST request builder for the given API path
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let url = format!("{}{}", self.endpoint, path);
+        self.http.post(url)
+    }
+
+    /// Creates a completion model with the given name
+    ///
+    /// # Arguments
+    /// * `model` - Name of the completion model to use, [`GEMINI_2_5_FLASH`] if empty
+    pub fn completion_model(&self, model: &str) -> CompletionModel {
+        CompletionModel::new(
+            self.clone(),
+            if model.is_empty() {
+                GEMINI_2_5_FLASH
+            } else {
+                model
+            },
+        )
+    }
+}
+
+/// Token usage information from Gemini API
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: u64,
+    #[serde(default)]
+    pub candidates_token_count: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thoughts_token_count: Option<u64>,
+}
+
+impl UsageMetadata {
+    fn to_usage(&self) -> ModelUsage {
+        ModelUsage {
+            input_tokens: self.prompt_token_count,
+            output_tokens: self.candidates_token_count
+                + self.thoughts_token_count.unwrap_or_default(),
+            requests: 1,
+            ..Default::default()
+        }
+    }
+}
+
+/// A function call part of a Gemini content.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FunctionCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+impl FunctionCall {
+    fn into_tool_call(self) -> ToolCall {
+        ToolCall {
+            id: self
+                .id
+                .filter(|id| !id.is_empty())
+                .unwrap_or_else(new_tool_call_id),
+            name: self.name,
+            args: if self.args.is_null() {
+                "{}".to_string()
+            } else {
+                self.args.to_string()
+            },
+            result: None,
+        }
+    }
+}
+
+/// A part of a Gemini content, other kinds of parts are ignored.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Whether the text is a thought summary, which is not returned to the engine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thought: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+}
+
+/// A content of a Gemini candidate.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Content {
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+/// A response candidate.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    #[serde(default)]
+    pub content: Content,
+    pub finish_reason: Option<String>,
+}
+
+/// Feedback on the prompt, with the reason if it was blocked.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptFeedback {
+    pub block_reason: Option<String>,
+}
+
+/// Response structure for Gemini generateContent API
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionResponse {
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    pub prompt_feedback: Option<PromptFeedback>,
+    pub usage_metadata: Option<UsageMetadata>,
+    pub model_version: Option<String>,
+}
+
+impl CompletionResponse {
+    /// Returns the text and the tool calls of the first candidate.
+    fn take_output(&mut self) -> (String, Vec<ToolCall>) {
+        let mut content = String::new();
+        let mut tool_calls = Vec::new();
+        if let Some(candidate) = self.candidates.first_mut() {
+            for part in candidate.content.parts.drain(..) {
+                if let Some(call) = part.function_call {
+                    tool_calls.push(call.into_tool_call());
+                } else if let Some(text) = part.text
+                    && part.thought != Some(true)
+                {
+                    content.push_str(&text);
+                }
+            }
+        }
+        (content, tool_calls)
+    }
+
+    /// Returns the failed reason of the response, None for a normal stop.
+    fn failed_reason(&self) -> Option<String> {
+        match self.candidates.first() {
+            Some(candidate) => failed_reason(candidate.finish_reason.clone()),
+            None => Some(
+                match self
+                    .prompt_feedback
+                    .as_ref()
+                    .and_then(|f| f.block_reason.as_ref())
+                {
+                    Some(reason) => format!("prompt blocked: {}", reason),
+                    None => "response without candidates".to_string(),
+                },
+            ),
+        }
+    }
+
+    fn try_into(mut self, mut full_history: Vec<Value>) -> Result<AgentOutput, BoxError> {
+        let failed_reason = self.failed_reason();
+        let (content, tool_calls) = self.take_output();
+        full_history.push(assistant_message(&content, &tool_calls));
+
+        Ok(AgentOutput {
+            content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            failed_reason,
+            full_history: Some(full_history),
+            usage: self
+                .usage_metadata
+                .map(|u| u.to_usage())
+                .unwrap_or_default(),
+            model: self.model_version,
+            ..Default::default()
+        })
+    }
+}
+
+/// Returns the failed reason of a finish reason, None for a normal stop.
+fn failed_reason(finish_reason: Option<String>) -> Option<String> {
+    match finish_reason {
+        Some(reason) if reason == "STOP" => None,
+        Some(reason) => Some(reason),
+        None => Some("response without a finish reason".to_string()),
+    }
+}
+
+/// Returns the assistant message with the tool calls in the OpenAI format of the chat history.
+fn assistant_message(content: &str, tool_calls: &[ToolCall]) -> Value {
+    let mut message = json!({
+        "role": "assistant",
+        "content": content,
+    });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = tool_calls
+            .iter()
+            .map(|tc| {
+                json!({
+                    "id": tc.id,
+                    "type": "function",
+                    "function": {"name": tc.name, "arguments": tc.args},
+                })
+            })
+            .collect();
+    }
+    message
+}
+
+/// Converts a chat history in the OpenAI format to the system instruction and the contents
+/// of the Gemini format.
+pub fn to_gemini_contents(history: &[Value]) -> (Vec<String>, Vec<Value>) {
+    let mut system: Vec<String> = Vec::new();
+    let mut contents: Vec<Value> = Vec::new();
+    // the function names of the tool calls, as function responses are matched by name
+    let mut call_names: BTreeMap<String, Value> = BTreeMap::new();
+    let mut push = |role: &str, parts: Vec<Value>| {
+        if parts.is_empty() {
+            return;
+        }
+        if let Some(last) = contents.last_mut()
+            && last["role"] == role
+            && let Some(content) = last["parts"].as_array_mut()
+        {
+            content.extend(parts);
+            return;
+        }
+        contents.push(json!({"role": role, "parts": parts}));
+    };
+
+    for msg in history {
+        let content = &msg["content"];
+        match msg["role"].as_str().unwrap_or_default() {
+            "system" | "developer" => {
+                let text = content_text(content);
+                if !text.is_empty() {
+                    system.push(text);
+                }
+            }
+            "assistant" => {
+                let mut parts = content_parts(content);
+                if let Some(calls) = msg["tool_calls"].as_array() {
+                    for call in calls {
+                        let name = call["function"]["name"].clone();
+                        if let Some(id) = call["id"].as_str() {
+                            call_names.insert(id.to_string(), name.clone());
+                        }
+                        let args = call["function"]["arguments"].as_str().unwrap_or_default();
+                        parts.push(json!({
+                            "functionCall": {
+                                "name": name,
+                                "args": serde_json::from_str::<Value>(args)
+                                    .ok()
+                                    .filter(|v| v.is_object())
+                                    .unwrap_or_else(|| json!({})),
+                            },
+                        }));
+                    }
+                }
+                push("model", parts);
+            }
+            "tool" => {
+                let name = msg["tool_call_id"]
+                    .as_str()
+                    .and_then(|id| call_names.get(id).cloned())
+                    .or_else(|| msg.get("name").cloned())
+                    .unwrap_or_default();
+                let text = content_text(content);
+                push(
+                    "user",
+                    vec![json!({
+                        "functionResponse": {
+                            "name": name,
+                            "response": {
+                                "result": serde_json::from_str::<Value>(&text)
+                                    .unwrap_or(Value::String(text)),
+                            },
+                        },
+                    })],
+                )
+            }
+            _ => push("user", content_parts(content)),
+        }
+    }
+    (system, contents)
+}
+
+/// Converts the content of a message to Gemini parts. Parts that are already Gemini parts
+/// are kept.
+fn content_parts(content: &Value) -> Vec<Value> {
+    match content {
+        Value::Null => Vec::new(),
+        Value::String(text) if text.is_empty() => Vec::new(),
+        Value::String(text) => vec![json!({"text": text})],
+        Value::Array(parts) => parts
+            .iter()
+            .map(
+                |part| match serde_json::from_value::<ContentPart>(part.clone()) {
+                    Ok(part) => part.to_gemini(),
+                    Err(_) => part.clone(),
+                },
+            )
+            .collect(),
+        other => vec![json!({"text": other.to_string()})],
+    }
+}
+
+/// Returns the text of the content of a message.
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        other => other.to_string(),
+    }
+}
+
+/// Completion model implementation for Gemini API
+#[derive(Clone)]
+pub struct CompletionModel {
+    client: Client,
+    pub model: String,
+}
+
+impl CompletionModel {
+    /// Creates a new completion model instance
+    ///
+    /// # Arguments
+    /// * `client` - Gemini client instance
+    /// * `model` - Name of the completion model
+    pub fn new(client: Client, model: &str) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+        }
+    }
+
+    /// Builds the JSON body of a generateContent request, and the full history of messages
+    /// in the OpenAI format that the response is appended to.
+    pub fn request_body(&self, mut req: CompletionRequest) -> (Value, Vec<Value>) {
+        let mut full_history = if let Some(system) = &req.system {
+            vec![json!(Message {
+                role: "system".into(),
+                content: system.to_owned().into(),
+                ..Default::default()
+            })]
+        } else {
+            vec![]
+        };
+
+        full_history.append(&mut req.chat_history);
+
+        if !req.content_parts.is_empty() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: json!(req.user_content_parts()),
+                ..Default::default()
+            }));
+        } else if let Some(prompt) = req.prompt_with_context() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: prompt.into(),
+                ..Default::default()
+            }));
+        }
+
+        let (system, contents) = to_gemini_contents(&full_history);
+        let mut body = json!({
+            "contents": contents,
+        });
+
+        let obj = body.as_object_mut().unwrap();
+        if !system.is_empty() {
+            obj.insert(
+                "systemInstruction".to_string(),
+                json!({"parts": [{"text": system.join("\n\n")}]}),
+            );
+        }
+
+        let mut config = serde_json::Map::new();
+        if let Some(temperature) = req.temperature {
+            config.insert("temperature".to_string(), Value::from(temperature));
+        }
+        if let Some(max_tokens) = req.max_tokens {
+            config.insert("maxOutputTokens".to_string(), Value::from(max_tokens));
+        }
+        if let Some(stop) = req.stop {
+            config.insert("stopSequences".to_string(), Value::from(stop));
+        }
+        if let Some(format) = &req.response_format {
+            match format["type"].as_str() {
+                Some("json_schema") => {
+                    config.insert("responseMimeType".to_string(), CONTENT_TYPE_JSON.into());
+                    config.insert(
+                        "responseJsonSchema".to_string(),
+                        format["json_schema"]["schema"].clone(),
+                    );
+                }
+                Some("json_object") => {
+                    config.insert("responseMimeType".to_string(), CONTENT_TYPE_JSON.into());
+                }
+                _ => {}
+            }
+        }
+        if !config.is_empty() {
+            obj.insert("generationConfig".to_string(), Value::Object(config));
+        }
+
+        if !req.tools.is_empty() {
+            obj.insert(
+                "tools".to_string(),
+                json!([{
+                    "functionDeclarations": req
+                        .tools
+                        .into_iter()
+                        .map(|tool| {
+                            json!({
+                                "name": tool.name,
+                                "description": tool.description,
+                                "parametersJsonSchema": tool.parameters,
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                }]),
+            );
+            obj.insert(
+                "toolConfig".to_string(),
+                json!({"functionCallingConfig": {
+                    "mode": if req.tool_choice_required { "ANY" } else { "AUTO" },
+                }}),
+            );
+        }
+
+        if !req.safety_settings.is_empty() {
+            obj.insert("safetySettings".to_string(), json!(req.safety_settings));
+        }
+
+        (body, full_history)
+    }
+}
+
+impl CompletionFeatures for CompletionModel {
+    async fn completion(
+        &self,
+        req: CompletionRequest,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<AgentOutput, BoxError> {
+        CompletionFeaturesDyn::completion(self, req).await
+    }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+        _resources: Option<Vec<Resource>>,
+    ) -> BoxStream<'_, Result<AgentOutput, BoxError>> {
+        CompletionFeaturesDyn::completion_stream(self, req)
+    }
+}
+
+impl CompletionFeaturesDyn for CompletionModel {
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (body, full_history) = self.request_body(req);
+        let client = self.client.clone();
+        let path = format!("/models/{}:generateContent", self.model);
+
+        Box::pin(async move {
+            if log_enabled!(Debug)
+                && let Ok(val) = serde_json::to_string(&body)
+            {
+                log::debug!(request = val; "Gemini generateContent request");
+            }
+
+            let response = client.post(&path).json(&body).send().await?;
+            if response.status().is_success() {
+                let text = response.text().await?;
+                match serde_json::from_str::<CompletionResponse>(&text) {
+                    Ok(res) => {
+                        if log_enabled!(Debug)
+                            && let Ok(val) = serde_json::to_string(&res)
+                        {
+                            log::debug!(response = val; "Gemini generateContent response");
+                        }
+                        res.try_into(full_history)
+                    }
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("Gemini generateContent error: {}, body: {}", err, text),
+                    )
+                    .into()),
+                }
+            } else {
+                let status = response.status().as_u16();
+                let msg = response.text().await?;
+                Err(
+                    ProviderError::from_response("Gemini generateContent error", status, msg)
+                        .into(),
+                )
+            }
+        })
+    }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        let (body, full_history) = self.request_body(req);
+        let path = format!("/models/{}:streamGenerateContent?alt=sse", self.model);
+        event_stream(
+            "Gemini generateContent error",
+            self.model.clone(),
+            self.client.post(&path).json(&body),
+            ResponseEventParser::new(full_history),
+        )
+    }
+
+    /// Gemini models see images and have a context of 1M tokens.
+    fn capabilities(&self) -> ModelCapabilities {
+        ModelCapabilities {
+            max_context: Some(1_048_576),
+            ..Default::default()
+        }
+    }
+}
+
+/// A chunk of a generateContent stream, or an error.
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(flatten)]
+    response: CompletionResponse,
+    error: Option<Value>,
+}
+
+/// Parses the events of a generateContent stream, where every event is a partial response.
+/// Function calls are not split across events.
+struct ResponseEventParser {
+    buf: Vec<u8>,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    usage: UsageMetadata,
+    finish_reason: Option<String>,
+    block_reason: Option<String>,
+    full_history: Vec<Value>,
+    done: bool,
+}
+
+impl ResponseEventParser {
+    fn new(full_history: Vec<Value>) -> Self {
+        Self {
+            buf: Vec::new(),
+            content: String::new(),
+            tool_calls: Vec::new(),
+            usage: UsageMetadata::default(),
+            finish_reason: None,
+            block_reason: None,
+            full_history,
+            done: false,
+        }
+    }
+
+    fn apply(&mut self, chunk: StreamChunk) -> Result<Option<AgentOutput>, BoxError> {
+        if let Some(error) = chunk.error {
+            let msg = error.to_string();
+            return Err(ProviderError::new(
+                ProviderErrorKind::from_message(&msg),
+                format!("Gemini generateContent error: {}", msg),
+            )
+            .into());
+        }
+
+        let mut res = chunk.response;
+        if let Some(usage) = res.usage_metadata.take() {
+            self.usage = usage;
+        }
+        if let Some(reason) = res.prompt_feedback.take().and_then(|f| f.block_reason) {
+            self.block_reason = Some(reason);
+            self.done = true;
+        }
+        if let Some(reason) = res.candidates.first().and_then(|c| c.finish_reason.clone()) {
+            self.finish_reason = Some(reason);
+            self.done = true;
+        }
+
+        let (content, tool_calls) = res.take_output();
+        if content.is_empty() && tool_calls.is_empty() {
+            return Ok(None);
+        }
+        self.content.push_str(&content);
+        self.tool_calls.extend(tool_calls.iter().cloned());
+        Ok(Some(AgentOutput {
+            content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            ..Default::default()
+        }))
+    }
+}
+
+impl EventParser for ResponseEventParser {
+    fn feed(&mut self, bytes: &[u8]) -> Result<Vec<AgentOutput>, BoxError> {
+        self.buf.extend_from_slice(bytes);
+        let mut outputs = Vec::new();
+        for data in take_data_lines(&mut self.buf)? {
+            let chunk: StreamChunk = serde_json::from_str(&data).map_err(|err| {
+                ProviderError::new(
+                    ProviderErrorKind::Server,
+                    format!("Gemini generateContent error: {}, data: {}", err, data),
+                )
+            })?;
+            if let Some(output) = self.apply(chunk)? {
+                outputs.push(output);
+            }
+        }
+        Ok(outputs)
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn finish(mut self) -> AgentOutput {
+        self.full_history
+            .push(assistant_message(&self.content, &self.tool_calls));
+        AgentOutput {
+            usage: self.usage.to_usage(),
+            failed_reason: match self.block_reason {
+                Some(reason) => Some(format!("prompt blocked: {}", reason)),
+                None if self.done => failed_reason(self.finish_reason),
+                None => Some("stream ended without a finish reason".to_string()),
+            },
+            full_history: Some(self.full_history),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::{FunctionDefinition, SafetySetting};
+
+    const EVENTS: &str = r#"data: {"candidates": [{"content": {"parts": [{"text": "Let me"}],"role": "model"},"index": 0}],"usageMetadata": {"promptTokenCount": 25,"totalTokenCount": 25},"modelVersion": "gemini-2.5-flash"}
+
+data: {"candidates": [{"content": {"parts": [{"text": " check"}, {"functionCall": {"name": "get_weather","args": {"city": "Paris"}}}],"role": "model"},"finishReason": "STOP","index": 0}],"usageMetadata": {"promptTokenCount": 25,"candidatesTokenCount": 20,"thoughtsTokenCount": 10,"totalTokenCount": 55},"modelVersion": "gemini-2.5-flash"}
+
+"#;
+
+    #[test]
+    fn test_to_gemini_contents() {
+        let history = vec![
+            json!({"role": "system", "content": "You are a helpful assistant."}),
+            json!({"role": "user", "content": "What is the weather in Paris and Berlin?"}),
+            json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                    {"id": "call_2", "type": "function", "function": {"name": "get_time", "arguments": ""}},
+                ],
+            }),
+            json!({"role": "tool", "tool_call_id": "call_1", "content": "Sunny"}),
+            json!({"role": "tool", "tool_call_id": "call_2", "content": "{\"time\":\"12:00\"}"}),
+            json!({"role": "user", "content": [
+                {"type": "text", "text": "And this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+                {"type": "input_audio", "input_audio": {"data": "AAAA", "format": "wav"}},
+            ]}),
+        ];
+        let (system, contents) = to_gemini_contents(&history);
+        assert_eq!(system, vec!["You are a helpful assistant."]);
+        assert_eq!(
+            Value::Array(contents),
+            json!([
+                {"role": "user", "parts": [{"text": "What is the weather in Paris and Berlin?"}]},
+                {"role": "model", "parts": [
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}},
+                    {"functionCall": {"name": "get_time", "args": {}}},
+                ]},
+                // function responses and the next user message are merged into one content
+                {"role": "user", "parts": [
+                    {"functionResponse": {"name": "get_weather", "response": {"result": "Sunny"}}},
+                    {"functionResponse": {"name": "get_time", "response": {"result": {"time": "12:00"}}}},
+                    {"text": "And this?"},
+                    {"inlineData": {"mimeType": "image/png", "data": "iVBORw0KGgo="}},
+                    {"inlineData": {"mimeType": "audio/wav", "data": "AAAA"}},
+                ]},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_completion_response() {
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "thinking...", "thought": true},
+                    {"text": "Let me check."},
+                    {"functionCall": {"id": "fc_1", "name": "get_weather", "args": {"city": "Paris"}}},
+                    {"functionCall": {"name": "get_time"}},
+                ]},
+                "finishReason": "STOP",
+                "safetyRatings": [],
+            }],
+            "usageMetadata": {"promptTokenCount": 20, "candidatesTokenCount": 10, "totalTokenCount": 30},
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert_eq!(output.content, "Let me check.");
+        assert!(output.failed_reason.is_none());
+        assert_eq!(output.usage.input_tokens, 20);
+        assert_eq!(output.usage.output_tokens, 10);
+        let tool_calls = output.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].id, "fc_1");
+        assert_eq!(tool_calls[0].args, r#"{"city":"Paris"}"#);
+        assert!(tool_calls[1].id.starts_with("call_"));
+        assert_eq!(tool_calls[1].args, "{}");
+
+        // the history converts back to the same function calls, and results to responses
+        let mut history = output.full_history.unwrap();
+        history.push(json!({"role": "tool", "tool_call_id": tool_calls[1].id, "content": "12:00"}));
+        let (_, contents) = to_gemini_contents(&history);
+        assert_eq!(
+            contents[0]["parts"][1],
+            json!({"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}})
+        );
+        assert_eq!(
+            contents[1]["parts"][0],
+            json!({"functionResponse": {"name": "get_time", "response": {"result": "12:00"}}})
+        );
+
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "candidates": [{"content": {"parts": [{"text": "Once upon"}]}, "finishReason": "MAX_TOKENS"}],
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert_eq!(output.failed_reason.as_deref(), Some("MAX_TOKENS"));
+
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "promptFeedback": {"blockReason": "SAFETY"},
+            "usageMetadata": {"promptTokenCount": 20},
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert_eq!(
+            output.failed_reason.as_deref(),
+            Some("prompt blocked: SAFETY")
+        );
+    }
+
+    #[test]
+    fn test_request_body() {
+        let model = Client::new("test", None).completion_model("");
+        let (body, history) = model.request_body(
+            CompletionRequest::builder()
+                .system("You are a helpful assistant.")
+                .prompt("Book a table for two.")
+                .tool(FunctionDefinition {
+                    name: "book_table".to_string(),
+                    description: "Books a table.".to_string(),
+                    parameters: json!({"type": "object", "properties": {"guests": {"type": "integer"}}}),
+                    strict: None,
+                })
+                .tool_choice_required()
+                .max_tokens(1024)
+                .response_format(json!({"type": "json_object"}))
+                .safety_setting(SafetySetting::new(
+                    "HARM_CATEGORY_DANGEROUS_CONTENT",
+                    "BLOCK_ONLY_HIGH",
+                ))
+                .build(),
+        );
+        assert_eq!(
+            body,
+            json!({
+                "contents": [{"role": "user", "parts": [{"text": "Book a table for two."}]}],
+                "systemInstruction": {"parts": [{"text": "You are a helpful assistant."}]},
+                "generationConfig": {"maxOutputTokens": 1024, "responseMimeType": "application/json"},
+                "tools": [{"functionDeclarations": [{
+                    "name": "book_table",
+                    "description": "Books a table.",
+                    "parametersJsonSchema": {"type": "object", "properties": {"guests": {"type": "integer"}}},
+                }]}],
+                "toolConfig": {"functionCallingConfig": {"mode": "ANY"}},
+                "safetySettings": [{"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "BLOCK_ONLY_HIGH"}],
+            })
+        );
+        assert_eq!(model.model, GEMINI_2_5_FLASH);
+        // the full history keeps the system message for the next turns
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["role"], "system");
+    }
+
+    #[test]
+    fn test_response_event_parser() {
+        let mut parser = ResponseEventParser::new(vec![json!({"role": "user", "content": "Hi"})]);
+        let mut outputs = Vec::new();
+        // feeds the events in pieces that split lines
+        for piece in EVENTS.as_bytes().chunks(7) {
+            outputs.extend(parser.feed(piece).unwrap());
+        }
+        assert!(parser.is_done());
+        assert_eq!(outputs.len(), 2);
+
+        let mut output = AgentOutput::default();
+        for delta in outputs {
+            output.accumulate(delta);
+        }
+        output.accumulate(parser.finish());
+        assert_eq!(output.content, "Let me check");
+        assert!(output.failed_reason.is_none());
+        assert_eq!(output.usage.input_tokens, 25);
+        assert_eq!(output.usage.output_tokens, 30);
+        let tool_calls = output.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].name, "get_weather");
+        assert_eq!(tool_calls[0].args, r#"{"city":"Paris"}"#);
+        let history = output.full_history.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1]["tool_calls"][0]["id"], tool_calls[0].id);
+
+        let mut parser = ResponseEventParser::new(vec![]);
+        let err = parser
+            .feed(b"data: {\"error\":{\"code\":429,\"message\":\"Resource has been exhausted\",\"status\":\"RESOURCE_EXHAUSTED\"}}\n")
+            .unwrap_err();
+        assert!(err.to_string().contains("RESOURCE_EXHAUSTED"));
+
+        let parser = ResponseEventParser::new(vec![]);
+        assert!(parser.finish().failed_reason.is_some());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn test_gemini() {
+        dotenv::dotenv().ok();
+
+        let api_key = std::env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY is not set");
+        let model = Client::new(&api_key, None).completion_model(GEMINI_2_5_FLASH);
+        let res = CompletionFeaturesDyn::completion(
+            &model,
+            CompletionRequest::builder()
+                .system("You are a helpful assistant.")
+                .prompt("Say hello in one word.")
+                .build(),
+        )
+        .await
+        .unwrap();
+        println!("{:?}", res);
+        assert!(res.failed_reason.is_none());
+    }
+}
@@ -0,0 +1,233 @@
+//! Golden snapshots of the request payloads of the providers.
+//!
+//! Each provider serializes a set of representative [`CompletionRequest`]s, and the bodies are
+//! compared with `goldens/{provider}.json`. A failing test means that a request format changed:
+//! if the change is intended, regenerate the goldens and review their diff:
+//!
+//! ```sh
+//! UPDATE_GOLDENS=1 cargo test -p anda_engine model::golden
+//! ```
+
+use anda_core::{
+    CompletionRequest, ContentPart, Document, FunctionDefinition, ImageDetail, Message,
+    SafetySetting,
+};
+use serde_json::{Map, Value, json};
+use std::path::PathBuf;
+
+use super::{anthropic, deepseek, gemini, ollama, openai, xai};
+
+fn requests() -> Vec<(&'static str, CompletionRequest)> {
+    vec![
+        (
+            "system_prompt",
+            CompletionRequest {
+                system: Some("You are a helpful assistant.".to_string()),
+                system_name: Some("Anda".to_string()),
+                prompt: "Hello!".to_string(),
+                prompter_name: Some("alice".to_string()),
+                temperature: Some(0.5),
+                max_tokens: Some(1024),
+                stop: Some(vec!["\n\n".to_string()]),
+                safety_settings: vec![SafetySetting::new(
+                    "HARM_CATEGORY_HARASSMENT",
+                    "BLOCK_LOW_AND_ABOVE",
+                )],
+                ..Default::default()
+            },
+        ),
+        (
+            "documents",
+            CompletionRequest {
+                prompt: "What is Anda?".to_string(),
+                documents: vec![
+                    Document {
+                        id: "1".to_string(),
+                        text: "Anda is an AI agent framework built with Rust.".to_string(),
+                        ..Default::default()
+                    },
+                    Document {
+                        id: "2".to_string(),
+                        text: "Anda is powered by ICP and TEEs.".to_string(),
+                        ..Default::default()
+                    },
+                ]
+                .into(),
+                ..Default::default()
+            },
+        ),
+        (
+            "chat_history",
+            CompletionRequest {
+                system: Some("You are a helpful assistant.".to_string()),
+                chat_history: vec![
+                    json!(Message {
+                        role: "user".to_string(),
+                        content: "What is the weather in Paris?".into(),
+                        ..Default::default()
+                    }),
+                    json!({
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"},
+                        }],
+                    }),
+                    json!(Message {
+                        role: "tool".to_string(),
+                        content: "Sunny, 25°C".into(),
+                        tool_call_id: Some("call_1".to_string()),
+                        ..Default::default()
+                    }),
+                ],
+                prompt: "And in Berlin?".to_string(),
+                ..Default::default()
+            },
+        ),
+        (
+            "tools",
+            CompletionRequest {
+                prompt: "Book a table for two at 7pm.".to_string(),
+                tools: vec![FunctionDefinition {
+                    name: "book_table".to_string(),
+                    description: "Books a table in the restaurant.".to_string(),
+                    parameters: json!({
+                        "type": "object",
+                        "properties": {
+                            "guests": {"type": "integer"},
+                            "time": {"type": "string"},
+                        },
+                        "required": ["guests", "time"],
+                        "additionalProperties": false,
+                    }),
+                    strict: Some(true),
+                }],
+                tool_choice_required: true,
+                response_format: Some(json!({
+                    "type": "json_schema",
+                    "json_schema": {
+                        "name": "booking",
+                        "schema": {"type": "object", "properties": {"ok": {"type": "boolean"}}},
+                    },
+                })),
+                ..Default::default()
+            },
+        ),
+        (
+            "images",
+            CompletionRequest {
+                prompt: "ignored when content parts are set".to_string(),
+                content_parts: vec![
+                    ContentPart::Text {
+                        text: "What is in this image?".to_string(),
+                    },
+                    ContentPart::Image {
+                        image_url: ImageDetail {
+                            url: "https://example.com/cat.png".to_string(),
+                            detail: Some("low".to_string()),
+                        },
+                    },
+                    ContentPart::Image {
+                        image_url: ImageDetail {
+                            url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                            detail: None,
+                        },
+                    },
+                ],
+                ..Default::default()
+            },
+        ),
+        (
+            "images_with_prompt",
+            CompletionRequest::builder()
+                .document("1", "The screenshot is from the settings page.")
+                .content_part(ContentPart::image_base64("image/png", b"\x89PNG\r\n\x1a\n"))
+                .content_part(
+                    ContentPart::image_url("https://example.com/2.png").with_detail("high"),
+                )
+                .prompt("What is wrong in these screenshots?")
+                .build(),
+        ),
+    ]
+}
+
+fn snapshot(build: impl Fn(CompletionRequest) -> (Value, Vec<Value>)) -> Value {
+    let mut snapshot = Map::new();
+    for (name, req) in requests() {
+        let (body, _) = build(req);
+        snapshot.insert(name.to_string(), body);
+    }
+    Value::Object(snapshot)
+}
+
+fn assert_golden(provider: &str, actual: Value) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/model/goldens")
+        .join(format!("{provider}.json"));
+    let actual_text = serde_json::to_string_pretty(&actual).unwrap() + "\n";
+    if std::env::var_os("UPDATE_GOLDENS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual_text).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "failed to read golden {}: {err}, run with UPDATE_GOLDENS=1 to create it",
+            path.display()
+        )
+    });
+    let expected: Value = serde_json::from_str(&expected).unwrap();
+    if expected != actual {
+        panic!(
+            "request payloads of {provider} differ from the golden {}, run with UPDATE_GOLDENS=1 \
+             to update it if the change is intended\n--- expected\n{}\n+++ actual\n{}",
+            path.display(),
+            serde_json::to_string_pretty(&expected).unwrap(),
+            actual_text
+        );
+    }
+}
+
+#[test]
+fn test_openai_golden() {
+    let client = openai::Client::new("test", None);
+    let model = client.completion_model("gpt-4o");
+    assert_golden("openai", snapshot(|req| model.request_body(req)));
+
+    // o1 models use the developer role and `max_completion_tokens`
+    let model = client.completion_model("o1-mini");
+    assert_golden("openai_o1", snapshot(|req| model.request_body(req)));
+}
+
+#[test]
+fn test_deepseek_golden() {
+    let model = deepseek::Client::new("test", None).completion_model("deepseek-chat");
+    assert_golden("deepseek", snapshot(|req| model.request_body(req)));
+}
+
+#[test]
+fn test_xai_golden() {
+    let model = xai::Client::new("test", None).completion_model("grok-2-latest");
+    assert_golden("xai", snapshot(|req| model.request_body(req)));
+}
+
+#[test]
+fn test_anthropic_golden() {
+    let model = anthropic::Client::new("test", None).completion_model(anthropic::CLAUDE_SONNET_4);
+    assert_golden("anthropic", snapshot(|req| model.request_body(req)));
+}
+
+#[test]
+fn test_gemini_golden() {
+    let model = gemini::Client::new("test", None).completion_model(gemini::GEMINI_2_5_FLASH);
+    assert_golden("gemini", snapshot(|req| model.request_body(req)));
+}
+
+#[test]
+fn test_ollama_golden() {
+    let model = ollama::Client::new(None).completion_model(ollama::LLAMA_3_2);
+    assert_golden("ollama", snapshot(|req| model.request_body(req)));
+}
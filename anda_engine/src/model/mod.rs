@@ -2,8 +2,25 @@
 //!
 //! This module provides implementations for various AI model providers, including:
 //! - OpenAI (completion and embedding models)
+//! - Anthropic (completion models)
+//! - Google Gemini (completion models)
 //! - DeepSeek (completion models)
-//! - Cohere (embedding models)
+//! - Cohere (embedding and rerank models)
+//! - Ollama (local completion and embedding models)
+//!
+//! Provider errors are classified into a stable taxonomy, see [`ProviderErrorKind`].
+//! Endpoints of a provider in multiple regions can be combined with an [`EndpointPool`], and
+//! providers can fail over to each other with a [`FallbackProvider`]. Retrieved documents can
+//! be reranked against the query with a [`Reranker`] before they are added to the prompt.
+//! Each completer reports its [`ModelCapabilities`], and requests are adapted to them before
+//! they are sent: images are dropped for text-only models, and requests that need tools or a
+//! response format that the model does not support are refused.
+//! Embeddings can be cached by content hash with a [`CachedEmbedder`], so that identical
+//! chunks are not embedded again, and large corpora can be embedded with [`embed_batch`],
+//! which splits the texts into batches that the provider accepts. Completions of deterministic
+//! requests can be cached with a [`CachedCompleter`], e.g. to replay test suites offline.
+//! With the [`ModelPricing`] of a model, e.g. from a [`PricingTable`], the usage of its
+//! completions reports their estimated cost in USD.
 //!
 //! Each provider implementation includes:
 //! - Client configuration and management
@@ -14,18 +31,61 @@
 //! while maintaining a consistent interface through the `CompletionFeaturesDyn` and
 //! `EmbeddingFeaturesDyn` traits.
 
-use anda_core::{AgentOutput, BoxError, BoxPinFut, CompletionRequest, Embedding, ToolCall, Usage};
-use std::sync::Arc;
+use anda_core::{
+    AgentOutput, BoxError, BoxPinFut, CompletionRequest, ContentPart, ContextSource, Embedding,
+    HeuristicTokenizer, ModelCapabilities, Tokenizer, ToolCall, Usage,
+};
+use futures::{StreamExt, TryStreamExt, stream::BoxStream};
+use std::{collections::BTreeMap, sync::Arc};
 
+pub mod anthropic;
+mod batch;
 pub mod cohere;
+mod completion_cache;
 pub mod deepseek;
+mod embedding_cache;
+mod error;
+mod fallback;
+pub mod gemini;
+#[cfg(test)]
+mod golden;
+pub mod ollama;
 pub mod openai;
+mod pool;
+mod pricing;
+mod stream;
+mod tokenizer;
 pub mod xai;
 
+pub use batch::*;
+pub use completion_cache::*;
+pub use embedding_cache::*;
+pub use error::*;
+pub use fallback::*;
+pub use pool::*;
+pub use pricing::*;
+pub use tokenizer::*;
+
 /// Trait for dynamic completion features that can be used across threads
 pub trait CompletionFeaturesDyn: Send + Sync + 'static {
     /// Performs a completion request and returns a future with the agent's output
     fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>>;
+
+    /// Performs a completion request and returns a stream of incremental outputs, see
+    /// [`CompletionFeatures::completion_stream`](anda_core::CompletionFeatures::completion_stream).
+    /// The default implementation yields the result of `completion` as a single output.
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        Box::pin(futures::stream::once(self.completion(req)))
+    }
+
+    /// Returns the capabilities of the model. The default implementation supports
+    /// everything, with an unknown context length.
+    fn capabilities(&self) -> ModelCapabilities {
+        ModelCapabilities::default()
+    }
 }
 
 /// Trait for dynamic embedding features that can be used across threads
@@ -33,6 +93,11 @@ pub trait EmbeddingFeaturesDyn: Send + Sync + 'static {
     /// Returns the number of dimensions for the embedding model
     fn ndims(&self) -> usize;
 
+    /// Returns the maximum number of texts per `embed` request, see [`embed_batch`].
+    fn max_batch_size(&self) -> usize {
+        DEFAULT_EMBED_BATCH_SIZE
+    }
+
     /// Embeds multiple texts and returns a future with the resulting embeddings
     fn embed(&self, texts: Vec<String>) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>>;
 
@@ -40,6 +105,49 @@ pub trait EmbeddingFeaturesDyn: Send + Sync + 'static {
     fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>>;
 }
 
+/// A document reranked by a [`Reranker`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reranked {
+    /// The index of the document in the input.
+    pub index: usize,
+    /// The relevance score, higher is more relevant.
+    pub score: f32,
+}
+
+/// Trait for rerankers that score retrieved documents against the query, usually with a
+/// cross-encoder such as Cohere Rerank or a local model.
+pub trait Reranker: Send + Sync + 'static {
+    /// Reranks the documents against the query, returning at most `top_n` results
+    /// ordered by descending relevance.
+    fn rerank(
+        &self,
+        query: String,
+        documents: Vec<String>,
+        top_n: usize,
+    ) -> BoxPinFut<Result<Vec<Reranked>, BoxError>>;
+}
+
+/// Reranks the candidate texts against the query and returns the `top_n` most relevant.
+pub async fn rerank_texts(
+    reranker: &dyn Reranker,
+    query: &str,
+    candidates: Vec<String>,
+    top_n: usize,
+) -> Result<Vec<String>, BoxError> {
+    if candidates.len() <= 1 {
+        return Ok(candidates.into_iter().take(top_n).collect());
+    }
+
+    let ranked = reranker
+        .rerank(query.to_string(), candidates.clone(), top_n)
+        .await?;
+    Ok(ranked
+        .into_iter()
+        .filter_map(|r| candidates.get(r.index).cloned())
+        .take(top_n)
+        .collect())
+}
+
 /// A placeholder implementation for unimplemented features
 #[derive(Clone, Debug)]
 pub struct NotImplemented;
@@ -128,6 +236,16 @@ pub struct Model {
     pub embedder: Arc<dyn EmbeddingFeaturesDyn>,
     /// Completion feature implementation
     pub completer: Arc<dyn CompletionFeaturesDyn>,
+    /// Tokenizer to count tokens of requests and outputs
+    pub tokenizer: Arc<dyn Tokenizer>,
+    /// Context window of the completion model in tokens, if known
+    pub context_window: Option<usize>,
+    /// Capabilities of the completion model, queried when the model is created
+    pub capabilities: ModelCapabilities,
+    /// Pricing of the completion model, to estimate the cost of completions, if known
+    pub pricing: Option<ModelPricing>,
+    /// Error counters per class
+    errors: Arc<ProviderErrorCounts>,
 }
 
 impl Model {
@@ -136,17 +254,29 @@ impl Model {
         completer: Arc<dyn CompletionFeaturesDyn>,
         embedder: Arc<dyn EmbeddingFeaturesDyn>,
     ) -> Self {
+        let capabilities = completer.capabilities();
         Self {
             embedder,
             completer,
+            tokenizer: Arc::new(HeuristicTokenizer),
+            context_window: capabilities.max_context,
+            capabilities,
+            pricing: None,
+            errors: Arc::new(ProviderErrorCounts::default()),
         }
     }
 
     /// Creates a Model with only completion features
     pub fn with_completer(completer: Arc<dyn CompletionFeaturesDyn>) -> Self {
+        let capabilities = completer.capabilities();
         Self {
             completer,
             embedder: Arc::new(NotImplemented),
+            tokenizer: Arc::new(HeuristicTokenizer),
+            context_window: capabilities.max_context,
+            capabilities,
+            pricing: None,
+            errors: Arc::new(ProviderErrorCounts::default()),
         }
     }
 
@@ -155,6 +285,11 @@ impl Model {
         Self {
             completer: Arc::new(NotImplemented),
             embedder: Arc::new(NotImplemented),
+            tokenizer: Arc::new(HeuristicTokenizer),
+            context_window: None,
+            capabilities: ModelCapabilities::default(),
+            pricing: None,
+            errors: Arc::new(ProviderErrorCounts::default()),
         }
     }
 
@@ -163,11 +298,168 @@ impl Model {
         Self {
             completer: Arc::new(MockImplemented),
             embedder: Arc::new(MockImplemented),
+            tokenizer: Arc::new(HeuristicTokenizer),
+            context_window: None,
+            capabilities: ModelCapabilities::default(),
+            pricing: None,
+            errors: Arc::new(ProviderErrorCounts::default()),
+        }
+    }
+
+    /// Sets the tokenizer of the model, e.g. a [`BpeTokenizer`] for OpenAI models.
+    /// The default is [`HeuristicTokenizer`].
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Sets the context window of the completion model in tokens. Requests are trimmed by
+    /// [`fit_context_window`](Self::fit_context_window) before they are sent.
+    /// Defaults to the context length reported in the capabilities of the completer.
+    pub fn with_context_window(mut self, tokens: usize) -> Self {
+        self.context_window = Some(tokens);
+        self
+    }
+
+    /// Overrides the capabilities reported by the completer, e.g. for a self-hosted model
+    /// that can not see images. Their context length, if known, sets the context window.
+    pub fn with_capabilities(mut self, capabilities: ModelCapabilities) -> Self {
+        if capabilities.max_context.is_some() {
+            self.context_window = capabilities.max_context;
+        }
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Sets the pricing of the completion model, e.g. from a [`PricingTable`], so that the
+    /// usage of completions reports their estimated cost.
+    pub fn with_pricing(mut self, pricing: ModelPricing) -> Self {
+        self.pricing = Some(pricing);
+        self
+    }
+
+    /// Adapts the request to the capabilities of the model before it is sent, see
+    /// [`CompletionRequest::adapt_to`].
+    pub fn adapt_request(&self, req: &mut CompletionRequest) -> Result<(), BoxError> {
+        let dropped = req.adapt_to(&self.capabilities)?;
+        if dropped > 0 {
+            log::warn!(dropped_images = dropped; "the model does not support images, dropped them");
+        }
+        Ok(())
+    }
+
+    /// Returns the number of tokens of the text.
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer.count_tokens(text)
+    }
+
+    /// Estimates the number of input tokens of the request: the system message, the chat
+    /// history, the prompt with context and the tool definitions, with a few tokens of
+    /// overhead per message. Images are not counted.
+    pub fn estimate_request_tokens(&self, req: &CompletionRequest) -> usize {
+        const MESSAGE_OVERHEAD: usize = 4;
+        let mut tokens = MESSAGE_OVERHEAD;
+        if let Some(system) = &req.system {
+            tokens += self.count_tokens(system) + MESSAGE_OVERHEAD;
+        }
+        for msg in &req.chat_history {
+            tokens += self.count_message_tokens(msg);
+        }
+        if let Some(prompt) = req.prompt_with_context() {
+            tokens += self.count_tokens(&prompt) + MESSAGE_OVERHEAD;
+        }
+        for part in &req.content_parts {
+            if let ContentPart::Text { text } = part {
+                tokens += self.count_tokens(text);
+            }
+        }
+        if !req.tools.is_empty() {
+            tokens += self.count_tokens(&serde_json::to_string(&req.tools).unwrap_or_default());
+        }
+        tokens
+    }
+
+    /// Returns the number of tokens of a chat history message: its content and tool calls,
+    /// with a few tokens of overhead.
+    pub fn count_message_tokens(&self, msg: &serde_json::Value) -> usize {
+        const MESSAGE_OVERHEAD: usize = 4;
+        let mut tokens = MESSAGE_OVERHEAD
+            + match msg.get("content") {
+                Some(serde_json::Value::String(text)) => self.count_tokens(text),
+                Some(serde_json::Value::Null) | None => 0,
+                Some(content) => self.count_tokens(&content.to_string()),
+            };
+        if let Some(calls) = msg.get("tool_calls") {
+            tokens += self.count_tokens(&calls.to_string());
+        }
+        tokens
+    }
+
+    /// Drops context from the request by its `context_drop_order` until the estimated input
+    /// and `max_tokens` of output fit in the context window. Returns the dropped sources;
+    /// the request may still not fit if there is nothing left to drop.
+    pub fn fit_context_window(&self, req: &mut CompletionRequest) -> Vec<ContextSource> {
+        let mut dropped = Vec::new();
+        let Some(window) = self.context_window else {
+            return dropped;
+        };
+        let budget = window.saturating_sub(req.max_tokens.unwrap_or_default());
+        while self.estimate_request_tokens(req) > budget {
+            match req.drop_context() {
+                Some(source) => dropped.push(source),
+                None => break,
+            }
+        }
+        dropped
+    }
+
+    /// Fills in the token usage of the output from the tokenizer if the provider did not
+    /// report it, and its cost from the pricing of the model if known.
+    pub fn estimate_usage(&self, req: &CompletionRequest, output: &mut AgentOutput) {
+        if output.usage.input_tokens == 0 && output.usage.output_tokens == 0 {
+            output.usage.input_tokens = self.estimate_request_tokens(req) as u64;
+            let mut tokens = self.count_tokens(&output.content);
+            if let Some(calls) = &output.tool_calls {
+                for call in calls {
+                    tokens += self.count_tokens(&call.name) + self.count_tokens(&call.args);
+                }
+            }
+            output.usage.output_tokens = tokens as u64;
+        }
+        if let Some(pricing) = &self.pricing
+            && output.usage.cost == 0.0
+        {
+            output.usage.cost = pricing.cost(&output.usage);
         }
     }
 
-    pub async fn completion(&self, req: CompletionRequest) -> Result<AgentOutput, BoxError> {
-        self.completer.completion(req).await
+    /// Returns the error counts per class since the model was created.
+    pub fn errors(&self) -> BTreeMap<ProviderErrorKind, u64> {
+        self.errors.snapshot()
+    }
+
+    /// Performs a completion request, adapted to the capabilities of the model.
+    pub async fn completion(&self, mut req: CompletionRequest) -> Result<AgentOutput, BoxError> {
+        self.adapt_request(&mut req)?;
+        self.completer
+            .completion(req)
+            .await
+            .inspect_err(|err| self.record_error("completion", err))
+    }
+
+    /// Performs a completion request and returns a stream of incremental outputs.
+    pub fn completion_stream(
+        &self,
+        mut req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        if let Err(err) = self.adapt_request(&mut req) {
+            return Box::pin(futures::stream::once(futures::future::ready(Err(err))));
+        }
+        let model = self.clone();
+        self.completer
+            .completion_stream(req)
+            .inspect_err(move |err| model.record_error("completion_stream", err))
+            .boxed()
     }
 
     pub fn ndims(&self) -> usize {
@@ -178,10 +470,179 @@ impl Model {
         &self,
         texts: impl IntoIterator<Item = String> + Send,
     ) -> Result<(Vec<Embedding>, Usage), BoxError> {
-        self.embedder.embed(texts.into_iter().collect()).await
+        self.embedder
+            .embed(texts.into_iter().collect())
+            .await
+            .inspect_err(|err| self.record_error("embed", err))
+    }
+
+    /// Embeds any number of texts in batches, see [`embed_batch`].
+    pub async fn embed_batch(
+        &self,
+        texts: impl IntoIterator<Item = String> + Send,
+        opts: &EmbedBatchOptions,
+    ) -> Result<(Vec<Embedding>, Usage), BoxError> {
+        embed_batch(self.embedder.as_ref(), texts.into_iter().collect(), opts)
+            .await
+            .inspect_err(|err| self.record_error("embed_batch", err))
     }
 
     pub async fn embed_query(&self, text: &str) -> Result<(Embedding, Usage), BoxError> {
-        self.embedder.embed_query(text.to_string()).await
+        self.embedder
+            .embed_query(text.to_string())
+            .await
+            .inspect_err(|err| self.record_error("embed_query", err))
+    }
+
+    fn record_error(&self, method: &str, err: &BoxError) {
+        let kind = self.errors.record(err);
+        log::warn!(method = method, error_kind = kind.as_str(); "model error: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::Document;
+    use serde_json::json;
+
+    #[test]
+    fn test_fit_context_window() {
+        let model = Model::mock_implemented();
+        let mut req = CompletionRequest {
+            system: Some("You are a helpful assistant.".to_string()),
+            chat_history: (0..8)
+                .map(|i| json!({"role": "user", "content": format!("message {i} ").repeat(20)}))
+                .collect(),
+            documents: vec![Document {
+                id: "1".to_string(),
+                text: "Anda is an AI agent framework built with Rust.".to_string(),
+                ..Default::default()
+            }]
+            .into(),
+            prompt: "What is Anda?".to_string(),
+            max_tokens: Some(100),
+            ..Default::default()
+        };
+        let tokens = model.estimate_request_tokens(&req);
+        assert!(tokens > 8 * 40, "{tokens}");
+
+        // no context window, nothing dropped
+        assert!(model.fit_context_window(&mut req).is_empty());
+
+        let model = model.with_context_window(tokens - 100);
+        let dropped = model.fit_context_window(&mut req);
+        assert_eq!(dropped, vec![ContextSource::ChatHistory; 3]);
+        assert_eq!(req.chat_history.len(), 4);
+        assert!(model.estimate_request_tokens(&req) + 100 <= tokens - 100);
+
+        // drops everything if the window is too small
+        let model = model.with_context_window(10);
+        model.fit_context_window(&mut req);
+        assert!(req.chat_history.is_empty());
+        assert!(req.documents.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_capabilities() {
+        let model = Model::mock_implemented().with_capabilities(ModelCapabilities {
+            tools: false,
+            images: false,
+            max_context: Some(1000),
+            ..Default::default()
+        });
+        assert_eq!(model.context_window, Some(1000));
+
+        let req = CompletionRequest {
+            prompt: "Hello".to_string(),
+            content_parts: vec![ContentPart::image_url("https://example.com/a.png")],
+            ..Default::default()
+        };
+        let mut adapted = req.clone();
+        model.adapt_request(&mut adapted).unwrap();
+        assert!(adapted.content_parts.is_empty());
+        assert_eq!(model.completion(req).await.unwrap().content, "Hello");
+
+        let req = CompletionRequest {
+            prompt: "Hello".to_string(),
+            tools: vec![anda_core::FunctionDefinition::default()],
+            ..Default::default()
+        };
+        assert!(model.completion(req.clone()).await.is_err());
+        assert!(model.completion_stream(req).next().await.unwrap().is_err());
+    }
+
+    #[test]
+    fn test_estimate_usage() {
+        let model = Model::mock_implemented();
+        let req = CompletionRequest {
+            prompt: "Hello, world".to_string(),
+            ..Default::default()
+        };
+        let mut output = AgentOutput {
+            content: "Hello, world".to_string(),
+            ..Default::default()
+        };
+        model.estimate_usage(&req, &mut output);
+        assert_eq!(output.usage.input_tokens, 4 + 3 + 4);
+        assert_eq!(output.usage.output_tokens, 3);
+
+        // keeps the reported usage
+        output.usage.output_tokens = 42;
+        model.estimate_usage(&req, &mut output);
+        assert_eq!(output.usage.output_tokens, 42);
+        assert_eq!(output.usage.cost, 0.0);
+
+        let model = model.with_pricing(ModelPricing::new(1_000_000.0, 2_000_000.0));
+        model.estimate_usage(&req, &mut output);
+        assert_eq!(output.usage.cost, 11.0 + 84.0);
+    }
+
+    /// Scores the documents by the number of query words they contain.
+    struct WordReranker;
+
+    impl Reranker for WordReranker {
+        fn rerank(
+            &self,
+            query: String,
+            documents: Vec<String>,
+            top_n: usize,
+        ) -> BoxPinFut<Result<Vec<Reranked>, BoxError>> {
+            let mut ranked: Vec<Reranked> = documents
+                .iter()
+                .enumerate()
+                .map(|(index, doc)| Reranked {
+                    index,
+                    score: query.split_whitespace().filter(|w| doc.contains(w)).count() as f32,
+                })
+                .collect();
+            ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+            ranked.truncate(top_n);
+            Box::pin(futures::future::ready(Ok(ranked)))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_rerank_texts() {
+        let candidates = vec![
+            "Anda is an AI agent framework.".to_string(),
+            "ICP canisters store the state of Anda agents.".to_string(),
+            "Rust is a systems programming language.".to_string(),
+        ];
+        let texts = rerank_texts(&WordReranker, "Anda agents state", candidates.clone(), 2)
+            .await
+            .unwrap();
+        assert_eq!(texts, vec![candidates[1].clone(), candidates[0].clone()]);
+
+        let texts = rerank_texts(&WordReranker, "Rust", candidates[2..].to_vec(), 2)
+            .await
+            .unwrap();
+        assert_eq!(texts, candidates[2..].to_vec());
+        assert!(
+            rerank_texts(&WordReranker, "Rust", vec![], 2)
+                .await
+                .unwrap()
+                .is_empty()
+        );
     }
 }
@@ -0,0 +1,1057 @@
+//! Ollama API client implementation for Anda Engine
+//!
+//! This module provides integration with the native API of [Ollama](https://ollama.com), so
+//! that agents can run fully offline against local models, including:
+//! - Client configuration, with the keep-alive duration of models in memory
+//! - Completion model handling, with tool calls and streaming
+//! - Embedding model handling
+//! - Listing of the local and running models
+//!
+//! The chat history of requests and outputs is in the OpenAI format, as with the other
+//! providers. It is converted to Ollama messages when sending: content parts are joined into
+//! the text content, images embedded as data URLs are sent as base64 images, and tool results
+//! are named after their tool calls. Ollama has no tool choice parameter, so
+//! `tool_choice_required` is not enforced. Tool calls have no IDs, they are generated.
+
+use anda_core::{
+    AgentOutput, BoxError, BoxPinFut, CONTENT_TYPE_JSON, CompletionFeatures, CompletionRequest,
+    ContentPart, Embedding, EmbeddingFeatures, Message, Resource, ToolCall, Usage,
+};
+use futures::stream::BoxStream;
+use log::{Level::Debug, log_enabled};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{
+    CompletionFeaturesDyn, EmbeddingFeaturesDyn, ProviderError, ProviderErrorKind,
+    stream::{EventParser, event_stream},
+};
+use crate::{APP_USER_AGENT, id::new_tool_call_id};
+
+// ================================================================
+// Main Ollama Client
+// ================================================================
+const API_BASE_URL: &str = "http://localhost:11434";
+
+/// `llama3.2` completion model
+pub const LLAMA_3_2: &str = "llama3.2";
+/// `qwen3` completion model
+pub const QWEN_3: &str = "qwen3";
+
+/// `nomic-embed-text` embedding model
+pub const NOMIC_EMBED_TEXT: &str = "nomic-embed-text";
+/// `mxbai-embed-large` embedding model
+pub const MXBAI_EMBED_LARGE: &str = "mxbai-embed-large";
+/// `all-minilm` embedding model
+pub const ALL_MINILM: &str = "all-minilm";
+
+/// Ollama API client configuration and HTTP client
+#[derive(Clone)]
+pub struct Client {
+    endpoint: String,
+    http: reqwest::Client,
+    keep_alive: Option<Value>,
+}
+
+impl Client {
+    /// Creates a new Ollama client instance
+    ///
+    /// # Arguments
+    /// * `endpoint` - API endpoint, `http://localhost:11434` if `None` or empty
+    pub fn new(endpoint: Option<String>) -> Self {
+        let endpoint = endpoint.unwrap_or_else(|| API_BASE_URL.to_string());
+        let endpoint = if endpoint.is_empty() {
+            API_BASE_URL.to_string()
+        } else {
+            endpoint.trim_end_matches('/').to_string()
+        };
+        Self {
+            endpoint,
+            http: reqwest::Client::builder()
+                .use_rustls_tls()
+                .connect_timeout(Duration::from_secs(10))
+                // local models may take a while to load and generate
+                .timeout(Duration::from_secs(600))
+                .user_agent(APP_USER_AGENT)
+                .default_headers({
+                    let mut headers = reqwest::header::HeaderMap::new();
+                    let ct: http::HeaderValue = CONTENT_TYPE_JSON.parse().unwrap();
+                    headers.insert(http::header::CONTENT_TYPE, ct.clone());
+                    headers.insert(http::header::ACCEPT, ct);
+                    headers
+                })
+                .build()
+                .expect("Ollama reqwest client should build"),
+            keep_alive: None,
+        }
+    }
+
+    /// Sets how long models stay loaded in memory after a request, e.g. "10m", "24h",
+    /// "-1" to keep them loaded or "0" to unload them at once. Ollama keeps them 5 minutes
+    /// by default.
+    pub fn with_keep_alive(mut self, keep_alive: &str) -> Self {
+        self.keep_alive = Some(match keep_alive.parse::<i64>() {
+            Ok(secs) => Value::from(secs),
+            Err(_) => Value::from(keep_alive),
+        });
+        self
+    }
+
+    /// Creates a POST request builder for the given API path
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let url = format!("{}{}", self.endpoint, path);
+        self.http.post(url)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, BoxError> {
+        let url = format!("{}{}", self.endpoint, path);
+        let response = self.http.get(url).send().await?;
+        if response.status().is_success() {
+            Ok(response.json::<T>().await?)
+        } else {
+            let status = response.status().as_u16();
+            let msg = response.text().await?;
+            Err(ProviderError::from_response("Ollama error", status, msg).into())
+        }
+    }
+
+    /// Lists the models available locally.
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>, BoxError> {
+        let res: ModelList<ModelInfo> = self.get("/api/tags").await?;
+        Ok(res.models)
+    }
+
+    /// Lists the models loaded in memory.
+    pub async fn list_running_models(&self) -> Result<Vec<RunningModel>, BoxError> {
+        let res: ModelList<RunningModel> = self.get("/api/ps").await?;
+        Ok(res.models)
+    }
+
+    /// Creates an embedding model with the given name
+    ///
+    /// # Arguments
+    /// * `model` - Name of the embedding model to use
+    ///
+    /// # Note
+    /// Default embedding dimension of 0 will be used if model is not known
+    pub fn embedding_model(&self, model: &str) -> EmbeddingModel {
+        let ndims = match model.split(':').next().unwrap_or_default() {
+            NOMIC_EMBED_TEXT => 768,
+            MXBAI_EMBED_LARGE => 1024,
+            ALL_MINILM => 384,
+            _ => 0,
+        };
+        EmbeddingModel::new(self.clone(), model, ndims)
+    }
+
+    /// Creates a completion model with the given name
+    ///
+    /// # Arguments
+    /// * `model` - Name of the completion model to use, [`LLAMA_3_2`] if empty
+    pub fn completion_model(&self, model: &str) -> CompletionModel {
+        CompletionModel::new(
+            self.clone(),
+            if model.is_empty() { LLAMA_3_2 } else { model },
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList<T> {
+    #[serde(default)]
+    models: Vec<T>,
+}
+
+/// The details of a model.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ModelDetails {
+    #[serde(default)]
+    pub format: String,
+    #[serde(default)]
+    pub family: String,
+    #[serde(default)]
+    pub parameter_size: String,
+    #[serde(default)]
+    pub quantization_level: String,
+}
+
+/// A model available locally.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ModelInfo {
+    pub name: String,
+    #[serde(default)]
+    pub modified_at: String,
+    /// The size of the model in bytes.
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+}
+
+/// A model loaded in memory.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RunningModel {
+    pub name: String,
+    /// The size of the model in bytes.
+    #[serde(default)]
+    pub size: u64,
+    /// The size of the model in the GPU memory, in bytes.
+    #[serde(default)]
+    pub size_vram: u64,
+    /// When the model will be unloaded.
+    #[serde(default)]
+    pub expires_at: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+}
+
+/// Response structure for Ollama embed API
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EmbeddingResponse {
+    pub model: String,
+    pub embeddings: Vec<Vec<f32>>,
+    #[serde(default)]
+    pub prompt_eval_count: u64,
+}
+
+impl EmbeddingResponse {
+    fn try_into(self, texts: Vec<String>) -> Result<(Vec<Embedding>, Usage), BoxError> {
+        if self.embeddings.len() != texts.len() {
+            return Err(format!(
+                "Expected {} embeddings, got {}",
+                texts.len(),
+                self.embeddings.len()
+            )
+            .into());
+        }
+
+        Ok((
+            self.embeddings
+                .into_iter()
+                .zip(texts)
+                .map(|(vec, text)| Embedding { text, vec })
+                .collect(),
+            Usage {
+                input_tokens: self.prompt_eval_count,
+                output_tokens: 0,
+                requests: 1,
+                ..Default::default()
+            },
+        ))
+    }
+}
+
+/// Ollama embedding model wrapper
+#[derive(Clone)]
+pub struct EmbeddingModel {
+    client: Client,
+    pub model: String,
+    ndims: usize,
+}
+
+impl EmbeddingModel {
+    /// Creates a new embedding model instance
+    ///
+    /// # Arguments
+    /// * `client` - Ollama client instance
+    /// * `model` - Name of the embedding model
+    /// * `ndims` - Number of dimensions for the embedding
+    pub fn new(client: Client, model: &str, ndims: usize) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+            ndims,
+        }
+    }
+}
+
+impl EmbeddingFeatures for EmbeddingModel {
+    fn ndims(&self) -> usize {
+        self.ndims
+    }
+
+    async fn embed(
+        &self,
+        texts: impl IntoIterator<Item = String> + Send,
+    ) -> Result<(Vec<Embedding>, Usage), BoxError> {
+        EmbeddingFeaturesDyn::embed(self, texts.into_iter().collect()).await
+    }
+
+    async fn embed_query(&self, text: &str) -> Result<(Embedding, Usage), BoxError> {
+        EmbeddingFeaturesDyn::embed_query(self, text.to_string()).await
+    }
+}
+
+impl EmbeddingFeaturesDyn for EmbeddingModel {
+    fn ndims(&self) -> usize {
+        self.ndims
+    }
+
+    fn embed(&self, texts: Vec<String>) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+        let mut body = json!({
+            "model": self.model,
+            "input": texts,
+        });
+        if let Some(keep_alive) = &self.client.keep_alive {
+            body["keep_alive"] = keep_alive.clone();
+        }
+        let client = self.client.clone();
+        Box::pin(async move {
+            let response = client.post("/api/embed").json(&body).send().await?;
+            if response.status().is_success() {
+                match response.json::<EmbeddingResponse>().await {
+                    Ok(res) => res.try_into(texts),
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("Ollama embeddings error: {}", err),
+                    )
+                    .into()),
+                }
+            } else {
+                let status = response.status().as_u16();
+                let msg = response.text().await?;
+                Err(ProviderError::from_response("Ollama embeddings error", status, msg).into())
+            }
+        })
+    }
+
+    fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move {
+            let (mut embeddings, usage) = EmbeddingFeaturesDyn::embed(&this, vec![text]).await?;
+            let embedding = embeddings.pop().ok_or("no embedding data")?;
+            Ok((embedding, usage))
+        })
+    }
+}
+
+/// A tool call of an Ollama message.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunction,
+}
+
+/// The function of an Ollama tool call, with the arguments as a JSON object.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OllamaFunction {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// A message of an Ollama chat response.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ResponseMessage {
+    #[serde(default)]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OllamaToolCall>,
+}
+
+impl ResponseMessage {
+    fn take_tool_calls(&mut self) -> Vec<ToolCall> {
+        self.tool_calls
+            .drain(..)
+            .map(|tc| ToolCall {
+                id: new_tool_call_id(),
+                name: tc.function.name,
+                args: if tc.function.arguments.is_null() {
+                    "{}".to_string()
+                } else {
+                    tc.function.arguments.to_string()
+                },
+                result: None,
+            })
+            .collect()
+    }
+}
+
+/// Response structure for Ollama chat API, also the chunks of a chat stream
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompletionResponse {
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub message: ResponseMessage,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub done_reason: Option<String>,
+    #[serde(default)]
+    pub prompt_eval_count: u64,
+    #[serde(default)]
+    pub eval_count: u64,
+}
+
+impl CompletionResponse {
+    fn usage(&self) -> Usage {
+        Usage {
+            input_tokens: self.prompt_eval_count,
+            output_tokens: self.eval_count,
+            requests: 1,
+            ..Default::default()
+        }
+    }
+
+    fn try_into(mut self, mut full_history: Vec<Value>) -> Result<AgentOutput, BoxError> {
+        let tool_calls = self.message.take_tool_calls();
+        full_history.push(assistant_message(&self.message.content, &tool_calls));
+
+        Ok(AgentOutput {
+            usage: self.usage(),
+            failed_reason: failed_reason(self.done, self.done_reason),
+            content: self.message.content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            full_history: Some(full_history),
+            model: Some(self.model).filter(|m| !m.is_empty()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Returns the failed reason of a done reason, None for a normal stop.
+fn failed_reason(done: bool, done_reason: Option<String>) -> Option<String> {
+    match done_reason {
+        Some(reason) if reason != "stop" => Some(reason),
+        _ if !done => Some("response is not done".to_string()),
+        _ => None,
+    }
+}
+
+/// Returns the assistant message with the tool calls in the OpenAI format of the chat history.
+fn assistant_message(content: &str, tool_calls: &[ToolCall]) -> Value {
+    let mut message = json!({
+        "role": "assistant",
+        "content": content,
+    });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = tool_calls
+            .iter()
+            .map(|tc| {
+                json!({
+                    "id": tc.id,
+                    "type": "function",
+                    "function": {"name": tc.name, "arguments": tc.args},
+                })
+            })
+            .collect();
+    }
+    message
+}
+
+/// Converts a chat history in the OpenAI format to Ollama messages.
+pub fn to_ollama_messages(history: &[Value]) -> Vec<Value> {
+    // the function names of the tool calls, as tool results are matched by name
+    let mut call_names: BTreeMap<String, Value> = BTreeMap::new();
+    let mut messages = Vec::with_capacity(history.len());
+    for msg in history {
+        let role = match msg["role"].as_str().unwrap_or_default() {
+            "developer" => "system",
+            role => role,
+        };
+        let (content, images) = content_and_images(&msg["content"]);
+        let mut message = json!({"role": role, "content": content});
+        if !images.is_empty() {
+            message["images"] = images.into();
+        }
+        match role {
+            "assistant" => {
+                if let Some(calls) = msg["tool_calls"].as_array() {
+                    message["tool_calls"] = calls
+                        .iter()
+                        .map(|call| {
+                            let name = call["function"]["name"].clone();
+                            if let Some(id) = call["id"].as_str() {
+                                call_names.insert(id.to_string(), name.clone());
+                            }
+                            let args = call["function"]["arguments"].as_str().unwrap_or_default();
+                            json!({"function": {
+                                "name": name,
+                                "arguments": serde_json::from_str::<Value>(args)
+                                    .ok()
+                                    .filter(|v| v.is_object())
+                                    .unwrap_or_else(|| json!({})),
+                            }})
+                        })
+                        .collect();
+                }
+            }
+            "tool" => {
+                if let Some(name) = msg["tool_call_id"]
+                    .as_str()
+                    .and_then(|id| call_names.get(id).cloned())
+                    .or_else(|| msg.get("name").cloned())
+                {
+                    message["tool_name"] = name;
+                }
+            }
+            _ => {}
+        }
+        messages.push(message);
+    }
+    messages
+}
+
+/// Returns the text content and the base64 images of the content of a message. Images
+/// that are not embedded are referenced by their URL in the text.
+fn content_and_images(content: &Value) -> (String, Vec<String>) {
+    match content {
+        Value::Null => (String::new(), Vec::new()),
+        Value::String(text) => (text.clone(), Vec::new()),
+        Value::Array(parts) => {
+            let mut texts = Vec::new();
+            let mut images = Vec::new();
+            for part in parts {
+                match serde_json::from_value::<ContentPart>(part.clone()) {
+                    Ok(ContentPart::Text { text }) => texts.push(text),
+                    Ok(ContentPart::Image { image_url }) => {
+                        match image_url.url.split_once(";base64,") {
+                            Some((prefix, data)) if prefix.starts_with("data:") => {
+                                images.push(data.to_string())
+                            }
+                            _ => texts.push(format!("[image: {}]", image_url.url)),
+                        }
+                    }
+                    // audio is not supported
+                    Ok(ContentPart::Audio { .. }) => {}
+                    Err(_) => {
+                        if let Some(text) = part["text"].as_str() {
+                            texts.push(text.to_string());
+                        }
+                    }
+                }
+            }
+            (texts.join("\n"), images)
+        }
+        other => (other.to_string(), Vec::new()),
+    }
+}
+
+/// Completion model implementation for Ollama API
+#[derive(Clone)]
+pub struct CompletionModel {
+    client: Client,
+    pub model: String,
+}
+
+impl CompletionModel {
+    /// Creates a new completion model instance
+    ///
+    /// # Arguments
+    /// * `client` - Ollama client instance
+    /// * `model` - Name of the completion model
+    pub fn new(client: Client, model: &str) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+        }
+    }
+
+    /// Builds the JSON body of a chat request, and the full history of messages in
+    /// the OpenAI format that the response is appended to.
+    pub fn request_body(&self, mut req: CompletionRequest) -> (Value, Vec<Value>) {
+        let mut full_history = if let Some(system) = &req.system {
+            vec![json!(Message {
+                role: "system".into(),
+                content: system.to_owned().into(),
+                ..Default::default()
+            })]
+        } else {
+            vec![]
+        };
+
+        full_history.append(&mut req.chat_history);
+
+        if !req.content_parts.is_empty() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: json!(req.user_content_parts()),
+                ..Default::default()
+            }));
+        } else if let Some(prompt) = req.prompt_with_context() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: prompt.into(),
+                ..Default::default()
+            }));
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "messages": to_ollama_messages(&full_history),
+            "stream": false,
+        });
+
+        let obj = body.as_object_mut().unwrap();
+        let mut options = serde_json::Map::new();
+        if let Some(temperature) = req.temperature {
+            options.insert("temperature".to_string(), Value::from(temperature));
+        }
+        if let Some(max_tokens) = req.max_tokens {
+            options.insert("num_predict".to_string(), Value::from(max_tokens));
+        }
+        if let Some(stop) = req.stop {
+            options.insert("stop".to_string(), Value::from(stop));
+        }
+        if !options.is_empty() {
+            obj.insert("options".to_string(), Value::Object(options));
+        }
+
+        if let Some(format) = &req.response_format {
+            match format["type"].as_str() {
+                Some("json_schema") => {
+                    obj.insert(
+                        "format".to_string(),
+                        format["json_schema"]["schema"].clone(),
+                    );
+                }
+                Some("json_object") => {
+                    obj.insert("format".to_string(), "json".into());
+                }
+                _ => {}
+            }
+        }
+
+        if !req.tools.is_empty() {
+            obj.insert(
+                "tools".to_string(),
+                req.tools
+                    .into_iter()
+                    .map(|tool| {
+                        json!({
+                            "type": "function",
+                            "function": {
+                                "name": tool.name,
+                                "description": tool.description,
+                                "parameters": tool.parameters,
+                            },
+                        })
+                    })
+                    .collect(),
+            );
+        }
+
+        if let Some(keep_alive) = &self.client.keep_alive {
+            obj.insert("keep_alive".to_string(), keep_alive.clone());
+        }
+
+        (body, full_history)
+    }
+}
+
+impl CompletionFeatures for CompletionModel {
+    async fn completion(
+        &self,
+        req: CompletionRequest,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<AgentOutput, BoxError> {
+        CompletionFeaturesDyn::completion(self, req).await
+    }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+        _resources: Option<Vec<Resource>>,
+    ) -> BoxStream<'_, Result<AgentOutput, BoxError>> {
+        CompletionFeaturesDyn::completion_stream(self, req)
+    }
+}
+
+impl CompletionFeaturesDyn for CompletionModel {
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (body, full_history) = self.request_body(req);
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            if log_enabled!(Debug)
+                && let Ok(val) = serde_json::to_string(&body)
+            {
+                log::debug!(request = val; "Ollama chat request");
+            }
+
+            let response = client.post("/api/chat").json(&body).send().await?;
+            if response.status().is_success() {
+                let text = response.text().await?;
+                match serde_json::from_str::<CompletionResponse>(&text) {
+                    Ok(res) => {
+                        if log_enabled!(Debug)
+                            && let Ok(val) = serde_json::to_string(&res)
+                        {
+                            log::debug!(response = val; "Ollama chat response");
+                        }
+                        res.try_into(full_history)
+                    }
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("Ollama chat error: {}, body: {}", err, text),
+                    )
+                    .into()),
+                }
+            } else {
+                let status = response.status().as_u16();
+                let msg = response.text().await?;
+                Err(ProviderError::from_response("Ollama chat error", status, msg).into())
+            }
+        })
+    }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        let (mut body, full_history) = self.request_body(req);
+        body["stream"] = Value::Bool(true);
+        event_stream(
+            "Ollama chat error",
+            self.model.clone(),
+            self.client.post("/api/chat").json(&body),
+            ChatEventParser::new(full_history),
+        )
+    }
+}
+
+/// A line of a chat stream, or an error.
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(flatten)]
+    response: CompletionResponse,
+    error: Option<Value>,
+}
+
+/// Parses a chat stream, which is newline-delimited JSON rather than server-sent events.
+/// Tool calls are not split across lines.
+struct ChatEventParser {
+    buf: Vec<u8>,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    usage: Usage,
+    done_reason: Option<String>,
+    full_history: Vec<Value>,
+    done: bool,
+}
+
+impl ChatEventParser {
+    fn new(full_history: Vec<Value>) -> Self {
+        Self {
+            buf: Vec::new(),
+            content: String::new(),
+            tool_calls: Vec::new(),
+            usage: Usage::default(),
+            done_reason: None,
+            full_history,
+            done: false,
+        }
+    }
+
+    fn apply(&mut self, chunk: StreamChunk) -> Result<Option<AgentOutput>, BoxError> {
+        if let Some(error) = chunk.error {
+            let msg = match error {
+                Value::String(msg) => msg,
+                other => other.to_string(),
+            };
+            return Err(ProviderError::new(
+                ProviderErrorKind::from_message(&msg),
+                format!("Ollama chat error: {}", msg),
+            )
+            .into());
+        }
+
+        let mut res = chunk.response;
+        if res.done {
+            self.done = true;
+            self.done_reason = res.done_reason.take();
+            self.usage = res.usage();
+        }
+
+        let tool_calls = res.message.take_tool_calls();
+        let content = res.message.content;
+        if content.is_empty() && tool_calls.is_empty() {
+            return Ok(None);
+        }
+        self.content.push_str(&content);
+        self.tool_calls.extend(tool_calls.iter().cloned());
+        Ok(Some(AgentOutput {
+            content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            ..Default::default()
+        }))
+    }
+}
+
+impl EventParser for ChatEventParser {
+    fn feed(&mut self, bytes: &[u8]) -> Result<Vec<AgentOutput>, BoxError> {
+        self.buf.extend_from_slice(bytes);
+        let mut outputs = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line = std::str::from_utf8(&line)?.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let chunk: StreamChunk = serde_json::from_str(line).map_err(|err| {
+                ProviderError::new(
+                    ProviderErrorKind::Server,
+                    format!("Ollama chat error: {}, data: {}", err, line),
+                )
+            })?;
+            if let Some(output) = self.apply(chunk)? {
+                outputs.push(output);
+            }
+        }
+        Ok(outputs)
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn finish(mut self) -> AgentOutput {
+        self.full_history
+            .push(assistant_message(&self.content, &self.tool_calls));
+        AgentOutput {
+            usage: self.usage,
+            failed_reason: failed_reason(self.done, self.done_reason),
+            full_history: Some(self.full_history),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: &str = r#"{"model":"llama3.2","created_at":"2026-10-15T08:00:00Z","message":{"role":"assistant","content":"Let me"},"done":false}
+{"model":"llama3.2","created_at":"2026-10-15T08:00:00Z","message":{"role":"assistant","content":" check","tool_calls":[{"function":{"name":"get_weather","arguments":{"city":"Paris"}}}]},"done":false}
+{"model":"llama3.2","created_at":"2026-10-15T08:00:01Z","message":{"role":"assistant","content":""},"done":true,"done_reason":"stop","total_duration":4883583458,"prompt_eval_count":25,"eval_count":30}
+"#;
+
+    #[test]
+    fn test_to_ollama_messages() {
+        let history = vec![
+            json!({"role": "developer", "content": "You are a helpful assistant."}),
+            json!({"role": "user", "content": [
+                {"type": "text", "text": "What is the weather here?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+                {"type": "image_url", "image_url": {"url": "https://example.com/b.png"}},
+                {"type": "input_audio", "input_audio": {"data": "AAAA", "format": "wav"}},
+            ]}),
+            json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                ],
+            }),
+            json!({"role": "tool", "tool_call_id": "call_1", "content": "Sunny"}),
+        ];
+        assert_eq!(
+            Value::Array(to_ollama_messages(&history)),
+            json!([
+                {"role": "system", "content": "You are a helpful assistant."},
+                {
+                    "role": "user",
+                    "content": "What is the weather here?\n[image: https://example.com/b.png]",
+                    "images": ["iVBORw0KGgo="],
+                },
+                {"role": "assistant", "content": "", "tool_calls": [
+                    {"function": {"name": "get_weather", "arguments": {"city": "Paris"}}},
+                ]},
+                {"role": "tool", "content": "Sunny", "tool_name": "get_weather"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_completion_response() {
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "model": "llama3.2",
+            "created_at": "2026-10-15T08:00:00Z",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{"function": {"name": "get_weather", "arguments": {"city": "Paris"}}}],
+            },
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 20,
+            "eval_count": 10,
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert!(output.failed_reason.is_none());
+        assert_eq!(output.usage.input_tokens, 20);
+        assert_eq!(output.usage.output_tokens, 10);
+        let tool_calls = output.tool_calls.unwrap();
+        assert!(tool_calls[0].id.starts_with("call_"));
+        assert_eq!(tool_calls[0].args, r#"{"city":"Paris"}"#);
+
+        // the history converts back to the same tool call, and results are named after it
+        let mut history = output.full_history.unwrap();
+        history.push(json!({"role": "tool", "tool_call_id": tool_calls[0].id, "content": "Sunny"}));
+        let messages = to_ollama_messages(&history);
+        assert_eq!(
+            messages[0]["tool_calls"][0],
+            json!({"function": {"name": "get_weather", "arguments": {"city": "Paris"}}})
+        );
+        assert_eq!(messages[1]["tool_name"], "get_weather");
+
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "message": {"role": "assistant", "content": "Once upon"},
+            "done": true,
+            "done_reason": "length",
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert_eq!(output.failed_reason.as_deref(), Some("length"));
+    }
+
+    #[test]
+    fn test_request_body() {
+        let client = Client::new(Some("http://127.0.0.1:11434/".to_string())).with_keep_alive("-1");
+        assert_eq!(client.endpoint, "http://127.0.0.1:11434");
+        let model = client.completion_model("");
+        let (body, history) = model.request_body(CompletionRequest {
+            system: Some("You are a helpful assistant.".to_string()),
+            prompt: "Hello!".to_string(),
+            temperature: Some(0.5),
+            max_tokens: Some(256),
+            response_format: Some(json!({
+                "type": "json_schema",
+                "json_schema": {"name": "greeting", "schema": {"type": "object"}},
+            })),
+            tools: vec![anda_core::FunctionDefinition {
+                name: "get_weather".to_string(),
+                description: "Gets the weather.".to_string(),
+                parameters: json!({"type": "object"}),
+                strict: None,
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            body,
+            json!({
+                "model": LLAMA_3_2,
+                "messages": [
+                    {"role": "system", "content": "You are a helpful assistant."},
+                    {"role": "user", "content": "Hello!"},
+                ],
+                "stream": false,
+                "options": {"temperature": 0.5, "num_predict": 256},
+                "format": {"type": "object"},
+                "tools": [{"type": "function", "function": {
+                    "name": "get_weather",
+                    "description": "Gets the weather.",
+                    "parameters": {"type": "object"},
+                }}],
+                "keep_alive": -1,
+            })
+        );
+        assert_eq!(history.len(), 2);
+
+        let client = Client::new(None).with_keep_alive("30m");
+        assert_eq!(client.keep_alive, Some(json!("30m")));
+        assert_eq!(client.embedding_model("nomic-embed-text:latest").ndims, 768);
+    }
+
+    #[test]
+    fn test_chat_event_parser() {
+        let mut parser = ChatEventParser::new(vec![json!({"role": "user", "content": "Hi"})]);
+        let mut outputs = Vec::new();
+        // feeds the lines in pieces that split them
+        for piece in LINES.as_bytes().chunks(7) {
+            outputs.extend(parser.feed(piece).unwrap());
+        }
+        assert!(parser.is_done());
+        assert_eq!(outputs.len(), 2);
+
+        let mut output = AgentOutput::default();
+        for delta in outputs {
+            output.accumulate(delta);
+        }
+        output.accumulate(parser.finish());
+        assert_eq!(output.content, "Let me check");
+        assert!(output.failed_reason.is_none());
+        assert_eq!(output.usage.input_tokens, 25);
+        assert_eq!(output.usage.output_tokens, 30);
+        let tool_calls = output.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].args, r#"{"city":"Paris"}"#);
+        let history = output.full_history.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1]["tool_calls"][0]["id"], tool_calls[0].id);
+
+        let mut parser = ChatEventParser::new(vec![]);
+        let err = parser
+            .feed(b"{\"error\":\"model 'llama9' not found\"}\n")
+            .unwrap_err();
+        assert!(err.to_string().contains("not found"));
+
+        let parser = ChatEventParser::new(vec![]);
+        assert!(parser.finish().failed_reason.is_some());
+    }
+
+    #[test]
+    fn test_model_list() {
+        let res: ModelList<ModelInfo> = serde_json::from_value(json!({"models": [{
+            "name": "llama3.2:latest",
+            "model": "llama3.2:latest",
+            "modified_at": "2026-10-01T08:00:00Z",
+            "size": 2019393189u64,
+            "digest": "a80c4f17acd5",
+            "details": {"format": "gguf", "family": "llama", "parameter_size": "3.2B", "quantization_level": "Q4_K_M"},
+        }]}))
+        .unwrap();
+        assert_eq!(res.models[0].name, "llama3.2:latest");
+        assert_eq!(res.models[0].details.parameter_size, "3.2B");
+
+        let res: ModelList<RunningModel> = serde_json::from_value(json!({"models": [{
+            "name": "llama3.2:latest",
+            "size": 2019393189u64,
+            "size_vram": 2019393189u64,
+            "expires_at": "2026-10-15T08:05:00Z",
+        }]}))
+        .unwrap();
+        assert_eq!(res.models[0].expires_at, "2026-10-15T08:05:00Z");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn test_ollama() {
+        let client = Client::new(None).with_keep_alive("10m");
+        let models = client.list_models().await.unwrap();
+        println!("{:?}", models);
+
+        let model = client.completion_model(LLAMA_3_2);
+        let res = CompletionFeatures::completion(
+            &model,
+            CompletionRequest {
+                prompt: "Say hello in one word.".to_string(),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        println!("{:?}", res);
+
+        let model = client.embedding_model(NOMIC_EMBED_TEXT);
+        let (embedding, _) = EmbeddingFeatures::embed_query(&model, "hello")
+            .await
+            .unwrap();
+        assert_eq!(embedding.vec.len(), model.ndims);
+    }
+}
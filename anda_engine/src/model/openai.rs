@@ -8,14 +8,18 @@
 
 use anda_core::{
     AgentOutput, BoxError, BoxPinFut, CONTENT_TYPE_JSON, CompletionRequest, Embedding,
-    FunctionDefinition, Message, ToolCall, Usage as ModelUsage,
+    FunctionDefinition, Message, ModelCapabilities, ToolCall, Usage as ModelUsage,
 };
+use futures::stream::BoxStream;
 use log::{Level::Debug, log_enabled};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use std::time::Duration;
 
-use super::{CompletionFeaturesDyn, EmbeddingFeaturesDyn};
+use super::{
+    CompletionFeaturesDyn, EmbeddingFeaturesDyn, ProviderError, ProviderErrorKind,
+    stream::chat_completion_stream,
+};
 use crate::APP_USER_AGENT;
 
 // ================================================================
@@ -157,6 +161,7 @@ impl EmbeddingResponse {
                     .total_tokens
                     .saturating_sub(self.usage.prompt_tokens) as u64,
                 requests: 1,
+                ..Default::default()
             },
         ))
     }
@@ -218,6 +223,7 @@ impl CompletionResponse {
                     .collect()
             }),
             full_history: Some(full_history),
+            model: Some(self.model),
             usage: self
                 .usage
                 .as_ref()
@@ -225,6 +231,7 @@ impl CompletionResponse {
                     input_tokens: u.prompt_tokens as u64,
                     output_tokens: u.completion_tokens as u64,
                     requests: 1,
+                    ..Default::default()
                 })
                 .unwrap_or_default(),
             ..Default::default()
@@ -300,6 +307,10 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
         self.ndims
     }
 
+    fn max_batch_size(&self) -> usize {
+        MAX_DOCUMENTS
+    }
+
     /// Generates embeddings for multiple texts in a batch
     /// Returns a vector of Embedding structs in the same order as input texts
     fn embed(
@@ -325,11 +336,16 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
             if response.status().is_success() {
                 match response.json::<EmbeddingResponse>().await {
                     Ok(res) => res.try_into(texts),
-                    Err(err) => Err(format!("OpenAI embeddings error: {}", err).into()),
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("OpenAI embeddings error: {}", err),
+                    )
+                    .into()),
                 }
             } else {
+                let status = response.status().as_u16();
                 let msg = response.text().await?;
-                Err(format!("OpenAI embeddings error: {}", msg).into())
+                Err(ProviderError::from_response("OpenAI embeddings error", status, msg).into())
             }
         })
     }
@@ -366,14 +382,20 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
                                     .saturating_sub(res.usage.prompt_tokens)
                                     as u64,
                                 requests: 1,
+                                ..Default::default()
                             },
                         ))
                     }
-                    Err(err) => Err(format!("OpenAI embeddings error: {}", err).into()),
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("OpenAI embeddings error: {}", err),
+                    )
+                    .into()),
                 }
             } else {
+                let status = response.status().as_u16();
                 let msg = response.text().await?;
-                Err(format!("OpenAI embeddings error: {}", msg).into())
+                Err(ProviderError::from_response("OpenAI embeddings error", status, msg).into())
             }
         })
     }
@@ -419,6 +441,95 @@ impl CompletionModel {
     fn is_new_model(&self) -> bool {
         self.model.starts_with("o1-")
     }
+
+    /// Builds the JSON body of a chat completions request, and the full history of
+    /// messages that the response is appended to.
+    pub fn request_body(&self, mut req: CompletionRequest) -> (Value, Vec<Value>) {
+        let is_new = self.is_new_model();
+
+        // Add preamble to chat history (if available)
+        let mut full_history = if let Some(system) = &req.system {
+            vec![json!(Message {
+                role: if is_new {
+                    "developer".into()
+                } else {
+                    "system".into()
+                },
+                content: system.to_owned().into(),
+                name: req.system_name.clone(),
+                ..Default::default()
+            })]
+        } else {
+            vec![]
+        };
+
+        // Extend existing chat history
+        full_history.append(&mut req.chat_history);
+
+        if !req.content_parts.is_empty() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: json!(req.user_content_parts()),
+                name: req.prompter_name,
+                ..Default::default()
+            }));
+        } else if let Some(prompt) = req.prompt_with_context() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: prompt.into(),
+                name: req.prompter_name,
+                ..Default::default()
+            }));
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "messages": full_history.clone(),
+        });
+
+        let obj = body.as_object_mut().unwrap();
+        if let Some(temperature) = req.temperature {
+            obj.insert("temperature".to_string(), Value::from(temperature));
+        }
+
+        if let Some(max_tokens) = req.max_tokens {
+            if is_new {
+                obj.insert("max_completion_tokens".to_string(), Value::from(max_tokens));
+            } else {
+                obj.insert("max_tokens".to_string(), Value::from(max_tokens));
+            }
+        }
+
+        if let Some(response_format) = req.response_format {
+            obj.insert("response_format".to_string(), response_format);
+        }
+
+        if let Some(stop) = req.stop {
+            obj.insert("stop".to_string(), Value::from(stop));
+        }
+
+        if !req.tools.is_empty() {
+            obj.insert(
+                "tools".to_string(),
+                json!(
+                    req.tools
+                        .into_iter()
+                        .map(ToolDefinition::from)
+                        .collect::<Vec<_>>()
+                ),
+            );
+            obj.insert(
+                "tool_choice".to_string(),
+                if req.tool_choice_required {
+                    Value::from("required")
+                } else {
+                    Value::from("auto")
+                },
+            );
+        };
+
+        (body, full_history)
+    }
 }
 
 // impl CompletionFeatures for CompletionModel {
@@ -432,100 +543,18 @@ impl CompletionModel {
 // }
 
 impl CompletionFeaturesDyn for CompletionModel {
-    fn completion(&self, mut req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
-        let is_new = self.is_new_model();
-        let model = self.model.clone();
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (body, full_history) = self.request_body(req);
         let client = self.client.clone();
 
         Box::pin(async move {
-            // Add preamble to chat history (if available)
-            let mut full_history = if let Some(system) = &req.system {
-                vec![json!(Message {
-                    role: if is_new {
-                        "developer".into()
-                    } else {
-                        "system".into()
-                    },
-                    content: system.to_owned().into(),
-                    name: req.system_name.clone(),
-                    ..Default::default()
-                })]
-            } else {
-                vec![]
-            };
-
-            // Extend existing chat history
-            full_history.append(&mut req.chat_history);
-
-            if !req.content_parts.is_empty() {
-                full_history.push(json!(Message {
-                    role: "user".into(),
-                    content: json!(req.content_parts),
-                    name: req.prompter_name,
-                    ..Default::default()
-                }));
-            } else if let Some(prompt) = req.prompt_with_context() {
-                full_history.push(json!(Message {
-                    role: "user".into(),
-                    content: prompt.into(),
-                    name: req.prompter_name,
-                    ..Default::default()
-                }));
-            }
-
-            let mut body = json!({
-                "model": model,
-                "messages": full_history.clone(),
-            });
-
-            let body = body.as_object_mut().unwrap();
-            if let Some(temperature) = req.temperature {
-                body.insert("temperature".to_string(), Value::from(temperature));
-            }
-
-            if let Some(max_tokens) = req.max_tokens {
-                if is_new {
-                    body.insert("max_completion_tokens".to_string(), Value::from(max_tokens));
-                } else {
-                    body.insert("max_tokens".to_string(), Value::from(max_tokens));
-                }
-            }
-
-            if let Some(response_format) = req.response_format {
-                body.insert("response_format".to_string(), response_format);
-            }
-
-            if let Some(stop) = req.stop {
-                body.insert("stop".to_string(), Value::from(stop));
-            }
-
-            if !req.tools.is_empty() {
-                body.insert(
-                    "tools".to_string(),
-                    json!(
-                        req.tools
-                            .into_iter()
-                            .map(ToolDefinition::from)
-                            .collect::<Vec<_>>()
-                    ),
-                );
-                body.insert(
-                    "tool_choice".to_string(),
-                    if req.tool_choice_required {
-                        Value::from("required")
-                    } else {
-                        Value::from("auto")
-                    },
-                );
-            };
-
             if log_enabled!(Debug) {
                 if let Ok(val) = serde_json::to_string(&body) {
                     log::debug!(request = val; "OpenAI completions request");
                 }
             }
 
-            let response = client.post("/chat/completions").json(body).send().await?;
+            let response = client.post("/chat/completions").json(&body).send().await?;
             if response.status().is_success() {
                 let text = response.text().await?;
                 match serde_json::from_str::<CompletionResponse>(&text) {
@@ -537,14 +566,39 @@ impl CompletionFeaturesDyn for CompletionModel {
                         }
                         res.try_into(full_history)
                     }
-                    Err(err) => {
-                        Err(format!("OpenAI completions error: {}, body: {}", err, text).into())
-                    }
+                    Err(err) => Err(ProviderError::new(
+                        ProviderErrorKind::Server,
+                        format!("OpenAI completions error: {}, body: {}", err, text),
+                    )
+                    .into()),
                 }
             } else {
+                let status = response.status().as_u16();
                 let msg = response.text().await?;
-                Err(format!("OpenAI completions error: {}", msg).into())
+                Err(ProviderError::from_response("OpenAI completions error", status, msg).into())
             }
         })
     }
+
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        let (body, full_history) = self.request_body(req);
+        chat_completion_stream(
+            "OpenAI completions error",
+            self.model.clone(),
+            self.client.post("/chat/completions"),
+            body,
+            full_history,
+        )
+    }
+
+    /// o3-mini is text-only.
+    fn capabilities(&self) -> ModelCapabilities {
+        ModelCapabilities {
+            images: !self.model.starts_with(O3_MINI),
+            ..Default::default()
+        }
+    }
 }
@@ -0,0 +1,259 @@
+//! Multi-region endpoint pool for a completion provider.
+//!
+//! An [`EndpointPool`] routes completion requests between endpoints of the same model in
+//! different regions (e.g. `us-east`, `eu-west`):
+//! - data residency: callers can be restricted to a set of regions, e.g. EU-only, and their
+//!   requests are never sent elsewhere, even if all allowed endpoints fail;
+//! - region affinity: endpoints in the pool's home region are preferred;
+//! - latency: within the same affinity, the endpoint with the lowest moving average latency
+//!   is tried first, and endpoints that failed with a retryable error are put on cooldown.
+//!
+//! The caller is taken from [`CompletionRequest::caller`], which is set by the engine.
+//!
+//! # Example
+//! ```rust,ignore
+//! let pool = EndpointPool::new("eu-west")
+//!     .with_endpoint("us-east", Arc::new(us_client.completion_model("gpt-4o")))
+//!     .with_endpoint("eu-west", Arc::new(eu_client.completion_model("gpt-4o")))
+//!     .with_residency(eu_customer, vec!["eu-west".to_string()]);
+//! let model = Model::with_completer(Arc::new(pool));
+//! ```
+
+use anda_core::{AgentOutput, BoxError, BoxPinFut, CompletionRequest, ModelCapabilities};
+use candid::Principal;
+use futures::stream::BoxStream;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use structured_logger::unix_ms;
+
+use super::{CompletionFeaturesDyn, ProviderErrorKind};
+
+/// How long an endpoint is avoided after a retryable error.
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+/// An endpoint of the pool.
+pub struct PoolEndpoint {
+    /// The region of the endpoint.
+    pub region: String,
+    completer: Arc<dyn CompletionFeaturesDyn>,
+    /// Exponential moving average of the latency in milliseconds, 0 if not measured yet.
+    latency_ms: AtomicU64,
+    /// The endpoint is avoided until this unix timestamp in milliseconds.
+    cooldown_until: AtomicU64,
+}
+
+impl PoolEndpoint {
+    /// Returns the moving average latency in milliseconds, 0 if not measured yet.
+    pub fn latency_ms(&self) -> u64 {
+        self.latency_ms.load(Ordering::Relaxed)
+    }
+
+    fn record_latency(&self, sample_ms: u64) {
+        let sample_ms = sample_ms.max(1);
+        let _ = self
+            .latency_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |prev| {
+                Some(if prev == 0 {
+                    sample_ms
+                } else {
+                    (prev * 4 + sample_ms) / 5
+                })
+            });
+    }
+
+    fn is_cooling_down(&self, now_ms: u64) -> bool {
+        self.cooldown_until.load(Ordering::Relaxed) > now_ms
+    }
+}
+
+/// A completion provider backed by endpoints in multiple regions.
+pub struct EndpointPool {
+    home_region: String,
+    endpoints: Vec<Arc<PoolEndpoint>>,
+    residency: BTreeMap<Principal, BTreeSet<String>>,
+}
+
+impl EndpointPool {
+    /// Creates an empty pool that prefers endpoints in the home region.
+    pub fn new(home_region: &str) -> Self {
+        Self {
+            home_region: home_region.to_string(),
+            endpoints: Vec::new(),
+            residency: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an endpoint in the region.
+    pub fn with_endpoint(
+        mut self,
+        region: &str,
+        completer: Arc<dyn CompletionFeaturesDyn>,
+    ) -> Self {
+        self.endpoints.push(Arc::new(PoolEndpoint {
+            region: region.to_string(),
+            completer,
+            latency_ms: AtomicU64::new(0),
+            cooldown_until: AtomicU64::new(0),
+        }));
+        self
+    }
+
+    /// Restricts the caller's requests to endpoints in the regions.
+    pub fn with_residency(mut self, caller: Principal, regions: Vec<String>) -> Self {
+        self.residency.insert(caller, regions.into_iter().collect());
+        self
+    }
+
+    /// Returns the endpoints of the pool.
+    pub fn endpoints(&self) -> &[Arc<PoolEndpoint>] {
+        &self.endpoints
+    }
+
+    /// Returns the indexes of the endpoints allowed for the caller, in the order to try them.
+    pub fn candidates(&self, caller: Option<&Principal>, now_ms: u64) -> Vec<usize> {
+        let allowed = caller.and_then(|c| self.residency.get(c));
+        let mut idxs: Vec<usize> = self
+            .endpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, ep)| allowed.is_none_or(|regions| regions.contains(&ep.region)))
+            .map(|(i, _)| i)
+            .collect();
+        idxs.sort_by_key(|&i| {
+            let ep = &self.endpoints[i];
+            (
+                ep.is_cooling_down(now_ms),
+                ep.region != self.home_region,
+                ep.latency_ms(),
+            )
+        });
+        idxs
+    }
+}
+
+impl CompletionFeaturesDyn for EndpointPool {
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let candidates: Vec<Arc<PoolEndpoint>> = self
+            .candidates(req.caller.as_ref(), unix_ms())
+            .into_iter()
+            .map(|i| self.endpoints[i].clone())
+            .collect();
+
+        Box::pin(async move {
+            if candidates.is_empty() {
+                return Err(format!(
+                    "no endpoint allowed for caller {:?}",
+                    req.caller.map(|c| c.to_text())
+                )
+                .into());
+            }
+
+            let mut last_err: Option<BoxError> = None;
+            for ep in candidates {
+                let start = Instant::now();
+                match ep.completer.completion(req.clone()).await {
+                    Ok(output) => {
+                        ep.record_latency(start.elapsed().as_millis() as u64);
+                        return Ok(output);
+                    }
+                    Err(err) => {
+                        if !ProviderErrorKind::of(&err).is_retryable() {
+                            return Err(err);
+                        }
+                        log::warn!(region = ep.region; "endpoint failed, trying the next one: {}", err);
+                        ep.cooldown_until
+                            .store(unix_ms() + COOLDOWN.as_millis() as u64, Ordering::Relaxed);
+                        last_err = Some(err);
+                    }
+                }
+            }
+            Err(last_err.unwrap())
+        })
+    }
+
+    /// Streams from the first endpoint allowed for the caller. Endpoints are not failed over
+    /// once the stream has started.
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+    ) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+        match self.candidates(req.caller.as_ref(), unix_ms()).first() {
+            Some(&i) => self.endpoints[i].completer.completion_stream(req),
+            None => Box::pin(futures::stream::once(futures::future::ready(Err(format!(
+                "no endpoint allowed for caller {:?}",
+                req.caller.map(|c| c.to_text())
+            )
+            .into())))),
+        }
+    }
+
+    /// Returns the capabilities supported by all endpoints, since any of them may serve
+    /// a request.
+    fn capabilities(&self) -> ModelCapabilities {
+        self.endpoints
+            .iter()
+            .map(|ep| ep.completer.capabilities())
+            .reduce(|a, b| a.intersect(&b))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{MockImplemented, ProviderError};
+
+    struct Failing;
+
+    impl CompletionFeaturesDyn for Failing {
+        fn completion(&self, _req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+            Box::pin(futures::future::ready(Err(ProviderError::new(
+                ProviderErrorKind::Server,
+                "overloaded".to_string(),
+            )
+            .into())))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_endpoint_pool() {
+        let eu_user = Principal::from_text("2vxsx-fae").unwrap();
+        let pool = EndpointPool::new("us-east")
+            .with_endpoint("eu-west", Arc::new(MockImplemented))
+            .with_endpoint("us-west", Arc::new(MockImplemented))
+            .with_endpoint("us-east", Arc::new(Failing))
+            .with_residency(eu_user, vec!["eu-west".to_string()]);
+
+        let now = unix_ms();
+        // home region first, then the lowest latency
+        pool.endpoints[0].record_latency(200);
+        pool.endpoints[1].record_latency(100);
+        assert_eq!(pool.candidates(None, now), vec![2, 1, 0]);
+        assert_eq!(pool.candidates(Some(&eu_user), now), vec![0]);
+
+        pool.endpoints[1].record_latency(600);
+        assert_eq!(pool.endpoints[1].latency_ms(), 200);
+        pool.endpoints[1].record_latency(600);
+        assert_eq!(pool.candidates(None, now), vec![2, 0, 1]);
+
+        // the home endpoint fails and cools down
+        let output = pool.completion(CompletionRequest::default()).await.unwrap();
+        assert!(output.failed_reason.is_none());
+        assert_eq!(pool.candidates(None, unix_ms()), vec![0, 1, 2]);
+
+        let pool = EndpointPool::new("eu-west")
+            .with_endpoint("us-east", Arc::new(MockImplemented))
+            .with_residency(eu_user, vec!["eu-west".to_string()]);
+        let req = CompletionRequest {
+            caller: Some(eu_user),
+            ..Default::default()
+        };
+        assert!(pool.completion(req).await.is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use anda_core::{BoxError, Usage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The prices of a model in USD, used to estimate the [`Usage::cost`] of its completions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ModelPricing {
+    /// The price per million input tokens.
+    pub input_per_1m: f64,
+
+    /// The price per million output tokens.
+    pub output_per_1m: f64,
+
+    /// The price per request, if any.
+    #[serde(default)]
+    pub per_request: f64,
+}
+
+impl ModelPricing {
+    /// Creates the pricing from the prices per million input and output tokens.
+    pub fn new(input_per_1m: f64, output_per_1m: f64) -> Self {
+        Self {
+            input_per_1m,
+            output_per_1m,
+            per_request: 0.0,
+        }
+    }
+
+    /// Returns the estimated cost of the usage in USD.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input_per_1m
+            + usage.output_tokens as f64 * self.output_per_1m)
+            / 1_000_000.0
+            + usage.requests as f64 * self.per_request
+    }
+}
+
+/// The pricing of models by model name.
+///
+/// # Example
+/// ```toml
+/// [models.gpt-4o]
+/// input_per_1m = 2.5
+/// output_per_1m = 10.0
+///
+/// [models.claude-3-5-haiku]
+/// input_per_1m = 0.8
+/// output_per_1m = 4.0
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct PricingTable {
+    pub models: BTreeMap<String, ModelPricing>,
+}
+
+impl PricingTable {
+    /// Creates a PricingTable from a TOML string.
+    pub fn from_toml(content: &str) -> Result<Self, BoxError> {
+        let table: Self = toml::from_str(content)?;
+        Ok(table)
+    }
+
+    /// Sets the pricing of a model.
+    pub fn with_model(mut self, model: &str, pricing: ModelPricing) -> Self {
+        self.models.insert(model.to_string(), pricing);
+        self
+    }
+
+    /// Returns the pricing of the model, or of the longest model name that prefixes it,
+    /// e.g. the pricing of "gpt-4o" for "gpt-4o-2024-08-06".
+    pub fn get(&self, model: &str) -> Option<ModelPricing> {
+        self.models.get(model).copied().or_else(|| {
+            self.models
+                .iter()
+                .filter(|(name, _)| model.starts_with(name.as_str()))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, pricing)| *pricing)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pricing_table() {
+        let table = PricingTable::from_toml(
+            r#"
+[models.gpt-4o]
+input_per_1m = 2.5
+output_per_1m = 10.0
+
+[models.gpt-4o-mini]
+input_per_1m = 0.15
+output_per_1m = 0.6
+per_request = 0.001
+"#,
+        )
+        .unwrap();
+        assert_eq!(table.get("gpt-4o"), Some(ModelPricing::new(2.5, 10.0)));
+        assert_eq!(
+            table.get("gpt-4o-2024-08-06"),
+            Some(ModelPricing::new(2.5, 10.0))
+        );
+        assert_eq!(
+            table.get("gpt-4o-mini-2024-07-18").unwrap().per_request,
+            0.001
+        );
+        assert_eq!(table.get("o3-mini"), None);
+
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 500_000,
+            requests: 2,
+            ..Default::default()
+        };
+        assert_eq!(ModelPricing::new(2.5, 10.0).cost(&usage), 7.5);
+        let cost = table.get("gpt-4o-mini").unwrap().cost(&usage);
+        assert!((cost - 0.452).abs() < 1e-9, "{cost}");
+    }
+}
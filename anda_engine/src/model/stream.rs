@@ -0,0 +1,377 @@
+//! Streaming of chat completions in the server-sent events format of the OpenAI API,
+//! which is also used by DeepSeek and xAI.
+
+use anda_core::{AgentOutput, BoxError, ToolCall, Usage as ModelUsage};
+use futures::{
+    StreamExt, TryFutureExt,
+    stream::{self, BoxStream},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+
+use super::{ProviderError, ProviderErrorKind};
+
+/// Parses the bytes of a server-sent events stream into incremental outputs.
+pub(crate) trait EventParser: Send + 'static {
+    /// Feeds bytes of the stream and returns the outputs of the complete events.
+    fn feed(&mut self, bytes: &[u8]) -> Result<Vec<AgentOutput>, BoxError>;
+
+    /// Returns true once the end of the stream has been received.
+    fn is_done(&self) -> bool;
+
+    /// Returns the last output, with the usage and the full history.
+    fn finish(self) -> AgentOutput;
+}
+
+/// Sends a chat completions request with `"stream": true` and returns the stream of
+/// incremental outputs. `provider` prefixes error messages, e.g. "OpenAI completions error".
+pub(crate) fn chat_completion_stream(
+    provider: &'static str,
+    model: String,
+    request: reqwest::RequestBuilder,
+    mut body: Value,
+    full_history: Vec<Value>,
+) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+    if let Some(obj) = body.as_object_mut() {
+        obj.insert("stream".to_string(), Value::Bool(true));
+        obj.insert("stream_options".to_string(), json!({"include_usage": true}));
+    }
+
+    event_stream(
+        provider,
+        model,
+        request.json(&body),
+        ChunkParser::new(provider, full_history),
+    )
+}
+
+/// Sends a streaming request and returns the outputs of the parser, ending with its
+/// last output, which has the identifier of the requested `model`.
+pub(crate) fn event_stream<P: EventParser>(
+    provider: &'static str,
+    model: String,
+    request: reqwest::RequestBuilder,
+    parser: P,
+) -> BoxStream<'static, Result<AgentOutput, BoxError>> {
+    async move {
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let msg = response.text().await?;
+            return Err(ProviderError::from_response(provider, status, msg).into());
+        }
+
+        let finish = move |parser: P| {
+            let mut output = parser.finish();
+            output.model.get_or_insert(model);
+            output
+        };
+        let state = (
+            response.bytes_stream().boxed(),
+            parser,
+            VecDeque::new(),
+            finish,
+        );
+        Ok(stream::unfold(Some(state), |state| async move {
+            let (mut bytes, mut parser, mut pending, finish) = state?;
+            loop {
+                if let Some(output) = pending.pop_front() {
+                    return Some((Ok(output), Some((bytes, parser, pending, finish))));
+                }
+                if parser.is_done() {
+                    return Some((Ok(finish(parser)), None));
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => match parser.feed(&chunk) {
+                        Ok(outputs) => pending.extend(outputs),
+                        Err(err) => return Some((Err(err), None)),
+                    },
+                    Some(Err(err)) => return Some((Err(err.into()), None)),
+                    // the server closed the stream without an end event
+                    None => return Some((Ok(finish(parser)), None)),
+                }
+            }
+        }))
+    }
+    .try_flatten_stream()
+    .boxed()
+}
+
+/// Takes the complete lines from the buffer and returns the payloads of the `data:` lines.
+/// Comments, event names and blank separators are skipped.
+pub(crate) fn take_data_lines(buf: &mut Vec<u8>) -> Result<Vec<String>, BoxError> {
+    let mut data = Vec::new();
+    while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+        let line: Vec<u8> = buf.drain(..=pos).collect();
+        let line = std::str::from_utf8(&line)?.trim();
+        if let Some(d) = line.strip_prefix("data:") {
+            data.push(d.trim().to_string());
+        }
+    }
+    Ok(data)
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<ChunkUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChunkDelta {
+    content: Option<String>,
+    refusal: Option<String>,
+    tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCallChunk {
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionChunk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionChunk {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkUsage {
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+/// Parses the events of a stream, keeping the state needed for the last output.
+struct ChunkParser {
+    provider: &'static str,
+    buf: Vec<u8>,
+    content: String,
+    refusal: Option<String>,
+    tool_calls: Vec<ToolCall>,
+    usage: Option<ChunkUsage>,
+    finish_reason: Option<String>,
+    full_history: Vec<Value>,
+    done: bool,
+}
+
+impl ChunkParser {
+    fn new(provider: &'static str, full_history: Vec<Value>) -> Self {
+        Self {
+            provider,
+            buf: Vec::new(),
+            content: String::new(),
+            refusal: None,
+            tool_calls: Vec::new(),
+            usage: None,
+            finish_reason: None,
+            full_history,
+            done: false,
+        }
+    }
+
+    fn apply(&mut self, chunk: CompletionChunk) -> Option<AgentOutput> {
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+        let choice = chunk.choices.into_iter().next()?;
+        if choice.finish_reason.is_some() {
+            self.finish_reason = choice.finish_reason;
+        }
+        if let Some(refusal) = choice.delta.refusal {
+            self.refusal
+                .get_or_insert_with(String::new)
+                .push_str(&refusal);
+        }
+
+        let mut output = AgentOutput::default();
+        if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+            self.content.push_str(&content);
+            output.content = content;
+        }
+        if let Some(calls) = choice.delta.tool_calls {
+            let mut fragments = Vec::with_capacity(calls.len());
+            for call in calls {
+                let (name, args) = call
+                    .function
+                    .map(|f| (f.name, f.arguments.unwrap_or_default()))
+                    .unwrap_or_default();
+                if call.index >= self.tool_calls.len() {
+                    self.tool_calls
+                        .resize_with(call.index + 1, ToolCall::default);
+                }
+                let tc = &mut self.tool_calls[call.index];
+                if let Some(id) = call.id {
+                    tc.id = id;
+                }
+                if let Some(name) = name {
+                    tc.name = name;
+                }
+                tc.args.push_str(&args);
+                fragments.push(ToolCall {
+                    id: tc.id.clone(),
+                    name: tc.name.clone(),
+                    args,
+                    result: None,
+                });
+            }
+            output.tool_calls = Some(fragments);
+        }
+
+        if output.content.is_empty() && output.tool_calls.is_none() {
+            return None;
+        }
+        Some(output)
+    }
+}
+
+impl EventParser for ChunkParser {
+    fn feed(&mut self, bytes: &[u8]) -> Result<Vec<AgentOutput>, BoxError> {
+        self.buf.extend_from_slice(bytes);
+        let mut outputs = Vec::new();
+        for data in take_data_lines(&mut self.buf)? {
+            if data == "[DONE]" {
+                self.done = true;
+                break;
+            }
+
+            let chunk: CompletionChunk = serde_json::from_str(&data).map_err(|err| {
+                ProviderError::new(
+                    ProviderErrorKind::Server,
+                    format!("{}: {}, data: {}", self.provider, err, data),
+                )
+            })?;
+            if let Some(output) = self.apply(chunk) {
+                outputs.push(output);
+            }
+        }
+        Ok(outputs)
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn finish(mut self) -> AgentOutput {
+        let mut message = json!({
+            "role": "assistant",
+            "content": self.content,
+        });
+        if !self.tool_calls.is_empty() {
+            message["tool_calls"] = self
+                .tool_calls
+                .iter()
+                .map(|tc| {
+                    json!({
+                        "id": tc.id,
+                        "type": "function",
+                        "function": {"name": tc.name, "arguments": tc.args},
+                    })
+                })
+                .collect();
+        }
+        self.full_history.push(message);
+
+        let failed_reason = match self.finish_reason {
+            _ if self.refusal.is_some() => self.refusal,
+            Some(reason) if !matches!(reason.as_str(), "stop" | "tool_calls") => Some(reason),
+            Some(_) => None,
+            None => Some("stream ended without a finish reason".to_string()),
+        };
+        AgentOutput {
+            usage: self
+                .usage
+                .map(|u| ModelUsage {
+                    input_tokens: u.prompt_tokens,
+                    output_tokens: u.completion_tokens,
+                    requests: 1,
+                    ..Default::default()
+                })
+                .unwrap_or_default(),
+            failed_reason,
+            full_history: Some(self.full_history),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENTS: &str = r#": keep-alive
+
+data: {"choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}],"usage":null}
+
+data: {"choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}],"usage":null}
+
+data: {"choices":[{"index":0,"delta":{"content":", world"},"finish_reason":null}],"usage":null}
+
+data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null}],"usage":null}
+
+data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]},"finish_reason":null}],"usage":null}
+
+data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]},"finish_reason":null}],"usage":null}
+
+data: {"choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}],"usage":null}
+
+data: {"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":7,"total_tokens":19}}
+
+data: [DONE]
+
+"#;
+
+    #[test]
+    fn test_chunk_parser() {
+        let mut parser = ChunkParser::new("test", vec![json!({"role": "user", "content": "Hi"})]);
+        let mut outputs = Vec::new();
+        // feeds the events in pieces that split lines
+        for piece in EVENTS.as_bytes().chunks(7) {
+            outputs.extend(parser.feed(piece).unwrap());
+        }
+        assert!(parser.done);
+        assert_eq!(outputs.len(), 5);
+        assert_eq!(outputs[0].content, "Hello");
+        assert_eq!(outputs[1].content, ", world");
+        let fragment = &outputs[4].tool_calls.as_ref().unwrap()[0];
+        assert_eq!(fragment.id, "call_1");
+        assert_eq!(fragment.name, "get_weather");
+        assert_eq!(fragment.args, r#""Paris"}"#);
+
+        let mut output = AgentOutput::default();
+        for delta in outputs {
+            output.accumulate(delta);
+        }
+        output.accumulate(parser.finish());
+        assert_eq!(output.content, "Hello, world");
+        assert!(output.failed_reason.is_none());
+        assert_eq!(output.usage.input_tokens, 12);
+        assert_eq!(output.usage.output_tokens, 7);
+        let tool_calls = output.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].args, r#"{"city":"Paris"}"#);
+        let history = output.full_history.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1]["content"], "Hello, world");
+        assert_eq!(
+            history[1]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Paris"}"#
+        );
+
+        let mut parser = ChunkParser::new("test", vec![]);
+        assert!(parser.feed(b"data: {not json}\n").is_err());
+        let output = parser.finish();
+        assert!(output.failed_reason.is_some());
+    }
+}
@@ -0,0 +1,247 @@
+//! Byte pair encoding tokenizers compatible with OpenAI's tiktoken.
+//!
+//! The vocabularies are not bundled: load them from the `.tiktoken` files published by
+//! OpenAI, e.g. `https://openaipublic.blob.core.windows.net/encodings/o200k_base.tiktoken`,
+//! with [`BpeTokenizer::from_tiktoken_file`]. Models without a known encoding can use
+//! [`HeuristicTokenizer`](anda_core::HeuristicTokenizer).
+
+use anda_core::{BoxError, Tokenizer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use fancy_regex::Regex;
+use std::{collections::HashMap, path::Path};
+
+const CL100K_BASE_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+const O200K_BASE_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+);
+
+/// The tiktoken encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// The encoding of GPT-4, GPT-3.5 and the text-embedding-3 models.
+    Cl100kBase,
+    /// The encoding of GPT-4o, GPT-4.1 and the o-series models.
+    O200kBase,
+}
+
+impl Encoding {
+    /// Returns the encoding of an OpenAI model, or `None` if the model is unknown.
+    pub fn for_model(model: &str) -> Option<Self> {
+        const O200K: &[&str] = &[
+            "gpt-4o",
+            "chatgpt-4o",
+            "gpt-4.1",
+            "gpt-4.5",
+            "gpt-5",
+            "o1",
+            "o3",
+            "o4",
+        ];
+        const CL100K: &[&str] = &[
+            "gpt-4",
+            "gpt-3.5",
+            "text-embedding-3",
+            "text-embedding-ada-002",
+        ];
+        if O200K.iter().any(|p| model.starts_with(p)) {
+            Some(Self::O200kBase)
+        } else if CL100K.iter().any(|p| model.starts_with(p)) {
+            Some(Self::Cl100kBase)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the name of the encoding, which is also the stem of its `.tiktoken` file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cl100kBase => "cl100k_base",
+            Self::O200kBase => "o200k_base",
+        }
+    }
+
+    /// Returns the regular expression that splits texts into pieces before encoding.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Self::Cl100kBase => CL100K_BASE_PATTERN,
+            Self::O200kBase => O200K_BASE_PATTERN,
+        }
+    }
+}
+
+/// A byte pair encoding tokenizer that produces the same tokens as tiktoken.
+///
+/// Special tokens such as `<|endoftext|>` are encoded as ordinary text.
+pub struct BpeTokenizer {
+    encoding: Encoding,
+    ranks: HashMap<Vec<u8>, u32>,
+    regex: Regex,
+}
+
+impl BpeTokenizer {
+    /// Creates a tokenizer from the content of a `.tiktoken` file: a line of a base64 encoded
+    /// token and its rank per token. The vocabulary must contain all single bytes.
+    pub fn from_tiktoken(data: &str, encoding: Encoding) -> Result<Self, BoxError> {
+        let mut ranks = HashMap::new();
+        for (i, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("invalid tiktoken line {}: {:?}", i + 1, line))?;
+            let token = BASE64_STANDARD
+                .decode(token)
+                .map_err(|err| format!("invalid token at line {}: {}", i + 1, err))?;
+            let rank: u32 = rank
+                .trim()
+                .parse()
+                .map_err(|err| format!("invalid rank at line {}: {}", i + 1, err))?;
+            ranks.insert(token, rank);
+        }
+        if let Some(b) = (0..=255u8).find(|b| !ranks.contains_key(&[*b][..])) {
+            return Err(format!("tiktoken vocabulary misses the byte {:#04x}", b).into());
+        }
+
+        let regex = Regex::new(encoding.pattern())?;
+        Ok(Self {
+            encoding,
+            ranks,
+            regex,
+        })
+    }
+
+    /// Creates a tokenizer from a `.tiktoken` file.
+    pub fn from_tiktoken_file(
+        path: impl AsRef<Path>,
+        encoding: Encoding,
+    ) -> Result<Self, BoxError> {
+        let data = std::fs::read_to_string(path)?;
+        Self::from_tiktoken(&data, encoding)
+    }
+
+    /// Returns the encoding of the tokenizer.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Encodes the text into token ranks.
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut tokens = Vec::new();
+        for piece in self.regex.find_iter(text) {
+            // fails only if the backtrack limit is exceeded, which the patterns do not reach
+            let Ok(piece) = piece else {
+                break;
+            };
+            self.encode_piece(piece.as_str().as_bytes(), &mut tokens);
+        }
+        tokens
+    }
+
+    /// Merges the adjacent pair with the lowest rank until no pair is in the vocabulary.
+    fn encode_piece(&self, piece: &[u8], tokens: &mut Vec<u32>) {
+        if let Some(rank) = self.ranks.get(piece) {
+            tokens.push(*rank);
+            return;
+        }
+
+        // the boundaries of the parts of the piece
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let mut best: Option<(u32, usize)> = None;
+            for i in 0..bounds.len().saturating_sub(2) {
+                if let Some(&rank) = self.ranks.get(&piece[bounds[i]..bounds[i + 2]])
+                    && best.is_none_or(|(r, _)| rank < r)
+                {
+                    best = Some((rank, i));
+                }
+            }
+            match best {
+                Some((_, i)) => {
+                    bounds.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+        tokens.extend(bounds.windows(2).map(|w| self.ranks[&piece[w[0]..w[1]]]));
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiktoken(merges: &[&str]) -> String {
+        let mut data = String::new();
+        let mut rank = 0;
+        for b in 0..=255u8 {
+            data.push_str(&format!("{} {}\n", BASE64_STANDARD.encode([b]), rank));
+            rank += 1;
+        }
+        for token in merges {
+            data.push_str(&format!("{} {}\n", BASE64_STANDARD.encode(token), rank));
+            rank += 1;
+        }
+        data
+    }
+
+    #[test]
+    fn test_encoding_for_model() {
+        assert_eq!(
+            Encoding::for_model("gpt-4o-mini"),
+            Some(Encoding::O200kBase)
+        );
+        assert_eq!(Encoding::for_model("o3-mini"), Some(Encoding::O200kBase));
+        assert_eq!(
+            Encoding::for_model("gpt-4-turbo"),
+            Some(Encoding::Cl100kBase)
+        );
+        assert_eq!(
+            Encoding::for_model("text-embedding-3-small"),
+            Some(Encoding::Cl100kBase)
+        );
+        assert_eq!(Encoding::for_model("deepseek-chat"), None);
+    }
+
+    #[test]
+    fn test_bpe_tokenizer() {
+        // ranks: he 256, ll 257, hell 258, " w" 259
+        let data = tiktoken(&["he", "ll", "hell", " w"]);
+        let t = BpeTokenizer::from_tiktoken(&data, Encoding::Cl100kBase).unwrap();
+        assert_eq!(t.encoding(), Encoding::Cl100kBase);
+        assert_eq!(t.encode(""), Vec::<u32>::new());
+        // "hello" merges he, then ll, then hell; " world" merges " w" only
+        assert_eq!(
+            t.encode("hello world"),
+            vec![
+                258,
+                b'o' as u32,
+                259,
+                b'o' as u32,
+                b'r' as u32,
+                b'l' as u32,
+                b'd' as u32
+            ]
+        );
+        // the regex splits "'s" and the digits in groups of 3
+        assert_eq!(t.encode("he's 12345").len(), 1 + 2 + 1 + 3 + 2);
+        assert_eq!(t.count_tokens("hell"), 1);
+        // multi-byte characters fall back to bytes
+        assert_eq!(t.count_tokens("你好"), 6);
+
+        let o200k = BpeTokenizer::from_tiktoken(&data, Encoding::O200kBase).unwrap();
+        assert_eq!(o200k.count_tokens("hello world"), 7);
+
+        assert!(BpeTokenizer::from_tiktoken("aGU= 1\n", Encoding::Cl100kBase).is_err());
+        assert!(BpeTokenizer::from_tiktoken(&(data + "aGU=\n"), Encoding::Cl100kBase).is_err());
+    }
+}
@@ -0,0 +1,1469 @@
+//! # Output Rendering Module
+//!
+//! Agents answer in Markdown, which chat platforms display differently or not at all.
+//! The renderers of this module translate an agent's output into the native format of each
+//! connector, so that one output displays correctly everywhere:
+//! - [`PlainTextRenderer`]: plain text, e.g. for SMS, email or voice;
+//! - [`TelegramRenderer`]: Telegram HTML messages, sent with `parse_mode: "HTML"`;
+//! - [`SlackRenderer`]: Slack Block Kit blocks with `mrkdwn` text;
+//! - [`DiscordRenderer`]: Discord embeds.
+//!
+//! Constructs that a platform lacks degrade gracefully: headings become bold text, tables
+//! become aligned preformatted text, images become links, and long outputs are split into
+//! several messages at block boundaries to fit the limits of the platform.
+//!
+//! The Markdown dialect is the one models write in practice: ATX headings, fenced code blocks,
+//! bullet and ordered lists, block quotes, pipe tables, rules, and inline strong, emphasis,
+//! strikethrough, code, links and images. Line breaks in paragraphs are kept.
+//!
+//! The [`UiHints`] of an output are rendered separately by the `render_ui` method of each
+//! renderer, as inline keyboards, actions blocks or message components. The ID of the action
+//! picked by the user is resolved with [`UiHints::resolve`] and sent back as a callback.
+//!
+//! # Example
+//! ```rust,ignore
+//! let output = engine.agent_run(caller, input).await?;
+//! for message in TelegramRenderer::new().render(&output.content) {
+//!     bot.send_message(chat_id, message).parse_mode(ParseMode::Html).await?;
+//! }
+//! ```
+
+use anda_core::{UiAction, UiHints, UiStyle};
+use serde_json::{Map, Value, json};
+
+/// An inline element of Markdown text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Strong(Vec<Inline>),
+    Emphasis(Vec<Inline>),
+    Strike(Vec<Inline>),
+    Code(String),
+    Link { text: Vec<Inline>, url: String },
+    Image { alt: String, url: String },
+    LineBreak,
+}
+
+/// An item of a list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    /// The nesting depth, 0 for top-level items.
+    pub depth: usize,
+    /// The number of an ordered item, None for a bullet item.
+    pub number: Option<u64>,
+    pub content: Vec<Inline>,
+}
+
+/// A block element of a Markdown document.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Heading {
+        level: u8,
+        content: Vec<Inline>,
+    },
+    Paragraph(Vec<Inline>),
+    Code {
+        lang: Option<String>,
+        code: String,
+    },
+    List(Vec<ListItem>),
+    Quote(Vec<Inline>),
+    Table {
+        header: Vec<String>,
+        rows: Vec<Vec<String>>,
+    },
+    Rule,
+}
+
+/// Renders Markdown into the format of a connector.
+pub trait Renderer {
+    type Output;
+
+    /// Renders parsed Markdown blocks.
+    fn render_blocks(&self, blocks: &[Block]) -> Self::Output;
+
+    /// Renders a Markdown text.
+    fn render(&self, markdown: &str) -> Self::Output {
+        self.render_blocks(&parse_markdown(markdown))
+    }
+}
+
+/// Parses a Markdown text into blocks.
+pub fn parse_markdown(markdown: &str) -> Vec<Block> {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut blocks = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let flush = |blocks: &mut Vec<Block>, paragraph: &mut Vec<&str>| {
+        if !paragraph.is_empty() {
+            blocks.push(Block::Paragraph(parse_inlines(&paragraph.join("\n"))));
+            paragraph.clear();
+        }
+    };
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end();
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            flush(&mut blocks, &mut paragraph);
+            i += 1;
+            continue;
+        }
+
+        if let Some(fence) = code_fence(trimmed) {
+            flush(&mut blocks, &mut paragraph);
+            let lang = trimmed[fence.len()..].trim();
+            let mut code = Vec::new();
+            i += 1;
+            while i < lines.len() && !lines[i].trim_start().starts_with(fence) {
+                code.push(lines[i]);
+                i += 1;
+            }
+            // skips the closing fence
+            i += 1;
+            blocks.push(Block::Code {
+                lang: (!lang.is_empty()).then(|| lang.to_string()),
+                code: code.join("\n"),
+            });
+            continue;
+        }
+
+        if let Some((level, text)) = heading(trimmed) {
+            flush(&mut blocks, &mut paragraph);
+            blocks.push(Block::Heading {
+                level,
+                content: parse_inlines(text),
+            });
+            i += 1;
+            continue;
+        }
+
+        if is_rule(trimmed) {
+            flush(&mut blocks, &mut paragraph);
+            blocks.push(Block::Rule);
+            i += 1;
+            continue;
+        }
+
+        if trimmed.starts_with('>') {
+            flush(&mut blocks, &mut paragraph);
+            let mut quote = Vec::new();
+            while i < lines.len()
+                && let Some(rest) = lines[i].trim().strip_prefix('>')
+            {
+                quote.push(rest.strip_prefix(' ').unwrap_or(rest));
+                i += 1;
+            }
+            blocks.push(Block::Quote(parse_inlines(&quote.join("\n"))));
+            continue;
+        }
+
+        if trimmed.starts_with('|') && i + 1 < lines.len() && is_table_separator(lines[i + 1]) {
+            flush(&mut blocks, &mut paragraph);
+            let header = table_cells(trimmed);
+            let mut rows = Vec::new();
+            i += 2;
+            while i < lines.len() && lines[i].trim_start().starts_with('|') {
+                rows.push(table_cells(lines[i]));
+                i += 1;
+            }
+            blocks.push(Block::Table { header, rows });
+            continue;
+        }
+
+        if let Some(item) = list_item(line) {
+            flush(&mut blocks, &mut paragraph);
+            let mut items = vec![item];
+            i += 1;
+            while i < lines.len() {
+                let next = lines[i].trim_end();
+                if let Some(item) = list_item(next) {
+                    items.push(item);
+                } else if next.starts_with([' ', '\t']) && !next.trim().is_empty() {
+                    // a continuation line of the last item
+                    let last = items.last_mut().unwrap();
+                    last.2 = format!("{}\n{}", last.2, next.trim());
+                } else {
+                    break;
+                }
+                i += 1;
+            }
+            blocks.push(Block::List(
+                items
+                    .into_iter()
+                    .map(|(depth, number, text)| ListItem {
+                        depth,
+                        number,
+                        content: parse_inlines(&text),
+                    })
+                    .collect(),
+            ));
+            continue;
+        }
+
+        paragraph.push(trimmed);
+        i += 1;
+    }
+    flush(&mut blocks, &mut paragraph);
+    blocks
+}
+
+fn code_fence(line: &str) -> Option<&'static str> {
+    if line.starts_with("```") {
+        Some("```")
+    } else if line.starts_with("~~~") {
+        Some("~~~")
+    } else {
+        None
+    }
+}
+
+fn heading(line: &str) -> Option<(u8, &str)> {
+    let level = line.len() - line.trim_start_matches('#').len();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if rest.is_empty() {
+        return Some((level as u8, ""));
+    }
+    rest.starts_with([' ', '\t'])
+        .then(|| (level as u8, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+fn is_rule(line: &str) -> bool {
+    let Some(c) = line.chars().next().filter(|c| matches!(c, '-' | '*' | '_')) else {
+        return false;
+    };
+    line.chars().all(|x| x == c || x == ' ') && line.chars().filter(|x| *x == c).count() >= 3
+}
+
+fn is_table_separator(line: &str) -> bool {
+    let line = line.trim();
+    line.contains('-')
+        && line.contains('|')
+        && line
+            .chars()
+            .all(|c| matches!(c, '|' | '-' | ':' | ' ' | '\t'))
+}
+
+fn table_cells(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|')
+        .map(|cell| plain_text(&parse_inlines(cell.trim())))
+        .collect()
+}
+
+/// Returns the depth, the number if ordered, and the text of a list item line.
+fn list_item(line: &str) -> Option<(usize, Option<u64>, String)> {
+    let trimmed = line.trim_start();
+    let indent: usize = line[..line.len() - trimmed.len()]
+        .chars()
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum();
+    let depth = indent / 2;
+    if let Some(text) = trimmed
+        .strip_prefix("- ")
+        .or_else(|| trimmed.strip_prefix("* "))
+        .or_else(|| trimmed.strip_prefix("+ "))
+    {
+        return Some((depth, None, text.trim().to_string()));
+    }
+    let digits = trimmed.len()
+        - trimmed
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    if (1..=9).contains(&digits) {
+        let rest = &trimmed[digits..];
+        if let Some(text) = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")) {
+            let number = trimmed[..digits].parse().ok()?;
+            return Some((depth, Some(number), text.trim().to_string()));
+        }
+    }
+    None
+}
+
+/// Parses the inline elements of a Markdown text. Newlines are parsed as line breaks.
+pub fn parse_inlines(text: &str) -> Vec<Inline> {
+    let mut inlines = Vec::new();
+    let mut buf = String::new();
+    let mut rest = text;
+    let push = |inlines: &mut Vec<Inline>, buf: &mut String, inline: Inline| {
+        if !buf.is_empty() {
+            inlines.push(Inline::Text(std::mem::take(buf)));
+        }
+        inlines.push(inline);
+    };
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '\\' => {
+                if let Some(next) = rest[1..].chars().next()
+                    && next.is_ascii_punctuation()
+                {
+                    buf.push(next);
+                    rest = &rest[1 + next.len_utf8()..];
+                    continue;
+                }
+            }
+            '\n' => {
+                push(&mut inlines, &mut buf, Inline::LineBreak);
+                rest = &rest[1..];
+                continue;
+            }
+            '`' => {
+                let n = rest.len() - rest.trim_start_matches('`').len();
+                let fence = &rest[..n];
+                if let Some(end) = rest[n..].find(fence) {
+                    let code = rest[n..n + end].trim().to_string();
+                    push(&mut inlines, &mut buf, Inline::Code(code));
+                    rest = &rest[n + end + n..];
+                } else {
+                    buf.push_str(fence);
+                    rest = &rest[n..];
+                }
+                continue;
+            }
+            '!' if rest.starts_with("![") => {
+                if let Some((alt, url, len)) = link(&rest[1..]) {
+                    let alt = plain_text(&parse_inlines(alt));
+                    push(&mut inlines, &mut buf, Inline::Image { alt, url });
+                    rest = &rest[1 + len..];
+                    continue;
+                }
+            }
+            '[' => {
+                if let Some((text, url, len)) = link(rest) {
+                    let text = parse_inlines(text);
+                    push(&mut inlines, &mut buf, Inline::Link { text, url });
+                    rest = &rest[len..];
+                    continue;
+                }
+            }
+            '<' => {
+                if let Some(end) = rest.find('>') {
+                    let url = &rest[1..end];
+                    if ["http://", "https://", "mailto:"]
+                        .iter()
+                        .any(|p| url.starts_with(p))
+                        && !url.contains(char::is_whitespace)
+                    {
+                        let inline = Inline::Link {
+                            text: vec![Inline::Text(url.to_string())],
+                            url: url.to_string(),
+                        };
+                        push(&mut inlines, &mut buf, inline);
+                        rest = &rest[end + 1..];
+                        continue;
+                    }
+                }
+            }
+            '*' | '_' | '~' => {
+                // `_` inside words, e.g. in snake_case, is not a delimiter
+                let intraword = c == '_' && buf.chars().last().is_some_and(char::is_alphanumeric);
+                let double = rest[1..].starts_with(c);
+                let delim = if double { &rest[..2] } else { &rest[..1] };
+                if !intraword
+                    && (double || c != '~')
+                    && let Some(end) = closing_delimiter(&rest[delim.len()..], delim)
+                {
+                    let inner = parse_inlines(&rest[delim.len()..delim.len() + end]);
+                    let inline = match (c, double) {
+                        ('~', _) => Inline::Strike(inner),
+                        (_, true) => Inline::Strong(inner),
+                        (_, false) => Inline::Emphasis(inner),
+                    };
+                    push(&mut inlines, &mut buf, inline);
+                    rest = &rest[delim.len() + end + delim.len()..];
+                    continue;
+                }
+                buf.push_str(delim);
+                rest = &rest[delim.len()..];
+                continue;
+            }
+            _ => {}
+        }
+        buf.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if !buf.is_empty() {
+        inlines.push(Inline::Text(buf));
+    }
+    inlines
+}
+
+/// Returns the position of the closing delimiter of an emphasis. The emphasized text
+/// should not be empty, nor start or end with whitespace.
+fn closing_delimiter(text: &str, delim: &str) -> Option<usize> {
+    if text.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut from = 0;
+    while let Some(pos) = text[from..].find(delim) {
+        let end = from + pos;
+        let after = &text[end + delim.len()..];
+        if end > 0
+            && !text[..end].ends_with(char::is_whitespace)
+            // `**` is not the end of a single `*`
+            && !after.starts_with(delim)
+            && (delim != "_" || !after.starts_with(char::is_alphanumeric))
+        {
+            return Some(end);
+        }
+        from = end + delim.len() + after.len() - after.trim_start_matches(delim).len();
+    }
+    None
+}
+
+/// Parses a `[text](url)` link at the start of the text, and returns the text, the URL and
+/// the length of the link.
+fn link(text: &str) -> Option<(&str, String, usize)> {
+    let mut depth = 0;
+    let mut close = None;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+            '\n' => return None,
+            _ => {}
+        }
+    }
+    let close = close?;
+    let rest = text[close + 1..].strip_prefix('(')?;
+    let end = rest.find(')')?;
+    // drops the title, e.g. [text](url "title")
+    let url = rest[..end].split_whitespace().next().unwrap_or_default();
+    if url.is_empty() {
+        return None;
+    }
+    Some((&text[1..close], url.to_string(), close + 2 + end + 1))
+}
+
+/// Returns the plain text of inline elements. Links are followed by their URL, and images
+/// are replaced by their alt text and URL.
+pub fn plain_text(inlines: &[Inline]) -> String {
+    let mut out = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) | Inline::Code(text) => out.push_str(text),
+            Inline::Strong(inner) | Inline::Emphasis(inner) | Inline::Strike(inner) => {
+                out.push_str(&plain_text(inner))
+            }
+            Inline::Link { text, url } => {
+                let text = plain_text(text);
+                if text.is_empty() || &text == url {
+                    out.push_str(url);
+                } else {
+                    out.push_str(&format!("{} ({})", text, url));
+                }
+            }
+            Inline::Image { alt, url } => {
+                if alt.is_empty() {
+                    out.push_str(url);
+                } else {
+                    out.push_str(&format!("{} ({})", alt, url));
+                }
+            }
+            Inline::LineBreak => out.push('\n'),
+        }
+    }
+    out
+}
+
+/// Formats a table as aligned text.
+fn format_table(header: &[String], rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|r| r.len()).chain([header.len()]).max();
+    let columns = columns.unwrap_or_default();
+    let mut widths = vec![0; columns];
+    for row in rows.iter().chain([&header.to_vec()]) {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+    let format_row = |row: &[String]| {
+        (0..columns)
+            .map(|i| {
+                let cell = row.get(i).map(|c| c.as_str()).unwrap_or_default();
+                format!("{:width$}", cell, width = widths[i])
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+    let mut lines = vec![format_row(header)];
+    lines.push(
+        widths
+            .iter()
+            .map(|w| "-".repeat(*w))
+            .collect::<Vec<_>>()
+            .join("-+-"),
+    );
+    lines.extend(rows.iter().map(|row| format_row(row)));
+    lines.join("\n")
+}
+
+/// Returns the length of a text in UTF-16 code units, as counted by Telegram. It is not less
+/// than the number of characters counted by Slack and Discord.
+fn text_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Splits a text into parts of at most `max_len`, at line boundaries if possible.
+fn split_text(text: &str, max_len: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut len = 0;
+    for line in text.split('\n') {
+        let line_len = text_len(line);
+        if !part.is_empty() && len + 1 + line_len <= max_len {
+            part.push('\n');
+            part.push_str(line);
+            len += 1 + line_len;
+            continue;
+        }
+        if !part.is_empty() {
+            parts.push(std::mem::take(&mut part));
+        }
+        len = 0;
+        for c in line.chars() {
+            if len + c.len_utf16() > max_len {
+                parts.push(std::mem::take(&mut part));
+                len = 0;
+            }
+            part.push(c);
+            len += c.len_utf16();
+        }
+    }
+    if !part.is_empty() || parts.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+/// Packs rendered pieces into messages of at most `max_len`, separated by blank lines.
+/// Every piece should fit in a message.
+fn pack(pieces: Vec<String>, max_len: usize) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut message = String::new();
+    for piece in pieces {
+        if !message.is_empty() && text_len(&message) + 2 + text_len(&piece) > max_len {
+            messages.push(std::mem::take(&mut message));
+        }
+        if !message.is_empty() {
+            message.push_str("\n\n");
+        }
+        message.push_str(&piece);
+    }
+    if !message.is_empty() {
+        messages.push(message);
+    }
+    messages
+}
+
+/// Renders the lines of a list with the given text of items.
+fn render_list(items: &[ListItem], mut render: impl FnMut(&[Inline]) -> String) -> String {
+    items
+        .iter()
+        .map(|item| {
+            let marker = match item.number {
+                Some(n) => format!("{}.", n),
+                None => "•".to_string(),
+            };
+            let indent = "    ".repeat(item.depth);
+            let text = render(&item.content).replace('\n', &format!("\n{}  ", indent));
+            format!("{}{} {}", indent, marker, text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders Markdown as plain text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlainTextRenderer;
+
+impl PlainTextRenderer {
+    fn render_block(block: &Block) -> String {
+        match block {
+            Block::Heading { content, .. } | Block::Paragraph(content) => plain_text(content),
+            Block::Code { code, .. } => code.clone(),
+            Block::List(items) => render_list(items, plain_text),
+            Block::Quote(content) => plain_text(content)
+                .lines()
+                .map(|line| format!("> {}", line))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::Table { header, rows } => format_table(header, rows),
+            Block::Rule => "---".to_string(),
+        }
+    }
+}
+
+impl PlainTextRenderer {
+    /// Renders the UI hints as a numbered list of options. The number `n` typed by the user
+    /// is the action `n - 1` of [`UiHints::actions`].
+    pub fn render_ui(&self, hints: &UiHints) -> String {
+        let mut lines = Vec::new();
+        if let Some(confirm) = &hints.confirm {
+            lines.push(confirm.text.clone());
+        }
+        for (i, action) in hints.actions().into_iter().enumerate() {
+            lines.push(format!("{}. {}", i + 1, action.label));
+        }
+        lines.join("\n")
+    }
+}
+
+impl Renderer for PlainTextRenderer {
+    type Output = String;
+
+    fn render_blocks(&self, blocks: &[Block]) -> String {
+        blocks
+            .iter()
+            .map(Self::render_block)
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// The maximum length of a Telegram message.
+pub const TELEGRAM_MAX_MESSAGE_LEN: usize = 4096;
+
+/// Renders Markdown as Telegram HTML messages.
+#[derive(Debug, Clone)]
+pub struct TelegramRenderer {
+    max_len: usize,
+}
+
+impl Default for TelegramRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TelegramRenderer {
+    /// Creates a renderer of messages of at most [`TELEGRAM_MAX_MESSAGE_LEN`].
+    pub fn new() -> Self {
+        Self {
+            max_len: TELEGRAM_MAX_MESSAGE_LEN,
+        }
+    }
+
+    /// Sets the maximum length of messages, e.g. 1024 for media captions.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len.max(64);
+        self
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    fn escape_attr(text: &str) -> String {
+        Self::escape(text).replace('"', "&quot;")
+    }
+
+    fn render_inlines(inlines: &[Inline]) -> String {
+        let mut out = String::new();
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => out.push_str(&Self::escape(text)),
+                Inline::Strong(inner) => {
+                    out.push_str(&format!("<b>{}</b>", Self::render_inlines(inner)))
+                }
+                Inline::Emphasis(inner) => {
+                    out.push_str(&format!("<i>{}</i>", Self::render_inlines(inner)))
+                }
+                Inline::Strike(inner) => {
+                    out.push_str(&format!("<s>{}</s>", Self::render_inlines(inner)))
+                }
+                Inline::Code(code) => out.push_str(&format!("<code>{}</code>", Self::escape(code))),
+                Inline::Link { text, url } => out.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    Self::escape_attr(url),
+                    Self::render_inlines(text)
+                )),
+                Inline::Image { alt, url } => out.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    Self::escape_attr(url),
+                    Self::escape(if alt.is_empty() { url } else { alt })
+                )),
+                Inline::LineBreak => out.push('\n'),
+            }
+        }
+        out
+    }
+
+    fn pre(code: &str, lang: Option<&str>) -> String {
+        match lang {
+            Some(lang) => format!(
+                "<pre><code class=\"language-{}\">{}</code></pre>",
+                Self::escape_attr(lang),
+                Self::escape(code)
+            ),
+            None => format!("<pre>{}</pre>", Self::escape(code)),
+        }
+    }
+
+    /// Renders a block into pieces that fit in a message.
+    fn render_block(&self, block: &Block) -> Vec<String> {
+        let html = match block {
+            Block::Heading { content, .. } => format!("<b>{}</b>", Self::render_inlines(content)),
+            Block::Paragraph(content) => Self::render_inlines(content),
+            Block::Code { lang, code } => Self::pre(code, lang.as_deref()),
+            Block::List(items) => render_list(items, Self::render_inlines),
+            Block::Quote(content) => {
+                format!("<blockquote>{}</blockquote>", Self::render_inlines(content))
+            }
+            Block::Table { header, rows } => Self::pre(&format_table(header, rows), None),
+            Block::Rule => "——————".to_string(),
+        };
+        if text_len(&html) <= self.max_len {
+            return vec![html];
+        }
+
+        // a block too long for a message is split without formatting, except for code
+        match block {
+            Block::Code { lang, code } => split_text(code, self.max_len - 64)
+                .into_iter()
+                .map(|part| Self::pre(&part, lang.as_deref()))
+                .collect(),
+            _ => split_text(&PlainTextRenderer::render_block(block), self.max_len)
+                .into_iter()
+                .map(|part| Self::escape(&part))
+                .collect(),
+        }
+    }
+}
+
+/// The maximum length of the callback data of a Telegram button, in bytes.
+pub const TELEGRAM_MAX_CALLBACK_DATA_LEN: usize = 64;
+
+impl TelegramRenderer {
+    /// Renders the UI hints as the `reply_markup` of an inline keyboard, sent with the last
+    /// message. The callback data of a button is the ID of its action; actions whose ID is
+    /// longer than [`TELEGRAM_MAX_CALLBACK_DATA_LEN`] are left out. The question of the
+    /// confirmation is not included, it should be sent as text.
+    pub fn render_ui(&self, hints: &UiHints) -> Option<Value> {
+        let mut actions = hints.actions();
+        // one row per reply or button, the confirm and cancel buttons side by side
+        let answers =
+            actions.split_off(actions.len() - if hints.confirm.is_some() { 2 } else { 0 });
+        let fits = |a: &&UiAction| a.id.len() <= TELEGRAM_MAX_CALLBACK_DATA_LEN;
+        let button = |a: &UiAction| json!({"text": a.label, "callback_data": a.id});
+        let mut rows: Vec<Value> = actions
+            .iter()
+            .filter(fits)
+            .map(|a| json!([button(a)]))
+            .collect();
+        let answers: Vec<Value> = answers.iter().filter(fits).map(button).collect();
+        if !answers.is_empty() {
+            rows.push(Value::Array(answers));
+        }
+        if rows.is_empty() {
+            return None;
+        }
+        Some(json!({"inline_keyboard": rows}))
+    }
+}
+
+impl Renderer for TelegramRenderer {
+    /// The HTML messages.
+    type Output = Vec<String>;
+
+    fn render_blocks(&self, blocks: &[Block]) -> Vec<String> {
+        let pieces = blocks.iter().flat_map(|b| self.render_block(b)).collect();
+        pack(pieces, self.max_len)
+    }
+}
+
+/// The maximum number of blocks in a Slack message.
+pub const SLACK_MAX_BLOCKS: usize = 50;
+/// The maximum length of the text of a Slack section block.
+pub const SLACK_MAX_SECTION_LEN: usize = 3000;
+const SLACK_MAX_HEADER_LEN: usize = 150;
+
+/// A rendered block of Slack, or texts to merge into sections.
+enum SlackPiece {
+    Block(Value),
+    Texts(Vec<String>),
+}
+
+/// Renders Markdown as Slack Block Kit blocks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlackRenderer;
+
+impl SlackRenderer {
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    fn render_inlines(inlines: &[Inline]) -> String {
+        let mut out = String::new();
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => out.push_str(&Self::escape(text)),
+                Inline::Strong(inner) => {
+                    out.push_str(&format!("*{}*", Self::render_inlines(inner)))
+                }
+                Inline::Emphasis(inner) => {
+                    out.push_str(&format!("_{}_", Self::render_inlines(inner)))
+                }
+                Inline::Strike(inner) => {
+                    out.push_str(&format!("~{}~", Self::render_inlines(inner)))
+                }
+                Inline::Code(code) => out.push_str(&format!("`{}`", Self::escape(code))),
+                Inline::Link { text, url } => {
+                    let text = Self::render_inlines(text).replace('|', "¦");
+                    out.push_str(&format!("<{}|{}>", url, text))
+                }
+                Inline::Image { alt, url } => {
+                    let alt = if alt.is_empty() { url } else { alt };
+                    out.push_str(&format!(
+                        "<{}|{}>",
+                        url,
+                        Self::escape(alt).replace('|', "¦")
+                    ))
+                }
+                Inline::LineBreak => out.push('\n'),
+            }
+        }
+        out
+    }
+
+    fn code(code: &str) -> String {
+        format!("```{}```", Self::escape(code))
+    }
+
+    fn section(text: String) -> Value {
+        json!({"type": "section", "text": {"type": "mrkdwn", "text": text}})
+    }
+
+    /// Renders a block as a Slack block, or as mrkdwn texts of sections.
+    fn render_block(block: &Block) -> SlackPiece {
+        if let Block::Paragraph(content) = block
+            && let [Inline::Image { alt, url }] = content.as_slice()
+            && url.starts_with("http")
+        {
+            return SlackPiece::Block(json!({
+                "type": "image",
+                "image_url": url,
+                "alt_text": if alt.is_empty() { "image" } else { alt.as_str() },
+            }));
+        }
+
+        let text = match block {
+            Block::Heading { level, content } if *level <= 2 => {
+                let text: String = plain_text(content)
+                    .replace('\n', " ")
+                    .chars()
+                    .take(SLACK_MAX_HEADER_LEN)
+                    .collect();
+                return SlackPiece::Block(json!({
+                    "type": "header",
+                    "text": {"type": "plain_text", "text": text, "emoji": true},
+                }));
+            }
+            Block::Rule => return SlackPiece::Block(json!({"type": "divider"})),
+            Block::Heading { content, .. } => format!("*{}*", Self::render_inlines(content)),
+            Block::Paragraph(content) => Self::render_inlines(content),
+            Block::Code { code, .. } => Self::code(code),
+            Block::List(items) => render_list(items, Self::render_inlines),
+            Block::Quote(content) => Self::render_inlines(content)
+                .lines()
+                .map(|line| format!("&gt; {}", line))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::Table { header, rows } => Self::code(&format_table(header, rows)),
+        };
+        if text_len(&text) <= SLACK_MAX_SECTION_LEN {
+            return SlackPiece::Texts(vec![text]);
+        }
+        SlackPiece::Texts(match block {
+            Block::Code { code, .. } => split_text(code, SLACK_MAX_SECTION_LEN - 16)
+                .into_iter()
+                .map(|part| Self::code(&part))
+                .collect(),
+            _ => split_text(
+                &PlainTextRenderer::render_block(block),
+                SLACK_MAX_SECTION_LEN - 64,
+            )
+            .into_iter()
+            .map(|part| Self::escape(&part))
+            .collect(),
+        })
+    }
+}
+
+const SLACK_MAX_ACTIONS: usize = 25;
+const SLACK_MAX_BUTTON_TEXT_LEN: usize = 75;
+
+impl SlackRenderer {
+    /// Renders the UI hints as blocks to append to the last message: a section with the
+    /// question of the confirmation, and actions blocks of buttons whose `value` is the ID
+    /// of their action.
+    pub fn render_ui(&self, hints: &UiHints) -> Vec<Value> {
+        let mut blocks = Vec::new();
+        if let Some(confirm) = &hints.confirm {
+            blocks.push(Self::section(Self::escape(&confirm.text)));
+        }
+        let actions = hints.actions();
+        for (i, chunk) in actions.chunks(SLACK_MAX_ACTIONS).enumerate() {
+            let elements: Vec<Value> = chunk
+                .iter()
+                .map(|action| {
+                    let text: String = action
+                        .label
+                        .chars()
+                        .take(SLACK_MAX_BUTTON_TEXT_LEN)
+                        .collect();
+                    let mut button = json!({
+                        "type": "button",
+                        "text": {"type": "plain_text", "text": text, "emoji": true},
+                        "action_id": action.id,
+                        "value": action.id,
+                    });
+                    match action.style {
+                        UiStyle::Primary => button["style"] = "primary".into(),
+                        UiStyle::Danger => button["style"] = "danger".into(),
+                        UiStyle::Default => {}
+                    }
+                    button
+                })
+                .collect();
+            blocks.push(json!({
+                "type": "actions",
+                "block_id": format!("ui_{}", i),
+                "elements": elements,
+            }));
+        }
+        blocks
+    }
+}
+
+impl Renderer for SlackRenderer {
+    /// The blocks of the messages, at most [`SLACK_MAX_BLOCKS`] per message.
+    type Output = Vec<Vec<Value>>;
+
+    fn render_blocks(&self, blocks: &[Block]) -> Vec<Vec<Value>> {
+        let mut rendered: Vec<Value> = Vec::new();
+        let mut texts: Vec<String> = Vec::new();
+        for block in blocks {
+            match Self::render_block(block) {
+                SlackPiece::Block(value) => {
+                    rendered.extend(
+                        pack(std::mem::take(&mut texts), SLACK_MAX_SECTION_LEN)
+                            .into_iter()
+                            .map(Self::section),
+                    );
+                    rendered.push(value);
+                }
+                // consecutive texts are merged into sections
+                SlackPiece::Texts(parts) => texts.extend(parts),
+            }
+        }
+        rendered.extend(
+            pack(texts, SLACK_MAX_SECTION_LEN)
+                .into_iter()
+                .map(Self::section),
+        );
+        rendered
+            .chunks(SLACK_MAX_BLOCKS)
+            .map(|chunk| chunk.to_vec())
+            .collect()
+    }
+}
+
+/// The maximum length of the description of a Discord embed.
+pub const DISCORD_MAX_DESCRIPTION_LEN: usize = 4096;
+/// The maximum number of embeds in a Discord message.
+pub const DISCORD_MAX_EMBEDS: usize = 10;
+/// The maximum total length of the embeds of a Discord message.
+pub const DISCORD_MAX_EMBEDS_LEN: usize = 6000;
+const DISCORD_MAX_TITLE_LEN: usize = 256;
+
+/// Renders Markdown as Discord embeds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiscordRenderer {
+    color: Option<u32>,
+}
+
+impl DiscordRenderer {
+    /// Creates a renderer of embeds without color.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the color of the embeds, e.g. `0x5865F2`.
+    pub fn with_color(mut self, color: u32) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    fn escape(text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|') {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    fn render_inlines(inlines: &[Inline]) -> String {
+        let mut out = String::new();
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => out.push_str(&Self::escape(text)),
+                Inline::Strong(inner) => {
+                    out.push_str(&format!("**{}**", Self::render_inlines(inner)))
+                }
+                Inline::Emphasis(inner) => {
+                    out.push_str(&format!("*{}*", Self::render_inlines(inner)))
+                }
+                Inline::Strike(inner) => {
+                    out.push_str(&format!("~~{}~~", Self::render_inlines(inner)))
+                }
+                Inline::Code(code) => out.push_str(&format!("`{}`", code.replace('`', "'"))),
+                Inline::Link { text, url } => {
+                    out.push_str(&format!("[{}]({})", Self::render_inlines(text), url))
+                }
+                Inline::Image { alt, url } => {
+                    let alt = if alt.is_empty() { url } else { alt };
+                    out.push_str(&format!("[{}]({})", Self::escape(alt), url))
+                }
+                Inline::LineBreak => out.push('\n'),
+            }
+        }
+        out
+    }
+
+    fn code(code: &str, lang: Option<&str>) -> String {
+        format!(
+            "```{}\n{}\n```",
+            lang.unwrap_or_default(),
+            code.replace("```", "'''")
+        )
+    }
+
+    fn render_block(block: &Block) -> Vec<String> {
+        let text = match block {
+            Block::Heading { content, .. } => format!("**{}**", Self::render_inlines(content)),
+            Block::Paragraph(content) => Self::render_inlines(content),
+            Block::Code { lang, code } => Self::code(code, lang.as_deref()),
+            Block::List(items) => render_list(items, Self::render_inlines),
+            Block::Quote(content) => Self::render_inlines(content)
+                .lines()
+                .map(|line| format!("> {}", line))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::Table { header, rows } => Self::code(&format_table(header, rows), None),
+            Block::Rule => "━━━━━━━━━━".to_string(),
+        };
+        if text_len(&text) <= DISCORD_MAX_DESCRIPTION_LEN {
+            return vec![text];
+        }
+        match block {
+            Block::Code { lang, code } => split_text(code, DISCORD_MAX_DESCRIPTION_LEN - 32)
+                .into_iter()
+                .map(|part| Self::code(&part, lang.as_deref()))
+                .collect(),
+            _ => split_text(
+                &PlainTextRenderer::render_block(block),
+                DISCORD_MAX_DESCRIPTION_LEN / 2,
+            )
+            .into_iter()
+            .map(|part| Self::escape(&part))
+            .collect(),
+        }
+    }
+}
+
+const DISCORD_MAX_ROW_BUTTONS: usize = 5;
+const DISCORD_MAX_ROWS: usize = 5;
+const DISCORD_MAX_LABEL_LEN: usize = 80;
+
+impl DiscordRenderer {
+    /// Renders the UI hints as the `components` of the last message: action rows of buttons
+    /// whose `custom_id` is the ID of their action. Discord allows at most 25 buttons, the
+    /// rest are left out. The question of the confirmation is not included.
+    pub fn render_ui(&self, hints: &UiHints) -> Vec<Value> {
+        let buttons: Vec<Value> = hints
+            .actions()
+            .into_iter()
+            .take(DISCORD_MAX_ROW_BUTTONS * DISCORD_MAX_ROWS)
+            .map(|action| {
+                let label: String = action.label.chars().take(DISCORD_MAX_LABEL_LEN).collect();
+                json!({
+                    "type": 2,
+                    "style": match action.style {
+                        UiStyle::Primary => 1,
+                        UiStyle::Default => 2,
+                        UiStyle::Danger => 4,
+                    },
+                    "label": label,
+                    "custom_id": action.id,
+                })
+            })
+            .collect();
+        buttons
+            .chunks(DISCORD_MAX_ROW_BUTTONS)
+            .map(|row| json!({"type": 1, "components": row}))
+            .collect()
+    }
+}
+
+impl Renderer for DiscordRenderer {
+    /// The embeds of the messages, at most [`DISCORD_MAX_EMBEDS`] per message.
+    type Output = Vec<Vec<Value>>;
+
+    fn render_blocks(&self, mut blocks: &[Block]) -> Vec<Vec<Value>> {
+        // a leading heading is the title of the first embed
+        let mut title = None;
+        if let Some((Block::Heading { content, .. }, rest)) = blocks.split_first() {
+            let text: String = plain_text(content)
+                .replace('\n', " ")
+                .chars()
+                .take(DISCORD_MAX_TITLE_LEN)
+                .collect();
+            title = Some(text);
+            blocks = rest;
+        }
+
+        // the first image alone in a paragraph is the image of the first embed
+        let mut image = None;
+        let mut pieces = Vec::new();
+        for block in blocks {
+            if image.is_none()
+                && let Block::Paragraph(content) = block
+                && let [Inline::Image { url, .. }] = content.as_slice()
+                && url.starts_with("http")
+            {
+                image = Some(url.clone());
+                continue;
+            }
+            pieces.extend(Self::render_block(block));
+        }
+
+        let mut embeds: Vec<Value> = pack(pieces, DISCORD_MAX_DESCRIPTION_LEN)
+            .into_iter()
+            .map(|description| json!({"description": description}))
+            .collect();
+        if embeds.is_empty() && (title.is_some() || image.is_some()) {
+            embeds.push(Value::Object(Map::new()));
+        }
+        if let Some(first) = embeds.first_mut() {
+            if let Some(title) = title {
+                first["title"] = title.into();
+            }
+            if let Some(url) = image {
+                first["image"] = json!({"url": url});
+            }
+        }
+        if let Some(color) = self.color {
+            for embed in &mut embeds {
+                embed["color"] = color.into();
+            }
+        }
+
+        let mut messages: Vec<Vec<Value>> = Vec::new();
+        let mut message: Vec<Value> = Vec::new();
+        let mut len = 0;
+        for embed in embeds {
+            let embed_len = embed["title"].as_str().map(text_len).unwrap_or_default()
+                + embed["description"]
+                    .as_str()
+                    .map(text_len)
+                    .unwrap_or_default();
+            if !message.is_empty()
+                && (message.len() >= DISCORD_MAX_EMBEDS || len + embed_len > DISCORD_MAX_EMBEDS_LEN)
+            {
+                messages.push(std::mem::take(&mut message));
+                len = 0;
+            }
+            len += embed_len;
+            message.push(embed);
+        }
+        if !message.is_empty() {
+            messages.push(message);
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKDOWN: &str = r#"# Weekly *report*
+
+Sales grew by **12%** in `Q3`, see [the dashboard](https://example.com/d?a=1&b=2).
+Costs: 2 * 3 < 7 and snake_case_names stay.
+
+![chart](https://example.com/chart.svg)
+
+- First ~~item~~
+  continued
+  - Nested _item_
+2. Second
+
+> Quoted
+> text
+
+| Region | Sales |
+|--------|------:|
+| EU     | 1,200 |
+| APAC   | 900 |
+
+```rust
+let x = a < b;
+```
+
+---"#;
+
+    #[test]
+    fn test_parse_markdown() {
+        let blocks = parse_markdown(MARKDOWN);
+        assert_eq!(blocks.len(), 8);
+        assert_eq!(
+            blocks[0],
+            Block::Heading {
+                level: 1,
+                content: vec![
+                    Inline::Text("Weekly ".to_string()),
+                    Inline::Emphasis(vec![Inline::Text("report".to_string())]),
+                ],
+            }
+        );
+        let Block::Paragraph(content) = &blocks[1] else {
+            panic!("expected a paragraph")
+        };
+        assert_eq!(
+            content[1],
+            Inline::Strong(vec![Inline::Text("12%".to_string())])
+        );
+        assert_eq!(content[3], Inline::Code("Q3".to_string()));
+        assert_eq!(
+            content[5],
+            Inline::Link {
+                text: vec![Inline::Text("the dashboard".to_string())],
+                url: "https://example.com/d?a=1&b=2".to_string(),
+            }
+        );
+        assert_eq!(content[7], Inline::LineBreak);
+        assert_eq!(
+            content[8],
+            Inline::Text("Costs: 2 * 3 < 7 and snake_case_names stay.".to_string())
+        );
+        assert_eq!(
+            blocks[2],
+            Block::Paragraph(vec![Inline::Image {
+                alt: "chart".to_string(),
+                url: "https://example.com/chart.svg".to_string(),
+            }])
+        );
+        let Block::List(items) = &blocks[3] else {
+            panic!("expected a list")
+        };
+        assert_eq!(items.len(), 3);
+        assert_eq!(plain_text(&items[0].content), "First item\ncontinued");
+        assert_eq!((items[1].depth, items[1].number), (1, None));
+        assert_eq!((items[2].depth, items[2].number), (0, Some(2)));
+        assert_eq!(
+            blocks[5],
+            Block::Table {
+                header: vec!["Region".to_string(), "Sales".to_string()],
+                rows: vec![
+                    vec!["EU".to_string(), "1,200".to_string()],
+                    vec!["APAC".to_string(), "900".to_string()],
+                ],
+            }
+        );
+        assert_eq!(
+            blocks[6],
+            Block::Code {
+                lang: Some("rust".to_string()),
+                code: "let x = a < b;".to_string(),
+            }
+        );
+        assert_eq!(blocks[7], Block::Rule);
+
+        assert_eq!(
+            parse_inlines(r"\*not emphasis\* and __strong__"),
+            vec![
+                Inline::Text("*not emphasis* and ".to_string()),
+                Inline::Strong(vec![Inline::Text("strong".to_string())]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plain_text_renderer() {
+        let text = PlainTextRenderer.render(MARKDOWN);
+        assert!(text.starts_with("Weekly report\n\nSales grew by 12% in Q3, see the dashboard (https://example.com/d?a=1&b=2).\n"));
+        assert!(text.contains("chart (https://example.com/chart.svg)"));
+        assert!(text.contains("• First item\n  continued\n    • Nested item\n2. Second"));
+        assert!(text.contains("> Quoted\n> text"));
+        assert!(text.contains("Region | Sales\n-------+------\nEU     | 1,200\nAPAC   | 900"));
+    }
+
+    #[test]
+    fn test_telegram_renderer() {
+        let messages = TelegramRenderer::new().render(MARKDOWN);
+        assert_eq!(messages.len(), 1);
+        let html = &messages[0];
+        assert!(html.starts_with("<b>Weekly <i>report</i></b>\n\n"));
+        assert!(html.contains(
+            "Sales grew by <b>12%</b> in <code>Q3</code>, see <a href=\"https://example.com/d?a=1&amp;b=2\">the dashboard</a>.\nCosts: 2 * 3 &lt; 7"
+        ));
+        assert!(html.contains("<a href=\"https://example.com/chart.svg\">chart</a>"));
+        assert!(html.contains("• First <s>item</s>"));
+        assert!(html.contains("<blockquote>Quoted\ntext</blockquote>"));
+        assert!(html.contains("<pre>Region | Sales\n"));
+        assert!(html.contains("<pre><code class=\"language-rust\">let x = a &lt; b;</code></pre>"));
+
+        // long outputs are split at block boundaries, and long blocks without formatting
+        let long = format!(
+            "{}\n\n**{}**\n\n```\n{}```",
+            "a".repeat(50),
+            "b ".repeat(100),
+            "c\n".repeat(60)
+        );
+        let messages = TelegramRenderer::new().with_max_len(100).render(&long);
+        assert!(messages.iter().all(|m| text_len(m) <= 100));
+        assert_eq!(messages[0], "a".repeat(50));
+        assert!(!messages[1].contains("<b>"));
+        assert!(messages.last().unwrap().starts_with("<pre>c\n"));
+        assert!(messages.last().unwrap().ends_with("c</pre>"));
+    }
+
+    #[test]
+    fn test_slack_renderer() {
+        let messages = SlackRenderer.render(MARKDOWN);
+        assert_eq!(messages.len(), 1);
+        let blocks = &messages[0];
+        assert_eq!(
+            blocks[0],
+            json!({"type": "header", "text": {"type": "plain_text", "text": "Weekly report", "emoji": true}})
+        );
+        assert_eq!(
+            blocks[1]["text"]["text"],
+            "Sales grew by *12%* in `Q3`, see <https://example.com/d?a=1&b=2|the dashboard>.\nCosts: 2 * 3 &lt; 7 and snake_case_names stay."
+        );
+        assert_eq!(
+            blocks[2],
+            json!({"type": "image", "image_url": "https://example.com/chart.svg", "alt_text": "chart"})
+        );
+        let text = blocks[3]["text"]["text"].as_str().unwrap();
+        assert!(text.starts_with("• First ~item~\n  continued\n    • Nested _item_"));
+        assert!(text.contains("&gt; Quoted\n&gt; text"));
+        assert!(text.contains("```Region | Sales\n"));
+        assert!(text.ends_with("```let x = a &lt; b;```"));
+        assert_eq!(blocks[4], json!({"type": "divider"}));
+        assert_eq!(blocks.len(), 5);
+
+        let long = "paragraph\n\n---\n\n".repeat(30);
+        let messages = SlackRenderer.render(&long);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].len(), SLACK_MAX_BLOCKS);
+        assert_eq!(messages[1].len(), 10);
+    }
+
+    #[test]
+    fn test_discord_renderer() {
+        let messages = DiscordRenderer::new().with_color(0x5865F2).render(MARKDOWN);
+        assert_eq!(messages.len(), 1);
+        let embed = &messages[0][0];
+        assert_eq!(embed["title"], "Weekly report");
+        assert_eq!(embed["image"]["url"], "https://example.com/chart.svg");
+        assert_eq!(embed["color"], 0x5865F2);
+        let description = embed["description"].as_str().unwrap();
+        assert!(description.starts_with(
+            "Sales grew by **12%** in `Q3`, see [the dashboard](https://example.com/d?a=1&b=2).\nCosts: 2 \\* 3 < 7 and snake\\_case\\_names stay."
+        ));
+        assert!(description.contains("• First ~~item~~"));
+        assert!(description.contains("> Quoted\n> text"));
+        assert!(description.contains("```\nRegion | Sales\n"));
+        assert!(description.contains("```rust\nlet x = a < b;\n```"));
+
+        let long = format!("{}\n\n", "word ".repeat(800)).repeat(12);
+        let messages = DiscordRenderer::new().render(&long);
+        for message in &messages {
+            assert!(message.len() <= DISCORD_MAX_EMBEDS);
+            let len: usize = message
+                .iter()
+                .map(|e| text_len(e["description"].as_str().unwrap()))
+                .sum();
+            assert!(len <= DISCORD_MAX_EMBEDS_LEN);
+        }
+        assert_eq!(messages.iter().map(|m| m.len()).sum::<usize>(), 12);
+    }
+
+    #[test]
+    fn test_render_ui() {
+        use anda_core::{UiButton, UiConfirm};
+
+        let hints = UiHints::default()
+            .with_reply("Show more")
+            .with_button(UiButton::new("buy", "Buy now").with_style(UiStyle::Primary))
+            .with_confirm(UiConfirm::new("transfer", "Transfer 10 ICP to alice?"));
+
+        assert_eq!(
+            PlainTextRenderer.render_ui(&hints),
+            "Transfer 10 ICP to alice?\n1. Show more\n2. Buy now\n3. Confirm\n4. Cancel"
+        );
+
+        let markup = TelegramRenderer::new().render_ui(&hints).unwrap();
+        assert_eq!(
+            markup,
+            json!({"inline_keyboard": [
+                [{"text": "Show more", "callback_data": "reply:0"}],
+                [{"text": "Buy now", "callback_data": "buy"}],
+                [
+                    {"text": "Confirm", "callback_data": "transfer:confirm"},
+                    {"text": "Cancel", "callback_data": "transfer:cancel"},
+                ],
+            ]})
+        );
+        assert!(
+            TelegramRenderer::new()
+                .render_ui(&UiHints::default())
+                .is_none()
+        );
+
+        let blocks = SlackRenderer.render_ui(&hints);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["text"]["text"], "Transfer 10 ICP to alice?");
+        let elements = blocks[1]["elements"].as_array().unwrap();
+        assert_eq!(elements.len(), 4);
+        assert_eq!(elements[1]["value"], "buy");
+        assert_eq!(elements[1]["style"], "primary");
+        assert!(elements[0].get("style").is_none());
+
+        let components = DiscordRenderer::new().render_ui(&hints);
+        assert_eq!(components.len(), 1);
+        let buttons = components[0]["components"].as_array().unwrap();
+        assert_eq!(buttons[2]["custom_id"], "transfer:confirm");
+        assert_eq!(buttons[2]["style"], 1);
+        assert_eq!(buttons[3]["style"], 2);
+
+        // the ID sent back by the platform resolves to the callback
+        let id = markup["inline_keyboard"][2][0]["callback_data"]
+            .as_str()
+            .unwrap();
+        assert_eq!(hints.resolve(id).unwrap().to_prompt(), "Confirmed.");
+    }
+}
@@ -0,0 +1,406 @@
+//! Blue/green rollouts of agent versions.
+//!
+//! A new version of an agent is registered as a separate agent, the candidate, next to the
+//! stable agent. A [`Rollout`] routes a percentage of the requests for the stable agent to
+//! the candidate. Users are assigned to a version by a hash of their identity, so a user
+//! talks to the same version as long as the percentage does not change.
+//!
+//! The engine tracks the error rate of the runs and the negative feedback rate of both
+//! versions. When the rates of the candidate exceed the thresholds of the rollout, after a
+//! minimum number of samples, the rollout is rolled back: all requests are routed to the
+//! stable agent until a manager sets the percentage again.
+//!
+//! ```rust,ignore
+//! let rollout = Rollout::new("assistant", "assistant_v2", 10)
+//!     .with_max_error_rate(0.05, 50)
+//!     .with_max_negative_feedback_rate(0.2, 20);
+//! let engine = EngineBuilder::new()
+//!     .register_agent(assistant)?
+//!     .register_agent(assistant_v2)?
+//!     .with_rollout(rollout)
+//!     .build("assistant".to_string())
+//!     .await?;
+//! ```
+
+use anda_core::{AgentOutput, BoxError, Xid};
+use candid::Principal;
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use structured_logger::unix_ms;
+
+/// The runs and feedback of a version of an agent.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VersionStats {
+    pub runs: u64,
+    /// The runs that failed or returned a failed reason.
+    pub errors: u64,
+    pub feedback: u64,
+    /// The feedback with a negative score.
+    pub negative_feedback: u64,
+}
+
+impl VersionStats {
+    /// Returns the ratio of failed runs, 0 without runs.
+    pub fn error_rate(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.runs as f64
+        }
+    }
+
+    /// Returns the ratio of negative feedback, 0 without feedback.
+    pub fn negative_feedback_rate(&self) -> f64 {
+        if self.feedback == 0 {
+            0.0
+        } else {
+            self.negative_feedback as f64 / self.feedback as f64
+        }
+    }
+}
+
+/// The status of a rollout.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RolloutStatus {
+    pub stable: String,
+    pub candidate: String,
+    /// The percentage of the requests routed to the candidate, 0 after a rollback.
+    pub percent: u8,
+    pub stable_stats: VersionStats,
+    pub candidate_stats: VersionStats,
+    /// Why the rollout was rolled back, if it was.
+    pub rolled_back: Option<String>,
+    /// When the rollout was rolled back, unix timestamp in milliseconds.
+    pub rolled_back_at: Option<u64>,
+}
+
+/// Splits the traffic of an agent between its stable and candidate versions, and rolls
+/// back to the stable version when the candidate is unhealthy.
+pub struct Rollout {
+    stable: String,
+    candidate: String,
+    max_error_rate: f64,
+    min_runs: u64,
+    max_negative_feedback_rate: f64,
+    min_feedback: u64,
+    state: Mutex<RolloutState>,
+    /// The threads answered by each version, true for the candidate, to attribute feedback.
+    threads: Cache<String, bool>,
+}
+
+struct RolloutState {
+    percent: u8,
+    stable: VersionStats,
+    candidate: VersionStats,
+    rolled_back: Option<(String, u64)>,
+}
+
+impl Rollout {
+    /// Creates a rollout that routes `percent` percent of the requests for the stable agent
+    /// to the candidate agent. Rolls back when more than 10% of at least 20 runs fail, or
+    /// when more than 30% of at least 10 feedback are negative.
+    pub fn new(stable: &str, candidate: &str, percent: u8) -> Self {
+        Self {
+            stable: stable.to_ascii_lowercase(),
+            candidate: candidate.to_ascii_lowercase(),
+            max_error_rate: 0.1,
+            min_runs: 20,
+            max_negative_feedback_rate: 0.3,
+            min_feedback: 10,
+            state: Mutex::new(RolloutState {
+                percent: percent.min(100),
+                stable: VersionStats::default(),
+                candidate: VersionStats::default(),
+                rolled_back: None,
+            }),
+            threads: Cache::new(100_000),
+        }
+    }
+
+    /// Sets the error rate of the candidate above which the rollout is rolled back, once
+    /// the candidate has at least `min_runs` runs.
+    pub fn with_max_error_rate(mut self, rate: f64, min_runs: u64) -> Self {
+        self.max_error_rate = rate;
+        self.min_runs = min_runs.max(1);
+        self
+    }
+
+    /// Sets the negative feedback rate of the candidate above which the rollout is rolled
+    /// back, once the candidate has at least `min_feedback` feedback.
+    pub fn with_max_negative_feedback_rate(mut self, rate: f64, min_feedback: u64) -> Self {
+        self.max_negative_feedback_rate = rate;
+        self.min_feedback = min_feedback.max(1);
+        self
+    }
+
+    /// Returns the name of the stable agent.
+    pub fn stable(&self) -> &str {
+        &self.stable
+    }
+
+    /// Returns the name of the candidate agent.
+    pub fn candidate(&self) -> &str {
+        &self.candidate
+    }
+
+    /// Returns the agent that serves a user, identified by the caller and the user of the
+    /// request, if any.
+    pub fn route(&self, caller: &Principal, user: Option<&str>) -> &str {
+        let percent = {
+            let state = self.state.lock().unwrap();
+            if state.rolled_back.is_some() {
+                0
+            } else {
+                state.percent
+            }
+        };
+        if percent > 0 && bucket(&self.stable, caller, user) < percent {
+            &self.candidate
+        } else {
+            &self.stable
+        }
+    }
+
+    /// Records the result of a run of the agent, one of the two versions, and rolls back
+    /// if the candidate's error rate exceeds the threshold.
+    pub async fn record_run(&self, agent: &str, res: &Result<AgentOutput, BoxError>) {
+        let is_candidate = agent == self.candidate;
+        if !is_candidate && agent != self.stable {
+            return;
+        }
+        {
+            let mut state = self.state.lock().unwrap();
+            let stats = if is_candidate {
+                &mut state.candidate
+            } else {
+                &mut state.stable
+            };
+            stats.runs += 1;
+            if !matches!(res, Ok(output) if output.failed_reason.is_none()) {
+                stats.errors += 1;
+            }
+            self.check(&mut state);
+        }
+        if let Ok(AgentOutput {
+            thread: Some(thread),
+            ..
+        }) = res
+        {
+            self.threads.insert(thread.to_string(), is_candidate).await;
+        }
+    }
+
+    /// Records a feedback on a thread answered by one of the two versions, and rolls back
+    /// if the candidate's negative feedback rate exceeds the threshold. Returns false if the
+    /// thread was not answered by this rollout.
+    pub async fn record_feedback(&self, thread: &Xid, score: i8) -> bool {
+        let Some(is_candidate) = self.threads.get(&thread.to_string()).await else {
+            return false;
+        };
+        let mut state = self.state.lock().unwrap();
+        let stats = if is_candidate {
+            &mut state.candidate
+        } else {
+            &mut state.stable
+        };
+        stats.feedback += 1;
+        if score < 0 {
+            stats.negative_feedback += 1;
+        }
+        self.check(&mut state);
+        true
+    }
+
+    /// Sets the percentage of the requests routed to the candidate. This resumes a rolled
+    /// back rollout and resets the stats of both versions.
+    pub fn set_percent(&self, percent: u8) {
+        let mut state = self.state.lock().unwrap();
+        state.percent = percent.min(100);
+        state.stable = VersionStats::default();
+        state.candidate = VersionStats::default();
+        state.rolled_back = None;
+    }
+
+    /// Returns the status of the rollout.
+    pub fn status(&self) -> RolloutStatus {
+        let state = self.state.lock().unwrap();
+        RolloutStatus {
+            stable: self.stable.clone(),
+            candidate: self.candidate.clone(),
+            percent: if state.rolled_back.is_some() {
+                0
+            } else {
+                state.percent
+            },
+            stable_stats: state.stable.clone(),
+            candidate_stats: state.candidate.clone(),
+            rolled_back: state.rolled_back.as_ref().map(|(reason, _)| reason.clone()),
+            rolled_back_at: state.rolled_back.as_ref().map(|(_, at)| *at),
+        }
+    }
+
+    fn check(&self, state: &mut RolloutState) {
+        if state.rolled_back.is_some() {
+            return;
+        }
+        let stats = &state.candidate;
+        let reason = if stats.runs >= self.min_runs && stats.error_rate() > self.max_error_rate {
+            format!(
+                "error rate {:.3} exceeds {:.3}",
+                stats.error_rate(),
+                self.max_error_rate
+            )
+        } else if stats.feedback >= self.min_feedback
+            && stats.negative_feedback_rate() > self.max_negative_feedback_rate
+        {
+            format!(
+                "negative feedback rate {:.3} exceeds {:.3}",
+                stats.negative_feedback_rate(),
+                self.max_negative_feedback_rate
+            )
+        } else {
+            return;
+        };
+        log::warn!(
+            agent = self.stable,
+            candidate = self.candidate;
+            "rollout rolled back: {}", reason
+        );
+        state.rolled_back = Some((reason, unix_ms()));
+    }
+}
+
+/// Returns the bucket of a user in [0, 100), salted by the stable agent so that the
+/// rollouts of different agents select different users.
+fn bucket(stable: &str, caller: &Principal, user: Option<&str>) -> u8 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(stable.as_bytes());
+    hasher.update(&[0]);
+    hasher.update(caller.as_slice());
+    if let Some(user) = user {
+        hasher.update(&[0]);
+        hasher.update(user.as_bytes());
+    }
+    let hash = hasher.finalize();
+    let n = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap());
+    (n % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(thread: Option<Xid>, failed: bool) -> Result<AgentOutput, BoxError> {
+        Ok(AgentOutput {
+            thread,
+            failed_reason: failed.then(|| "failed".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_route() {
+        let rollout = Rollout::new("Assistant", "assistant_v2", 30);
+        let callers: Vec<Principal> = (0..1000u32)
+            .map(|i| Principal::from_slice(&i.to_be_bytes()))
+            .collect();
+        let candidates = callers
+            .iter()
+            .filter(|c| rollout.route(c, None) == "assistant_v2")
+            .count();
+        assert!((230..370).contains(&candidates), "{candidates}");
+
+        // routing is sticky
+        for caller in &callers[..50] {
+            assert_eq!(rollout.route(caller, None), rollout.route(caller, None));
+        }
+        let caller = callers[0];
+        let users: Vec<&str> = (0..100)
+            .map(|i| rollout.route(&caller, Some(&format!("telegram:{i}"))))
+            .collect();
+        assert!(users.contains(&"assistant") && users.contains(&"assistant_v2"));
+
+        rollout.set_percent(0);
+        assert!(
+            callers
+                .iter()
+                .all(|c| rollout.route(c, None) == "assistant")
+        );
+        rollout.set_percent(100);
+        assert!(
+            callers
+                .iter()
+                .all(|c| rollout.route(c, None) == "assistant_v2")
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_rollback() {
+        let rollout = Rollout::new("assistant", "assistant_v2", 100).with_max_error_rate(0.2, 5);
+        for _ in 0..4 {
+            rollout
+                .record_run("assistant_v2", &output(None, true))
+                .await;
+        }
+        // not enough runs yet
+        assert_eq!(rollout.status().rolled_back, None);
+        rollout.record_run("assistant", &output(None, true)).await;
+        rollout.record_run("other", &output(None, true)).await;
+        assert_eq!(rollout.status().stable_stats.runs, 1);
+
+        rollout
+            .record_run("assistant_v2", &Err("model error".into()))
+            .await;
+        let status = rollout.status();
+        assert_eq!(status.percent, 0);
+        assert_eq!(status.candidate_stats.runs, 5);
+        assert_eq!(status.candidate_stats.errors, 5);
+        assert_eq!(
+            status.rolled_back.as_deref(),
+            Some("error rate 1.000 exceeds 0.200")
+        );
+        assert!(status.rolled_back_at.is_some());
+        assert_eq!(rollout.route(&Principal::anonymous(), None), "assistant");
+
+        rollout.set_percent(50);
+        let status = rollout.status();
+        assert_eq!(status.percent, 50);
+        assert_eq!(status.rolled_back, None);
+        assert_eq!(status.candidate_stats, VersionStats::default());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_feedback_rollback() {
+        let rollout =
+            Rollout::new("assistant", "assistant_v2", 50).with_max_negative_feedback_rate(0.5, 3);
+        let stable_thread = Xid::new();
+        let candidate_thread = Xid::new();
+        rollout
+            .record_run("assistant", &output(Some(stable_thread.clone()), false))
+            .await;
+        rollout
+            .record_run(
+                "assistant_v2",
+                &output(Some(candidate_thread.clone()), false),
+            )
+            .await;
+
+        assert!(!rollout.record_feedback(&Xid::new(), -1).await);
+        for _ in 0..5 {
+            assert!(rollout.record_feedback(&stable_thread, -1).await);
+        }
+        assert!(rollout.record_feedback(&candidate_thread, 1).await);
+        assert!(rollout.record_feedback(&candidate_thread, -1).await);
+        assert_eq!(rollout.status().rolled_back, None);
+        assert!(rollout.record_feedback(&candidate_thread, -1).await);
+
+        let status = rollout.status();
+        assert_eq!(status.stable_stats.negative_feedback, 5);
+        assert_eq!(status.candidate_stats.feedback, 3);
+        assert_eq!(
+            status.rolled_back.as_deref(),
+            Some("negative feedback rate 0.667 exceeds 0.500")
+        );
+    }
+}
@@ -0,0 +1,696 @@
+//! In-memory vector store and knowledge store.
+//!
+//! They need no storage engine, for unit tests and small agents, e.g. in TEEs:
+//! - [`InMemoryKnowledgeStore`] implements [`KnowledgeFeatures`] and [`VectorSearchFeatures`]
+//!   with a brute-force cosine search over all documents. It can be persisted to a single
+//!   file, rewritten after each change;
+//! - [`InMemoryVectorStore`] implements [`VectorSearchFeaturesDyn`], a namespace being a
+//!   knowledge store.
+//!
+//! Keyword searches rank the documents by the number of query words they contain, and the
+//! hybrid mode fuses the vector and keyword rankings by [`reciprocal_rank_fusion`].
+//! With the `multi_vector` feature, a knowledge store can also store several vectors per
+//! document and rerank its vector candidates by late interaction, see [`super::multi_vector`].
+//!
+//! ```rust,ignore
+//! let store = InMemoryKnowledgeStore::open("knowledge.cbor", 384, Some(embedder))?;
+//! store.knowledge_add(docs).await?;
+//! let docs = store.knowledge_search("how to deploy", 5, &filter).await?;
+//! ```
+
+use anda_core::{
+    BoxError, BoxPinFut, Knowledge, KnowledgeFeatures, KnowledgeFilter, KnowledgeInput, Path,
+    RRF_K, SearchMode, VectorSearchFeatures, Xid, reciprocal_rank_fusion,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+#[cfg(feature = "multi_vector")]
+use super::multi_vector::{MultiVectorFeaturesDyn, late_interaction_score};
+use super::{VectorSearchFeaturesDyn, similarity::cosine_similarity};
+use crate::{model::EmbeddingFeaturesDyn, unix_ms};
+
+/// The number of candidates fetched by each search of the hybrid mode, per result.
+const HYBRID_CANDIDATES_FACTOR: usize = 3;
+
+/// The number of candidates reranked by late interaction, per result.
+#[cfg(feature = "multi_vector")]
+const LATE_INTERACTION_CANDIDATES_FACTOR: usize = 4;
+
+/// A document with its embedding.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Doc {
+    knowledge: Knowledge,
+    vec: Vec<f32>,
+    /// The vectors for late interaction, if the store has a multi-vector embedder.
+    #[cfg(feature = "multi_vector")]
+    #[serde(default)]
+    vecs: Vec<Vec<f32>>,
+    /// The creation time in unix milliseconds.
+    created_at: u64,
+}
+
+/// The content of the file of a store.
+#[derive(Deserialize, Serialize)]
+struct Snapshot {
+    dim: usize,
+    docs: Vec<Doc>,
+}
+
+/// A knowledge store that holds its documents and their vectors in memory.
+pub struct InMemoryKnowledgeStore {
+    docs: RwLock<Vec<Doc>>,
+    dim: usize,
+    embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
+    #[cfg(feature = "multi_vector")]
+    multi_vector: Option<Arc<dyn MultiVectorFeaturesDyn>>,
+    path: Option<PathBuf>,
+}
+
+impl InMemoryKnowledgeStore {
+    /// Creates an empty store of vectors of `dim` dimensions. Searches need the embedder,
+    /// except in the keyword mode.
+    pub fn new(dim: usize, embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>) -> Self {
+        Self {
+            docs: RwLock::new(Vec::new()),
+            dim,
+            embedder,
+            #[cfg(feature = "multi_vector")]
+            multi_vector: None,
+            path: None,
+        }
+    }
+
+    /// Stores the vectors of the multi-vector embedder with the documents added from now on,
+    /// and reranks the candidates of vector searches by their late-interaction score.
+    /// Documents added without them are scored by their single vector.
+    #[cfg(feature = "multi_vector")]
+    pub fn with_multi_vector(mut self, embedder: Arc<dyn MultiVectorFeaturesDyn>) -> Self {
+        self.multi_vector = Some(embedder);
+        self
+    }
+
+    /// Opens a store persisted to the file, loading its documents if it exists.
+    /// The file is rewritten atomically after each change.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        dim: usize,
+        embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
+    ) -> Result<Self, BoxError> {
+        let path = path.into();
+        let mut store = Self::new(dim, embedder);
+        if path.exists() {
+            let data = fs::read(&path)?;
+            let snapshot: Snapshot = ciborium::from_reader(&data[..])?;
+            if snapshot.dim != dim {
+                return Err(format!(
+                    "invalid dimensions of {}, expected {}, got {}",
+                    path.display(),
+                    dim,
+                    snapshot.dim
+                )
+                .into());
+            }
+            store.docs = RwLock::new(snapshot.docs);
+        }
+        store.path = Some(path);
+        Ok(store)
+    }
+
+    pub fn len(&self) -> usize {
+        self.docs.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the default search mode: hybrid if the store has an embedder, keyword otherwise.
+    pub fn default_search_mode(&self) -> SearchMode {
+        if self.embedder.is_some() {
+            SearchMode::Hybrid
+        } else {
+            SearchMode::Keyword
+        }
+    }
+
+    /// Searches the top n knowledge documents matching the filter, in the given mode.
+    pub async fn knowledge_search_with(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+        mode: SearchMode,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        if query.is_empty() {
+            return Ok(self.latest(n, filter));
+        }
+
+        match mode {
+            SearchMode::Vector => self.vector_search(query, n, filter).await,
+            SearchMode::Keyword => Ok(self.keyword_search(query, n, filter)),
+            SearchMode::Hybrid => {
+                let candidates = n.saturating_mul(HYBRID_CANDIDATES_FACTOR);
+                let mut docs: HashMap<String, Knowledge> = HashMap::new();
+                let rankings: Vec<Vec<String>> = [
+                    self.vector_search(query, candidates, filter).await?,
+                    self.keyword_search(query, candidates, filter),
+                ]
+                .into_iter()
+                .map(|list| {
+                    list.into_iter()
+                        .map(|doc| {
+                            let id = doc.id.clone();
+                            docs.insert(id.clone(), doc);
+                            id
+                        })
+                        .collect()
+                })
+                .collect();
+                Ok(reciprocal_rank_fusion(&rankings, RRF_K)
+                    .into_iter()
+                    .take(n)
+                    .filter_map(|(id, _)| docs.remove(&id))
+                    .collect())
+            }
+        }
+    }
+
+    /// Returns the n documents most similar to the vector that match the filter.
+    pub fn search_by_vector(
+        &self,
+        vec: &[f32],
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> Vec<Knowledge> {
+        self.top_by(n, filter, |doc| Some(cosine_similarity(vec, &doc.vec)))
+    }
+
+    /// Deletes the documents that match the filter, all documents if it is empty.
+    /// Returns the number of deleted documents.
+    pub fn knowledge_delete(&self, filter: &KnowledgeFilter) -> Result<usize, BoxError> {
+        let mut docs = self.docs.write().unwrap();
+        let len = docs.len();
+        docs.retain(|doc| !filter.matches(&doc.knowledge));
+        let deleted = len - docs.len();
+        if deleted > 0 {
+            self.persist(&docs)?;
+        }
+        Ok(deleted)
+    }
+
+    /// Returns the number of documents that match the filter.
+    pub fn knowledge_count(&self, filter: &KnowledgeFilter) -> usize {
+        let docs = self.docs.read().unwrap();
+        docs.iter()
+            .filter(|doc| filter.matches(&doc.knowledge))
+            .count()
+    }
+
+    async fn vector_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        let vec = self.embed_query(query).await?;
+        #[cfg(feature = "multi_vector")]
+        if let Some(multi_vector) = &self.multi_vector {
+            let candidates = self.search_by_vector(
+                &vec,
+                n.saturating_mul(LATE_INTERACTION_CANDIDATES_FACTOR),
+                filter,
+            );
+            let (query_vecs, _) = multi_vector.embed_query_multi(query.to_string()).await?;
+            return Ok(self.rerank_by_late_interaction(&query_vecs, candidates, n));
+        }
+        Ok(self.search_by_vector(&vec, n, filter))
+    }
+
+    /// Returns the n candidates with the highest late-interaction scores, in the order of
+    /// the candidates for equal scores.
+    #[cfg(feature = "multi_vector")]
+    fn rerank_by_late_interaction(
+        &self,
+        query_vecs: &[Vec<f32>],
+        candidates: Vec<Knowledge>,
+        n: usize,
+    ) -> Vec<Knowledge> {
+        let docs = self.docs.read().unwrap();
+        let by_id: HashMap<&str, &Doc> = docs
+            .iter()
+            .map(|doc| (doc.knowledge.id.as_str(), doc))
+            .collect();
+        let mut scored: Vec<(f32, Knowledge)> = candidates
+            .into_iter()
+            .map(|knowledge| {
+                let score = by_id.get(knowledge.id.as_str()).map_or(f32::MIN, |doc| {
+                    if doc.vecs.is_empty() {
+                        late_interaction_score(query_vecs, std::slice::from_ref(&doc.vec))
+                    } else {
+                        late_interaction_score(query_vecs, &doc.vecs)
+                    }
+                });
+                (score, knowledge)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+            .into_iter()
+            .take(n)
+            .map(|(_, knowledge)| knowledge)
+            .collect()
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>, BoxError> {
+        let embedder = self
+            .embedder
+            .as_ref()
+            .ok_or("vector search requires an embedder")?;
+        let (embedding, _) = embedder.embed_query(query.to_string()).await?;
+        Ok(embedding.vec)
+    }
+
+    fn keyword_search(&self, query: &str, n: usize, filter: &KnowledgeFilter) -> Vec<Knowledge> {
+        let words: Vec<String> = query.split_whitespace().map(|w| w.to_lowercase()).collect();
+        self.top_by(n, filter, |doc| {
+            let text = doc.knowledge.text.to_lowercase();
+            let hits = words.iter().filter(|w| text.contains(w.as_str())).count();
+            (hits > 0).then_some(hits as f32)
+        })
+    }
+
+    fn latest(&self, n: usize, filter: &KnowledgeFilter) -> Vec<Knowledge> {
+        self.top_by(n, filter, |_| Some(0.0))
+    }
+
+    /// Returns the n documents that match the filter with the highest scores, newer ones
+    /// first for equal scores, skipping the ones without score.
+    fn top_by(
+        &self,
+        n: usize,
+        filter: &KnowledgeFilter,
+        score: impl Fn(&Doc) -> Option<f32>,
+    ) -> Vec<Knowledge> {
+        let docs = self.docs.read().unwrap();
+        let mut scored: Vec<(f32, &Doc)> = docs
+            .iter()
+            .filter(|doc| filter.matches(&doc.knowledge))
+            .filter_map(|doc| score(doc).map(|s| (s, doc)))
+            .collect();
+        scored.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then_with(|| b.1.created_at.cmp(&a.1.created_at))
+        });
+        scored
+            .into_iter()
+            .take(n)
+            .map(|(_, doc)| doc.knowledge.clone())
+            .collect()
+    }
+
+    /// Rewrites the file of the store, if any, with a temporary file renamed over it.
+    fn persist(&self, docs: &[Doc]) -> Result<(), BoxError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut data = Vec::new();
+        ciborium::into_writer(
+            &Snapshot {
+                dim: self.dim,
+                docs: docs.to_vec(),
+            },
+            &mut data,
+        )?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+impl VectorSearchFeatures for InMemoryKnowledgeStore {
+    async fn top_n(&self, query: &str, n: usize) -> Result<Vec<String>, BoxError> {
+        let docs = self
+            .knowledge_search_with(
+                query,
+                n,
+                &KnowledgeFilter::default(),
+                self.default_search_mode(),
+            )
+            .await?;
+        Ok(docs.into_iter().map(|doc| doc.text).collect())
+    }
+
+    async fn top_n_ids(&self, query: &str, n: usize) -> Result<Vec<String>, BoxError> {
+        let docs = self
+            .knowledge_search_with(
+                query,
+                n,
+                &KnowledgeFilter::default(),
+                self.default_search_mode(),
+            )
+            .await?;
+        Ok(docs.into_iter().map(|doc| doc.id).collect())
+    }
+}
+
+impl KnowledgeFeatures for InMemoryKnowledgeStore {
+    async fn knowledge_top_n(
+        &self,
+        query: &str,
+        n: usize,
+        user: Option<String>,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        let filter = KnowledgeFilter {
+            user,
+            ..Default::default()
+        };
+        self.knowledge_search(query, n, &filter).await
+    }
+
+    async fn knowledge_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        self.knowledge_search_with(query, n, filter, self.default_search_mode())
+            .await
+    }
+
+    async fn knowledge_latest_n(
+        &self,
+        last_seconds: u32,
+        n: usize,
+        user: Option<String>,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        if last_seconds == 0 || n == 0 {
+            return Ok(vec![]);
+        }
+        let filter = KnowledgeFilter {
+            user,
+            created_after: Some(unix_ms().saturating_sub(last_seconds as u64 * 1000)),
+            ..Default::default()
+        };
+        Ok(self.latest(n, &filter))
+    }
+
+    async fn knowledge_add(&self, docs: Vec<KnowledgeInput>) -> Result<(), BoxError> {
+        let docs: Vec<KnowledgeInput> = docs.into_iter().map(|doc| doc.normalize()).collect();
+        if let Some(doc) = docs.iter().find(|doc| doc.vec.len() != self.dim) {
+            return Err(format!(
+                "invalid vector length, expected {}, got {}",
+                self.dim,
+                doc.vec.len()
+            )
+            .into());
+        }
+        #[cfg(feature = "multi_vector")]
+        let mut multi_vecs = match &self.multi_vector {
+            Some(multi_vector) => {
+                let texts = docs.iter().map(|doc| doc.text.clone()).collect();
+                multi_vector.embed_multi(texts).await?.0.into_iter()
+            }
+            None => Vec::new().into_iter(),
+        };
+        let mut added: Vec<Doc> = Vec::with_capacity(docs.len());
+        for doc in docs {
+            added.push(Doc {
+                knowledge: Knowledge {
+                    id: Xid::new().to_string(),
+                    user: doc.user.to_ascii_lowercase(),
+                    text: doc.text,
+                    meta: doc.meta,
+                },
+                vec: doc.vec,
+                #[cfg(feature = "multi_vector")]
+                vecs: multi_vecs.next().unwrap_or_default(),
+                created_at: unix_ms(),
+            });
+        }
+
+        let mut docs = self.docs.write().unwrap();
+        docs.extend(added);
+        self.persist(&docs)
+    }
+}
+
+/// A [`VectorSearchFeaturesDyn`] over in-memory knowledge stores, one per namespace,
+/// searched by the vectors of the queries embedded with the embedder.
+#[derive(Clone)]
+pub struct InMemoryVectorStore {
+    stores: Arc<RwLock<BTreeMap<Path, Arc<InMemoryKnowledgeStore>>>>,
+    embedder: Arc<dyn EmbeddingFeaturesDyn>,
+}
+
+impl InMemoryVectorStore {
+    pub fn new(embedder: Arc<dyn EmbeddingFeaturesDyn>) -> Self {
+        Self {
+            stores: Arc::new(RwLock::new(BTreeMap::new())),
+            embedder,
+        }
+    }
+
+    /// Returns the knowledge store of a namespace, created if it does not exist.
+    pub fn knowledge_store(&self, namespace: &Path) -> Arc<InMemoryKnowledgeStore> {
+        if let Some(store) = self.stores.read().unwrap().get(namespace) {
+            return store.clone();
+        }
+        self.stores
+            .write()
+            .unwrap()
+            .entry(namespace.clone())
+            .or_insert_with(|| {
+                Arc::new(InMemoryKnowledgeStore::new(
+                    self.embedder.ndims(),
+                    Some(self.embedder.clone()),
+                ))
+            })
+            .clone()
+    }
+
+    /// Adds a knowledge store for a namespace, e.g. one persisted to a file.
+    pub fn with_knowledge_store(self, namespace: Path, store: Arc<InMemoryKnowledgeStore>) -> Self {
+        self.stores.write().unwrap().insert(namespace, store);
+        self
+    }
+}
+
+impl VectorSearchFeaturesDyn for InMemoryVectorStore {
+    fn top_n(
+        &self,
+        namespace: Path,
+        query: String,
+        n: usize,
+    ) -> BoxPinFut<Result<Vec<String>, BoxError>> {
+        let store = self.knowledge_store(&namespace);
+        Box::pin(async move { VectorSearchFeatures::top_n(store.as_ref(), &query, n).await })
+    }
+
+    fn top_n_ids(
+        &self,
+        namespace: Path,
+        query: String,
+        n: usize,
+    ) -> BoxPinFut<Result<Vec<String>, BoxError>> {
+        let store = self.knowledge_store(&namespace);
+        Box::pin(async move { VectorSearchFeatures::top_n_ids(store.as_ref(), &query, n).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::MockImplemented;
+    use anda_core::META_TAGS;
+    use serde_json::json;
+
+    fn input(user: &str, text: &str, vec: Vec<f32>) -> KnowledgeInput {
+        KnowledgeInput {
+            user: user.to_string(),
+            text: text.to_string(),
+            meta: BTreeMap::from([(META_TAGS.to_string(), json!(["rust"]))]),
+            vec,
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_in_memory_knowledge_store() {
+        let path = std::env::temp_dir().join(format!("anda_knowledge_{}.cbor", Xid::new()));
+        let store = InMemoryKnowledgeStore::open(&path, 3, None).unwrap();
+        store
+            .knowledge_add(vec![
+                input(
+                    "Alice",
+                    "Anda is an AI agent framework",
+                    vec![1.0, 0.0, 0.0],
+                ),
+                input(
+                    "bob",
+                    "Rust is a systems programming language",
+                    vec![0.0, 1.0, 0.0],
+                ),
+                input(
+                    "alice",
+                    "Anda agents are written in Rust",
+                    vec![0.7, 0.7, 0.0],
+                ),
+            ])
+            .await
+            .unwrap();
+        assert!(
+            store
+                .knowledge_add(vec![input("a", "b", vec![1.0])])
+                .await
+                .is_err()
+        );
+        assert_eq!(store.len(), 3);
+
+        let docs = store.search_by_vector(&[1.0, 0.1, 0.0], 2, &KnowledgeFilter::default());
+        assert_eq!(docs[0].text, "Anda is an AI agent framework");
+        assert_eq!(docs[1].text, "Anda agents are written in Rust");
+
+        // keyword search ranks by the number of query words
+        let docs = store
+            .knowledge_search("anda rust", 3, &KnowledgeFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[0].text, "Anda agents are written in Rust");
+
+        let filter = KnowledgeFilter {
+            user: Some("ALICE".to_string()),
+            tags: vec!["rust".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(store.knowledge_count(&filter), 2);
+        let docs = store
+            .knowledge_top_n("rust", 5, Some("bob".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].user, "bob");
+        let docs = store.knowledge_latest_n(60, 5, None).await.unwrap();
+        assert_eq!(docs.len(), 3);
+
+        // persisted to the file
+        let reopened = InMemoryKnowledgeStore::open(&path, 3, None).unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert!(InMemoryKnowledgeStore::open(&path, 4, None).is_err());
+        assert_eq!(reopened.knowledge_delete(&filter).unwrap(), 2);
+        let reopened = InMemoryKnowledgeStore::open(&path, 3, None).unwrap();
+        assert_eq!(reopened.len(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_in_memory_vector_store() {
+        let store = InMemoryVectorStore::new(Arc::new(MockImplemented));
+        let ns = Path::from("anda/knowledge");
+        let knowledge = store.knowledge_store(&ns);
+        knowledge
+            .knowledge_add(vec![input("alice", "Anda", vec![0.0; 384])])
+            .await
+            .unwrap();
+        assert_eq!(store.knowledge_store(&ns).len(), 1);
+        let texts = store
+            .top_n(ns.clone(), "anda".to_string(), 5)
+            .await
+            .unwrap();
+        assert_eq!(texts, vec!["Anda".to_string()]);
+        let ids = store.top_n_ids(ns, "anda".to_string(), 5).await.unwrap();
+        assert_eq!(ids.len(), 1);
+        assert!(
+            store
+                .top_n(Path::from("other"), "anda".to_string(), 5)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[cfg(feature = "multi_vector")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_late_interaction_search() {
+        use crate::store::multi_vector::SegmentedEmbedder;
+        use anda_core::{BoxPinFut, Embedding, Usage};
+
+        /// Embeds a text by the keywords it contains.
+        struct KeywordEmbedder;
+
+        fn keyword_vec(text: &str) -> Vec<f32> {
+            let text = text.to_lowercase();
+            ["canister", "cycles", "panda"]
+                .iter()
+                .map(|w| if text.contains(w) { 1.0 } else { 0.0 })
+                .collect()
+        }
+
+        impl EmbeddingFeaturesDyn for KeywordEmbedder {
+            fn ndims(&self) -> usize {
+                3
+            }
+
+            fn embed(
+                &self,
+                texts: Vec<String>,
+            ) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+                let embeddings = texts
+                    .into_iter()
+                    .map(|text| Embedding {
+                        vec: keyword_vec(&text),
+                        text,
+                    })
+                    .collect();
+                Box::pin(futures::future::ready(Ok((embeddings, Usage::default()))))
+            }
+
+            fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+                let embedding = Embedding {
+                    vec: keyword_vec(&text),
+                    text,
+                };
+                Box::pin(futures::future::ready(Ok((embedding, Usage::default()))))
+            }
+        }
+
+        let docs = vec![
+            input(
+                "alice",
+                "canister upgrade steps. canister memory limits. cycles fuel compute.",
+                vec![0.9, 0.3, 0.0],
+            ),
+            input("alice", "pandas eat bamboo", vec![0.5, 0.6, 0.6]),
+        ];
+        let embedder = Arc::new(KeywordEmbedder);
+        let single = InMemoryKnowledgeStore::new(3, Some(embedder.clone()));
+        single.knowledge_add(docs.clone()).await.unwrap();
+        let multi = InMemoryKnowledgeStore::new(3, Some(embedder.clone()))
+            .with_multi_vector(Arc::new(SegmentedEmbedder::new(embedder, 3)));
+        multi.knowledge_add(docs).await.unwrap();
+
+        let filter = KnowledgeFilter::default();
+        let found = single
+            .knowledge_search_with("cycles fuel", 1, &filter, SearchMode::Vector)
+            .await
+            .unwrap();
+        assert_eq!(found[0].text, "pandas eat bamboo");
+        // the passage about cycles matches the query
+        let found = multi
+            .knowledge_search_with("cycles fuel", 2, &filter, SearchMode::Vector)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found[0].text.starts_with("canister upgrade"));
+        assert_eq!(multi.docs.read().unwrap()[0].vecs.len(), 3);
+    }
+}
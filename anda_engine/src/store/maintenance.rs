@@ -0,0 +1,354 @@
+//! Scheduled maintenance of stores.
+//!
+//! Stores accumulate garbage over time: append-only logs keep the entries of replaced and
+//! deleted items, and vector indexes keep the rows of deleted documents until they are
+//! rebuilt. A [`MaintenanceScheduler`] runs [`MaintenanceJob`]s periodically:
+//! - each job runs only when it reports work to do, see [`MaintenanceJob::is_due`];
+//! - jobs run one at a time, in the batch lane of the engine's scheduler, so interactive
+//!   requests are admitted first;
+//! - jobs process items in batches with a pause in between, see [`Throttle`];
+//! - progress and results are recorded in [`MaintenanceStats`], exposed in the engine metrics.
+//!
+//! ```rust,ignore
+//! let index = Arc::new(MmapVectorIndex::open("./data/index", 384)?);
+//! let maintenance = MaintenanceScheduler::new(Duration::from_secs(600))
+//!     .with_throttle(Throttle::new(1000, Duration::from_millis(10)))
+//!     .with_job(Arc::new(VectorIndexCompaction::new(index, 0.2)));
+//! let engine = EngineBuilder::new().with_maintenance(maintenance).build(agent).await?;
+//! ```
+
+use anda_core::{BoxError, BoxPinFut, RequestPriority};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use structured_logger::unix_ms;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::MmapVectorIndex;
+use crate::context::Scheduler;
+
+/// Limits the pace of a maintenance job: it processes `batch_size` items at a time and
+/// pauses between batches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Throttle {
+    pub batch_size: usize,
+    pub pause: Duration,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new(1000, Duration::from_millis(10))
+    }
+}
+
+impl Throttle {
+    /// Creates a throttle.
+    pub fn new(batch_size: usize, pause: Duration) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            pause,
+        }
+    }
+
+    /// Returns a throttle that processes all items at once.
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX, Duration::ZERO)
+    }
+
+    /// Pauses between batches, on the blocking thread of the job.
+    pub fn pause_blocking(&self) {
+        if !self.pause.is_zero() {
+            std::thread::sleep(self.pause);
+        }
+    }
+}
+
+/// The progress of a running job, updated by the job.
+#[derive(Clone, Debug, Default)]
+pub struct MaintenanceProgress {
+    processed: Arc<AtomicU64>,
+    total: Arc<AtomicU64>,
+}
+
+impl MaintenanceProgress {
+    /// Sets the number of items to process, and resets the processed items.
+    pub fn start(&self, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+        self.processed.store(0, Ordering::Relaxed);
+    }
+
+    /// Records processed items.
+    pub fn advance(&self, n: u64) {
+        self.processed.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Returns the processed and total items.
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.processed.load(Ordering::Relaxed),
+            self.total.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// The progress and results of a maintenance job.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MaintenanceStats {
+    pub name: String,
+
+    /// Whether the job is running.
+    pub running: bool,
+
+    /// The processed and total items of the running or last run.
+    pub processed: u64,
+    pub total: u64,
+
+    pub runs: u64,
+    pub failures: u64,
+
+    /// The total number of reclaimed items, e.g. deleted rows removed from an index.
+    pub reclaimed: u64,
+
+    /// When the last run started, unix timestamp in milliseconds.
+    #[serde(default)]
+    pub last_run_at: Option<u64>,
+
+    pub last_duration_ms: u64,
+
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// A maintenance job of a store.
+pub trait MaintenanceJob: Send + Sync + 'static {
+    /// The name of the job, unique in the scheduler.
+    fn name(&self) -> String;
+
+    /// Returns true if the job has work to do.
+    fn is_due(&self) -> bool;
+
+    /// Runs the job and returns the number of reclaimed items.
+    fn run(
+        &self,
+        throttle: Throttle,
+        progress: MaintenanceProgress,
+    ) -> BoxPinFut<Result<u64, BoxError>>;
+}
+
+/// Compacts a [`MmapVectorIndex`] when the ratio of deleted rows reaches a threshold:
+/// the deleted and replaced rows are vacuumed, and the vector file and the log are rewritten.
+/// The index stays searchable and writable during the compaction.
+pub struct VectorIndexCompaction {
+    index: Arc<MmapVectorIndex>,
+    min_deleted_ratio: f32,
+}
+
+impl VectorIndexCompaction {
+    /// Creates a compaction job of the index.
+    pub fn new(index: Arc<MmapVectorIndex>, min_deleted_ratio: f32) -> Self {
+        Self {
+            index,
+            min_deleted_ratio,
+        }
+    }
+}
+
+impl MaintenanceJob for VectorIndexCompaction {
+    fn name(&self) -> String {
+        format!("vector_index_compaction:{}", self.index.dir().display())
+    }
+
+    fn is_due(&self) -> bool {
+        let ratio = self.index.deleted_ratio();
+        ratio > 0.0 && ratio >= self.min_deleted_ratio
+    }
+
+    fn run(
+        &self,
+        throttle: Throttle,
+        progress: MaintenanceProgress,
+    ) -> BoxPinFut<Result<u64, BoxError>> {
+        let index = self.index.clone();
+        Box::pin(async move {
+            let n =
+                tokio::task::spawn_blocking(move || index.compact_throttled(throttle, &progress))
+                    .await??;
+            Ok(n as u64)
+        })
+    }
+}
+
+/// Runs maintenance jobs periodically.
+pub struct MaintenanceScheduler {
+    interval: Duration,
+    throttle: Throttle,
+    jobs: Vec<(Arc<dyn MaintenanceJob>, RwLock<MaintenanceStats>)>,
+    scheduler: Option<Arc<Scheduler>>,
+}
+
+impl MaintenanceScheduler {
+    /// Creates a scheduler that checks the jobs every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            throttle: Throttle::default(),
+            jobs: Vec::new(),
+            scheduler: None,
+        }
+    }
+
+    /// Sets the throttle of the jobs.
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Adds a job.
+    pub fn with_job(mut self, job: Arc<dyn MaintenanceJob>) -> Self {
+        let stats = MaintenanceStats {
+            name: job.name(),
+            ..Default::default()
+        };
+        self.jobs.push((job, RwLock::new(stats)));
+        self
+    }
+
+    /// Runs the jobs in the batch lane of the scheduler, set by the engine.
+    pub fn with_scheduler(mut self, scheduler: Arc<Scheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Returns the stats of the jobs.
+    pub fn stats(&self) -> Vec<MaintenanceStats> {
+        self.jobs
+            .iter()
+            .map(|(_, stats)| stats.read().unwrap().clone())
+            .collect()
+    }
+
+    /// Runs the due jobs one by one. Returns the number of jobs that ran.
+    pub async fn run_due(&self) -> usize {
+        let mut n = 0;
+        for (job, stats) in &self.jobs {
+            if !job.is_due() {
+                continue;
+            }
+            let _permit = match &self.scheduler {
+                Some(scheduler) => Some(scheduler.acquire(RequestPriority::Batch).await),
+                None => None,
+            };
+
+            let progress = MaintenanceProgress::default();
+            let started_at = unix_ms();
+            {
+                let mut stats = stats.write().unwrap();
+                stats.running = true;
+                stats.last_run_at = Some(started_at);
+            }
+            let res = {
+                let run = job.run(self.throttle, progress.clone());
+                tokio::pin!(run);
+                // refresh the progress in the stats while the job runs
+                let mut ticker = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    tokio::select! {
+                        res = &mut run => break res,
+                        _ = ticker.tick() => {
+                            let (processed, total) = progress.get();
+                            let mut stats = stats.write().unwrap();
+                            stats.processed = processed;
+                            stats.total = total;
+                        }
+                    }
+                }
+            };
+
+            let (processed, total) = progress.get();
+            let mut stats = stats.write().unwrap();
+            stats.running = false;
+            stats.processed = processed;
+            stats.total = total;
+            stats.runs += 1;
+            stats.last_duration_ms = unix_ms().saturating_sub(started_at);
+            match res {
+                Ok(reclaimed) => {
+                    stats.reclaimed += reclaimed;
+                    stats.last_error = None;
+                    log::info!(job = stats.name; "maintenance reclaimed {} items", reclaimed);
+                }
+                Err(err) => {
+                    stats.failures += 1;
+                    stats.last_error = Some(err.to_string());
+                    log::error!(job = stats.name; "maintenance failed: {}", err);
+                }
+            }
+            n += 1;
+        }
+        n
+    }
+
+    /// Spawns a background task that runs the due jobs every interval, until the token
+    /// is cancelled.
+    pub fn spawn(self: Arc<Self>, token: CancellationToken) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => return,
+                    _ = tokio::time::sleep(self.interval) => {}
+                }
+                tokio::select! {
+                    _ = token.cancelled() => return,
+                    _ = self.run_due() => {}
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rand_number;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_maintenance_scheduler() {
+        let dir = std::env::temp_dir().join(format!("anda_maint_{}", rand_number(0..u64::MAX)));
+        let index = Arc::new(MmapVectorIndex::open(&dir, 2).unwrap());
+        index
+            .add_batch(
+                (0..10)
+                    .map(|i| (format!("d{}", i), vec![1.0, i as f32]))
+                    .collect(),
+            )
+            .unwrap();
+
+        let maintenance = MaintenanceScheduler::new(Duration::from_secs(60))
+            .with_throttle(Throttle::new(3, Duration::from_millis(1)))
+            .with_job(Arc::new(VectorIndexCompaction::new(index.clone(), 0.3)));
+        assert_eq!(maintenance.run_due().await, 0);
+
+        for i in 0..4 {
+            index.remove(&format!("d{}", i)).unwrap();
+        }
+        assert_eq!(maintenance.run_due().await, 1);
+        let stats = &maintenance.stats()[0];
+        assert!(!stats.running);
+        assert_eq!(stats.runs, 1);
+        assert_eq!(stats.reclaimed, 4);
+        assert_eq!((stats.processed, stats.total), (6, 6));
+        assert_eq!(index.deleted_ratio(), 0.0);
+        assert_eq!(index.len(), 6);
+        assert_eq!(index.get("d9"), Some(vec![1.0, 9.0]));
+        assert_eq!(maintenance.run_due().await, 0);
+
+        drop(index);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
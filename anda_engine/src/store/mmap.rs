@@ -0,0 +1,851 @@
+//! Memory-mapped on-disk vector index.
+//!
+//! [`MmapVectorIndex`] keeps vectors in a flat file that is memory-mapped for searching,
+//! so knowledge bases larger than RAM can be searched locally with bounded memory:
+//! only the IDs are held in memory, the vectors are paged in by the OS on demand.
+//!
+//! ## On-disk layout
+//!
+//! An index directory contains:
+//! - `CURRENT`: the generation number of the live files;
+//! - `{generation}.vec`: a header (magic and dimensions) followed by fixed-size `f32` rows;
+//! - `{generation}.log`: an append-only log of add/delete entries, each with a CRC32 checksum.
+//!
+//! ## Crash safety
+//!
+//! Vectors are written and synced before their log entries, so a log entry is the commit point.
+//! On open, a torn or corrupted log tail is truncated and uncommitted vectors are discarded.
+//! Compaction writes a new generation and switches to it by atomically replacing `CURRENT`.
+
+use anda_core::BoxError;
+use memmap2::Mmap;
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    maintenance::{MaintenanceProgress, Throttle},
+    similarity::cosine_similarity,
+};
+
+const MAGIC: &[u8; 8] = b"ANDAVEC1";
+const HEADER_SIZE: usize = 16;
+const OP_ADD: u8 = 1;
+const OP_DELETE: u8 = 2;
+const CURRENT_FILE: &str = "CURRENT";
+
+/// A memory-mapped, append-only vector index with background compaction.
+pub struct MmapVectorIndex {
+    dir: PathBuf,
+    ndims: usize,
+    inner: RwLock<IndexInner>,
+}
+
+struct IndexInner {
+    generation: u64,
+    vec_file: File,
+    log_file: File,
+    mmap: Option<Mmap>,
+    /// row -> id, empty if the row was deleted
+    ids: Vec<String>,
+    /// live id -> row
+    rows: HashMap<String, usize>,
+    deleted: usize,
+}
+
+impl MmapVectorIndex {
+    /// Opens the index in the directory, creating it if it does not exist.
+    /// Recovers from an interrupted write or compaction.
+    ///
+    /// # Arguments
+    /// * `dir` - The directory of the index;
+    /// * `ndims` - The number of dimensions of the vectors.
+    pub fn open(dir: impl AsRef<Path>, ndims: usize) -> Result<Self, BoxError> {
+        if ndims == 0 {
+            return Err("ndims must be greater than 0".into());
+        }
+
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let generation = match fs::read_to_string(dir.join(CURRENT_FILE)) {
+            Ok(s) => s
+                .trim()
+                .parse::<u64>()
+                .map_err(|err| format!("invalid CURRENT file: {}", err))?,
+            Err(_) => {
+                let (vec_file, log_file) = create_generation(&dir, 0, ndims)?;
+                drop((vec_file, log_file));
+                write_current(&dir, 0)?;
+                0
+            }
+        };
+
+        remove_stale_generations(&dir, generation);
+        let inner = IndexInner::load(&dir, generation, ndims)?;
+        Ok(Self {
+            dir,
+            ndims,
+            inner: RwLock::new(inner),
+        })
+    }
+
+    /// Returns the number of dimensions of the vectors.
+    pub fn ndims(&self) -> usize {
+        self.ndims
+    }
+
+    /// Returns the directory of the index.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the number of live vectors.
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().rows.len()
+    }
+
+    /// Returns true if the index contains no live vectors.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the ratio of deleted rows to all rows, used to decide when to compact.
+    pub fn deleted_ratio(&self) -> f32 {
+        let inner = self.inner.read().unwrap();
+        if inner.ids.is_empty() {
+            0.0
+        } else {
+            inner.deleted as f32 / inner.ids.len() as f32
+        }
+    }
+
+    /// Returns true if the index contains the ID.
+    pub fn contains(&self, id: &str) -> bool {
+        self.inner.read().unwrap().rows.contains_key(id)
+    }
+
+    /// Retrieves the vector of the ID.
+    pub fn get(&self, id: &str) -> Option<Vec<f32>> {
+        let inner = self.inner.read().unwrap();
+        let row = *inner.rows.get(id)?;
+        let mut buf = vec![0f32; self.ndims];
+        inner.read_row(row, self.ndims, &mut buf);
+        Some(buf)
+    }
+
+    /// Adds or replaces a vector.
+    pub fn add(&self, id: String, vec: Vec<f32>) -> Result<(), BoxError> {
+        self.add_batch(vec![(id, vec)])
+    }
+
+    /// Adds or replaces vectors in a batch with a single sync and remap, which is much
+    /// faster than adding them one by one.
+    pub fn add_batch(&self, items: Vec<(String, Vec<f32>)>) -> Result<(), BoxError> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        for (id, vec) in &items {
+            if id.is_empty() || id.len() > u16::MAX as usize {
+                return Err(format!("invalid id length: {}", id.len()).into());
+            }
+            if vec.len() != self.ndims {
+                return Err(format!(
+                    "invalid vector dimensions for {}, expected {}, got {}",
+                    id,
+                    self.ndims,
+                    vec.len()
+                )
+                .into());
+            }
+        }
+
+        let mut data = Vec::with_capacity(items.len() * self.ndims * 4);
+        for (_, vec) in &items {
+            for v in vec {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        // adding an existing ID implicitly deletes its previous row
+        let mut log = Vec::new();
+        for (id, _) in &items {
+            encode_entry(&mut log, OP_ADD, id);
+        }
+
+        let mut inner = self.inner.write().unwrap();
+        let vec_len = inner.vec_file.metadata()?.len();
+        let log_len = inner.log_file.metadata()?.len();
+        if let Err(err) = inner.append(&data, &log) {
+            // the rows of the vector file must match the log entries for the next writes
+            if let Err(err) = inner.truncate(vec_len, log_len) {
+                log::error!(dir = self.dir.display().to_string(); "failed to roll back a write: {}", err);
+            }
+            return Err(err);
+        }
+
+        for (id, _) in items {
+            inner.apply_delete(&id);
+            let row = inner.ids.len();
+            inner.rows.insert(id.clone(), row);
+            inner.ids.push(id);
+        }
+        inner.remap()?;
+        Ok(())
+    }
+
+    /// Removes a vector. Returns false if the ID does not exist.
+    /// The space is reclaimed by [`MmapVectorIndex::compact`].
+    pub fn remove(&self, id: &str) -> Result<bool, BoxError> {
+        let mut inner = self.inner.write().unwrap();
+        if !inner.rows.contains_key(id) {
+            return Ok(false);
+        }
+
+        let mut log = Vec::new();
+        encode_entry(&mut log, OP_DELETE, id);
+        let vec_len = inner.vec_file.metadata()?.len();
+        let log_len = inner.log_file.metadata()?.len();
+        if let Err(err) = inner.append(&[], &log) {
+            // a torn log entry would corrupt the entries of the next writes
+            if let Err(err) = inner.truncate(vec_len, log_len) {
+                log::error!(dir = self.dir.display().to_string(); "failed to roll back a write: {}", err);
+            }
+            return Err(err);
+        }
+        inner.apply_delete(id);
+        Ok(true)
+    }
+
+    /// Finds the top N vectors most similar to the query by cosine similarity.
+    /// Returns the IDs and scores, in descending order of score.
+    pub fn search(&self, query: &[f32], n: usize) -> Result<Vec<(String, f32)>, BoxError> {
+        if query.len() != self.ndims {
+            return Err(format!(
+                "invalid query dimensions, expected {}, got {}",
+                self.ndims,
+                query.len()
+            )
+            .into());
+        }
+        if n == 0 {
+            return Ok(vec![]);
+        }
+
+        let inner = self.inner.read().unwrap();
+        let mut heap: BinaryHeap<Scored> = BinaryHeap::with_capacity(n + 1);
+        let mut buf = vec![0f32; self.ndims];
+        for (row, id) in inner.ids.iter().enumerate() {
+            if id.is_empty() {
+                continue;
+            }
+            inner.read_row(row, self.ndims, &mut buf);
+            let score = cosine_similarity(query, &buf);
+            if heap.len() < n {
+                heap.push(Scored(score, row));
+            } else if let Some(min) = heap.peek()
+                && score > min.0
+            {
+                heap.pop();
+                heap.push(Scored(score, row));
+            }
+        }
+
+        Ok(heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Scored(score, row)| (inner.ids[row].clone(), score))
+            .collect())
+    }
+
+    /// Rewrites the index without deleted rows into a new generation.
+    /// Returns the number of reclaimed rows.
+    pub fn compact(&self) -> Result<usize, BoxError> {
+        self.compact_throttled(Throttle::unlimited(), &MaintenanceProgress::default())
+    }
+
+    /// Rewrites the index without deleted rows into a new generation, copying the live rows
+    /// in batches under the read lock, so that the index stays searchable and writable.
+    /// The rows added, replaced or removed during the copy are applied under the write lock
+    /// when switching to the new generation. Returns the number of reclaimed rows.
+    pub fn compact_throttled(
+        &self,
+        throttle: Throttle,
+        progress: &MaintenanceProgress,
+    ) -> Result<usize, BoxError> {
+        let (generation, snapshot) = {
+            let inner = self.inner.read().unwrap();
+            if inner.deleted == 0 {
+                return Ok(0);
+            }
+            let snapshot: Vec<(usize, String)> = inner
+                .ids
+                .iter()
+                .enumerate()
+                .filter(|(_, id)| !id.is_empty())
+                .map(|(row, id)| (row, id.clone()))
+                .collect();
+            (inner.generation, snapshot)
+        };
+        progress.start(snapshot.len() as u64);
+
+        let next = generation + 1;
+        let (mut vec_file, mut log_file) = create_generation(&self.dir, next, self.ndims)?;
+        let res = self.copy_and_switch(
+            generation,
+            &snapshot,
+            &mut vec_file,
+            &mut log_file,
+            throttle,
+            progress,
+        );
+        drop((vec_file, log_file));
+        if res.is_err() {
+            remove_generation(&self.dir, next);
+        }
+        res
+    }
+
+    fn copy_and_switch(
+        &self,
+        generation: u64,
+        snapshot: &[(usize, String)],
+        vec_file: &mut File,
+        log_file: &mut File,
+        throttle: Throttle,
+        progress: &MaintenanceProgress,
+    ) -> Result<usize, BoxError> {
+        let mut buf = vec![0f32; self.ndims];
+        // the copied rows, in the order of the new generation
+        let mut copied: Vec<(usize, &str)> = Vec::with_capacity(snapshot.len());
+        for (i, batch) in snapshot.chunks(throttle.batch_size).enumerate() {
+            if i > 0 {
+                throttle.pause_blocking();
+            }
+            let mut data = Vec::with_capacity(batch.len() * self.ndims * 4);
+            {
+                let inner = self.inner.read().unwrap();
+                if inner.generation != generation {
+                    return Err("the index was compacted concurrently".into());
+                }
+                for (row, id) in batch {
+                    // skip the rows removed or replaced since the snapshot
+                    if inner.rows.get(id) != Some(row) {
+                        continue;
+                    }
+                    inner.read_row(*row, self.ndims, &mut buf);
+                    for v in &buf {
+                        data.extend_from_slice(&v.to_le_bytes());
+                    }
+                    copied.push((*row, id));
+                }
+            }
+            vec_file.write_all(&data)?;
+            progress.advance(batch.len() as u64);
+        }
+
+        let mut inner = self.inner.write().unwrap();
+        if inner.generation != generation {
+            return Err("the index was compacted concurrently".into());
+        }
+        let mut data = Vec::new();
+        let mut log = Vec::new();
+        for (row, id) in &copied {
+            encode_entry(&mut log, OP_ADD, id);
+            if inner.rows.get(*id) != Some(row) {
+                encode_entry(&mut log, OP_DELETE, id);
+            }
+        }
+        // the rows added during the copy
+        let last = snapshot.last().map(|(row, _)| row + 1).unwrap_or(0);
+        for (row, id) in inner.ids.iter().enumerate().skip(last) {
+            if id.is_empty() {
+                continue;
+            }
+            inner.read_row(row, self.ndims, &mut buf);
+            for v in &buf {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+            encode_entry(&mut log, OP_ADD, id);
+        }
+        vec_file.write_all(&data)?;
+        vec_file.sync_all()?;
+        log_file.write_all(&log)?;
+        log_file.sync_all()?;
+
+        // switching CURRENT is the commit point of the compaction
+        write_current(&self.dir, generation + 1)?;
+        let deleted = inner.deleted;
+        *inner = IndexInner::load(&self.dir, generation + 1, self.ndims)?;
+        remove_generation(&self.dir, generation);
+        // the rows removed during the copy are still in the new generation
+        Ok(deleted - inner.deleted)
+    }
+
+    /// Spawns a background task that compacts the index when the ratio of deleted rows
+    /// reaches `min_deleted_ratio`, checked every `interval`, until the token is cancelled.
+    pub fn spawn_compaction(
+        self: Arc<Self>,
+        interval: Duration,
+        min_deleted_ratio: f32,
+        token: CancellationToken,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => return,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+
+                if self.deleted_ratio() < min_deleted_ratio {
+                    continue;
+                }
+                let index = self.clone();
+                match tokio::task::spawn_blocking(move || index.compact()).await {
+                    Ok(Ok(n)) => {
+                        log::info!(dir = self.dir.display().to_string(); "compacted {} rows", n)
+                    }
+                    Ok(Err(err)) => {
+                        log::error!(dir = self.dir.display().to_string(); "compaction failed: {}", err)
+                    }
+                    Err(err) => {
+                        log::error!(dir = self.dir.display().to_string(); "compaction task failed: {}", err)
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl IndexInner {
+    fn load(dir: &Path, generation: u64, ndims: usize) -> Result<Self, BoxError> {
+        let (vec_path, log_path) = generation_paths(dir, generation);
+        let mut vec_file = OpenOptions::new().read(true).write(true).open(&vec_path)?;
+        let mut log_file = OpenOptions::new().read(true).write(true).open(&log_path)?;
+
+        let mut header = [0u8; HEADER_SIZE];
+        vec_file.read_exact(&mut header)?;
+        if &header[0..8] != MAGIC {
+            return Err(format!("invalid vector file {}", vec_path.display()).into());
+        }
+        let file_ndims = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        if file_ndims != ndims {
+            return Err(format!(
+                "vector dimensions mismatch, expected {}, got {}",
+                ndims, file_ndims
+            )
+            .into());
+        }
+
+        // replay the log, stop at the first torn or corrupted entry
+        let mut log = Vec::new();
+        log_file.read_to_end(&mut log)?;
+        let mut ids: Vec<String> = Vec::new();
+        let mut rows: HashMap<String, usize> = HashMap::new();
+        let mut deleted = 0;
+        let mut offset = 0;
+        while let Some((op, id, size)) = decode_entry(&log[offset..]) {
+            match op {
+                OP_ADD => {
+                    if let Some(prev) = rows.insert(id.clone(), ids.len()) {
+                        ids[prev].clear();
+                        deleted += 1;
+                    }
+                    ids.push(id);
+                }
+                OP_DELETE => {
+                    if let Some(row) = rows.remove(&id) {
+                        ids[row].clear();
+                        deleted += 1;
+                    }
+                }
+                _ => break,
+            }
+            offset += size;
+        }
+        if offset < log.len() {
+            log::warn!(file = log_path.display().to_string(); "truncating {} bytes of torn log", log.len() - offset);
+            log_file.set_len(offset as u64)?;
+            log_file.sync_all()?;
+        }
+        log_file.seek(SeekFrom::End(0))?;
+
+        let expected = (HEADER_SIZE + ids.len() * ndims * 4) as u64;
+        let actual = vec_file.metadata()?.len();
+        match actual.cmp(&expected) {
+            Ordering::Less => {
+                return Err(format!(
+                    "vector file {} is corrupted, expected {} bytes, got {}",
+                    vec_path.display(),
+                    expected,
+                    actual
+                )
+                .into());
+            }
+            Ordering::Greater => {
+                // vectors written without committed log entries
+                vec_file.set_len(expected)?;
+                vec_file.sync_all()?;
+            }
+            Ordering::Equal => {}
+        }
+
+        let mut inner = Self {
+            generation,
+            vec_file,
+            log_file,
+            mmap: None,
+            ids,
+            rows,
+            deleted,
+        };
+        inner.remap()?;
+        Ok(inner)
+    }
+
+    /// Appends the vectors, then their log entries, which commit them.
+    fn append(&mut self, data: &[u8], log: &[u8]) -> Result<(), BoxError> {
+        // vectors are written first, they are not visible until the log entries are committed
+        self.vec_file.seek(SeekFrom::End(0))?;
+        self.vec_file.write_all(data)?;
+        self.vec_file.sync_data()?;
+        self.log_file.write_all(log)?;
+        self.log_file.sync_data()?;
+        Ok(())
+    }
+
+    /// Truncates the files to their lengths before a failed append.
+    fn truncate(&mut self, vec_len: u64, log_len: u64) -> Result<(), BoxError> {
+        self.vec_file.set_len(vec_len)?;
+        self.log_file.set_len(log_len)?;
+        self.log_file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    fn remap(&mut self) -> Result<(), BoxError> {
+        self.mmap = if self.ids.is_empty() {
+            None
+        } else {
+            // Safety: the file is only appended to or truncated while holding the write lock,
+            // and is remapped right after.
+            Some(unsafe { Mmap::map(&self.vec_file)? })
+        };
+        Ok(())
+    }
+
+    fn apply_delete(&mut self, id: &str) {
+        if let Some(row) = self.rows.remove(id) {
+            self.ids[row].clear();
+            self.deleted += 1;
+        }
+    }
+
+    fn read_row(&self, row: usize, ndims: usize, buf: &mut [f32]) {
+        let mmap = self.mmap.as_ref().expect("vector file should be mapped");
+        let start = HEADER_SIZE + row * ndims * 4;
+        let data = &mmap[start..start + ndims * 4];
+        for (v, b) in buf.iter_mut().zip(data.chunks_exact(4)) {
+            *v = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+    }
+}
+
+#[derive(PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// reversed, so that BinaryHeap is a min-heap on score
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(self.1.cmp(&other.1))
+    }
+}
+
+fn encode_entry(buf: &mut Vec<u8>, op: u8, id: &str) {
+    let start = buf.len();
+    buf.push(op);
+    buf.extend_from_slice(&(id.len() as u16).to_le_bytes());
+    buf.extend_from_slice(id.as_bytes());
+    let crc = crc32fast::hash(&buf[start..]);
+    buf.extend_from_slice(&crc.to_le_bytes());
+}
+
+/// Decodes a log entry, returns None if it is incomplete or corrupted.
+fn decode_entry(data: &[u8]) -> Option<(u8, String, usize)> {
+    if data.len() < 3 {
+        return None;
+    }
+    let len = u16::from_le_bytes([data[1], data[2]]) as usize;
+    let size = 3 + len + 4;
+    if data.len() < size {
+        return None;
+    }
+    let crc = u32::from_le_bytes(data[3 + len..size].try_into().unwrap());
+    if crc32fast::hash(&data[..3 + len]) != crc {
+        return None;
+    }
+    let id = String::from_utf8(data[3..3 + len].to_vec()).ok()?;
+    Some((data[0], id, size))
+}
+
+fn generation_paths(dir: &Path, generation: u64) -> (PathBuf, PathBuf) {
+    (
+        dir.join(format!("{:020}.vec", generation)),
+        dir.join(format!("{:020}.log", generation)),
+    )
+}
+
+fn create_generation(dir: &Path, generation: u64, ndims: usize) -> Result<(File, File), BoxError> {
+    let (vec_path, log_path) = generation_paths(dir, generation);
+    let mut vec_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(vec_path)?;
+    let mut header = [0u8; HEADER_SIZE];
+    header[0..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&(ndims as u32).to_le_bytes());
+    vec_file.write_all(&header)?;
+    vec_file.sync_all()?;
+
+    let log_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(log_path)?;
+    log_file.sync_all()?;
+    Ok((vec_file, log_file))
+}
+
+fn write_current(dir: &Path, generation: u64) -> Result<(), BoxError> {
+    let tmp = dir.join(format!("{}.tmp", CURRENT_FILE));
+    let mut file = File::create(&tmp)?;
+    file.write_all(generation.to_string().as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, dir.join(CURRENT_FILE))?;
+    // persist the rename
+    if let Ok(d) = File::open(dir) {
+        let _ = d.sync_all();
+    }
+    Ok(())
+}
+
+fn remove_generation(dir: &Path, generation: u64) {
+    let (vec_path, log_path) = generation_paths(dir, generation);
+    let _ = fs::remove_file(vec_path);
+    let _ = fs::remove_file(log_path);
+}
+
+/// Removes files left by an interrupted compaction.
+fn remove_stale_generations(dir: &Path, current: u64) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if let Some(generation) = name
+                .strip_suffix(".vec")
+                .or_else(|| name.strip_suffix(".log"))
+                .and_then(|s| s.parse::<u64>().ok())
+                && generation != current
+            {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rand_number;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("anda_mmap_{}", rand_number(0..u64::MAX)))
+    }
+
+    #[test]
+    fn test_mmap_vector_index() {
+        let dir = temp_dir();
+        let index = MmapVectorIndex::open(&dir, 3).unwrap();
+        assert!(index.is_empty());
+        assert!(index.search(&[1.0, 0.0, 0.0], 2).unwrap().is_empty());
+        assert!(index.add("x".to_string(), vec![1.0, 0.0]).is_err());
+
+        index
+            .add_batch(vec![
+                ("a".to_string(), vec![1.0, 0.0, 0.0]),
+                ("b".to_string(), vec![0.0, 1.0, 0.0]),
+                ("c".to_string(), vec![0.9, 0.1, 0.0]),
+            ])
+            .unwrap();
+        assert_eq!(index.len(), 3);
+        let res = index.search(&[1.0, 0.0, 0.0], 2).unwrap();
+        assert_eq!(res[0].0, "a");
+        assert_eq!(res[1].0, "c");
+
+        // replace and remove
+        index.add("a".to_string(), vec![0.0, 0.0, 1.0]).unwrap();
+        assert_eq!(index.get("a"), Some(vec![0.0, 0.0, 1.0]));
+        assert!(index.remove("b").unwrap());
+        assert!(!index.remove("b").unwrap());
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.deleted_ratio(), 0.5);
+        let res = index.search(&[1.0, 0.0, 0.0], 3).unwrap();
+        assert_eq!(
+            res.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(),
+            vec!["c", "a"]
+        );
+        drop(index);
+
+        // reopen
+        let index = MmapVectorIndex::open(&dir, 3).unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get("a"), Some(vec![0.0, 0.0, 1.0]));
+        assert!(index.get("b").is_none());
+        assert!(MmapVectorIndex::open(&dir, 4).is_err());
+
+        // compact
+        assert_eq!(index.compact().unwrap(), 2);
+        assert_eq!(index.deleted_ratio(), 0.0);
+        assert_eq!(index.get("c"), Some(vec![0.9, 0.1, 0.0]));
+        drop(index);
+        let index = MmapVectorIndex::open(&dir, 3).unwrap();
+        assert_eq!(index.len(), 2);
+        let res = index.search(&[1.0, 0.0, 0.0], 1).unwrap();
+        assert_eq!(res[0].0, "c");
+        drop(index);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mmap_vector_index_compact_throttled() {
+        let dir = temp_dir();
+        let index = Arc::new(MmapVectorIndex::open(&dir, 2).unwrap());
+        index
+            .add_batch(
+                (0..100)
+                    .map(|i| (format!("d{}", i), vec![1.0, i as f32]))
+                    .collect(),
+            )
+            .unwrap();
+        for i in 0..50 {
+            index.remove(&format!("d{}", i)).unwrap();
+        }
+
+        // writes during the compaction are kept
+        let progress = MaintenanceProgress::default();
+        let handle = {
+            let index = index.clone();
+            let progress = progress.clone();
+            std::thread::spawn(move || {
+                index.compact_throttled(Throttle::new(5, Duration::from_millis(2)), &progress)
+            })
+        };
+        for i in 50..60 {
+            index.remove(&format!("d{}", i)).unwrap();
+            index.add(format!("e{}", i), vec![2.0, i as f32]).unwrap();
+        }
+        index.add("d99".to_string(), vec![3.0, 0.0]).unwrap();
+        assert!(handle.join().unwrap().unwrap() >= 50);
+        // a write may be in progress at the snapshot, between a removal and an add
+        let (done, total) = progress.get();
+        assert_eq!(done, total);
+        assert!((49..=51).contains(&total), "{total}");
+
+        let check = |index: &MmapVectorIndex| {
+            assert_eq!(index.len(), 50);
+            assert!(!index.contains("d55"));
+            assert_eq!(index.get("d60"), Some(vec![1.0, 60.0]));
+            assert_eq!(index.get("e55"), Some(vec![2.0, 55.0]));
+            assert_eq!(index.get("d99"), Some(vec![3.0, 0.0]));
+        };
+        check(&index);
+        drop(index);
+        let index = MmapVectorIndex::open(&dir, 2).unwrap();
+        check(&index);
+        drop(index);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mmap_vector_index_recovery() {
+        let dir = temp_dir();
+        let index = MmapVectorIndex::open(&dir, 2).unwrap();
+        index.add("a".to_string(), vec![1.0, 0.0]).unwrap();
+        let generation = index.inner.read().unwrap().generation;
+        drop(index);
+
+        // simulate a crash: uncommitted vector and torn log entry
+        let (vec_path, log_path) = generation_paths(&dir, generation);
+        let mut f = OpenOptions::new().append(true).open(&vec_path).unwrap();
+        f.write_all(&[0u8; 8]).unwrap();
+        let mut f = OpenOptions::new().append(true).open(&log_path).unwrap();
+        let mut entry = Vec::new();
+        encode_entry(&mut entry, OP_ADD, "b");
+        f.write_all(&entry[..entry.len() - 1]).unwrap();
+        // simulate an interrupted compaction
+        create_generation(&dir, generation + 1, 2).unwrap();
+
+        let index = MmapVectorIndex::open(&dir, 2).unwrap();
+        assert_eq!(index.len(), 1);
+        assert!(!index.contains("b"));
+        assert!(!generation_paths(&dir, generation + 1).0.exists());
+        index.add("b".to_string(), vec![0.0, 1.0]).unwrap();
+        drop(index);
+
+        let index = MmapVectorIndex::open(&dir, 2).unwrap();
+        assert_eq!(index.get("b"), Some(vec![0.0, 1.0]));
+
+        // simulate a failed log write: the vector is written, the log entry is torn
+        {
+            let mut inner = index.inner.write().unwrap();
+            let vec_len = inner.vec_file.metadata().unwrap().len();
+            let log_len = inner.log_file.metadata().unwrap().len();
+            let mut entry = Vec::new();
+            encode_entry(&mut entry, OP_ADD, "x");
+            inner.append(&[0u8; 8], &entry[..entry.len() - 1]).unwrap();
+            inner.truncate(vec_len, log_len).unwrap();
+        }
+        index.add("c".to_string(), vec![1.0, 1.0]).unwrap();
+        assert_eq!(index.get("c"), Some(vec![1.0, 1.0]));
+
+        // simulate a failed delete: the log entry is torn
+        {
+            let mut inner = index.inner.write().unwrap();
+            let vec_len = inner.vec_file.metadata().unwrap().len();
+            let log_len = inner.log_file.metadata().unwrap().len();
+            let mut entry = Vec::new();
+            encode_entry(&mut entry, OP_DELETE, "a");
+            inner.append(&[], &entry[..entry.len() - 1]).unwrap();
+            inner.truncate(vec_len, log_len).unwrap();
+        }
+        assert!(index.remove("b").unwrap());
+        drop(index);
+
+        let index = MmapVectorIndex::open(&dir, 2).unwrap();
+        assert_eq!(index.len(), 2);
+        assert!(index.contains("a"));
+        assert!(!index.contains("b"));
+        assert_eq!(index.get("c"), Some(vec![1.0, 1.0]));
+        drop(index);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -8,6 +8,14 @@
 //! - **Store**: Main storage interface that handles object storage operations
 //! - **VectorStore**: Wrapper for vector search functionality
 //! - **VectorSearchFeaturesDyn**: Trait defining vector search capabilities
+//! - **InMemoryVectorStore** and **InMemoryKnowledgeStore**: In-memory vector and knowledge stores for tests and small agents
+//! - **MmapVectorIndex**: Memory-mapped on-disk vector index for local knowledge bases
+//! - **QdrantVectorStore** and **QdrantKnowledgeStore**: Vector and knowledge stores backed by Qdrant
+//! - **MaintenanceScheduler**: Scheduled, throttled compaction and vacuum of stores
+//! - **Counters** and **Leaderboard**: Concurrency-safe counters and sorted sets with batched persistence
+//! - **TimeSeriesStore**: Time series of measurements with downsampling and range queries
+//! - **similarity**: SIMD-accelerated vector similarity scoring
+//! - **multi_vector**: Multi-vector late-interaction retrieval, behind the `multi_vector` feature
 //!
 //! ## Features
 //!
@@ -34,12 +42,47 @@ use anda_core::{BoxError, BoxPinFut, ObjectMeta, Path, PutMode, PutResult, path_
 use futures::TryStreamExt;
 use object_store::PutOptions;
 use std::io::Read;
+
+use crate::redaction::Redactor;
 use std::sync::Arc;
 
 pub use object_store::{ObjectStore, local::LocalFileSystem, memory::InMemory};
 
+pub mod in_memory;
+pub mod maintenance;
+pub mod mmap;
+#[cfg(feature = "multi_vector")]
+pub mod multi_vector;
+pub mod qdrant;
+pub mod similarity;
+pub mod tally;
+pub mod timeseries;
+
+pub use in_memory::{InMemoryKnowledgeStore, InMemoryVectorStore};
+pub use maintenance::{MaintenanceJob, MaintenanceScheduler, MaintenanceStats};
+pub use mmap::MmapVectorIndex;
+pub use qdrant::{QdrantClient, QdrantKnowledgeStore, QdrantVectorStore};
+pub use tally::{Counters, Leaderboard};
+pub use timeseries::{Aggregation, DataPoint, TimeSeriesStore};
+
 pub const MAX_STORE_OBJECT_SIZE: usize = 1024 * 1024 * 2; // 2 MB
 
+/// Returns true if the error of a store operation is a missing object.
+pub(crate) fn is_not_found(err: &BoxError) -> bool {
+    matches!(
+        err.downcast_ref::<object_store::Error>(),
+        Some(object_store::Error::NotFound { .. })
+    )
+}
+
+/// Returns true if the error of a conditional write is a conflict with another write.
+pub(crate) fn is_conflict(err: &BoxError) -> bool {
+    matches!(
+        err.downcast_ref::<object_store::Error>(),
+        Some(object_store::Error::Precondition { .. } | object_store::Error::AlreadyExists { .. })
+    )
+}
+
 /// Trait defining vector search capabilities
 pub trait VectorSearchFeaturesDyn: Send + Sync + 'static {
     /// Find top N similar items based on query string
@@ -163,11 +206,21 @@ impl VectorSearchFeaturesDyn for MockImplemented {
 #[derive(Clone)]
 pub struct Store {
     store: Arc<dyn ObjectStore>,
+    redactor: Option<Redactor>,
 }
 
 impl Store {
     pub fn new(store: Arc<dyn ObjectStore>) -> Self {
-        Self { store }
+        Self {
+            store,
+            redactor: None,
+        }
+    }
+
+    /// Redacts secrets from every object before it is written to the backend.
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
     }
 
     /// Retrieves data from storage at the specified path
@@ -231,6 +284,10 @@ impl Store {
         val: bytes::Bytes,
     ) -> Result<PutResult, BoxError> {
         let path = path_lowercase(&namespace.child(path.as_ref()));
+        let val = match &self.redactor {
+            Some(redactor) => redactor.redact_bytes(val),
+            None => val,
+        };
         let res = self
             .store
             .put_opts(
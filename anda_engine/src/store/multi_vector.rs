@@ -0,0 +1,197 @@
+//! Multi-vector (late-interaction) retrieval, enabled by the `multi_vector` feature.
+//!
+//! A single vector per chunk blurs long technical documents, where a query usually matches
+//! one passage of a chunk. With late interaction, in the style of ColBERT, a chunk is stored
+//! with several vectors, and a query, also embedded as several vectors, is scored against it
+//! by [`late_interaction_score`]: the mean, over the query vectors, of their best cosine
+//! similarity with the vectors of the chunk. Precision improves at the cost of storing every
+//! vector of every chunk, hence the feature flag.
+//!
+//! Vectors come from a [`MultiVectorFeaturesDyn`], e.g. a ColBERT model that returns token
+//! vectors, or a [`SegmentedEmbedder`], which embeds the passages of a text with an
+//! ordinary embedder. A knowledge store with [`InMemoryKnowledgeStore::with_multi_vector`]
+//! stores the vectors of the documents it adds, and reranks the candidates of its vector
+//! searches by their late-interaction score.
+//!
+//! ```rust,ignore
+//! let multi = Arc::new(SegmentedEmbedder::new(embedder.clone(), 48));
+//! let store = InMemoryKnowledgeStore::new(384, Some(embedder)).with_multi_vector(multi);
+//! ```
+//!
+//! [`InMemoryKnowledgeStore::with_multi_vector`]: super::InMemoryKnowledgeStore::with_multi_vector
+
+use anda_core::{BoxError, BoxPinFut, Usage};
+use std::sync::Arc;
+
+use super::similarity::cosine_similarity;
+use crate::model::{EmbedBatchOptions, EmbeddingFeaturesDyn, embed_batch};
+
+/// The default maximum number of vectors of a text.
+pub const DEFAULT_MAX_VECTORS: usize = 32;
+
+/// Trait for embedders that embed a text as several vectors.
+pub trait MultiVectorFeaturesDyn: Send + Sync + 'static {
+    /// Returns the number of dimensions of the vectors.
+    fn ndims(&self) -> usize;
+
+    /// Embeds multiple texts, returning the vectors of each text in the order of the texts.
+    fn embed_multi(
+        &self,
+        texts: Vec<String>,
+    ) -> BoxPinFut<Result<(Vec<Vec<Vec<f32>>>, Usage), BoxError>>;
+
+    /// Embeds a query text as several vectors.
+    fn embed_query_multi(
+        &self,
+        text: String,
+    ) -> BoxPinFut<Result<(Vec<Vec<f32>>, Usage), BoxError>>;
+}
+
+/// Scores a document against a query by late interaction: the mean, over the query vectors,
+/// of their maximum cosine similarity with the document vectors, in the range [-1.0, 1.0].
+/// Returns 0.0 if either side has no vectors.
+pub fn late_interaction_score(query: &[Vec<f32>], doc: &[Vec<f32>]) -> f32 {
+    if query.is_empty() || doc.is_empty() {
+        return 0.0;
+    }
+    let total: f32 = query
+        .iter()
+        .map(|q| {
+            doc.iter()
+                .map(|d| cosine_similarity(q, d))
+                .fold(f32::MIN, f32::max)
+        })
+        .sum();
+    total / query.len() as f32
+}
+
+/// A [`MultiVectorFeaturesDyn`] that splits texts into passages of words and embeds each
+/// passage with an ordinary embedder.
+///
+/// Queries shorter than a passage are embedded as a single vector, so a document is scored
+/// by its passage closest to the query.
+#[derive(Clone)]
+pub struct SegmentedEmbedder {
+    inner: Arc<dyn EmbeddingFeaturesDyn>,
+    segment_words: usize,
+    max_vectors: usize,
+}
+
+impl SegmentedEmbedder {
+    /// Creates an embedder of passages of `segment_words` words.
+    pub fn new(inner: Arc<dyn EmbeddingFeaturesDyn>, segment_words: usize) -> Self {
+        Self {
+            inner,
+            segment_words: segment_words.max(1),
+            max_vectors: DEFAULT_MAX_VECTORS,
+        }
+    }
+
+    /// Sets the maximum number of vectors of a text, [`DEFAULT_MAX_VECTORS`] by default.
+    /// Longer texts are split into longer passages, to bound the storage of a chunk.
+    pub fn with_max_vectors(mut self, max_vectors: usize) -> Self {
+        self.max_vectors = max_vectors.max(1);
+        self
+    }
+
+    /// Splits the text into passages, at most `max_vectors`.
+    pub fn segments(&self, text: &str) -> Vec<String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty() {
+            return vec![];
+        }
+        let size = self
+            .segment_words
+            .max(words.len().div_ceil(self.max_vectors));
+        words.chunks(size).map(|chunk| chunk.join(" ")).collect()
+    }
+
+    async fn embed_segments(
+        &self,
+        texts: Vec<String>,
+    ) -> Result<(Vec<Vec<Vec<f32>>>, Usage), BoxError> {
+        let segments: Vec<Vec<String>> = texts.iter().map(|text| self.segments(text)).collect();
+        let flat: Vec<String> = segments.iter().flatten().cloned().collect();
+        let (embeddings, usage) =
+            embed_batch(self.inner.as_ref(), flat, &EmbedBatchOptions::default()).await?;
+
+        let mut embeddings = embeddings.into_iter();
+        let vecs = segments
+            .iter()
+            .map(|parts| {
+                embeddings
+                    .by_ref()
+                    .take(parts.len())
+                    .map(|embedding| embedding.vec)
+                    .collect()
+            })
+            .collect();
+        Ok((vecs, usage))
+    }
+}
+
+impl MultiVectorFeaturesDyn for SegmentedEmbedder {
+    fn ndims(&self) -> usize {
+        self.inner.ndims()
+    }
+
+    fn embed_multi(
+        &self,
+        texts: Vec<String>,
+    ) -> BoxPinFut<Result<(Vec<Vec<Vec<f32>>>, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.embed_segments(texts).await })
+    }
+
+    fn embed_query_multi(
+        &self,
+        text: String,
+    ) -> BoxPinFut<Result<(Vec<Vec<f32>>, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move {
+            let (mut vecs, usage) = this.embed_segments(vec![text]).await?;
+            Ok((vecs.pop().unwrap_or_default(), usage))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::MockImplemented;
+
+    #[test]
+    fn test_late_interaction_score() {
+        let doc = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        assert_eq!(late_interaction_score(&[vec![0.0, 1.0, 0.0]], &doc), 1.0);
+        assert_eq!(
+            late_interaction_score(&[vec![1.0, 0.0, 0.0], vec![0.0, 0.0, 1.0]], &doc),
+            0.5
+        );
+        assert_eq!(late_interaction_score(&[], &doc), 0.0);
+        assert_eq!(late_interaction_score(&[vec![1.0, 0.0, 0.0]], &[]), 0.0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_segmented_embedder() {
+        let embedder = SegmentedEmbedder::new(Arc::new(MockImplemented), 3);
+        assert_eq!(
+            embedder.segments("a b c d e f g"),
+            vec!["a b c", "d e f", "g"]
+        );
+        assert!(embedder.segments("  ").is_empty());
+        let embedder = embedder.with_max_vectors(2);
+        assert_eq!(embedder.segments("a b c d e f g"), vec!["a b c d", "e f g"]);
+
+        let (vecs, _) = embedder
+            .embed_multi(vec!["a b c d e".to_string(), "f".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(vecs.len(), 2);
+        assert_eq!(vecs[0].len(), 2);
+        assert_eq!(vecs[1].len(), 1);
+        assert!(vecs[0].iter().all(|vec| vec.len() == embedder.ndims()));
+        let (vecs, _) = embedder.embed_query_multi("x y".to_string()).await.unwrap();
+        assert_eq!(vecs.len(), 1);
+    }
+}
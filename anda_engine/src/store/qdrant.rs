@@ -0,0 +1,819 @@
+//! Vector store and knowledge store backed by [Qdrant](https://qdrant.tech).
+//!
+//! Deployments that already run Qdrant can use it instead of LanceDB:
+//! - [`QdrantClient`] manages collections, payload indexes and points with the REST API;
+//! - [`QdrantVectorStore`] implements [`VectorSearchFeaturesDyn`], a namespace being a
+//!   collection of points with `id` and `text` payload fields;
+//! - [`QdrantKnowledgeStore`] implements [`KnowledgeFeatures`] and [`VectorSearchFeatures`]
+//!   on a collection. [`KnowledgeFilter`]s are pushed down to Qdrant as payload filters,
+//!   and documents are upserted in batches.
+//!
+//! Qdrant ranks points by vector similarity only. Its full-text index matches the words
+//! of the query without ranking, so the [`SearchMode::Keyword`] mode returns the latest
+//! matching documents, and the [`SearchMode::Hybrid`] mode fuses the vector ranking with
+//! the vector ranking of the documents that match the words.
+//!
+//! ```rust,ignore
+//! let client = QdrantClient::new("http://localhost:6333").with_api_key(&api_key);
+//! let store = QdrantKnowledgeStore::init(client, "anda_knowledge", 1024, Some(embedder)).await?;
+//! store.knowledge_add(docs).await?;
+//! let docs = store.knowledge_search("how to deploy", 5, &filter).await?;
+//! ```
+
+use anda_core::{
+    BoxError, BoxPinFut, Knowledge, KnowledgeFeatures, KnowledgeFilter, KnowledgeInput,
+    META_AUTHOR, META_LANG, META_SOURCE, META_TAGS, Path, RRF_K, SearchMode, VectorSearchFeatures,
+    Xid, reciprocal_rank_fusion,
+};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use super::VectorSearchFeaturesDyn;
+use crate::{APP_USER_AGENT, model::EmbeddingFeaturesDyn, unix_ms};
+
+/// The distance metric of the vectors of a collection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Distance {
+    #[default]
+    Cosine,
+    Euclid,
+    Dot,
+    Manhattan,
+}
+
+/// The type of a payload index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadSchema {
+    /// Exact match of strings.
+    Keyword,
+    /// Exact match and ranges of integers, required to order points by the field.
+    Integer,
+    Float,
+    Bool,
+    /// Full-text match of the words of strings.
+    Text,
+}
+
+/// A point of a collection.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Point {
+    /// An unsigned integer or a UUID.
+    pub id: Value,
+    pub vector: Vec<f32>,
+    pub payload: Map<String, Value>,
+}
+
+/// A point returned by a search or a scroll, without its vector.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ScoredPoint {
+    pub id: Value,
+    /// The similarity to the query vector, 0 for scrolled points.
+    #[serde(default)]
+    pub score: f32,
+    #[serde(default)]
+    pub payload: Map<String, Value>,
+}
+
+/// A client of the REST API of Qdrant.
+#[derive(Clone)]
+pub struct QdrantClient {
+    http: reqwest::Client,
+    endpoint: String,
+    api_key: Option<String>,
+}
+
+impl QdrantClient {
+    /// Creates a client of the endpoint, e.g. "http://localhost:6333".
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .use_rustls_tls()
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(60))
+                .gzip(true)
+                .user_agent(APP_USER_AGENT)
+                .build()
+                .expect("qdrant reqwest client should build"),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    /// Sets the API key of the cluster.
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// Sends a request and returns the `result` of the response.
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Value, BoxError> {
+        let mut req = self
+            .http
+            .request(method.clone(), format!("{}{}", self.endpoint, path));
+        if let Some(api_key) = &self.api_key {
+            req = req.header("api-key", api_key);
+        }
+        if let Some(body) = body {
+            req = req.json(&body);
+        }
+        let res = req.send().await?;
+        let status = res.status();
+        let body = res.text().await?;
+        if !status.is_success() {
+            return Err(format!(
+                "Qdrant {} {} failed, status: {}, body: {}",
+                method, path, status, body
+            )
+            .into());
+        }
+        let mut res: Value = serde_json::from_str(&body)?;
+        Ok(res.get_mut("result").map(Value::take).unwrap_or_default())
+    }
+
+    /// Returns the names of the collections.
+    pub async fn list_collections(&self) -> Result<Vec<String>, BoxError> {
+        let res = self.request(Method::GET, "/collections", None).await?;
+        Ok(res["collections"]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|c| c["name"].as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Returns true if the collection exists.
+    pub async fn collection_exists(&self, name: &str) -> Result<bool, BoxError> {
+        let res = self
+            .request(Method::GET, &format!("/collections/{name}/exists"), None)
+            .await?;
+        Ok(res["exists"].as_bool().unwrap_or(false))
+    }
+
+    /// Creates a collection of vectors of `dim` dimensions.
+    pub async fn create_collection(
+        &self,
+        name: &str,
+        dim: usize,
+        distance: Distance,
+    ) -> Result<(), BoxError> {
+        self.request(
+            Method::PUT,
+            &format!("/collections/{name}"),
+            Some(json!({"vectors": {"size": dim, "distance": distance}})),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes a collection and its points.
+    pub async fn delete_collection(&self, name: &str) -> Result<(), BoxError> {
+        self.request(Method::DELETE, &format!("/collections/{name}"), None)
+            .await?;
+        Ok(())
+    }
+
+    /// Creates an index of a payload field, e.g. "meta.lang". Filters on indexed fields are
+    /// faster, and full-text matches and ordering need an index.
+    pub async fn create_payload_index(
+        &self,
+        name: &str,
+        field: &str,
+        schema: PayloadSchema,
+    ) -> Result<(), BoxError> {
+        let field_schema = match schema {
+            PayloadSchema::Text => json!({
+                "type": "text",
+                "tokenizer": "multilingual",
+                "lowercase": true,
+            }),
+            schema => json!(schema),
+        };
+        self.request(
+            Method::PUT,
+            &format!("/collections/{name}/index?wait=true"),
+            Some(json!({"field_name": field, "field_schema": field_schema})),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Inserts or replaces points, and waits until they are searchable.
+    pub async fn upsert(&self, name: &str, points: Vec<Point>) -> Result<(), BoxError> {
+        self.request(
+            Method::PUT,
+            &format!("/collections/{name}/points?wait=true"),
+            Some(json!({"points": points})),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the `n` points most similar to the vector that match the filter.
+    pub async fn search(
+        &self,
+        name: &str,
+        vector: Vec<f32>,
+        n: usize,
+        filter: Option<Value>,
+    ) -> Result<Vec<ScoredPoint>, BoxError> {
+        let mut body = json!({"vector": vector, "limit": n, "with_payload": true});
+        if let Some(filter) = filter {
+            body["filter"] = filter;
+        }
+        let res = self
+            .request(
+                Method::POST,
+                &format!("/collections/{name}/points/search"),
+                Some(body),
+            )
+            .await?;
+        Ok(serde_json::from_value(res)?)
+    }
+
+    /// Returns `n` points that match the filter, ordered by an indexed payload field if
+    /// `order_by` is set, with true for the descending order.
+    pub async fn scroll(
+        &self,
+        name: &str,
+        n: usize,
+        filter: Option<Value>,
+        order_by: Option<(&str, bool)>,
+    ) -> Result<Vec<ScoredPoint>, BoxError> {
+        let mut body = json!({"limit": n, "with_payload": true});
+        if let Some(filter) = filter {
+            body["filter"] = filter;
+        }
+        if let Some((key, desc)) = order_by {
+            body["order_by"] = json!({
+                "key": key,
+                "direction": if desc { "desc" } else { "asc" },
+            });
+        }
+        let mut res = self
+            .request(
+                Method::POST,
+                &format!("/collections/{name}/points/scroll"),
+                Some(body),
+            )
+            .await?;
+        Ok(serde_json::from_value(res["points"].take())?)
+    }
+
+    /// Returns the number of points that match the filter.
+    pub async fn count(&self, name: &str, filter: Option<Value>) -> Result<u64, BoxError> {
+        let mut body = json!({"exact": true});
+        if let Some(filter) = filter {
+            body["filter"] = filter;
+        }
+        let res = self
+            .request(
+                Method::POST,
+                &format!("/collections/{name}/points/count"),
+                Some(body),
+            )
+            .await?;
+        Ok(res["count"].as_u64().unwrap_or_default())
+    }
+
+    /// Deletes the points that match the filter.
+    pub async fn delete_points(&self, name: &str, filter: Value) -> Result<(), BoxError> {
+        self.request(
+            Method::POST,
+            &format!("/collections/{name}/points/delete?wait=true"),
+            Some(json!({"filter": filter})),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Returns the name of the collection of a namespace, e.g. "anda_knowledge" for
+/// "anda/knowledge".
+pub fn collection_name(namespace: &Path) -> String {
+    namespace.as_ref().to_ascii_lowercase().replace('/', "_")
+}
+
+/// A [`VectorSearchFeaturesDyn`] over Qdrant collections, searched by the vectors of the
+/// queries embedded with the embedder.
+#[derive(Clone)]
+pub struct QdrantVectorStore {
+    client: QdrantClient,
+    embedder: Arc<dyn EmbeddingFeaturesDyn>,
+}
+
+impl QdrantVectorStore {
+    pub fn new(client: QdrantClient, embedder: Arc<dyn EmbeddingFeaturesDyn>) -> Self {
+        Self { client, embedder }
+    }
+
+    pub fn client(&self) -> &QdrantClient {
+        &self.client
+    }
+
+    /// Opens the knowledge store of a namespace, created if it does not exist.
+    pub async fn knowledge_store(
+        &self,
+        namespace: &Path,
+    ) -> Result<QdrantKnowledgeStore, BoxError> {
+        QdrantKnowledgeStore::init(
+            self.client.clone(),
+            &collection_name(namespace),
+            self.embedder.ndims(),
+            Some(self.embedder.clone()),
+        )
+        .await
+    }
+
+    fn search_field(
+        &self,
+        namespace: Path,
+        query: String,
+        n: usize,
+        field: &'static str,
+    ) -> BoxPinFut<Result<Vec<String>, BoxError>> {
+        let client = self.client.clone();
+        let embedder = self.embedder.clone();
+        Box::pin(async move {
+            if n == 0 {
+                return Ok(vec![]);
+            }
+            let (embedding, _) = embedder.embed_query(query).await?;
+            let points = client
+                .search(&collection_name(&namespace), embedding.vec, n, None)
+                .await?;
+            Ok(points
+                .into_iter()
+                .filter_map(|p| p.payload.get(field)?.as_str().map(String::from))
+                .collect())
+        })
+    }
+}
+
+impl VectorSearchFeaturesDyn for QdrantVectorStore {
+    fn top_n(
+        &self,
+        namespace: Path,
+        query: String,
+        n: usize,
+    ) -> BoxPinFut<Result<Vec<String>, BoxError>> {
+        self.search_field(namespace, query, n, "text")
+    }
+
+    fn top_n_ids(
+        &self,
+        namespace: Path,
+        query: String,
+        n: usize,
+    ) -> BoxPinFut<Result<Vec<String>, BoxError>> {
+        self.search_field(namespace, query, n, "id")
+    }
+}
+
+/// A knowledge store on a Qdrant collection. Each document is a point whose payload has
+/// its xid `id`, `user`, `text`, `meta` and `created_at` in unix milliseconds.
+#[derive(Clone)]
+pub struct QdrantKnowledgeStore {
+    client: QdrantClient,
+    collection: String,
+    dim: usize,
+    embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
+    batch_size: usize,
+}
+
+/// The payload fields indexed by [`QdrantKnowledgeStore::init`].
+const INDEXES: &[(&str, PayloadSchema)] = &[
+    ("user", PayloadSchema::Keyword),
+    ("created_at", PayloadSchema::Integer),
+    ("text", PayloadSchema::Text),
+    ("meta.lang", PayloadSchema::Keyword),
+    ("meta.source", PayloadSchema::Keyword),
+    ("meta.author", PayloadSchema::Keyword),
+    ("meta.tags", PayloadSchema::Keyword),
+];
+
+impl QdrantKnowledgeStore {
+    /// Opens the knowledge store of a collection of vectors of `dim` dimensions. The
+    /// collection is created with cosine distance and the payload indexes of the filters
+    /// if it does not exist. Searches need the embedder, except in the keyword mode.
+    pub async fn init(
+        client: QdrantClient,
+        collection: &str,
+        dim: usize,
+        embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
+    ) -> Result<Self, BoxError> {
+        if let Some(embedder) = &embedder
+            && embedder.ndims() != dim
+        {
+            return Err(format!(
+                "invalid embedder dimensions, expected {}, got {}",
+                dim,
+                embedder.ndims()
+            )
+            .into());
+        }
+        if !client.collection_exists(collection).await? {
+            client
+                .create_collection(collection, dim, Distance::Cosine)
+                .await?;
+            for (field, schema) in INDEXES {
+                client
+                    .create_payload_index(collection, field, *schema)
+                    .await?;
+            }
+        }
+        Ok(Self {
+            client,
+            collection: collection.to_string(),
+            dim,
+            embedder,
+            batch_size: 256,
+        })
+    }
+
+    /// Sets the number of documents upserted per request. Defaults to 256.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// Returns the default search mode: hybrid if the store has an embedder, keyword otherwise.
+    pub fn default_search_mode(&self) -> SearchMode {
+        if self.embedder.is_some() {
+            SearchMode::Hybrid
+        } else {
+            SearchMode::Keyword
+        }
+    }
+
+    /// Searches the top n knowledge documents matching the filter, in the given mode.
+    pub async fn knowledge_search_with(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+        mode: SearchMode,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        let filter = qdrant_filter(filter);
+        if query.is_empty() {
+            return self.latest(n, filter).await;
+        }
+
+        let points = match mode {
+            SearchMode::Vector => self.vector_search(query, n, filter).await?,
+            SearchMode::Keyword => {
+                return self.latest(n, Some(with_text_match(filter, query))).await;
+            }
+            SearchMode::Hybrid => {
+                let candidates = n.saturating_mul(HYBRID_CANDIDATES_FACTOR);
+                let keyword_filter = with_text_match(filter.clone(), query);
+                let (vector, keyword) = futures::future::try_join(
+                    self.vector_search(query, candidates, filter),
+                    self.vector_search(query, candidates, Some(keyword_filter)),
+                )
+                .await?;
+                let mut points: BTreeMap<String, ScoredPoint> = BTreeMap::new();
+                let rankings: Vec<Vec<String>> = [vector, keyword]
+                    .into_iter()
+                    .map(|list| {
+                        list.into_iter()
+                            .map(|p| {
+                                let id = p.id.to_string();
+                                points.insert(id.clone(), p);
+                                id
+                            })
+                            .collect()
+                    })
+                    .collect();
+                reciprocal_rank_fusion(&rankings, RRF_K)
+                    .into_iter()
+                    .take(n)
+                    .filter_map(|(id, _)| points.remove(&id))
+                    .collect()
+            }
+        };
+        Ok(points
+            .into_iter()
+            .filter_map(knowledge_from_point)
+            .collect())
+    }
+
+    /// Deletes the documents that match the filter, all documents if it is empty.
+    pub async fn knowledge_delete(&self, filter: &KnowledgeFilter) -> Result<(), BoxError> {
+        let filter = qdrant_filter(filter).unwrap_or_else(|| json!({"must": []}));
+        self.client.delete_points(&self.collection, filter).await
+    }
+
+    /// Returns the number of documents that match the filter.
+    pub async fn knowledge_count(&self, filter: &KnowledgeFilter) -> Result<u64, BoxError> {
+        self.client
+            .count(&self.collection, qdrant_filter(filter))
+            .await
+    }
+
+    async fn vector_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: Option<Value>,
+    ) -> Result<Vec<ScoredPoint>, BoxError> {
+        let embedder = self
+            .embedder
+            .as_ref()
+            .ok_or("vector search requires an embedder")?;
+        let (embedding, _) = embedder.embed_query(query.to_string()).await?;
+        self.client
+            .search(&self.collection, embedding.vec, n, filter)
+            .await
+    }
+
+    async fn latest(&self, n: usize, filter: Option<Value>) -> Result<Vec<Knowledge>, BoxError> {
+        let points = self
+            .client
+            .scroll(&self.collection, n, filter, Some(("created_at", true)))
+            .await?;
+        Ok(points
+            .into_iter()
+            .filter_map(knowledge_from_point)
+            .collect())
+    }
+}
+
+/// The number of candidates fetched by each search of the hybrid mode, per result.
+const HYBRID_CANDIDATES_FACTOR: usize = 3;
+
+/// Builds the payload filter of a knowledge filter. Users and languages are stored in
+/// lowercase.
+fn qdrant_filter(filter: &KnowledgeFilter) -> Option<Value> {
+    let matches = |key: &str, value: String| json!({"key": key, "match": {"value": value}});
+    let mut must: Vec<Value> = Vec::new();
+    if let Some(user) = &filter.user {
+        must.push(matches("user", user.to_ascii_lowercase()));
+    }
+    if let Some(lang) = &filter.lang {
+        must.push(matches(
+            &format!("meta.{META_LANG}"),
+            lang.to_ascii_lowercase(),
+        ));
+    }
+    if let Some(source) = &filter.source {
+        must.push(matches(&format!("meta.{META_SOURCE}"), source.clone()));
+    }
+    if let Some(author) = &filter.author {
+        must.push(matches(&format!("meta.{META_AUTHOR}"), author.to_text()));
+    }
+    // an array field matches if any of its items matches
+    for tag in &filter.tags {
+        must.push(matches(&format!("meta.{META_TAGS}"), tag.clone()));
+    }
+    if filter.created_after.is_some() || filter.created_before.is_some() {
+        let mut range = Map::new();
+        if let Some(after) = filter.created_after {
+            range.insert("gte".to_string(), after.into());
+        }
+        if let Some(before) = filter.created_before {
+            range.insert("lt".to_string(), before.into());
+        }
+        must.push(json!({"key": "created_at", "range": range}));
+    }
+    (!must.is_empty()).then(|| json!({ "must": must }))
+}
+
+/// Adds a full-text match of the query to a payload filter.
+fn with_text_match(filter: Option<Value>, query: &str) -> Value {
+    let condition = json!({"key": "text", "match": {"text": query}});
+    match filter {
+        Some(mut filter) => {
+            if let Some(must) = filter["must"].as_array_mut() {
+                must.push(condition);
+            }
+            filter
+        }
+        None => json!({ "must": [condition] }),
+    }
+}
+
+/// Returns the UUID point ID of an xid, its 12 bytes followed by 4 zero bytes.
+fn point_id(id: &Xid) -> String {
+    let mut bytes = [0u8; 16];
+    bytes[..12].copy_from_slice(&id.0);
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn knowledge_from_point(point: ScoredPoint) -> Option<Knowledge> {
+    let mut payload = point.payload;
+    let mut text = |key: &str| match payload.remove(key) {
+        Some(Value::String(s)) => Some(s),
+        _ => None,
+    };
+    let id = text("id")?;
+    let user = text("user").unwrap_or_default();
+    let body = text("text").unwrap_or_default();
+    let meta = match payload.remove("meta") {
+        Some(Value::Object(meta)) => meta.into_iter().collect(),
+        _ => BTreeMap::new(),
+    };
+    Some(Knowledge {
+        id,
+        user,
+        text: body,
+        meta,
+    })
+}
+
+impl VectorSearchFeatures for QdrantKnowledgeStore {
+    async fn top_n(&self, query: &str, n: usize) -> Result<Vec<String>, BoxError> {
+        let docs = self
+            .knowledge_search_with(
+                query,
+                n,
+                &KnowledgeFilter::default(),
+                self.default_search_mode(),
+            )
+            .await?;
+        Ok(docs.into_iter().map(|doc| doc.text).collect())
+    }
+
+    async fn top_n_ids(&self, query: &str, n: usize) -> Result<Vec<String>, BoxError> {
+        let docs = self
+            .knowledge_search_with(
+                query,
+                n,
+                &KnowledgeFilter::default(),
+                self.default_search_mode(),
+            )
+            .await?;
+        Ok(docs.into_iter().map(|doc| doc.id).collect())
+    }
+}
+
+impl KnowledgeFeatures for QdrantKnowledgeStore {
+    async fn knowledge_top_n(
+        &self,
+        query: &str,
+        n: usize,
+        user: Option<String>,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        let filter = KnowledgeFilter {
+            user,
+            ..Default::default()
+        };
+        self.knowledge_search(query, n, &filter).await
+    }
+
+    async fn knowledge_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        self.knowledge_search_with(query, n, filter, self.default_search_mode())
+            .await
+    }
+
+    async fn knowledge_latest_n(
+        &self,
+        last_seconds: u32,
+        n: usize,
+        user: Option<String>,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        if last_seconds == 0 || n == 0 {
+            return Ok(vec![]);
+        }
+        let filter = KnowledgeFilter {
+            user,
+            created_after: Some(unix_ms().saturating_sub(last_seconds as u64 * 1000)),
+            ..Default::default()
+        };
+        self.latest(n, qdrant_filter(&filter)).await
+    }
+
+    async fn knowledge_add(&self, docs: Vec<KnowledgeInput>) -> Result<(), BoxError> {
+        let mut points: Vec<Point> = Vec::with_capacity(docs.len());
+        for doc in docs {
+            let doc = doc.normalize();
+            if doc.vec.len() != self.dim {
+                return Err(format!(
+                    "invalid vector length, expected {}, got {}",
+                    self.dim,
+                    doc.vec.len()
+                )
+                .into());
+            }
+            let id = Xid::new();
+            let mut payload = Map::new();
+            payload.insert("id".to_string(), id.to_string().into());
+            payload.insert("user".to_string(), doc.user.to_ascii_lowercase().into());
+            payload.insert("text".to_string(), doc.text.into());
+            payload.insert(
+                "meta".to_string(),
+                Value::Object(doc.meta.into_iter().collect()),
+            );
+            payload.insert("created_at".to_string(), unix_ms().into());
+            points.push(Point {
+                id: point_id(&id).into(),
+                vector: doc.vec,
+                payload,
+            });
+        }
+
+        while !points.is_empty() {
+            let rest = points.split_off(points.len().min(self.batch_size));
+            self.client.upsert(&self.collection, points).await?;
+            points = rest;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qdrant_filter() {
+        assert_eq!(qdrant_filter(&KnowledgeFilter::default()), None);
+
+        let filter = KnowledgeFilter {
+            user: Some("Alice".to_string()),
+            lang: Some("EN".to_string()),
+            tags: vec!["rust".to_string(), "ai".to_string()],
+            created_after: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(
+            qdrant_filter(&filter),
+            Some(json!({"must": [
+                {"key": "user", "match": {"value": "alice"}},
+                {"key": "meta.lang", "match": {"value": "en"}},
+                {"key": "meta.tags", "match": {"value": "rust"}},
+                {"key": "meta.tags", "match": {"value": "ai"}},
+                {"key": "created_at", "range": {"gte": 1000}},
+            ]}))
+        );
+
+        let filter = with_text_match(qdrant_filter(&filter), "deploy");
+        assert_eq!(filter["must"].as_array().unwrap().len(), 6);
+        assert_eq!(
+            with_text_match(None, "deploy"),
+            json!({"must": [{"key": "text", "match": {"text": "deploy"}}]})
+        );
+    }
+
+    #[test]
+    fn test_point() {
+        let id = Xid::new();
+        let pid = point_id(&id);
+        assert_eq!(pid.len(), 36);
+        assert!(pid.ends_with("00000000"));
+
+        let point = ScoredPoint {
+            id: pid.into(),
+            score: 0.5,
+            payload: json!({
+                "id": id.to_string(),
+                "user": "alice",
+                "text": "hello",
+                "meta": {"lang": "en"},
+                "created_at": 1000,
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        };
+        let doc = knowledge_from_point(point).unwrap();
+        assert_eq!(doc.id, id.to_string());
+        assert_eq!(doc.user, "alice");
+        assert_eq!(doc.text, "hello");
+        assert_eq!(doc.meta.get("lang"), Some(&json!("en")));
+        assert_eq!(
+            collection_name(&Path::from("anda/Knowledge")),
+            "anda_knowledge"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Vector similarity scoring.
+//!
+//! Scoring dominates the latency of in-memory retrieval over large candidate sets,
+//! so the functions here use explicit SIMD kernels when the CPU supports them:
+//! - x86_64: AVX2 + FMA, detected at runtime;
+//! - aarch64: NEON.
+//!
+//! Other targets, and vectors shorter than one SIMD lane group, use the [`scalar`] implementations.
+//! Vectors of different lengths are scored on their common prefix.
+
+/// Computes the dot product of two vectors.
+pub fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    #[cfg(target_arch = "x86_64")]
+    if len >= 8 && is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        // Safety: the required CPU features are detected above.
+        return unsafe { x86::dot_product(a, b) };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if len >= 4 {
+        // Safety: NEON is always available on aarch64.
+        return unsafe { neon::dot_product(a, b) };
+    }
+
+    scalar::dot_product(a, b)
+}
+
+/// Computes the cosine similarity of two vectors, in the range [-1.0, 1.0].
+/// Returns 0.0 if either vector has zero magnitude.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    #[cfg(target_arch = "x86_64")]
+    if len >= 8 && is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        // Safety: the required CPU features are detected above.
+        let (dot, na, nb) = unsafe { x86::dot_and_norms(a, b) };
+        return cosine(dot, na, nb);
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if len >= 4 {
+        // Safety: NEON is always available on aarch64.
+        let (dot, na, nb) = unsafe { neon::dot_and_norms(a, b) };
+        return cosine(dot, na, nb);
+    }
+
+    scalar::cosine_similarity(a, b)
+}
+
+#[inline]
+fn cosine(dot: f32, na: f32, nb: f32) -> f32 {
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na.sqrt() * nb.sqrt())
+    }
+}
+
+/// Portable implementations, used as the fallback and as the reference in tests and benchmarks.
+pub mod scalar {
+    /// Computes the dot product of two vectors.
+    pub fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    /// Computes the cosine similarity of two vectors.
+    pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+        let mut dot = 0f32;
+        let mut na = 0f32;
+        let mut nb = 0f32;
+        for (x, y) in a.iter().zip(b) {
+            dot += x * y;
+            na += x * x;
+            nb += y * y;
+        }
+        super::cosine(dot, na, nb)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2,fma")]
+    fn hsum(v: __m256) -> f32 {
+        let hi = _mm256_extractf128_ps(v, 1);
+        let lo = _mm256_castps256_ps128(v);
+        let sum = _mm_add_ps(hi, lo);
+        let sum = _mm_add_ps(sum, _mm_movehl_ps(sum, sum));
+        let sum = _mm_add_ss(sum, _mm_shuffle_ps(sum, sum, 1));
+        _mm_cvtss_f32(sum)
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+        let mut acc0 = _mm256_setzero_ps();
+        let mut acc1 = _mm256_setzero_ps();
+        let mut i = 0;
+        // two accumulators to hide the FMA latency
+        while i + 16 <= len {
+            unsafe {
+                acc0 =
+                    _mm256_fmadd_ps(_mm256_loadu_ps(pa.add(i)), _mm256_loadu_ps(pb.add(i)), acc0);
+                acc1 = _mm256_fmadd_ps(
+                    _mm256_loadu_ps(pa.add(i + 8)),
+                    _mm256_loadu_ps(pb.add(i + 8)),
+                    acc1,
+                );
+            }
+            i += 16;
+        }
+        while i + 8 <= len {
+            unsafe {
+                acc0 =
+                    _mm256_fmadd_ps(_mm256_loadu_ps(pa.add(i)), _mm256_loadu_ps(pb.add(i)), acc0);
+            }
+            i += 8;
+        }
+
+        let mut sum = hsum(_mm256_add_ps(acc0, acc1));
+        while i < len {
+            sum += a[i] * b[i];
+            i += 1;
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot_and_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let len = a.len();
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+        let mut dot = _mm256_setzero_ps();
+        let mut na = _mm256_setzero_ps();
+        let mut nb = _mm256_setzero_ps();
+        let mut i = 0;
+        while i + 8 <= len {
+            unsafe {
+                let x = _mm256_loadu_ps(pa.add(i));
+                let y = _mm256_loadu_ps(pb.add(i));
+                dot = _mm256_fmadd_ps(x, y, dot);
+                na = _mm256_fmadd_ps(x, x, na);
+                nb = _mm256_fmadd_ps(y, y, nb);
+            }
+            i += 8;
+        }
+
+        let (mut dot, mut na, mut nb) = (hsum(dot), hsum(na), hsum(nb));
+        while i < len {
+            dot += a[i] * b[i];
+            na += a[i] * a[i];
+            nb += b[i] * b[i];
+            i += 1;
+        }
+        (dot, na, nb)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+        let mut acc0 = vdupq_n_f32(0.0);
+        let mut acc1 = vdupq_n_f32(0.0);
+        let mut i = 0;
+        while i + 8 <= len {
+            unsafe {
+                acc0 = vfmaq_f32(acc0, vld1q_f32(pa.add(i)), vld1q_f32(pb.add(i)));
+                acc1 = vfmaq_f32(acc1, vld1q_f32(pa.add(i + 4)), vld1q_f32(pb.add(i + 4)));
+            }
+            i += 8;
+        }
+        while i + 4 <= len {
+            unsafe {
+                acc0 = vfmaq_f32(acc0, vld1q_f32(pa.add(i)), vld1q_f32(pb.add(i)));
+            }
+            i += 4;
+        }
+
+        let mut sum = vaddvq_f32(vaddq_f32(acc0, acc1));
+        while i < len {
+            sum += a[i] * b[i];
+            i += 1;
+        }
+        sum
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn dot_and_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let len = a.len();
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+        let mut dot = vdupq_n_f32(0.0);
+        let mut na = vdupq_n_f32(0.0);
+        let mut nb = vdupq_n_f32(0.0);
+        let mut i = 0;
+        while i + 4 <= len {
+            unsafe {
+                let x = vld1q_f32(pa.add(i));
+                let y = vld1q_f32(pb.add(i));
+                dot = vfmaq_f32(dot, x, y);
+                na = vfmaq_f32(na, x, x);
+                nb = vfmaq_f32(nb, y, y);
+            }
+            i += 4;
+        }
+
+        let (mut dot, mut na, mut nb) = (vaddvq_f32(dot), vaddvq_f32(na), vaddvq_f32(nb));
+        while i < len {
+            dot += a[i] * b[i];
+            na += a[i] * a[i];
+            nb += b[i] * b[i];
+            i += 1;
+        }
+        (dot, na, nb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rand_number;
+
+    fn rand_vec(n: usize) -> Vec<f32> {
+        (0..n).map(|_| rand_number(-1.0f32..1.0f32)).collect()
+    }
+
+    #[test]
+    fn test_similarity() {
+        for n in [0, 1, 3, 4, 7, 8, 9, 16, 17, 33, 384, 1536] {
+            let a = rand_vec(n);
+            let b = rand_vec(n);
+            let d1 = dot_product(&a, &b);
+            let d2 = scalar::dot_product(&a, &b);
+            assert!((d1 - d2).abs() < 1e-3, "dot {}: {} != {}", n, d1, d2);
+            let c1 = cosine_similarity(&a, &b);
+            let c2 = scalar::cosine_similarity(&a, &b);
+            assert!((c1 - c2).abs() < 1e-4, "cosine {}: {} != {}", n, c1, c2);
+        }
+
+        let a = rand_vec(100);
+        assert!((cosine_similarity(&a, &a) - 1.0).abs() < 1e-5);
+        let b: Vec<f32> = a.iter().map(|v| -v).collect();
+        assert!((cosine_similarity(&a, &b) + 1.0).abs() < 1e-5);
+        assert_eq!(cosine_similarity(&a, &[0.0; 100]), 0.0);
+        // common prefix
+        assert_eq!(
+            dot_product(&[1.0, 2.0, 3.0], &[1.0, 1.0]),
+            scalar::dot_product(&[1.0, 2.0], &[1.0, 1.0])
+        );
+    }
+}
@@ -0,0 +1,477 @@
+//! Concurrency-safe counters and leaderboards.
+//!
+//! A read-modify-write of a value in the store loses updates when requests, or engine
+//! instances, update it at the same time. [`Counters`] and [`Leaderboard`] instead record
+//! the updates in memory, atomically, and merge them into the store in batches:
+//! - updates are applied to a pending batch under a lock, and reads include them;
+//! - [`flush`](Counters::flush) merges the batch into the stored values with compare-and-swap,
+//!   and retries on conflicts with the flushes of other instances, so no update is lost;
+//! - [`spawn_flush`](Counters::spawn_flush) flushes periodically in the background, and
+//!   once more when it is cancelled.
+//!
+//! Reads include the updates of other instances since their last flush and ours.
+//!
+//! ```rust,ignore
+//! let board = Arc::new(Leaderboard::new(store, Path::from("community"), "weekly_points"));
+//! board.clone().spawn_flush(Duration::from_secs(10), token);
+//! board.incr("alice", 10);
+//! let top = board.top(10);
+//! ```
+
+use anda_core::{BoxError, OsVersion, Path, PutMode, UpdateVersion};
+use ciborium::from_reader;
+use ic_cose_types::to_cbor_bytes;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::{MAX_STORE_OBJECT_SIZE, Store, is_conflict, is_not_found};
+
+/// The maximum number of compare-and-swap attempts of a flush.
+const MAX_FLUSH_ATTEMPTS: usize = 10;
+
+/// An update of a value, merged with later updates before it is flushed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Add(i64),
+    Set(i64),
+    Remove,
+}
+
+impl Op {
+    /// Returns the update equivalent to this update followed by `next`.
+    fn then(self, next: Op) -> Op {
+        match (self, next) {
+            (Op::Add(a), Op::Add(b)) => Op::Add(a.saturating_add(b)),
+            (Op::Set(a), Op::Add(b)) => Op::Set(a.saturating_add(b)),
+            (Op::Remove, Op::Add(b)) => Op::Set(b),
+            (_, next) => next,
+        }
+    }
+
+    fn apply(self, value: Option<i64>) -> Option<i64> {
+        match self {
+            Op::Add(d) => Some(value.unwrap_or_default().saturating_add(d)),
+            Op::Set(v) => Some(v),
+            Op::Remove => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TallyState {
+    /// The values in the store as of the last flush or load.
+    persisted: BTreeMap<String, i64>,
+    /// The updates being flushed.
+    flushing: BTreeMap<String, Op>,
+    /// The updates since the last flush started.
+    pending: BTreeMap<String, Op>,
+}
+
+impl TallyState {
+    fn value(&self, key: &str) -> Option<i64> {
+        let mut value = self.persisted.get(key).copied();
+        if let Some(op) = self.flushing.get(key) {
+            value = op.apply(value);
+        }
+        if let Some(op) = self.pending.get(key) {
+            value = op.apply(value);
+        }
+        value
+    }
+
+    fn values(&self) -> BTreeMap<String, i64> {
+        let mut values = self.persisted.clone();
+        for ops in [&self.flushing, &self.pending] {
+            for (key, op) in ops {
+                match op.apply(values.get(key).copied()) {
+                    Some(v) => values.insert(key.clone(), v),
+                    None => values.remove(key),
+                };
+            }
+        }
+        values
+    }
+}
+
+/// Integer values by key in a store object, updated in batches.
+struct Tally {
+    store: Store,
+    namespace: Path,
+    path: Path,
+    state: Mutex<TallyState>,
+    /// Serializes flushes.
+    flush_lock: tokio::sync::Mutex<()>,
+}
+
+impl Tally {
+    fn new(store: Store, namespace: Path, name: &str) -> Self {
+        Self {
+            store,
+            namespace,
+            path: Path::from(format!("{}.cbor", name)),
+            state: Mutex::new(TallyState::default()),
+            flush_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn update(&self, key: &str, op: Op) -> Option<i64> {
+        let mut state = self.state.lock().unwrap();
+        let op = match state.pending.get(key) {
+            Some(prev) => prev.then(op),
+            None => op,
+        };
+        state.pending.insert(key.to_string(), op);
+        state.value(key)
+    }
+
+    fn value(&self, key: &str) -> Option<i64> {
+        self.state.lock().unwrap().value(key)
+    }
+
+    fn values(&self) -> BTreeMap<String, i64> {
+        self.state.lock().unwrap().values()
+    }
+
+    async fn load(&self) -> Result<(BTreeMap<String, i64>, Option<UpdateVersion>), BoxError> {
+        match self.store.store_get(&self.namespace, &self.path).await {
+            Ok((data, meta)) => Ok((
+                from_reader(&data[..])?,
+                Some(UpdateVersion {
+                    e_tag: meta.e_tag,
+                    version: meta.version,
+                }),
+            )),
+            Err(err) if is_not_found(&err) => Ok((BTreeMap::new(), None)),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn flush(&self) -> Result<usize, BoxError> {
+        let _guard = self.flush_lock.lock().await;
+        let ops = {
+            let mut state = self.state.lock().unwrap();
+            state.flushing = std::mem::take(&mut state.pending);
+            state.flushing.clone()
+        };
+
+        match self.merge(&ops).await {
+            Ok(values) => {
+                let mut state = self.state.lock().unwrap();
+                state.persisted = values;
+                state.flushing.clear();
+                Ok(ops.len())
+            }
+            Err(err) => {
+                // keeps the updates for the next flush, before the newer ones
+                let mut state = self.state.lock().unwrap();
+                let mut ops = std::mem::take(&mut state.flushing);
+                for (key, op) in std::mem::take(&mut state.pending) {
+                    let op = match ops.get(&key) {
+                        Some(prev) => prev.then(op),
+                        None => op,
+                    };
+                    ops.insert(key, op);
+                }
+                state.pending = ops;
+                Err(err)
+            }
+        }
+    }
+
+    /// Merges the updates into the stored values. Returns the merged values.
+    async fn merge(&self, ops: &BTreeMap<String, Op>) -> Result<BTreeMap<String, i64>, BoxError> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let (mut values, version) = self.load().await?;
+            if ops.is_empty() {
+                return Ok(values);
+            }
+
+            for (key, op) in ops {
+                match op.apply(values.get(key).copied()) {
+                    Some(v) => values.insert(key.clone(), v),
+                    None => values.remove(key),
+                };
+            }
+            let data = to_cbor_bytes(&values);
+            if data.len() > MAX_STORE_OBJECT_SIZE {
+                return Err(format!(
+                    "{} exceeds the object size limit {} with {} keys",
+                    self.path,
+                    MAX_STORE_OBJECT_SIZE,
+                    values.len()
+                )
+                .into());
+            }
+
+            let mode = match version {
+                Some(ver) => PutMode::Update(OsVersion {
+                    e_tag: ver.e_tag,
+                    version: ver.version,
+                }),
+                None => PutMode::Create,
+            };
+            match self
+                .store
+                .store_put(&self.namespace, &self.path, mode, data.into())
+                .await
+            {
+                Ok(_) => return Ok(values),
+                Err(err) if attempts < MAX_FLUSH_ATTEMPTS && is_conflict(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+macro_rules! impl_flush {
+    ($ty:ty) => {
+        impl $ty {
+            /// Merges the updates into the store and loads the updates of other instances.
+            /// Returns the number of flushed keys. The updates are kept on failure.
+            pub async fn flush(&self) -> Result<usize, BoxError> {
+                self.tally.flush().await
+            }
+
+            /// Spawns a background task that flushes every interval, and once more when the
+            /// token is cancelled.
+            pub fn spawn_flush(
+                self: Arc<Self>,
+                interval: Duration,
+                token: CancellationToken,
+            ) -> JoinHandle<()> {
+                tokio::spawn(async move {
+                    loop {
+                        let cancelled = tokio::select! {
+                            _ = token.cancelled() => true,
+                            _ = tokio::time::sleep(interval) => false,
+                        };
+                        if let Err(err) = self.flush().await {
+                            log::warn!(path = self.tally.path.as_ref(); "flush failed: {}", err);
+                        }
+                        if cancelled {
+                            return;
+                        }
+                    }
+                })
+            }
+        }
+    };
+}
+
+/// Named counters, e.g. rate counters and usage tallies, stored in one object.
+pub struct Counters {
+    tally: Tally,
+}
+
+impl Counters {
+    /// Creates the counters stored at `{name}.cbor` in the namespace. Call
+    /// [`flush`](Self::flush) to load the stored values.
+    pub fn new(store: Store, namespace: Path, name: &str) -> Self {
+        Self {
+            tally: Tally::new(store, namespace, name),
+        }
+    }
+
+    /// Adds `delta` to the counter and returns its value.
+    pub fn incr(&self, key: &str, delta: i64) -> i64 {
+        self.tally.update(key, Op::Add(delta)).unwrap_or_default()
+    }
+
+    /// Returns the value of the counter, 0 if it does not exist.
+    pub fn get(&self, key: &str) -> i64 {
+        self.tally.value(key).unwrap_or_default()
+    }
+
+    /// Removes the counter.
+    pub fn reset(&self, key: &str) {
+        self.tally.update(key, Op::Remove);
+    }
+
+    /// Returns the values of all counters.
+    pub fn all(&self) -> BTreeMap<String, i64> {
+        self.tally.values()
+    }
+}
+
+impl_flush!(Counters);
+
+/// A sorted set of members by score, e.g. a leaderboard of a community, stored in one object.
+pub struct Leaderboard {
+    tally: Tally,
+}
+
+impl Leaderboard {
+    /// Creates the leaderboard stored at `{name}.cbor` in the namespace. Call
+    /// [`flush`](Self::flush) to load the stored scores.
+    pub fn new(store: Store, namespace: Path, name: &str) -> Self {
+        Self {
+            tally: Tally::new(store, namespace, name),
+        }
+    }
+
+    /// Adds `delta` to the score of the member, adding the member if missing.
+    /// Returns the new score.
+    pub fn incr(&self, member: &str, delta: i64) -> i64 {
+        self.tally
+            .update(member, Op::Add(delta))
+            .unwrap_or_default()
+    }
+
+    /// Sets the score of the member. The last set wins over the sets of other instances.
+    pub fn set(&self, member: &str, score: i64) {
+        self.tally.update(member, Op::Set(score));
+    }
+
+    /// Removes the member.
+    pub fn remove(&self, member: &str) {
+        self.tally.update(member, Op::Remove);
+    }
+
+    /// Returns the score of the member.
+    pub fn score(&self, member: &str) -> Option<i64> {
+        self.tally.value(member)
+    }
+
+    /// Returns the number of members.
+    pub fn len(&self) -> usize {
+        self.tally.values().len()
+    }
+
+    /// Returns true if there are no members.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the members ranked by score, highest first; members with the same score
+    /// are ordered by name.
+    pub fn ranking(&self) -> Vec<(String, i64)> {
+        let mut members: Vec<(String, i64)> = self.tally.values().into_iter().collect();
+        members.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        members
+    }
+
+    /// Returns the top `n` members with their scores.
+    pub fn top(&self, n: usize) -> Vec<(String, i64)> {
+        let mut members = self.ranking();
+        members.truncate(n);
+        members
+    }
+
+    /// Returns the rank of the member, starting at 1, and its score.
+    pub fn rank(&self, member: &str) -> Option<(usize, i64)> {
+        self.ranking()
+            .into_iter()
+            .position(|(m, _)| m == member)
+            .map(|i| (i + 1, self.score(member).unwrap_or_default()))
+    }
+}
+
+impl_flush!(Leaderboard);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn test_op_then() {
+        assert_eq!(Op::Add(1).then(Op::Add(2)), Op::Add(3));
+        assert_eq!(Op::Set(5).then(Op::Add(2)), Op::Set(7));
+        assert_eq!(Op::Remove.then(Op::Add(2)), Op::Set(2));
+        assert_eq!(Op::Add(1).then(Op::Remove), Op::Remove);
+        assert_eq!(Op::Add(1).then(Op::Set(0)), Op::Set(0));
+        assert_eq!(Op::Add(i64::MAX).then(Op::Add(1)), Op::Add(i64::MAX));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_counters() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let ns = Path::from("test");
+        let a = Arc::new(Counters::new(store.clone(), ns.clone(), "usage"));
+        let b = Counters::new(store, ns, "usage");
+
+        // concurrent increments in one instance
+        let mut tasks = Vec::new();
+        for _ in 0..10 {
+            let a = a.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..10 {
+                    a.incr("requests", 1);
+                }
+            }));
+        }
+        for t in tasks {
+            t.await.unwrap();
+        }
+        assert_eq!(a.get("requests"), 100);
+        assert_eq!(b.get("requests"), 0);
+
+        // both instances flush their increments without losing any
+        b.incr("requests", 5);
+        b.incr("tokens", 42);
+        assert_eq!(a.flush().await.unwrap(), 1);
+        assert_eq!(b.flush().await.unwrap(), 2);
+        assert_eq!(b.get("requests"), 105);
+        assert_eq!(a.get("requests"), 100);
+        assert_eq!(a.flush().await.unwrap(), 0);
+        assert_eq!(a.get("requests"), 105);
+        assert_eq!(a.get("tokens"), 42);
+
+        a.reset("tokens");
+        assert_eq!(a.get("tokens"), 0);
+        a.flush().await.unwrap();
+        b.flush().await.unwrap();
+        assert_eq!(b.all(), BTreeMap::from([("requests".to_string(), 105)]));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_leaderboard() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let board = Arc::new(Leaderboard::new(
+            store.clone(),
+            Path::from("test"),
+            "points",
+        ));
+        assert!(board.is_empty());
+        assert_eq!(board.incr("alice", 10), 10);
+        board.incr("bob", 20);
+        board.incr("carol", 10);
+        board.set("dave", 5);
+        assert_eq!(board.len(), 4);
+        assert_eq!(
+            board.top(3),
+            vec![
+                ("bob".to_string(), 20),
+                ("alice".to_string(), 10),
+                ("carol".to_string(), 10),
+            ]
+        );
+        assert_eq!(board.rank("carol"), Some((3, 10)));
+        assert_eq!(board.rank("eve"), None);
+
+        let token = CancellationToken::new();
+        let handle = board
+            .clone()
+            .spawn_flush(Duration::from_secs(3600), token.clone());
+        token.cancel();
+        handle.await.unwrap();
+
+        // another instance sees the flushed scores
+        let other = Leaderboard::new(store, Path::from("test"), "points");
+        other.flush().await.unwrap();
+        assert_eq!(other.ranking(), board.ranking());
+        other.remove("bob");
+        other.incr("alice", 1);
+        other.flush().await.unwrap();
+        board.flush().await.unwrap();
+        assert_eq!(board.rank("alice"), Some((1, 11)));
+        assert_eq!(board.score("bob"), None);
+    }
+}
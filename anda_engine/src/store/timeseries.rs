@@ -0,0 +1,530 @@
+//! Time series of measurements in the store.
+//!
+//! [`TimeSeriesStore`] keeps recurring measurements of agents, e.g. the value of a portfolio
+//! or sensor readings, without an external time series database. A series is stored in
+//! chunks, one object per span of time (a day by default):
+//! - [`append`](TimeSeriesStore::append) merges points into their chunks with compare-and-swap,
+//!   so concurrent appends are not lost; a point replaces the point at the same timestamp;
+//! - [`range`](TimeSeriesStore::range) and [`downsample`](TimeSeriesStore::downsample) read the
+//!   chunks of a time range, the latter aggregating the points per window;
+//! - [`rollup`](TimeSeriesStore::rollup) and [`delete_before`](TimeSeriesStore::delete_before)
+//!   bound the storage of old points.
+//!
+//! Timestamps are Unix milliseconds. A chunk must fit in [`MAX_STORE_OBJECT_SIZE`], about
+//! 100,000 points: use a shorter chunk span for series with more points per day.
+//!
+//! ```rust,ignore
+//! let metrics = TimeSeriesStore::new(store, Path::from("portfolio_agent"));
+//! metrics.append("portfolio_value", &[DataPoint::new(unix_ms(), 1250.5)]).await?;
+//! let hourly = metrics
+//!     .downsample("portfolio_value", start, end, Duration::from_secs(3600), Aggregation::Mean)
+//!     .await?;
+//! ```
+
+use anda_core::{BoxError, OsVersion, Path, PutMode, validate_path_part};
+use ciborium::from_reader;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::{MAX_STORE_OBJECT_SIZE, Store, is_conflict, is_not_found};
+
+/// The default span of time of a chunk.
+const DEFAULT_CHUNK_SPAN: Duration = Duration::from_secs(24 * 3600);
+
+/// The maximum number of compare-and-swap attempts of a chunk update.
+const MAX_UPDATE_ATTEMPTS: usize = 10;
+
+/// A measurement at a time.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DataPoint {
+    /// Unix timestamp in milliseconds.
+    pub timestamp: u64,
+    pub value: f64,
+}
+
+impl DataPoint {
+    pub fn new(timestamp: u64, value: f64) -> Self {
+        Self { timestamp, value }
+    }
+}
+
+/// How the points of a window are aggregated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    Mean,
+    Min,
+    Max,
+    Sum,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregation {
+    /// Aggregates the values, in time order. Returns None if there are no values.
+    pub fn apply(&self, values: &[f64]) -> Option<f64> {
+        let (first, last) = (values.first()?, values.last()?);
+        Some(match self {
+            Self::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Self::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Self::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Self::Sum => values.iter().sum(),
+            Self::Count => values.len() as f64,
+            Self::First => *first,
+            Self::Last => *last,
+        })
+    }
+}
+
+/// Aggregates points sorted by time into windows of `step` milliseconds aligned to the
+/// Unix epoch. The timestamp of an aggregated point is the start of its window.
+pub fn downsample(points: &[DataPoint], step: u64, aggregation: Aggregation) -> Vec<DataPoint> {
+    let step = step.max(1);
+    let mut rt = Vec::new();
+    let mut values: Vec<f64> = Vec::new();
+    let mut window = None;
+    for p in points {
+        let start = p.timestamp - p.timestamp % step;
+        if window != Some(start) {
+            if let Some(ts) = window
+                && let Some(value) = aggregation.apply(&values)
+            {
+                rt.push(DataPoint::new(ts, value));
+            }
+            window = Some(start);
+            values.clear();
+        }
+        values.push(p.value);
+    }
+    if let Some(ts) = window
+        && let Some(value) = aggregation.apply(&values)
+    {
+        rt.push(DataPoint::new(ts, value));
+    }
+    rt
+}
+
+/// Time series stored in chunks under a namespace of the store.
+#[derive(Clone)]
+pub struct TimeSeriesStore {
+    store: Store,
+    namespace: Path,
+    chunk_span: u64,
+}
+
+impl TimeSeriesStore {
+    /// Creates a time series store in the namespace, with chunks of a day.
+    pub fn new(store: Store, namespace: Path) -> Self {
+        Self {
+            store,
+            namespace,
+            chunk_span: DEFAULT_CHUNK_SPAN.as_millis() as u64,
+        }
+    }
+
+    /// Sets the span of time of the chunks. It must not change for existing series.
+    pub fn with_chunk_span(mut self, span: Duration) -> Self {
+        self.chunk_span = (span.as_millis() as u64).max(1);
+        self
+    }
+
+    /// Appends points to the series. A point replaces the point at the same timestamp.
+    /// Series names must be lowercase path parts, e.g. "portfolio_value".
+    pub async fn append(&self, series: &str, points: &[DataPoint]) -> Result<(), BoxError> {
+        validate_series(series)?;
+        if let Some(p) = points.iter().find(|p| !p.value.is_finite()) {
+            return Err(format!("invalid value {} at {}", p.value, p.timestamp).into());
+        }
+
+        let mut points = points.to_vec();
+        points.sort_by_key(|p| p.timestamp);
+        for group in
+            points.chunk_by(|a, b| self.chunk_of(a.timestamp) == self.chunk_of(b.timestamp))
+        {
+            self.update_chunk(series, self.chunk_of(group[0].timestamp), |chunk| {
+                for p in group {
+                    match chunk.binary_search_by_key(&p.timestamp, |c| c.timestamp) {
+                        Ok(i) => chunk[i] = *p,
+                        Err(i) => chunk.insert(i, *p),
+                    }
+                }
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the points of the series in the time range `[start, end)`, in time order.
+    pub async fn range(
+        &self,
+        series: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<DataPoint>, BoxError> {
+        validate_series(series)?;
+        let mut rt = Vec::new();
+        for chunk in self.chunks(series).await? {
+            if chunk + self.chunk_span <= start || chunk >= end {
+                continue;
+            }
+            let (points, _) = self.load_chunk(series, chunk).await?;
+            rt.extend(
+                points
+                    .into_iter()
+                    .filter(|p| p.timestamp >= start && p.timestamp < end),
+            );
+        }
+        Ok(rt)
+    }
+
+    /// Returns the points of the series in the time range `[start, end)` aggregated into
+    /// windows of `step`, see [`downsample`].
+    pub async fn downsample(
+        &self,
+        series: &str,
+        start: u64,
+        end: u64,
+        step: Duration,
+        aggregation: Aggregation,
+    ) -> Result<Vec<DataPoint>, BoxError> {
+        let points = self.range(series, start, end).await?;
+        Ok(downsample(&points, step.as_millis() as u64, aggregation))
+    }
+
+    /// Replaces the points of the series before `before` with their aggregates in windows
+    /// of `step`, which must divide the chunk span. `before` is rounded down to a window.
+    /// Rolling up a window again keeps its aggregate, except for [`Aggregation::Count`].
+    /// Returns the number of removed points.
+    pub async fn rollup(
+        &self,
+        series: &str,
+        before: u64,
+        step: Duration,
+        aggregation: Aggregation,
+    ) -> Result<usize, BoxError> {
+        validate_series(series)?;
+        let step = step.as_millis() as u64;
+        if step == 0 || !self.chunk_span.is_multiple_of(step) {
+            return Err(format!(
+                "rollup step {}ms must divide the chunk span {}ms",
+                step, self.chunk_span
+            )
+            .into());
+        }
+
+        let before = before - before % step;
+        let mut removed = 0;
+        for chunk in self.chunks(series).await? {
+            if chunk >= before {
+                break;
+            }
+            let mut n_removed = 0;
+            self.update_chunk(series, chunk, |points| {
+                let n = points.partition_point(|p| p.timestamp < before);
+                let mut rolled = downsample(&points[..n], step, aggregation);
+                n_removed = n - rolled.len();
+                rolled.extend_from_slice(&points[n..]);
+                *points = rolled;
+            })
+            .await?;
+            removed += n_removed;
+        }
+        Ok(removed)
+    }
+
+    /// Deletes the points of the series before `before`. Returns the number of deleted points.
+    pub async fn delete_before(&self, series: &str, before: u64) -> Result<usize, BoxError> {
+        validate_series(series)?;
+        let mut deleted = 0;
+        for chunk in self.chunks(series).await? {
+            if chunk >= before {
+                break;
+            }
+            if chunk + self.chunk_span <= before {
+                let (points, _) = self.load_chunk(series, chunk).await?;
+                self.store
+                    .store_delete(&self.namespace.child(series), &chunk_path(chunk))
+                    .await?;
+                deleted += points.len();
+            } else {
+                let mut n_deleted = 0;
+                self.update_chunk(series, chunk, |points| {
+                    n_deleted = points.partition_point(|p| p.timestamp < before);
+                    points.drain(..n_deleted);
+                })
+                .await?;
+                deleted += n_deleted;
+            }
+        }
+        Ok(deleted)
+    }
+
+    fn chunk_of(&self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.chunk_span
+    }
+
+    /// Returns the start times of the chunks of the series, in time order.
+    async fn chunks(&self, series: &str) -> Result<Vec<u64>, BoxError> {
+        let prefix = Path::from(series);
+        let metas = self
+            .store
+            .store_list(&self.namespace, Some(&prefix), &prefix)
+            .await?;
+        let mut chunks: Vec<u64> = metas
+            .iter()
+            .filter_map(|meta| {
+                meta.location
+                    .filename()?
+                    .strip_suffix(".cbor")?
+                    .parse()
+                    .ok()
+            })
+            .collect();
+        chunks.sort_unstable();
+        Ok(chunks)
+    }
+
+    async fn load_chunk(
+        &self,
+        series: &str,
+        chunk: u64,
+    ) -> Result<(Vec<DataPoint>, Option<OsVersion>), BoxError> {
+        match self
+            .store
+            .store_get(&self.namespace.child(series), &chunk_path(chunk))
+            .await
+        {
+            Ok((data, meta)) => {
+                let points: Vec<(u64, f64)> = from_reader(&data[..])?;
+                Ok((
+                    points
+                        .into_iter()
+                        .map(|(timestamp, value)| DataPoint { timestamp, value })
+                        .collect(),
+                    Some(OsVersion {
+                        e_tag: meta.e_tag,
+                        version: meta.version,
+                    }),
+                ))
+            }
+            Err(err) if is_not_found(&err) => Ok((Vec::new(), None)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Updates the points of a chunk with compare-and-swap, retrying on conflicts.
+    /// The chunk is deleted if it has no points left.
+    async fn update_chunk(
+        &self,
+        series: &str,
+        chunk: u64,
+        mut f: impl FnMut(&mut Vec<DataPoint>),
+    ) -> Result<(), BoxError> {
+        let namespace = self.namespace.child(series);
+        let path = chunk_path(chunk);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let (mut points, version) = self.load_chunk(series, chunk).await?;
+            f(&mut points);
+            if points.is_empty() {
+                if version.is_some() {
+                    self.store.store_delete(&namespace, &path).await?;
+                }
+                return Ok(());
+            }
+
+            let points: Vec<(u64, f64)> = points.iter().map(|p| (p.timestamp, p.value)).collect();
+            let data = to_cbor_bytes(&points);
+            if data.len() > MAX_STORE_OBJECT_SIZE {
+                return Err(format!(
+                    "chunk {} of series {} exceeds the object size limit {} with {} points, \
+                     use a shorter chunk span",
+                    chunk,
+                    series,
+                    MAX_STORE_OBJECT_SIZE,
+                    points.len()
+                )
+                .into());
+            }
+
+            let mode = match version {
+                Some(ver) => PutMode::Update(ver),
+                None => PutMode::Create,
+            };
+            match self
+                .store
+                .store_put(&namespace, &path, mode, data.into())
+                .await
+            {
+                Ok(_) => return Ok(()),
+                Err(err) if attempts < MAX_UPDATE_ATTEMPTS && is_conflict(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+fn validate_series(series: &str) -> Result<(), BoxError> {
+    validate_path_part(series)?;
+    if series != series.to_ascii_lowercase() {
+        return Err(format!("series name must be lowercase: {}", series).into());
+    }
+    Ok(())
+}
+
+/// Returns the path of a chunk, zero-padded to list the chunks in time order.
+fn chunk_path(chunk: u64) -> Path {
+    Path::from(format!("{:020}.cbor", chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    const HOUR: u64 = 3600 * 1000;
+    const DAY: u64 = 24 * HOUR;
+
+    #[test]
+    fn test_downsample() {
+        let points: Vec<DataPoint> = [(0, 1.0), (10, 3.0), (100, 5.0), (250, 2.0), (299, 4.0)]
+            .into_iter()
+            .map(|(t, v)| DataPoint::new(t, v))
+            .collect();
+        let values = |agg| {
+            downsample(&points, 100, agg)
+                .into_iter()
+                .map(|p| (p.timestamp, p.value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            values(Aggregation::Mean),
+            vec![(0, 2.0), (100, 5.0), (200, 3.0)]
+        );
+        assert_eq!(
+            values(Aggregation::Max),
+            vec![(0, 3.0), (100, 5.0), (200, 4.0)]
+        );
+        assert_eq!(
+            values(Aggregation::Count),
+            vec![(0, 2.0), (100, 1.0), (200, 2.0)]
+        );
+        assert_eq!(
+            values(Aggregation::Last),
+            vec![(0, 3.0), (100, 5.0), (200, 4.0)]
+        );
+        assert!(downsample(&[], 100, Aggregation::Sum).is_empty());
+        assert_eq!(Aggregation::Min.apply(&[]), None);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_time_series_store() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let ts = TimeSeriesStore::new(store, Path::from("agent"));
+
+        // hourly points over 3 days, appended out of order and across chunks
+        let points: Vec<DataPoint> = (0..72)
+            .rev()
+            .map(|h| DataPoint::new(h * HOUR, h as f64))
+            .collect();
+        ts.append("portfolio_value", &points).await.unwrap();
+        ts.append("sensor", &[DataPoint::new(5, 1.0)])
+            .await
+            .unwrap();
+        assert_eq!(
+            ts.chunks("portfolio_value").await.unwrap(),
+            vec![0, DAY, 2 * DAY]
+        );
+
+        let range = ts
+            .range("portfolio_value", 23 * HOUR, 26 * HOUR)
+            .await
+            .unwrap();
+        assert_eq!(
+            range,
+            vec![
+                DataPoint::new(23 * HOUR, 23.0),
+                DataPoint::new(24 * HOUR, 24.0),
+                DataPoint::new(25 * HOUR, 25.0),
+            ]
+        );
+
+        // replaces the point at the same timestamp
+        ts.append("portfolio_value", &[DataPoint::new(24 * HOUR, 100.0)])
+            .await
+            .unwrap();
+        let daily = ts
+            .downsample(
+                "portfolio_value",
+                0,
+                u64::MAX,
+                Duration::from_secs(24 * 3600),
+                Aggregation::Max,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            daily,
+            vec![
+                DataPoint::new(0, 23.0),
+                DataPoint::new(DAY, 100.0),
+                DataPoint::new(2 * DAY, 71.0),
+            ]
+        );
+
+        // rolls up the first day and a half into 6-hour means
+        let removed = ts
+            .rollup(
+                "portfolio_value",
+                DAY + 12 * HOUR + 1,
+                Duration::from_secs(6 * 3600),
+                Aggregation::Mean,
+            )
+            .await
+            .unwrap();
+        assert_eq!(removed, 36 - 6);
+        let range = ts.range("portfolio_value", 0, 2 * DAY).await.unwrap();
+        assert_eq!(range.len(), 6 + 12);
+        assert_eq!(range[0], DataPoint::new(0, 2.5));
+        assert_eq!(range[6], DataPoint::new(DAY + 12 * HOUR, 36.0));
+        assert!(
+            ts.rollup(
+                "portfolio_value",
+                DAY,
+                Duration::from_secs(7 * 3600),
+                Aggregation::Mean
+            )
+            .await
+            .is_err()
+        );
+
+        let deleted = ts
+            .delete_before("portfolio_value", DAY + 18 * HOUR)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 4 + 2 + 6);
+        assert_eq!(
+            ts.chunks("portfolio_value").await.unwrap(),
+            vec![DAY, 2 * DAY]
+        );
+        let range = ts.range("portfolio_value", 0, u64::MAX).await.unwrap();
+        assert_eq!(range.len(), 6 + 24);
+        assert_eq!(range[0].timestamp, DAY + 18 * HOUR);
+
+        // the other series is not affected
+        assert_eq!(
+            ts.range("sensor", 0, u64::MAX).await.unwrap(),
+            vec![DataPoint::new(5, 1.0)]
+        );
+        assert!(ts.append("Sensor", &[]).await.is_err());
+        assert!(
+            ts.append("sensor", &[DataPoint::new(6, f64::NAN)])
+                .await
+                .is_err()
+        );
+    }
+}
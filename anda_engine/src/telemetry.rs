@@ -0,0 +1,488 @@
+//! Opt-in, anonymized usage telemetry.
+//!
+//! Platform operators running many engines can monitor their fleet from a central
+//! endpoint: a [`Telemetry`] reporter counts feature usage and error classes in memory,
+//! and posts a [`TelemetryReport`] to the endpoint on a schedule. Nothing is collected nor
+//! sent unless the engine is built with [`crate::engine::EngineBuilder::with_telemetry`].
+//!
+//! Reports hold only aggregate counters: no prompts, completions, tool arguments, error
+//! messages, thread IDs nor callers. The engine is identified by an installation ID, a salted
+//! hash of its principal, so reports of an engine can be correlated over time but not traced
+//! back to the engine by the endpoint without the salt.
+//!
+//! ```rust,ignore
+//! let telemetry = Telemetry::new("https://telemetry.example.com/v1/reports", Duration::from_secs(3600))
+//!     .with_token(&token)
+//!     .with_salt(&fleet_salt);
+//! let engine = EngineBuilder::new()
+//!     .with_telemetry(telemetry)
+//!     .build(agent)
+//!     .await?;
+//! ```
+
+use anda_core::{AgentOutput, BoxError};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use structured_logger::unix_ms;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    APP_USER_AGENT,
+    model::{Model, ProviderError, ProviderErrorKind},
+};
+
+/// The configuration of telemetry, e.g. from a config file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TelemetryConfig {
+    /// The URL that reports are posted to. Telemetry is disabled if empty.
+    pub endpoint: String,
+    /// The bearer token of the endpoint.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// The salt of the installation ID. Defaults to the endpoint.
+    #[serde(default)]
+    pub salt: Option<String>,
+    /// The interval of reports in seconds. Defaults to 3600.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+}
+
+impl TelemetryConfig {
+    /// Creates the reporter of the config, `None` if telemetry is disabled.
+    pub fn build(&self) -> Option<Telemetry> {
+        if self.endpoint.is_empty() {
+            return None;
+        }
+        let interval = Duration::from_secs(self.interval_secs.unwrap_or(3600).max(60));
+        let mut telemetry = Telemetry::new(&self.endpoint, interval);
+        if let Some(token) = &self.token {
+            telemetry = telemetry.with_token(token);
+        }
+        if let Some(salt) = &self.salt {
+            telemetry = telemetry.with_salt(salt);
+        }
+        Some(telemetry)
+    }
+}
+
+/// A report of the usage of an engine in a time window.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TelemetryReport {
+    /// The anonymized ID of the engine.
+    pub installation: String,
+    /// The version of the anda_engine crate.
+    pub version: String,
+    pub os: String,
+    pub arch: String,
+    /// The start of the window, unix timestamp in milliseconds.
+    pub window_start: u64,
+    /// The end of the window, unix timestamp in milliseconds.
+    pub window_end: u64,
+    /// The optional features enabled in the engine, e.g. "memory" or "workflows".
+    pub features: BTreeSet<String>,
+    pub agents: u64,
+    pub tools: u64,
+    /// The usage counters, e.g. "agent_run" or "tool_call".
+    pub usage: BTreeMap<String, u64>,
+    /// The error counters per class, e.g. "deadline_exceeded" or "model.quota".
+    pub errors: BTreeMap<String, u64>,
+}
+
+/// The state of a reporter.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TelemetryStats {
+    pub reports: u64,
+    pub failures: u64,
+    /// Unix timestamp in milliseconds.
+    #[serde(default)]
+    pub last_report_at: Option<u64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// Classifies an engine error into a stable class, without its message.
+pub fn error_class(err: &BoxError) -> String {
+    if err.downcast_ref::<ProviderError>().is_some()
+        || err.downcast_ref::<reqwest::Error>().is_some()
+    {
+        return format!("model.{}", ProviderErrorKind::of(err));
+    }
+
+    let msg = err.to_string().to_ascii_lowercase();
+    let has = |patterns: &[&str]| patterns.iter().any(|p| msg.contains(p));
+    let class = if has(&["deadline exceeded", "timed out", "timeout"]) {
+        "deadline_exceeded"
+    } else if has(&["cancelled", "canceled"]) {
+        "cancelled"
+    } else if has(&["read-only"]) {
+        "read_only"
+    } else if has(&["permission", "unauthorized", "forbidden", "api key"]) {
+        "permission"
+    } else if has(&["limit", "quota", "budget", "too many"]) {
+        "limited"
+    } else if has(&["not found"]) {
+        "not_found"
+    } else if has(&["invalid", "missing", "expected"]) {
+        "invalid_input"
+    } else {
+        "other"
+    };
+    class.to_string()
+}
+
+#[derive(Default)]
+struct Window {
+    start: u64,
+    usage: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+struct Attachment {
+    installation: String,
+    features: BTreeSet<String>,
+    agents: u64,
+    tools: u64,
+    model: Option<Model>,
+    // the model error counts at the last snapshot
+    model_errors: BTreeMap<ProviderErrorKind, u64>,
+}
+
+/// Counts the usage of an engine and posts [`TelemetryReport`]s to an endpoint.
+pub struct Telemetry {
+    http: reqwest::Client,
+    endpoint: String,
+    token: Option<String>,
+    salt: String,
+    interval: Duration,
+    attachment: Mutex<Attachment>,
+    window: Mutex<Window>,
+    stats: Mutex<TelemetryStats>,
+}
+
+impl Telemetry {
+    /// Creates a reporter that posts a report to the endpoint every `interval`.
+    pub fn new(endpoint: &str, interval: Duration) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .use_rustls_tls()
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(30))
+                .user_agent(APP_USER_AGENT)
+                .build()
+                .expect("telemetry reqwest client should build"),
+            endpoint: endpoint.to_string(),
+            token: None,
+            salt: endpoint.to_string(),
+            interval,
+            attachment: Mutex::new(Attachment::default()),
+            window: Mutex::new(Window {
+                start: unix_ms(),
+                ..Default::default()
+            }),
+            stats: Mutex::new(TelemetryStats::default()),
+        }
+    }
+
+    /// Sets the bearer token of the endpoint.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Sets the salt of the installation ID, shared by the engines of a fleet that
+    /// want their reports to be correlated by the operator only.
+    pub fn with_salt(mut self, salt: &str) -> Self {
+        self.salt = salt.to_string();
+        self
+    }
+
+    /// Attaches the reporter to the engine being built.
+    pub(crate) fn attach(
+        &self,
+        engine: &Principal,
+        model: Model,
+        features: BTreeSet<String>,
+        agents: usize,
+        tools: usize,
+    ) {
+        let mut attachment = self.attachment.lock().unwrap();
+        attachment.installation = installation_id(&self.salt, engine);
+        attachment.features = features;
+        attachment.agents = agents as u64;
+        attachment.tools = tools as u64;
+        attachment.model_errors = model.errors();
+        attachment.model = Some(model);
+    }
+
+    /// Returns the anonymized ID of the engine, empty before the engine is built.
+    pub fn installation(&self) -> String {
+        self.attachment.lock().unwrap().installation.clone()
+    }
+
+    /// Increments a usage counter.
+    pub fn record_usage(&self, name: &str, n: u64) {
+        if n > 0 {
+            let mut window = self.window.lock().unwrap();
+            *window.usage.entry(name.to_string()).or_default() += n;
+        }
+    }
+
+    /// Increments the counter of an error class, see [`error_class`].
+    pub fn record_error(&self, class: &str) {
+        let mut window = self.window.lock().unwrap();
+        *window.errors.entry(class.to_string()).or_default() += 1;
+    }
+
+    /// Records an agent run and its outcome.
+    pub fn record_run(&self, api_key: bool, res: &Result<AgentOutput, BoxError>) {
+        self.record_usage("agent_run", 1);
+        if api_key {
+            self.record_usage("agent_run.api_key", 1);
+        }
+        match res {
+            Ok(output) => {
+                self.record_usage(
+                    "agent_run.tool_calls",
+                    output.tool_calls.as_ref().map_or(0, |c| c.len() as u64),
+                );
+                self.record_usage("agent_run.requests", output.usage.requests);
+                if output.failed_reason.is_some() {
+                    self.record_error("agent_failed");
+                }
+            }
+            Err(err) => self.record_error(&error_class(err)),
+        }
+    }
+
+    /// Records a tool call and its outcome.
+    pub fn record_tool_call<T>(&self, api_key: bool, res: &Result<T, BoxError>) {
+        self.record_usage("tool_call", 1);
+        if api_key {
+            self.record_usage("tool_call.api_key", 1);
+        }
+        if let Err(err) = res {
+            self.record_error(&error_class(err));
+        }
+    }
+
+    /// Returns the stats of the reporter.
+    pub fn stats(&self) -> TelemetryStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Closes the current window and returns its report, with the model errors since the
+    /// last report.
+    pub fn snapshot(&self, now: u64) -> TelemetryReport {
+        let mut attachment = self.attachment.lock().unwrap();
+        let model_errors = attachment
+            .model
+            .as_ref()
+            .map(|m| m.errors())
+            .unwrap_or_default();
+        let window = {
+            let mut window = self.window.lock().unwrap();
+            for (kind, count) in &model_errors {
+                let delta = count.saturating_sub(
+                    attachment
+                        .model_errors
+                        .get(kind)
+                        .copied()
+                        .unwrap_or_default(),
+                );
+                if delta > 0 {
+                    *window.errors.entry(format!("model.{kind}")).or_default() += delta;
+                }
+            }
+            std::mem::replace(
+                &mut *window,
+                Window {
+                    start: now,
+                    ..Default::default()
+                },
+            )
+        };
+        attachment.model_errors = model_errors;
+
+        TelemetryReport {
+            installation: attachment.installation.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            window_start: window.start,
+            window_end: now,
+            features: attachment.features.clone(),
+            agents: attachment.agents,
+            tools: attachment.tools,
+            usage: window.usage,
+            errors: window.errors,
+        }
+    }
+
+    /// Posts the report of the current window. The counters of a failed report are
+    /// kept for the next one.
+    pub async fn flush(&self) -> Result<TelemetryReport, BoxError> {
+        let report = self.snapshot(unix_ms());
+        let res = self.post(&report).await;
+
+        let mut stats = self.stats.lock().unwrap();
+        match res {
+            Ok(_) => {
+                stats.reports += 1;
+                stats.last_report_at = Some(report.window_end);
+                stats.last_error = None;
+                Ok(report)
+            }
+            Err(err) => {
+                stats.failures += 1;
+                stats.last_error = Some(err.to_string());
+                self.restore(report);
+                Err(err)
+            }
+        }
+    }
+
+    /// Spawns a background task that posts a report every interval,
+    /// and a last time when the token is cancelled.
+    pub fn spawn(self: Arc<Self>, token: CancellationToken) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let cancelled = tokio::select! {
+                    _ = token.cancelled() => true,
+                    _ = tokio::time::sleep(self.interval) => false,
+                };
+                if let Err(err) = self.flush().await {
+                    log::warn!("failed to post telemetry: {}", err);
+                }
+                if cancelled {
+                    return;
+                }
+            }
+        })
+    }
+
+    async fn post(&self, report: &TelemetryReport) -> Result<(), BoxError> {
+        let mut req = self.http.post(&self.endpoint).json(report);
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+        let res = req.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(format!("telemetry report failed, status: {}", status).into());
+        }
+        Ok(())
+    }
+
+    /// Merges the counters of a report back into the current window.
+    fn restore(&self, report: TelemetryReport) {
+        let mut window = self.window.lock().unwrap();
+        window.start = window.start.min(report.window_start);
+        for (name, n) in report.usage {
+            *window.usage.entry(name).or_default() += n;
+        }
+        for (class, n) in report.errors {
+            *window.errors.entry(class).or_default() += n;
+        }
+    }
+}
+
+/// Derives the anonymized ID of an engine from the salt.
+fn installation_id(salt: &str, engine: &Principal) -> String {
+    let mut hasher = blake3::Hasher::new_derive_key("anda telemetry installation");
+    hasher.update(salt.as_bytes());
+    hasher.update(&[0]);
+    hasher.update(engine.as_slice());
+    hasher.finalize().to_hex()[..32].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::Usage;
+
+    #[test]
+    fn test_error_class() {
+        let err: BoxError = "deadline exceeded".into();
+        assert_eq!(error_class(&err), "deadline_exceeded");
+        let err: BoxError = "caller does not have permission to access the thread".into();
+        assert_eq!(error_class(&err), "permission");
+        let err: BoxError = "tool search not found".into();
+        assert_eq!(error_class(&err), "not_found");
+        let err: BoxError = "something odd: secret prompt".into();
+        assert_eq!(error_class(&err), "other");
+        let err: BoxError = ProviderError::new(ProviderErrorKind::Quota, "slow down".into()).into();
+        assert_eq!(error_class(&err), "model.quota");
+    }
+
+    #[test]
+    fn test_telemetry_report() {
+        let telemetry = Telemetry::new("http://localhost:9/reports", Duration::from_secs(60));
+        assert_eq!(telemetry.installation(), "");
+
+        let engine = Principal::from_text("aaaaa-aa").unwrap();
+        let features = BTreeSet::from(["memory".to_string()]);
+        telemetry.attach(&engine, Model::not_implemented(), features.clone(), 2, 5);
+        let installation = telemetry.installation();
+        assert_eq!(installation.len(), 32);
+        assert!(!installation.contains("aaaaa"));
+        assert_ne!(
+            installation,
+            installation_id("another salt", &engine),
+            "the salt should change the installation ID"
+        );
+
+        telemetry.record_run(
+            true,
+            &Ok(AgentOutput {
+                usage: Usage {
+                    requests: 2,
+                    ..Default::default()
+                },
+                failed_reason: Some("the prompt is secret".to_string()),
+                ..Default::default()
+            }),
+        );
+        telemetry.record_run(false, &Err("deadline exceeded".into()));
+        telemetry.record_tool_call::<()>(false, &Err("tool x not found".into()));
+
+        let report = telemetry.snapshot(unix_ms());
+        assert_eq!(report.installation, installation);
+        assert_eq!(report.features, features);
+        assert_eq!((report.agents, report.tools), (2, 5));
+        assert_eq!(report.usage["agent_run"], 2);
+        assert_eq!(report.usage["agent_run.api_key"], 1);
+        assert_eq!(report.usage["agent_run.requests"], 2);
+        assert_eq!(report.usage["tool_call"], 1);
+        assert!(!report.usage.contains_key("tool_call.api_key"));
+        assert_eq!(report.errors["agent_failed"], 1);
+        assert_eq!(report.errors["deadline_exceeded"], 1);
+        assert_eq!(report.errors["not_found"], 1);
+        assert!(!serde_json::to_string(&report).unwrap().contains("secret"));
+
+        // the window is closed, and a failed report is merged back
+        let next = telemetry.snapshot(unix_ms());
+        assert!(next.usage.is_empty() && next.errors.is_empty());
+        telemetry.restore(report);
+        let next = telemetry.snapshot(unix_ms());
+        assert_eq!(next.usage["agent_run"], 2);
+        assert_eq!(next.errors["deadline_exceeded"], 1);
+    }
+
+    #[test]
+    fn test_telemetry_config() {
+        assert!(TelemetryConfig::default().build().is_none());
+        let config: TelemetryConfig =
+            serde_json::from_str(r#"{"endpoint":"https://t.example.com","salt":"fleet"}"#).unwrap();
+        let telemetry = config.build().unwrap();
+        assert_eq!(telemetry.endpoint, "https://t.example.com");
+        assert_eq!(telemetry.salt, "fleet");
+        assert_eq!(telemetry.interval, Duration::from_secs(3600));
+    }
+}
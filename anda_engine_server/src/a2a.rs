@@ -0,0 +1,766 @@
+//! An adapter of the [A2A (Agent2Agent) protocol](https://github.com/google/A2A) over the engines,
+//! so that A2A orchestrators can invoke anda agents.
+//!
+//! Each engine is an A2A agent at `/a2a/{engine_id}`, with its card at
+//! `/a2a/{engine_id}/.well-known/agent.json` and its exported agents as skills.
+//! A task runs the skill named by the `skill` metadata of the message, or the default agent.
+//! The task's session ID is the thread ID. Supported JSON-RPC methods:
+//! - `tasks/send`: runs a task and returns it when it is done;
+//! - `tasks/sendSubscribe`: runs a task and streams its status and artifact updates with SSE;
+//! - `tasks/get`: returns a task of the caller;
+//! - `tasks/cancel`: cancels a running task of the caller.
+//!
+//! Text parts of a message are the prompt, file and data parts are attached as [`Resource`]s.
+//! The agent's reply is the text artifact "response", and each generated resource is a
+//! file artifact.
+
+use anda_core::{AgentInput, AgentOutput, ByteBufB64, RequestMeta, Resource, Xid};
+use anda_engine::engine::Engine;
+use axum::{
+    Extension,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        IntoResponse, Json, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::{collections::HashMap, convert::Infallible, str::FromStr};
+use structured_logger::unix_ms;
+use tokio::sync::{RwLock, mpsc};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    auth::VerifiedCaller,
+    handler::{AppState, public_url, verify_user},
+};
+
+/// How long finished tasks are kept for `tasks/get`, in milliseconds.
+const TASK_TTL_MS: u64 = 3600 * 1000;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+const TASK_NOT_FOUND: i64 = -32001;
+const TASK_NOT_CANCELABLE: i64 = -32002;
+const PUSH_NOT_SUPPORTED: i64 = -32003;
+
+#[derive(Debug, Deserialize)]
+pub struct JsonRpcRequest {
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: &'static str,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl JsonRpcResponse {
+    fn result(id: Value, result: impl Serialize) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result: Some(serde_json::to_value(result).unwrap_or_default()),
+            error: None,
+        }
+    }
+
+    fn error(id: Value, code: i64, message: String) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result: None,
+            error: Some(JsonRpcError { code, message }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskState {
+    Submitted,
+    Working,
+    InputRequired,
+    Completed,
+    Canceled,
+    Failed,
+    Unknown,
+}
+
+impl TaskState {
+    fn is_final(&self) -> bool {
+        matches!(self, Self::Completed | Self::Canceled | Self::Failed)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// The base64 encoded content of the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Part {
+    Text { text: String },
+    File { file: FileContent },
+    Data { data: Value },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Message {
+    /// "user" or "agent".
+    pub role: String,
+    pub parts: Vec<Part>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Map<String, Value>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskStatus {
+    pub state: TaskState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<Message>,
+    /// RFC 3339 timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+impl TaskStatus {
+    fn new(state: TaskState, message: Option<Message>) -> Self {
+        Self {
+            state,
+            message,
+            timestamp: chrono::DateTime::from_timestamp_millis(unix_ms() as i64)
+                .map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Artifact {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub parts: Vec<Part>,
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_chunk: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Task {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub status: TaskStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<Vec<Artifact>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Map<String, Value>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSendParams {
+    pub id: String,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub message: Message,
+    #[serde(default)]
+    pub metadata: Option<Map<String, Value>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskIdParams {
+    pub id: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskStatusUpdateEvent {
+    pub id: String,
+    pub status: TaskStatus,
+    #[serde(rename = "final")]
+    pub is_final: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskArtifactUpdateEvent {
+    pub id: String,
+    pub artifact: Artifact,
+}
+
+struct TaskEntry {
+    task: Task,
+    owner: Principal,
+    cancel: CancellationToken,
+    updated_at: u64,
+}
+
+/// The A2A tasks of the server, kept in memory.
+#[derive(Default)]
+pub struct A2aTasks {
+    tasks: RwLock<HashMap<String, TaskEntry>>,
+}
+
+impl A2aTasks {
+    async fn get(&self, owner: &Principal, id: &str) -> Option<Task> {
+        let tasks = self.tasks.read().await;
+        tasks
+            .get(id)
+            .filter(|e| &e.owner == owner)
+            .map(|e| e.task.clone())
+    }
+
+    /// Adds a task, or returns an error if a task with the ID is running.
+    async fn submit(
+        &self,
+        owner: Principal,
+        task: Task,
+        cancel: CancellationToken,
+    ) -> Result<(), String> {
+        let now_ms = unix_ms();
+        let mut tasks = self.tasks.write().await;
+        tasks.retain(|_, e| !e.task.status.state.is_final() || e.updated_at + TASK_TTL_MS > now_ms);
+        if let Some(e) = tasks.get(&task.id)
+            && !e.task.status.state.is_final()
+        {
+            return Err(format!("task {} is running", task.id));
+        }
+        tasks.insert(
+            task.id.clone(),
+            TaskEntry {
+                task,
+                owner,
+                cancel,
+                updated_at: now_ms,
+            },
+        );
+        Ok(())
+    }
+
+    async fn update(&self, task: &Task) {
+        let mut tasks = self.tasks.write().await;
+        if let Some(e) = tasks.get_mut(&task.id) {
+            e.task = task.clone();
+            e.updated_at = unix_ms();
+        }
+    }
+
+    async fn cancel(&self, owner: &Principal, id: &str) -> Result<Task, (i64, String)> {
+        let tasks = self.tasks.read().await;
+        let e = tasks
+            .get(id)
+            .filter(|e| &e.owner == owner)
+            .ok_or_else(|| (TASK_NOT_FOUND, format!("task {} not found", id)))?;
+        if e.task.status.state.is_final() {
+            return Err((
+                TASK_NOT_CANCELABLE,
+                format!("task {} is not cancelable", id),
+            ));
+        }
+        e.cancel.cancel();
+        Ok(e.task.clone())
+    }
+}
+
+/// GET /a2a/{id}/.well-known/agent.json
+pub async fn get_a2a_agent_card(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let Some((id, engine)) = get_engine(&app, &id) else {
+        return (StatusCode::NOT_FOUND, format!("engine {id} not found")).into_response();
+    };
+
+    let skills: Vec<Value> = engine
+        .agent_cards()
+        .await
+        .into_iter()
+        .map(|card| {
+            json!({
+                "id": card.name,
+                "name": card.name,
+                "description": card.description,
+                "tags": card.capabilities,
+                "inputModes": modes_of(&card.input_modalities),
+                "outputModes": ["text/plain"],
+            })
+        })
+        .collect();
+    Json(json!({
+        "name": engine.name(),
+        "description": engine.description(),
+        "url": format!("{}/a2a/{}", public_url(&app, &headers), id.to_text()),
+        "version": env!("CARGO_PKG_VERSION"),
+        "capabilities": {
+            "streaming": true,
+            "pushNotifications": false,
+            "stateTransitionHistory": false,
+        },
+        "defaultInputModes": ["text/plain"],
+        "defaultOutputModes": ["text/plain"],
+        "skills": skills,
+    }))
+    .into_response()
+}
+
+/// POST /a2a/{id}
+pub async fn a2a_engine(
+    State(app): State<AppState>,
+    Extension(caller): Extension<VerifiedCaller>,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Response {
+    let req: JsonRpcRequest = match serde_json::from_slice(&body) {
+        Ok(req) => req,
+        Err(err) => {
+            return Json(JsonRpcResponse::error(
+                Value::Null,
+                PARSE_ERROR,
+                format!("invalid JSON-RPC request: {err}"),
+            ))
+            .into_response();
+        }
+    };
+    let Some((_, engine)) = get_engine(&app, &id) else {
+        return Json(JsonRpcResponse::error(
+            req.id,
+            INVALID_PARAMS,
+            format!("engine {id} not found"),
+        ))
+        .into_response();
+    };
+
+    log::info!(
+        method = req.method.as_str(),
+        engine = id,
+        caller = caller.principal.to_text();
+        "a2a_engine",
+    );
+    match req.method.as_str() {
+        "tasks/send" => {
+            let params: TaskSendParams = match serde_json::from_value(req.params) {
+                Ok(params) => params,
+                Err(err) => return invalid_params(req.id, err),
+            };
+            let (task, input, cancel) = match submit(&app, &engine, &caller, params).await {
+                Ok(rt) => rt,
+                Err(err) => {
+                    return Json(JsonRpcResponse::error(req.id, INVALID_PARAMS, err))
+                        .into_response();
+                }
+            };
+            let task = run_task(&app, &engine, &caller, task, input, cancel, None).await;
+            Json(JsonRpcResponse::result(req.id, task)).into_response()
+        }
+        "tasks/sendSubscribe" => {
+            let params: TaskSendParams = match serde_json::from_value(req.params) {
+                Ok(params) => params,
+                Err(err) => return invalid_params(req.id, err),
+            };
+            let (task, input, cancel) = match submit(&app, &engine, &caller, params).await {
+                Ok(rt) => rt,
+                Err(err) => {
+                    return Json(JsonRpcResponse::error(req.id, INVALID_PARAMS, err))
+                        .into_response();
+                }
+            };
+            let (tx, rx) = mpsc::channel::<Value>(16);
+            let rpc_id = req.id;
+            tokio::spawn(async move {
+                run_task(&app, &engine, &caller, task, input, cancel, Some(tx)).await;
+            });
+            let stream = futures_util::stream::unfold(rx, move |mut rx| {
+                let rpc_id = rpc_id.clone();
+                async move {
+                    let event = rx.recv().await?;
+                    let res = JsonRpcResponse::result(rpc_id, event);
+                    let event = Event::default().json_data(res).unwrap_or_default();
+                    Some((Ok::<_, Infallible>(event), rx))
+                }
+            });
+            Sse::new(stream)
+                .keep_alive(KeepAlive::default())
+                .into_response()
+        }
+        "tasks/get" => {
+            let params: TaskIdParams = match serde_json::from_value(req.params) {
+                Ok(params) => params,
+                Err(err) => return invalid_params(req.id, err),
+            };
+            match app.a2a.get(&caller.principal, &params.id).await {
+                Some(task) => Json(JsonRpcResponse::result(req.id, task)).into_response(),
+                None => Json(JsonRpcResponse::error(
+                    req.id,
+                    TASK_NOT_FOUND,
+                    format!("task {} not found", params.id),
+                ))
+                .into_response(),
+            }
+        }
+        "tasks/cancel" => {
+            let params: TaskIdParams = match serde_json::from_value(req.params) {
+                Ok(params) => params,
+                Err(err) => return invalid_params(req.id, err),
+            };
+            match app.a2a.cancel(&caller.principal, &params.id).await {
+                Ok(task) => Json(JsonRpcResponse::result(req.id, task)).into_response(),
+                Err((code, msg)) => Json(JsonRpcResponse::error(req.id, code, msg)).into_response(),
+            }
+        }
+        "tasks/pushNotification/set" | "tasks/pushNotification/get" => {
+            Json(JsonRpcResponse::error(
+                req.id,
+                PUSH_NOT_SUPPORTED,
+                "push notification is not supported".to_string(),
+            ))
+            .into_response()
+        }
+        method => Json(JsonRpcResponse::error(
+            req.id,
+            METHOD_NOT_FOUND,
+            format!("method {method} not found"),
+        ))
+        .into_response(),
+    }
+}
+
+fn invalid_params(id: Value, err: serde_json::Error) -> Response {
+    Json(JsonRpcResponse::error(
+        id,
+        INVALID_PARAMS,
+        format!("invalid params: {err}"),
+    ))
+    .into_response()
+}
+
+fn get_engine(app: &AppState, id: &str) -> Option<(Principal, Engine)> {
+    let id = if id == "default" {
+        app.default_engine
+    } else {
+        Principal::from_text(id).ok()?
+    };
+    app.engines.get(&id).map(|e| (id, e.clone()))
+}
+
+/// Submits the task of the message, and returns it with the agent input.
+async fn submit(
+    app: &AppState,
+    engine: &Engine,
+    caller: &VerifiedCaller,
+    params: TaskSendParams,
+) -> Result<(Task, AgentInput, CancellationToken), String> {
+    let skill = params
+        .message
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get("skill"))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| engine.default_agent());
+    let (prompt, resources) = message_to_input(&params.message)?;
+    let thread = match &params.session_id {
+        Some(id) => Some(Xid::from_str(id).map_err(|err| format!("invalid sessionId: {err}"))?),
+        None => None,
+    };
+    let mut meta = Some(RequestMeta {
+        thread,
+        ..Default::default()
+    });
+    verify_user(app, caller, &mut meta);
+
+    let input = AgentInput {
+        name: skill,
+        prompt,
+        resources: (!resources.is_empty()).then_some(resources),
+        meta,
+    };
+    let task = Task {
+        id: params.id,
+        session_id: params.session_id,
+        status: TaskStatus::new(TaskState::Submitted, None),
+        artifacts: None,
+        metadata: params.metadata,
+    };
+    let cancel = CancellationToken::new();
+    app.a2a
+        .submit(caller.principal, task.clone(), cancel.clone())
+        .await?;
+    Ok((task, input, cancel))
+}
+
+/// Runs the task to its end, sending the updates to the subscriber if any.
+async fn run_task(
+    app: &AppState,
+    engine: &Engine,
+    caller: &VerifiedCaller,
+    mut task: Task,
+    input: AgentInput,
+    cancel: CancellationToken,
+    updates: Option<mpsc::Sender<Value>>,
+) -> Task {
+    let notify = |task: &Task, is_final: bool| {
+        let event = TaskStatusUpdateEvent {
+            id: task.id.clone(),
+            status: task.status.clone(),
+            is_final,
+        };
+        let updates = updates.clone();
+        async move {
+            if let Some(tx) = updates {
+                let _ = tx
+                    .send(serde_json::to_value(event).unwrap_or_default())
+                    .await;
+            }
+        }
+    };
+
+    task.status = TaskStatus::new(TaskState::Working, None);
+    app.a2a.update(&task).await;
+    notify(&task, false).await;
+
+    let run = async {
+        match &caller.api_key {
+            Some(secret) => engine.agent_run_with_api_key(secret, input).await,
+            None => engine.agent_run(caller.principal, input).await,
+        }
+    };
+    let res = tokio::select! {
+        res = run => Some(res),
+        _ = cancel.cancelled() => None,
+    };
+
+    match res {
+        None => {
+            task.status = TaskStatus::new(TaskState::Canceled, None);
+        }
+        Some(Err(err)) => {
+            task.status = TaskStatus::new(TaskState::Failed, Some(agent_message(err.to_string())));
+        }
+        Some(Ok(output)) => {
+            if let Some(thread) = &output.thread {
+                task.session_id = Some(thread.to_string());
+            }
+            let artifacts = output_to_artifacts(&output);
+            if let Some(tx) = &updates {
+                for artifact in &artifacts {
+                    let event = TaskArtifactUpdateEvent {
+                        id: task.id.clone(),
+                        artifact: artifact.clone(),
+                    };
+                    let _ = tx
+                        .send(serde_json::to_value(event).unwrap_or_default())
+                        .await;
+                }
+            }
+            task.artifacts = Some(artifacts);
+            task.status = match output.failed_reason {
+                Some(reason) => TaskStatus::new(TaskState::Failed, Some(agent_message(reason))),
+                None => TaskStatus::new(TaskState::Completed, Some(agent_message(output.content))),
+            };
+        }
+    }
+
+    app.a2a.update(&task).await;
+    notify(&task, true).await;
+    task
+}
+
+fn agent_message(text: String) -> Message {
+    Message {
+        role: "agent".to_string(),
+        parts: vec![Part::Text { text }],
+        metadata: None,
+    }
+}
+
+/// Converts a message to the prompt and the resources of an agent input.
+fn message_to_input(message: &Message) -> Result<(String, Vec<Resource>), String> {
+    let mut texts: Vec<&str> = Vec::new();
+    let mut resources = Vec::new();
+    for part in &message.parts {
+        match part {
+            Part::Text { text } => texts.push(text),
+            Part::File { file } => {
+                let blob = match &file.bytes {
+                    Some(bytes) => Some(ByteBufB64::from(
+                        BASE64_STANDARD
+                            .decode(bytes)
+                            .map_err(|err| format!("invalid file bytes: {err}"))?,
+                    )),
+                    None => None,
+                };
+                if blob.is_none() && file.uri.is_none() {
+                    return Err("file part requires bytes or uri".to_string());
+                }
+                resources.push(Resource {
+                    tag: tag_of_mime(file.mime_type.as_deref()),
+                    uri: file.uri.clone(),
+                    name: file.name.clone(),
+                    mime_type: file.mime_type.clone(),
+                    size: blob.as_ref().map(|b| b.len()),
+                    blob,
+                    ..Default::default()
+                });
+            }
+            Part::Data { data } => {
+                let data = serde_json::to_vec(data).map_err(|err| err.to_string())?;
+                resources.push(Resource {
+                    tag: "json".to_string(),
+                    mime_type: Some("application/json".to_string()),
+                    size: Some(data.len()),
+                    blob: Some(ByteBufB64::from(data)),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    Ok((texts.join("\n"), resources))
+}
+
+/// Converts the agent's reply and generated resources to artifacts.
+fn output_to_artifacts(output: &AgentOutput) -> Vec<Artifact> {
+    let mut artifacts = Vec::new();
+    if !output.content.is_empty() {
+        artifacts.push(Artifact {
+            name: Some("response".to_string()),
+            description: None,
+            parts: vec![Part::Text {
+                text: output.content.clone(),
+            }],
+            index: 0,
+            last_chunk: Some(true),
+        });
+    }
+    for resource in output.resources.iter().flatten() {
+        artifacts.push(Artifact {
+            name: resource.name.clone(),
+            description: resource.description.clone(),
+            parts: vec![Part::File {
+                file: FileContent {
+                    name: resource.name.clone(),
+                    mime_type: resource.mime_type.clone(),
+                    bytes: resource
+                        .blob
+                        .as_ref()
+                        .map(|b| BASE64_STANDARD.encode(b.as_slice())),
+                    uri: resource.uri.clone(),
+                },
+            }],
+            index: artifacts.len() as u32,
+            last_chunk: Some(true),
+        });
+    }
+    artifacts
+}
+
+/// Returns the resource tag of a MIME type, e.g. "image" for "image/png".
+fn tag_of_mime(mime_type: Option<&str>) -> String {
+    let Some(mime_type) = mime_type else {
+        return "file".to_string();
+    };
+    match mime_type.split_once('/') {
+        Some(("image" | "audio" | "video" | "text", _)) => {
+            mime_type.split('/').next().unwrap_or("file").to_string()
+        }
+        Some((_, sub)) => sub.split(['+', ';']).next().unwrap_or(sub).to_string(),
+        None => "file".to_string(),
+    }
+}
+
+/// Returns the A2A input modes of the input modalities of an agent card.
+fn modes_of(modalities: &[String]) -> Vec<String> {
+    modalities
+        .iter()
+        .map(|m| match m.as_str() {
+            "text" => "text/plain".to_string(),
+            "image" | "audio" | "video" => format!("{m}/*"),
+            other => format!("application/{other}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_to_input() {
+        let message: Message = serde_json::from_value(json!({
+            "role": "user",
+            "parts": [
+                {"type": "text", "text": "describe this image"},
+                {"type": "file", "file": {"name": "cat.png", "mimeType": "image/png", "bytes": "aGVsbG8="}},
+                {"type": "file", "file": {"mimeType": "application/pdf", "uri": "https://example.com/a.pdf"}},
+                {"type": "data", "data": {"width": 100}},
+            ]
+        }))
+        .unwrap();
+        let (prompt, resources) = message_to_input(&message).unwrap();
+        assert_eq!(prompt, "describe this image");
+        assert_eq!(resources.len(), 3);
+        assert_eq!(resources[0].tag, "image");
+        assert_eq!(resources[0].blob.as_ref().unwrap().as_slice(), b"hello");
+        assert_eq!(resources[1].tag, "pdf");
+        assert_eq!(
+            resources[1].uri.as_deref(),
+            Some("https://example.com/a.pdf")
+        );
+        assert_eq!(resources[2].tag, "json");
+
+        let output = AgentOutput {
+            content: "a cat".to_string(),
+            resources: Some(vec![resources[0].clone()]),
+            ..Default::default()
+        };
+        let artifacts = output_to_artifacts(&output);
+        assert_eq!(artifacts.len(), 2);
+        assert_eq!(artifacts[1].index, 1);
+        let artifact = serde_json::to_value(&artifacts[1]).unwrap();
+        assert_eq!(artifact["parts"][0]["type"], "file");
+        assert_eq!(artifact["parts"][0]["file"]["bytes"], "aGVsbG8=");
+        assert_eq!(artifact["lastChunk"], true);
+
+        let status = serde_json::to_value(TaskStatusUpdateEvent {
+            id: "1".to_string(),
+            status: TaskStatus::new(TaskState::InputRequired, None),
+            is_final: false,
+        })
+        .unwrap();
+        assert_eq!(status["status"]["state"], "input-required");
+        assert_eq!(status["final"], false);
+    }
+}
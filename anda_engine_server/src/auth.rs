@@ -0,0 +1,334 @@
+use anda_engine::management::ApiKey;
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use candid::Principal;
+use ic_auth_verifier::envelope::{ANONYMOUS_PRINCIPAL, SignedEnvelope, unix_ms};
+use ic_cose_types::cose::sha3_256;
+
+use crate::handler::AppState;
+
+/// The header of API keys issued by engine managers.
+pub static API_KEY_HEADER: &str = "x-api-key";
+
+/// The maximum body size of authenticated requests, same as axum's default body limit.
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// The caller of a request, verified by the `ic_auth` middleware.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedCaller {
+    /// The caller principal, anonymous if the request is not signed.
+    pub principal: Principal,
+
+    /// Whether the request is signed by a delegated session key, e.g. from Internet Identity.
+    pub delegated: bool,
+
+    /// When the delegation chain or the bearer token expires, unix timestamp in milliseconds.
+    pub expires_at: Option<u64>,
+
+    /// The subject of the OIDC bearer token the caller is authenticated by.
+    pub oidc_subject: Option<String>,
+
+    /// The API key the caller presents. It is verified by the engine for each call,
+    /// against the key's scope and limits.
+    pub api_key: Option<String>,
+}
+
+impl VerifiedCaller {
+    pub fn anonymous() -> Self {
+        Self {
+            principal: ANONYMOUS_PRINCIPAL,
+            delegated: false,
+            expires_at: None,
+            oidc_subject: None,
+            api_key: None,
+        }
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.principal == ANONYMOUS_PRINCIPAL
+    }
+}
+
+/// Middleware that verifies the signed envelope of a request, including Internet Identity
+/// delegation chains, and adds the [`VerifiedCaller`] to the request extensions.
+///
+/// POST requests to engines are verified against the SHA3-256 digest of the body and the
+/// engine ID as the delegation target. Requests with an invalid signature or an expired
+/// delegation are rejected, unsigned requests are anonymous unless authentication is required.
+/// If OIDC is configured, requests with a bearer token are authenticated by the token instead.
+/// Requests with an `x-api-key` header call as the key's principal, see [`ApiKey::principal_of`].
+pub async fn ic_auth(State(app): State<AppState>, req: Request, next: Next) -> Response {
+    if let Some(secret) = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        let caller = match ApiKey::id_of(secret) {
+            Ok(id) => VerifiedCaller {
+                principal: ApiKey::principal_of(&id),
+                delegated: false,
+                expires_at: None,
+                oidc_subject: None,
+                api_key: Some(secret.to_string()),
+            },
+            Err(err) => return (StatusCode::UNAUTHORIZED, err.to_string()).into_response(),
+        };
+        let mut req = req;
+        req.extensions_mut().insert(caller);
+        return next.run(req).await;
+    }
+
+    if let Some(oidc) = &app.oidc
+        && let Some(token) = bearer_token(req.headers())
+    {
+        let caller = match oidc.verify(token).await {
+            Ok((principal, claims)) => VerifiedCaller {
+                principal,
+                delegated: false,
+                expires_at: Some(claims.exp * 1000),
+                oidc_subject: Some(claims.sub),
+                api_key: None,
+            },
+            Err(err) => {
+                log::warn!("invalid bearer token: {}", err);
+                return (StatusCode::UNAUTHORIZED, err).into_response();
+            }
+        };
+        let mut req = req;
+        req.extensions_mut().insert(caller);
+        return next.run(req).await;
+    }
+
+    let Some(se) = SignedEnvelope::try_from(req.headers()) else {
+        if app.require_auth && req.method() == Method::POST {
+            return (StatusCode::UNAUTHORIZED, "authentication required").into_response();
+        }
+        let mut req = req;
+        req.extensions_mut().insert(VerifiedCaller::anonymous());
+        return next.run(req).await;
+    };
+
+    let (target, req) = if req.method() == Method::POST {
+        let id = req.uri().path().trim_start_matches('/');
+        let target = if id == "default" {
+            Some(app.default_engine)
+        } else {
+            Principal::from_text(id).ok()
+        };
+
+        let (parts, body) = req.into_parts();
+        let body = match to_bytes(body, MAX_BODY_SIZE).await {
+            Ok(body) => body,
+            Err(err) => {
+                return (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("failed to read body: {err}"),
+                )
+                    .into_response();
+            }
+        };
+        let digest = sha3_256(&body);
+        if let Err(err) = se.verify(unix_ms(), target, Some(digest.as_slice())) {
+            log::warn!(sender = se.sender().to_text(); "invalid signed envelope: {}", err);
+            return (StatusCode::UNAUTHORIZED, err).into_response();
+        }
+        (target, Request::from_parts(parts, Body::from(body)))
+    } else {
+        if let Err(err) = se.verify(unix_ms(), None, None) {
+            return (StatusCode::UNAUTHORIZED, err).into_response();
+        }
+        (None, req)
+    };
+
+    let caller = VerifiedCaller {
+        principal: se.sender(),
+        delegated: se.delegation.is_some(),
+        expires_at: se
+            .delegation
+            .as_ref()
+            .and_then(|ds| ds.iter().map(|d| d.delegation.expiration / 1_000_000).min()),
+        oidc_subject: None,
+        api_key: None,
+    };
+    log::debug!(
+        caller = caller.principal.to_text(),
+        delegated = caller.delegated,
+        target = target.map(|t| t.to_text());
+        "verified caller",
+    );
+    let mut req = req;
+    req.extensions_mut().insert(caller);
+    next.run(req).await
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|t| !t.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::RequestMeta;
+    use axum::{Extension, Router, middleware, routing};
+    use ed25519_consensus::SigningKey;
+    use ic_agent::{
+        Identity,
+        identity::{BasicIdentity, DelegatedIdentity, Delegation, SignedDelegation},
+    };
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        sync::Arc,
+    };
+    use tokio::net::TcpListener;
+
+    use crate::{a2a::A2aTasks, handler::verify_user};
+
+    fn app_state(trusted_connectors: BTreeSet<Principal>) -> AppState {
+        AppState {
+            engines: Arc::new(BTreeMap::new()),
+            default_engine: Principal::management_canister(),
+            start_time_ms: unix_ms(),
+            require_auth: false,
+            trusted_connectors: Arc::new(trusted_connectors),
+            oidc: None,
+            public_url: None,
+            a2a: Arc::new(A2aTasks::default()),
+        }
+    }
+
+    async fn serve(app: AppState) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new()
+            .route("/{*id}", routing::post(echo_caller))
+            .layer(middleware::from_fn_with_state(app.clone(), ic_auth))
+            .with_state(app);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{}/default", addr)
+    }
+
+    async fn echo_caller(Extension(caller): Extension<VerifiedCaller>) -> String {
+        format!("{} {}", caller.principal.to_text(), caller.delegated)
+    }
+
+    /// Returns an identity that signs with a session key, delegated by the user until
+    /// `expiration`, unix timestamp in nanoseconds.
+    fn delegated(user: &BasicIdentity, expiration: u64) -> DelegatedIdentity {
+        let session = BasicIdentity::from_signing_key(SigningKey::from([2u8; 32]));
+        let delegation = Delegation {
+            pubkey: session.public_key().unwrap(),
+            expiration,
+            targets: None,
+        };
+        let signature = user.sign_arbitrary(&delegation.signable()).unwrap();
+        DelegatedIdentity::new_unchecked(
+            user.public_key().unwrap(),
+            Box::new(session),
+            vec![SignedDelegation {
+                delegation,
+                signature: signature.signature.unwrap(),
+            }],
+        )
+    }
+
+    async fn post(
+        url: &str,
+        identity: Option<&dyn Identity>,
+        signed_body: &[u8],
+        body: &[u8],
+    ) -> (reqwest::StatusCode, String) {
+        let mut headers = HeaderMap::new();
+        if let Some(identity) = identity {
+            let se = SignedEnvelope::sign_digest(identity, sha3_256(signed_body).into()).unwrap();
+            se.to_headers(&mut headers).unwrap();
+        }
+        let res = reqwest::Client::new()
+            .post(url)
+            .headers(headers)
+            .body(body.to_vec())
+            .send()
+            .await
+            .unwrap();
+        (res.status(), res.text().await.unwrap())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_ic_auth() {
+        let url = serve(app_state(BTreeSet::new())).await;
+        let user = BasicIdentity::from_signing_key(SigningKey::from([1u8; 32]));
+        let user_id = user.sender().unwrap();
+
+        let (status, text) = post(&url, None, b"", b"hello").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(text, format!("{} false", ANONYMOUS_PRINCIPAL.to_text()));
+
+        let (status, text) = post(&url, Some(&user), b"hello", b"hello").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(text, format!("{} false", user_id.to_text()));
+
+        // the body is tampered with after signing
+        let (status, _) = post(&url, Some(&user), b"hello", b"hello!").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let now_ns = unix_ms() * 1_000_000;
+        let session = delegated(&user, now_ns + 3_600_000_000_000);
+        let (status, text) = post(&url, Some(&session), b"hello", b"hello").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(text, format!("{} true", user_id.to_text()));
+
+        let expired = delegated(&user, now_ns - 1_000_000_000);
+        let (status, _) = post(&url, Some(&expired), b"hello", b"hello").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_verify_user() {
+        let connector = Principal::from_slice(&[1, 2, 3]);
+        let app = app_state(BTreeSet::from([connector]));
+        let user_meta = || {
+            Some(RequestMeta {
+                user: Some("alice".to_string()),
+                ..Default::default()
+            })
+        };
+
+        // a trusted connector sets the user it serves
+        let caller = VerifiedCaller {
+            principal: connector,
+            ..VerifiedCaller::anonymous()
+        };
+        let mut meta = user_meta();
+        verify_user(&app, &caller, &mut meta);
+        assert_eq!(meta.unwrap().user.as_deref(), Some("alice"));
+
+        // other callers are the user
+        let other = Principal::from_slice(&[4, 5, 6]);
+        let caller = VerifiedCaller {
+            principal: other,
+            ..VerifiedCaller::anonymous()
+        };
+        let mut meta = user_meta();
+        verify_user(&app, &caller, &mut meta);
+        assert_eq!(meta.unwrap().user, Some(other.to_text()));
+        let mut meta = None;
+        verify_user(&app, &caller, &mut meta);
+        assert_eq!(meta.unwrap().user, Some(other.to_text()));
+
+        let mut meta = user_meta();
+        verify_user(&app, &VerifiedCaller::anonymous(), &mut meta);
+        assert_eq!(meta.unwrap().user, None);
+        let mut meta = None;
+        verify_user(&app, &VerifiedCaller::anonymous(), &mut meta);
+        assert!(meta.is_none());
+    }
+}
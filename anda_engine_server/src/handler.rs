@@ -1,44 +1,50 @@
-use anda_core::{AgentInput, ToolInput, Value};
-use anda_engine::engine::{Engine, Information};
+use anda_core::{AgentInput, KeyRotation, RequestMeta, ToolInput, Value, Xid};
+use anda_engine::{
+    engine::{Engine, Information},
+    fleet::FleetConfig,
+    management::CreateApiKeyArgs,
+};
 use axum::{
+    Extension,
     extract::{Path, State},
     http::StatusCode,
     response::IntoResponse,
 };
 use candid::Principal;
 use ciborium::from_reader;
-use ic_auth_verifier::envelope::{ANONYMOUS_PRINCIPAL, SignedEnvelope, unix_ms};
 use ic_cose_types::to_cbor_bytes;
 use ic_tee_agent::{
     RPCRequest, RPCResponse,
     http::{Content, ContentWithSHA3},
 };
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
-use crate::types::*;
+use crate::{a2a::A2aTasks, auth::VerifiedCaller, oidc::OidcVerifier, types::*};
 
 #[derive(Clone)]
 pub struct AppState {
     pub(crate) engines: Arc<BTreeMap<Principal, Engine>>,
     pub(crate) default_engine: Principal,
     pub(crate) start_time_ms: u64,
+    /// Rejects unsigned requests to engines.
+    pub(crate) require_auth: bool,
+    /// Connectors, e.g. chat bots, trusted to set `RequestMeta.user` for their users.
+    pub(crate) trusted_connectors: Arc<BTreeSet<Principal>>,
+    /// Authenticates bearer tokens if configured.
+    pub(crate) oidc: Option<Arc<OidcVerifier>>,
+    /// The public URL of the server, for the endpoints in agent cards.
+    pub(crate) public_url: Option<String>,
+    /// The tasks of A2A callers.
+    pub(crate) a2a: Arc<A2aTasks>,
 }
 
 /// GET /.well-known/information
 pub async fn get_information(
     State(app): State<AppState>,
+    Extension(caller): Extension<VerifiedCaller>,
     headers: http::HeaderMap,
 ) -> impl IntoResponse {
-    let caller = if let Some(se) = SignedEnvelope::try_from(&headers) {
-        match se.verify(unix_ms(), None, None) {
-            Ok(_) => se.sender(),
-            Err(_) => ANONYMOUS_PRINCIPAL,
-        }
-    } else {
-        ANONYMOUS_PRINCIPAL
-    };
-
     let info = AppInformation {
         engines: app
             .engines
@@ -50,11 +56,12 @@ pub async fn get_information(
                 agents: vec![],
                 tools: vec![],
                 endpoint: "".to_string(),
+                read_only: e.is_read_only(),
             })
             .collect(),
         default_engine: app.default_engine,
         start_time_ms: app.start_time_ms,
-        caller,
+        caller: caller.principal,
     };
 
     match Content::from(&headers) {
@@ -63,6 +70,106 @@ pub async fn get_information(
     }
 }
 
+/// GET /.well-known/metrics
+pub async fn get_metrics(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+) -> impl IntoResponse {
+    let metrics = AppMetrics {
+        engines: app.engines.values().map(|e| e.metrics()).collect(),
+        start_time_ms: app.start_time_ms,
+    };
+
+    match Content::from(&headers) {
+        Content::CBOR(_, _) => Content::CBOR(metrics, None).into_response(),
+        _ => Content::JSON(metrics, None).into_response(),
+    }
+}
+
+/// GET /.well-known/agents
+pub async fn get_agent_cards(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+) -> impl IntoResponse {
+    let base = public_url(&app, &headers);
+    let mut cards = Vec::new();
+    for (id, engine) in app.engines.iter() {
+        for mut card in engine.agent_cards().await {
+            card.endpoint = format!("{}/{}", base, id.to_text());
+            cards.push(card);
+        }
+    }
+
+    match Content::from(&headers) {
+        Content::CBOR(_, _) => Content::CBOR(cards, None).into_response(),
+        _ => Content::JSON(cards, None).into_response(),
+    }
+}
+
+/// GET /.well-known/agents/{id}
+pub async fn get_engine_agent_cards(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let id = if &id == "default" {
+        app.default_engine
+    } else if let Ok(id) = Principal::from_text(&id) {
+        id
+    } else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid engine id: {id:?}"),
+        )
+            .into_response();
+    };
+
+    match app.engines.get(&id) {
+        Some(engine) => {
+            let base = public_url(&app, &headers);
+            let mut cards = engine.agent_cards().await;
+            for card in cards.iter_mut() {
+                card.endpoint = format!("{}/{}", base, id.to_text());
+            }
+            match Content::from(&headers) {
+                Content::CBOR(_, _) => Content::CBOR(cards, None).into_response(),
+                _ => Content::JSON(cards, None).into_response(),
+            }
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            format!("engine {} not found", id.to_text()),
+        )
+            .into_response(),
+    }
+}
+
+/// GET /.well-known/schemas
+pub async fn get_schemas() -> impl IntoResponse {
+    Content::JSON(anda_core::wire_schemas(), None).into_response()
+}
+
+/// GET /.well-known/schemas/{name}
+pub async fn get_schema(Path(name): Path<String>) -> impl IntoResponse {
+    let name = name.strip_suffix(".json").unwrap_or(&name);
+    match anda_core::wire_schemas().remove(name) {
+        Some(schema) => Content::JSON(schema, None).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("schema {name:?} not found")).into_response(),
+    }
+}
+
+/// Returns the configured public URL, or the URL of the request's host.
+pub(crate) fn public_url(app: &AppState, headers: &http::HeaderMap) -> String {
+    if let Some(url) = &app.public_url {
+        return url.trim_end_matches('/').to_string();
+    }
+    let host = headers
+        .get(http::header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("localhost");
+    format!("https://{}", host)
+}
+
 /// GET /.well-known/information/{id}
 pub async fn get_engine_information(
     State(app): State<AppState>,
@@ -100,7 +207,7 @@ pub async fn get_engine_information(
 /// POST /{*id}
 pub async fn anda_engine(
     State(app): State<AppState>,
-    headers: http::HeaderMap,
+    Extension(caller): Extension<VerifiedCaller>,
     Path(id): Path<String>,
     ct: ContentWithSHA3<RPCRequest>,
 ) -> impl IntoResponse {
@@ -116,24 +223,16 @@ pub async fn anda_engine(
             .into_response();
     };
 
-    let (req, hash) = match &ct {
-        ContentWithSHA3::CBOR(req, hash) => (req, hash),
-        ContentWithSHA3::JSON(req, hash) => (req, hash),
-    };
-
-    let caller = if let Some(se) = SignedEnvelope::try_from(&headers) {
-        match se.verify(unix_ms(), Some(id), Some(hash.as_slice())) {
-            Ok(_) => se.sender(),
-            Err(_) => ANONYMOUS_PRINCIPAL,
-        }
-    } else {
-        ANONYMOUS_PRINCIPAL
+    let req = match &ct {
+        ContentWithSHA3::CBOR(req, _) => req,
+        ContentWithSHA3::JSON(req, _) => req,
     };
 
     log::info!(
         method = req.method.as_str(),
         agent = id.to_text(),
-        caller = caller.to_text();
+        caller = caller.principal.to_text(),
+        delegated = caller.delegated;
         "anda_engine",
     );
     let res = engine_run(req, &app, caller, id).await;
@@ -146,7 +245,7 @@ pub async fn anda_engine(
 async fn engine_run(
     req: &RPCRequest,
     app: &AppState,
-    caller: Principal,
+    caller: VerifiedCaller,
     id: Principal,
 ) -> RPCResponse {
     let engine = app
@@ -156,25 +255,133 @@ async fn engine_run(
 
     match req.method.as_str() {
         "agent_run" => {
-            let args: (AgentInput,) = from_reader(req.params.as_slice())
+            let mut args: (AgentInput,) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            verify_user(app, &caller, &mut args.0.meta);
+            let res = match &caller.api_key {
+                Some(secret) => engine.agent_run_with_api_key(secret, args.0).await,
+                None => engine.agent_run(caller.principal, args.0).await,
+            }
+            .map_err(|err| format!("failed to run agent: {err:?}"))?;
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "tool_call" => {
+            let mut args: (ToolInput<Value>,) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            verify_user(app, &caller, &mut args.0.meta);
+            let res = match &caller.api_key {
+                Some(secret) => engine.tool_call_with_api_key(secret, args.0).await,
+                None => engine.tool_call(caller.principal, args.0).await,
+            }
+            .map_err(|err| format!("failed to call tool: {err:?}"))?;
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "information" => {
+            let res = engine.information();
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "health" => {
+            let res = engine.health();
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "metrics" => {
+            let res = engine.metrics();
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "remaining_quota" => {
+            let args: (Option<Principal>,) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            let res = match &caller.api_key {
+                Some(secret) => engine.remaining_quota_with_api_key(secret, args.0).await,
+                None => engine.remaining_quota(&caller.principal, args.0).await,
+            }
+            .map_err(|err| format!("failed to get remaining quota: {err:?}"))?;
+            Ok(to_cbor_bytes(&res).into())
+        }
+        _ if caller.api_key.is_some() => Err(format!(
+            "{} is not allowed with an API key",
+            req.method.as_str()
+        )),
+        "create_api_key" => {
+            let args: (CreateApiKeyArgs,) = from_reader(req.params.as_slice())
                 .map_err(|err| format!("failed to decode params: {err:?}"))?;
             let res = engine
-                .agent_run(caller, args.0)
+                .create_api_key(&caller.principal, args.0)
                 .await
-                .map_err(|err| format!("failed to run agent: {err:?}"))?;
+                .map_err(|err| format!("failed to create API key: {err:?}"))?;
             Ok(to_cbor_bytes(&res).into())
         }
-        "tool_call" => {
-            let args: (ToolInput<Value>,) = from_reader(req.params.as_slice())
+        "list_api_keys" => {
+            let res = engine
+                .api_keys(&caller.principal)
+                .await
+                .map_err(|err| format!("failed to list API keys: {err:?}"))?;
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "rotate_api_key" => {
+            let args: (Xid,) = from_reader(req.params.as_slice())
                 .map_err(|err| format!("failed to decode params: {err:?}"))?;
             let res = engine
-                .tool_call(caller, args.0)
+                .rotate_api_key(&caller.principal, &args.0)
                 .await
-                .map_err(|err| format!("failed to call tool: {err:?}"))?;
+                .map_err(|err| format!("failed to rotate API key: {err:?}"))?;
             Ok(to_cbor_bytes(&res).into())
         }
-        "information" => {
-            let res = engine.information();
+        "revoke_api_key" => {
+            let args: (Xid,) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            let res = engine
+                .revoke_api_key(&caller.principal, &args.0)
+                .await
+                .map_err(|err| format!("failed to revoke API key: {err:?}"))?;
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "key_rotated" => {
+            let args: (KeyRotation,) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            engine
+                .key_rotated(caller.principal, args.0)
+                .await
+                .map_err(|err| format!("failed to accept key rotation: {err:?}"))?;
+            Ok(to_cbor_bytes(&()).into())
+        }
+        "submit_feedback" => {
+            let args: (Xid, i8, Option<String>) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            engine
+                .submit_feedback(caller.principal, &args.0, args.1, args.2)
+                .await
+                .map_err(|err| format!("failed to submit feedback: {err:?}"))?;
+            Ok(to_cbor_bytes(&()).into())
+        }
+        "apply_fleet_config" => {
+            let args: (FleetConfig,) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            engine
+                .apply_fleet_config(&caller.principal, args.0)
+                .await
+                .map_err(|err| format!("failed to apply fleet config: {err:?}"))?;
+            Ok(to_cbor_bytes(&()).into())
+        }
+        "rollouts" => {
+            let res = engine.rollouts();
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "set_rollout_percent" => {
+            let args: (String, u8) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            let res = engine
+                .set_rollout_percent(&caller.principal, &args.0, args.1)
+                .map_err(|err| format!("failed to set rollout percent: {err:?}"))?;
+            Ok(to_cbor_bytes(&res).into())
+        }
+        "resume_workflow" => {
+            let args: (Xid, String, Value) = from_reader(req.params.as_slice())
+                .map_err(|err| format!("failed to decode params: {err:?}"))?;
+            let res = engine
+                .resume_workflow(&caller.principal, &args.0, &args.1, args.2)
+                .await
+                .map_err(|err| format!("failed to resume workflow: {err:?}"))?;
             Ok(to_cbor_bytes(&res).into())
         }
         method => Err(format!(
@@ -183,3 +390,22 @@ async fn engine_run(
         )),
     }
 }
+
+/// Replaces the free-text `RequestMeta.user` with the verified caller, unless the caller is
+/// a trusted connector that serves its own users.
+pub(crate) fn verify_user(app: &AppState, caller: &VerifiedCaller, meta: &mut Option<RequestMeta>) {
+    if app.trusted_connectors.contains(&caller.principal) {
+        return;
+    }
+    let user = (!caller.is_anonymous()).then(|| caller.principal.to_text());
+    match meta {
+        Some(meta) => meta.user = user,
+        None if user.is_some() => {
+            *meta = Some(RequestMeta {
+                user,
+                ..Default::default()
+            })
+        }
+        None => {}
+    }
+}
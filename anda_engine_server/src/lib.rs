@@ -1,16 +1,29 @@
 use anda_core::BoxError;
-use anda_engine::engine::Engine;
-use axum::{Router, routing};
+use anda_engine::{engine::Engine, mtls::MtlsConfig};
+use axum::{Router, middleware, routing};
 use candid::Principal;
-use std::{collections::BTreeMap, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
 use structured_logger::unix_ms;
 use tokio::signal;
 use tokio_util::sync::CancellationToken;
 
+mod a2a;
+mod auth;
 mod handler;
+mod oidc;
+mod tls;
 mod types;
 
+pub use auth::VerifiedCaller;
 use handler::*;
+pub use oidc::{OidcClaims, OidcConfig};
+pub use tls::TlsListener;
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -21,6 +34,11 @@ pub struct ServerBuilder {
     addr: String,
     engines: BTreeMap<Principal, Engine>,
     default_engine: Option<Principal>,
+    require_auth: bool,
+    trusted_connectors: BTreeSet<Principal>,
+    oidc: Option<OidcConfig>,
+    mtls: Option<MtlsConfig>,
+    public_url: Option<String>,
 }
 
 impl Default for ServerBuilder {
@@ -40,6 +58,11 @@ impl ServerBuilder {
             addr: "127.0.0.1:8042".to_string(),
             engines: BTreeMap::new(),
             default_engine: None,
+            require_auth: false,
+            trusted_connectors: BTreeSet::new(),
+            oidc: None,
+            mtls: None,
+            public_url: None,
         }
     }
 
@@ -68,6 +91,41 @@ impl ServerBuilder {
         self
     }
 
+    /// Rejects unsigned requests to engines. By default they run as the anonymous caller.
+    pub fn with_require_auth(mut self, require_auth: bool) -> Self {
+        self.require_auth = require_auth;
+        self
+    }
+
+    /// Sets the connectors, e.g. chat bots, trusted to set `RequestMeta.user` for the users
+    /// they serve. For other callers, the server replaces `RequestMeta.user` with the
+    /// verified caller principal.
+    pub fn with_trusted_connectors(mut self, connectors: BTreeSet<Principal>) -> Self {
+        self.trusted_connectors = connectors;
+        self
+    }
+
+    /// Accepts OIDC bearer tokens from the provider, for end users without ICP identities.
+    /// Token subjects are mapped to principals, see [`OidcConfig::principal_of`].
+    pub fn with_oidc(mut self, config: OidcConfig) -> Self {
+        self.oidc = Some(config);
+        self
+    }
+
+    /// Sets the public URL of the server, e.g. "https://agents.example.com", for the
+    /// endpoints in agent cards. By default it is derived from the request's host.
+    pub fn with_public_url(mut self, public_url: String) -> Self {
+        self.public_url = Some(public_url);
+        self
+    }
+
+    /// Serves over mutual TLS, for engine-to-engine traffic. Clients must present a pinned
+    /// certificate; requests are still verified by their signed envelopes.
+    pub fn with_mtls(mut self, config: MtlsConfig) -> Self {
+        self.mtls = Some(config);
+        self
+    }
+
     pub async fn serve(
         self,
         signal: impl Future<Output = ()> + Send + 'static,
@@ -87,6 +145,13 @@ impl ServerBuilder {
             engines: Arc::new(self.engines),
             default_engine,
             start_time_ms: unix_ms(),
+            require_auth: self.require_auth,
+            trusted_connectors: Arc::new(self.trusted_connectors),
+            oidc: self
+                .oidc
+                .map(|config| Arc::new(oidc::OidcVerifier::new(config))),
+            public_url: self.public_url,
+            a2a: Arc::new(a2a::A2aTasks::default()),
         };
         let app = Router::new()
             .route("/", routing::get(get_information))
@@ -95,7 +160,21 @@ impl ServerBuilder {
                 "/.well-known/information/{id}",
                 routing::get(get_engine_information),
             )
+            .route("/.well-known/agents", routing::get(get_agent_cards))
+            .route(
+                "/.well-known/agents/{id}",
+                routing::get(get_engine_agent_cards),
+            )
+            .route("/.well-known/schemas", routing::get(get_schemas))
+            .route("/.well-known/schemas/{name}", routing::get(get_schema))
+            .route("/.well-known/metrics", routing::get(get_metrics))
+            .route("/a2a/{id}", routing::post(a2a::a2a_engine))
+            .route(
+                "/a2a/{id}/.well-known/agent.json",
+                routing::get(a2a::get_a2a_agent_card),
+            )
             .route("/{*id}", routing::post(anda_engine))
+            .layer(middleware::from_fn_with_state(state.clone(), auth::ic_auth))
             .with_state(state);
 
         let addr: SocketAddr = self.addr.parse()?;
@@ -107,9 +186,19 @@ impl ServerBuilder {
             addr
         );
 
-        axum::serve(listener, app)
-            .with_graceful_shutdown(signal)
-            .await?;
+        match self.mtls {
+            Some(mtls) => {
+                let listener = TlsListener::new(listener, Arc::new(mtls.server_config()?))?;
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(signal)
+                    .await?
+            }
+            None => {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(signal)
+                    .await?
+            }
+        }
 
         Ok(())
     }
@@ -0,0 +1,403 @@
+//! OpenID Connect bearer-token authentication.
+//!
+//! Validates the JWT ID or access tokens issued by an OIDC provider, e.g. Okta, Auth0,
+//! Azure AD or Keycloak, for deployments whose end users don't have ICP identities:
+//! - The signing keys are fetched from the provider's JWKS, discovered from the issuer if
+//!   not configured, and refreshed when a token is signed by an unknown key;
+//! - RS256, ES256 and EdDSA signatures are supported;
+//! - The issuer, audience, expiration and not-before claims are checked.
+//!
+//! Token subjects are mapped to internal identities: explicitly mapped subjects get their
+//! configured principals, others get a principal derived from the issuer and the subject.
+
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use candid::Principal;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use structured_logger::unix_ms;
+use tokio::sync::RwLock;
+
+/// The JWKS is refreshed after this duration.
+const JWKS_TTL_MS: u64 = 3600 * 1000;
+
+/// The JWKS is refetched for an unknown key at most once in this duration.
+const JWKS_MIN_REFRESH_MS: u64 = 60 * 1000;
+
+/// The OIDC provider and the tokens it issues to accept.
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    /// The issuer URL, must match the `iss` claim, e.g. "https://example.okta.com".
+    pub issuer: String,
+
+    /// The accepted audiences, one of them must be in the `aud` claim.
+    pub audiences: BTreeSet<String>,
+
+    /// The JWKS URL. Discovered from the issuer's OpenID configuration if not set.
+    pub jwks_uri: Option<String>,
+
+    /// The clock skew allowed when checking `exp` and `nbf`, in seconds.
+    pub leeway_secs: u64,
+
+    /// Subjects mapped to existing principals.
+    pub subjects: BTreeMap<String, Principal>,
+}
+
+impl OidcConfig {
+    /// Creates a config accepting the tokens of the issuer for the audience, e.g. the client ID.
+    pub fn new(issuer: String, audience: String) -> Self {
+        Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            audiences: BTreeSet::from([audience]),
+            jwks_uri: None,
+            leeway_secs: 60,
+            subjects: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_audience(mut self, audience: String) -> Self {
+        self.audiences.insert(audience);
+        self
+    }
+
+    pub fn with_jwks_uri(mut self, jwks_uri: String) -> Self {
+        self.jwks_uri = Some(jwks_uri);
+        self
+    }
+
+    /// Maps a token subject to an existing principal, e.g. a user's ICP identity.
+    pub fn with_subject(mut self, subject: String, principal: Principal) -> Self {
+        self.subjects.insert(subject, principal);
+        self
+    }
+
+    /// Returns the internal identity of a token subject.
+    pub fn principal_of(&self, subject: &str) -> Principal {
+        match self.subjects.get(subject) {
+            Some(principal) => *principal,
+            None => Principal::self_authenticating(format!("oidc:{}#{}", self.issuer, subject)),
+        }
+    }
+}
+
+/// The claims of a validated token.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OidcClaims {
+    pub iss: String,
+    pub sub: String,
+    /// Expiration time, unix timestamp in seconds.
+    pub exp: u64,
+    #[serde(default)]
+    pub nbf: Option<u64>,
+    #[serde(default)]
+    pub aud: Audience,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+    #[default]
+    None,
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains_any(&self, audiences: &BTreeSet<String>) -> bool {
+        match self {
+            Audience::None => false,
+            Audience::One(aud) => audiences.contains(aud),
+            Audience::Many(auds) => auds.iter().any(|aud| audiences.contains(aud)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// A JSON Web Key, only the fields of the supported key types.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default)]
+    pub kid: Option<String>,
+    #[serde(default)]
+    pub crv: Option<String>,
+    #[serde(default)]
+    pub n: Option<String>,
+    #[serde(default)]
+    pub e: Option<String>,
+    #[serde(default)]
+    pub x: Option<String>,
+    #[serde(default)]
+    pub y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Default)]
+struct JwksCache {
+    keys: Vec<Jwk>,
+    fetched_at: u64,
+}
+
+/// Validates bearer tokens against an OIDC provider.
+pub struct OidcVerifier {
+    config: OidcConfig,
+    client: reqwest::Client,
+    jwks: RwLock<JwksCache>,
+}
+
+impl OidcVerifier {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            jwks: RwLock::new(JwksCache::default()),
+        }
+    }
+
+    /// Validates a token and returns the internal identity of its subject.
+    pub async fn verify(&self, token: &str) -> Result<(Principal, OidcClaims), String> {
+        let now_ms = unix_ms();
+        let header = decode_header(token)?;
+        {
+            let jwks = self.jwks.read().await;
+            if now_ms < jwks.fetched_at + JWKS_TTL_MS
+                && find_key(&jwks.keys, header.kid.as_deref(), &header.alg).is_some()
+            {
+                return self.verify_with(&jwks.keys, token, now_ms);
+            }
+        }
+
+        let mut jwks = self.jwks.write().await;
+        // another request may have refreshed the keys while waiting for the lock
+        let expired = now_ms >= jwks.fetched_at + JWKS_TTL_MS;
+        let missing = find_key(&jwks.keys, header.kid.as_deref(), &header.alg).is_none();
+        if expired || (missing && now_ms >= jwks.fetched_at + JWKS_MIN_REFRESH_MS) {
+            match self.fetch_jwks().await {
+                Ok(keys) => {
+                    jwks.keys = keys;
+                    jwks.fetched_at = now_ms;
+                }
+                Err(err) => log::error!("failed to fetch OIDC JWKS: {}", err),
+            }
+        }
+        self.verify_with(&jwks.keys, token, now_ms)
+    }
+
+    fn verify_with(
+        &self,
+        keys: &[Jwk],
+        token: &str,
+        now_ms: u64,
+    ) -> Result<(Principal, OidcClaims), String> {
+        let claims = verify_token(keys, token)?;
+        let config = &self.config;
+        if claims.iss.trim_end_matches('/') != config.issuer {
+            return Err(format!("invalid token issuer {:?}", claims.iss));
+        }
+        if !claims.aud.contains_any(&config.audiences) {
+            return Err("invalid token audience".to_string());
+        }
+        let now = now_ms / 1000;
+        if claims.exp + config.leeway_secs <= now {
+            return Err("token has expired".to_string());
+        }
+        if let Some(nbf) = claims.nbf
+            && nbf > now + config.leeway_secs
+        {
+            return Err("token is not valid yet".to_string());
+        }
+        Ok((config.principal_of(&claims.sub), claims))
+    }
+
+    async fn fetch_jwks(&self) -> Result<Vec<Jwk>, String> {
+        let jwks_uri = match &self.config.jwks_uri {
+            Some(uri) => uri.clone(),
+            None => {
+                let url = format!("{}/.well-known/openid-configuration", self.config.issuer);
+                let conf: Value = self.get_json(&url).await?;
+                conf.get("jwks_uri")
+                    .and_then(|v| v.as_str())
+                    .ok_or("jwks_uri not found in the OpenID configuration")?
+                    .to_string()
+            }
+        };
+        let jwks: JwkSet = self.get_json(&jwks_uri).await?;
+        Ok(jwks.keys)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|err| format!("failed to fetch {url}: {err}"))?;
+        if !res.status().is_success() {
+            return Err(format!("failed to fetch {url}: {}", res.status()));
+        }
+        res.json()
+            .await
+            .map_err(|err| format!("failed to decode {url}: {err}"))
+    }
+}
+
+fn decode_part(part: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(part.trim_end_matches('='))
+        .map_err(|err| format!("invalid token encoding: {err}"))
+}
+
+fn decode_header(token: &str) -> Result<JwtHeader, String> {
+    let header = token.split('.').next().unwrap_or_default();
+    serde_json::from_slice(&decode_part(header)?)
+        .map_err(|err| format!("invalid token header: {err}"))
+}
+
+fn find_key<'a>(keys: &'a [Jwk], kid: Option<&str>, alg: &str) -> Option<&'a Jwk> {
+    let kty = match alg {
+        "RS256" => "RSA",
+        "ES256" => "EC",
+        "EdDSA" => "OKP",
+        _ => return None,
+    };
+    keys.iter()
+        .filter(|k| k.kty == kty)
+        .find(|k| kid.is_none() || k.kid.as_deref() == kid)
+}
+
+/// Verifies the token signature with the keys and returns its claims, without checking them.
+fn verify_token(keys: &[Jwk], token: &str) -> Result<OidcClaims, String> {
+    let mut parts = token.split('.');
+    let (header, payload, sig) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(h), Some(p), Some(s), None) => (h, p, s),
+        _ => return Err("invalid token format".to_string()),
+    };
+    let jwt_header = decode_header(token)?;
+    let key = find_key(keys, jwt_header.kid.as_deref(), &jwt_header.alg).ok_or_else(|| {
+        format!(
+            "no key found for token alg {:?} and kid {:?}",
+            jwt_header.alg, jwt_header.kid
+        )
+    })?;
+
+    let message = &token[..header.len() + 1 + payload.len()];
+    let sig = decode_part(sig)?;
+    let field = |v: &Option<String>| {
+        v.as_deref()
+            .ok_or_else(|| "invalid JWK".to_string())
+            .and_then(decode_part)
+    };
+    let verified = match jwt_header.alg.as_str() {
+        "RS256" => RsaPublicKeyComponents {
+            n: field(&key.n)?,
+            e: field(&key.e)?,
+        }
+        .verify(
+            &signature::RSA_PKCS1_2048_8192_SHA256,
+            message.as_bytes(),
+            &sig,
+        ),
+        "ES256" if key.crv.as_deref() == Some("P-256") => {
+            let mut point = vec![0x04];
+            point.extend(field(&key.x)?);
+            point.extend(field(&key.y)?);
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                .verify(message.as_bytes(), &sig)
+        }
+        "EdDSA" if key.crv.as_deref() == Some("Ed25519") => {
+            UnparsedPublicKey::new(&signature::ED25519, field(&key.x)?)
+                .verify(message.as_bytes(), &sig)
+        }
+        alg => return Err(format!("unsupported token alg {alg:?}")),
+    };
+    verified.map_err(|_| "invalid token signature".to_string())?;
+
+    serde_json::from_slice(&decode_part(payload)?)
+        .map_err(|err| format!("invalid token claims: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+    use serde_json::json;
+
+    fn sign(key: &Ed25519KeyPair, kid: &str, claims: Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({"alg": "EdDSA", "kid": kid}).to_string());
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let message = format!("{header}.{payload}");
+        let sig = URL_SAFE_NO_PAD.encode(key.sign(message.as_bytes()));
+        format!("{message}.{sig}")
+    }
+
+    #[test]
+    fn test_oidc_verify() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let keys = vec![Jwk {
+            kty: "OKP".to_string(),
+            kid: Some("k1".to_string()),
+            crv: Some("Ed25519".to_string()),
+            n: None,
+            e: None,
+            x: Some(URL_SAFE_NO_PAD.encode(key.public_key().as_ref())),
+            y: None,
+        }];
+        let alice =
+            Principal::from_text("77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe")
+                .unwrap();
+        let verifier = OidcVerifier::new(
+            OidcConfig::new("https://idp.example.com/".to_string(), "anda".to_string())
+                .with_subject("alice".to_string(), alice),
+        );
+        let now_ms = unix_ms();
+        let now = now_ms / 1000;
+        let claims = |sub: &str, aud: Value, exp: u64| json!({"iss": "https://idp.example.com", "sub": sub, "aud": aud, "exp": exp});
+
+        let token = sign(&key, "k1", claims("alice", json!(["x", "anda"]), now + 60));
+        let (principal, claims_) = verifier.verify_with(&keys, &token, now_ms).unwrap();
+        assert_eq!(principal, alice);
+        assert_eq!(claims_.sub, "alice");
+
+        let token = sign(&key, "k1", claims("bob", json!("anda"), now + 60));
+        let (bob, _) = verifier.verify_with(&keys, &token, now_ms).unwrap();
+        assert_ne!(bob, alice);
+        assert_eq!(bob, verifier.config.principal_of("bob"));
+
+        let token = sign(&key, "k1", claims("bob", json!("other"), now + 60));
+        assert!(verifier.verify_with(&keys, &token, now_ms).is_err());
+        let token = sign(&key, "k1", claims("bob", json!("anda"), now - 120));
+        assert_eq!(
+            verifier.verify_with(&keys, &token, now_ms).unwrap_err(),
+            "token has expired"
+        );
+        let token = sign(&key, "k2", claims("bob", json!("anda"), now + 60));
+        assert!(verifier.verify_with(&keys, &token, now_ms).is_err());
+
+        let token = sign(&key, "k1", claims("bob", json!("anda"), now + 60));
+        let tampered = token.replace(
+            token.split('.').nth(1).unwrap(),
+            &URL_SAFE_NO_PAD.encode(claims("alice", json!("anda"), now + 60).to_string()),
+        );
+        assert_eq!(
+            verifier.verify_with(&keys, &tampered, now_ms).unwrap_err(),
+            "invalid token signature"
+        );
+    }
+}
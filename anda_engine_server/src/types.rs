@@ -2,6 +2,8 @@ use anda_engine::context::Information;
 use candid::Principal;
 use serde::{Deserialize, Serialize};
 
+pub use anda_engine::fleet::EngineMetrics;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AppInformation {
     pub engines: Vec<Information>,
@@ -9,3 +11,9 @@ pub struct AppInformation {
     pub caller: Principal,
     pub start_time_ms: u64,
 }
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AppMetrics {
+    pub engines: Vec<EngineMetrics>,
+    pub start_time_ms: u64,
+}
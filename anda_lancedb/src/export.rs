@@ -0,0 +1,259 @@
+//! Arrow/Parquet export and import of knowledge namespaces.
+//!
+//! Exported files use a plain Arrow schema so they can be analyzed or bulk-loaded with
+//! standard tooling (pandas, polars, DuckDB, Spark...):
+//!
+//! | column | type                               |
+//! |--------|------------------------------------|
+//! | id     | Utf8                               |
+//! | user   | Utf8                               |
+//! | text   | Utf8                               |
+//! | meta   | Utf8 (JSON object)                 |
+//! | vec    | FixedSizeList<Float32>[dimensions] |
+//!
+//! Vectors are stored as Float16 in LanceDB and widened to Float32 on export for compatibility.
+//! Import accepts both Float16 and Float32 vectors.
+
+use anda_core::BoxError;
+use arrow_array::{
+    Array, ArrayRef, Float16Array, Float32Array,
+    cast::AsArray,
+    types::{Float16Type, Float32Type},
+};
+use futures::TryStreamExt;
+use parquet::{
+    arrow::{ArrowWriter, arrow_reader::ParquetRecordBatchReaderBuilder},
+    basic::{Compression, ZstdLevel},
+    file::{metadata::KeyValue, properties::WriterProperties, reader::ChunkReader},
+};
+use std::{io::Write, sync::Arc};
+
+use crate::{knowledge::KnowledgeStore, lancedb::*};
+
+/// The text columns of a knowledge namespace, in order.
+pub const KNOWLEDGE_TEXT_COLUMNS: [&str; 4] = ["id", "user", "text", "meta"];
+/// The vector column of a knowledge namespace.
+pub const KNOWLEDGE_VEC_COLUMN: &str = "vec";
+
+/// Returns the Arrow schema of exported knowledge with the given vector dimensions.
+pub fn knowledge_export_schema(dim: i32) -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("user", DataType::Utf8, false),
+        Field::new("text", DataType::Utf8, false),
+        Field::new("meta", DataType::Utf8, false),
+        Field::new(
+            KNOWLEDGE_VEC_COLUMN,
+            DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, false)), dim),
+            false,
+        ),
+    ]))
+}
+
+/// Converts a record batch of knowledge to the given schema, whose vector column
+/// must be a FixedSizeList of Float16 or Float32 with `dim` dimensions.
+pub fn convert_knowledge_batch(
+    batch: &RecordBatch,
+    schema: SchemaRef,
+    dim: i32,
+) -> Result<RecordBatch, BoxError> {
+    let mut columns: Vec<ArrayRef> = Vec::with_capacity(KNOWLEDGE_TEXT_COLUMNS.len() + 1);
+    for name in KNOWLEDGE_TEXT_COLUMNS {
+        let col = batch
+            .column_by_name(name)
+            .ok_or_else(|| format!("column {} not found", name))?;
+        if col.data_type() != &DataType::Utf8 {
+            return Err(format!("column {} must be of type Utf8", name).into());
+        }
+        columns.push(col.clone());
+    }
+
+    let col = batch
+        .column_by_name(KNOWLEDGE_VEC_COLUMN)
+        .ok_or_else(|| format!("column {} not found", KNOWLEDGE_VEC_COLUMN))?;
+    let vecs = vec_values(col.as_ref(), dim)?;
+    let item = match schema.field_with_name(KNOWLEDGE_VEC_COLUMN)?.data_type() {
+        DataType::FixedSizeList(item, _) => item.clone(),
+        dt => return Err(format!("unsupported vector column type {}", dt).into()),
+    };
+    let values: ArrayRef = match item.data_type() {
+        DataType::Float32 => Arc::new(Float32Array::from(vecs)),
+        DataType::Float16 => Arc::new(Float16Array::from(
+            vecs.into_iter()
+                .map(half::f16::from_f32)
+                .collect::<Vec<_>>(),
+        )),
+        dt => return Err(format!("unsupported vector item type {}", dt).into()),
+    };
+    let vecs: ArrayRef = Arc::new(FixedSizeListArray::try_new(item, dim, values, None)?);
+    columns.push(vecs);
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// Reads the vectors of a FixedSizeList column as flattened f32 values.
+fn vec_values(col: &dyn Array, dim: i32) -> Result<Vec<f32>, BoxError> {
+    let list = col
+        .as_fixed_size_list_opt()
+        .ok_or("vector column must be a fixed size list")?;
+    if list.value_length() != dim {
+        return Err(format!(
+            "invalid vector length, expected {}, got {}",
+            dim,
+            list.value_length()
+        )
+        .into());
+    }
+    if list.null_count() > 0 {
+        return Err("vector column must not contain nulls".into());
+    }
+
+    let values = list.values();
+    let start = list.value_offset(0) as usize;
+    let values = values.slice(start, list.len() * dim as usize);
+    if values.null_count() > 0 {
+        return Err("vectors must not contain nulls".into());
+    }
+    match values.data_type() {
+        DataType::Float16 => Ok(values
+            .as_primitive::<Float16Type>()
+            .values()
+            .iter()
+            .map(|v| v.to_f32())
+            .collect()),
+        DataType::Float32 => Ok(values.as_primitive::<Float32Type>().values().to_vec()),
+        dt => Err(format!("unsupported vector item type {}", dt).into()),
+    }
+}
+
+impl KnowledgeStore {
+    /// Exports all knowledge in the namespace as a Parquet file with ZSTD compression.
+    /// Returns the number of exported rows.
+    pub async fn export_parquet<W: Write + Send>(&self, writer: W) -> Result<usize, BoxError> {
+        let schema = knowledge_export_schema(self.dim);
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .set_key_value_metadata(Some(vec![
+                KeyValue::new("anda:namespace".to_string(), self.name.to_string()),
+                KeyValue::new("anda:dimensions".to_string(), self.dim.to_string()),
+            ]))
+            .build();
+        let mut writer = ArrowWriter::try_new(writer, schema.clone(), Some(props))?;
+
+        let mut columns: Vec<String> = KNOWLEDGE_TEXT_COLUMNS
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        columns.push(KNOWLEDGE_VEC_COLUMN.to_string());
+        let mut stream = self
+            .table
+            .query()
+            .select(Select::Columns(columns))
+            .execute()
+            .await?;
+
+        let mut count = 0;
+        while let Some(batch) = stream.try_next().await? {
+            let batch = convert_knowledge_batch(&batch, schema.clone(), self.dim)?;
+            count += batch.num_rows();
+            writer.write(&batch)?;
+        }
+        writer.close()?;
+        Ok(count)
+    }
+
+    /// Imports knowledge from a Parquet file in the export format, keeping the original IDs.
+    /// Rows are added in batches of `batch_size`. Returns the number of imported rows.
+    pub async fn import_parquet<R: ChunkReader + 'static>(
+        &self,
+        reader: R,
+        batch_size: usize,
+    ) -> Result<usize, BoxError> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(reader)?
+            .with_batch_size(batch_size.max(1))
+            .build()?;
+        let schema = self.table.schema().await?;
+
+        let mut count = 0;
+        for batch in reader {
+            let batch = convert_knowledge_batch(&batch?, schema.clone(), self.dim)?;
+            if batch.num_rows() == 0 {
+                continue;
+            }
+            count += batch.num_rows();
+            let batches = RecordBatchIterator::new(vec![Ok(batch)], schema.clone());
+            self.table.add(batches).execute().await?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::{KnowledgeFeatures, KnowledgeInput, Path};
+    use anda_engine::store::InMemory;
+    use ic_cose_types::types::object_store::CHUNK_SIZE;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_parquet_export_import() {
+        let mut store = LanceVectorStore::new_with_object_store(
+            "test://object_store".to_string(),
+            Arc::new(InMemory::new()),
+            Some(CHUNK_SIZE),
+            None,
+        )
+        .await
+        .unwrap();
+
+        const DIM: u16 = 8;
+        let ks = KnowledgeStore::init(&mut store, Path::from("source"), DIM, None)
+            .await
+            .unwrap();
+        ks.knowledge_add(vec![
+            KnowledgeInput {
+                user: "Anda".to_string(),
+                text: "Hello".to_string(),
+                vec: vec![0.5; DIM as usize],
+                ..Default::default()
+            },
+            KnowledgeInput {
+                user: "Dom".to_string(),
+                text: "Anda".to_string(),
+                vec: vec![0.25; DIM as usize],
+                ..Default::default()
+            },
+        ])
+        .await
+        .unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        let n = ks.export_parquet(&mut buf).await.unwrap();
+        assert_eq!(n, 2);
+
+        let target = KnowledgeStore::init(&mut store, Path::from("target"), DIM, None)
+            .await
+            .unwrap();
+        let n = target
+            .import_parquet(bytes::Bytes::from(buf), 1)
+            .await
+            .unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(target.table.count_rows(None).await.unwrap(), 2);
+
+        let res = target.knowledge_latest_n(10, 10, None).await.unwrap();
+        assert_eq!(res.len(), 2);
+
+        let other = KnowledgeStore::init(&mut store, Path::from("other"), DIM * 2, None)
+            .await
+            .unwrap();
+        let mut buf: Vec<u8> = Vec::new();
+        ks.export_parquet(&mut buf).await.unwrap();
+        assert!(
+            other
+                .import_parquet(bytes::Bytes::from(buf), 10)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,16 +1,24 @@
 use anda_core::{
-    BoxError, Knowledge, KnowledgeFeatures, KnowledgeInput, Path, VectorSearchFeatures,
+    BoxError, BoxPinFut, Knowledge, KnowledgeFeatures, KnowledgeFilter, KnowledgeInput,
+    META_AUTHOR, META_LANG, META_SOURCE, META_TAGS, Path, SearchMode, VectorSearchFeatures,
+};
+use anda_engine::{
+    store::maintenance::{MaintenanceJob, MaintenanceProgress, Throttle},
+    unix_ms,
 };
-use anda_engine::unix_ms;
 use std::{sync::Arc, vec};
 
 use crate::lancedb::*;
 
+/// The maximum factor by which a filtered search over-fetches documents, because the SQL
+/// filter may match more documents than the [`KnowledgeFilter`].
+const MAX_OVERFETCH: usize = 16;
+
 #[derive(Clone)]
 pub struct KnowledgeStore {
-    name: Path,
-    dim: i32,
-    table: Arc<Table>,
+    pub(crate) name: Path,
+    pub(crate) dim: i32,
+    pub(crate) table: Arc<Table>,
     embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
 }
 
@@ -64,6 +72,70 @@ impl KnowledgeStore {
         })
     }
 
+    /// Returns the default search mode: hybrid if the store has an embedder, keyword otherwise.
+    pub fn default_search_mode(&self) -> SearchMode {
+        if self.embedder.is_some() {
+            SearchMode::Hybrid
+        } else {
+            SearchMode::Keyword
+        }
+    }
+
+    /// Searches the top n knowledge documents matching the filter, in the given mode.
+    /// The search is repeated with a larger limit, up to [`MAX_OVERFETCH`] times n, while
+    /// the documents checked with [`KnowledgeFilter::matches`] are fewer than n.
+    pub async fn knowledge_search_with(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+        mode: SearchMode,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+
+        let conditions = filter.clone();
+        let filter = sql_filter(filter);
+        let max_limit = n.saturating_mul(MAX_OVERFETCH);
+        let mut limit = n;
+        loop {
+            let docs = search(
+                &self.table,
+                self.embedder.clone(),
+                [
+                    "id".to_string(),
+                    "user".to_string(),
+                    "text".to_string(),
+                    "meta".to_string(),
+                ],
+                query.to_string(),
+                limit,
+                filter.clone(),
+                mode,
+            )
+            .await?;
+            let fetched = docs.len();
+            let docs: Vec<Knowledge> = docs
+                .into_iter()
+                .map(|doc| Knowledge {
+                    id: doc[0].to_owned(),
+                    user: doc[1].to_owned(),
+                    text: doc[2].to_owned(),
+                    meta: serde_json::from_str(&doc[3]).unwrap_or_default(),
+                })
+                .filter(|doc| conditions.matches(doc))
+                .take(n)
+                .collect();
+
+            // enough documents, or all the documents matching the SQL filter
+            if docs.len() == n || fetched < limit || limit >= max_limit {
+                return Ok(docs);
+            }
+            limit = limit.saturating_mul(2).min(max_limit);
+        }
+    }
+
     pub async fn create_index(&self) -> Result<(), BoxError> {
         self.table
             .create_index(&["text"], Index::FTS(FtsIndexBuilder::default()))
@@ -84,6 +156,107 @@ impl KnowledgeStore {
     }
 }
 
+/// Builds the SQL filter of the knowledge table, pushed down to LanceDB. The creation time
+/// is matched on the xid IDs. The metadata is stored as compact JSON, so its fields are
+/// matched in the JSON text, which may match more documents than the filter, e.g. a tag
+/// value in another field, so the results are checked with [`KnowledgeFilter::matches`].
+fn sql_filter(filter: &KnowledgeFilter) -> Option<String> {
+    let mut conditions: Vec<String> = Vec::new();
+    if let Some(user) = &filter.user {
+        conditions.push(format!("user = {:?}", user.to_ascii_lowercase()));
+    }
+    if let Some(after) = filter.created_after {
+        let id = xid_from_timestamp(after.div_ceil(1000).min(u32::MAX as u64) as u32);
+        conditions.push(format!("id >= {:?}", id.to_string()));
+    }
+    if let Some(before) = filter.created_before {
+        let id = xid_from_timestamp(before.div_ceil(1000).min(u32::MAX as u64) as u32);
+        conditions.push(format!("id < {:?}", id.to_string()));
+    }
+    if let Some(lang) = &filter.lang {
+        conditions.push(meta_like(META_LANG, &lang.to_ascii_lowercase(), false));
+    }
+    if let Some(source) = &filter.source {
+        conditions.push(meta_like(META_SOURCE, source, false));
+    }
+    if let Some(author) = &filter.author {
+        conditions.push(meta_like(META_AUTHOR, &author.to_text(), false));
+    }
+    for tag in &filter.tags {
+        conditions.push(meta_like(META_TAGS, tag, true));
+    }
+
+    match conditions.len() {
+        0 => None,
+        1 => conditions.pop(),
+        _ => Some(
+            conditions
+                .iter()
+                .map(|c| format!("({c})"))
+                .collect::<Vec<_>>()
+                .join(" AND "),
+        ),
+    }
+}
+
+/// Matches a string field of the metadata, or an item of an array field, in its JSON text.
+fn meta_like(key: &str, value: &str, array: bool) -> String {
+    let key = like_escape(&serde_json::to_string(key).unwrap_or_default());
+    let value = like_escape(&serde_json::to_string(value).unwrap_or_default());
+    if array {
+        format!("meta LIKE '%{key}:[%{value}%'")
+    } else {
+        format!("meta LIKE '%{key}:{value}%'")
+    }
+}
+
+/// Escapes the text for a LIKE pattern in a SQL string literal: the wildcards `%` and `_`,
+/// and `\`, the default escape character of LIKE in LanceDB, are matched literally, and
+/// quotes are doubled.
+fn like_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '%' | '_' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\'' => escaped.push_str("''"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Compacts the table files, prunes the deleted rows and old versions, and optimizes the
+/// indexes. LanceDB throttles the optimization itself.
+impl MaintenanceJob for KnowledgeStore {
+    fn name(&self) -> String {
+        format!("lancedb_optimize:{}", self.name)
+    }
+
+    fn is_due(&self) -> bool {
+        true
+    }
+
+    fn run(
+        &self,
+        _throttle: Throttle,
+        progress: MaintenanceProgress,
+    ) -> BoxPinFut<Result<u64, BoxError>> {
+        let table = self.table.clone();
+        Box::pin(async move {
+            progress.start(1);
+            let stats = table.optimize(OptimizeAction::All).await?;
+            progress.advance(1);
+            Ok(stats
+                .compaction
+                .map(|c| c.fragments_removed as u64)
+                .unwrap_or_default())
+        })
+    }
+}
+
 impl VectorSearchFeatures for KnowledgeStore {
     async fn top_n(&self, query: &str, n: usize) -> Result<Vec<String>, BoxError> {
         if n == 0 {
@@ -127,36 +300,21 @@ impl KnowledgeFeatures for KnowledgeStore {
         n: usize,
         user: Option<String>,
     ) -> Result<Vec<Knowledge>, BoxError> {
-        if n == 0 {
-            return Ok(vec![]);
-        }
-
-        let filter = user.map(|user| format!("user = {:?}", user.to_ascii_lowercase()));
-        let docs = hybrid_search(
-            &self.table,
-            self.embedder.clone(),
-            [
-                "id".to_string(),
-                "user".to_string(),
-                "text".to_string(),
-                "meta".to_string(),
-            ],
-            query.to_string(),
-            n,
-            filter,
-        )
-        .await?;
-        let docs: Vec<Knowledge> = docs
-            .into_iter()
-            .map(|doc| Knowledge {
-                id: doc[0].to_owned(),
-                user: doc[1].to_owned(),
-                text: doc[2].to_owned(),
-                meta: serde_json::from_str(&doc[3]).unwrap_or_default(),
-            })
-            .collect();
+        let filter = KnowledgeFilter {
+            user,
+            ..Default::default()
+        };
+        self.knowledge_search(query, n, &filter).await
+    }
 
-        Ok(docs)
+    async fn knowledge_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        self.knowledge_search_with(query, n, filter, self.default_search_mode())
+            .await
     }
 
     async fn knowledge_latest_n(
@@ -215,6 +373,7 @@ impl KnowledgeFeatures for KnowledgeStore {
         let mut metas: Vec<String> = Vec::with_capacity(docs.len());
         let mut vecs: Vec<Option<Vec<Option<half::f16>>>> = Vec::with_capacity(docs.len());
         for doc in docs {
+            let doc = doc.normalize();
             if doc.vec.len() != self.dim as usize {
                 return Err(format!(
                     "invalid vector length, expected {}, got {}",
@@ -266,6 +425,39 @@ mod tests {
         agent::build_agent,
         client::{Client, ObjectStoreClient},
     };
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_sql_filter() {
+        assert_eq!(sql_filter(&KnowledgeFilter::default()), None);
+        let filter = KnowledgeFilter {
+            user: Some("Anda".to_string()),
+            lang: Some("EN".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            sql_filter(&filter).unwrap(),
+            r#"(user = "anda") AND (meta LIKE '%"lang":"en"%')"#
+        );
+
+        let filter = KnowledgeFilter {
+            source: Some("it's_50%.md".to_string()),
+            author: Some(Principal::from_text("aaaaa-aa").unwrap()),
+            tags: vec!["rust".to_string(), "a\"b".to_string()],
+            created_after: Some(1_700_000_000_001),
+            created_before: Some(1_700_000_002_000),
+            ..Default::default()
+        };
+        let after = xid_from_timestamp(1_700_000_001).to_string();
+        let before = xid_from_timestamp(1_700_000_002).to_string();
+        assert_eq!(
+            sql_filter(&filter).unwrap(),
+            format!(
+                r#"(id >= "{after}") AND (id < "{before}") AND (meta LIKE '%"source":"it''s\_50\%.md"%') AND (meta LIKE '%"author":"aaaaa-aa"%') AND (meta LIKE '%"tags":[%"rust"%') AND (meta LIKE '%"tags":[%"a\\"b"%')"#
+            )
+        );
+    }
 
     #[tokio::test(flavor = "current_thread")]
     async fn test_knowledge_store() {
@@ -327,6 +519,19 @@ mod tests {
         assert_eq!(res3.len(), 1);
         assert_eq!(res3[0].text, "Anda");
 
+        let filter = KnowledgeFilter::default();
+        let res = ks
+            .knowledge_search_with("anda", 10, &filter, SearchMode::Keyword)
+            .await
+            .unwrap();
+        assert_eq!(res, res3);
+        // no embedder for the vector and hybrid modes
+        assert!(
+            ks.knowledge_search_with("anda", 10, &filter, SearchMode::Hybrid)
+                .await
+                .is_err()
+        );
+
         let res = store
             .top_n_ids(namespace.clone(), "hello".to_string(), 10)
             .await
@@ -353,6 +558,43 @@ mod tests {
         println!("latest_n Dom:\n{:?}", res);
         assert_eq!(res.len(), 1);
         assert_eq!(res[0].user, "dom");
+
+        // the SQL filter also matches the tag in another field
+        ks.knowledge_add(vec![
+            KnowledgeInput {
+                user: "Anda".to_string(),
+                text: "Rust rust rust".to_string(),
+                meta: BTreeMap::from([
+                    (META_TAGS.to_string(), json!(["go"])),
+                    ("topic".to_string(), json!("rust")),
+                ]),
+                vec: vec![0.1; DIM as usize],
+            },
+            KnowledgeInput {
+                user: "Anda".to_string(),
+                text: "Rust guide".to_string(),
+                meta: BTreeMap::from([(META_TAGS.to_string(), json!(["rust"]))]),
+                vec: vec![0.1; DIM as usize],
+            },
+        ])
+        .await
+        .unwrap();
+        ks.create_index().await.unwrap();
+
+        let filter = KnowledgeFilter {
+            tags: vec!["rust".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            sql_filter(&filter).unwrap(),
+            r#"meta LIKE '%"tags":[%"rust"%'"#
+        );
+        let res = ks
+            .knowledge_search_with("rust", 1, &filter, SearchMode::Keyword)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].text, "Rust guide");
     }
 
     #[tokio::test(flavor = "current_thread")]
@@ -1,4 +1,4 @@
-use anda_core::{BoxError, BoxPinFut, Path};
+use anda_core::{BoxError, BoxPinFut, Path, RRF_K, SearchMode, reciprocal_rank_fusion};
 use futures::TryStreamExt;
 use object_store::DynObjectStore;
 use std::{collections::BTreeMap, sync::Arc};
@@ -14,6 +14,7 @@ pub use lance_io::object_store::{
 };
 pub use lancedb::{
     Table,
+    arrow::SendableRecordBatchStream,
     connection::{ConnectBuilder, Connection},
     database::CreateTableMode,
     index::{Index, scalar::FtsIndexBuilder},
@@ -36,6 +37,8 @@ pub struct LanceVectorTable {
     pub text_field: String,
 }
 
+/// Searches the table in the [`SearchMode::Hybrid`] mode if the embedder is set, or in the
+/// [`SearchMode::Keyword`] mode otherwise.
 pub async fn hybrid_search<const N: usize>(
     table: &Table,
     embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
@@ -44,7 +47,27 @@ pub async fn hybrid_search<const N: usize>(
     n: usize,
     filter: Option<String>,
 ) -> Result<Vec<[String; N]>, BoxError> {
-    let mut res = if query.is_empty() {
+    let mode = if embedder.is_some() {
+        SearchMode::Hybrid
+    } else {
+        SearchMode::Keyword
+    };
+    search(table, embedder, select_columns, query, n, filter, mode).await
+}
+
+/// Searches the table for the top n rows matching the query, or the first n rows if the
+/// query is empty. In the [`SearchMode::Hybrid`] mode, the vector and keyword searches
+/// each fetch more candidates and their rankings are fused by reciprocal rank fusion.
+pub async fn search<const N: usize>(
+    table: &Table,
+    embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
+    select_columns: [String; N],
+    query: String,
+    n: usize,
+    filter: Option<String>,
+    mode: SearchMode,
+) -> Result<Vec<[String; N]>, BoxError> {
+    if query.is_empty() {
         let mut q = table
             .query()
             .select(Select::Columns(select_columns.to_vec()))
@@ -52,30 +75,83 @@ pub async fn hybrid_search<const N: usize>(
         if let Some(filter) = filter {
             q = q.only_if(filter);
         }
-        q.execute().await?
-    } else if let Some(embedder) = embedder {
-        let (prompt_embedding, _) = embedder.embed_query(query.clone()).await?;
-        let mut q = table
-            .vector_search(prompt_embedding.vec.clone())?
-            .full_text_search(FullTextSearchQuery::new(query))
-            .select(Select::Columns(select_columns.to_vec()))
-            .limit(n);
-        if let Some(filter) = filter {
-            q = q.only_if(filter);
+        return collect_rows(q.execute().await?, &select_columns).await;
+    }
+
+    match mode {
+        SearchMode::Vector => {
+            let embedder = embedder.ok_or("vector search requires an embedder")?;
+            vector_search(table, embedder, select_columns, query, n, filter).await
         }
-        q.execute().await?
-    } else {
-        let mut q = table
-            .query()
-            .full_text_search(FullTextSearchQuery::new(query))
-            .select(Select::Columns(select_columns.to_vec()))
-            .limit(n);
-        if let Some(filter) = filter {
-            q = q.only_if(filter);
+        SearchMode::Keyword => keyword_search(table, select_columns, query, n, filter).await,
+        SearchMode::Hybrid => {
+            let embedder = embedder.ok_or("hybrid search requires an embedder")?;
+            let candidates = n.saturating_mul(HYBRID_CANDIDATES_FACTOR);
+            let (vector, keyword) = futures::future::try_join(
+                vector_search(
+                    table,
+                    embedder,
+                    select_columns.clone(),
+                    query.clone(),
+                    candidates,
+                    filter.clone(),
+                ),
+                keyword_search(table, select_columns, query, candidates, filter),
+            )
+            .await?;
+            Ok(reciprocal_rank_fusion(&[vector, keyword], RRF_K)
+                .into_iter()
+                .take(n)
+                .map(|(row, _)| row)
+                .collect())
         }
-        q.execute().await?
-    };
+    }
+}
+
+/// The number of candidates fetched by each search of the hybrid mode, per result.
+const HYBRID_CANDIDATES_FACTOR: usize = 3;
+
+async fn vector_search<const N: usize>(
+    table: &Table,
+    embedder: Arc<dyn EmbeddingFeaturesDyn>,
+    select_columns: [String; N],
+    query: String,
+    n: usize,
+    filter: Option<String>,
+) -> Result<Vec<[String; N]>, BoxError> {
+    let (embedding, _) = embedder.embed_query(query).await?;
+    let mut q = table
+        .vector_search(embedding.vec)?
+        .select(Select::Columns(select_columns.to_vec()))
+        .limit(n);
+    if let Some(filter) = filter {
+        q = q.only_if(filter);
+    }
+    collect_rows(q.execute().await?, &select_columns).await
+}
+
+async fn keyword_search<const N: usize>(
+    table: &Table,
+    select_columns: [String; N],
+    query: String,
+    n: usize,
+    filter: Option<String>,
+) -> Result<Vec<[String; N]>, BoxError> {
+    let mut q = table
+        .query()
+        .full_text_search(FullTextSearchQuery::new(query))
+        .select(Select::Columns(select_columns.to_vec()))
+        .limit(n);
+    if let Some(filter) = filter {
+        q = q.only_if(filter);
+    }
+    collect_rows(q.execute().await?, &select_columns).await
+}
 
+async fn collect_rows<const N: usize>(
+    mut res: SendableRecordBatchStream,
+    select_columns: &[String; N],
+) -> Result<Vec<[String; N]>, BoxError> {
     let mut docs: Vec<[String; N]> = Vec::new();
     while let Some(batch) = res.try_next().await? {
         let mut rows: Vec<[String; N]> = vec![[const { String::new() }; N]; batch.num_rows()];
@@ -1,5 +1,7 @@
+pub mod export;
 pub mod knowledge;
 pub mod lancedb;
 
+pub use export::*;
 pub use knowledge::*;
 pub use lancedb::*;
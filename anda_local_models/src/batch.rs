@@ -0,0 +1,301 @@
+//! Batching scheduler for local embedding models.
+//!
+//! Accelerators are much more efficient on large batches than on many single-text forward
+//! passes, while concurrent agents tend to embed one query or a few chunks at a time.
+//! [`BatchEmbedder`] queues concurrent embedding requests and groups them into batches of
+//! up to `max_batch_size` texts. A batch is dispatched when it is full or when its first
+//! request has waited `max_wait`, so `max_wait` bounds the added latency and
+//! `max_batch_size` the memory of one forward pass.
+//!
+//! Batches run one at a time, in queue order; `queue_size` bounds the number of pending
+//! requests, applying backpressure to callers when the model can't keep up.
+
+use anda_core::{BoxError, BoxPinFut, Embedding, Usage};
+use anda_engine::model::EmbeddingFeaturesDyn;
+use candle_core::Device;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::embedding::LocalEmbeddingModel;
+
+/// Tuning knobs of the [`BatchEmbedder`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BatchConfig {
+    /// The maximum number of texts in one batch.
+    pub max_batch_size: usize,
+    /// The maximum time a request waits for other requests to fill its batch.
+    pub max_wait: Duration,
+    /// The maximum number of pending requests.
+    pub queue_size: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self::for_device(&Device::Cpu)
+    }
+}
+
+impl BatchConfig {
+    /// Returns the default configuration for the device.
+    /// GPUs get larger batches and a longer wait, favoring throughput;
+    /// CPUs gain little from large batches, so they favor latency.
+    pub fn for_device(device: &Device) -> Self {
+        if device.is_cpu() {
+            Self {
+                max_batch_size: 32,
+                max_wait: Duration::from_millis(2),
+                queue_size: 1024,
+            }
+        } else {
+            Self {
+                max_batch_size: 256,
+                max_wait: Duration::from_millis(10),
+                queue_size: 4096,
+            }
+        }
+    }
+}
+
+/// Statistics of the [`BatchEmbedder`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BatchStats {
+    /// The number of embedding requests.
+    pub requests: u64,
+    /// The number of dispatched batches.
+    pub batches: u64,
+    /// The number of embedded texts.
+    pub texts: u64,
+    /// The number of requests waiting in the queue.
+    pub pending: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    batches: AtomicU64,
+    texts: AtomicU64,
+}
+
+/// The vectors and the number of input tokens of a request, or the error message.
+type JobResult = Result<(Vec<Vec<f32>>, u64), String>;
+
+struct Job {
+    texts: Vec<String>,
+    tx: oneshot::Sender<JobResult>,
+}
+
+/// A local embedding model behind a batching scheduler.
+#[derive(Clone)]
+pub struct BatchEmbedder {
+    model: LocalEmbeddingModel,
+    config: BatchConfig,
+    tx: mpsc::Sender<Job>,
+    counters: Arc<Counters>,
+}
+
+impl BatchEmbedder {
+    /// Creates a new batch embedder, with a [`BatchConfig`] for the model's device.
+    /// Must be called within a tokio runtime.
+    pub fn new(model: LocalEmbeddingModel) -> Self {
+        let config = BatchConfig::for_device(model.device());
+        Self::with_config(model, config)
+    }
+
+    /// Creates a new batch embedder with the given configuration.
+    /// Must be called within a tokio runtime.
+    pub fn with_config(model: LocalEmbeddingModel, config: BatchConfig) -> Self {
+        let config = BatchConfig {
+            max_batch_size: config.max_batch_size.max(1),
+            queue_size: config.queue_size.max(1),
+            ..config
+        };
+        let model = model.with_max_batch_size(config.max_batch_size);
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        let counters = Arc::new(Counters::default());
+        tokio::spawn(run_batches(
+            model.clone(),
+            config.clone(),
+            rx,
+            counters.clone(),
+        ));
+        Self {
+            model,
+            config,
+            tx,
+            counters,
+        }
+    }
+
+    /// Returns the configuration.
+    pub fn config(&self) -> &BatchConfig {
+        &self.config
+    }
+
+    /// Returns the statistics.
+    pub fn stats(&self) -> BatchStats {
+        BatchStats {
+            requests: self.counters.requests.load(Ordering::Relaxed),
+            batches: self.counters.batches.load(Ordering::Relaxed),
+            texts: self.counters.texts.load(Ordering::Relaxed),
+            pending: (self.config.queue_size - self.tx.capacity()) as u64,
+        }
+    }
+
+    async fn submit(&self, texts: Vec<String>) -> Result<(Vec<Vec<f32>>, u64), BoxError> {
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Job { texts, tx })
+            .await
+            .map_err(|_| "batch embedder is closed")?;
+        let rt = rx.await.map_err(|_| "batch embedder is closed")?;
+        rt.map_err(|err| err.into())
+    }
+}
+
+async fn run_batches(
+    model: LocalEmbeddingModel,
+    config: BatchConfig,
+    mut rx: mpsc::Receiver<Job>,
+    counters: Arc<Counters>,
+) {
+    while let Some(job) = rx.recv().await {
+        let deadline = tokio::time::Instant::now() + config.max_wait;
+        let mut size = job.texts.len();
+        let mut jobs = vec![job];
+        while size < config.max_batch_size {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(job)) => {
+                    size += job.texts.len();
+                    jobs.push(job);
+                }
+                // all senders dropped, or the deadline is reached
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let texts: Vec<String> = jobs.iter().flat_map(|j| j.texts.iter().cloned()).collect();
+        counters.batches.fetch_add(1, Ordering::Relaxed);
+        counters
+            .texts
+            .fetch_add(texts.len() as u64, Ordering::Relaxed);
+        let m = model.clone();
+        let rt = tokio::task::spawn_blocking(move || m.embed_with_tokens(&texts))
+            .await
+            .map_err(|err| err.to_string())
+            .and_then(|rt| rt.map_err(|err| err.to_string()));
+
+        match rt {
+            Ok((mut vecs, mut tokens)) => {
+                // split the results back into requests, in order
+                for job in jobs.into_iter().rev() {
+                    let at = vecs.len() - job.texts.len();
+                    let v = vecs.split_off(at);
+                    let t = tokens.split_off(at).into_iter().sum();
+                    let _ = job.tx.send(Ok((v, t)));
+                }
+            }
+            Err(err) => {
+                log::error!(texts = size; "batch embedding failed: {}", err);
+                for job in jobs {
+                    let _ = job.tx.send(Err(err.clone()));
+                }
+            }
+        }
+    }
+}
+
+impl EmbeddingFeaturesDyn for BatchEmbedder {
+    fn ndims(&self) -> usize {
+        self.model.ndims()
+    }
+
+    fn embed(&self, texts: Vec<String>) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move {
+            if texts.is_empty() {
+                return Ok((Vec::new(), Usage::default()));
+            }
+            let (vecs, tokens) = this.submit(texts.clone()).await?;
+            let embeddings = texts
+                .into_iter()
+                .zip(vecs)
+                .map(|(text, vec)| Embedding { text, vec })
+                .collect();
+            Ok((
+                embeddings,
+                Usage {
+                    input_tokens: tokens,
+                    output_tokens: 0,
+                    requests: 1,
+                    ..Default::default()
+                },
+            ))
+        })
+    }
+
+    fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move {
+            let (mut embeddings, usage) = this.embed(vec![text]).await?;
+            let embedding = embeddings.pop().ok_or("no embedding returned")?;
+            Ok((embedding, usage))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hub::ModelHub;
+
+    #[test]
+    fn test_batch_config() {
+        let cfg = BatchConfig::default();
+        assert_eq!(cfg.max_batch_size, 32);
+        assert_eq!(cfg, BatchConfig::for_device(&Device::Cpu));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn test_batch_embedder() {
+        let model = LocalEmbeddingModel::from_hub(
+            &ModelHub::default(),
+            "sentence-transformers/all-MiniLM-L6-v2",
+            None,
+        )
+        .await
+        .unwrap();
+        let embedder = BatchEmbedder::with_config(
+            model.clone(),
+            BatchConfig {
+                max_batch_size: 8,
+                max_wait: Duration::from_millis(50),
+                queue_size: 16,
+            },
+        );
+
+        let texts: Vec<String> = (0..10).map(|i| format!("text number {}", i)).collect();
+        let futs = texts.iter().map(|t| embedder.embed_query(t.clone()));
+        let rt = futures::future::try_join_all(futs).await.unwrap();
+        assert_eq!(rt.len(), 10);
+        let stats = embedder.stats();
+        assert_eq!(stats.requests, 10);
+        assert_eq!(stats.texts, 10);
+        assert!(stats.batches < 10);
+
+        let (expected, _) = model.embed_sync(&texts).unwrap();
+        for ((emb, _), (text, vec)) in rt.iter().zip(texts.iter().zip(expected)) {
+            assert_eq!(&emb.text, text);
+            let dot: f32 = emb.vec.iter().zip(&vec).map(|(a, b)| a * b).sum();
+            assert!((dot - 1.0).abs() < 1e-4);
+        }
+    }
+}
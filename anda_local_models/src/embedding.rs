@@ -0,0 +1,299 @@
+//! Local sentence-transformer embedding models.
+//!
+//! [`LocalEmbeddingModel`] runs BERT-family sentence-transformer models (all-MiniLM-L6-v2,
+//! bge-small-en-v1.5, multilingual-e5-small...) on the local CPU with mean pooling, so
+//! bulk ingestion does not need a paid embedding API call per chunk.
+//!
+//! # Example
+//! ```rust,ignore
+//! use anda_local_models::{LocalEmbeddingModel, ModelHub};
+//!
+//! let model = LocalEmbeddingModel::from_hub(
+//!     &ModelHub::default(),
+//!     "sentence-transformers/all-MiniLM-L6-v2",
+//!     None,
+//! )
+//! .await?;
+//! let engine = EngineBuilder::new().with_model(Model::new(completer, Arc::new(model)));
+//! ```
+
+use anda_core::{BoxError, BoxPinFut, Embedding, Usage};
+use anda_engine::model::EmbeddingFeaturesDyn;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+use crate::hub::ModelHub;
+
+/// The files of a sentence-transformer model.
+pub static EMBEDDING_MODEL_FILES: [&str; 3] =
+    ["config.json", "tokenizer.json", "model.safetensors"];
+
+/// The default maximum number of texts embedded in one forward pass.
+pub static MAX_BATCH_SIZE: usize = 32;
+
+/// A sentence-transformer embedding model running locally.
+#[derive(Clone)]
+pub struct LocalEmbeddingModel {
+    inner: Arc<Inner>,
+    normalize: bool,
+    max_batch_size: usize,
+}
+
+struct Inner {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+    ndims: usize,
+}
+
+impl LocalEmbeddingModel {
+    /// Loads a model from the hub, downloading it to the cache if needed.
+    pub async fn from_hub(
+        hub: &ModelHub,
+        model_id: &str,
+        revision: Option<&str>,
+    ) -> Result<Self, BoxError> {
+        let paths = hub
+            .fetch(model_id, revision, &EMBEDDING_MODEL_FILES)
+            .await?;
+        let (config, tokenizer, weights) = (paths[0].clone(), paths[1].clone(), paths[2].clone());
+        tokio::task::spawn_blocking(move || Self::load(&config, &tokenizer, &weights)).await?
+    }
+
+    /// Loads a model from a local directory containing `config.json`,
+    /// `tokenizer.json` and `model.safetensors`.
+    pub fn from_dir(dir: &Path) -> Result<Self, BoxError> {
+        let paths: Vec<PathBuf> = EMBEDDING_MODEL_FILES.iter().map(|f| dir.join(f)).collect();
+        Self::load(&paths[0], &paths[1], &paths[2])
+    }
+
+    /// Loads a model from its config, tokenizer and safetensors weights files on the CPU.
+    pub fn load(config: &Path, tokenizer: &Path, weights: &Path) -> Result<Self, BoxError> {
+        Self::load_with_device(config, tokenizer, weights, Device::Cpu)
+    }
+
+    /// Loads a model on the given device, e.g. `Device::new_cuda(0)` with the `cuda` feature
+    /// or `Device::new_metal(0)` with the `metal` feature.
+    pub fn load_with_device(
+        config: &Path,
+        tokenizer: &Path,
+        weights: &Path,
+        device: Device,
+    ) -> Result<Self, BoxError> {
+        let config: Config = serde_json::from_slice(&std::fs::read(config)?)?;
+        let tokenizer = load_tokenizer(tokenizer, config.max_position_embeddings)?;
+
+        // Safety: the weights file is not modified while mapped.
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let model = BertModel::load(vb, &config)?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                model,
+                tokenizer,
+                device,
+                ndims: config.hidden_size,
+            }),
+            normalize: true,
+            max_batch_size: MAX_BATCH_SIZE,
+        })
+    }
+
+    /// Returns the device the model runs on.
+    pub fn device(&self) -> &Device {
+        &self.inner.device
+    }
+
+    /// Sets whether embeddings are L2-normalized, which is the default.
+    /// Normalized embeddings can be compared with a plain dot product.
+    pub fn with_normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Sets the maximum number of texts embedded in one forward pass.
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size.max(1);
+        self
+    }
+
+    /// Embeds texts synchronously, returning the vectors and the number of input tokens.
+    /// This is CPU-bound, prefer [`EmbeddingFeaturesDyn::embed`] in async contexts.
+    pub fn embed_sync(&self, texts: &[String]) -> Result<(Vec<Vec<f32>>, u64), BoxError> {
+        let (vecs, tokens) = self.embed_with_tokens(texts)?;
+        Ok((vecs, tokens.into_iter().sum()))
+    }
+
+    /// Embeds texts synchronously, returning the vectors and the number of tokens of each text.
+    pub(crate) fn embed_with_tokens(
+        &self,
+        texts: &[String],
+    ) -> Result<(Vec<Vec<f32>>, Vec<u64>), BoxError> {
+        let mut vecs = Vec::with_capacity(texts.len());
+        let mut tokens = Vec::with_capacity(texts.len());
+        for chunk in texts.chunks(self.max_batch_size) {
+            let (mut rt, mut n) = self.inner.embed_batch(chunk, self.normalize)?;
+            vecs.append(&mut rt);
+            tokens.append(&mut n);
+        }
+        Ok((vecs, tokens))
+    }
+}
+
+impl Inner {
+    fn embed_batch(
+        &self,
+        texts: &[String],
+        normalize: bool,
+    ) -> Result<(Vec<Vec<f32>>, Vec<u64>), BoxError> {
+        let encodings = self.tokenizer.encode_batch(texts.to_vec(), true)?;
+        let mut ids = Vec::with_capacity(encodings.len());
+        let mut masks = Vec::with_capacity(encodings.len());
+        let mut tokens = Vec::with_capacity(encodings.len());
+        for enc in &encodings {
+            ids.push(Tensor::new(enc.get_ids(), &self.device)?);
+            masks.push(Tensor::new(enc.get_attention_mask(), &self.device)?);
+            tokens.push(enc.get_attention_mask().iter().sum::<u32>() as u64);
+        }
+
+        let ids = Tensor::stack(&ids, 0)?;
+        let mask = Tensor::stack(&masks, 0)?;
+        let token_type_ids = ids.zeros_like()?;
+        let output = self.model.forward(&ids, &token_type_ids, Some(&mask))?;
+        let pooled = mean_pool(&output, &mask)?;
+        let pooled = if normalize {
+            l2_normalize(&pooled)?
+        } else {
+            pooled
+        };
+        Ok((pooled.to_vec2::<f32>()?, tokens))
+    }
+}
+
+/// Loads a tokenizer that pads batches to the longest input and truncates inputs to `max_length`.
+pub(crate) fn load_tokenizer(path: &Path, max_length: usize) -> Result<Tokenizer, BoxError> {
+    let mut tokenizer = Tokenizer::from_file(path)?;
+    tokenizer.with_padding(Some(PaddingParams {
+        strategy: PaddingStrategy::BatchLongest,
+        ..Default::default()
+    }));
+    tokenizer.with_truncation(Some(TruncationParams {
+        max_length,
+        ..Default::default()
+    }))?;
+    Ok(tokenizer)
+}
+
+/// Averages the token embeddings `[batch, seq, hidden]` over the attention mask `[batch, seq]`.
+pub fn mean_pool(output: &Tensor, mask: &Tensor) -> candle_core::Result<Tensor> {
+    let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+    let sum = output.broadcast_mul(&mask)?.sum(1)?;
+    let count = mask.sum(1)?.clamp(1e-9, f64::MAX)?;
+    sum.broadcast_div(&count)
+}
+
+/// L2-normalizes the rows of a `[batch, hidden]` tensor.
+pub fn l2_normalize(v: &Tensor) -> candle_core::Result<Tensor> {
+    let norm = v.sqr()?.sum_keepdim(1)?.sqrt()?.clamp(1e-12, f64::MAX)?;
+    v.broadcast_div(&norm)
+}
+
+impl EmbeddingFeaturesDyn for LocalEmbeddingModel {
+    fn ndims(&self) -> usize {
+        self.inner.ndims
+    }
+
+    fn embed(&self, texts: Vec<String>) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let (vecs, tokens) = this.embed_sync(&texts)?;
+                let embeddings = texts
+                    .into_iter()
+                    .zip(vecs)
+                    .map(|(text, vec)| Embedding { text, vec })
+                    .collect();
+                Ok((
+                    embeddings,
+                    Usage {
+                        input_tokens: tokens,
+                        output_tokens: 0,
+                        requests: 1,
+                        ..Default::default()
+                    },
+                ))
+            })
+            .await?
+        })
+    }
+
+    fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move {
+            let (mut embeddings, usage) = this.embed(vec![text]).await?;
+            let embedding = embeddings.pop().ok_or("no embedding returned")?;
+            Ok((embedding, usage))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mean_pool() {
+        let device = Device::Cpu;
+        // batch 2, seq 3, hidden 2; the second sequence has one padding token
+        let output = Tensor::new(
+            &[
+                [[1f32, 2.0], [3.0, 4.0], [5.0, 6.0]],
+                [[2f32, 0.0], [4.0, 0.0], [100.0, 100.0]],
+            ],
+            &device,
+        )
+        .unwrap();
+        let mask = Tensor::new(&[[1u32, 1, 1], [1, 1, 0]], &device).unwrap();
+        let pooled = mean_pool(&output, &mask).unwrap();
+        assert_eq!(
+            pooled.to_vec2::<f32>().unwrap(),
+            vec![vec![3.0, 4.0], vec![3.0, 0.0]]
+        );
+
+        let normalized = l2_normalize(&pooled).unwrap().to_vec2::<f32>().unwrap();
+        assert!((normalized[0][0] - 0.6).abs() < 1e-6);
+        assert!((normalized[0][1] - 0.8).abs() < 1e-6);
+        assert_eq!(normalized[1], vec![1.0, 0.0]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn test_local_embedding() {
+        let model = LocalEmbeddingModel::from_hub(
+            &ModelHub::default(),
+            "sentence-transformers/all-MiniLM-L6-v2",
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(model.ndims(), 384);
+
+        let (res, usage) = model
+            .embed(vec![
+                "The cat sits on the mat".to_string(),
+                "A cat is sitting on a mat".to_string(),
+                "Rust is a systems programming language".to_string(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(usage.input_tokens > 0);
+        let sim = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+        assert!(sim(&res[0].vec, &res[1].vec) > sim(&res[0].vec, &res[2].vec));
+    }
+}
@@ -0,0 +1,126 @@
+//! Model download and cache management.
+//!
+//! Models are fetched from the Hugging Face Hub (or a compatible mirror) and stored in
+//! the standard Hugging Face cache layout, so they are shared with other tools on the
+//! same host (`huggingface-cli`, Python `transformers`...). In offline mode only the
+//! local cache is used, which suits TEE and air-gapped deployments where models are
+//! provisioned ahead of time.
+
+use anda_core::BoxError;
+use hf_hub::{Cache, Repo, RepoType, api::tokio::ApiBuilder};
+use std::path::{Path, PathBuf};
+
+/// The default revision of models.
+pub static DEFAULT_REVISION: &str = "main";
+
+/// Fetches model files from the Hugging Face Hub and manages the local cache.
+#[derive(Clone, Debug)]
+pub struct ModelHub {
+    cache_dir: PathBuf,
+    endpoint: Option<String>,
+    token: Option<String>,
+    offline: bool,
+}
+
+impl Default for ModelHub {
+    /// Uses the cache directory and token from the environment (`HF_HOME`, `HF_ENDPOINT`),
+    /// defaulting to `~/.cache/huggingface/hub`.
+    fn default() -> Self {
+        let cache = Cache::from_env();
+        Self {
+            cache_dir: cache.path().clone(),
+            endpoint: std::env::var("HF_ENDPOINT").ok(),
+            token: cache.token(),
+            offline: false,
+        }
+    }
+}
+
+impl ModelHub {
+    /// Creates a new model hub with the given cache directory.
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self {
+            cache_dir,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the endpoint of the hub, e.g. a mirror of huggingface.co.
+    pub fn with_endpoint(mut self, endpoint: String) -> Self {
+        self.endpoint = Some(endpoint);
+        self
+    }
+
+    /// Sets the access token for gated or private models.
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Disables downloads, only files in the local cache are used.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Returns the cache directory.
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// Returns the local paths of the given files of a model, downloading missing files
+    /// unless in offline mode.
+    pub async fn fetch(
+        &self,
+        model_id: &str,
+        revision: Option<&str>,
+        files: &[&str],
+    ) -> Result<Vec<PathBuf>, BoxError> {
+        let repo = Repo::with_revision(
+            model_id.to_string(),
+            RepoType::Model,
+            revision.unwrap_or(DEFAULT_REVISION).to_string(),
+        );
+
+        if self.offline {
+            let cache = Cache::new(self.cache_dir.clone()).repo(repo);
+            return files
+                .iter()
+                .map(|f| {
+                    cache.get(f).ok_or_else(|| {
+                        format!("file {} of model {} not found in cache", f, model_id).into()
+                    })
+                })
+                .collect();
+        }
+
+        let mut builder = ApiBuilder::new()
+            .with_progress(false)
+            .with_cache_dir(self.cache_dir.clone())
+            .with_token(self.token.clone());
+        if let Some(endpoint) = &self.endpoint {
+            builder = builder.with_endpoint(endpoint.clone());
+        }
+        let api = builder.build()?.repo(repo);
+
+        let mut paths = Vec::with_capacity(files.len());
+        for f in files {
+            let path = api.get(f).await.map_err(|err| {
+                format!("failed to fetch file {} of model {}: {}", f, model_id, err)
+            })?;
+            log::info!(model = model_id, file = f; "model file ready");
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Returns whether all the given files of a model are in the local cache.
+    pub fn is_cached(&self, model_id: &str, revision: Option<&str>, files: &[&str]) -> bool {
+        let cache = Cache::new(self.cache_dir.clone()).repo(Repo::with_revision(
+            model_id.to_string(),
+            RepoType::Model,
+            revision.unwrap_or(DEFAULT_REVISION).to_string(),
+        ));
+        files.iter().all(|f| cache.get(f).is_some())
+    }
+}
@@ -0,0 +1,9 @@
+pub mod batch;
+pub mod embedding;
+pub mod hub;
+pub mod reranker;
+
+pub use batch::*;
+pub use embedding::*;
+pub use hub::*;
+pub use reranker::*;
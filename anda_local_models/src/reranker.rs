@@ -0,0 +1,234 @@
+//! Local cross-encoder rerankers.
+//!
+//! [`LocalReranker`] scores (query, document) pairs with a BERT-family cross-encoder
+//! (cross-encoder/ms-marco-MiniLM-L-6-v2, ms-marco-TinyBERT-L-2-v2...) on the local CPU.
+//! Together with [`crate::LocalEmbeddingModel`] it allows a RAG pipeline to run fully
+//! offline: retrieve top-k candidates by vector similarity, then rerank them here. It
+//! implements the engine's [`Reranker`] trait, so it can be plugged in retrieval pipelines.
+//!
+//! Models are loaded from safetensors weights, the same runtime as the embedding models,
+//! so no ONNX runtime library has to be shipped with the binary.
+//!
+//! # Example
+//! ```rust,ignore
+//! use anda_local_models::{LocalReranker, ModelHub};
+//!
+//! let reranker = LocalReranker::from_hub(
+//!     &ModelHub::default().with_offline(true),
+//!     "cross-encoder/ms-marco-MiniLM-L-6-v2",
+//!     None,
+//! )
+//! .await?;
+//! let ranked = reranker.rerank("what is ICP?", documents, 5).await?;
+//! ```
+
+use anda_core::{BoxError, BoxPinFut};
+use candle_core::{D, Device, Tensor};
+use candle_nn::{Linear, Module, VarBuilder, linear};
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokenizers::{EncodeInput, Tokenizer};
+
+use crate::{embedding::load_tokenizer, hub::ModelHub};
+
+pub use anda_engine::model::{Reranked, Reranker};
+
+/// The files of a cross-encoder model.
+pub static RERANKER_MODEL_FILES: [&str; 3] = ["config.json", "tokenizer.json", "model.safetensors"];
+
+/// The maximum number of (query, document) pairs scored in one forward pass.
+pub static MAX_RERANK_BATCH_SIZE: usize = 16;
+
+/// A cross-encoder reranker running locally.
+#[derive(Clone)]
+pub struct LocalReranker {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    model: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl LocalReranker {
+    /// Loads a model from the hub, downloading it to the cache if needed.
+    pub async fn from_hub(
+        hub: &ModelHub,
+        model_id: &str,
+        revision: Option<&str>,
+    ) -> Result<Self, BoxError> {
+        let paths = hub.fetch(model_id, revision, &RERANKER_MODEL_FILES).await?;
+        let (config, tokenizer, weights) = (paths[0].clone(), paths[1].clone(), paths[2].clone());
+        tokio::task::spawn_blocking(move || Self::load(&config, &tokenizer, &weights)).await?
+    }
+
+    /// Loads a model from a local directory containing `config.json`,
+    /// `tokenizer.json` and `model.safetensors`.
+    pub fn from_dir(dir: &Path) -> Result<Self, BoxError> {
+        let paths: Vec<PathBuf> = RERANKER_MODEL_FILES.iter().map(|f| dir.join(f)).collect();
+        Self::load(&paths[0], &paths[1], &paths[2])
+    }
+
+    /// Loads a model from its config, tokenizer and safetensors weights files.
+    /// The model must have a single-label classification head.
+    pub fn load(config: &Path, tokenizer: &Path, weights: &Path) -> Result<Self, BoxError> {
+        let config: Config = serde_json::from_slice(&std::fs::read(config)?)?;
+        let tokenizer = load_tokenizer(tokenizer, config.max_position_embeddings)?;
+
+        let device = Device::Cpu;
+        // Safety: the weights file is not modified while mapped.
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let model = BertModel::load(vb.clone(), &config)?;
+        let prefix = config.model_type.as_deref().unwrap_or("bert");
+        let pooler = linear(
+            config.hidden_size,
+            config.hidden_size,
+            vb.pp(format!("{prefix}.pooler.dense")),
+        )
+        .or_else(|_| {
+            linear(
+                config.hidden_size,
+                config.hidden_size,
+                vb.pp("pooler.dense"),
+            )
+        })?;
+        let classifier = linear(config.hidden_size, 1, vb.pp("classifier"))?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                model,
+                pooler,
+                classifier,
+                tokenizer,
+                device,
+            }),
+        })
+    }
+
+    /// Scores documents against the query synchronously, in input order.
+    /// This is CPU-bound, prefer [`LocalReranker::rerank`] in async contexts.
+    pub fn score_sync(&self, query: &str, documents: &[String]) -> Result<Vec<f32>, BoxError> {
+        let mut scores = Vec::with_capacity(documents.len());
+        for chunk in documents.chunks(MAX_RERANK_BATCH_SIZE) {
+            scores.append(&mut self.inner.score_batch(query, chunk)?);
+        }
+        Ok(scores)
+    }
+
+    /// Reranks documents against the query, returning at most `top_n` results
+    /// ordered by descending relevance.
+    pub async fn rerank(
+        &self,
+        query: &str,
+        documents: Vec<String>,
+        top_n: usize,
+    ) -> Result<Vec<Reranked>, BoxError> {
+        let this = self.clone();
+        let query = query.to_string();
+        let scores =
+            tokio::task::spawn_blocking(move || this.score_sync(&query, &documents)).await??;
+        Ok(top_ranked(scores, top_n))
+    }
+}
+
+/// The relevance scores are in the range [0.0, 1.0].
+impl Reranker for LocalReranker {
+    fn rerank(
+        &self,
+        query: String,
+        documents: Vec<String>,
+        top_n: usize,
+    ) -> BoxPinFut<Result<Vec<Reranked>, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { LocalReranker::rerank(&this, &query, documents, top_n).await })
+    }
+}
+
+impl Inner {
+    fn score_batch(&self, query: &str, documents: &[String]) -> Result<Vec<f32>, BoxError> {
+        let inputs: Vec<EncodeInput> = documents
+            .iter()
+            .map(|doc| (query, doc.as_str()).into())
+            .collect();
+        let encodings = self.tokenizer.encode_batch(inputs, true)?;
+        let mut ids = Vec::with_capacity(encodings.len());
+        let mut type_ids = Vec::with_capacity(encodings.len());
+        let mut masks = Vec::with_capacity(encodings.len());
+        for enc in &encodings {
+            ids.push(Tensor::new(enc.get_ids(), &self.device)?);
+            type_ids.push(Tensor::new(enc.get_type_ids(), &self.device)?);
+            masks.push(Tensor::new(enc.get_attention_mask(), &self.device)?);
+        }
+
+        let ids = Tensor::stack(&ids, 0)?;
+        let type_ids = Tensor::stack(&type_ids, 0)?;
+        let mask = Tensor::stack(&masks, 0)?;
+        let output = self.model.forward(&ids, &type_ids, Some(&mask))?;
+        // the [CLS] token
+        let cls = output.narrow(1, 0, 1)?.squeeze(1)?;
+        let pooled = self.pooler.forward(&cls)?.tanh()?;
+        let logits = self.classifier.forward(&pooled)?.squeeze(D::Minus1)?;
+        let scores = candle_nn::ops::sigmoid(&logits)?;
+        Ok(scores.to_vec1::<f32>()?)
+    }
+}
+
+/// Returns the indexes and scores of the `top_n` highest scores, in descending order.
+pub fn top_ranked(scores: Vec<f32>, top_n: usize) -> Vec<Reranked> {
+    let mut ranked: Vec<Reranked> = scores
+        .into_iter()
+        .enumerate()
+        .map(|(index, score)| Reranked { index, score })
+        .collect();
+    ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+    ranked.truncate(top_n);
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_ranked() {
+        let ranked = top_ranked(vec![0.1, 0.9, 0.5, 0.7], 3);
+        assert_eq!(
+            ranked.iter().map(|r| r.index).collect::<Vec<_>>(),
+            vec![1, 3, 2]
+        );
+        assert_eq!(ranked[0].score, 0.9);
+        assert!(top_ranked(vec![], 3).is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn test_local_reranker() {
+        let reranker = LocalReranker::from_hub(
+            &ModelHub::default(),
+            "cross-encoder/ms-marco-MiniLM-L-6-v2",
+            None,
+        )
+        .await
+        .unwrap();
+
+        let ranked = reranker
+            .rerank(
+                "How many people live in Berlin?",
+                vec![
+                    "New York City is famous for the Metropolitan Museum of Art.".to_string(),
+                    "Berlin has a population of 3,520,031 registered inhabitants.".to_string(),
+                ],
+                2,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].index, 1);
+        assert!(ranked[0].score > ranked[1].score);
+    }
+}
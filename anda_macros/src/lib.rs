@@ -0,0 +1,566 @@
+//! Procedural macros for Anda, re-exported by `anda_core`.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::ToTokens;
+use syn::{
+    Attribute, Error, Expr, ExprArray, ExprLit, ImplItem, ItemImpl, Lit, LitBool, LitStr, Meta,
+    Type, meta::ParseNestedMeta, parse_macro_input, spanned::Spanned,
+};
+
+/// Implements the metadata methods of an [`Agent`] from the attribute arguments and the
+/// doc comments of the `impl` block, so that only `run` needs to be written.
+///
+/// Arguments, all optional:
+/// - `name`: the name of the agent, a string literal or a constant expression. Defaults to
+///   the type name in snake case. Literal names are validated at compile time;
+/// - `description`: the description of the agent. Defaults to the doc comments of the
+///   `impl` block, which is required if the argument is missing;
+/// - `tools`: the tool dependencies, an array of strings or constant expressions;
+/// - `resource_tags`: the supported resource tags, an array of strings or constant expressions.
+///
+/// Methods defined in the `impl` block are kept; the macro only generates the missing
+/// `name`, `description`, `tool_dependencies` and `supported_resource_tags`. The agent's
+/// definition, and so its discovery card, uses the generated name and description.
+///
+/// ```rust,ignore
+/// use anda_core::{Agent, AgentOutput, BoxError, Resource, agent};
+/// use anda_engine::context::AgentCtx;
+///
+/// pub struct WeatherAgent;
+///
+/// /// Answers questions about the weather of a city.
+/// #[agent(name = "weather_agent", tools = ["get_weather"])]
+/// impl Agent<AgentCtx> for WeatherAgent {
+///     async fn run(
+///         &self,
+///         ctx: AgentCtx,
+///         prompt: String,
+///         _resources: Option<Vec<Resource>>,
+///     ) -> Result<AgentOutput, BoxError> {
+///         todo!()
+///     }
+/// }
+/// ```
+///
+/// [`Agent`]: https://docs.rs/anda_core/latest/anda_core/agent/trait.Agent.html
+#[proc_macro_attribute]
+pub fn agent(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut attrs = AgentArgs::default();
+    let parser = syn::meta::parser(|meta| attrs.parse(meta));
+    parse_macro_input!(args with parser);
+    let item = parse_macro_input!(input as ItemImpl);
+    expand_agent(attrs, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct AgentArgs {
+    name: Option<Expr>,
+    description: Option<LitStr>,
+    tools: Option<ExprArray>,
+    resource_tags: Option<ExprArray>,
+}
+
+impl AgentArgs {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("name") {
+            self.name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("description") {
+            self.description = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("tools") {
+            self.tools = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("resource_tags") {
+            self.resource_tags = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error(
+                "unsupported agent argument, expected `name`, `description`, `tools` or `resource_tags`",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Implements the metadata methods of a [`Tool`] from the attribute arguments and the
+/// doc comments of the `impl` block, so that only `call` and the `Args` and `Output` types
+/// need to be written.
+///
+/// The parameters schema of the definition is generated from the `Args` type with
+/// `schemars`, so `Args` must derive `JsonSchema`, and the doc comments of its fields
+/// describe the parameters to the model. The raw JSON arguments of a tool call are
+/// deserialized into `Args` by [`Tool::call_raw`], and typed calls are built with
+/// `ToolInput::new(tool.name(), args)`.
+///
+/// Arguments, all optional:
+/// - `name`: the name of the tool, a string literal or a constant expression. Defaults to
+///   the type name in snake case. Literal names are validated at compile time;
+/// - `description`: the description of the tool. Defaults to the doc comments of the
+///   `impl` block, which is required if the argument is missing;
+/// - `strict`: whether the model must follow the parameters schema strictly, a boolean;
+/// - `resource_tags`: the supported resource tags, an array of strings or constant expressions.
+///
+/// Methods defined in the `impl` block are kept; the macro only generates the missing
+/// `name`, `description`, `definition` and `supported_resource_tags`.
+///
+/// ```rust,ignore
+/// use anda_core::{BoxError, Resource, Tool, ToolOutput, tool};
+/// use anda_engine::context::BaseCtx;
+/// use schemars::JsonSchema;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize, JsonSchema)]
+/// pub struct WeatherArgs {
+///     /// The city, e.g. "Paris".
+///     pub city: String,
+/// }
+///
+/// pub struct GetWeather;
+///
+/// /// Gets the current weather of a city.
+/// #[tool(name = "get_weather", strict = true)]
+/// impl Tool<BaseCtx> for GetWeather {
+///     type Args = WeatherArgs;
+///     type Output = String;
+///
+///     async fn call(
+///         &self,
+///         ctx: BaseCtx,
+///         args: Self::Args,
+///         _resources: Option<Vec<Resource>>,
+///     ) -> Result<ToolOutput<Self::Output>, BoxError> {
+///         todo!()
+///     }
+/// }
+/// ```
+///
+/// [`Tool`]: https://docs.rs/anda_core/latest/anda_core/tool/trait.Tool.html
+/// [`Tool::call_raw`]: https://docs.rs/anda_core/latest/anda_core/tool/trait.Tool.html#method.call_raw
+#[proc_macro_attribute]
+pub fn tool(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut attrs = ToolArgs::default();
+    let parser = syn::meta::parser(|meta| attrs.parse(meta));
+    parse_macro_input!(args with parser);
+    let item = parse_macro_input!(input as ItemImpl);
+    expand_tool(attrs, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct ToolArgs {
+    name: Option<Expr>,
+    description: Option<LitStr>,
+    strict: Option<LitBool>,
+    resource_tags: Option<ExprArray>,
+}
+
+impl ToolArgs {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("name") {
+            self.name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("description") {
+            self.description = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("strict") {
+            self.strict = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("resource_tags") {
+            self.resource_tags = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error(
+                "unsupported tool argument, expected `name`, `description`, `strict` or `resource_tags`",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn expand_agent(args: AgentArgs, mut item: ItemImpl) -> syn::Result<proc_macro2::TokenStream> {
+    if item.trait_.is_none() {
+        return Err(Error::new(
+            item.impl_token.span(),
+            "#[agent] must be used on an `impl Agent<Ctx> for Type` block",
+        ));
+    }
+
+    let mut generated: Vec<ImplItem> = Vec::new();
+    if !defined(&item, "name") {
+        let name = name_expr("agent", args.name, &item)?;
+        generated.push(syn::parse_quote! {
+            fn name(&self) -> String {
+                (#name).to_string()
+            }
+        });
+    }
+
+    if !defined(&item, "description") {
+        let description = description_lit("agent", args.description, &item)?;
+        generated.push(syn::parse_quote! {
+            fn description(&self) -> String {
+                #description.to_string()
+            }
+        });
+    }
+
+    let defined = |name: &str| defined(&item, name);
+
+    if let Some(tools) = args.tools.filter(|_| !defined("tool_dependencies")) {
+        let tools = tools.elems.iter();
+        generated.push(syn::parse_quote! {
+            fn tool_dependencies(&self) -> Vec<String> {
+                vec![#((#tools).to_string()),*]
+            }
+        });
+    }
+
+    if let Some(tags) = args
+        .resource_tags
+        .filter(|_| !defined("supported_resource_tags"))
+    {
+        let tags = tags.elems.iter();
+        generated.push(syn::parse_quote! {
+            fn supported_resource_tags(&self) -> Vec<String> {
+                vec![#((#tags).to_string()),*]
+            }
+        });
+    }
+
+    generated.append(&mut item.items);
+    item.items = generated;
+    Ok(item.into_token_stream())
+}
+
+fn expand_tool(args: ToolArgs, mut item: ItemImpl) -> syn::Result<proc_macro2::TokenStream> {
+    if item.trait_.is_none() {
+        return Err(Error::new(
+            item.impl_token.span(),
+            "#[tool] must be used on an `impl Tool<Ctx> for Type` block",
+        ));
+    }
+
+    let mut generated: Vec<ImplItem> = Vec::new();
+    if !defined(&item, "name") {
+        let name = name_expr("tool", args.name, &item)?;
+        generated.push(syn::parse_quote! {
+            fn name(&self) -> String {
+                (#name).to_string()
+            }
+        });
+    }
+
+    if !defined(&item, "description") {
+        let description = description_lit("tool", args.description, &item)?;
+        generated.push(syn::parse_quote! {
+            fn description(&self) -> String {
+                #description.to_string()
+            }
+        });
+    }
+
+    if !defined(&item, "definition") {
+        let strict = match args.strict {
+            Some(strict) => quote::quote!(Some(#strict)),
+            None => quote::quote!(None),
+        };
+        generated.push(syn::parse_quote! {
+            fn definition(&self) -> ::anda_core::FunctionDefinition {
+                ::anda_core::FunctionDefinition {
+                    name: self.name(),
+                    description: self.description(),
+                    parameters: ::anda_core::gen_schema_for::<Self::Args>(),
+                    strict: #strict,
+                }
+            }
+        });
+    }
+
+    if let Some(tags) = args
+        .resource_tags
+        .filter(|_| !defined(&item, "supported_resource_tags"))
+    {
+        let tags = tags.elems.iter();
+        generated.push(syn::parse_quote! {
+            fn supported_resource_tags(&self) -> Vec<String> {
+                vec![#((#tags).to_string()),*]
+            }
+        });
+    }
+
+    generated.append(&mut item.items);
+    item.items = generated;
+    Ok(item.into_token_stream())
+}
+
+/// Returns true if the method is defined in the `impl` block.
+fn defined(item: &ItemImpl, name: &str) -> bool {
+    item.items
+        .iter()
+        .any(|i| matches!(i, ImplItem::Fn(f) if f.sig.ident == name))
+}
+
+/// Returns the name expression of the agent or tool: the `name` argument, validated if it is
+/// a literal, or the type name in snake case.
+fn name_expr(
+    kind: &str,
+    name: Option<Expr>,
+    item: &ItemImpl,
+) -> syn::Result<proc_macro2::TokenStream> {
+    match name {
+        Some(name) => {
+            if let Expr::Lit(ExprLit {
+                lit: Lit::Str(lit), ..
+            }) = &name
+            {
+                validate_name(kind, &lit.value()).map_err(|msg| Error::new(lit.span(), msg))?;
+            }
+            Ok(name.into_token_stream())
+        }
+        None => {
+            let name = snake_case(&type_name(kind, &item.self_ty)?);
+            validate_name(kind, &name).map_err(|msg| Error::new(item.self_ty.span(), msg))?;
+            Ok(LitStr::new(&name, Span::call_site()).into_token_stream())
+        }
+    }
+}
+
+/// Returns the description of the agent or tool: the `description` argument, or the doc
+/// comments of the `impl` block.
+fn description_lit(
+    kind: &str,
+    description: Option<LitStr>,
+    item: &ItemImpl,
+) -> syn::Result<LitStr> {
+    if let Some(description) = description {
+        return Ok(description);
+    }
+    let docs = doc_comments(&item.attrs);
+    if docs.is_empty() {
+        return Err(Error::new(
+            item.impl_token.span(),
+            format!(
+                "#[{kind}] requires doc comments on the impl block or a `description` argument"
+            ),
+        ));
+    }
+    Ok(LitStr::new(&docs, Span::call_site()))
+}
+
+/// Returns the doc comments, with the lines of a paragraph joined by spaces and the
+/// paragraphs separated by blank lines.
+fn doc_comments(attrs: &[Attribute]) -> String {
+    let mut paragraphs: Vec<String> = vec![String::new()];
+    for attr in attrs {
+        let Meta::NameValue(nv) = &attr.meta else {
+            continue;
+        };
+        if !nv.path.is_ident("doc") {
+            continue;
+        }
+        let Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) = &nv.value
+        else {
+            continue;
+        };
+        for line in lit.value().split('\n') {
+            let line = line.trim();
+            let last = paragraphs.last_mut().unwrap();
+            if line.is_empty() {
+                if !last.is_empty() {
+                    paragraphs.push(String::new());
+                }
+            } else {
+                if !last.is_empty() {
+                    last.push(' ');
+                }
+                last.push_str(line);
+            }
+        }
+    }
+    paragraphs.retain(|p| !p.is_empty());
+    paragraphs.join("\n\n")
+}
+
+fn type_name(kind: &str, ty: &Type) -> syn::Result<String> {
+    match ty {
+        Type::Path(p) => p
+            .path
+            .segments
+            .last()
+            .map(|s| s.ident.to_string())
+            .ok_or_else(|| Error::new(ty.span(), "expected a type name")),
+        _ => Err(Error::new(
+            ty.span(),
+            format!("#[{kind}] requires a `name` argument for this type"),
+        )),
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut rt = String::with_capacity(name.len() + 4);
+    let chars: Vec<char> = name.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            // a word starts at an uppercase letter after a lowercase letter or a digit, or
+            // before a lowercase letter in an acronym, e.g. "ICPLedgerAgent"
+            let prev = i.checked_sub(1).map(|j| chars[j]);
+            let next = chars.get(i + 1);
+            if prev.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit())
+                || (prev.is_some_and(|p| p.is_uppercase())
+                    && next.is_some_and(|n| n.is_lowercase()))
+            {
+                rt.push('_');
+            }
+            rt.extend(c.to_lowercase());
+        } else {
+            rt.push(*c);
+        }
+    }
+    rt
+}
+
+/// The same rules as `anda_core::validate_function_name`.
+fn validate_name(kind: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("{kind} name must not be empty"));
+    }
+    if name.len() > 64 {
+        return Err(format!("{kind} name must not exceed 64 characters"));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return Err(format!("{kind} name must start with a lowercase letter"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(format!(
+            "{kind} name {name:?} can only contain lowercase letters, digits and underscores"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(snake_case("Translator"), "translator");
+        assert_eq!(snake_case("DocumentSegmenter"), "document_segmenter");
+        assert_eq!(snake_case("ICPLedgerAgent"), "icp_ledger_agent");
+        assert_eq!(snake_case("Agent2Go"), "agent2_go");
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let item: ItemImpl = syn::parse_quote! {
+            /// Answers questions
+            /// about the weather.
+            ///
+            /// Uses the `get_weather` tool.
+            impl Agent<AgentCtx> for WeatherAgent {}
+        };
+        assert_eq!(
+            doc_comments(&item.attrs),
+            "Answers questions about the weather.\n\nUses the `get_weather` tool."
+        );
+    }
+
+    #[test]
+    fn test_expand_agent() {
+        let item: ItemImpl = syn::parse_quote! {
+            /// Answers questions about the weather.
+            impl Agent<AgentCtx> for WeatherAgent {
+                fn tool_dependencies(&self) -> Vec<String> {
+                    Vec::new()
+                }
+            }
+        };
+        let args = AgentArgs {
+            tools: Some(syn::parse_quote!(["get_weather"])),
+            resource_tags: Some(syn::parse_quote!([Self::TAG])),
+            ..Default::default()
+        };
+        let out = expand_agent(args, item).unwrap().to_string();
+        assert!(out.contains("\"weather_agent\""), "{out}");
+        assert!(out.contains("\"Answers questions about the weather.\""));
+        // the defined method is kept and not generated
+        assert_eq!(out.matches("fn tool_dependencies").count(), 1);
+        assert!(!out.contains("get_weather"));
+        assert!(out.contains("Self :: TAG"));
+
+        let item: ItemImpl = syn::parse_quote! {
+            impl Agent<AgentCtx> for WeatherAgent {}
+        };
+        let err = expand_agent(AgentArgs::default(), item.clone()).unwrap_err();
+        assert!(err.to_string().contains("doc comments"));
+
+        let args = AgentArgs {
+            name: Some(syn::parse_quote!("Weather")),
+            description: Some(syn::parse_quote!("Weather.")),
+            ..Default::default()
+        };
+        let err = expand_agent(args, item).unwrap_err();
+        assert!(err.to_string().contains("lowercase"));
+
+        let item: ItemImpl = syn::parse_quote! {
+            /// Weather.
+            impl WeatherAgent {}
+        };
+        assert!(expand_agent(AgentArgs::default(), item).is_err());
+    }
+
+    #[test]
+    fn test_expand_tool() {
+        let item: ItemImpl = syn::parse_quote! {
+            /// Gets the current weather of a city.
+            impl Tool<BaseCtx> for GetWeather {
+                type Args = WeatherArgs;
+                type Output = String;
+            }
+        };
+        let args = ToolArgs {
+            strict: Some(syn::parse_quote!(true)),
+            resource_tags: Some(syn::parse_quote!(["text"])),
+            ..Default::default()
+        };
+        let out = expand_tool(args, item.clone()).unwrap().to_string();
+        assert!(out.contains("\"get_weather\""), "{out}");
+        assert!(out.contains("\"Gets the current weather of a city.\""));
+        assert!(out.contains("gen_schema_for :: < Self :: Args >"));
+        assert!(out.contains("strict : Some (true)"));
+        assert!(out.contains("fn supported_resource_tags"));
+
+        let out = expand_tool(ToolArgs::default(), item).unwrap().to_string();
+        assert!(out.contains("strict : None"));
+
+        let item: ItemImpl = syn::parse_quote! {
+            impl Tool<BaseCtx> for GetWeather {
+                fn definition(&self) -> FunctionDefinition {
+                    todo!()
+                }
+            }
+        };
+        let err = expand_tool(ToolArgs::default(), item.clone()).unwrap_err();
+        assert!(err.to_string().contains("#[tool] requires doc comments"));
+
+        let args = ToolArgs {
+            name: Some(syn::parse_quote!("get-weather")),
+            description: Some(syn::parse_quote!("Weather.")),
+            ..Default::default()
+        };
+        let err = expand_tool(args, item.clone()).unwrap_err();
+        assert!(err.to_string().starts_with("tool name"));
+
+        let args = ToolArgs {
+            name: Some(syn::parse_quote!(Self::NAME)),
+            description: Some(syn::parse_quote!("Weather.")),
+            ..Default::default()
+        };
+        let out = expand_tool(args, item).unwrap().to_string();
+        // the defined method is kept and not generated
+        assert_eq!(out.matches("fn definition").count(), 1);
+        assert!(out.contains("Self :: NAME"));
+    }
+}
@@ -0,0 +1,530 @@
+use anda_core::{
+    BoxError, BoxPinFut, Knowledge, KnowledgeFeatures, KnowledgeFilter, KnowledgeInput,
+    META_AUTHOR, META_LANG, META_SOURCE, META_TAGS, RRF_K, SearchMode, VectorSearchFeatures, Xid,
+    reciprocal_rank_fusion,
+};
+use anda_engine::{
+    store::maintenance::{MaintenanceJob, MaintenanceProgress, Throttle},
+    unix_ms,
+};
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use tokio_postgres::Row;
+
+use crate::{migration::*, pgvector::*};
+
+/// The parameters of a query, boxed to be built incrementally.
+type Params = Vec<Box<dyn ToSql + Sync + Send>>;
+
+/// The columns of a knowledge document.
+const COLUMNS: &str = r#"id, "user", text, meta"#;
+
+/// The number of candidates fetched by each search of the hybrid mode, per result.
+const HYBRID_CANDIDATES_FACTOR: usize = 3;
+
+/// A knowledge store on a Postgres table with a pgvector column. Each document is a row
+/// with its xid `id`, `user`, `text`, `meta` as JSONB, `vec` and `created_at` in unix
+/// milliseconds. [`KnowledgeFilter`]s are pushed down as SQL conditions, the metadata being
+/// matched by JSONB containment.
+///
+/// Keyword searches use the `simple` text search configuration, which splits words without
+/// stemming, so it works for any language.
+#[derive(Clone)]
+pub struct PgKnowledgeStore {
+    client: Arc<Client>,
+    table: String,
+    dim: usize,
+    embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
+    batch_size: usize,
+}
+
+/// Returns the migrations of a knowledge table of vectors of `dim` dimensions: the table
+/// and its indexes of the filters, then the HNSW index of the vectors.
+pub fn knowledge_migrations(table: &str, dim: usize, hnsw: &HnswParams) -> Vec<Migration> {
+    let t = quote_ident(table);
+    let index = |name: &str| quote_ident(&format!("{table}_{name}"));
+    vec![
+        Migration::new(
+            1,
+            format!(
+                r#"CREATE EXTENSION IF NOT EXISTS vector;
+CREATE TABLE IF NOT EXISTS {t} (
+  id TEXT PRIMARY KEY,
+  "user" TEXT NOT NULL,
+  text TEXT NOT NULL,
+  meta JSONB NOT NULL DEFAULT '{{}}',
+  vec vector({dim}) NOT NULL,
+  created_at BIGINT NOT NULL,
+  tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple', text)) STORED
+);
+CREATE INDEX IF NOT EXISTS {} ON {t} ("user", created_at);
+CREATE INDEX IF NOT EXISTS {} ON {t} (created_at);
+CREATE INDEX IF NOT EXISTS {} ON {t} USING gin (meta jsonb_path_ops);
+CREATE INDEX IF NOT EXISTS {} ON {t} USING gin (tsv)"#,
+                index("user_created_at"),
+                index("created_at"),
+                index("meta"),
+                index("tsv"),
+            ),
+        ),
+        Migration::new(2, hnsw_index_sql(table, "vec", hnsw)),
+    ]
+}
+
+impl PgKnowledgeStore {
+    /// Opens the knowledge store of a table of vectors of `dim` dimensions, migrated to the
+    /// latest schema with the default HNSW parameters. Searches need the embedder, except
+    /// in the keyword mode.
+    pub async fn init(
+        client: Arc<Client>,
+        table: &str,
+        dim: usize,
+        embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
+    ) -> Result<Self, BoxError> {
+        Self::init_with(client, table, dim, embedder, &HnswParams::default()).await
+    }
+
+    /// Opens the knowledge store of a table like [`init`](Self::init), with the parameters
+    /// of the HNSW index if it is created.
+    pub async fn init_with(
+        client: Arc<Client>,
+        table: &str,
+        dim: usize,
+        embedder: Option<Arc<dyn EmbeddingFeaturesDyn>>,
+        hnsw: &HnswParams,
+    ) -> Result<Self, BoxError> {
+        if let Some(embedder) = &embedder
+            && embedder.ndims() != dim
+        {
+            return Err(format!(
+                "invalid embedder dimensions, expected {}, got {}",
+                dim,
+                embedder.ndims()
+            )
+            .into());
+        }
+        migrate(&client, table, &knowledge_migrations(table, dim, hnsw)).await?;
+        Ok(Self {
+            client,
+            table: table.to_string(),
+            dim,
+            embedder,
+            batch_size: 256,
+        })
+    }
+
+    /// Sets the number of documents inserted per statement. Defaults to 256.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the default search mode: hybrid if the store has an embedder, keyword otherwise.
+    pub fn default_search_mode(&self) -> SearchMode {
+        if self.embedder.is_some() {
+            SearchMode::Hybrid
+        } else {
+            SearchMode::Keyword
+        }
+    }
+
+    /// Searches the top n knowledge documents matching the filter, in the given mode.
+    pub async fn knowledge_search_with(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+        mode: SearchMode,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        if query.is_empty() {
+            return self.latest(n, filter).await;
+        }
+
+        match mode {
+            SearchMode::Vector => self.vector_search(query, n, filter).await,
+            SearchMode::Keyword => self.keyword_search(query, n, filter).await,
+            SearchMode::Hybrid => {
+                let candidates = n.saturating_mul(HYBRID_CANDIDATES_FACTOR);
+                let (vector, keyword) = futures::future::try_join(
+                    self.vector_search(query, candidates, filter),
+                    self.keyword_search(query, candidates, filter),
+                )
+                .await?;
+                let mut docs: HashMap<String, Knowledge> = HashMap::new();
+                let rankings: Vec<Vec<String>> = [vector, keyword]
+                    .into_iter()
+                    .map(|list| {
+                        list.into_iter()
+                            .map(|doc| {
+                                let id = doc.id.clone();
+                                docs.insert(id.clone(), doc);
+                                id
+                            })
+                            .collect()
+                    })
+                    .collect();
+                Ok(reciprocal_rank_fusion(&rankings, RRF_K)
+                    .into_iter()
+                    .take(n)
+                    .filter_map(|(id, _)| docs.remove(&id))
+                    .collect())
+            }
+        }
+    }
+
+    /// Deletes the documents that match the filter, all documents if it is empty.
+    /// Returns the number of deleted documents.
+    pub async fn knowledge_delete(&self, filter: &KnowledgeFilter) -> Result<u64, BoxError> {
+        let (condition, params) = sql_filter(filter);
+        let sql = format!(
+            "DELETE FROM {}{}",
+            quote_ident(&self.table),
+            where_clause(condition)
+        );
+        Ok(self.client.execute(&sql, &param_refs(&params)).await?)
+    }
+
+    /// Returns the number of documents that match the filter.
+    pub async fn knowledge_count(&self, filter: &KnowledgeFilter) -> Result<u64, BoxError> {
+        let (condition, params) = sql_filter(filter);
+        let sql = format!(
+            "SELECT count(*) FROM {}{}",
+            quote_ident(&self.table),
+            where_clause(condition)
+        );
+        let row = self.client.query_one(&sql, &param_refs(&params)).await?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    async fn vector_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        let embedder = self
+            .embedder
+            .as_ref()
+            .ok_or("vector search requires an embedder")?;
+        let (embedding, _) = embedder.embed_query(query.to_string()).await?;
+        let (condition, mut params) = sql_filter(filter);
+        params.push(Box::new(Vector::from(embedding.vec)));
+        params.push(Box::new(n as i64));
+        let sql = format!(
+            "SELECT {COLUMNS} FROM {}{} ORDER BY vec <=> ${} LIMIT ${}",
+            quote_ident(&self.table),
+            where_clause(condition),
+            params.len() - 1,
+            params.len()
+        );
+        self.query_docs(&sql, &params).await
+    }
+
+    async fn keyword_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        let (condition, mut params) = sql_filter(filter);
+        params.push(Box::new(query.to_string()));
+        params.push(Box::new(n as i64));
+        let tsquery = format!("plainto_tsquery('simple', ${})", params.len() - 1);
+        let condition = match condition {
+            Some(condition) => format!("tsv @@ {tsquery} AND {condition}"),
+            None => format!("tsv @@ {tsquery}"),
+        };
+        let sql = format!(
+            "SELECT {COLUMNS} FROM {} WHERE {condition} ORDER BY ts_rank(tsv, {tsquery}) DESC LIMIT ${}",
+            quote_ident(&self.table),
+            params.len()
+        );
+        self.query_docs(&sql, &params).await
+    }
+
+    async fn latest(&self, n: usize, filter: &KnowledgeFilter) -> Result<Vec<Knowledge>, BoxError> {
+        let (condition, mut params) = sql_filter(filter);
+        params.push(Box::new(n as i64));
+        let sql = format!(
+            "SELECT {COLUMNS} FROM {}{} ORDER BY created_at DESC LIMIT ${}",
+            quote_ident(&self.table),
+            where_clause(condition),
+            params.len()
+        );
+        self.query_docs(&sql, &params).await
+    }
+
+    async fn query_docs(&self, sql: &str, params: &Params) -> Result<Vec<Knowledge>, BoxError> {
+        let rows = self.client.query(sql, &param_refs(params)).await?;
+        Ok(rows.iter().map(knowledge_from_row).collect())
+    }
+}
+
+/// Builds the SQL condition of a knowledge filter and its parameters, numbered from `$1`.
+/// Users and languages are stored in lowercase.
+fn sql_filter(filter: &KnowledgeFilter) -> (Option<String>, Params) {
+    let mut conditions: Vec<String> = Vec::new();
+    let mut params: Params = Vec::new();
+    let mut push = |condition: &str, param: Box<dyn ToSql + Sync + Send>| {
+        params.push(param);
+        conditions.push(condition.replace('?', &format!("${}", params.len())));
+    };
+    if let Some(user) = &filter.user {
+        push(r#""user" = ?"#, Box::new(user.to_ascii_lowercase()));
+    }
+    if let Some(after) = filter.created_after {
+        push(
+            "created_at >= ?",
+            Box::new(after.min(i64::MAX as u64) as i64),
+        );
+    }
+    if let Some(before) = filter.created_before {
+        push(
+            "created_at < ?",
+            Box::new(before.min(i64::MAX as u64) as i64),
+        );
+    }
+    let mut meta = serde_json::Map::new();
+    if let Some(lang) = &filter.lang {
+        meta.insert(META_LANG.to_string(), lang.to_ascii_lowercase().into());
+    }
+    if let Some(source) = &filter.source {
+        meta.insert(META_SOURCE.to_string(), source.clone().into());
+    }
+    if let Some(author) = &filter.author {
+        meta.insert(META_AUTHOR.to_string(), author.to_text().into());
+    }
+    // an array contains the items of another array
+    if !filter.tags.is_empty() {
+        meta.insert(META_TAGS.to_string(), json!(filter.tags));
+    }
+    if !meta.is_empty() {
+        push("meta @> ?", Box::new(Value::Object(meta)));
+    }
+
+    let condition = (!conditions.is_empty()).then(|| conditions.join(" AND "));
+    (condition, params)
+}
+
+fn where_clause(condition: Option<String>) -> String {
+    condition.map(|c| format!(" WHERE {c}")).unwrap_or_default()
+}
+
+fn param_refs(params: &Params) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+        .collect()
+}
+
+fn knowledge_from_row(row: &Row) -> Knowledge {
+    Knowledge {
+        id: row.get(0),
+        user: row.get(1),
+        text: row.get(2),
+        meta: match row.get::<_, Value>(3) {
+            Value::Object(meta) => meta.into_iter().collect(),
+            _ => BTreeMap::new(),
+        },
+    }
+}
+
+/// Vacuums the dead rows of the deleted documents and updates the statistics of the
+/// planner. Postgres throttles the vacuum itself with its cost-based delay.
+impl MaintenanceJob for PgKnowledgeStore {
+    fn name(&self) -> String {
+        format!("pgvector_vacuum:{}", self.table)
+    }
+
+    fn is_due(&self) -> bool {
+        true
+    }
+
+    fn run(
+        &self,
+        _throttle: Throttle,
+        progress: MaintenanceProgress,
+    ) -> BoxPinFut<Result<u64, BoxError>> {
+        let client = self.client.clone();
+        let sql = format!("VACUUM ANALYZE {}", quote_ident(&self.table));
+        Box::pin(async move {
+            progress.start(1);
+            client.batch_execute(&sql).await?;
+            progress.advance(1);
+            Ok(0)
+        })
+    }
+}
+
+impl VectorSearchFeatures for PgKnowledgeStore {
+    async fn top_n(&self, query: &str, n: usize) -> Result<Vec<String>, BoxError> {
+        let docs = self
+            .knowledge_search_with(
+                query,
+                n,
+                &KnowledgeFilter::default(),
+                self.default_search_mode(),
+            )
+            .await?;
+        Ok(docs.into_iter().map(|doc| doc.text).collect())
+    }
+
+    async fn top_n_ids(&self, query: &str, n: usize) -> Result<Vec<String>, BoxError> {
+        let docs = self
+            .knowledge_search_with(
+                query,
+                n,
+                &KnowledgeFilter::default(),
+                self.default_search_mode(),
+            )
+            .await?;
+        Ok(docs.into_iter().map(|doc| doc.id).collect())
+    }
+}
+
+impl KnowledgeFeatures for PgKnowledgeStore {
+    async fn knowledge_top_n(
+        &self,
+        query: &str,
+        n: usize,
+        user: Option<String>,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        let filter = KnowledgeFilter {
+            user,
+            ..Default::default()
+        };
+        self.knowledge_search(query, n, &filter).await
+    }
+
+    async fn knowledge_search(
+        &self,
+        query: &str,
+        n: usize,
+        filter: &KnowledgeFilter,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        self.knowledge_search_with(query, n, filter, self.default_search_mode())
+            .await
+    }
+
+    async fn knowledge_latest_n(
+        &self,
+        last_seconds: u32,
+        n: usize,
+        user: Option<String>,
+    ) -> Result<Vec<Knowledge>, BoxError> {
+        if last_seconds == 0 || n == 0 {
+            return Ok(vec![]);
+        }
+        let filter = KnowledgeFilter {
+            user,
+            created_after: Some(unix_ms().saturating_sub(last_seconds as u64 * 1000)),
+            ..Default::default()
+        };
+        self.latest(n, &filter).await
+    }
+
+    async fn knowledge_add(&self, docs: Vec<KnowledgeInput>) -> Result<(), BoxError> {
+        let mut rows: Params = Vec::with_capacity(docs.len() * 6);
+        for doc in docs {
+            let doc = doc.normalize();
+            if doc.vec.len() != self.dim {
+                return Err(format!(
+                    "invalid vector length, expected {}, got {}",
+                    self.dim,
+                    doc.vec.len()
+                )
+                .into());
+            }
+            rows.push(Box::new(Xid::new().to_string()));
+            rows.push(Box::new(doc.user.to_ascii_lowercase()));
+            rows.push(Box::new(doc.text));
+            rows.push(Box::new(Value::Object(doc.meta.into_iter().collect())));
+            rows.push(Box::new(Vector::from(doc.vec)));
+            rows.push(Box::new(unix_ms() as i64));
+        }
+
+        for batch in rows.chunks(self.batch_size * 6) {
+            let values: Vec<String> = (0..batch.len() / 6)
+                .map(|i| {
+                    let p = i * 6;
+                    format!(
+                        "(${}, ${}, ${}, ${}, ${}, ${})",
+                        p + 1,
+                        p + 2,
+                        p + 3,
+                        p + 4,
+                        p + 5,
+                        p + 6
+                    )
+                })
+                .collect();
+            let sql = format!(
+                r#"INSERT INTO {} (id, "user", text, meta, vec, created_at) VALUES {} ON CONFLICT (id) DO NOTHING"#,
+                quote_ident(&self.table),
+                values.join(", ")
+            );
+            let params: Vec<&(dyn ToSql + Sync)> = batch
+                .iter()
+                .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+                .collect();
+            self.client.execute(&sql, &params).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::{Path, Principal};
+
+    #[test]
+    fn test_sql_filter() {
+        let (condition, params) = sql_filter(&KnowledgeFilter::default());
+        assert_eq!(condition, None);
+        assert!(params.is_empty());
+
+        let filter = KnowledgeFilter {
+            user: Some("Anda".to_string()),
+            lang: Some("EN".to_string()),
+            author: Some(Principal::from_text("aaaaa-aa").unwrap()),
+            tags: vec!["rust".to_string()],
+            created_after: Some(1_700_000_000_000),
+            ..Default::default()
+        };
+        let (condition, params) = sql_filter(&filter);
+        assert_eq!(
+            condition.unwrap(),
+            r#""user" = $1 AND created_at >= $2 AND meta @> $3"#
+        );
+        assert_eq!(params.len(), 3);
+        assert_eq!(where_clause(None), "");
+        assert_eq!(
+            table_name(&Path::from("anda/Knowledge-1")),
+            "anda_knowledge_1"
+        );
+    }
+
+    #[test]
+    fn test_knowledge_migrations() {
+        let migrations = knowledge_migrations("anda_knowledge", 384, &HnswParams::default());
+        assert_eq!(migrations.len(), 2);
+        assert!(migrations[0].sql.contains("vec vector(384) NOT NULL"));
+        assert!(
+            migrations[0]
+                .sql
+                .contains("meta JSONB NOT NULL DEFAULT '{}'")
+        );
+        assert!(migrations[1].sql.contains("USING hnsw"));
+    }
+}
@@ -0,0 +1,7 @@
+pub mod knowledge;
+pub mod migration;
+pub mod pgvector;
+
+pub use knowledge::*;
+pub use migration::*;
+pub use pgvector::*;
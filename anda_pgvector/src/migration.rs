@@ -0,0 +1,202 @@
+use anda_core::BoxError;
+use anda_engine::unix_ms;
+use tokio_postgres::Client;
+
+/// The table that records the applied migrations of each schema.
+pub const MIGRATIONS_TABLE: &str = "anda_migrations";
+
+/// A versioned change of a schema, applied once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Migration {
+    /// The version of the schema after the migration, starting from 1.
+    pub version: u32,
+    /// The SQL statements of the migration, separated by semicolons.
+    pub sql: String,
+}
+
+impl Migration {
+    pub fn new(version: u32, sql: impl Into<String>) -> Self {
+        Self {
+            version,
+            sql: sql.into(),
+        }
+    }
+}
+
+/// The distance operator class of an HNSW index, which must match the operator of the
+/// searches, e.g. `<=>` for cosine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VectorOps {
+    #[default]
+    Cosine,
+    L2,
+    InnerProduct,
+}
+
+impl VectorOps {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cosine => "vector_cosine_ops",
+            Self::L2 => "vector_l2_ops",
+            Self::InnerProduct => "vector_ip_ops",
+        }
+    }
+
+    /// Returns the distance operator of the searches.
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Self::Cosine => "<=>",
+            Self::L2 => "<->",
+            Self::InnerProduct => "<#>",
+        }
+    }
+}
+
+/// The parameters of an HNSW index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HnswParams {
+    pub ops: VectorOps,
+    /// The maximum number of connections per layer, 16 by default.
+    pub m: u32,
+    /// The size of the candidate list when building the graph, 64 by default.
+    pub ef_construction: u32,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self {
+            ops: VectorOps::Cosine,
+            m: 16,
+            ef_construction: 64,
+        }
+    }
+}
+
+/// Returns the SQL that creates an HNSW index of a vector column if it does not exist.
+/// Unlike IVFFlat indexes, HNSW indexes can be created on empty tables.
+pub fn hnsw_index_sql(table: &str, column: &str, params: &HnswParams) -> String {
+    format!(
+        "CREATE INDEX IF NOT EXISTS {} ON {} USING hnsw ({} {}) WITH (m = {}, ef_construction = {})",
+        quote_ident(&format!("{table}_{column}_hnsw")),
+        quote_ident(table),
+        quote_ident(column),
+        params.ops.as_str(),
+        params.m,
+        params.ef_construction
+    )
+}
+
+/// Creates an HNSW index of a vector column if it does not exist.
+pub async fn create_hnsw_index(
+    client: &Client,
+    table: &str,
+    column: &str,
+    params: &HnswParams,
+) -> Result<(), BoxError> {
+    client
+        .batch_execute(&hnsw_index_sql(table, column, params))
+        .await?;
+    Ok(())
+}
+
+/// Returns the version of a schema, 0 if no migration was applied.
+pub async fn schema_version(client: &Client, schema: &str) -> Result<u32, BoxError> {
+    ensure_migrations_table(client).await?;
+    let row = client
+        .query_opt(
+            &format!("SELECT version FROM {MIGRATIONS_TABLE} WHERE name = $1"),
+            &[&schema],
+        )
+        .await?;
+    Ok(row.map(|row| row.get::<_, i32>(0) as u32).unwrap_or(0))
+}
+
+/// Applies the migrations of a schema newer than its version, in order. Each migration runs
+/// in a transaction with its version record, under an advisory lock of the schema, so
+/// concurrent migrators apply it once. Returns the version of the schema.
+pub async fn migrate(
+    client: &Client,
+    schema: &str,
+    migrations: &[Migration],
+) -> Result<u32, BoxError> {
+    let mut version = schema_version(client, schema).await?;
+    let mut migrations: Vec<&Migration> = migrations.iter().collect();
+    migrations.sort_by_key(|m| m.version);
+    for migration in migrations {
+        if migration.version <= version {
+            continue;
+        }
+        let sql = format!(
+            "BEGIN;
+SELECT pg_advisory_xact_lock(hashtext({name}));
+DO $$ BEGIN
+  IF COALESCE((SELECT version FROM {MIGRATIONS_TABLE} WHERE name = {name}), 0) >= {v} THEN
+    RAISE EXCEPTION 'anda migration % of % already applied', {v}, {name};
+  END IF;
+END $$;
+{sql};
+INSERT INTO {MIGRATIONS_TABLE} (name, version, applied_at) VALUES ({name}, {v}, {now})
+  ON CONFLICT (name) DO UPDATE SET version = EXCLUDED.version, applied_at = EXCLUDED.applied_at;
+COMMIT;",
+            name = quote_literal(schema),
+            v = migration.version,
+            sql = migration.sql.trim().trim_end_matches(';'),
+            now = unix_ms(),
+        );
+        if let Err(err) = client.batch_execute(&sql).await {
+            let _ = client.batch_execute("ROLLBACK").await;
+            // applied by a concurrent migrator
+            let current = schema_version(client, schema).await?;
+            if current < migration.version {
+                return Err(format!(
+                    "migration {} of {} failed: {}",
+                    migration.version, schema, err
+                )
+                .into());
+            }
+            version = current;
+            continue;
+        }
+        log::info!(schema = schema, version = migration.version; "schema migrated");
+        version = migration.version;
+    }
+    Ok(version)
+}
+
+async fn ensure_migrations_table(client: &Client) -> Result<(), BoxError> {
+    client
+        .batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {MIGRATIONS_TABLE} (
+  name TEXT PRIMARY KEY,
+  version INTEGER NOT NULL,
+  applied_at BIGINT NOT NULL
+)"
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Quotes an identifier, e.g. a table name.
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Quotes a string literal.
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hnsw_index_sql() {
+        assert_eq!(
+            hnsw_index_sql("anda_knowledge", "vec", &HnswParams::default()),
+            r#"CREATE INDEX IF NOT EXISTS "anda_knowledge_vec_hnsw" ON "anda_knowledge" USING hnsw ("vec" vector_cosine_ops) WITH (m = 16, ef_construction = 64)"#
+        );
+        assert_eq!(quote_ident(r#"a"b"#), r#""a""b""#);
+        assert_eq!(quote_literal("it's"), "'it''s'");
+    }
+}
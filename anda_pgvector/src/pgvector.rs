@@ -0,0 +1,111 @@
+use anda_core::{BoxError, BoxPinFut, Path};
+use std::sync::Arc;
+use tokio_postgres::NoTls;
+
+pub use anda_engine::{model::EmbeddingFeaturesDyn, store::VectorSearchFeaturesDyn};
+pub use pgvector::Vector;
+pub use tokio_postgres::{Client, Config, types::ToSql};
+
+use crate::{knowledge::PgKnowledgeStore, migration::quote_ident};
+
+/// Connects to Postgres without TLS, and drives the connection in a background task.
+/// Connect with a TLS connector of `tokio_postgres` and wrap the client in an [`Arc`]
+/// for connections that leave the host.
+pub async fn connect(config: &str) -> Result<Arc<Client>, BoxError> {
+    let (client, connection) = tokio_postgres::connect(config, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            log::error!("postgres connection error: {}", err);
+        }
+    });
+    Ok(Arc::new(client))
+}
+
+/// Returns the name of the table of a namespace, e.g. "anda_knowledge" for "anda/knowledge".
+pub fn table_name(namespace: &Path) -> String {
+    namespace
+        .as_ref()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// A [`VectorSearchFeaturesDyn`] over the knowledge tables of Postgres, a namespace being
+/// a table created by [`PgKnowledgeStore::init`].
+#[derive(Clone)]
+pub struct PgVectorStore {
+    client: Arc<Client>,
+    embedder: Arc<dyn EmbeddingFeaturesDyn>,
+}
+
+impl PgVectorStore {
+    pub fn new(client: Arc<Client>, embedder: Arc<dyn EmbeddingFeaturesDyn>) -> Self {
+        Self { client, embedder }
+    }
+
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    /// Opens the knowledge store of a namespace, migrated to the latest schema.
+    pub async fn knowledge_store(&self, namespace: &Path) -> Result<PgKnowledgeStore, BoxError> {
+        PgKnowledgeStore::init(
+            self.client.clone(),
+            &table_name(namespace),
+            self.embedder.ndims(),
+            Some(self.embedder.clone()),
+        )
+        .await
+    }
+
+    fn search_column(
+        &self,
+        namespace: Path,
+        query: String,
+        n: usize,
+        column: &'static str,
+    ) -> BoxPinFut<Result<Vec<String>, BoxError>> {
+        let client = self.client.clone();
+        let embedder = self.embedder.clone();
+        Box::pin(async move {
+            if n == 0 {
+                return Ok(vec![]);
+            }
+            let (embedding, _) = embedder.embed_query(query).await?;
+            let sql = format!(
+                "SELECT {column} FROM {} ORDER BY vec <=> $1 LIMIT $2",
+                quote_ident(&table_name(&namespace))
+            );
+            let rows = client
+                .query(&sql, &[&Vector::from(embedding.vec), &(n as i64)])
+                .await?;
+            Ok(rows.iter().map(|row| row.get(0)).collect())
+        })
+    }
+}
+
+impl VectorSearchFeaturesDyn for PgVectorStore {
+    fn top_n(
+        &self,
+        namespace: Path,
+        query: String,
+        n: usize,
+    ) -> BoxPinFut<Result<Vec<String>, BoxError>> {
+        self.search_column(namespace, query, n, "text")
+    }
+
+    fn top_n_ids(
+        &self,
+        namespace: Path,
+        query: String,
+        n: usize,
+    ) -> BoxPinFut<Result<Vec<String>, BoxError>> {
+        self.search_column(namespace, query, n, "id")
+    }
+}
@@ -1,5 +1,5 @@
 use anda_core::{BoxError, BoxPinFut, HttpFeatures, RPCRequestRef, cbor_rpc};
-use anda_engine::context::Web3ClientFeatures;
+use anda_engine::{context::Web3ClientFeatures, mtls::MtlsConfig};
 use candid::{
     CandidType, Decode, Principal,
     utils::{ArgumentEncoder, encode_args},
@@ -34,6 +34,7 @@ use anda_engine::APP_USER_AGENT;
 #[derive(Clone)]
 pub struct Client {
     outer_http: reqwest::Client,
+    rpc_http: Option<reqwest::Client>,
     root_secret: [u8; 48],
     id: Principal,
     identity: Arc<dyn Identity>,
@@ -49,6 +50,7 @@ pub struct ClientBuilder {
     identity: Arc<dyn Identity>,
     cose_canister: Principal,
     outer_http: reqwest::Client,
+    rpc_http: Option<reqwest::Client>,
     allow_http: bool,
 }
 
@@ -107,6 +109,7 @@ impl Default for ClientBuilder {
                 .user_agent(APP_USER_AGENT)
                 .build()
                 .expect("Could not create HTTP client"),
+            rpc_http: None,
             allow_http: false,
         }
     }
@@ -143,6 +146,13 @@ impl ClientBuilder {
         self
     }
 
+    /// Sends signed RPC calls to other engines over mutual TLS, only to pinned peers.
+    /// Other HTTP requests use the external HTTP client.
+    pub fn with_mtls(mut self, mtls: &MtlsConfig) -> Result<Self, BoxError> {
+        self.rpc_http = Some(mtls.http_client()?);
+        Ok(self)
+    }
+
     /// Allow HTTP connections (default is false)
     pub fn with_allow_http(
         mut self,
@@ -179,6 +189,7 @@ impl ClientBuilder {
 
         Ok(Client {
             outer_http: self.outer_http,
+            rpc_http: self.rpc_http,
             root_secret: self.root_secret,
             id: self
                 .identity
@@ -509,9 +520,9 @@ impl Web3ClientFeatures for Client {
             return Box::pin(futures::future::ready(Err(err.into())));
         }
 
-        let outer_http = self.outer_http.clone();
+        let rpc_http = self.rpc_http.as_ref().unwrap_or(&self.outer_http).clone();
         Box::pin(async move {
-            let res = cbor_rpc(&outer_http, &endpoint, &method, Some(headers), body).await?;
+            let res = cbor_rpc(&rpc_http, &endpoint, &method, Some(headers), body).await?;
             Ok(res.into_vec())
         })
     }
@@ -607,7 +618,8 @@ impl HttpFeatures for Client {
         let se = SignedEnvelope::sign_digest(self.identity.as_ref(), digest.into())?;
         let mut headers = http::HeaderMap::new();
         se.to_headers(&mut headers)?;
-        let res = cbor_rpc(&self.outer_http, endpoint, &method, Some(headers), body).await?;
+        let rpc_http = self.rpc_http.as_ref().unwrap_or(&self.outer_http);
+        let res = cbor_rpc(rpc_http, endpoint, &method, Some(headers), body).await?;
         let res = from_reader(&res[..])?;
         Ok(res)
     }
@@ -1,6 +1,6 @@
 use anda_core::{
     ANONYMOUS, Agent, AgentContext, AgentOutput, BoxError, CanisterCaller, CompletionFeatures,
-    CompletionRequest, Resource, StateFeatures, ToolSet,
+    CompletionRequest, Resource, StateFeatures, ToolSet, agent,
 };
 use anda_engine::context::{AgentCtx, BaseCtx};
 use anda_icp::ledger::{BalanceOfTool, ICPLedgers, TransferTool};
@@ -55,18 +55,9 @@ impl ICPLedgerAgent {
     }
 }
 
-/// Implementation of the [`Agent`] trait for ICPLedgerAgent.
+/// Interacts with ICP blockchain ledgers
+#[agent(name = Self::NAME)]
 impl Agent<AgentCtx> for ICPLedgerAgent {
-    /// Returns the agent's name identifier
-    fn name(&self) -> String {
-        Self::NAME.to_string()
-    }
-
-    /// Returns a description of the agent's purpose and capabilities.
-    fn description(&self) -> String {
-        "Interacts with ICP blockchain ledgers".to_string()
-    }
-
     /// Returns a list of tool names that this agent depends on
     fn tool_dependencies(&self) -> Vec<String> {
         self.tools.iter().map(|v| v.to_string()).collect()
@@ -92,21 +83,18 @@ impl Agent<AgentCtx> for ICPLedgerAgent {
             return Err("anonymous caller not allowed".into());
         }
 
-        let req = CompletionRequest {
-            system: Some(
+        let req = CompletionRequest::builder()
+            .system(
                 "\
             You are an AI assistant designed to interact with the ICP blockchain ledger by given tools.\n\
             1. Please decline any requests that are not related to the ICP blockchain ledger.\n\
             2. For requests that are not supported by the tools available, kindly inform the user \
-            of your current capabilities."
-                    .to_string(),
-            ),
-            prompt,
-            tools: ctx.tool_definitions(Some(&self.tools)),
-            tool_choice_required: false,
-            ..Default::default()
-        }
-        .context("user_address".to_string(), caller.to_string());
+            of your current capabilities.",
+            )
+            .tools(ctx.tool_definitions(Some(&self.tools)))
+            .document("user_address", caller.to_string())
+            .prompt(prompt)
+            .build();
         let res = ctx.completion(req, None).await?;
         Ok(res)
     }
@@ -2,7 +2,7 @@ use anda_core::BoxError;
 use anda_engine::{
     context::Web3SDK,
     engine::{EngineBuilder, ManagementBuilder, Visibility},
-    model::{Model, deepseek, openai, xai},
+    model::{Model, anthropic, deepseek, gemini, openai, xai},
     store::{InMemory, Store},
 };
 use anda_engine_server::{ServerBuilder, shutdown_signal};
@@ -51,6 +51,14 @@ struct Cli {
     #[arg(long, env = "XAI_API_KEY", default_value = "")]
     xai_api_key: String,
 
+    /// Anthropic API key for AI model
+    #[arg(long, env = "ANTHROPIC_API_KEY", default_value = "")]
+    anthropic_api_key: String,
+
+    /// Gemini API key for AI model
+    #[arg(long, env = "GEMINI_API_KEY", default_value = "")]
+    gemini_api_key: String,
+
     /// AI model endpoint, empty for default to auto-detect
     #[arg(long, env = "MODEL_ENDPOINT", default_value = "")]
     model_endpoint: String,
@@ -72,7 +80,7 @@ struct Cli {
 /// - ICP API host: The ICP network endpoint (default: https://icp-api.io)
 /// - ID Secret: 32-byte hex-encoded secret for identity management
 /// - Root Secret: 48-byte hex-encoded root secret for cryptographic operations
-/// - AI Model: Supports both Deepseek and OpenAI models (Deepseek is default)
+/// - AI Model: Supports Deepseek, OpenAI, xAI, Anthropic and Gemini models (Deepseek is default)
 ///
 /// # Features
 /// - Real-time interaction with ICP ledger
@@ -141,6 +149,16 @@ async fn main() -> Result<(), BoxError> {
             xai::Client::new(&cli.xai_api_key, Some(cli.model_endpoint))
                 .completion_model(&cli.model_name),
         )
+    } else if !cli.anthropic_api_key.is_empty() {
+        Arc::new(
+            anthropic::Client::new(&cli.anthropic_api_key, Some(cli.model_endpoint))
+                .completion_model(&cli.model_name),
+        )
+    } else if !cli.gemini_api_key.is_empty() {
+        Arc::new(
+            gemini::Client::new(&cli.gemini_api_key, Some(cli.model_endpoint))
+                .completion_model(&cli.model_name),
+        )
     } else {
         return Err("missing AI model API key".into());
     });
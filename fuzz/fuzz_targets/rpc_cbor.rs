@@ -0,0 +1,20 @@
+//! Decodes CBOR request bodies as the engine server does: the RPC envelope, then the
+//! params of `agent_run` and `tool_call`.
+
+#![no_main]
+
+use anda_core::{AgentInput, ToolInput, Value, Xid};
+use ciborium::from_reader;
+use ic_tee_agent::RPCRequest;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(req) = from_reader::<RPCRequest, _>(data) {
+        let params = req.params.as_slice();
+        let _ = from_reader::<(AgentInput,), _>(params);
+        let _ = from_reader::<(ToolInput<Value>,), _>(params);
+        let _ = from_reader::<(Xid, i8, Option<String>), _>(params);
+    }
+    let _ = from_reader::<(AgentInput,), _>(data);
+    let _ = from_reader::<(ToolInput<Value>,), _>(data);
+});
@@ -0,0 +1,16 @@
+//! Decodes JSON request bodies as the engine server and the A2A endpoint do.
+
+#![no_main]
+
+use anda_core::{AgentInput, ToolInput, Value};
+use ic_tee_agent::RPCRequest;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = serde_json::from_slice::<RPCRequest>(data);
+    let _ = serde_json::from_slice::<AgentInput>(data);
+    if let Ok(input) = serde_json::from_slice::<ToolInput<Value>>(data) {
+        // tools re-decode their args from the JSON value
+        let _ = serde_json::from_value::<Vec<String>>(input.args);
+    }
+});
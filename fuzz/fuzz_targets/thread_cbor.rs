@@ -0,0 +1,11 @@
+//! Decodes the CBOR documents of threads, which may be written by an older or newer engine.
+
+#![no_main]
+
+use anda_core::{ThreadMessage, ThreadMeta, Versioned};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Versioned::<ThreadMeta>::decode(data);
+    let _ = Versioned::<Vec<ThreadMessage>>::decode(data);
+});
@@ -0,0 +1,175 @@
+//! Client of the [ICPSwap](https://www.icpswap.com/) pool canisters
+//!
+//! ICPSwap deploys a canister per token pair. Pools are loaded by their canister IDs and
+//! indexed by their token pair. Swaps pay with an ICRC-2 allowance approved to the pool,
+//! which deposits, swaps and withdraws to the caller in one call.
+
+use anda_core::{BoxError, CanisterCaller};
+use candid::{CandidType, Nat, Principal};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum ICPSwapError {
+    CommonError,
+    InternalError(String),
+    UnsupportedToken(String),
+    InsufficientFunds,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum ICPSwapResult<T> {
+    #[serde(rename = "ok")]
+    Ok(T),
+    #[serde(rename = "err")]
+    Err(ICPSwapError),
+}
+
+impl<T> ICPSwapResult<T> {
+    fn into_result(self, action: &str) -> Result<T, BoxError> {
+        match self {
+            ICPSwapResult::Ok(v) => Ok(v),
+            ICPSwapResult::Err(err) => Err(format!("ICPSwap {} failed: {:?}", action, err).into()),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct PoolToken {
+    pub address: String,
+    pub standard: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct PoolMetadata {
+    pub token0: PoolToken,
+    pub token1: PoolToken,
+    pub fee: Nat,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SwapArgs {
+    #[serde(rename = "amountIn")]
+    pub amount_in: String,
+    #[serde(rename = "zeroForOne")]
+    pub zero_for_one: bool,
+    #[serde(rename = "amountOutMinimum")]
+    pub amount_out_minimum: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct DepositAndSwapArgs {
+    #[serde(rename = "amountIn")]
+    pub amount_in: String,
+    #[serde(rename = "zeroForOne")]
+    pub zero_for_one: bool,
+    #[serde(rename = "amountOutMinimum")]
+    pub amount_out_minimum: String,
+    #[serde(rename = "tokenInFee")]
+    pub token_in_fee: Nat,
+    #[serde(rename = "tokenOutFee")]
+    pub token_out_fee: Nat,
+}
+
+/// An ICPSwap pool
+#[derive(Debug, Clone)]
+pub struct ICPSwapPool {
+    pub canister: Principal,
+    pub token0: Principal,
+    pub token1: Principal,
+}
+
+/// ICPSwap client
+#[derive(Debug, Clone, Default)]
+pub struct ICPSwap {
+    /// Map of the ordered token pairs to their pools
+    pub pools: BTreeMap<(Principal, Principal), ICPSwapPool>,
+}
+
+impl ICPSwap {
+    /// Loads the pools by their canister IDs.
+    pub async fn load(
+        ctx: &impl CanisterCaller,
+        pool_canisters: BTreeSet<Principal>,
+    ) -> Result<ICPSwap, BoxError> {
+        let mut pools = BTreeMap::new();
+        for canister in pool_canisters {
+            let res: ICPSwapResult<PoolMetadata> =
+                ctx.canister_query(&canister, "metadata", ()).await?;
+            let meta = res.into_result("metadata")?;
+            let pool = ICPSwapPool {
+                canister,
+                token0: Principal::from_text(&meta.token0.address)?,
+                token1: Principal::from_text(&meta.token1.address)?,
+            };
+            pools.insert(Self::pair(&pool.token0, &pool.token1), pool);
+        }
+        Ok(ICPSwap { pools })
+    }
+
+    fn pair(a: &Principal, b: &Principal) -> (Principal, Principal) {
+        if a < b { (*a, *b) } else { (*b, *a) }
+    }
+
+    /// Returns the pool of the token pair.
+    pub fn pool(&self, pay: &Principal, receive: &Principal) -> Option<&ICPSwapPool> {
+        self.pools.get(&Self::pair(pay, receive))
+    }
+
+    /// Simulates a swap of `pay_amount` base units of the `pay` token, returns the amount to receive.
+    pub async fn quote(
+        &self,
+        ctx: &impl CanisterCaller,
+        pay: &Principal,
+        pay_amount: Nat,
+        receive: &Principal,
+    ) -> Result<Nat, BoxError> {
+        let pool = self
+            .pool(pay, receive)
+            .ok_or("ICPSwap pool not found for the token pair")?;
+        let res: ICPSwapResult<Nat> = ctx
+            .canister_query(
+                &pool.canister,
+                "quote",
+                (SwapArgs {
+                    amount_in: pay_amount.0.to_string(),
+                    zero_for_one: &pool.token0 == pay,
+                    amount_out_minimum: "0".to_string(),
+                },),
+            )
+            .await?;
+        res.into_result("quote")
+    }
+
+    /// Swaps with an ICRC-2 allowance approved to the pool, returns the amount received.
+    /// The swap fails if fewer than `min_receive` base units would be received.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn swap(
+        &self,
+        ctx: &impl CanisterCaller,
+        pay: &Principal,
+        pay_amount: Nat,
+        receive: &Principal,
+        min_receive: Nat,
+        pay_fee: Nat,
+        receive_fee: Nat,
+    ) -> Result<Nat, BoxError> {
+        let pool = self
+            .pool(pay, receive)
+            .ok_or("ICPSwap pool not found for the token pair")?;
+        let res: ICPSwapResult<Nat> = ctx
+            .canister_update(
+                &pool.canister,
+                "depositFromAndSwap",
+                (DepositAndSwapArgs {
+                    amount_in: pay_amount.0.to_string(),
+                    zero_for_one: &pool.token0 == pay,
+                    amount_out_minimum: min_receive.0.to_string(),
+                    token_in_fee: pay_fee,
+                    token_out_fee: receive_fee,
+                },),
+            )
+            .await?;
+        res.into_result("swap")
+    }
+}
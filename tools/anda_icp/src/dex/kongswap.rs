@@ -0,0 +1,127 @@
+//! Client of the [KongSwap](https://www.kongswap.io/) backend canister
+//!
+//! KongSwap is a single-canister DEX. Tokens are referenced as `IC.<ledger canister ID>`.
+//! Swaps pay with an ICRC-2 allowance approved to the KongSwap canister.
+
+use anda_core::{BoxError, CanisterCaller};
+use candid::{CandidType, Nat, Principal};
+use serde::Deserialize;
+
+/// The KongSwap backend canister ID on the mainnet.
+pub const KONGSWAP_CANISTER: &str = "2ipq2-uqaaa-aaaar-qailq-cai";
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SwapAmountsReply {
+    pub pay_symbol: String,
+    pub pay_amount: Nat,
+    pub receive_symbol: String,
+    pub receive_amount: Nat,
+    pub price: f64,
+    pub mid_price: f64,
+    /// The price impact of the swap, in percent.
+    pub slippage: f64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum TxId {
+    BlockIndex(Nat),
+    TransactionId(String),
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SwapArgs {
+    pub pay_token: String,
+    pub pay_amount: Nat,
+    /// None to pay with an ICRC-2 allowance.
+    pub pay_tx_id: Option<TxId>,
+    pub receive_token: String,
+    pub receive_amount: Option<Nat>,
+    pub receive_address: Option<String>,
+    /// The maximum slippage of the swap, in percent.
+    pub max_slippage: Option<f64>,
+    pub referred_by: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SwapReply {
+    pub tx_id: u64,
+    pub request_id: u64,
+    pub status: String,
+    pub pay_amount: Nat,
+    pub receive_amount: Nat,
+    pub price: f64,
+    pub slippage: f64,
+}
+
+/// KongSwap client
+#[derive(Debug, Clone)]
+pub struct KongSwap {
+    pub canister: Principal,
+}
+
+impl KongSwap {
+    /// Creates a client of the mainnet KongSwap canister.
+    pub fn new() -> Self {
+        Self {
+            canister: Principal::from_text(KONGSWAP_CANISTER).expect("invalid canister ID"),
+        }
+    }
+
+    fn token(ledger: &Principal) -> String {
+        format!("IC.{}", ledger.to_text())
+    }
+
+    /// Simulates a swap of `pay_amount` base units of the `pay` token.
+    pub async fn quote(
+        &self,
+        ctx: &impl CanisterCaller,
+        pay: &Principal,
+        pay_amount: Nat,
+        receive: &Principal,
+    ) -> Result<SwapAmountsReply, BoxError> {
+        let res: Result<SwapAmountsReply, String> = ctx
+            .canister_query(
+                &self.canister,
+                "swap_amounts",
+                (Self::token(pay), pay_amount, Self::token(receive)),
+            )
+            .await?;
+        res.map_err(|err| format!("KongSwap quote failed: {}", err).into())
+    }
+
+    /// Swaps with an ICRC-2 allowance approved to the KongSwap canister. The received tokens
+    /// go to the caller. The swap fails if fewer than `min_receive` base units would be received.
+    pub async fn swap(
+        &self,
+        ctx: &impl CanisterCaller,
+        pay: &Principal,
+        pay_amount: Nat,
+        receive: &Principal,
+        min_receive: Nat,
+        max_slippage: f64,
+    ) -> Result<SwapReply, BoxError> {
+        let res: Result<SwapReply, String> = ctx
+            .canister_update(
+                &self.canister,
+                "swap",
+                (SwapArgs {
+                    pay_token: Self::token(pay),
+                    pay_amount,
+                    pay_tx_id: None,
+                    receive_token: Self::token(receive),
+                    receive_amount: Some(min_receive),
+                    receive_address: None,
+                    max_slippage: Some(max_slippage),
+                    referred_by: None,
+                },),
+            )
+            .await?;
+        res.map_err(|err| format!("KongSwap swap failed: {}", err).into())
+    }
+}
+
+impl Default for KongSwap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,370 @@
+//! Module for quoting and executing token swaps on ICP DEXes
+//!
+//! This module provides functionality for:
+//! - Querying the prices of token pairs on [KongSwap](https://www.kongswap.io/) and
+//!   [ICPSwap](https://www.icpswap.com/) pools
+//! - Simulating swaps to compare the amounts received on each DEX
+//! - Executing swaps on the best DEX within a slippage limit
+//!
+//! Swaps spend the Agent's tokens, so [`DexSwapTool`] should be gated by operator approval
+//! in deployments, while [`DexQuoteTool`] is read-only:
+//! ```rust,ignore
+//! use anda_engine::management::{ApprovalPolicy, ManagementBuilder};
+//! use anda_icp::dex::{DexSwapTool, ICPDexes, KongSwap};
+//!
+//! let dexes = Arc::new(ICPDexes::new(ledgers).with_kongswap(KongSwap::new()));
+//! let policy = ApprovalPolicy::new(operators, 2, Duration::from_secs(3600))
+//!     .with_tool(DexSwapTool::NAME);
+//! let engine = Engine::builder()
+//!     .with_management(ManagementBuilder::new(visibility, controller).with_approval_policy(policy)?)
+//!     .register_tool(DexQuoteTool::new(dexes.clone()))?
+//!     .register_tool(DexSwapTool::new(dexes))?
+//!     .build("default_agent".to_string())?;
+//! ```
+
+use anda_core::{BoxError, CanisterCaller};
+use candid::{Nat, Principal};
+use icrc_ledger_types::{
+    icrc1::account::Account,
+    icrc2::approve::{ApproveArgs, ApproveError},
+};
+use num_traits::cast::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::ledger::ICPLedgers;
+
+pub mod icpswap;
+pub mod kongswap;
+pub mod quote;
+pub mod swap;
+
+pub use icpswap::ICPSwap;
+pub use kongswap::KongSwap;
+pub use quote::*;
+pub use swap::*;
+
+/// A simulated swap on a DEX
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SwapQuote {
+    /// The DEX, "KongSwap" or "ICPSwap"
+    pub venue: String,
+    pub pay_symbol: String,
+    pub pay_amount: f64,
+    pub receive_symbol: String,
+    pub receive_amount: f64,
+    /// The amount of receive tokens per pay token
+    pub price: f64,
+    /// The price impact of the swap in percent, if reported by the DEX
+    pub price_impact: Option<f64>,
+}
+
+/// The result of an executed swap
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SwapReceipt {
+    pub venue: String,
+    pub pay_symbol: String,
+    pub pay_amount: f64,
+    pub receive_symbol: String,
+    pub receive_amount: f64,
+    /// The minimum amount that was accepted, after the slippage limit
+    pub min_receive_amount: f64,
+    /// The transaction ID on the DEX, if any
+    pub tx_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Venue {
+    KongSwap,
+    ICPSwap,
+}
+
+impl Venue {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Venue::KongSwap => "KongSwap",
+            Venue::ICPSwap => "ICPSwap",
+        }
+    }
+}
+
+struct Quoted {
+    venue: Venue,
+    quote: SwapQuote,
+    receive_units: Nat,
+}
+
+/// DEXes on ICP for the tokens of the ledgers
+#[derive(Debug, Clone)]
+pub struct ICPDexes {
+    /// The ledgers of the tradable tokens
+    pub ledgers: Arc<ICPLedgers>,
+    pub kongswap: Option<KongSwap>,
+    pub icpswap: Option<ICPSwap>,
+    /// The maximum slippage allowed for swaps, in percent
+    pub max_slippage: f64,
+}
+
+impl ICPDexes {
+    /// Creates ICPDexes for the ledgers, with a 1% maximum slippage and no DEX.
+    pub fn new(ledgers: Arc<ICPLedgers>) -> Self {
+        Self {
+            ledgers,
+            kongswap: None,
+            icpswap: None,
+            max_slippage: 1.0,
+        }
+    }
+
+    pub fn with_kongswap(mut self, kongswap: KongSwap) -> Self {
+        self.kongswap = Some(kongswap);
+        self
+    }
+
+    pub fn with_icpswap(mut self, icpswap: ICPSwap) -> Self {
+        self.icpswap = Some(icpswap);
+        self
+    }
+
+    /// Sets the maximum slippage allowed for swaps, in percent.
+    pub fn with_max_slippage(mut self, max_slippage: f64) -> Self {
+        self.max_slippage = max_slippage;
+        self
+    }
+
+    fn token(&self, symbol: &str) -> Result<(Principal, u8), BoxError> {
+        self.ledgers
+            .ledgers
+            .get(symbol)
+            .copied()
+            .ok_or_else(|| format!("Token {} is not supported", symbol).into())
+    }
+
+    async fn quote_all(
+        &self,
+        ctx: &impl CanisterCaller,
+        args: &QuoteSwapArgs,
+    ) -> Result<Vec<Quoted>, BoxError> {
+        if args.pay_symbol == args.receive_symbol {
+            return Err("pay and receive tokens should be different".into());
+        }
+        if args.pay_amount.is_nan() || args.pay_amount <= 0.0 {
+            return Err("pay amount should be positive".into());
+        }
+        let (pay, pay_decimals) = self.token(&args.pay_symbol)?;
+        let (receive, receive_decimals) = self.token(&args.receive_symbol)?;
+        let pay_units = to_units(args.pay_amount, pay_decimals);
+
+        let mut quotes = Vec::new();
+        let mut errors = Vec::new();
+        let mut push = |venue: Venue, receive_units: Nat, price_impact: Option<f64>| {
+            let receive_amount = from_units(&receive_units, receive_decimals);
+            quotes.push(Quoted {
+                venue,
+                quote: SwapQuote {
+                    venue: venue.as_str().to_string(),
+                    pay_symbol: args.pay_symbol.clone(),
+                    pay_amount: args.pay_amount,
+                    receive_symbol: args.receive_symbol.clone(),
+                    receive_amount,
+                    price: receive_amount / args.pay_amount,
+                    price_impact,
+                },
+                receive_units,
+            });
+        };
+
+        if let Some(kongswap) = &self.kongswap {
+            match kongswap.quote(ctx, &pay, pay_units.clone(), &receive).await {
+                Ok(res) => push(Venue::KongSwap, res.receive_amount, Some(res.slippage)),
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+        if let Some(icpswap) = &self.icpswap
+            && icpswap.pool(&pay, &receive).is_some()
+        {
+            match icpswap.quote(ctx, &pay, pay_units.clone(), &receive).await {
+                Ok(res) => push(Venue::ICPSwap, res, None),
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+
+        quotes.retain(|q| q.receive_units > 0u64);
+        if quotes.is_empty() {
+            if errors.is_empty() {
+                return Err(format!(
+                    "no DEX supports {} to {}",
+                    args.pay_symbol, args.receive_symbol
+                )
+                .into());
+            }
+            return Err(errors.join("; ").into());
+        }
+        quotes.sort_by(|a, b| b.receive_units.cmp(&a.receive_units));
+        Ok(quotes)
+    }
+
+    /// Simulates the swap on every DEX, returns the quotes from the best to the worst.
+    pub async fn quote(
+        &self,
+        ctx: &impl CanisterCaller,
+        args: QuoteSwapArgs,
+    ) -> Result<Vec<SwapQuote>, BoxError> {
+        let quotes = self.quote_all(ctx, &args).await?;
+        Ok(quotes.into_iter().map(|q| q.quote).collect())
+    }
+
+    /// Executes the swap on the DEX with the best quote, paying from the Agent's main account.
+    /// The swap fails if the received amount would be lower than the quote by more than the
+    /// slippage limit.
+    pub async fn swap(
+        &self,
+        ctx: &impl CanisterCaller,
+        args: SwapTokensArgs,
+    ) -> Result<SwapReceipt, BoxError> {
+        let slippage = args.max_slippage.unwrap_or(self.max_slippage);
+        if !(0.0..=self.max_slippage).contains(&slippage) {
+            return Err(format!(
+                "slippage {}% exceeds the limit of {}%",
+                slippage, self.max_slippage
+            )
+            .into());
+        }
+
+        let quote_args = QuoteSwapArgs {
+            pay_symbol: args.pay_symbol,
+            pay_amount: args.pay_amount,
+            receive_symbol: args.receive_symbol,
+        };
+        let best = self
+            .quote_all(ctx, &quote_args)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or("no quote available")?;
+        let (pay, pay_decimals) = self.token(&quote_args.pay_symbol)?;
+        let (receive, receive_decimals) = self.token(&quote_args.receive_symbol)?;
+        let pay_units = to_units(quote_args.pay_amount, pay_decimals);
+        let min_receive = min_receive_units(&best.receive_units, slippage);
+
+        let spender = match best.venue {
+            Venue::KongSwap => self.kongswap.as_ref().map(|k| k.canister),
+            Venue::ICPSwap => self
+                .icpswap
+                .as_ref()
+                .and_then(|s| s.pool(&pay, &receive))
+                .map(|p| p.canister),
+        }
+        .ok_or("DEX not found")?;
+        let pay_fee: Nat = ctx.canister_query(&pay, "icrc1_fee", ()).await?;
+        let approved: Result<Nat, ApproveError> = ctx
+            .canister_update(
+                &pay,
+                "icrc2_approve",
+                (ApproveArgs {
+                    from_subaccount: None,
+                    spender: Account {
+                        owner: spender,
+                        subaccount: None,
+                    },
+                    amount: pay_units.clone() + pay_fee.clone(),
+                    expected_allowance: None,
+                    expires_at: None,
+                    fee: None,
+                    memo: None,
+                    created_at_time: None,
+                },),
+            )
+            .await?;
+        approved.map_err(|err| format!("failed to approve tokens, error: {:?}", err))?;
+
+        let (receive_units, tx_id) = match best.venue {
+            Venue::KongSwap => {
+                let kongswap = self.kongswap.as_ref().ok_or("DEX not found")?;
+                let res = kongswap
+                    .swap(
+                        ctx,
+                        &pay,
+                        pay_units,
+                        &receive,
+                        min_receive.clone(),
+                        slippage,
+                    )
+                    .await?;
+                (res.receive_amount, Some(res.tx_id.to_string()))
+            }
+            Venue::ICPSwap => {
+                let icpswap = self.icpswap.as_ref().ok_or("DEX not found")?;
+                let receive_fee: Nat = ctx.canister_query(&receive, "icrc1_fee", ()).await?;
+                let res = icpswap
+                    .swap(
+                        ctx,
+                        &pay,
+                        pay_units,
+                        &receive,
+                        min_receive.clone(),
+                        pay_fee,
+                        receive_fee,
+                    )
+                    .await?;
+                (res, None)
+            }
+        };
+
+        let receipt = SwapReceipt {
+            venue: best.quote.venue,
+            pay_symbol: quote_args.pay_symbol,
+            pay_amount: quote_args.pay_amount,
+            receive_symbol: quote_args.receive_symbol,
+            receive_amount: from_units(&receive_units, receive_decimals),
+            min_receive_amount: from_units(&min_receive, receive_decimals),
+            tx_id,
+        };
+        log::info!(
+            venue = receipt.venue,
+            pay_symbol = receipt.pay_symbol,
+            pay_amount = receipt.pay_amount,
+            receive_symbol = receipt.receive_symbol,
+            receive_amount = receipt.receive_amount;
+            "dex_swap",
+        );
+        Ok(receipt)
+    }
+}
+
+fn to_units(amount: f64, decimals: u8) -> Nat {
+    Nat::from((amount * 10u64.pow(decimals as u32) as f64) as u64)
+}
+
+fn from_units(amount: &Nat, decimals: u8) -> f64 {
+    amount.0.to_f64().unwrap_or_default() / 10u64.pow(decimals as u32) as f64
+}
+
+/// Returns the quoted amount reduced by the slippage in percent, rounded down.
+fn min_receive_units(quoted: &Nat, slippage: f64) -> Nat {
+    let bps = (slippage * 100.0).round().clamp(0.0, 10_000.0) as u64;
+    quoted.clone() * Nat::from(10_000 - bps) / Nat::from(10_000u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_receive_units() {
+        assert_eq!(
+            min_receive_units(&Nat::from(1_000_000u64), 0.5),
+            Nat::from(995_000u64)
+        );
+        assert_eq!(
+            min_receive_units(&Nat::from(999u64), 1.0),
+            Nat::from(989u64)
+        );
+        assert_eq!(
+            min_receive_units(&Nat::from(999u64), 0.0),
+            Nat::from(999u64)
+        );
+        assert_eq!(to_units(1.5, 8), Nat::from(150_000_000u64));
+        assert_eq!(from_units(&Nat::from(150_000_000u64), 8), 1.5);
+    }
+}
@@ -0,0 +1,85 @@
+//! Enables AI Agent to query the prices of token pairs on ICP DEXes
+//!
+//! Simulates a swap on every configured DEX without executing it, so that the Agent can
+//! compare the prices, the amounts received and the price impacts.
+
+use anda_core::{BoxError, FunctionDefinition, Resource, Tool, ToolOutput, gen_schema_for};
+use anda_engine::context::BaseCtx;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{ICPDexes, SwapQuote};
+
+/// Arguments for quoting a swap
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct QuoteSwapArgs {
+    /// Symbol of the token to pay, e.g. "ICP"
+    pub pay_symbol: String,
+    /// Amount of the token to pay, e.g. 1.1 ICP
+    pub pay_amount: f64,
+    /// Symbol of the token to receive, e.g. "ckUSDC"
+    pub receive_symbol: String,
+}
+
+/// ICP DEX quote tool implementation
+#[derive(Debug, Clone)]
+pub struct DexQuoteTool {
+    dexes: Arc<ICPDexes>,
+    schema: Value,
+}
+
+impl DexQuoteTool {
+    pub const NAME: &'static str = "icp_dex_quote";
+
+    /// Creates a new DexQuoteTool instance
+    pub fn new(dexes: Arc<ICPDexes>) -> Self {
+        let schema = gen_schema_for::<QuoteSwapArgs>();
+
+        DexQuoteTool { dexes, schema }
+    }
+}
+
+/// Implementation of the [`Tool`] trait for DexQuoteTool
+impl Tool<BaseCtx> for DexQuoteTool {
+    type Args = QuoteSwapArgs;
+    type Output = Vec<SwapQuote>;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        let tokens = self
+            .dexes
+            .ledgers
+            .ledgers
+            .keys()
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>();
+        format!(
+            "Query the swap prices on ICP DEXes without swapping, for the following tokens: {}. Quotes are sorted from the best to the worst.",
+            tokens.join(", ")
+        )
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        data: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let quotes = self.dexes.quote(&ctx, data).await?;
+        Ok(ToolOutput::new(quotes))
+    }
+}
@@ -0,0 +1,226 @@
+//! Enables AI Agent to swap tokens on ICP DEXes
+//!
+//! Executes a swap from the Agent's main account on the DEX with the best quote. The swap
+//! is rejected by the DEX if the received amount would be lower than the quote by more than
+//! the slippage limit. Register this tool in the [`anda_engine::management::ApprovalPolicy`]
+//! so that swaps are executed only after operator approval.
+
+use anda_core::{BoxError, FunctionDefinition, Resource, Tool, ToolOutput, gen_schema_for};
+use anda_engine::context::BaseCtx;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{ICPDexes, SwapReceipt};
+
+/// Arguments for swapping tokens
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct SwapTokensArgs {
+    /// Symbol of the token to pay, e.g. "ICP"
+    pub pay_symbol: String,
+    /// Amount of the token to pay, e.g. 1.1 ICP
+    pub pay_amount: f64,
+    /// Symbol of the token to receive, e.g. "ckUSDC"
+    pub receive_symbol: String,
+    /// Maximum slippage in percent, e.g. 0.5. Defaults to the configured limit
+    pub max_slippage: Option<f64>,
+}
+
+/// ICP DEX swap tool implementation
+#[derive(Debug, Clone)]
+pub struct DexSwapTool {
+    dexes: Arc<ICPDexes>,
+    schema: Value,
+}
+
+impl DexSwapTool {
+    pub const NAME: &'static str = "icp_dex_swap";
+
+    /// Creates a new DexSwapTool instance
+    pub fn new(dexes: Arc<ICPDexes>) -> Self {
+        let schema = gen_schema_for::<SwapTokensArgs>();
+
+        DexSwapTool { dexes, schema }
+    }
+}
+
+/// Implementation of the [`Tool`] trait for DexSwapTool
+impl Tool<BaseCtx> for DexSwapTool {
+    type Args = SwapTokensArgs;
+    type Output = SwapReceipt;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "Swap tokens on the ICP DEX with the best price, with at most {}% slippage. Query the quotes before swapping.",
+            self.dexes.max_slippage
+        )
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        data: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let receipt = self.dexes.swap(&ctx, data).await?;
+        Ok(ToolOutput::new(receipt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dex::{
+            ICPSwap, KongSwap, QuoteSwapArgs,
+            icpswap::{DepositAndSwapArgs, ICPSwapPool, ICPSwapResult},
+            kongswap::{SwapAmountsReply, SwapArgs, SwapReply},
+        },
+        ledger::ICPLedgers,
+    };
+    use anda_engine::{context::mock, management::ApprovalPolicy};
+    use candid::{Nat, Principal, decode_args, encode_args};
+    use icrc_ledger_types::icrc2::approve::{ApproveArgs, ApproveError};
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        sync::Mutex,
+        time::Duration,
+    };
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_icp_dex_swap() {
+        let icp = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let panda = Principal::from_text("druyg-tyaaa-aaaaq-aactq-cai").unwrap();
+        let pool = Principal::from_text("5fq4w-lyaaa-aaaag-qjqta-cai").unwrap();
+        let ledgers = Arc::new(ICPLedgers {
+            ledgers: BTreeMap::from([
+                (String::from("ICP"), (icp, 8)),
+                (String::from("PANDA"), (panda, 8)),
+            ]),
+            from_user_subaccount: false,
+        });
+        let mut icpswap = ICPSwap::default();
+        icpswap.pools.insert(
+            (icp, panda),
+            ICPSwapPool {
+                canister: pool,
+                token0: icp,
+                token1: panda,
+            },
+        );
+        let kongswap = KongSwap::new();
+        let kong = kongswap.canister;
+        let dexes = Arc::new(
+            ICPDexes::new(ledgers)
+                .with_kongswap(kongswap)
+                .with_icpswap(icpswap)
+                .with_max_slippage(2.0),
+        );
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls2 = calls.clone();
+        let mocker = mock::MockCanisterCaller::new(move |canister, method, args| {
+            calls2.lock().unwrap().push(method.to_string());
+            match method {
+                "swap_amounts" => {
+                    let res: Result<SwapAmountsReply, String> = Ok(SwapAmountsReply {
+                        pay_symbol: "ICP".to_string(),
+                        pay_amount: Nat::from(100_000_000u64),
+                        receive_symbol: "PANDA".to_string(),
+                        receive_amount: Nat::from(900_000_000u64),
+                        price: 9.0,
+                        mid_price: 9.1,
+                        slippage: 0.8,
+                    });
+                    encode_args((res,)).unwrap()
+                }
+                "quote" => encode_args((ICPSwapResult::Ok(Nat::from(1_000_000_000u64)),)).unwrap(),
+                "icrc1_fee" => encode_args((Nat::from(10_000u64),)).unwrap(),
+                "icrc2_approve" => {
+                    assert_eq!(canister, &icp);
+                    let (args,): (ApproveArgs,) = decode_args(&args).unwrap();
+                    assert_eq!(args.spender.owner, pool);
+                    assert_eq!(args.amount, Nat::from(100_010_000u64));
+                    let res: Result<Nat, ApproveError> = Ok(Nat::from(1u64));
+                    encode_args((res,)).unwrap()
+                }
+                "depositFromAndSwap" => {
+                    assert_eq!(canister, &pool);
+                    let (args,): (DepositAndSwapArgs,) = decode_args(&args).unwrap();
+                    assert!(args.zero_for_one);
+                    assert_eq!(args.amount_in, "100000000");
+                    assert_eq!(args.amount_out_minimum, "995000000");
+                    encode_args((ICPSwapResult::Ok(Nat::from(998_000_000u64)),)).unwrap()
+                }
+                "swap" => {
+                    assert_eq!(canister, &kong);
+                    let (_args,): (SwapArgs,) = decode_args(&args).unwrap();
+                    let res: Result<SwapReply, String> = Err("unexpected".to_string());
+                    encode_args((res,)).unwrap()
+                }
+                _ => panic!("unexpected method {}", method),
+            }
+        });
+
+        let quotes = dexes
+            .quote(
+                &mocker,
+                QuoteSwapArgs {
+                    pay_symbol: "ICP".to_string(),
+                    pay_amount: 1.0,
+                    receive_symbol: "PANDA".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes[0].venue, "ICPSwap");
+        assert_eq!(quotes[0].receive_amount, 10.0);
+        assert_eq!(quotes[1].venue, "KongSwap");
+        assert_eq!(quotes[1].price_impact, Some(0.8));
+
+        let args = SwapTokensArgs {
+            pay_symbol: "ICP".to_string(),
+            pay_amount: 1.0,
+            receive_symbol: "PANDA".to_string(),
+            max_slippage: Some(5.0),
+        };
+        let err = dexes.swap(&mocker, args.clone()).await.unwrap_err();
+        assert!(err.to_string().contains("exceeds the limit"));
+
+        calls.lock().unwrap().clear();
+        let receipt = dexes
+            .swap(
+                &mocker,
+                SwapTokensArgs {
+                    max_slippage: Some(0.5),
+                    ..args
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(receipt.venue, "ICPSwap");
+        assert_eq!(receipt.receive_amount, 9.98);
+        assert_eq!(receipt.min_receive_amount, 9.95);
+        assert!(!calls.lock().unwrap().contains(&"swap".to_string()));
+
+        let operator = [1u8; 32];
+        let policy = ApprovalPolicy::new(BTreeSet::from([operator]), 1, Duration::from_secs(60))
+            .with_tool(DexSwapTool::NAME);
+        assert!(policy.requires_approval(&DexSwapTool::new(dexes).name()));
+    }
+}
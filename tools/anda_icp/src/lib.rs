@@ -1 +1,4 @@
+pub mod dex;
 pub mod ledger;
+pub mod nft;
+pub mod sns;
@@ -0,0 +1,199 @@
+//! Enables AI Agent to mint ICRC-7 NFTs of its outputs
+//!
+//! The media resource of the call, e.g. an image generated by the Agent, is uploaded to the
+//! tool's storage together with the generated metadata JSON, then the NFT is minted with
+//! the media and metadata URLs.
+
+use anda_core::{
+    BoxError, FunctionDefinition, Path, PutMode, Resource, StateFeatures, StoreFeatures, Tool,
+    ToolOutput, gen_schema_for,
+};
+use anda_engine::context::BaseCtx;
+use candid::Principal;
+use ic_cose_types::ANONYMOUS;
+use icrc_ledger_types::{icrc::generic_value::ICRC3Value, icrc1::account::Account};
+use num_traits::cast::ToPrimitive;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{ICRC7Collections, NFTAttribute, media_path, metadata_json};
+
+/// Arguments for minting an NFT
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct MintNFTArgs {
+    /// Collection symbol, e.g. "PANDA"
+    pub symbol: String,
+    /// Name of the NFT
+    pub name: String,
+    /// Description of the NFT
+    pub description: String,
+    /// Attributes of the NFT
+    #[serde(default)]
+    pub attributes: Vec<NFTAttribute>,
+    /// ICP principal to receive the NFT, defaults to the user
+    pub owner: Option<String>,
+}
+
+/// The minted NFT
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MintedNFT {
+    pub symbol: String,
+    pub token_id: u64,
+    pub owner: String,
+    pub image: Option<String>,
+    pub metadata_url: String,
+}
+
+/// ICRC-7 NFT mint tool implementation
+#[derive(Debug, Clone)]
+pub struct NFTMintTool {
+    collections: Arc<ICRC7Collections>,
+    schema: Value,
+}
+
+impl NFTMintTool {
+    pub const NAME: &'static str = "icp_nft_mint";
+
+    /// Creates a new NFTMintTool instance
+    pub fn new(collections: Arc<ICRC7Collections>) -> Self {
+        let schema = gen_schema_for::<MintNFTArgs>();
+
+        NFTMintTool {
+            collections,
+            schema,
+        }
+    }
+
+    async fn upload(
+        &self,
+        ctx: &impl StoreFeatures,
+        path: String,
+        data: Vec<u8>,
+    ) -> Result<(), BoxError> {
+        // content-addressed, so overwriting is idempotent
+        ctx.store_put(&Path::from(path), PutMode::Overwrite, data.into())
+            .await?;
+        Ok(())
+    }
+}
+
+/// Implementation of the [`Tool`] trait for NFTMintTool
+impl Tool<BaseCtx> for NFTMintTool {
+    type Args = MintNFTArgs;
+    type Output = MintedNFT;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        let collections = self
+            .collections
+            .collections
+            .keys()
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>();
+        format!(
+            "Mint an NFT on ICP blockchain with the attached image, video or audio as its media, for the following collections: {}",
+            collections.join(", ")
+        )
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    fn supported_resource_tags(&self) -> Vec<String> {
+        vec![
+            "image".to_string(),
+            "video".to_string(),
+            "audio".to_string(),
+        ]
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        data: Self::Args,
+        resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let owner = match &data.owner {
+            Some(owner) => Principal::from_text(owner)?,
+            None if ctx.caller() != ANONYMOUS => ctx.caller(),
+            None => return Err("owner is required".into()),
+        };
+
+        let media = resources.and_then(|rs| rs.into_iter().next());
+        let image = match &media {
+            Some(resource) => match media_path(resource) {
+                Some(path) => {
+                    let blob = resource.blob.as_ref().map(|b| b.to_vec());
+                    self.upload(&ctx, path.clone(), blob.unwrap_or_default())
+                        .await?;
+                    Some(self.collections.media_url(&path))
+                }
+                None => resource.uri.clone(),
+            },
+            None => None,
+        };
+
+        let json = metadata_json(
+            &data.name,
+            &data.description,
+            image.as_deref(),
+            &data.attributes,
+        );
+        let json = serde_json::to_vec(&json)?;
+        let path = media_path(&Resource {
+            tag: "json".to_string(),
+            mime_type: Some("application/json".to_string()),
+            blob: Some(json.clone().into()),
+            ..Default::default()
+        })
+        .expect("metadata should have blob");
+        self.upload(&ctx, path.clone(), json).await?;
+        let metadata_url = self.collections.media_url(&path);
+
+        let mut metadata = vec![
+            ("icrc7:name".to_string(), ICRC3Value::Text(data.name)),
+            (
+                "icrc7:description".to_string(),
+                ICRC3Value::Text(data.description),
+            ),
+            (
+                "icrc7:metadata".to_string(),
+                ICRC3Value::Text(metadata_url.clone()),
+            ),
+        ];
+        if let Some(image) = &image {
+            metadata.push(("icrc7:image".to_string(), ICRC3Value::Text(image.clone())));
+        }
+        let token_id = self
+            .collections
+            .mint(
+                &ctx,
+                &data.symbol,
+                Account {
+                    owner,
+                    subaccount: None,
+                },
+                metadata,
+            )
+            .await?;
+
+        Ok(ToolOutput::new(MintedNFT {
+            symbol: data.symbol,
+            token_id: token_id.0.to_u64().unwrap_or(0),
+            owner: owner.to_text(),
+            image,
+            metadata_url,
+        }))
+    }
+}
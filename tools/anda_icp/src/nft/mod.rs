@@ -0,0 +1,396 @@
+//! Module for minting, transferring and querying ICRC-7 NFTs
+//!
+//! This module provides functionality for:
+//! - Loading multiple ICRC-7 collection canisters by their symbols
+//! - Uploading media resources to the Agent's storage and generating metadata JSON
+//! - Minting NFTs of the Agent's outputs, transferring and querying NFTs
+//!
+//! ICRC-7 does not standardize minting. Collections are minted with the
+//! `icrc7_mint : (vec MintArg) -> (vec opt TransferResult)` method by default, where
+//! `MintArg = record { to : Account; token_id : nat; metadata : vec record { text; Value }; memo : opt blob; created_at_time : opt nat64 }`,
+//! and the Agent must be a minter of the collections. Set another method with compatible
+//! arguments by [`ICRC7Collections::with_mint_method`].
+//!
+//! Media and metadata JSON are stored in the mint tool's storage at `nft/<sha3-256>.<ext>`,
+//! so the `media_base_url` should be the public URL where that storage is served.
+//!
+//! # Examples
+//! ```rust,ignore
+//! use anda_icp::nft::ICRC7Collections;
+//!
+//! let canisters = BTreeSet::from([Principal::from_text("xxxxx-xxxxx-xxxxx-xxxxx-cai").unwrap()]);
+//! let collections = ICRC7Collections::load(ctx, canisters, "https://media.example.com/icp_nft_mint".to_string()).await?;
+//! ```
+
+use anda_core::{BoxError, CanisterCaller, Resource};
+use candid::{CandidType, Nat, Principal};
+use ic_cose_types::cose::sha3_256;
+use icrc_ledger_types::{
+    icrc::generic_value::ICRC3Value,
+    icrc1::{
+        account::{Account, Subaccount},
+        transfer::Memo,
+    },
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub mod mint;
+pub mod query;
+pub mod transfer;
+
+pub use mint::*;
+pub use query::*;
+pub use transfer::*;
+
+/// Arguments of the ICRC-7 `icrc7_transfer` method
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<Memo>,
+    pub created_at_time: Option<u64>,
+}
+
+/// Errors of the ICRC-7 `icrc7_transfer` method
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+/// Arguments of the mint method
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct MintArg {
+    pub to: Account,
+    pub token_id: Nat,
+    pub metadata: Vec<(String, ICRC3Value)>,
+    pub memo: Option<Memo>,
+    pub created_at_time: Option<u64>,
+}
+
+/// An attribute of an NFT, e.g. { "trait_type": "style", "value": "pixel art" }
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct NFTAttribute {
+    pub trait_type: String,
+    pub value: String,
+}
+
+/// The metadata of an NFT
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NFTInfo {
+    pub symbol: String,
+    pub token_id: u64,
+    /// The owner's principal, if the token exists
+    pub owner: Option<String>,
+    /// The token metadata, with ICRC-3 values converted to JSON
+    pub metadata: BTreeMap<String, Value>,
+}
+
+/// ICRC-7 collections the Agent can mint, transfer and query
+#[derive(Debug, Clone)]
+pub struct ICRC7Collections {
+    /// Map of collection symbols to their canister IDs
+    pub collections: BTreeMap<String, Principal>,
+    /// The public URL where the mint tool's storage is served
+    pub media_base_url: String,
+    /// The method to mint NFTs, "icrc7_mint" by default
+    pub mint_method: String,
+}
+
+impl ICRC7Collections {
+    /// Loads the collections by their canister IDs
+    ///
+    /// # Arguments
+    /// * `ctx` - Canister caller context
+    /// * `canisters` - Set of 1 to N ICRC-7 collection canister IDs
+    /// * `media_base_url` - The public URL where the mint tool's storage is served
+    pub async fn load(
+        ctx: &impl CanisterCaller,
+        canisters: BTreeSet<Principal>,
+        media_base_url: String,
+    ) -> Result<ICRC7Collections, BoxError> {
+        if canisters.is_empty() {
+            return Err("No collection canister specified".into());
+        }
+        let mut collections = BTreeMap::new();
+        for canister in canisters {
+            let symbol: String = ctx.canister_query(&canister, "icrc7_symbol", ()).await?;
+            collections.insert(symbol, canister);
+        }
+
+        Ok(ICRC7Collections {
+            collections,
+            media_base_url: media_base_url.trim_end_matches('/').to_string(),
+            mint_method: "icrc7_mint".to_string(),
+        })
+    }
+
+    pub fn with_mint_method(mut self, method: String) -> Self {
+        self.mint_method = method;
+        self
+    }
+
+    fn collection(&self, symbol: &str) -> Result<Principal, BoxError> {
+        self.collections
+            .get(symbol)
+            .copied()
+            .ok_or_else(|| format!("Collection {} is not supported", symbol).into())
+    }
+
+    /// Returns the public URL of a stored object.
+    fn media_url(&self, path: &str) -> String {
+        format!("{}/{}", self.media_base_url, path)
+    }
+
+    /// Mints an NFT with the next token ID of the collection.
+    async fn mint(
+        &self,
+        ctx: &impl CanisterCaller,
+        symbol: &str,
+        to: Account,
+        metadata: Vec<(String, ICRC3Value)>,
+    ) -> Result<Nat, BoxError> {
+        let canister = self.collection(symbol)?;
+        let token_id: Nat = ctx
+            .canister_query(&canister, "icrc7_total_supply", ())
+            .await?;
+        let res: Vec<Option<Result<Nat, TransferError>>> = ctx
+            .canister_update(
+                &canister,
+                &self.mint_method,
+                (vec![MintArg {
+                    to,
+                    token_id: token_id.clone(),
+                    metadata,
+                    memo: None,
+                    created_at_time: None,
+                }],),
+            )
+            .await?;
+        log::info!(
+            symbol = symbol,
+            token_id = token_id.to_string(),
+            to = to.owner.to_text();
+            "icrc7_mint",
+        );
+        match res.into_iter().next().flatten() {
+            Some(Ok(_)) => Ok(token_id),
+            Some(Err(err)) => Err(format!("failed to mint NFT, error: {:?}", err).into()),
+            None => Err("failed to mint NFT, no result".into()),
+        }
+    }
+
+    /// Transfers an NFT from the Agent's main account, returns the transaction ID.
+    async fn transfer(
+        &self,
+        ctx: &impl CanisterCaller,
+        symbol: &str,
+        token_id: u64,
+        to: Principal,
+    ) -> Result<Nat, BoxError> {
+        let canister = self.collection(symbol)?;
+        let res: Vec<Option<Result<Nat, TransferError>>> = ctx
+            .canister_update(
+                &canister,
+                "icrc7_transfer",
+                (vec![TransferArg {
+                    from_subaccount: None,
+                    to: Account {
+                        owner: to,
+                        subaccount: None,
+                    },
+                    token_id: Nat::from(token_id),
+                    memo: None,
+                    created_at_time: None,
+                }],),
+            )
+            .await?;
+        log::info!(
+            symbol = symbol,
+            token_id = token_id,
+            to = to.to_text();
+            "icrc7_transfer",
+        );
+        match res.into_iter().next().flatten() {
+            Some(Ok(tx)) => Ok(tx),
+            Some(Err(err)) => Err(format!("failed to transfer NFT, error: {:?}", err).into()),
+            None => Err("failed to transfer NFT, no result".into()),
+        }
+    }
+
+    /// Retrieves the owner and metadata of an NFT.
+    async fn token_info(
+        &self,
+        ctx: &impl CanisterCaller,
+        symbol: &str,
+        token_id: u64,
+    ) -> Result<NFTInfo, BoxError> {
+        let canister = self.collection(symbol)?;
+        let ids = vec![Nat::from(token_id)];
+        let owners: Vec<Option<Account>> = ctx
+            .canister_query(&canister, "icrc7_owner_of", (ids.clone(),))
+            .await?;
+        let metadata: Vec<Option<Vec<(String, ICRC3Value)>>> = ctx
+            .canister_query(&canister, "icrc7_token_metadata", (ids,))
+            .await?;
+        Ok(NFTInfo {
+            symbol: symbol.to_string(),
+            token_id,
+            owner: owners.into_iter().next().flatten().map(|a| a.to_string()),
+            metadata: metadata
+                .into_iter()
+                .next()
+                .flatten()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| (k, icrc3_to_json(v)))
+                .collect(),
+        })
+    }
+
+    /// Lists the token IDs owned by an account.
+    async fn tokens_of(
+        &self,
+        ctx: &impl CanisterCaller,
+        symbol: &str,
+        owner: Principal,
+    ) -> Result<Vec<u64>, BoxError> {
+        let canister = self.collection(symbol)?;
+        let ids: Vec<Nat> = ctx
+            .canister_query(
+                &canister,
+                "icrc7_tokens_of",
+                (
+                    Account {
+                        owner,
+                        subaccount: None,
+                    },
+                    None::<Nat>,
+                    None::<Nat>,
+                ),
+            )
+            .await?;
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| u64::try_from(id.0).ok())
+            .collect())
+    }
+}
+
+/// Returns the storage path of a media resource: `nft/<sha3-256>.<ext>`.
+/// Resources without blob are not stored.
+pub fn media_path(resource: &Resource) -> Option<String> {
+    let blob = resource.blob.as_ref()?;
+    let hash = resource
+        .hash
+        .as_ref()
+        .map(|h| h.0)
+        .unwrap_or_else(|| sha3_256(blob));
+    let ext = match resource.mime_type.as_deref() {
+        Some("image/png") => "png",
+        Some("image/jpeg") => "jpg",
+        Some("image/gif") => "gif",
+        Some("image/webp") => "webp",
+        Some("image/svg+xml") => "svg",
+        Some("video/mp4") => "mp4",
+        Some("audio/mpeg") => "mp3",
+        Some("application/json") => "json",
+        _ => "bin",
+    };
+    Some(format!("nft/{}.{}", const_hex::encode(hash), ext))
+}
+
+/// Generates the metadata JSON of an NFT, in the format widely supported by NFT marketplaces.
+pub fn metadata_json(
+    name: &str,
+    description: &str,
+    image: Option<&str>,
+    attributes: &[NFTAttribute],
+) -> Value {
+    let mut metadata = json!({
+        "name": name,
+        "description": description,
+        "attributes": attributes,
+    });
+    if let Some(image) = image {
+        metadata["image"] = image.into();
+    }
+    metadata
+}
+
+fn icrc3_to_json(value: ICRC3Value) -> Value {
+    match value {
+        ICRC3Value::Text(s) => Value::String(s),
+        ICRC3Value::Nat(n) => match u64::try_from(n.0.clone()) {
+            Ok(v) => v.into(),
+            Err(_) => n.0.to_string().into(),
+        },
+        ICRC3Value::Int(i) => match i64::try_from(i.0.clone()) {
+            Ok(v) => v.into(),
+            Err(_) => i.0.to_string().into(),
+        },
+        ICRC3Value::Blob(b) => const_hex::encode(b).into(),
+        ICRC3Value::Array(arr) => arr.into_iter().map(icrc3_to_json).collect(),
+        ICRC3Value::Map(map) => map
+            .into_iter()
+            .map(|(k, v)| (k, icrc3_to_json(v)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_path_and_metadata() {
+        let resource = Resource {
+            tag: "image".to_string(),
+            mime_type: Some("image/png".to_string()),
+            blob: Some(b"png".to_vec().into()),
+            ..Default::default()
+        };
+        let path = media_path(&resource).unwrap();
+        assert!(path.starts_with("nft/"));
+        assert!(path.ends_with(".png"));
+        assert_eq!(path.len(), "nft/".len() + 64 + ".png".len());
+        assert!(
+            media_path(&Resource {
+                blob: None,
+                ..resource
+            })
+            .is_none()
+        );
+
+        let metadata = metadata_json(
+            "Panda #1",
+            "The first panda",
+            Some("https://media.example.com/nft/1.png"),
+            &[NFTAttribute {
+                trait_type: "style".to_string(),
+                value: "pixel art".to_string(),
+            }],
+        );
+        assert_eq!(metadata["image"], "https://media.example.com/nft/1.png");
+        assert_eq!(metadata["attributes"][0]["value"], "pixel art");
+
+        assert_eq!(
+            icrc3_to_json(ICRC3Value::Map(BTreeMap::from([(
+                "n".to_string(),
+                ICRC3Value::Nat(Nat::from(7u64))
+            )]))),
+            json!({"n": 7})
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Enables AI Agent to query ICRC-7 NFTs
+//!
+//! Queries the owner and metadata of an NFT, or the NFTs owned by an account.
+
+use anda_core::{BoxError, FunctionDefinition, Resource, Tool, ToolOutput, gen_schema_for};
+use anda_engine::context::BaseCtx;
+use candid::Principal;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{ICRC7Collections, NFTInfo};
+
+/// Arguments for querying NFTs
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct QueryNFTArgs {
+    /// Collection symbol, e.g. "PANDA"
+    pub symbol: String,
+    /// Token ID of the NFT to query
+    pub token_id: Option<u64>,
+    /// ICP account address (principal) to list the NFTs owned by, used if token_id is not provided
+    pub owner: Option<String>,
+}
+
+/// ICRC-7 NFT query tool implementation
+#[derive(Debug, Clone)]
+pub struct NFTQueryTool {
+    collections: Arc<ICRC7Collections>,
+    schema: Value,
+}
+
+impl NFTQueryTool {
+    pub const NAME: &'static str = "icp_nft_query";
+
+    /// Creates a new NFTQueryTool instance
+    pub fn new(collections: Arc<ICRC7Collections>) -> Self {
+        let schema = gen_schema_for::<QueryNFTArgs>();
+
+        NFTQueryTool {
+            collections,
+            schema,
+        }
+    }
+}
+
+/// Implementation of the [`Tool`] trait for NFTQueryTool
+impl Tool<BaseCtx> for NFTQueryTool {
+    type Args = QueryNFTArgs;
+    type Output = Vec<NFTInfo>;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        let collections = self
+            .collections
+            .collections
+            .keys()
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>();
+        format!(
+            "Query an NFT by token ID or the NFTs owned by an account on ICP blockchain, for the following collections: {}",
+            collections.join(", ")
+        )
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        data: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let token_ids = match (data.token_id, &data.owner) {
+            (Some(token_id), _) => vec![token_id],
+            (None, Some(owner)) => {
+                let owner = Principal::from_text(owner)?;
+                self.collections
+                    .tokens_of(&ctx, &data.symbol, owner)
+                    .await?
+            }
+            (None, None) => return Err("token_id or owner is required".into()),
+        };
+
+        let mut res = Vec::with_capacity(token_ids.len());
+        for token_id in token_ids {
+            res.push(
+                self.collections
+                    .token_info(&ctx, &data.symbol, token_id)
+                    .await?,
+            );
+        }
+        Ok(ToolOutput::new(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::{MintArg, TransferError};
+    use anda_engine::context::mock;
+    use candid::{Nat, decode_args, encode_args};
+    use icrc_ledger_types::{icrc::generic_value::ICRC3Value, icrc1::account::Account};
+    use std::collections::BTreeMap;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_icrc7_nft() {
+        let canister = Principal::from_text("druyg-tyaaa-aaaaq-aactq-cai").unwrap();
+        let alice =
+            Principal::from_text("77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe")
+                .unwrap();
+        let collections = ICRC7Collections {
+            collections: BTreeMap::from([(String::from("PANDA"), canister)]),
+            media_base_url: "https://media.example.com/icp_nft_mint".to_string(),
+            mint_method: "icrc7_mint".to_string(),
+        };
+        let tool = NFTQueryTool::new(Arc::new(collections.clone()));
+        assert_eq!(tool.definition().name, "icp_nft_query");
+
+        let mocker = mock::MockCanisterCaller::new(move |c, method, args| {
+            assert_eq!(c, &canister);
+            match method {
+                "icrc7_total_supply" => encode_args((Nat::from(7u64),)).unwrap(),
+                "icrc7_mint" => {
+                    let (args,): (Vec<MintArg>,) = decode_args(&args).unwrap();
+                    assert_eq!(args[0].token_id, Nat::from(7u64));
+                    assert_eq!(args[0].to.owner, alice);
+                    let res: Vec<Option<Result<Nat, TransferError>>> =
+                        vec![Some(Ok(Nat::from(1u64)))];
+                    encode_args((res,)).unwrap()
+                }
+                "icrc7_transfer" => {
+                    let res: Vec<Option<Result<Nat, TransferError>>> =
+                        vec![Some(Err(TransferError::Unauthorized))];
+                    encode_args((res,)).unwrap()
+                }
+                "icrc7_tokens_of" => encode_args((vec![Nat::from(7u64)],)).unwrap(),
+                "icrc7_owner_of" => encode_args((vec![Some(Account {
+                    owner: alice,
+                    subaccount: None,
+                })],))
+                .unwrap(),
+                "icrc7_token_metadata" => encode_args((vec![Some(vec![(
+                    "icrc7:name".to_string(),
+                    ICRC3Value::Text("Panda #7".to_string()),
+                )])],))
+                .unwrap(),
+                _ => panic!("unexpected method {}", method),
+            }
+        });
+
+        let token_id = collections
+            .mint(
+                &mocker,
+                "PANDA",
+                Account {
+                    owner: alice,
+                    subaccount: None,
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(token_id, Nat::from(7u64));
+
+        let err = collections
+            .transfer(&mocker, "PANDA", 7, alice)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Unauthorized"));
+
+        let ids = collections
+            .tokens_of(&mocker, "PANDA", alice)
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![7]);
+        let info = collections.token_info(&mocker, "PANDA", 7).await.unwrap();
+        assert_eq!(info.owner, Some(alice.to_text()));
+        assert_eq!(info.metadata["icrc7:name"], "Panda #7");
+        assert!(collections.token_info(&mocker, "OTHER", 7).await.is_err());
+    }
+}
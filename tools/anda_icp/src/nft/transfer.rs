@@ -0,0 +1,95 @@
+//! Enables AI Agent to transfer ICRC-7 NFTs
+//!
+//! Transfers an NFT owned by the Agent's main account to another account.
+
+use anda_core::{BoxError, FunctionDefinition, Resource, Tool, ToolOutput, gen_schema_for};
+use anda_engine::context::BaseCtx;
+use candid::Principal;
+use num_traits::cast::ToPrimitive;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::ICRC7Collections;
+
+/// Arguments for transferring an NFT to an account
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct TransferNFTArgs {
+    /// ICP account address (principal) to receive the NFT, e.g. "77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe"
+    pub account: String,
+    /// Collection symbol, e.g. "PANDA"
+    pub symbol: String,
+    /// Token ID of the NFT
+    pub token_id: u64,
+}
+
+/// ICRC-7 NFT transfer tool implementation
+#[derive(Debug, Clone)]
+pub struct NFTTransferTool {
+    collections: Arc<ICRC7Collections>,
+    schema: Value,
+}
+
+impl NFTTransferTool {
+    pub const NAME: &'static str = "icp_nft_transfer";
+
+    /// Creates a new NFTTransferTool instance
+    pub fn new(collections: Arc<ICRC7Collections>) -> Self {
+        let schema = gen_schema_for::<TransferNFTArgs>();
+
+        NFTTransferTool {
+            collections,
+            schema,
+        }
+    }
+}
+
+/// Implementation of the [`Tool`] trait for NFTTransferTool
+impl Tool<BaseCtx> for NFTTransferTool {
+    type Args = TransferNFTArgs;
+    type Output = String;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        let collections = self
+            .collections
+            .collections
+            .keys()
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>();
+        format!(
+            "Transfer an NFT to the specified account on ICP blockchain, for the following collections: {}",
+            collections.join(", ")
+        )
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        data: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let to = Principal::from_text(&data.account)?;
+        let tx = self
+            .collections
+            .transfer(&ctx, &data.symbol, data.token_id, to)
+            .await?;
+        Ok(ToolOutput::new(format!(
+            "Successful, transaction ID: {}",
+            tx.0.to_u64().unwrap_or(0)
+        )))
+    }
+}
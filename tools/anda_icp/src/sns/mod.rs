@@ -0,0 +1,342 @@
+//! Module for SNS DAO governance
+//!
+//! This module provides functionality for:
+//! - Listing the proposals of an SNS DAO with their status and tallies
+//! - Summarizing proposals with the Agent's model, see [`ProposalSummarizer`]
+//! - Casting votes with a neuron the Agent controls as a hotkey
+//!
+//! Votes are cast with the DAO's voting power, so [`VoteTool`] should be gated by operator
+//! approval in deployments:
+//! ```rust,ignore
+//! use anda_engine::management::ApprovalPolicy;
+//! use anda_icp::sns::{ListProposalsTool, ProposalSummarizer, SnsGovernance, VoteTool};
+//!
+//! let governance = Arc::new(
+//!     SnsGovernance::new("OpenChat".to_string(), Principal::from_text("2jvtu-yqaaa-aaaaq-aaama-cai")?)
+//!         .with_neuron(&neuron_id_hex)?,
+//! );
+//! let policy = ApprovalPolicy::new(operators, 2, Duration::from_secs(3600 * 24))
+//!     .with_tool(VoteTool::NAME);
+//! let engine = Engine::builder()
+//!     .with_management(ManagementBuilder::new(visibility, controller).with_approval_policy(policy)?)
+//!     .register_tool(ListProposalsTool::new(governance.clone()))?
+//!     .register_tool(VoteTool::new(governance.clone()))?
+//!     .register_agent(ProposalSummarizer::new(governance))?
+//!     .build("default_agent".to_string())?;
+//! ```
+
+use anda_core::{BoxError, CanisterCaller};
+use candid::{CandidType, Principal};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub mod proposals;
+pub mod summarizer;
+pub mod vote;
+
+pub use proposals::*;
+pub use summarizer::*;
+pub use vote::*;
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ProposalId {
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct Proposal {
+    pub url: String,
+    pub title: String,
+    pub summary: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct Tally {
+    pub no: u64,
+    pub yes: u64,
+    pub total: u64,
+    pub timestamp_seconds: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct WaitForQuietState {
+    pub current_deadline_timestamp_seconds: u64,
+}
+
+/// The fields of the SNS `ProposalData` used by the tools
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ProposalData {
+    pub id: Option<ProposalId>,
+    pub proposal: Option<Proposal>,
+    pub action: u64,
+    pub latest_tally: Option<Tally>,
+    pub proposal_creation_timestamp_seconds: u64,
+    pub decided_timestamp_seconds: u64,
+    pub executed_timestamp_seconds: u64,
+    pub failed_timestamp_seconds: u64,
+    pub wait_for_quiet_state: Option<WaitForQuietState>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ListProposals {
+    pub include_reward_status: Vec<i32>,
+    pub before_proposal: Option<ProposalId>,
+    pub limit: u32,
+    pub exclude_type: Vec<u64>,
+    pub include_status: Vec<i32>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ListProposalsResponse {
+    pub proposals: Vec<ProposalData>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct GovernanceError {
+    pub error_message: String,
+    pub error_type: i32,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct GetProposal {
+    pub proposal_id: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum GetProposalResult {
+    Error(GovernanceError),
+    Proposal(ProposalData),
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct GetProposalResponse {
+    pub result: Option<GetProposalResult>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RegisterVote {
+    pub vote: i32,
+    pub proposal: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum Command {
+    RegisterVote(RegisterVote),
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ManageNeuron {
+    pub subaccount: Vec<u8>,
+    pub command: Option<Command>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RegisterVoteResponse {}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum CommandResponse {
+    Error(GovernanceError),
+    RegisterVote(RegisterVoteResponse),
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ManageNeuronResponse {
+    pub command: Option<CommandResponse>,
+}
+
+/// The decision status of a proposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Open,
+    Rejected,
+    Adopted,
+    Executed,
+    Failed,
+}
+
+impl ProposalStatus {
+    /// The `ProposalDecisionStatus` code of the SNS governance
+    fn code(&self) -> i32 {
+        match self {
+            ProposalStatus::Open => 1,
+            ProposalStatus::Rejected => 2,
+            ProposalStatus::Adopted => 3,
+            ProposalStatus::Executed => 4,
+            ProposalStatus::Failed => 5,
+        }
+    }
+}
+
+/// A proposal of an SNS DAO
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ProposalInfo {
+    pub id: u64,
+    pub title: String,
+    pub url: String,
+    pub summary: String,
+    pub status: Option<ProposalStatus>,
+    /// The voting power voted yes, in e8s
+    pub yes: u64,
+    /// The voting power voted no, in e8s
+    pub no: u64,
+    /// The total voting power, in e8s
+    pub total: u64,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    /// The voting deadline, unix timestamp in seconds
+    pub deadline: Option<u64>,
+}
+
+impl From<ProposalData> for ProposalInfo {
+    fn from(data: ProposalData) -> Self {
+        let tally = data.latest_tally.unwrap_or_default();
+        let status = if data.executed_timestamp_seconds > 0 {
+            ProposalStatus::Executed
+        } else if data.failed_timestamp_seconds > 0 {
+            ProposalStatus::Failed
+        } else if data.decided_timestamp_seconds == 0 {
+            ProposalStatus::Open
+        } else if tally.yes > tally.no {
+            ProposalStatus::Adopted
+        } else {
+            ProposalStatus::Rejected
+        };
+        let proposal = data.proposal.unwrap_or(Proposal {
+            url: String::new(),
+            title: String::new(),
+            summary: String::new(),
+        });
+        ProposalInfo {
+            id: data.id.map(|id| id.id).unwrap_or_default(),
+            title: proposal.title,
+            url: proposal.url,
+            summary: proposal.summary,
+            status: Some(status),
+            yes: tally.yes,
+            no: tally.no,
+            total: tally.total,
+            created_at: data.proposal_creation_timestamp_seconds,
+            deadline: data
+                .wait_for_quiet_state
+                .map(|s| s.current_deadline_timestamp_seconds),
+        }
+    }
+}
+
+/// An SNS DAO governance canister, with an optional neuron to vote with
+#[derive(Debug, Clone)]
+pub struct SnsGovernance {
+    /// The name of the DAO, e.g. "OpenChat"
+    pub name: String,
+    /// The governance canister ID
+    pub governance: Principal,
+    /// The ID (subaccount) of the neuron to vote with. The Agent must be a hotkey of the
+    /// neuron with the vote permission.
+    pub neuron: Option<Vec<u8>>,
+}
+
+impl SnsGovernance {
+    pub fn new(name: String, governance: Principal) -> Self {
+        Self {
+            name,
+            governance,
+            neuron: None,
+        }
+    }
+
+    /// Sets the neuron to vote with by its hex ID.
+    pub fn with_neuron(mut self, neuron_id: &str) -> Result<Self, BoxError> {
+        let id = const_hex::decode(neuron_id.trim())?;
+        if id.len() != 32 {
+            return Err(format!("invalid neuron ID {:?}", neuron_id).into());
+        }
+        self.neuron = Some(id);
+        Ok(self)
+    }
+
+    /// Lists the proposals from the newest, optionally by status and before a proposal ID.
+    pub async fn list_proposals(
+        &self,
+        ctx: &impl CanisterCaller,
+        status: Option<ProposalStatus>,
+        before_proposal: Option<u64>,
+        limit: u32,
+    ) -> Result<Vec<ProposalInfo>, BoxError> {
+        let res: ListProposalsResponse = ctx
+            .canister_query(
+                &self.governance,
+                "list_proposals",
+                (ListProposals {
+                    include_reward_status: vec![],
+                    before_proposal: before_proposal.map(|id| ProposalId { id }),
+                    limit: limit.clamp(1, 100),
+                    exclude_type: vec![],
+                    include_status: status.map(|s| vec![s.code()]).unwrap_or_default(),
+                },),
+            )
+            .await?;
+        Ok(res.proposals.into_iter().map(ProposalInfo::from).collect())
+    }
+
+    /// Retrieves a proposal by ID.
+    pub async fn get_proposal(
+        &self,
+        ctx: &impl CanisterCaller,
+        proposal_id: u64,
+    ) -> Result<ProposalInfo, BoxError> {
+        let res: GetProposalResponse = ctx
+            .canister_query(
+                &self.governance,
+                "get_proposal",
+                (GetProposal {
+                    proposal_id: Some(ProposalId { id: proposal_id }),
+                },),
+            )
+            .await?;
+        match res.result {
+            Some(GetProposalResult::Proposal(data)) => Ok(data.into()),
+            Some(GetProposalResult::Error(err)) => Err(err.error_message.into()),
+            None => Err(format!("proposal {} not found", proposal_id).into()),
+        }
+    }
+
+    /// Votes on a proposal with the configured neuron.
+    pub async fn vote(
+        &self,
+        ctx: &impl CanisterCaller,
+        proposal_id: u64,
+        adopt: bool,
+    ) -> Result<(), BoxError> {
+        let neuron = self
+            .neuron
+            .as_ref()
+            .ok_or("no neuron is configured to vote")?;
+        let res: ManageNeuronResponse = ctx
+            .canister_update(
+                &self.governance,
+                "manage_neuron",
+                (ManageNeuron {
+                    subaccount: neuron.clone(),
+                    command: Some(Command::RegisterVote(RegisterVote {
+                        vote: if adopt { 1 } else { 2 },
+                        proposal: Some(ProposalId { id: proposal_id }),
+                    })),
+                },),
+            )
+            .await?;
+        log::info!(
+            dao = self.name,
+            proposal = proposal_id,
+            adopt = adopt;
+            "sns_vote",
+        );
+        match res.command {
+            Some(CommandResponse::RegisterVote(_)) => Ok(()),
+            Some(CommandResponse::Error(err)) => {
+                Err(format!("failed to vote, error: {}", err.error_message).into())
+            }
+            None => Err("failed to vote, no result".into()),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Enables AI Agent to list the proposals of an SNS DAO
+
+use anda_core::{BoxError, FunctionDefinition, Resource, Tool, ToolOutput, gen_schema_for};
+use anda_engine::context::BaseCtx;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{ProposalInfo, ProposalStatus, SnsGovernance};
+
+/// Proposal summaries in the list are truncated to this number of characters.
+const SUMMARY_PREVIEW_CHARS: usize = 500;
+
+/// Arguments for listing proposals
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ListProposalsArgs {
+    /// Only list the proposals with this status
+    pub status: Option<ProposalStatus>,
+    /// List the proposals before this proposal ID, for pagination
+    pub before_proposal: Option<u64>,
+    /// The number of proposals to list, 10 by default
+    pub limit: Option<u32>,
+}
+
+/// SNS proposals list tool implementation
+#[derive(Debug, Clone)]
+pub struct ListProposalsTool {
+    governance: Arc<SnsGovernance>,
+    schema: Value,
+}
+
+impl ListProposalsTool {
+    pub const NAME: &'static str = "sns_list_proposals";
+
+    /// Creates a new ListProposalsTool instance
+    pub fn new(governance: Arc<SnsGovernance>) -> Self {
+        let schema = gen_schema_for::<ListProposalsArgs>();
+
+        ListProposalsTool { governance, schema }
+    }
+}
+
+/// Implementation of the [`Tool`] trait for ListProposalsTool
+impl Tool<BaseCtx> for ListProposalsTool {
+    type Args = ListProposalsArgs;
+    type Output = Vec<ProposalInfo>;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "List the governance proposals of the {} DAO on ICP blockchain from the newest, with their status and votes.",
+            self.governance.name
+        )
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        data: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let mut proposals = self
+            .governance
+            .list_proposals(
+                &ctx,
+                data.status,
+                data.before_proposal,
+                data.limit.unwrap_or(10),
+            )
+            .await?;
+        for p in proposals.iter_mut() {
+            if let Some((i, _)) = p.summary.char_indices().nth(SUMMARY_PREVIEW_CHARS) {
+                p.summary.truncate(i);
+                p.summary.push_str("...");
+            }
+        }
+        Ok(ToolOutput::new(proposals))
+    }
+}
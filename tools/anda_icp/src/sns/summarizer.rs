@@ -0,0 +1,125 @@
+//! Enables AI Agent to summarize SNS DAO proposals
+//!
+//! [`ProposalSummarizer`] is an Agent that takes a proposal ID as the prompt, fetches the
+//! proposal and summarizes what it changes, its risks and its voting status, so that
+//! community members and operators can decide how to vote.
+
+use anda_core::{Agent, AgentOutput, BoxError, CompletionFeatures, CompletionRequest, Resource};
+use anda_engine::context::AgentCtx;
+use std::sync::Arc;
+
+use super::{ProposalInfo, SnsGovernance};
+
+/// An Agent that summarizes SNS DAO proposals
+#[derive(Debug, Clone)]
+pub struct ProposalSummarizer {
+    governance: Arc<SnsGovernance>,
+}
+
+impl ProposalSummarizer {
+    pub const NAME: &'static str = "sns_proposal_summarizer";
+
+    /// Creates a new ProposalSummarizer instance
+    pub fn new(governance: Arc<SnsGovernance>) -> Self {
+        Self { governance }
+    }
+
+    /// Summarizes a proposal with the model.
+    pub async fn summarize(
+        &self,
+        ctx: &impl CompletionFeatures,
+        proposal: &ProposalInfo,
+    ) -> Result<AgentOutput, BoxError> {
+        let req = CompletionRequest {
+            system: Some(format!(
+                "\
+                You are a governance assistant of the {} DAO. Summarize the user's governance proposal for community members in a few short paragraphs:\n\
+                - What the proposal changes and why;\n\
+                - Its benefits and risks, including anything unclear or unusual;\n\
+                - Its current status and votes.\n\
+                Be neutral and factual, and do not recommend how to vote.\
+                ",
+                self.governance.name
+            )),
+            prompt: proposal_prompt(proposal),
+            temperature: Some(0.0),
+            ..Default::default()
+        };
+        ctx.completion(req, None).await
+    }
+}
+
+/// Formats a proposal as the prompt of the summarizer.
+fn proposal_prompt(p: &ProposalInfo) -> String {
+    let status = p
+        .status
+        .map(|s| serde_json::to_value(s).unwrap_or_default())
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| "unknown".to_string());
+    let votes = |e8s: u64| e8s as f64 / 100_000_000f64;
+    format!(
+        "Proposal {}: {}\nURL: {}\nStatus: {}\nVotes: {} yes, {} no, {} total voting power\n\n{}",
+        p.id,
+        p.title,
+        p.url,
+        status,
+        votes(p.yes),
+        votes(p.no),
+        votes(p.total),
+        p.summary
+    )
+}
+
+impl Agent<AgentCtx> for ProposalSummarizer {
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "Summarize a governance proposal of the {} DAO by its proposal ID.",
+            self.governance.name
+        )
+    }
+
+    /// Summarizes the proposal with the ID in the prompt
+    async fn run(
+        &self,
+        ctx: AgentCtx,
+        prompt: String,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<AgentOutput, BoxError> {
+        let id = prompt
+            .trim()
+            .trim_start_matches('#')
+            .parse::<u64>()
+            .map_err(|_| format!("invalid proposal ID {:?}", prompt))?;
+        let proposal = self.governance.get_proposal(&ctx, id).await?;
+        self.summarize(&ctx, &proposal).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sns::ProposalStatus;
+
+    #[test]
+    fn test_proposal_prompt() {
+        let prompt = proposal_prompt(&ProposalInfo {
+            id: 42,
+            title: "Upgrade the backend".to_string(),
+            url: "https://oc.app".to_string(),
+            summary: "Fixes bugs.".to_string(),
+            status: Some(ProposalStatus::Open),
+            yes: 150_000_000,
+            no: 0,
+            total: 1_000_000_000,
+            ..Default::default()
+        });
+        assert!(prompt.starts_with("Proposal 42: Upgrade the backend\n"));
+        assert!(prompt.contains("Status: open\n"));
+        assert!(prompt.contains("Votes: 1.5 yes, 0 no, 10 total voting power"));
+        assert!(prompt.ends_with("\n\nFixes bugs."));
+    }
+}
@@ -0,0 +1,187 @@
+//! Enables AI Agent to vote on SNS DAO proposals
+//!
+//! Votes with the neuron configured in [`SnsGovernance`]. Register this tool in the
+//! [`anda_engine::management::ApprovalPolicy`] so that votes are cast only after operator
+//! approval.
+
+use anda_core::{BoxError, FunctionDefinition, Resource, Tool, ToolOutput, gen_schema_for};
+use anda_engine::context::BaseCtx;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::SnsGovernance;
+
+/// A vote on a proposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteChoice {
+    Yes,
+    No,
+}
+
+/// Arguments for voting on a proposal
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct VoteArgs {
+    /// The proposal ID
+    pub proposal_id: u64,
+    /// The vote
+    pub vote: VoteChoice,
+}
+
+/// SNS vote tool implementation
+#[derive(Debug, Clone)]
+pub struct VoteTool {
+    governance: Arc<SnsGovernance>,
+    schema: Value,
+}
+
+impl VoteTool {
+    pub const NAME: &'static str = "sns_vote";
+
+    /// Creates a new VoteTool instance
+    pub fn new(governance: Arc<SnsGovernance>) -> Self {
+        let schema = gen_schema_for::<VoteArgs>();
+
+        VoteTool { governance, schema }
+    }
+}
+
+/// Implementation of the [`Tool`] trait for VoteTool
+impl Tool<BaseCtx> for VoteTool {
+    type Args = VoteArgs;
+    type Output = String;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "Vote yes or no on a governance proposal of the {} DAO with the Agent's neuron.",
+            self.governance.name
+        )
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        data: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        self.governance
+            .vote(&ctx, data.proposal_id, data.vote == VoteChoice::Yes)
+            .await?;
+        Ok(ToolOutput::new(format!(
+            "Successful, voted {} on proposal {}",
+            if data.vote == VoteChoice::Yes {
+                "yes"
+            } else {
+                "no"
+            },
+            data.proposal_id
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sns::{
+        Command, CommandResponse, GovernanceError, ListProposals, ListProposalsResponse,
+        ManageNeuron, ManageNeuronResponse, Proposal, ProposalData, ProposalId, ProposalStatus,
+        RegisterVoteResponse, Tally,
+    };
+    use anda_engine::context::mock;
+    use candid::{Principal, decode_args, encode_args};
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_sns_governance() {
+        let canister = Principal::from_text("2jvtu-yqaaa-aaaaq-aaama-cai").unwrap();
+        let neuron = const_hex::encode([7u8; 32]);
+        let governance = SnsGovernance::new("OpenChat".to_string(), canister)
+            .with_neuron(&neuron)
+            .unwrap();
+        assert!(
+            SnsGovernance::new("OpenChat".to_string(), canister)
+                .with_neuron("0102")
+                .is_err()
+        );
+        let tool = VoteTool::new(Arc::new(governance.clone()));
+        assert_eq!(tool.definition().name, "sns_vote");
+
+        let mocker = mock::MockCanisterCaller::new(move |c, method, args| {
+            assert_eq!(c, &canister);
+            match method {
+                "list_proposals" => {
+                    let (args,): (ListProposals,) = decode_args(&args).unwrap();
+                    assert_eq!(args.include_status, vec![1]);
+                    let data = |id: u64, decided: u64| ProposalData {
+                        id: Some(ProposalId { id }),
+                        proposal: Some(Proposal {
+                            url: "https://oc.app".to_string(),
+                            title: format!("Proposal {}", id),
+                            summary: "Upgrade".to_string(),
+                        }),
+                        action: 3,
+                        latest_tally: Some(Tally {
+                            no: 1,
+                            yes: 2,
+                            total: 10,
+                            timestamp_seconds: 0,
+                        }),
+                        proposal_creation_timestamp_seconds: 1,
+                        decided_timestamp_seconds: decided,
+                        executed_timestamp_seconds: 0,
+                        failed_timestamp_seconds: 0,
+                        wait_for_quiet_state: None,
+                    };
+                    encode_args((ListProposalsResponse {
+                        proposals: vec![data(2, 0), data(1, 100)],
+                    },))
+                    .unwrap()
+                }
+                "manage_neuron" => {
+                    let (args,): (ManageNeuron,) = decode_args(&args).unwrap();
+                    assert_eq!(args.subaccount, vec![7u8; 32]);
+                    let Some(Command::RegisterVote(vote)) = args.command else {
+                        panic!("unexpected command");
+                    };
+                    let res = if vote.proposal == Some(ProposalId { id: 2 }) {
+                        assert_eq!(vote.vote, 1);
+                        CommandResponse::RegisterVote(RegisterVoteResponse {})
+                    } else {
+                        CommandResponse::Error(GovernanceError {
+                            error_message: "proposal is not open".to_string(),
+                            error_type: 15,
+                        })
+                    };
+                    encode_args((ManageNeuronResponse { command: Some(res) },)).unwrap()
+                }
+                _ => panic!("unexpected method {}", method),
+            }
+        });
+
+        let proposals = governance
+            .list_proposals(&mocker, Some(ProposalStatus::Open), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(proposals.len(), 2);
+        assert_eq!(proposals[0].status, Some(ProposalStatus::Open));
+        assert_eq!(proposals[1].status, Some(ProposalStatus::Adopted));
+
+        governance.vote(&mocker, 2, true).await.unwrap();
+        let err = governance.vote(&mocker, 1, false).await.unwrap_err();
+        assert!(err.to_string().contains("not open"));
+    }
+}